@@ -11,8 +11,15 @@ use proxmox_schema::{
 
 use crate::PROXMOX_SAFE_ID_REGEX_STR;
 
+/// ACL path component preceding the type and ID of a backup group, which cannot be confused
+/// with a namespace name.
+pub const ACL_PATH_GROUP_COMPONENT: &str = "@group";
+
 const_regex! {
-    pub ACL_PATH_REGEX = concatcp!(r"^(?:/|", r"(?:/", PROXMOX_SAFE_ID_REGEX_STR, ")+", r")$");
+    pub ACL_PATH_REGEX = concatcp!(
+        r"^(?:/|", r"(?:/", PROXMOX_SAFE_ID_REGEX_STR, ")+",
+        r"(?:/", ACL_PATH_GROUP_COMPONENT, r"(?:/", PROXMOX_SAFE_ID_REGEX_STR, "){2})?", r")$"
+    );
 }
 
 // define Privilege bitfield
@@ -120,6 +127,14 @@ pub const ROLE_DATASTORE_READER: u64 = 0
     | PRIV_DATASTORE_VERIFY
     | PRIV_DATASTORE_READ;
 
+#[rustfmt::skip]
+#[allow(clippy::identity_op)]
+/// Datastore.Restore can list and restore datastore content, but cannot create, change or delete
+/// backups, notes or protection, and cannot verify snapshots.
+pub const ROLE_DATASTORE_RESTORE: u64 = 0
+    | PRIV_DATASTORE_AUDIT
+    | PRIV_DATASTORE_READ;
+
 #[rustfmt::skip]
 #[allow(clippy::identity_op)]
 /// Datastore.Backup can do backup and restore, but no prune.
@@ -213,6 +228,8 @@ pub enum Role {
     DatastoreAdmin = ROLE_DATASTORE_ADMIN,
     /// Datastore Reader (inspect datastore content and do restores)
     DatastoreReader = ROLE_DATASTORE_READER,
+    /// Datastore Restore (list content and do restores, without changing any backups)
+    DatastoreRestore = ROLE_DATASTORE_RESTORE,
     /// Datastore Backup (backup and restore owned backups)
     DatastoreBackup = ROLE_DATASTORE_BACKUP,
     /// Datastore PowerUser (backup, restore and prune owned backup)
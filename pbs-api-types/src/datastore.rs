@@ -5,6 +5,7 @@ use anyhow::{bail, format_err, Error};
 use const_format::concatcp;
 use serde::{Deserialize, Serialize};
 
+use proxmox_human_byte::HumanByte;
 use proxmox_schema::{
     api, const_regex, ApiStringFormat, ApiType, ArraySchema, EnumEntry, IntegerSchema, ReturnType,
     Schema, StringSchema, Updater, UpdaterType,
@@ -60,13 +61,15 @@ pub const BACKUP_ID_SCHEMA: Schema = StringSchema::new("Backup ID.")
     .format(&BACKUP_ID_FORMAT)
     .schema();
 
-pub const BACKUP_TYPE_SCHEMA: Schema = StringSchema::new("Backup type.")
-    .format(&ApiStringFormat::Enum(&[
-        EnumEntry::new("vm", "Virtual Machine Backup"),
-        EnumEntry::new("ct", "Container Backup"),
-        EnumEntry::new("host", "Host Backup"),
-    ]))
-    .schema();
+pub const BACKUP_TYPE_FORMAT: ApiStringFormat = ApiStringFormat::Pattern(&BACKUP_TYPE_REGEX);
+
+pub const BACKUP_TYPE_SCHEMA: Schema =
+    StringSchema::new("Backup type (vm, ct, host or a custom backup type).")
+        .format(&BACKUP_TYPE_FORMAT)
+        .schema();
+
+pub const CUSTOM_BACKUP_TYPE_LIST_SCHEMA: Schema =
+    ArraySchema::new("List of custom backup types.", &BACKUP_TYPE_SCHEMA).schema();
 
 pub const BACKUP_TIME_SCHEMA: Schema = IntegerSchema::new("Backup time (Unix epoch.)")
     .minimum(1)
@@ -86,6 +89,24 @@ pub const BACKUP_NAMESPACE_SCHEMA: Schema = StringSchema::new("Namespace.")
     .max_length(MAX_BACKUP_NAMESPACE_LENGTH) // 256
     .schema();
 
+/// Default number of entries held in memory while creating a pxar archive.
+pub const PXAR_ENTRIES_MAX_DEFAULT: u64 = 1024 * 1024;
+
+/// Upper limit of the number of entries held in memory while creating a pxar archive.
+///
+/// The archive format requires sorted lookup tables of each directory, so memory usage grows
+/// with the number of entries in the directories currently being archived.
+pub const PXAR_ENTRIES_MAX_LIMIT: u64 = 64 * 1024 * 1024;
+
+pub const PXAR_ENTRIES_MAX_SCHEMA: Schema = IntegerSchema::new(
+    "Max number of entries held in memory while creating an archive. Raise it for directory \
+    trees with more entries than the default, at the cost of memory.",
+)
+.minimum(1)
+.maximum(PXAR_ENTRIES_MAX_LIMIT as isize)
+.default(PXAR_ENTRIES_MAX_DEFAULT as isize)
+.schema();
+
 pub const NS_MAX_DEPTH_SCHEMA: Schema =
     IntegerSchema::new("How many levels of namespaces should be operated on (0 == no recursion)")
         .minimum(0)
@@ -145,6 +166,13 @@ pub const PRUNE_SCHEMA_KEEP_LAST: Schema = IntegerSchema::new("Number of backups
     .minimum(1)
     .schema();
 
+pub const GROUP_MAX_SNAPSHOTS_SCHEMA: Schema = IntegerSchema::new(
+    "Maximum number of snapshots in a backup group. After a new backup finished, the oldest \
+    unprotected snapshots exceeding this limit are removed.",
+)
+.minimum(1)
+.schema();
+
 pub const PRUNE_SCHEMA_KEEP_MONTHLY: Schema =
     IntegerSchema::new("Number of monthly backups to keep.")
         .minimum(1)
@@ -160,6 +188,24 @@ pub const PRUNE_SCHEMA_KEEP_YEARLY: Schema =
         .minimum(1)
         .schema();
 
+pub const RETENTION_TAG_SCHEMA: Schema =
+    StringSchema::new("Keep option for whose period a snapshot was kept in the last prune run.")
+        .format(&ApiStringFormat::Enum(&[
+            EnumEntry::new("keep-last", "Kept as one of the last snapshots."),
+            EnumEntry::new("keep-hourly", "Kept as last snapshot of an hour."),
+            EnumEntry::new("keep-daily", "Kept as last snapshot of a day."),
+            EnumEntry::new("keep-weekly", "Kept as last snapshot of a week."),
+            EnumEntry::new("keep-monthly", "Kept as last snapshot of a month."),
+            EnumEntry::new("keep-yearly", "Kept as last snapshot of a year."),
+        ]))
+        .schema();
+
+pub const RETENTION_TAG_LIST_SCHEMA: Schema = ArraySchema::new(
+    "Keep options for whose periods the snapshot was kept in the last prune run.",
+    &RETENTION_TAG_SCHEMA,
+)
+.schema();
+
 #[api]
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -186,7 +232,12 @@ pub enum DatastoreFSyncLevel {
     /// But it may cause losing data on powerloss or system crash without any uninterruptible power
     /// supply.
     None,
-    /// Triggers a fsync after writing any chunk on the datastore. While this can slow down
+    /// Triggers a fsync after writing an index file, a blob or the manifest of a snapshot, but
+    /// not for chunks. A finished snapshot is then never left with incomplete metadata after a
+    /// crash, while lost chunks are only detected by verification.
+    Index,
+    /// Triggers a fsync after writing any chunk, index file, blob or manifest on the datastore.
+    /// While this can slow down
     /// backups significantly, depending on the underlying file system and storage used, it
     /// will ensure fine-grained consistency. Depending on the exact setup, there might be no
     /// benefits over the file system level sync, so if the setup allows it, you should prefer
@@ -203,12 +254,88 @@ pub enum DatastoreFSyncLevel {
     Filesystem,
 }
 
+#[api]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// What to do with unfinished snapshots left behind by aborted or crashed backups.
+pub enum UnfinishedSnapshotAction {
+    /// Only report them in the task log.
+    #[default]
+    Report,
+    /// Remove them.
+    Remove,
+    /// Move them into the `.unfinished` directory of the datastore.
+    Quarantine,
+}
+
+#[api]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Who owns backup groups created by an API token.
+pub enum TokenOwnership {
+    /// The token itself owns the group, sibling tokens of the same user have no access.
+    #[default]
+    Token,
+    /// The user of the token owns the group, all tokens of that user can access it.
+    User,
+}
+
 #[api(
     properties: {
         "chunk-order": {
             type: ChunkOrder,
             optional: true,
         },
+        "reader-rate": {
+            type: HumanByte,
+            optional: true,
+        },
+        "unfinished-snapshots": {
+            type: UnfinishedSnapshotAction,
+            optional: true,
+        },
+        "merkle-roots": {
+            type: bool,
+            optional: true,
+            default: false,
+        },
+        "group-tombstone-days": {
+            type: Integer,
+            minimum: 1,
+            maximum: 3650,
+            optional: true,
+        },
+        "listing-cache": {
+            type: bool,
+            optional: true,
+            default: false,
+        },
+        "content-index": {
+            type: bool,
+            optional: true,
+            default: false,
+        },
+        "filename-index": {
+            type: bool,
+            optional: true,
+            default: false,
+        },
+        "token-ownership": {
+            type: TokenOwnership,
+            optional: true,
+        },
+        "throttle-latency": {
+            type: Integer,
+            minimum: 1,
+            maximum: 10_000,
+            optional: true,
+        },
+        "throttle-load": {
+            type: Number,
+            minimum: 0.1,
+            maximum: 100.0,
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Default)]
@@ -220,6 +347,37 @@ pub struct DatastoreTuning {
     pub chunk_order: Option<ChunkOrder>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sync_level: Option<DatastoreFSyncLevel>,
+    /// Rate limit for the data sent by each reader (restore) session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reader_rate: Option<HumanByte>,
+    /// What garbage collection does with stale unfinished snapshots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unfinished_snapshots: Option<UnfinishedSnapshotAction>,
+    /// Record a Merkle root over the chunk digests of each archive in the manifest
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merkle_roots: Option<bool>,
+    /// Keep the names of removed backup groups reserved for this many days
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_tombstone_days: Option<u64>,
+    /// Cache snapshot listings in memory, only re-reading changed directories
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listing_cache: Option<bool>,
+    /// Keep an index of the snapshots and their metadata for listings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_index: Option<bool>,
+    /// Keep an index of the file names in the catalogs of each group for file searches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename_index: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_ownership: Option<TokenOwnership>,
+    /// Slow down garbage collection and verification while the average IO latency of the
+    /// datastore disk exceeds this many milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throttle_latency: Option<u64>,
+    /// Slow down garbage collection and verification while the load average per CPU exceeds
+    /// this value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throttle_load: Option<f64>,
 }
 
 pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore tuning options")
@@ -228,6 +386,72 @@ pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore
     ))
     .schema();
 
+#[api(
+    properties: {
+        "chunk-size": {
+            type: Integer,
+            minimum: 64,
+            maximum: 4096,
+            optional: true,
+        },
+        rate: {
+            type: HumanByte,
+            optional: true,
+        },
+        burst: {
+            type: HumanByte,
+            optional: true,
+        },
+        "require-encryption": {
+            type: bool,
+            optional: true,
+            default: false,
+        },
+        "entries-max": {
+            schema: PXAR_ENTRIES_MAX_SCHEMA,
+            optional: true,
+        },
+        "entries-max-limit": {
+            type: Integer,
+            minimum: 1,
+            maximum: PXAR_ENTRIES_MAX_LIMIT as isize,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Default options for backup clients of a datastore
+///
+/// Clients apply these unless the respective option is given locally.
+pub struct ClientDefaults {
+    /// Chunk size in KiB, must be a power of 2
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u64>,
+    /// Rate limit for the backup upload
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate: Option<HumanByte>,
+    /// Burst size for the upload rate limit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst: Option<HumanByte>,
+    /// Refuse to create unencrypted backups
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_encryption: Option<bool>,
+    /// Max number of entries the client holds in memory when creating archives
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries_max: Option<u64>,
+    /// Highest 'entries-max' clients may use, also if given on the command line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries_max_limit: Option<u64>,
+}
+
+pub const DATASTORE_CLIENT_DEFAULTS_STRING_SCHEMA: Schema =
+    StringSchema::new("Default options for backup clients")
+        .format(&ApiStringFormat::PropertyString(
+            &ClientDefaults::API_SCHEMA,
+        ))
+        .schema();
+
 #[api(
     properties: {
         name: {
@@ -264,10 +488,27 @@ pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore
             optional: true,
             type: bool,
         },
+        "encrypt-at-rest": {
+            description: "Encrypt chunks and blobs on disk with a datastore key. Cannot be disabled once enabled.",
+            optional: true,
+            type: bool,
+        },
+        "compliance-report": {
+            optional: true,
+            type: ComplianceReportPeriod,
+        },
+        "digest-algorithm": {
+            optional: true,
+            type: ChunkDigestAlgorithm,
+        },
         tuning: {
             optional: true,
             schema: DATASTORE_TUNING_STRING_SCHEMA,
         },
+        "client-defaults": {
+            optional: true,
+            schema: DATASTORE_CLIENT_DEFAULTS_STRING_SCHEMA,
+        },
         "maintenance-mode": {
             optional: true,
             format: &ApiStringFormat::PropertyString(&MaintenanceMode::API_SCHEMA),
@@ -301,6 +542,10 @@ pub struct DataStoreConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub verify_new: Option<bool>,
 
+    /// If enabled, chunks and blobs are encrypted on disk with the datastore key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypt_at_rest: Option<bool>,
+
     /// Send job email notification to this user
     #[serde(skip_serializing_if = "Option::is_none")]
     pub notify_user: Option<Userid>,
@@ -313,10 +558,23 @@ pub struct DataStoreConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub notification_mode: Option<NotificationMode>,
 
+    /// Generate and send a compliance report in this interval
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compliance_report: Option<ComplianceReportPeriod>,
+
+    /// Hash algorithm of the chunk digests, can only be set on creation
+    #[updater(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest_algorithm: Option<ChunkDigestAlgorithm>,
+
     /// Datastore tuning options
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tuning: Option<String>,
 
+    /// Default options for backup clients
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_defaults: Option<String>,
+
     /// Maintenance mode, type is either 'offline' or 'read-only', message should be enclosed in "
     #[serde(skip_serializing_if = "Option::is_none")]
     pub maintenance_mode: Option<String>,
@@ -339,6 +597,44 @@ pub enum NotificationMode {
     NotificationSystem,
 }
 
+#[api]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Hash algorithm for the digests identifying chunks.
+///
+/// Chunks are stored and deduplicated by digest, so all chunks of a datastore must use the same
+/// algorithm. The algorithm is recorded in the datastore configuration and in the manifests
+/// of snapshots, a missing entry means SHA-256.
+pub enum ChunkDigestAlgorithm {
+    /// SHA-256, keyed with the encryption key for encrypted chunks.
+    #[default]
+    Sha256,
+}
+
+serde_plain::derive_display_from_serialize!(ChunkDigestAlgorithm);
+serde_plain::derive_fromstr_from_deserialize!(ChunkDigestAlgorithm);
+
+#[api]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Interval for generating and sending compliance reports of a datastore.
+pub enum ComplianceReportPeriod {
+    /// Every monday, covering the last 7 days.
+    Weekly,
+    /// On the first of every month, covering the previous calendar month.
+    Monthly,
+}
+
+impl ComplianceReportPeriod {
+    /// Calendar event on which the report is generated.
+    pub fn schedule(&self) -> &'static str {
+        match self {
+            ComplianceReportPeriod::Weekly => "mon 06:00",
+            ComplianceReportPeriod::Monthly => "*-*-01 06:00",
+        }
+    }
+}
+
 impl DataStoreConfig {
     pub fn new(name: String, path: String) -> Self {
         Self {
@@ -349,10 +645,14 @@ impl DataStoreConfig {
             prune_schedule: None,
             keep: Default::default(),
             verify_new: None,
+            encrypt_at_rest: None,
             notify_user: None,
             notify: None,
             notification_mode: None,
+            compliance_report: None,
+            digest_algorithm: None,
             tuning: None,
+            client_defaults: None,
             maintenance_mode: None,
         }
     }
@@ -435,6 +735,15 @@ pub struct DataStoreListItem {
             type: CryptMode,
             optional: true,
         },
+        "previous-names": {
+            type: Array,
+            optional: true,
+            description: "Previous names of the archive in its backup group, most recent first \
+                (from backup manifest).",
+            items: {
+                schema: BACKUP_ARCHIVE_NAME_SCHEMA,
+            },
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
@@ -448,6 +757,8 @@ pub struct BackupContent {
     /// Archive size (from backup manifest).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub previous_names: Vec<String>,
 }
 
 #[api()]
@@ -461,6 +772,34 @@ pub enum VerifyState {
     Failed,
 }
 
+#[api()]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Verification state to filter snapshots by.
+pub enum VerifyStateFilter {
+    /// Last verification was successful
+    Ok,
+    /// Last verification reported errors
+    Failed,
+    /// Never verified
+    None,
+}
+
+#[api()]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Aggregated verification state of the snapshots of a group.
+pub enum GroupHealth {
+    /// The newest snapshot was verified, and no verification failed
+    Ok,
+    /// The last verification of at least one snapshot failed
+    Failed,
+    /// No snapshot was verified yet
+    None,
+    /// No verification failed, but the newest snapshot was not verified yet
+    Outdated,
+}
+
 #[api(
     properties: {
         upid: {
@@ -728,6 +1067,17 @@ impl BackupNamespace {
         }
     }
 
+    /// Returns the ACL path of a backup group in this namespace.
+    ///
+    /// Privileges on this path apply to the group only, in addition to those of the namespace.
+    pub fn group_acl_path<'a>(&'a self, store: &'a str, group: &'a BackupGroup) -> Vec<&'a str> {
+        let mut path = self.acl_path(store);
+        path.push(crate::ACL_PATH_GROUP_COMPONENT);
+        path.push(group.ty.as_str());
+        path.push(&group.id);
+        path
+    }
+
     /// Check whether this namespace contains another namespace.
     ///
     /// If so, the depth is returned.
@@ -801,10 +1151,52 @@ impl fmt::Display for BackupNamespacePath<'_> {
     }
 }
 
-#[api]
+/// Maximum length of a custom backup type name.
+pub const CUSTOM_BACKUP_TYPE_MAX_LEN: usize = 16;
+
+/// Name of a custom backup type.
+///
+/// The name is stored inline, so that [`BackupType`] can stay `Copy`.
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+pub struct CustomBackupType {
+    len: u8,
+    name: [u8; CUSTOM_BACKUP_TYPE_MAX_LEN],
+}
+
+impl CustomBackupType {
+    /// Create a custom backup type, the built-in type names are rejected.
+    pub fn new(name: &str) -> Result<Self, Error> {
+        if !BACKUP_TYPE_REGEX.is_match(name) || matches!(name, "vm" | "ct" | "host") {
+            bail!("invalid custom backup type {name:?}");
+        }
+        let mut data = [0u8; CUSTOM_BACKUP_TYPE_MAX_LEN];
+        data[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(Self {
+            len: name.len() as u8,
+            name: data,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // the name was checked against BACKUP_TYPE_REGEX, so it is plain ASCII
+        std::str::from_utf8(&self.name[..self.len as usize]).unwrap()
+    }
+}
+
+impl fmt::Debug for CustomBackupType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CustomBackupType")
+            .field(&self.as_str())
+            .finish()
+    }
+}
+
 /// Backup types.
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Deserialize, Serialize)]
-#[serde(rename_all = "lowercase")]
+///
+/// Besides the built-in types, custom types can be used for integrations which are neither
+/// virtual machines, containers nor hosts. Which custom types a server accepts for new backups
+/// is configured in the node configuration.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, UpdaterType)]
 pub enum BackupType {
     /// Virtual machines.
     Vm,
@@ -814,27 +1206,40 @@ pub enum BackupType {
 
     /// "Host" backups.
     Host,
-    // NOTE: if you add new types, don't forget to adapt the iter below!
+
+    /// Custom backup types.
+    Custom(CustomBackupType),
+    // NOTE: if you add new built-in types, don't forget to adapt the iter below!
 }
 
 impl BackupType {
-    pub const fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match self {
             BackupType::Vm => "vm",
             BackupType::Ct => "ct",
             BackupType::Host => "host",
+            BackupType::Custom(ty) => ty.as_str(),
         }
     }
 
+    /// Returns whether this is one of the built-in backup types.
+    pub fn is_builtin(&self) -> bool {
+        !matches!(self, BackupType::Custom(_))
+    }
+
     /// We used to have alphabetical ordering here when this was a string.
+    ///
+    /// Custom types are sorted after the built-in ones, by name.
     const fn order(self) -> u8 {
         match self {
             BackupType::Ct => 0,
             BackupType::Host => 1,
             BackupType::Vm => 2,
+            BackupType::Custom(_) => 3,
         }
     }
 
+    /// Iterate over the built-in backup types.
     #[inline]
     pub fn iter() -> impl Iterator<Item = BackupType> + Send + Sync + Unpin + 'static {
         [BackupType::Vm, BackupType::Ct, BackupType::Host]
@@ -843,6 +1248,13 @@ impl BackupType {
     }
 }
 
+serde_plain::derive_deserialize_from_fromstr!(BackupType, "valid backup type");
+serde_plain::derive_serialize_from_display!(BackupType);
+
+impl ApiType for BackupType {
+    const API_SCHEMA: Schema = BACKUP_TYPE_SCHEMA;
+}
+
 impl fmt::Display for BackupType {
     #[inline]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -859,7 +1271,9 @@ impl std::str::FromStr for BackupType {
             "ct" => BackupType::Ct,
             "host" => BackupType::Host,
             "vm" => BackupType::Vm,
-            _ => bail!("invalid backup type {ty:?}"),
+            _ => BackupType::Custom(
+                CustomBackupType::new(ty).map_err(|_| format_err!("invalid backup type {ty:?}"))?,
+            ),
         })
     }
 }
@@ -867,7 +1281,9 @@ impl std::str::FromStr for BackupType {
 impl std::cmp::Ord for BackupType {
     #[inline]
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.order().cmp(&other.order())
+        self.order()
+            .cmp(&other.order())
+            .then_with(|| self.as_str().cmp(other.as_str()))
     }
 }
 
@@ -1054,12 +1470,36 @@ impl From<(BackupType, String, i64)> for BackupDir {
     }
 }
 
+/// Format a backup time as used for snapshot directory names, e.g. `2020-06-15T05:18:33Z`.
+///
+/// Backup times are UTC epochs, so the name does not depend on the timezone of the server.
+pub fn backup_time_to_string(backup_time: i64) -> Result<String, Error> {
+    proxmox_time::epoch_to_rfc3339_utc(backup_time)
+}
+
+/// Parse a snapshot directory name into a backup time.
+///
+/// Only the canonical form produced by [`backup_time_to_string`] is accepted. Other RFC 3339
+/// variants, like local offsets or fractional seconds, would resolve to a snapshot whose
+/// directory has a different name.
+pub fn backup_time_from_string(backup_time: &str) -> Result<i64, Error> {
+    if !BACKUP_DATE_REGEX.is_match(backup_time) {
+        bail!("invalid backup time {backup_time:?}, expected format YYYY-MM-DDTHH:MM:SSZ");
+    }
+    let time = proxmox_time::parse_rfc3339(backup_time)?;
+    // out of range fields like '2020-02-30' would get normalized to another day
+    if backup_time_to_string(time)? != backup_time {
+        bail!("invalid backup time {backup_time:?}");
+    }
+    Ok(time)
+}
+
 impl BackupDir {
     pub fn with_rfc3339<T>(ty: BackupType, id: T, backup_time_string: &str) -> Result<Self, Error>
     where
         T: Into<String>,
     {
-        let time = proxmox_time::parse_rfc3339(backup_time_string)?;
+        let time = backup_time_from_string(backup_time_string)?;
         let group = BackupGroup::new(ty, id.into());
         Ok(Self { group, time })
     }
@@ -1097,7 +1537,7 @@ impl std::str::FromStr for BackupDir {
 impl fmt::Display for BackupDir {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // FIXME: log error?
-        let time = proxmox_time::epoch_to_rfc3339_utc(self.time).map_err(|_| fmt::Error)?;
+        let time = backup_time_to_string(self.time).map_err(|_| fmt::Error)?;
         write!(f, "{}/{}", self.group, time)
     }
 }
@@ -1151,6 +1591,10 @@ impl std::str::FromStr for BackupPart {
             type: Authid,
             optional: true,
         },
+        "retention-tags": {
+            schema: RETENTION_TAG_LIST_SCHEMA,
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
@@ -1179,6 +1623,156 @@ pub struct SnapshotListItem {
     /// Protection from prunes
     #[serde(default)]
     pub protected: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retention_tags: Vec<String>,
+    /// Only the inventory of the snapshot was synced, its archives lack their data
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub inventory_only: bool,
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        "backup": { type: BackupDir },
+        archive: { schema: BACKUP_ARCHIVE_NAME_SCHEMA },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// An entry found by searching the catalogs of the snapshots.
+pub struct FileSearchResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+    #[serde(flatten)]
+    pub backup: BackupDir,
+    pub archive: String,
+    /// Path of the entry within the archive.
+    pub path: String,
+    /// Base64-encoded path including the archive name, as used for downloading single files.
+    pub filepath: String,
+    /// Type of the entry, as in the catalog listing.
+    #[serde(rename = "type")]
+    pub entry_type: String,
+    /// The file size, if the entry is a file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// The modification time of the file, if the entry is a file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+}
+
+#[api]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// State of the signature of a manifest, as far as the server can tell.
+///
+/// The server never has access to the encryption keys, so it cannot verify the signature itself,
+/// only compare the key fingerprint with the one of the key stored for the backup group.
+pub enum ManifestSignatureState {
+    /// The manifest is not signed.
+    Unsigned,
+    /// The manifest is signed, but no key is stored on the server for the backup group.
+    UnknownKey,
+    /// The manifest is signed with the key stored on the server for the backup group.
+    KeyMatches,
+    /// The manifest is signed with another key than the one stored for the backup group.
+    KeyMismatch,
+}
+
+#[api(
+    properties: {
+        "backup": { type: BackupDir },
+        files: {
+            items: { type: BackupContent },
+        },
+        fingerprint: {
+            type: String,
+            optional: true,
+        },
+        "known-fingerprint": {
+            type: String,
+            optional: true,
+        },
+        "signature-state": { type: ManifestSignatureState },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// The manifest of a backup snapshot, with information about its signature.
+pub struct ManifestInfo {
+    #[serde(flatten)]
+    pub backup: BackupDir,
+    /// Version of the manifest format.
+    pub version: u64,
+    /// The files of the snapshot.
+    pub files: Vec<BackupContent>,
+    /// The signature of the manifest (hex encoded), if signed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Fingerprint of the key the manifest is signed with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<Fingerprint>,
+    /// Fingerprint of the key stored on the server for the backup group.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub known_fingerprint: Option<Fingerprint>,
+    pub signature_state: ManifestSignatureState,
+}
+
+#[api]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Type of a backup protocol session.
+pub enum SessionType {
+    /// Backup session, uploading a new snapshot.
+    Backup,
+    /// Reader session, downloading from an existing snapshot.
+    Reader,
+}
+
+#[api(
+    properties: {
+        "session-type": { type: SessionType },
+        upid: { schema: UPID::API_SCHEMA },
+        "auth-id": { type: Authid },
+        client: {
+            type: String,
+            optional: true,
+        },
+        store: { schema: DATASTORE_SCHEMA },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        "backup": { type: BackupDir },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// An active backup or reader session.
+pub struct ActiveSession {
+    pub session_type: SessionType,
+    /// The task of the session.
+    pub upid: String,
+    pub auth_id: Authid,
+    /// IP address of the client.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    pub store: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+    #[serde(flatten)]
+    pub backup: BackupDir,
+    /// Session start time (epoch).
+    pub starttime: i64,
+    /// Bytes uploaded (backup) or downloaded (reader) so far.
+    pub transferred: u64,
 }
 
 #[api(
@@ -1197,6 +1791,10 @@ pub struct SnapshotListItem {
             type: Authid,
             optional: true,
         },
+        health: {
+            type: GroupHealth,
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
@@ -1217,6 +1815,8 @@ pub struct GroupListItem {
     /// The first line from group "notes"
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<GroupHealth>,
 }
 
 #[api()]
@@ -1238,6 +1838,10 @@ pub struct NamespaceListItem {
 #[api(
     properties: {
         "backup": { type: BackupDir },
+        "retention-tags": {
+            schema: RETENTION_TAG_LIST_SCHEMA,
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize)]
@@ -1249,6 +1853,9 @@ pub struct PruneListItem {
 
     /// Keep snapshot
     pub keep: bool,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retention_tags: Vec<String>,
 }
 
 #[api(
@@ -1443,6 +2050,66 @@ pub struct DataStoreStatusListItem {
     pub gc_status: Option<GarbageCollectionStatus>,
 }
 
+#[api(
+    properties: {
+        store: {
+            schema: DATASTORE_SCHEMA,
+        },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        "missed-backups": {
+            type: Array,
+            items: {
+                type: String,
+                description: "Group which missed its backup window.",
+            },
+        },
+        "rpo-violations": {
+            type: Array,
+            items: {
+                type: String,
+                description: "Gap between two backups exceeding the allowed maximum age.",
+            },
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Backup compliance report of a datastore (or namespace) for a time period.
+pub struct ComplianceReport {
+    pub store: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+    /// Start of the reported period (epoch).
+    pub since: i64,
+    /// End of the reported period (epoch).
+    pub until: i64,
+    /// Number of successful backup tasks of the whole datastore. Only included for users with
+    /// audit privileges on the datastore.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backups_ok: Option<u64>,
+    /// Number of failed backup tasks of the whole datastore.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backups_failed: Option<u64>,
+    /// Percentage of successful backup tasks (100 if there were none).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub success_rate: Option<f64>,
+    /// Number of snapshots created in the period.
+    pub snapshots: u64,
+    /// Number of those snapshots which were verified successfully.
+    pub verified: u64,
+    /// Number of those snapshots which failed verification.
+    pub verify_failed: u64,
+    /// Percentage of snapshots with a successful verification (100 if there were none).
+    pub verify_coverage: f64,
+    /// Groups which currently miss their expected backup window.
+    pub missed_backups: Vec<String>,
+    /// Recovery point objective violations in the period.
+    pub rpo_violations: Vec<String>,
+}
+
 impl DataStoreStatusListItem {
     pub fn empty(store: &str, err: Option<String>) -> Self {
         DataStoreStatusListItem {
@@ -1478,6 +2145,15 @@ pub const ADMIN_DATASTORE_LIST_SNAPSHOT_FILES_RETURN_TYPE: ReturnType = ReturnTy
     .schema(),
 };
 
+pub const ADMIN_DATASTORE_SEARCH_FILES_RETURN_TYPE: ReturnType = ReturnType {
+    optional: false,
+    schema: &ArraySchema::new(
+        "Returns the matching catalog entries, newest snapshots first.",
+        &FileSearchResult::API_SCHEMA,
+    )
+    .schema(),
+};
+
 pub const ADMIN_DATASTORE_LIST_GROUPS_RETURN_TYPE: ReturnType = ReturnType {
     optional: false,
     schema: &ArraySchema::new(
@@ -1532,6 +2208,82 @@ pub struct TapeRestoreNamespace {
     pub max_depth: Option<usize>,
 }
 
+#[api(
+    properties: {
+        digest: {
+            schema: CHUNK_DIGEST_SCHEMA,
+        },
+        snapshots: {
+            type: Array,
+            items: {
+                description: "Snapshot referencing the chunk.",
+                type: String,
+            },
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A chunk found corrupt by verification.
+pub struct QuarantinedChunk {
+    pub digest: String,
+    /// Time the chunk was first found corrupt (epoch).
+    pub detected: i64,
+    /// Why the chunk was found corrupt.
+    pub reason: String,
+    /// Snapshots found referencing the chunk.
+    pub snapshots: Vec<String>,
+}
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        group: {
+            type: BackupGroup,
+            flatten: true,
+        },
+        owner: {
+            type: Authid,
+        },
+        "removed-by": {
+            type: Authid,
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A removed backup group, whose name is kept from being reused for a while.
+pub struct GroupTombstone {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+    #[serde(flatten)]
+    pub group: BackupGroup,
+    /// The owner of the group when it was removed.
+    pub owner: Authid,
+    /// Time the group was removed (epoch).
+    pub removed: i64,
+    /// The user or token which removed the group.
+    pub removed_by: Authid,
+    /// Time the tombstone expires (epoch).
+    pub expires: i64,
+}
+
+#[api]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A snapshot without manifest, left behind by a running, aborted or crashed backup.
+pub struct UnfinishedSnapshot {
+    /// The snapshot, prefixed by its namespace.
+    pub snapshot: String,
+    /// Last modification of the snapshot directory (epoch).
+    pub last_modified: i64,
+    /// Whether the snapshot is locked, e.g. by a running backup.
+    pub in_use: bool,
+}
+
 pub const TAPE_RESTORE_NAMESPACE_SCHEMA: Schema = StringSchema::new("A namespace mapping")
     .format(&ApiStringFormat::PropertyString(
         &TapeRestoreNamespace::API_SCHEMA,
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, IntegerSchema, Schema, StringSchema, Updater};
+
+use crate::{PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_SCHEMA};
+
+pub const HOOK_ID_SCHEMA: Schema = StringSchema::new("Hook ID.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(3)
+    .max_length(32)
+    .schema();
+
+pub const HOOK_SCRIPT_SCHEMA: Schema =
+    StringSchema::new("Absolute path of the executable run for the events.")
+        .min_length(2)
+        .max_length(1024)
+        .schema();
+
+pub const HOOK_TIMEOUT_SCHEMA: Schema =
+    IntegerSchema::new("Kill the hook if it did not finish after this many seconds.")
+        .minimum(1)
+        .maximum(3600)
+        .default(60)
+        .schema();
+
+#[api]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Event triggering a hook.
+pub enum HookEvent {
+    /// A backup task finished, successfully or not.
+    BackupFinish,
+    /// A backup task failed.
+    BackupFailure,
+    /// A verification task or job finished, successfully or not.
+    VerifyFinish,
+    /// A verification task or job failed.
+    VerifyFailure,
+}
+serde_plain::derive_display_from_serialize!(HookEvent);
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+#[api(
+    properties: {
+        id: {
+            schema: HOOK_ID_SCHEMA,
+        },
+        event: {
+            type: Array,
+            items: {
+                type: HookEvent,
+            },
+        },
+        script: {
+            schema: HOOK_SCRIPT_SCHEMA,
+        },
+        timeout: {
+            schema: HOOK_TIMEOUT_SCHEMA,
+            optional: true,
+        },
+        disable: {
+            type: Boolean,
+            optional: true,
+            default: false,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize, PartialEq, Updater)]
+#[serde(rename_all = "kebab-case")]
+/// Script run on the server when one of its events occurs.
+pub struct HookConfig {
+    #[updater(skip)]
+    pub id: String,
+    pub event: Vec<HookEvent>,
+    pub script: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Do not run this hook.
+    #[serde(default, skip_serializing_if = "is_false")]
+    #[updater(serde(skip_serializing_if = "Option::is_none"))]
+    pub disable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
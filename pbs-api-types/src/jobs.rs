@@ -8,10 +8,11 @@ use serde::{Deserialize, Serialize};
 use proxmox_schema::*;
 
 use crate::{
-    Authid, BackupNamespace, BackupType, NotificationMode, RateLimitConfig, Userid,
-    BACKUP_GROUP_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_NS_RE, DATASTORE_SCHEMA,
-    DRIVE_NAME_SCHEMA, MEDIA_POOL_NAME_SCHEMA, NS_MAX_DEPTH_REDUCED_SCHEMA, PROXMOX_SAFE_ID_FORMAT,
-    PROXMOX_SAFE_ID_REGEX_STR, REMOTE_ID_SCHEMA, SINGLE_LINE_COMMENT_SCHEMA,
+    Authid, BackupGroup, BackupNamespace, BackupType, NotificationMode, RateLimitConfig,
+    TaskStateType, Userid, BACKUP_GROUP_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_NS_RE,
+    DATASTORE_SCHEMA, DRIVE_NAME_SCHEMA, MEDIA_POOL_NAME_SCHEMA, NS_MAX_DEPTH_REDUCED_SCHEMA,
+    PROXMOX_SAFE_ID_FORMAT, PROXMOX_SAFE_ID_REGEX_STR, REMOTE_ID_SCHEMA,
+    SINGLE_LINE_COMMENT_SCHEMA, UPID,
 };
 
 const_regex! {
@@ -86,6 +87,22 @@ pub const REMOVE_VANISHED_BACKUPS_SCHEMA: Schema = BooleanSchema::new(
             optional: true,
             type: Integer,
         },
+        "last-run-duration": {
+            description: "Duration of the last run in seconds.",
+            optional: true,
+            type: Integer,
+        },
+        "success-streak": {
+            description: "Number of consecutive runs without errors, up to the last run.",
+            optional: true,
+            type: Integer,
+        },
+        history: {
+            description: "The most recent runs, newest first.",
+            optional: true,
+            type: Array,
+            items: { type: JobRunInfo },
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
@@ -100,6 +117,60 @@ pub struct JobScheduleStatus {
     pub last_run_upid: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_run_endtime: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_run_duration: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub success_streak: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<JobRunInfo>>,
+}
+
+#[api(
+    properties: {
+        upid: { schema: UPID::API_SCHEMA },
+        status: { type: TaskStateType },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A finished run of a job
+pub struct JobRunInfo {
+    pub upid: String,
+    /// Start time (UNIX epoch).
+    pub starttime: i64,
+    /// End time (UNIX epoch).
+    pub endtime: i64,
+    pub status: TaskStateType,
+}
+
+#[api()]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Type of a scheduled job
+pub enum ScheduledJobType {
+    /// Sync job
+    Sync,
+    /// Verification job
+    Verify,
+    /// Prune job
+    Prune,
+    /// Tape backup job
+    TapeBackup,
+    /// Garbage collection of a datastore, the job id is the datastore name
+    GarbageCollection,
+}
+
+impl ScheduledJobType {
+    /// The job type as used for the job state files.
+    pub fn jobstate_type(&self) -> &'static str {
+        match self {
+            ScheduledJobType::Sync => "syncjob",
+            ScheduledJobType::Verify => "verificationjob",
+            ScheduledJobType::Prune => "prunejob",
+            ScheduledJobType::TapeBackup => "tape-backup-job",
+            ScheduledJobType::GarbageCollection => "garbage_collection",
+        }
+    }
 }
 
 #[api()]
@@ -203,6 +274,14 @@ pub const VERIFICATION_OUTDATED_AFTER_SCHEMA: Schema =
             optional: true,
             schema: crate::NS_MAX_DEPTH_SCHEMA,
         },
+        notify: {
+            type: Notify,
+            optional: true,
+        },
+        "notify-user": {
+            type: Userid,
+            optional: true,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Updater, Clone, PartialEq)]
@@ -233,6 +312,12 @@ pub struct VerificationJobConfig {
     /// how deep the verify should go from the `ns` level downwards. Passing 0 verifies only the
     /// snapshots on the same level as the passed `ns`, or the datastore root if none.
     pub max_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// when to send notifications, overrides the datastore's 'notify' setting for verify jobs
+    pub notify: Option<Notify>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// send email notifications to this user, overrides the datastore's 'notify-user'
+    pub notify_user: Option<Userid>,
 }
 
 impl VerificationJobConfig {
@@ -498,6 +583,20 @@ pub const TRANSFER_LAST_SCHEMA: Schema =
         .minimum(1)
         .schema();
 
+pub const SYNC_METADATA_SCHEMA: Schema = BooleanSchema::new(
+    "Also sync group owners and notes, as well as notes, protection and verification state of \
+    snapshots, overwriting the local values.",
+)
+.default(false)
+.schema();
+
+pub const SYNC_INVENTORY_ONLY_SCHEMA: Schema = BooleanSchema::new(
+    "Only sync manifests and blobs, but not the archives and their chunks. Snapshots synced \
+    like this are completed by a later sync without this option.",
+)
+.default(false)
+.schema();
+
 #[api(
     properties: {
         id: {
@@ -552,6 +651,22 @@ pub const TRANSFER_LAST_SCHEMA: Schema =
             schema: TRANSFER_LAST_SCHEMA,
             optional: true,
         },
+        "sync-metadata": {
+            schema: SYNC_METADATA_SCHEMA,
+            optional: true,
+        },
+        "inventory-only": {
+            schema: SYNC_INVENTORY_ONLY_SCHEMA,
+            optional: true,
+        },
+        notify: {
+            type: Notify,
+            optional: true,
+        },
+        "notify-user": {
+            type: Userid,
+            optional: true,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
@@ -585,6 +700,16 @@ pub struct SyncJobConfig {
     pub limit: RateLimitConfig,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub transfer_last: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_metadata: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inventory_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// When to send notifications, overrides the datastore's 'notify' setting for sync jobs.
+    pub notify: Option<Notify>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Send email notifications to this user, overrides the datastore's 'notify-user'.
+    pub notify_user: Option<Userid>,
 }
 
 impl SyncJobConfig {
@@ -742,6 +867,14 @@ impl PruneJobOptions {
         options: {
             type: PruneJobOptions,
         },
+        notify: {
+            type: Notify,
+            optional: true,
+        },
+        "notify-user": {
+            type: Userid,
+            optional: true,
+        },
     },
 )]
 #[derive(Deserialize, Serialize, Updater, Clone, PartialEq)]
@@ -766,6 +899,14 @@ pub struct PruneJobConfig {
 
     #[serde(flatten)]
     pub options: PruneJobOptions,
+
+    /// When to send notifications, overrides the datastore's 'notify' setting for prune jobs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify: Option<Notify>,
+
+    /// Send email notifications to this user, overrides the datastore's 'notify-user'.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_user: Option<Userid>,
 }
 
 impl PruneJobConfig {
@@ -797,3 +938,111 @@ pub struct PruneJobStatus {
     #[serde(flatten)]
     pub status: JobScheduleStatus,
 }
+
+pub const EXPECTED_BACKUP_MAX_AGE_SCHEMA: Schema = IntegerSchema::new(
+    "Maximum age (in hours) of the last finished backup of each matching group.",
+)
+.minimum(1)
+.schema();
+
+#[api(
+    properties: {
+        id: {
+            schema: JOB_ID_SCHEMA,
+        },
+        store: {
+            schema: DATASTORE_SCHEMA,
+        },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        "group-filter": {
+            schema: GROUP_FILTER_LIST_SCHEMA,
+            optional: true,
+        },
+        "max-age": {
+            schema: EXPECTED_BACKUP_MAX_AGE_SCHEMA,
+        },
+        disable: {
+            type: Boolean,
+            optional: true,
+            default: false,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+    },
+)]
+#[derive(Deserialize, Serialize, Updater, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Expected backups of a set of groups.
+///
+/// All groups in the namespace matching the group filters are expected to have a finished
+/// backup not older than 'max-age' hours. Groups named explicitly with a 'group:' include filter
+/// are expected even if they do not exist (yet).
+pub struct ExpectedBackupConfig {
+    /// unique ID to address this entry
+    #[updater(skip)]
+    pub id: String,
+
+    pub store: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_filter: Option<Vec<GroupFilter>>,
+
+    pub max_age: u64,
+
+    /// Disable monitoring of this entry.
+    #[serde(default, skip_serializing_if = "is_false")]
+    #[updater(serde(skip_serializing_if = "Option::is_none"))]
+    pub disable: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl ExpectedBackupConfig {
+    pub fn acl_path(&self) -> Vec<&str> {
+        match &self.ns {
+            Some(ns) => ns.acl_path(&self.store),
+            None => vec!["datastore", &self.store],
+        }
+    }
+}
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        group: {
+            type: BackupGroup,
+        },
+        "last-backup": {
+            description: "Time of the last finished backup.",
+            optional: true,
+            type: Integer,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Status of a group monitored by an expected backup entry.
+pub struct ExpectedBackupGroupStatus {
+    /// The expected backup entry.
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+    #[serde(flatten)]
+    pub group: BackupGroup,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_backup: Option<i64>,
+    /// The last backup is missing or older than allowed.
+    pub overdue: bool,
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, IntegerSchema, Schema, StringSchema, Updater};
+
+use crate::{PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_SCHEMA};
+
+pub const KMS_ID_SCHEMA: Schema = StringSchema::new("Key management service ID.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(3)
+    .max_length(32)
+    .schema();
+
+pub const KMS_CACHE_TIME_SCHEMA: Schema = IntegerSchema::new(
+    "Seconds a fetched key is cached (in memory backed storage, 0 disables caching).",
+)
+.minimum(0)
+.maximum(86400)
+.default(300)
+.schema();
+
+#[api()]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Key management service backend.
+pub enum KmsBackend {
+    /// HashiCorp Vault, KV secrets engine (version 2).
+    Vault,
+    /// External helper command, e.g. a wrapper around a cloud KMS CLI.
+    ///
+    /// The command is called as `<command> get <fingerprint>` and has to print the hex encoded
+    /// key, and as `<command> put <fingerprint>` with the hex encoded key on stdin.
+    Command,
+}
+
+#[api()]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Behavior when the key management service cannot be reached.
+pub enum KmsFailurePolicy {
+    /// Fail the operation.
+    #[default]
+    Fail,
+    /// Use a previously cached key, even if its cache time has expired.
+    UseStale,
+}
+
+#[api(
+    properties: {
+        id: {
+            schema: KMS_ID_SCHEMA,
+        },
+        backend: {
+            type: KmsBackend,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+        url: {
+            optional: true,
+            type: String,
+            description: "Vault server URL, for example 'https://vault.example.com:8200'.",
+        },
+        mount: {
+            optional: true,
+            type: String,
+            description: "Vault KV mount point (default 'secret').",
+        },
+        path: {
+            optional: true,
+            type: String,
+            description: "Vault path below the mount point, the key fingerprint is appended.",
+        },
+        token: {
+            optional: true,
+            type: String,
+            description: "Vault access token.",
+        },
+        command: {
+            optional: true,
+            type: String,
+            description: "Absolute path of the helper command.",
+        },
+        "cache-time": {
+            optional: true,
+            schema: KMS_CACHE_TIME_SCHEMA,
+        },
+        "failure-policy": {
+            optional: true,
+            type: KmsFailurePolicy,
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize, Updater, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// External key management service, used to fetch encryption keys at use time.
+pub struct KmsConfig {
+    #[updater(skip)]
+    pub id: String,
+    pub backend: KmsBackend,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mount: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_time: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure_policy: Option<KmsFailurePolicy>,
+}
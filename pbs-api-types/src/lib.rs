@@ -55,8 +55,9 @@ use proxmox_schema::api_types::{DNS_NAME_STR, IPRE_BRACKET_STR};
 #[rustfmt::skip]
 pub const BACKUP_ID_RE: &str = r"[A-Za-z0-9_][A-Za-z0-9._\-]*";
 
+// the built-in host, vm and ct, or a custom type - "ns" is reserved for namespace directories
 #[rustfmt::skip]
-pub const BACKUP_TYPE_RE: &str = r"(?:host|vm|ct)";
+pub const BACKUP_TYPE_RE: &str = r"(?:[a-mo-z][a-z0-9]|n[a-rt-z0-9]|[a-z][a-z0-9]{2,15})";
 
 #[rustfmt::skip]
 pub const BACKUP_TIME_RE: &str = r"[0-9]{4}-[0-9]{2}-[0-9]{2}T[0-9]{2}:[0-9]{2}:[0-9]{2}Z";
@@ -89,18 +90,27 @@ pub use acl::*;
 mod datastore;
 pub use datastore::*;
 
+mod hook;
+pub use hook::*;
+
 mod jobs;
 pub use jobs::*;
 
 mod key_derivation;
 pub use key_derivation::{Kdf, KeyInfo};
 
+mod kms;
+pub use kms::*;
+
 mod maintenance;
 pub use maintenance::*;
 
 mod network;
 pub use network::*;
 
+mod network_share;
+pub use network_share::*;
+
 mod node;
 pub use node::*;
 
@@ -226,6 +236,13 @@ pub const PROXMOX_CONFIG_DIGEST_SCHEMA: Schema = StringSchema::new(
 .format(&PVE_CONFIG_DIGEST_FORMAT)
 .schema();
 
+pub const CONFIRMATION_TOKEN_SCHEMA: Schema = StringSchema::new(
+    "Confirmation token returned by the corresponding preview call. Required if safe mode \
+    is enabled on the node.",
+)
+.max_length(128)
+.schema();
+
 /// API schema format definition for repository URLs
 pub const BACKUP_REPO_URL: ApiStringFormat = ApiStringFormat::Pattern(&BACKUP_REPO_URL_REGEX);
 
@@ -243,6 +260,92 @@ pub struct StorageStatus {
     pub avail: u64,
 }
 
+#[api(
+    properties: {
+        items: {
+            type: Array,
+            items: {
+                type: String,
+                description: "Item which will be removed.",
+            },
+        },
+        token: {
+            schema: CONFIRMATION_TOKEN_SCHEMA,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Preview of a destructive operation.
+pub struct RemovalPreview {
+    /// Everything which will be removed by the operation.
+    pub items: Vec<String>,
+    /// Token to pass to the operation to confirm it.
+    pub token: String,
+    /// Expiration time of the token (epoch).
+    pub expires: i64,
+}
+
+#[api()]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A problem found by the configuration check.
+pub struct ConfigProblem {
+    /// The configuration file, e.g. "sync.cfg".
+    pub config: String,
+    /// The entry of the configuration file the problem refers to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Description of the problem.
+    pub message: String,
+}
+
+pub const NOTIFICATION_ID_SCHEMA: Schema = StringSchema::new("Notification ID.")
+    .format(&UUID_FORMAT)
+    .schema();
+
+#[api()]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// State of a queued notification.
+pub enum QueuedNotificationState {
+    /// Waiting to be sent, or to be retried.
+    Pending,
+    /// Sending failed too often, the notification is not retried anymore.
+    Failed,
+}
+
+#[api(
+    properties: {
+        id: {
+            schema: NOTIFICATION_ID_SCHEMA,
+        },
+        state: {
+            type: QueuedNotificationState,
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A notification waiting in the queue.
+pub struct QueuedNotificationInfo {
+    pub id: String,
+    /// Creation time of the notification (epoch).
+    pub time: i64,
+    pub state: QueuedNotificationState,
+    /// Number of failed attempts to send the notification.
+    pub attempts: u64,
+    /// Time of the next attempt (epoch).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_attempt: Option<i64>,
+    /// Error of the last attempt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Recipients, if the notification is sent via sendmail directly (legacy mode).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mailto: Option<String>,
+}
+
 pub const PASSWORD_HINT_SCHEMA: Schema = StringSchema::new("Password hint.")
     .format(&SINGLE_LINE_COMMENT_FORMAT)
     .min_length(1)
@@ -289,7 +392,7 @@ pub enum NodePowerCommand {
 }
 
 #[api()]
-#[derive(Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TaskStateType {
     /// Ok
@@ -0,0 +1,230 @@
+use anyhow::{bail, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, const_regex, ApiStringFormat, Schema, StringSchema, Updater};
+
+use crate::{
+    DNS_NAME_OR_IP_SCHEMA, PASSWORD_FORMAT, PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_SCHEMA,
+};
+
+/// Directory below which network shares are mounted.
+pub const NETWORK_SHARE_MOUNT_DIR: &str = "/mnt/network-share";
+
+const_regex! {
+    // no whitespace, commas or backslashes, as they would break the mount unit and options
+    pub NETWORK_SHARE_PATH_REGEX = r"^[^\s,\\]+$";
+    pub NETWORK_SHARE_OPTIONS_REGEX = r"^[\w.:/=\-]+(?:,[\w.:/=\-]+)*$";
+    pub NETWORK_SHARE_USER_REGEX = r"^[^\s,=\\]+$";
+}
+
+pub const NETWORK_SHARE_PATH_FORMAT: ApiStringFormat =
+    ApiStringFormat::Pattern(&NETWORK_SHARE_PATH_REGEX);
+pub const NETWORK_SHARE_OPTIONS_FORMAT: ApiStringFormat =
+    ApiStringFormat::VerifyFn(verify_network_share_options);
+pub const NETWORK_SHARE_USER_FORMAT: ApiStringFormat =
+    ApiStringFormat::Pattern(&NETWORK_SHARE_USER_REGEX);
+
+/// Mount options which may be set for a share, with or without a value.
+///
+/// Everything else is refused, as options like `uid`, `credentials`, `suid` or the `x-systemd.*`
+/// ones change who owns and may access the datastore, or what the mount unit does.
+pub const NETWORK_SHARE_ALLOWED_OPTIONS: &[&str] = &[
+    // NFS
+    "vers",
+    "nfsvers",
+    "proto",
+    "port",
+    "mountport",
+    "timeo",
+    "retrans",
+    "hard",
+    "soft",
+    "softreval",
+    "rsize",
+    "wsize",
+    "sec",
+    "actimeo",
+    "ac",
+    "noac",
+    "lookupcache",
+    "nconnect",
+    "lock",
+    "nolock",
+    "local_lock",
+    // CIFS
+    "seal",
+    "cache",
+    "multichannel",
+    "max_channels",
+    "echo_interval",
+    "handletimeout",
+    "serverino",
+    "noserverino",
+    "nosharesock",
+    "iocharset",
+    // generic
+    "noatime",
+    "relatime",
+    "nodiratime",
+];
+
+fn verify_network_share_options(options: &str) -> Result<(), Error> {
+    if !NETWORK_SHARE_OPTIONS_REGEX.is_match(options) {
+        bail!("value does not match the regex pattern");
+    }
+    for option in options.split(',') {
+        let name = option.split_once('=').map_or(option, |(name, _)| name);
+        if !NETWORK_SHARE_ALLOWED_OPTIONS.contains(&name) {
+            bail!("mount option '{name}' is not allowed");
+        }
+    }
+    Ok(())
+}
+
+pub const NETWORK_SHARE_ID_SCHEMA: Schema = StringSchema::new("Network share ID.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(3)
+    .max_length(32)
+    .schema();
+
+pub const NETWORK_SHARE_PATH_SCHEMA: Schema = StringSchema::new(
+    "Exported path for NFS, share name (optionally followed by a sub directory) for CIFS.",
+)
+.format(&NETWORK_SHARE_PATH_FORMAT)
+.min_length(1)
+.max_length(1024)
+.schema();
+
+pub const NETWORK_SHARE_OPTIONS_SCHEMA: Schema = StringSchema::new(
+    "Additional comma separated mount options, see the documentation for the allowed ones.",
+)
+.format(&NETWORK_SHARE_OPTIONS_FORMAT)
+.max_length(1024)
+.schema();
+
+pub const NETWORK_SHARE_USER_SCHEMA: Schema = StringSchema::new("CIFS user name.")
+    .format(&NETWORK_SHARE_USER_FORMAT)
+    .max_length(256)
+    .schema();
+
+pub const NETWORK_SHARE_DOMAIN_SCHEMA: Schema = StringSchema::new("CIFS domain.")
+    .format(&NETWORK_SHARE_USER_FORMAT)
+    .max_length(256)
+    .schema();
+
+pub const NETWORK_SHARE_PASSWORD_SCHEMA: Schema = StringSchema::new("CIFS password.")
+    .format(&PASSWORD_FORMAT)
+    .max_length(1024)
+    .schema();
+
+#[api]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Network share protocol.
+pub enum NetworkShareType {
+    /// CIFS/SMB share.
+    Cifs,
+    /// NFS export.
+    Nfs,
+}
+serde_plain::derive_display_from_serialize!(NetworkShareType);
+
+#[api(
+    properties: {
+        name: {
+            schema: NETWORK_SHARE_ID_SCHEMA,
+        },
+        "share-type": {
+            type: NetworkShareType,
+        },
+        server: {
+            schema: DNS_NAME_OR_IP_SCHEMA,
+        },
+        path: {
+            schema: NETWORK_SHARE_PATH_SCHEMA,
+        },
+        username: {
+            schema: NETWORK_SHARE_USER_SCHEMA,
+            optional: true,
+        },
+        domain: {
+            schema: NETWORK_SHARE_DOMAIN_SCHEMA,
+            optional: true,
+        },
+        options: {
+            schema: NETWORK_SHARE_OPTIONS_SCHEMA,
+            optional: true,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize, PartialEq, Updater)]
+#[serde(rename_all = "kebab-case")]
+/// CIFS or NFS share mounted on the server, to be used as datastore path.
+pub struct NetworkShareConfig {
+    #[updater(skip)]
+    pub name: String,
+    #[updater(skip)]
+    pub share_type: NetworkShareType,
+    pub server: String,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl NetworkShareConfig {
+    /// Returns the path the share is mounted on.
+    pub fn mount_point(&self) -> String {
+        format!("{NETWORK_SHARE_MOUNT_DIR}/{}", self.name)
+    }
+
+    /// Returns the source of the mount, in the format expected by the mount helpers.
+    pub fn mount_source(&self) -> String {
+        match self.share_type {
+            NetworkShareType::Cifs => {
+                format!("//{}/{}", self.server, self.path.trim_start_matches('/'))
+            }
+            // IPv6 addresses need to be enclosed in brackets
+            NetworkShareType::Nfs if self.server.contains(':') => {
+                format!("[{}]:{}", self.server, self.path)
+            }
+            NetworkShareType::Nfs => format!("{}:{}", self.server, self.path),
+        }
+    }
+}
+
+#[api(
+    properties: {
+        name: {
+            schema: NETWORK_SHARE_ID_SCHEMA,
+        },
+        "share-type": {
+            type: NetworkShareType,
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Mount status of a network share.
+pub struct NetworkShareStatus {
+    pub name: String,
+    pub share_type: NetworkShareType,
+    /// Source of the mount.
+    pub source: String,
+    /// Path the share is mounted on.
+    pub mount_point: String,
+    /// The share is mounted and responding.
+    pub available: bool,
+    /// Why the share is not available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
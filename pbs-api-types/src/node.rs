@@ -3,7 +3,7 @@ use std::ffi::OsStr;
 use proxmox_schema::*;
 use serde::{Deserialize, Serialize};
 
-use crate::StorageStatus;
+use crate::{StorageStatus, JOB_ID_SCHEMA, REMOTE_ID_SCHEMA};
 
 #[api]
 #[derive(Serialize, Deserialize, Default)]
@@ -160,3 +160,90 @@ pub struct NodeStatus {
     /// Current boot mode
     pub boot_info: BootModeInformation,
 }
+
+#[api]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Health state of a server component
+pub enum HealthState {
+    /// The component works as expected.
+    Ok,
+    /// The component has a problem.
+    Error,
+}
+
+#[api]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+/// Health of a single server component
+pub struct HealthComponent {
+    /// Component name, e.g. `config`, `scheduler` or `datastore/<name>`.
+    pub name: String,
+    pub status: HealthState,
+    /// Description of the problem, only included for privileged users.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[api(
+    properties: {
+        components: {
+            type: Array,
+            items: { type: HealthComponent },
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+/// Overall server health
+pub struct HealthStatus {
+    /// `ok` if all components are healthy.
+    pub status: HealthState,
+    pub components: Vec<HealthComponent>,
+}
+
+#[api]
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Role of a node in an active-standby failover pair
+pub enum FailoverRole {
+    /// The node accepts backups.
+    #[default]
+    Active,
+    /// The node only pulls from its peer and refuses new backups.
+    Standby,
+}
+
+#[api(
+    properties: {
+        peer: {
+            schema: REMOTE_ID_SCHEMA,
+            optional: true,
+        },
+        "sync-job": {
+            schema: JOB_ID_SCHEMA,
+            optional: true,
+        },
+        "active-backups": {
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+/// Failover state of a node
+pub struct FailoverStatus {
+    pub role: FailoverRole,
+    /// Incremented on every promotion, the node with the higher epoch is the rightful active one.
+    pub epoch: u64,
+    /// Time of the last role change.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_job: Option<String>,
+    /// Number of backup sessions currently running on this node.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_backups: Option<usize>,
+}
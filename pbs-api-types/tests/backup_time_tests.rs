@@ -0,0 +1,19 @@
+use pbs_api_types::{backup_time_from_string, backup_time_to_string, BackupDir};
+
+#[test]
+fn test_backup_time_roundtrip() {
+    let time = backup_time_from_string("2020-06-15T05:18:33Z").unwrap();
+    assert_eq!(time, 1592198313);
+    assert_eq!(backup_time_to_string(time).unwrap(), "2020-06-15T05:18:33Z");
+}
+
+#[test]
+fn test_backup_time_non_canonical() {
+    assert!(backup_time_from_string("2020-06-15T07:18:33+02:00").is_err());
+    assert!(backup_time_from_string("2020-06-15T05:18:33.5Z").is_err());
+    assert!(backup_time_from_string("2020-06-15 05:18:33Z").is_err());
+    assert!(backup_time_from_string("2020-02-30T05:18:33Z").is_err());
+    assert!("host/elsa/2020-06-15T07:18:33+02:00"
+        .parse::<BackupDir>()
+        .is_err());
+}
@@ -71,6 +71,11 @@ pub const PROXMOX_BACKUP_LOG_DIR: &str = PROXMOX_BACKUP_LOG_DIR_M!();
 /// failed logins can be logged here with full information, use the auth log for that.
 pub const API_ACCESS_LOG_FN: &str = concat!(PROXMOX_BACKUP_LOG_DIR_M!(), "/api/access.log");
 
+/// optional logfile with one JSON object per API request handled by the proxy, meant as input for
+/// security monitoring.
+pub const API_STRUCTURED_ACCESS_LOG_FN: &str =
+    concat!(PROXMOX_BACKUP_LOG_DIR_M!(), "/api/access.json.log");
+
 /// logfile for any failed authentication, via ticket or via token, and new successful ticket
 /// creations. This file can be useful for fail2ban.
 pub const API_AUTH_LOG_FN: &str = concat!(PROXMOX_BACKUP_LOG_DIR_M!(), "/api/auth.log");
@@ -0,0 +1,121 @@
+/*
+ * C interface to the Proxmox Backup client library (libpbs_client_ffi.so).
+ *
+ * Functions with an "_async" suffix return immediately, store the result in
+ * *result (negative on error, with *error set) and then call
+ * callback(callback_data) from a library worker thread.
+ *
+ * Passing a NULL handle is reported as error. The callback, result and error
+ * pointers may be NULL if the caller is not interested in them.
+ *
+ * Error strings must be released with proxmox_backup_free_error().
+ */
+
+#ifndef PBS_CLIENT_FFI_H
+#define PBS_CLIENT_FFI_H
+
+#include <stdint.h>
+
+/* Images are split into chunks of this size (the last chunk may be smaller). */
+#define PROXMOX_BACKUP_IMAGE_CHUNK_SIZE ((uint64_t)4 * 1024 * 1024)
+
+typedef struct ProxmoxBackupHandle ProxmoxBackupHandle;
+typedef struct ProxmoxRestoreHandle ProxmoxRestoreHandle;
+
+typedef void (*ProxmoxBackupCallback)(void *callback_data);
+
+void proxmox_backup_free_error(char *ptr);
+
+/* backup */
+
+ProxmoxBackupHandle *proxmox_backup_new(const char *repo,
+                                        const char *backup_ns,
+                                        const char *backup_type,
+                                        const char *backup_id,
+                                        uint64_t backup_time,
+                                        const char *password,
+                                        const char *keyfile,
+                                        const char *key_password,
+                                        const char *fingerprint,
+                                        char **error);
+
+void proxmox_backup_connect_async(ProxmoxBackupHandle *handle,
+                                  ProxmoxBackupCallback callback,
+                                  void *callback_data,
+                                  int *result,
+                                  char **error);
+
+void proxmox_backup_register_image_async(ProxmoxBackupHandle *handle,
+                                         const char *device_name,
+                                         uint64_t size,
+                                         ProxmoxBackupCallback callback,
+                                         void *callback_data,
+                                         int *result,
+                                         char **error);
+
+void proxmox_backup_write_data_async(ProxmoxBackupHandle *handle,
+                                     int dev_id,
+                                     const uint8_t *data,
+                                     uint64_t offset,
+                                     uint64_t size,
+                                     ProxmoxBackupCallback callback,
+                                     void *callback_data,
+                                     int *result,
+                                     char **error);
+
+void proxmox_backup_close_image_async(ProxmoxBackupHandle *handle,
+                                      int dev_id,
+                                      ProxmoxBackupCallback callback,
+                                      void *callback_data,
+                                      int *result,
+                                      char **error);
+
+void proxmox_backup_finish_async(ProxmoxBackupHandle *handle,
+                                 ProxmoxBackupCallback callback,
+                                 void *callback_data,
+                                 int *result,
+                                 char **error);
+
+void proxmox_backup_disconnect(ProxmoxBackupHandle *handle);
+
+/* restore */
+
+ProxmoxRestoreHandle *proxmox_restore_new(const char *repo,
+                                          const char *backup_ns,
+                                          const char *snapshot,
+                                          const char *password,
+                                          const char *keyfile,
+                                          const char *key_password,
+                                          const char *fingerprint,
+                                          char **error);
+
+void proxmox_restore_connect_async(ProxmoxRestoreHandle *handle,
+                                   ProxmoxBackupCallback callback,
+                                   void *callback_data,
+                                   int *result,
+                                   char **error);
+
+void proxmox_restore_open_image_async(ProxmoxRestoreHandle *handle,
+                                      const char *archive_name,
+                                      ProxmoxBackupCallback callback,
+                                      void *callback_data,
+                                      int *result,
+                                      char **error);
+
+long proxmox_restore_get_image_length(ProxmoxRestoreHandle *handle,
+                                      int aid,
+                                      char **error);
+
+void proxmox_restore_read_image_at_async(ProxmoxRestoreHandle *handle,
+                                         int aid,
+                                         uint8_t *data,
+                                         uint64_t offset,
+                                         uint64_t size,
+                                         ProxmoxBackupCallback callback,
+                                         void *callback_data,
+                                         int *result,
+                                         char **error);
+
+void proxmox_restore_disconnect(ProxmoxRestoreHandle *handle);
+
+#endif /* PBS_CLIENT_FFI_H */
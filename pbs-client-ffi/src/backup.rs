@@ -0,0 +1,505 @@
+//! Backup handle: write fixed size images (block devices) into a new snapshot.
+
+use std::collections::HashSet;
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+use serde_json::json;
+
+use pbs_api_types::{BackupDir, BackupNamespace, BackupType, CryptMode};
+use pbs_client::{BackupSession, BackupSessionOptions, BackupWriter};
+use pbs_datastore::data_blob::DataChunkBuilder;
+
+use crate::{
+    handle_ref, new_runtime, optional_str, required_str, set_error, CallbackPointers, ConnectInfo,
+};
+
+/// Images are always split into chunks of this size (the last chunk may be smaller).
+pub const PROXMOX_BACKUP_IMAGE_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+struct ImageState {
+    archive_name: String,
+    wid: u64,
+    size: u64,
+    digests: Vec<Option<[u8; 32]>>,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct BackupState {
+    session: Option<BackupSession>,
+    images: Vec<ImageState>,
+    finished: bool,
+}
+
+/// Opaque backup handle, see `proxmox_backup_new`.
+pub struct ProxmoxBackupHandle {
+    runtime: tokio::runtime::Runtime,
+    info: Arc<ConnectInfo>,
+    ns: BackupNamespace,
+    snapshot: BackupDir,
+    state: Arc<Mutex<BackupState>>,
+    known_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+}
+
+impl ProxmoxBackupHandle {
+    fn crypt_mode(&self) -> CryptMode {
+        if self.info.crypt_config().is_some() {
+            CryptMode::Encrypt
+        } else {
+            CryptMode::None
+        }
+    }
+
+    fn writer(&self) -> Result<Arc<BackupWriter>, Error> {
+        let state = self.state.lock().unwrap();
+        match state.session {
+            Some(ref session) => Ok(session.writer().clone()),
+            None if state.finished => bail!("backup already finished"),
+            None => bail!("not connected"),
+        }
+    }
+
+    fn image_info(&self, dev_id: c_int) -> Result<(u64, u64), Error> {
+        let state = self.state.lock().unwrap();
+        let image = usize::try_from(dev_id)
+            .ok()
+            .and_then(|id| state.images.get(id))
+            .ok_or_else(|| format_err!("invalid device id {}", dev_id))?;
+        if image.closed {
+            bail!("image '{}' already closed", image.archive_name);
+        }
+        Ok((image.wid, image.size))
+    }
+
+    fn spawn<F>(&self, cb: CallbackPointers, future: F)
+    where
+        F: std::future::Future<Output = Result<c_int, Error>> + Send + 'static,
+    {
+        self.runtime.spawn(async move {
+            let result = future.await;
+            cb.send_result(result);
+        });
+    }
+}
+
+/// Create a new backup handle, this does not connect to the server yet.
+///
+/// `backup_ns`, `keyfile`, `key_password` and `fingerprint` may be NULL. Returns NULL and sets
+/// `*error` on failure.
+///
+/// # Safety
+///
+/// All string parameters must be NULL or point to NUL terminated strings, and `error` must be
+/// NULL or valid for writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn proxmox_backup_new(
+    repo: *const c_char,
+    backup_ns: *const c_char,
+    backup_type: *const c_char,
+    backup_id: *const c_char,
+    backup_time: u64,
+    password: *const c_char,
+    keyfile: *const c_char,
+    key_password: *const c_char,
+    fingerprint: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut ProxmoxBackupHandle {
+    let result: Result<_, Error> = (|| {
+        let info = unsafe { ConnectInfo::new(repo, password, keyfile, key_password, fingerprint) }?;
+        let ns = match unsafe { optional_str(backup_ns, "backup_ns") }? {
+            Some(ns) => ns.parse()?,
+            None => BackupNamespace::root(),
+        };
+        let backup_type: BackupType =
+            unsafe { required_str(backup_type, "backup_type") }?.parse()?;
+        let backup_id = unsafe { required_str(backup_id, "backup_id") }?;
+        let snapshot = BackupDir::from((backup_type, backup_id, backup_time as i64));
+
+        Ok(Box::new(ProxmoxBackupHandle {
+            runtime: new_runtime()?,
+            info: Arc::new(info),
+            ns,
+            snapshot,
+            state: Arc::new(Mutex::new(BackupState::default())),
+            known_chunks: Arc::new(Mutex::new(HashSet::new())),
+        }))
+    })();
+
+    match result {
+        Ok(handle) => Box::into_raw(handle),
+        Err(err) => {
+            unsafe { set_error(error, err) };
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Connect to the server and start the backup.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle of `proxmox_backup_new` which was not disconnected yet.
+/// `result`, `error` and `callback_data` must stay valid until the callback was called.
+#[no_mangle]
+pub unsafe extern "C" fn proxmox_backup_connect_async(
+    handle: *mut ProxmoxBackupHandle,
+    callback: Option<extern "C" fn(*mut c_void)>,
+    callback_data: *mut c_void,
+    result: *mut c_int,
+    error: *mut *mut c_char,
+) {
+    let cb = CallbackPointers {
+        callback,
+        callback_data,
+        result,
+        error,
+    };
+    let handle = match unsafe { handle_ref(handle) } {
+        Ok(handle) => handle,
+        Err(err) => return cb.send_result(Err(err)),
+    };
+
+    let info = Arc::clone(&handle.info);
+    let ns = handle.ns.clone();
+    let snapshot = handle.snapshot.clone();
+    let crypt_mode = handle.crypt_mode();
+    let state = Arc::clone(&handle.state);
+
+    handle.spawn(cb, async move {
+        if state.lock().unwrap().session.is_some() {
+            bail!("already connected");
+        }
+
+        let client = info.connect()?;
+        let options = BackupSessionOptions {
+            crypt_config: info.crypt_config(),
+            crypt_mode,
+            ..Default::default()
+        };
+        let session = BackupSession::start(&client, info.store(), &ns, &snapshot, options).await?;
+
+        state.lock().unwrap().session = Some(session);
+        Ok(0)
+    });
+}
+
+/// Register a new image archive of `size` bytes, `*result` is the device id used to write data.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle of `proxmox_backup_new` which was not disconnected yet,
+/// and `device_name` must be NULL or point to a NUL terminated string.
+/// `result`, `error` and `callback_data` must stay valid until the callback was called.
+#[no_mangle]
+pub unsafe extern "C" fn proxmox_backup_register_image_async(
+    handle: *mut ProxmoxBackupHandle,
+    device_name: *const c_char,
+    size: u64,
+    callback: Option<extern "C" fn(*mut c_void)>,
+    callback_data: *mut c_void,
+    result: *mut c_int,
+    error: *mut *mut c_char,
+) {
+    let cb = CallbackPointers {
+        callback,
+        callback_data,
+        result,
+        error,
+    };
+    let handle = match unsafe { handle_ref(handle) } {
+        Ok(handle) => handle,
+        Err(err) => return cb.send_result(Err(err)),
+    };
+
+    let device_name = unsafe { required_str(device_name, "device_name") };
+    let writer = handle.writer();
+    let state = Arc::clone(&handle.state);
+
+    handle.spawn(cb, async move {
+        let archive_name = format!("{}.img.fidx", device_name?);
+        let writer = writer?;
+
+        let param = json!({ "archive-name": archive_name, "size": size });
+        let wid = writer
+            .post("fixed_index", Some(param))
+            .await?
+            .as_u64()
+            .ok_or_else(|| format_err!("got unexpected writer id"))?;
+
+        let chunk_count = size.div_ceil(PROXMOX_BACKUP_IMAGE_CHUNK_SIZE) as usize;
+
+        let mut state = state.lock().unwrap();
+        let dev_id = state.images.len();
+        state.images.push(ImageState {
+            archive_name,
+            wid,
+            size,
+            digests: vec![None; chunk_count],
+            closed: false,
+        });
+        Ok(dev_id as c_int)
+    });
+}
+
+/// Write one chunk of image data at `offset`.
+///
+/// `offset` must be a multiple of `PROXMOX_BACKUP_IMAGE_CHUNK_SIZE` and `size` must be exactly
+/// the chunk size, except for the last chunk of the image. Passing NULL as `data` writes zeroes.
+/// The data is copied before this function returns. On success, `*result` is `size`.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle of `proxmox_backup_new` which was not disconnected yet,
+/// and `data` must be NULL or valid for reads of `size` bytes.
+/// `result`, `error` and `callback_data` must stay valid until the callback was called.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn proxmox_backup_write_data_async(
+    handle: *mut ProxmoxBackupHandle,
+    dev_id: c_int,
+    data: *const u8,
+    offset: u64,
+    size: u64,
+    callback: Option<extern "C" fn(*mut c_void)>,
+    callback_data: *mut c_void,
+    result: *mut c_int,
+    error: *mut *mut c_char,
+) {
+    let cb = CallbackPointers {
+        callback,
+        callback_data,
+        result,
+        error,
+    };
+    let handle = match unsafe { handle_ref(handle) } {
+        Ok(handle) => handle,
+        Err(err) => return cb.send_result(Err(err)),
+    };
+
+    let data = if data.is_null() || size > PROXMOX_BACKUP_IMAGE_CHUNK_SIZE {
+        None
+    } else {
+        Some(unsafe { std::slice::from_raw_parts(data, size as usize) }.to_vec())
+    };
+    let writer = handle.writer();
+    let image = handle.image_info(dev_id);
+    let crypt_config = handle.info.crypt_config();
+    let state = Arc::clone(&handle.state);
+    let known_chunks = Arc::clone(&handle.known_chunks);
+
+    handle.spawn(cb, async move {
+        let writer = writer?;
+        let (wid, image_size) = image?;
+
+        let end = offset
+            .checked_add(size)
+            .ok_or_else(|| format_err!("invalid offset/size"))?;
+        if offset % PROXMOX_BACKUP_IMAGE_CHUNK_SIZE != 0
+            || size == 0
+            || size > PROXMOX_BACKUP_IMAGE_CHUNK_SIZE
+            || end > image_size
+            || (size != PROXMOX_BACKUP_IMAGE_CHUNK_SIZE && end != image_size)
+        {
+            bail!(
+                "write of {} bytes at offset {} is not chunk aligned",
+                size,
+                offset
+            );
+        }
+
+        let (chunk, digest) = match data {
+            Some(ref data) => {
+                let mut builder = DataChunkBuilder::new(data).compress(true);
+                if let Some(ref crypt_config) = crypt_config {
+                    builder = builder.crypt_config(crypt_config);
+                }
+                builder.build()?
+            }
+            None => {
+                DataChunkBuilder::build_zero_chunk(crypt_config.as_deref(), size as usize, true)?
+            }
+        };
+
+        let digest_str = hex::encode(digest);
+        let known = known_chunks.lock().unwrap().contains(&digest);
+        if !known {
+            let chunk_data = chunk.into_inner();
+            let param = json!({
+                "wid": wid,
+                "digest": digest_str,
+                "size": size,
+                "encoded-size": chunk_data.len(),
+            });
+            writer
+                .upload_post(
+                    "fixed_chunk",
+                    Some(param),
+                    "application/octet-stream",
+                    chunk_data,
+                )
+                .await?;
+            known_chunks.lock().unwrap().insert(digest);
+        }
+
+        let param = json!({ "wid": wid, "digest-list": [digest_str], "offset-list": [offset] });
+        writer
+            .upload_put(
+                "fixed_index",
+                None,
+                "application/json",
+                param.to_string().into_bytes(),
+            )
+            .await?;
+
+        let mut state = state.lock().unwrap();
+        let image = &mut state.images[dev_id as usize];
+        image.digests[(offset / PROXMOX_BACKUP_IMAGE_CHUNK_SIZE) as usize] = Some(digest);
+
+        Ok(size as c_int)
+    });
+}
+
+/// Close an image after all data was written, and add it to the manifest.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle of `proxmox_backup_new` which was not disconnected yet.
+/// `result`, `error` and `callback_data` must stay valid until the callback was called.
+#[no_mangle]
+pub unsafe extern "C" fn proxmox_backup_close_image_async(
+    handle: *mut ProxmoxBackupHandle,
+    dev_id: c_int,
+    callback: Option<extern "C" fn(*mut c_void)>,
+    callback_data: *mut c_void,
+    result: *mut c_int,
+    error: *mut *mut c_char,
+) {
+    let cb = CallbackPointers {
+        callback,
+        callback_data,
+        result,
+        error,
+    };
+    let handle = match unsafe { handle_ref(handle) } {
+        Ok(handle) => handle,
+        Err(err) => return cb.send_result(Err(err)),
+    };
+
+    let writer = handle.writer();
+    let image = handle.image_info(dev_id);
+    let crypt_mode = handle.crypt_mode();
+    let state = Arc::clone(&handle.state);
+
+    handle.spawn(cb, async move {
+        let writer = writer?;
+        let (wid, size) = image?;
+
+        let (archive_name, chunk_count, csum) = {
+            let state = state.lock().unwrap();
+            let image = &state.images[dev_id as usize];
+            let mut csum = openssl::sha::Sha256::new();
+            for (pos, digest) in image.digests.iter().enumerate() {
+                match digest {
+                    Some(digest) => csum.update(digest),
+                    None => bail!(
+                        "image '{}' incomplete - missing data at offset {}",
+                        image.archive_name,
+                        pos as u64 * PROXMOX_BACKUP_IMAGE_CHUNK_SIZE,
+                    ),
+                }
+            }
+            (
+                image.archive_name.clone(),
+                image.digests.len(),
+                csum.finish(),
+            )
+        };
+
+        let param = json!({
+            "wid": wid,
+            "chunk-count": chunk_count,
+            "size": size,
+            "csum": hex::encode(csum),
+        });
+        writer.post("fixed_close", Some(param)).await?;
+
+        let mut state = state.lock().unwrap();
+        state.images[dev_id as usize].closed = true;
+        state
+            .session
+            .as_mut()
+            .ok_or_else(|| format_err!("not connected"))?
+            .manifest_mut()
+            .add_file(archive_name, size, csum, crypt_mode)?;
+
+        Ok(0)
+    });
+}
+
+/// Upload the manifest and mark the backup as successful. All images must be closed.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle of `proxmox_backup_new` which was not disconnected yet.
+/// `result`, `error` and `callback_data` must stay valid until the callback was called.
+#[no_mangle]
+pub unsafe extern "C" fn proxmox_backup_finish_async(
+    handle: *mut ProxmoxBackupHandle,
+    callback: Option<extern "C" fn(*mut c_void)>,
+    callback_data: *mut c_void,
+    result: *mut c_int,
+    error: *mut *mut c_char,
+) {
+    let cb = CallbackPointers {
+        callback,
+        callback_data,
+        result,
+        error,
+    };
+    let handle = match unsafe { handle_ref(handle) } {
+        Ok(handle) => handle,
+        Err(err) => return cb.send_result(Err(err)),
+    };
+
+    let state = Arc::clone(&handle.state);
+
+    handle.spawn(cb, async move {
+        let session = {
+            let mut state = state.lock().unwrap();
+            if let Some(image) = state.images.iter().find(|image| !image.closed) {
+                bail!("image '{}' is still open", image.archive_name);
+            }
+            let session = state
+                .session
+                .take()
+                .ok_or_else(|| format_err!("not connected"))?;
+            state.finished = true;
+            session
+        };
+
+        session.finish().await?;
+        Ok(0)
+    });
+}
+
+/// Abort the backup (unless it was finished) and free the handle.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle of `proxmox_backup_new` which was not disconnected yet.
+/// It must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn proxmox_backup_disconnect(handle: *mut ProxmoxBackupHandle) {
+    if handle.is_null() {
+        return;
+    }
+    let handle = *unsafe { Box::from_raw(handle) };
+    let session = handle.state.lock().unwrap().session.take();
+    if let Some(session) = session {
+        session.writer().cancel();
+    }
+    handle.runtime.shutdown_background();
+}
@@ -0,0 +1,344 @@
+//! C interface to the backup client
+//!
+//! This exposes the backup and restore session primitives of `pbs-client` to C code, for
+//! example QEMU's block layer integration. All objects are passed around as opaque handles.
+//!
+//! Functions report errors by storing a newly allocated, NUL terminated error string in
+//! `*error`. Such strings must be released with [`proxmox_backup_free_error`].
+//!
+//! Functions with an `_async` suffix return immediately. Once the operation is done, the result
+//! is stored in `*result` (a negative value signals an error, in which case `*error` is set as
+//! well) and `callback(callback_data)` is called from one of the handle's worker threads.
+//!
+//! NULL handles are reported as error, and NULL `result`, `error` or `callback` pointers are
+//! skipped. All other pointers must be valid as described in the functions' safety sections.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use pbs_client::{BackupRepository, HttpClient, HttpClientOptions};
+use pbs_key_config::load_and_decrypt_key;
+use pbs_tools::crypt_config::CryptConfig;
+
+mod backup;
+pub use backup::*;
+
+mod restore;
+pub use restore::*;
+
+/// Store `err` as newly allocated C string in `*error` (if `error` is not NULL).
+///
+/// # Safety
+///
+/// `error` must be NULL or valid for writes.
+pub(crate) unsafe fn set_error(error: *mut *mut c_char, err: Error) {
+    if error.is_null() {
+        return;
+    }
+    let msg = CString::new(format!("{:#}", err).replace('\0', ""))
+        .unwrap_or_else(|_| CString::new("unknown error").unwrap());
+    unsafe {
+        *error = msg.into_raw();
+    }
+}
+
+/// Copy a required string argument.
+///
+/// # Safety
+///
+/// See [`optional_str`].
+pub(crate) unsafe fn required_str(ptr: *const c_char, name: &str) -> Result<String, Error> {
+    unsafe { optional_str(ptr, name) }?.ok_or_else(|| format_err!("missing parameter '{}'", name))
+}
+
+/// Copy an optional (NULL-able) string argument.
+///
+/// # Safety
+///
+/// `ptr` must be NULL or point to a NUL terminated string.
+pub(crate) unsafe fn optional_str(ptr: *const c_char, name: &str) -> Result<Option<String>, Error> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    let value = unsafe { CStr::from_ptr(ptr) }
+        .to_str()
+        .map_err(|err| format_err!("parameter '{}' is not valid utf8 - {}", name, err))?;
+    Ok(Some(value.to_owned()))
+}
+
+/// Get a reference to the object behind a handle.
+///
+/// # Safety
+///
+/// `handle` must be NULL or point to a handle which was not freed yet.
+pub(crate) unsafe fn handle_ref<'a, T>(handle: *mut T) -> Result<&'a T, Error> {
+    unsafe { handle.as_ref() }.ok_or_else(|| format_err!("invalid handle (NULL)"))
+}
+
+/// Completion callback of an asynchronous call.
+pub(crate) struct CallbackPointers {
+    pub callback: Option<extern "C" fn(*mut c_void)>,
+    pub callback_data: *mut c_void,
+    pub result: *mut c_int,
+    pub error: *mut *mut c_char,
+}
+
+// The caller guarantees that the pointers stay valid until the callback was called.
+unsafe impl Send for CallbackPointers {}
+
+impl CallbackPointers {
+    pub(crate) fn send_result(self, result: Result<c_int, Error>) {
+        let value = match result {
+            Ok(value) => value,
+            Err(err) => {
+                unsafe { set_error(self.error, err) };
+                -1
+            }
+        };
+        if !self.result.is_null() {
+            unsafe {
+                *self.result = value;
+            }
+        }
+        if let Some(callback) = self.callback {
+            callback(self.callback_data);
+        }
+    }
+}
+
+/// Connection and encryption parameters shared by backup and restore handles.
+pub(crate) struct ConnectInfo {
+    repo: BackupRepository,
+    password: String,
+    fingerprint: Option<String>,
+    crypt_config: Option<Arc<CryptConfig>>,
+}
+
+impl ConnectInfo {
+    /// # Safety
+    ///
+    /// All parameters must be NULL or point to NUL terminated strings.
+    pub(crate) unsafe fn new(
+        repo: *const c_char,
+        password: *const c_char,
+        keyfile: *const c_char,
+        key_password: *const c_char,
+        fingerprint: *const c_char,
+    ) -> Result<Self, Error> {
+        let repo: BackupRepository = unsafe { required_str(repo, "repo") }?.parse()?;
+        let password = unsafe { required_str(password, "password") }?;
+        let fingerprint = unsafe { optional_str(fingerprint, "fingerprint") }?;
+        let key_password = unsafe { optional_str(key_password, "key_password") }?;
+
+        let crypt_config = match unsafe { optional_str(keyfile, "keyfile") }? {
+            None => None,
+            Some(keyfile) => {
+                let (key, _created, _fingerprint) =
+                    load_and_decrypt_key(Path::new(&keyfile), &|| match key_password {
+                        Some(ref password) => Ok(password.as_bytes().to_vec()),
+                        None => bail!("missing parameter 'key_password'"),
+                    })?;
+                Some(Arc::new(CryptConfig::new(key)?))
+            }
+        };
+
+        Ok(Self {
+            repo,
+            password,
+            fingerprint,
+            crypt_config,
+        })
+    }
+
+    pub(crate) fn store(&self) -> &str {
+        self.repo.store()
+    }
+
+    pub(crate) fn crypt_config(&self) -> Option<Arc<CryptConfig>> {
+        self.crypt_config.clone()
+    }
+
+    /// Create the HTTP client, must be called from within the handle's runtime.
+    pub(crate) fn connect(&self) -> Result<HttpClient, Error> {
+        let options =
+            HttpClientOptions::new_non_interactive(self.password.clone(), self.fingerprint.clone());
+
+        HttpClient::new(
+            self.repo.host(),
+            self.repo.port(),
+            self.repo.auth_id(),
+            options,
+        )
+    }
+}
+
+pub(crate) fn new_runtime() -> Result<tokio::runtime::Runtime, Error> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| format_err!("unable to create tokio runtime - {}", err))
+}
+
+/// Free an error string returned by any of the library functions.
+///
+/// # Safety
+///
+/// `ptr` must be NULL or an error string of this library which was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn proxmox_backup_free_error(ptr: *mut c_char) {
+    if !ptr.is_null() {
+        unsafe {
+            drop(CString::from_raw(ptr));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::{c_char, c_int, c_void};
+    use std::sync::Barrier;
+
+    use super::*;
+
+    extern "C" fn wait_callback(data: *mut c_void) {
+        unsafe { &*(data as *const Barrier) }.wait();
+    }
+
+    fn take_error(error: *mut c_char) -> String {
+        assert!(!error.is_null());
+        let msg = unsafe { CStr::from_ptr(error) }
+            .to_string_lossy()
+            .into_owned();
+        unsafe { proxmox_backup_free_error(error) };
+        msg
+    }
+
+    #[test]
+    fn test_string_parameters() {
+        let value = CString::new("store").unwrap();
+        assert_eq!(
+            unsafe { optional_str(value.as_ptr(), "repo") }.unwrap(),
+            Some("store".to_string())
+        );
+        assert_eq!(
+            unsafe { optional_str(std::ptr::null(), "repo") }.unwrap(),
+            None
+        );
+        assert!(unsafe { required_str(std::ptr::null(), "repo") }.is_err());
+
+        let invalid = CString::new(vec![0xff, 0xfe]).unwrap();
+        assert!(unsafe { optional_str(invalid.as_ptr(), "repo") }.is_err());
+    }
+
+    #[test]
+    fn test_null_pointers() {
+        unsafe {
+            proxmox_backup_free_error(std::ptr::null_mut());
+            proxmox_backup_disconnect(std::ptr::null_mut());
+            proxmox_restore_disconnect(std::ptr::null_mut());
+        }
+
+        let mut error: *mut c_char = std::ptr::null_mut();
+        let handle = unsafe {
+            proxmox_backup_new(
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null(),
+                0,
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null(),
+                &mut error,
+            )
+        };
+        assert!(handle.is_null());
+        assert_eq!(take_error(error), "missing parameter 'repo'");
+
+        // invalid handles are reported through the callback, before returning
+        let barrier = Barrier::new(1);
+        let mut result: c_int = 0;
+        let mut error: *mut c_char = std::ptr::null_mut();
+        unsafe {
+            proxmox_backup_finish_async(
+                std::ptr::null_mut(),
+                Some(wait_callback),
+                &barrier as *const Barrier as *mut c_void,
+                &mut result,
+                &mut error,
+            )
+        };
+        assert_eq!(result, -1);
+        assert_eq!(take_error(error), "invalid handle (NULL)");
+
+        // without any output pointers
+        unsafe {
+            proxmox_restore_connect_async(
+                std::ptr::null_mut(),
+                None,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+
+        let mut error: *mut c_char = std::ptr::null_mut();
+        let length =
+            unsafe { proxmox_restore_get_image_length(std::ptr::null_mut(), 0, &mut error) };
+        assert_eq!(length, -1);
+        assert_eq!(take_error(error), "invalid handle (NULL)");
+    }
+
+    #[test]
+    fn test_backup_handle_not_connected() {
+        let repo = CString::new("root@pam@localhost:store").unwrap();
+        let backup_type = CString::new("vm").unwrap();
+        let backup_id = CString::new("100").unwrap();
+        let password = CString::new("secret").unwrap();
+
+        let mut error: *mut c_char = std::ptr::null_mut();
+        let handle = unsafe {
+            proxmox_backup_new(
+                repo.as_ptr(),
+                std::ptr::null(),
+                backup_type.as_ptr(),
+                backup_id.as_ptr(),
+                1_700_000_000,
+                password.as_ptr(),
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null(),
+                &mut error,
+            )
+        };
+        assert!(!handle.is_null());
+        assert!(error.is_null());
+
+        let barrier = Barrier::new(2);
+        let mut result: c_int = 0;
+        let mut error: *mut c_char = std::ptr::null_mut();
+        let device_name = CString::new("drive-scsi0").unwrap();
+        unsafe {
+            proxmox_backup_register_image_async(
+                handle,
+                device_name.as_ptr(),
+                PROXMOX_BACKUP_IMAGE_CHUNK_SIZE,
+                Some(wait_callback),
+                &barrier as *const Barrier as *mut c_void,
+                &mut result,
+                &mut error,
+            )
+        };
+        barrier.wait();
+        assert_eq!(result, -1);
+        assert_eq!(take_error(error), "not connected");
+
+        unsafe { proxmox_backup_disconnect(handle) };
+    }
+}
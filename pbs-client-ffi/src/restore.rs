@@ -0,0 +1,343 @@
+//! Restore handle: random access to the image archives of an existing snapshot.
+
+use std::os::raw::{c_char, c_int, c_long, c_void};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+
+use pbs_api_types::{BackupDir, BackupNamespace};
+use pbs_client::{RemoteChunkReader, RestoreSession};
+use pbs_datastore::fixed_index::FixedIndexReader;
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::read_chunk::AsyncReadChunk;
+
+use crate::{
+    handle_ref, new_runtime, optional_str, required_str, set_error, CallbackPointers, ConnectInfo,
+};
+
+struct ImageReader {
+    index: FixedIndexReader,
+    chunk_reader: RemoteChunkReader,
+}
+
+impl ImageReader {
+    async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, Error> {
+        let mut done = 0;
+        while done < buf.len() {
+            let (pos, chunk_offset) = match self.index.chunk_from_offset(offset + done as u64) {
+                Some(info) => info,
+                None => break, // EOF
+            };
+            let digest = self.index.index_digest(pos).unwrap();
+            let data = self.chunk_reader.read_chunk(digest).await?;
+
+            let chunk_offset = chunk_offset as usize;
+            if chunk_offset >= data.len() {
+                bail!("chunk {} too small", hex::encode(digest));
+            }
+            let len = (data.len() - chunk_offset).min(buf.len() - done);
+            buf[done..(done + len)].copy_from_slice(&data[chunk_offset..(chunk_offset + len)]);
+            done += len;
+        }
+        Ok(done)
+    }
+}
+
+#[derive(Default)]
+struct RestoreState {
+    session: Option<Arc<RestoreSession>>,
+    images: Vec<Arc<ImageReader>>,
+}
+
+/// Opaque restore handle, see `proxmox_restore_new`.
+pub struct ProxmoxRestoreHandle {
+    runtime: tokio::runtime::Runtime,
+    info: Arc<ConnectInfo>,
+    ns: BackupNamespace,
+    snapshot: BackupDir,
+    state: Arc<Mutex<RestoreState>>,
+}
+
+impl ProxmoxRestoreHandle {
+    fn session(&self) -> Result<Arc<RestoreSession>, Error> {
+        self.state
+            .lock()
+            .unwrap()
+            .session
+            .clone()
+            .ok_or_else(|| format_err!("not connected"))
+    }
+
+    fn image(&self, aid: c_int) -> Result<Arc<ImageReader>, Error> {
+        usize::try_from(aid)
+            .ok()
+            .and_then(|id| self.state.lock().unwrap().images.get(id).cloned())
+            .ok_or_else(|| format_err!("invalid archive id {}", aid))
+    }
+
+    fn spawn<F>(&self, cb: CallbackPointers, future: F)
+    where
+        F: std::future::Future<Output = Result<c_int, Error>> + Send + 'static,
+    {
+        self.runtime.spawn(async move {
+            let result = future.await;
+            cb.send_result(result);
+        });
+    }
+}
+
+/// Output buffer of `proxmox_restore_read_image_at_async`.
+struct DataPointer(*mut u8);
+
+// The caller guarantees that the buffer stays valid until the callback was called.
+unsafe impl Send for DataPointer {}
+
+impl DataPointer {
+    fn get(&self) -> *mut u8 {
+        self.0
+    }
+}
+
+/// Create a new restore handle for `snapshot` (`type/id/time`), this does not connect yet.
+///
+/// `backup_ns`, `keyfile`, `key_password` and `fingerprint` may be NULL. Returns NULL and sets
+/// `*error` on failure.
+///
+/// # Safety
+///
+/// All string parameters must be NULL or point to NUL terminated strings, and `error` must be
+/// NULL or valid for writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn proxmox_restore_new(
+    repo: *const c_char,
+    backup_ns: *const c_char,
+    snapshot: *const c_char,
+    password: *const c_char,
+    keyfile: *const c_char,
+    key_password: *const c_char,
+    fingerprint: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut ProxmoxRestoreHandle {
+    let result: Result<_, Error> = (|| {
+        let info = unsafe { ConnectInfo::new(repo, password, keyfile, key_password, fingerprint) }?;
+        let ns = match unsafe { optional_str(backup_ns, "backup_ns") }? {
+            Some(ns) => ns.parse()?,
+            None => BackupNamespace::root(),
+        };
+        let snapshot: BackupDir = unsafe { required_str(snapshot, "snapshot") }?.parse()?;
+
+        Ok(Box::new(ProxmoxRestoreHandle {
+            runtime: new_runtime()?,
+            info: Arc::new(info),
+            ns,
+            snapshot,
+            state: Arc::new(Mutex::new(RestoreState::default())),
+        }))
+    })();
+
+    match result {
+        Ok(handle) => Box::into_raw(handle),
+        Err(err) => {
+            unsafe { set_error(error, err) };
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Connect to the server and download the snapshot's manifest.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle of `proxmox_restore_new` which was not disconnected yet.
+/// `result`, `error` and `callback_data` must stay valid until the callback was called.
+#[no_mangle]
+pub unsafe extern "C" fn proxmox_restore_connect_async(
+    handle: *mut ProxmoxRestoreHandle,
+    callback: Option<extern "C" fn(*mut c_void)>,
+    callback_data: *mut c_void,
+    result: *mut c_int,
+    error: *mut *mut c_char,
+) {
+    let cb = CallbackPointers {
+        callback,
+        callback_data,
+        result,
+        error,
+    };
+    let handle = match unsafe { handle_ref(handle) } {
+        Ok(handle) => handle,
+        Err(err) => return cb.send_result(Err(err)),
+    };
+
+    let info = Arc::clone(&handle.info);
+    let ns = handle.ns.clone();
+    let snapshot = handle.snapshot.clone();
+    let state = Arc::clone(&handle.state);
+
+    handle.spawn(cb, async move {
+        let client = info.connect()?;
+        let session = RestoreSession::start(
+            &client,
+            info.store(),
+            &ns,
+            &snapshot,
+            info.crypt_config(),
+            false,
+        )
+        .await?;
+        session.check_fingerprint()?;
+
+        state.lock().unwrap().session = Some(Arc::new(session));
+        Ok(0)
+    });
+}
+
+/// Open the image archive `archive_name` (e.g. `drive-scsi0.img.fidx`).
+///
+/// `*result` is the archive id used for reading.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle of `proxmox_restore_new` which was not disconnected yet,
+/// and `archive_name` must be NULL or point to a NUL terminated string.
+/// `result`, `error` and `callback_data` must stay valid until the callback was called.
+#[no_mangle]
+pub unsafe extern "C" fn proxmox_restore_open_image_async(
+    handle: *mut ProxmoxRestoreHandle,
+    archive_name: *const c_char,
+    callback: Option<extern "C" fn(*mut c_void)>,
+    callback_data: *mut c_void,
+    result: *mut c_int,
+    error: *mut *mut c_char,
+) {
+    let cb = CallbackPointers {
+        callback,
+        callback_data,
+        result,
+        error,
+    };
+    let handle = match unsafe { handle_ref(handle) } {
+        Ok(handle) => handle,
+        Err(err) => return cb.send_result(Err(err)),
+    };
+
+    let archive_name = unsafe { required_str(archive_name, "archive_name") };
+    let session = handle.session();
+    let crypt_config = handle.info.crypt_config();
+    let state = Arc::clone(&handle.state);
+
+    handle.spawn(cb, async move {
+        let archive_name = archive_name?;
+        let session = session?;
+
+        let manifest = session.manifest();
+        let index = session
+            .reader()
+            .download_fixed_index(manifest, &archive_name)
+            .await?;
+        let file_info = manifest.lookup_file_info(&archive_name)?;
+        let chunk_reader = RemoteChunkReader::new(
+            session.reader().clone(),
+            crypt_config,
+            file_info.chunk_crypt_mode(),
+            index.find_most_used_chunks(8),
+        );
+
+        let mut state = state.lock().unwrap();
+        let aid = state.images.len();
+        state.images.push(Arc::new(ImageReader {
+            index,
+            chunk_reader,
+        }));
+        Ok(aid as c_int)
+    });
+}
+
+/// Size of an opened image in bytes, or -1 (setting `*error`) on failure.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle of `proxmox_restore_new` which was not disconnected yet,
+/// and `error` must be NULL or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn proxmox_restore_get_image_length(
+    handle: *mut ProxmoxRestoreHandle,
+    aid: c_int,
+    error: *mut *mut c_char,
+) -> c_long {
+    match unsafe { handle_ref(handle) }.and_then(|handle| handle.image(aid)) {
+        Ok(image) => image.index.index_bytes() as c_long,
+        Err(err) => {
+            unsafe { set_error(error, err) };
+            -1
+        }
+    }
+}
+
+/// Read `size` bytes at `offset` from an opened image into `data`.
+///
+/// `data` must stay valid until the callback was called. On success, `*result` is the number of
+/// bytes read, which is only smaller than `size` at the end of the image.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle of `proxmox_restore_new` which was not disconnected yet,
+/// and `data` must be NULL or valid for writes of `size` bytes until the callback was called.
+/// `result`, `error` and `callback_data` must stay valid until the callback was called.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn proxmox_restore_read_image_at_async(
+    handle: *mut ProxmoxRestoreHandle,
+    aid: c_int,
+    data: *mut u8,
+    offset: u64,
+    size: u64,
+    callback: Option<extern "C" fn(*mut c_void)>,
+    callback_data: *mut c_void,
+    result: *mut c_int,
+    error: *mut *mut c_char,
+) {
+    let cb = CallbackPointers {
+        callback,
+        callback_data,
+        result,
+        error,
+    };
+    let handle = match unsafe { handle_ref(handle) } {
+        Ok(handle) => handle,
+        Err(err) => return cb.send_result(Err(err)),
+    };
+
+    let image = handle.image(aid);
+    let data = DataPointer(data);
+
+    handle.spawn(cb, async move {
+        let image = image?;
+        let data = data.get();
+        if data.is_null() {
+            bail!("missing data buffer");
+        }
+        if size > c_int::MAX as u64 {
+            bail!("read size {} too large", size);
+        }
+        let buf = unsafe { std::slice::from_raw_parts_mut(data, size as usize) };
+        let done = image.read_at(buf, offset).await?;
+        Ok(done as c_int)
+    });
+}
+
+/// Free the restore handle.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle of `proxmox_restore_new` which was not disconnected yet.
+/// It must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn proxmox_restore_disconnect(handle: *mut ProxmoxRestoreHandle) {
+    if handle.is_null() {
+        return;
+    }
+    let handle = *unsafe { Box::from_raw(handle) };
+    handle.runtime.shutdown_background();
+}
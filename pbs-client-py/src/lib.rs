@@ -0,0 +1,340 @@
+//! Python bindings for browsing a backup repository and restoring files.
+//!
+//! ```python
+//! import pbs_client_py
+//!
+//! repo = pbs_client_py.Repository("root@pam@localhost:store1", password="...")
+//! for snapshot in repo.list_snapshots(group="host/myhost"):
+//!     print(snapshot["backup-time"])
+//! repo.list_dir("host/myhost/2024-01-01T00:00:00Z", "/root.pxar.didx/etc")
+//! repo.restore("host/myhost/2024-01-01T00:00:00Z", "/root.pxar.didx/etc/hosts", "/tmp/restore")
+//! ```
+//!
+//! Paths use the same layout as the catalog shell: the first component is the archive name.
+
+use std::io::{Seek, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use serde_json::{json, Value};
+
+use pbs_api_types::{BackupDir, BackupGroup, BackupNamespace};
+use pbs_client::pxar::extract_sub_dir;
+use pbs_client::{BackupRepository, HttpClient, HttpClientOptions, RestoreSession};
+use pbs_datastore::catalog::{CatalogEntryType, CatalogReader, DirEntryAttribute};
+use pbs_datastore::dynamic_index::LocalDynamicReadAt;
+use pbs_datastore::CATALOG_NAME;
+use pbs_key_config::load_and_decrypt_key;
+use pbs_tools::crypt_config::CryptConfig;
+
+fn to_py_err(err: Error) -> PyErr {
+    PyRuntimeError::new_err(format!("{:#}", err))
+}
+
+fn json_to_py(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(b) => b.into_py(py),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(n), _) => n.into_py(py),
+            (None, Some(n)) => n.into_py(py),
+            _ => n.as_f64().unwrap_or_default().into_py(py),
+        },
+        Value::String(s) => s.into_py(py),
+        Value::Array(list) => {
+            let items = list
+                .iter()
+                .map(|item| json_to_py(py, item))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, items).into_py(py)
+        }
+        Value::Object(map) => {
+            let dict = PyDict::new(py);
+            for (key, item) in map {
+                dict.set_item(key, json_to_py(py, item)?)?;
+            }
+            dict.into_py(py)
+        }
+    })
+}
+
+fn parse_ns(ns: Option<&str>) -> Result<BackupNamespace, Error> {
+    match ns {
+        Some(ns) => ns.parse(),
+        None => Ok(BackupNamespace::root()),
+    }
+}
+
+/// Connection to a single datastore.
+#[pyclass]
+struct Repository {
+    runtime: tokio::runtime::Runtime,
+    repo: BackupRepository,
+    client: HttpClient,
+    crypt_config: Option<Arc<CryptConfig>>,
+}
+
+impl Repository {
+    fn connect(
+        repository: &str,
+        password: String,
+        fingerprint: Option<String>,
+        keyfile: Option<String>,
+        key_password: Option<String>,
+    ) -> Result<Self, Error> {
+        let repo: BackupRepository = repository.parse()?;
+
+        let crypt_config = match keyfile {
+            None => None,
+            Some(keyfile) => {
+                let (key, _created, _fingerprint) =
+                    load_and_decrypt_key(Path::new(&keyfile), &|| match key_password {
+                        Some(ref password) => Ok(password.as_bytes().to_vec()),
+                        None => bail!("missing key password"),
+                    })?;
+                Some(Arc::new(CryptConfig::new(key)?))
+            }
+        };
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+
+        let client = {
+            let _guard = runtime.enter();
+            let options = HttpClientOptions::new_non_interactive(password, fingerprint);
+            HttpClient::new(repo.host(), repo.port(), repo.auth_id(), options)?
+        };
+
+        Ok(Self {
+            runtime,
+            repo,
+            client,
+            crypt_config,
+        })
+    }
+
+    async fn open_snapshot(
+        &self,
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
+    ) -> Result<RestoreSession, Error> {
+        let session = RestoreSession::start(
+            &self.client,
+            self.repo.store(),
+            ns,
+            snapshot,
+            self.crypt_config.clone(),
+            false,
+        )
+        .await?;
+        session.check_fingerprint()?;
+        Ok(session)
+    }
+
+    async fn list_snapshots_do(
+        &self,
+        ns: &BackupNamespace,
+        group: Option<&BackupGroup>,
+    ) -> Result<Value, Error> {
+        let path = format!("api2/json/admin/datastore/{}/snapshots", self.repo.store());
+
+        let mut args = match group {
+            Some(group) => serde_json::to_value(group)?,
+            None => json!({}),
+        };
+        if !ns.is_root() {
+            args["ns"] = serde_json::to_value(ns)?;
+        }
+
+        let mut result = self.client.get(&path, Some(args)).await?;
+        Ok(result["data"].take())
+    }
+
+    async fn list_dir_do(
+        &self,
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
+        path: &str,
+    ) -> Result<Value, Error> {
+        let session = self.open_snapshot(ns, snapshot).await?;
+        let mut reader = session.dynamic_reader(CATALOG_NAME).await?;
+
+        let mut catalogfile = std::fs::OpenOptions::new()
+            .write(true)
+            .read(true)
+            .custom_flags(libc::O_TMPFILE)
+            .open("/tmp")?;
+
+        std::io::copy(&mut reader, &mut catalogfile)
+            .map_err(|err| format_err!("unable to download catalog - {}", err))?;
+        catalogfile.seek(SeekFrom::Start(0))?;
+
+        let mut catalog = CatalogReader::new(catalogfile);
+        let dir = catalog.lookup_recursive(path.as_bytes())?;
+        if !dir.is_directory() {
+            bail!("'{}' is not a directory", path);
+        }
+
+        let mut list = Vec::new();
+        for entry in catalog.read_dir(&dir)? {
+            let name = String::from_utf8_lossy(&entry.name).into_owned();
+            let mut item = json!({
+                "name": name,
+                "path": format!("{}/{}", path.trim_end_matches('/'), name),
+                "type": CatalogEntryType::from(&entry.attr).to_string(),
+            });
+            if let DirEntryAttribute::File { size, mtime } = entry.attr {
+                item["size"] = size.into();
+                item["mtime"] = mtime.into();
+            }
+            list.push(item);
+        }
+
+        Ok(Value::Array(list))
+    }
+
+    async fn restore_do(
+        &self,
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
+        path: &str,
+        target: &str,
+    ) -> Result<(), Error> {
+        let path = path.trim_start_matches('/');
+        let (archive_name, file_path) = path.split_once('/').unwrap_or((path, ""));
+        if !archive_name.ends_with(".pxar.didx") {
+            bail!(
+                "can only restore from pxar archives, got '{}'",
+                archive_name
+            );
+        }
+
+        let session = self.open_snapshot(ns, snapshot).await?;
+        let reader = session.dynamic_reader(archive_name).await?;
+        let archive_size = reader.archive_size();
+        let reader: Arc<dyn pxar::accessor::ReadAt + Send + Sync> =
+            Arc::new(LocalDynamicReadAt::new(reader));
+        let decoder = pxar::accessor::aio::Accessor::new(reader, archive_size).await?;
+
+        extract_sub_dir(target, decoder, format!("/{}", file_path)).await
+    }
+}
+
+#[pymethods]
+impl Repository {
+    /// Connect to `repository` (`[[auth-id@]server[:port]:]datastore`).
+    #[new]
+    #[pyo3(signature = (repository, password, fingerprint=None, keyfile=None, key_password=None))]
+    fn new(
+        repository: &str,
+        password: String,
+        fingerprint: Option<String>,
+        keyfile: Option<String>,
+        key_password: Option<String>,
+    ) -> PyResult<Self> {
+        Self::connect(repository, password, fingerprint, keyfile, key_password).map_err(to_py_err)
+    }
+
+    /// List snapshots, optionally restricted to a namespace and/or group (`type/id`).
+    #[pyo3(signature = (ns=None, group=None))]
+    fn list_snapshots(
+        &self,
+        py: Python<'_>,
+        ns: Option<&str>,
+        group: Option<&str>,
+    ) -> PyResult<PyObject> {
+        let ns = parse_ns(ns).map_err(to_py_err)?;
+        let group: Option<BackupGroup> = group
+            .map(|group| group.parse())
+            .transpose()
+            .map_err(to_py_err)?;
+
+        let data = py
+            .allow_threads(|| {
+                self.runtime
+                    .block_on(self.list_snapshots_do(&ns, group.as_ref()))
+            })
+            .map_err(to_py_err)?;
+        json_to_py(py, &data)
+    }
+
+    /// List a directory of the snapshot's catalog, e.g. `/root.pxar.didx/etc`.
+    #[pyo3(signature = (snapshot, path="/", ns=None))]
+    fn list_dir(
+        &self,
+        py: Python<'_>,
+        snapshot: &str,
+        path: &str,
+        ns: Option<&str>,
+    ) -> PyResult<PyObject> {
+        let ns = parse_ns(ns).map_err(to_py_err)?;
+        let snapshot: BackupDir = snapshot.parse().map_err(to_py_err)?;
+
+        let data = py
+            .allow_threads(|| {
+                self.runtime
+                    .block_on(self.list_dir_do(&ns, &snapshot, path))
+            })
+            .map_err(to_py_err)?;
+        json_to_py(py, &data)
+    }
+
+    /// Restore a file or directory (e.g. `/root.pxar.didx/etc/hosts`) into `target`.
+    #[pyo3(signature = (snapshot, path, target, ns=None))]
+    fn restore(
+        &self,
+        py: Python<'_>,
+        snapshot: &str,
+        path: &str,
+        target: &str,
+        ns: Option<&str>,
+    ) -> PyResult<()> {
+        let ns = parse_ns(ns).map_err(to_py_err)?;
+        let snapshot: BackupDir = snapshot.parse().map_err(to_py_err)?;
+
+        py.allow_threads(|| {
+            self.runtime
+                .block_on(self.restore_do(&ns, &snapshot, path, target))
+        })
+        .map_err(to_py_err)
+    }
+}
+
+#[pymodule]
+fn pbs_client_py(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Repository>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use pyo3::exceptions::PyRuntimeError;
+    use pyo3::prelude::*;
+
+    #[test]
+    fn test_module() {
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "pbs_client_py").unwrap();
+            super::pbs_client_py(py, module).unwrap();
+
+            let repository = module.getattr("Repository").unwrap();
+            for method in ["list_snapshots", "list_dir", "restore"] {
+                assert!(
+                    repository.hasattr(method).unwrap(),
+                    "missing method {method}"
+                );
+            }
+
+            let err = repository
+                .call1(("not a repository!", "password"))
+                .unwrap_err();
+            assert!(err.is_instance_of::<PyRuntimeError>(py));
+        });
+    }
+}
@@ -1,24 +1,69 @@
-use anyhow::{format_err, Error};
+use anyhow::{bail, format_err, Error};
 use std::fs::File;
 use std::io::{Seek, SeekFrom, Write};
-use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use futures::future::AbortHandle;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use pbs_api_types::{BackupDir, BackupNamespace};
-use pbs_datastore::data_blob::DataBlob;
+use proxmox_sys::fs::file_read_optional_string;
+
+use pbs_api_types::{BackupDir, BackupNamespace, CryptMode};
+use pbs_datastore::data_blob::{DataBlob, DataChunkBuilder};
 use pbs_datastore::data_blob_reader::DataBlobReader;
 use pbs_datastore::dynamic_index::DynamicIndexReader;
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
-use pbs_datastore::manifest::MANIFEST_BLOB_NAME;
+use pbs_datastore::manifest::{archive_type, ArchiveType, MANIFEST_BLOB_NAME};
+use pbs_datastore::read_chunk::AsyncReadChunk;
 use pbs_datastore::{BackupManifest, PROXMOX_BACKUP_READER_PROTOCOL_ID_V1};
 use pbs_tools::crypt_config::CryptConfig;
 use pbs_tools::sha::sha256;
 
-use super::{H2Client, HttpClient};
+use super::tools::state_file::replace_state_file;
+use super::{H2Client, HttpClient, RemoteChunkReader};
+
+/// How often the state of [`BackupReader::download_archive_to_path`] is saved.
+const DOWNLOAD_STATE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Progress of an archive download, saved next to the target to allow resuming it.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DownloadState {
+    archive_name: String,
+    /// Checksum of the index, a resumed download must be of the very same archive.
+    #[serde(with = "hex::serde")]
+    index_csum: [u8; 32],
+    /// Bitmap of the chunks already written.
+    #[serde(with = "hex::serde")]
+    done: Vec<u8>,
+}
+
+impl DownloadState {
+    fn path(target: &Path) -> PathBuf {
+        let mut path = target.as_os_str().to_owned();
+        path.push(".download-state");
+        PathBuf::from(path)
+    }
+
+    fn is_done(&self, pos: usize) -> bool {
+        self.done[pos / 8] & (1 << (pos % 8)) != 0
+    }
+
+    fn set_done(&mut self, pos: usize) {
+        self.done[pos / 8] |= 1 << (pos % 8);
+    }
+
+    fn save(&self, target: &Path) -> Result<(), Error> {
+        let data = serde_json::to_vec(self)?;
+        replace_state_file(Self::path(target), &data, false)
+    }
+}
 
 /// Backup Reader
 pub struct BackupReader {
@@ -119,6 +164,154 @@ impl BackupReader {
         self.h2.download(path, Some(param), output).await
     }
 
+    /// Download an index archive and restore its content into the file at `path`.
+    ///
+    /// Chunks are fetched and decoded with up to `parallel` requests in flight, and written at
+    /// their offset, so the order they arrive in does not matter. Chunks containing only zeroes
+    /// are not written at all, which keeps the target sparse. Chunks which were skipped as zero
+    /// chunks during backup are not downloaded either.
+    ///
+    /// The progress is saved in a `<path>.download-state` file while the download runs. With
+    /// `resume` set, an interrupted download of the same archive into an existing file is
+    /// continued where it stopped, otherwise the target must not exist yet. Returns the number
+    /// of bytes written.
+    pub async fn download_archive_to_path(
+        self: &Arc<Self>,
+        manifest: &BackupManifest,
+        archive_name: &str,
+        path: &Path,
+        parallel: usize,
+        resume: bool,
+    ) -> Result<u64, Error> {
+        let mut zero_digest = None;
+        let index: Box<dyn IndexFile + Send> = match archive_type(archive_name)? {
+            ArchiveType::FixedIndex => {
+                let index = self.download_fixed_index(manifest, archive_name).await?;
+                zero_digest = self.zero_chunk_digest(manifest, archive_name, &index)?;
+                Box::new(index)
+            }
+            ArchiveType::DynamicIndex => {
+                Box::new(self.download_dynamic_index(manifest, archive_name).await?)
+            }
+            ArchiveType::Blob => bail!("'{archive_name}' is not an index archive"),
+        };
+        let (index_csum, _) = index.compute_csum();
+        let file_info = manifest.lookup_file_info(archive_name)?;
+
+        let state_path = DownloadState::path(path);
+        let state_data = file_read_optional_string(&state_path)?;
+        // only an interrupted download may be continued, anything else at the target is kept
+        let resuming = resume && state_data.is_some();
+        let mut state = match state_data {
+            Some(data) if resume => {
+                let state: DownloadState = serde_json::from_str(&data)
+                    .map_err(|err| format_err!("unable to parse {state_path:?} - {err}"))?;
+                if state.archive_name != archive_name || state.index_csum != index_csum {
+                    bail!("{path:?} holds an interrupted download of a different archive");
+                }
+                state
+            }
+            Some(_) => bail!("{path:?} holds an interrupted download, resume or remove it"),
+            None => DownloadState {
+                archive_name: archive_name.to_string(),
+                index_csum,
+                done: vec![0u8; index.index_count().div_ceil(8)],
+            },
+        };
+
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(!resuming)
+            .open(path)
+            .map_err(|err| format_err!("unable to create target file {path:?} - {err}"))?;
+        file.set_len(index.index_bytes())?;
+
+        let chunk_reader = RemoteChunkReader::new(
+            self.clone(),
+            self.crypt_config.clone(),
+            file_info.chunk_crypt_mode(),
+            index.find_most_used_chunks(8),
+        );
+
+        let todo: Vec<usize> = (0..index.index_count())
+            .filter(|pos| !state.is_done(*pos))
+            .collect();
+        if todo.len() < index.index_count() {
+            log::info!(
+                "resuming download, {} of {} chunks left",
+                todo.len(),
+                index.index_count()
+            );
+        }
+
+        let mut bytes = 0;
+
+        // chunks skipped as zero chunks during backup are not downloaded, but left as holes
+        let (zero, todo): (Vec<usize>, Vec<usize>) = todo.into_iter().partition(|pos| {
+            zero_digest.is_some() && index.index_digest(*pos) == zero_digest.as_ref()
+        });
+        if !zero.is_empty() {
+            log::info!("skipping download of {} zero chunks", zero.len());
+        }
+        for pos in zero {
+            bytes += index.chunk_info(pos).unwrap().size();
+            state.set_done(pos);
+        }
+
+        let mut chunks = stream::iter(todo)
+            .map(|pos| {
+                let info = index.chunk_info(pos).unwrap();
+                let chunk_reader = chunk_reader.clone();
+                // spawn, so that chunks also get decoded in parallel
+                let handle = tokio::spawn(async move {
+                    let data = AsyncReadChunk::read_chunk(&chunk_reader, &info.digest).await?;
+                    Ok::<_, Error>((pos, info.range.start, data))
+                });
+                async move { handle.await? }
+            })
+            .buffer_unordered(parallel.max(1));
+
+        let mut last_save = Instant::now();
+
+        let result: Result<(), Error> = async {
+            while let Some(chunk) = chunks.next().await {
+                let (pos, offset, data) = chunk?;
+                if data.iter().any(|b| *b != 0) {
+                    file.write_all_at(&data, offset)?;
+                }
+                bytes += data.len() as u64;
+                state.set_done(pos);
+
+                if last_save.elapsed() >= DOWNLOAD_STATE_INTERVAL {
+                    file.sync_data()?;
+                    state.save(path)?;
+                    last_save = Instant::now();
+                }
+            }
+            Ok(())
+        }
+        .await;
+
+        if let Err(err) = result {
+            // only chunks persisted to disk may be skipped when resuming
+            if file.sync_data().is_ok() {
+                if let Err(save_err) = state.save(path) {
+                    log::error!("unable to save download state - {save_err}");
+                }
+            }
+            return Err(err);
+        }
+
+        file.sync_all()?;
+        if let Err(err) = std::fs::remove_file(&state_path) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                bail!("unable to remove {state_path:?} - {err}");
+            }
+        }
+
+        Ok(bytes)
+    }
+
     pub fn force_close(self) {
         self.abort.abort();
     }
@@ -141,18 +334,14 @@ impl BackupReader {
 
     /// Download a .blob file
     ///
-    /// This creates a temporary file in /tmp (using O_TMPFILE). The data is verified using
+    /// This creates a temporary file in /tmp (anonymous, using O_TMPFILE on Linux). The data is verified using
     /// the provided manifest.
     pub async fn download_blob(
         &self,
         manifest: &BackupManifest,
         name: &str,
     ) -> Result<DataBlobReader<'_, File>, Error> {
-        let mut tmpfile = std::fs::OpenOptions::new()
-            .write(true)
-            .read(true)
-            .custom_flags(libc::O_TMPFILE)
-            .open("/tmp")?;
+        let mut tmpfile = pbs_tools::fs::open_tmpfile("/tmp")?;
 
         self.download(name, &mut tmpfile).await?;
 
@@ -167,18 +356,14 @@ impl BackupReader {
 
     /// Download dynamic index file
     ///
-    /// This creates a temporary file in /tmp (using O_TMPFILE). The index is verified using
+    /// This creates a temporary file in /tmp (anonymous, using O_TMPFILE on Linux). The index is verified using
     /// the provided manifest.
     pub async fn download_dynamic_index(
         &self,
         manifest: &BackupManifest,
         name: &str,
     ) -> Result<DynamicIndexReader, Error> {
-        let mut tmpfile = std::fs::OpenOptions::new()
-            .write(true)
-            .read(true)
-            .custom_flags(libc::O_TMPFILE)
-            .open("/tmp")?;
+        let mut tmpfile = pbs_tools::fs::open_tmpfile("/tmp")?;
 
         self.download(name, &mut tmpfile).await?;
 
@@ -194,18 +379,14 @@ impl BackupReader {
 
     /// Download fixed index file
     ///
-    /// This creates a temporary file in /tmp (using O_TMPFILE). The index is verified using
+    /// This creates a temporary file in /tmp (anonymous, using O_TMPFILE on Linux). The index is verified using
     /// the provided manifest.
     pub async fn download_fixed_index(
         &self,
         manifest: &BackupManifest,
         name: &str,
     ) -> Result<FixedIndexReader, Error> {
-        let mut tmpfile = std::fs::OpenOptions::new()
-            .write(true)
-            .read(true)
-            .custom_flags(libc::O_TMPFILE)
-            .open("/tmp")?;
+        let mut tmpfile = pbs_tools::fs::open_tmpfile("/tmp")?;
 
         self.download(name, &mut tmpfile).await?;
 
@@ -218,4 +399,36 @@ impl BackupReader {
 
         Ok(index)
     }
+
+    /// Returns the digest of the zero chunks of the fixed index archive `name`, if the client
+    /// skipped them when creating the backup.
+    ///
+    /// The digest recorded in the manifest is not signed, so it is only returned if it matches a
+    /// zero chunk with the chunk size of `index`, built with the crypt config of this reader.
+    pub fn zero_chunk_digest(
+        &self,
+        manifest: &BackupManifest,
+        name: &str,
+        index: &FixedIndexReader,
+    ) -> Result<Option<[u8; 32]>, Error> {
+        let recorded = match manifest.zero_chunk(name)? {
+            Some(digest) => digest,
+            None => return Ok(None),
+        };
+
+        let zero_bytes = vec![0; index.chunk_size];
+        let mut chunk_builder = DataChunkBuilder::new(&zero_bytes);
+        if manifest.lookup_file_info(name)?.chunk_crypt_mode() == CryptMode::Encrypt {
+            if let Some(crypt_config) = &self.crypt_config {
+                chunk_builder = chunk_builder.crypt_config(crypt_config);
+            }
+        }
+
+        if *chunk_builder.digest() != recorded {
+            log::warn!("ignoring zero chunk digest of '{name}' recorded in manifest - mismatch");
+            return Ok(None);
+        }
+
+        Ok(Some(recorded))
+    }
 }
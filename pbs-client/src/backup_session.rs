@@ -0,0 +1,752 @@
+//! High level backup interface
+//!
+//! [`BackupSession`] bundles a [`BackupWriter`] with the manifest and catalog handling, so that
+//! tools embedding the client library do not have to replicate what `proxmox-backup-client
+//! backup` does internally. A typical session looks like this:
+//!
+//! ```ignore
+//! let client = pbs_client::tools::connect(&repo)?;
+//! let mut session = BackupSession::start(&client, repo.store(), &ns, &snapshot, options).await?;
+//! session.add_pxar("/etc", "etc.pxar", pxar_options).await?;
+//! let size = proxmox_sys::fs::image_size(Path::new("/dev/sdb"))?;
+//! session.add_image("/dev/sdb", "disk.img", size).await?;
+//! session.finish().await?;
+//! ```
+//!
+//! A second session, e.g. on another server, can be attached with
+//! [`BackupSession::set_secondary`]. The sources are then read only once, and each chunk stream
+//! is uploaded to both sessions.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+use bytes::BytesMut;
+use futures::stream::{Stream, StreamExt, TryStreamExt};
+use tokio::io::AsyncRead;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::codec::{BytesCodec, FramedRead};
+
+use proxmox_async::blocking::TokioWriterAdapter;
+use proxmox_io::StdChannelWriter;
+
+use pbs_api_types::{BackupDir, BackupNamespace, CryptMode};
+use pbs_datastore::catalog::{BackupCatalogWriter, CatalogWriter};
+use pbs_datastore::manifest::{BackupManifest, ENCRYPTED_KEY_BLOB_NAME, MANIFEST_BLOB_NAME};
+use pbs_datastore::CATALOG_NAME;
+use pbs_tools::crypt_config::CryptConfig;
+
+use crate::pxar::PxarCreateOptions;
+use crate::{
+    BackupStats, BackupWriter, ChunkStream, FixedChunkStream, HttpClient, PrehashCache,
+    PxarBackupStream, UploadOptions,
+};
+
+/// Catalog writer feeding the catalog upload stream.
+pub type CatalogUploadWriter = CatalogWriter<TokioWriterAdapter<StdChannelWriter<Error>>>;
+
+/// Options used when starting a [`BackupSession`].
+#[derive(Clone)]
+pub struct BackupSessionOptions {
+    /// Encryption/signing configuration, required unless `crypt_mode` is `CryptMode::None`.
+    pub crypt_config: Option<Arc<CryptConfig>>,
+    /// How archives are protected.
+    pub crypt_mode: CryptMode,
+    /// RSA encrypted copy of the encryption key (master key), stored along with the snapshot.
+    pub rsa_encrypted_key: Option<Vec<u8>>,
+    /// Chunk size for dynamic archives (average) and images (fixed), in bytes.
+    pub chunk_size: Option<usize>,
+    /// Enable debug mode on the server side.
+    pub debug: bool,
+    /// Snapshot of another group in the same namespace to use as base instead of the last
+    /// snapshot of this group, e.g. the template a clone was created from.
+    pub previous_ref: Option<BackupDir>,
+    /// Skip hashing and uploading all-zero chunks of images, and mark them in the manifest so
+    /// that a restore does not need to download them.
+    pub skip_zero_chunks: bool,
+    /// Keep a local cache of the chunk digests by BLAKE3 hash for each archive, so that
+    /// unchanged chunks do not need to be hashed with SHA-256 again.
+    pub prehash_cache: bool,
+}
+
+impl Default for BackupSessionOptions {
+    fn default() -> Self {
+        Self {
+            crypt_config: None,
+            crypt_mode: CryptMode::None,
+            rsa_encrypted_key: None,
+            chunk_size: None,
+            debug: false,
+            previous_ref: None,
+            skip_zero_chunks: false,
+            prehash_cache: false,
+        }
+    }
+}
+
+struct CatalogUploadResult {
+    catalog_writer: Arc<Mutex<CatalogUploadWriter>>,
+    result: oneshot::Receiver<Result<BackupStats, Error>>,
+    secondary_result: Option<oneshot::Receiver<Result<BackupStats, Error>>>,
+}
+
+/// A running backup, created via [`BackupSession::start`].
+///
+/// Archives are added one after the other, and [`BackupSession::finish`] uploads the catalog and
+/// the manifest. Dropping a session without finishing it aborts the backup on the server.
+pub struct BackupSession {
+    writer: Arc<BackupWriter>,
+    ns: BackupNamespace,
+    snapshot: BackupDir,
+    manifest: BackupManifest,
+    previous_manifest: Option<Arc<BackupManifest>>,
+    crypt_config: Option<Arc<CryptConfig>>,
+    crypt_mode: CryptMode,
+    rsa_encrypted_key: Option<Vec<u8>>,
+    chunk_size: Option<usize>,
+    skip_zero_chunks: bool,
+    prehash_cache: bool,
+    catalog: Option<CatalogUploadResult>,
+    renamed_archives: HashMap<String, String>,
+    secondary: Option<Box<BackupSession>>,
+    secondary_error: Option<Error>,
+}
+
+impl BackupSession {
+    /// Start a new backup session for `snapshot` on datastore `store`.
+    ///
+    /// This also tries to download the manifest of the previous snapshot in the group (or of
+    /// `options.previous_ref`), which is used to avoid re-uploading known chunks.
+    pub async fn start(
+        client: &HttpClient,
+        store: &str,
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
+        options: BackupSessionOptions,
+    ) -> Result<Self, Error> {
+        if options.crypt_mode != CryptMode::None && options.crypt_config.is_none() {
+            bail!(
+                "crypt mode '{:?}' requires a crypt config",
+                options.crypt_mode
+            );
+        }
+
+        let writer = BackupWriter::start(
+            client,
+            options.crypt_config.clone(),
+            store,
+            ns,
+            snapshot,
+            options.debug,
+            false,
+            options.previous_ref.as_ref(),
+        )
+        .await?;
+
+        let previous_manifest =
+            Self::load_previous_manifest(&writer, options.crypt_config.as_deref()).await;
+
+        Ok(Self {
+            writer,
+            ns: ns.clone(),
+            snapshot: snapshot.clone(),
+            manifest: BackupManifest::new(snapshot.clone()),
+            previous_manifest,
+            crypt_config: options.crypt_config,
+            crypt_mode: options.crypt_mode,
+            rsa_encrypted_key: options.rsa_encrypted_key,
+            chunk_size: options.chunk_size,
+            skip_zero_chunks: options.skip_zero_chunks,
+            prehash_cache: options.prehash_cache,
+            catalog: None,
+            renamed_archives: HashMap::new(),
+            secondary: None,
+            secondary_error: None,
+        })
+    }
+
+    async fn load_previous_manifest(
+        writer: &BackupWriter,
+        crypt_config: Option<&CryptConfig>,
+    ) -> Option<Arc<BackupManifest>> {
+        match writer.previous_backup_time().await {
+            Ok(Some(backup_time)) => {
+                if let Ok(time) = proxmox_time::strftime_local("%c", backup_time) {
+                    log::info!("Downloading previous manifest ({time})");
+                }
+            }
+            Ok(None) => {
+                log::info!("No previous manifest available.");
+                return None;
+            }
+            Err(_) => {} // outdated server, just try to download it
+        }
+
+        match writer.download_previous_manifest().await {
+            Ok(previous_manifest) => match previous_manifest.check_fingerprint(crypt_config) {
+                Ok(()) => Some(Arc::new(previous_manifest)),
+                Err(err) => {
+                    log::error!("Couldn't re-use previous manifest - {}", err);
+                    None
+                }
+            },
+            Err(err) => {
+                log::error!("Couldn't download previous manifest - {}", err);
+                None
+            }
+        }
+    }
+
+    /// The underlying protocol writer, for low level operations.
+    pub fn writer(&self) -> &Arc<BackupWriter> {
+        &self.writer
+    }
+
+    /// The manifest of the previous snapshot in this group, if available and usable.
+    pub fn previous_manifest(&self) -> Option<&Arc<BackupManifest>> {
+        self.previous_manifest.as_ref()
+    }
+
+    /// The manifest built so far.
+    pub fn manifest(&self) -> &BackupManifest {
+        &self.manifest
+    }
+
+    /// Mutable access to the manifest, e.g. to set unprotected data before finishing.
+    pub fn manifest_mut(&mut self) -> &mut BackupManifest {
+        &mut self.manifest
+    }
+
+    /// How archives of this session are protected.
+    pub fn crypt_mode(&self) -> CryptMode {
+        self.crypt_mode
+    }
+
+    fn encrypt(&self) -> bool {
+        self.crypt_mode == CryptMode::Encrypt
+    }
+
+    /// Upload all archives added from now on to `secondary` as well, e.g. a session for the same
+    /// snapshot on another server.
+    ///
+    /// The sources are read and chunked only once, and the chunk streams are uploaded to both
+    /// sessions in parallel, so the slower one limits the speed. If an upload to the secondary
+    /// session fails, it is aborted and the backup continues with this session only, see
+    /// [`BackupSession::secondary_error`]. Errors of this session do not affect the secondary
+    /// one before [`BackupSession::finish`].
+    pub fn set_secondary(&mut self, secondary: BackupSession) -> Result<(), Error> {
+        if !self.manifest.files().is_empty() || self.catalog.is_some() {
+            bail!("secondary session must be set before adding archives");
+        }
+        if secondary.secondary.is_some() {
+            bail!("secondary session must not have a secondary session itself");
+        }
+        let mut secondary = Box::new(secondary);
+        secondary
+            .renamed_archives
+            .extend(self.renamed_archives.clone());
+        self.secondary = Some(secondary);
+        Ok(())
+    }
+
+    /// Mark archive `archive_name` as continuation of archive `previous_name` of the previous
+    /// snapshot, e.g. after the source was moved. Neither name contains the index extension.
+    ///
+    /// The chunks of the previous archive are reused when uploading `archive_name`, and the
+    /// previous names are recorded in the manifest.
+    pub fn rename_archive(&mut self, archive_name: &str, previous_name: &str) {
+        if let Some(secondary) = self.secondary.as_deref_mut() {
+            secondary.rename_archive(archive_name, previous_name);
+        }
+        self.renamed_archives
+            .insert(archive_name.to_string(), previous_name.to_string());
+    }
+
+    // the name of `target` in the previous snapshot, if it was renamed
+    fn previous_target(&self, target: &str) -> Option<String> {
+        let (archive_name, extension) = target.rsplit_once('.')?;
+        let previous_name = self.renamed_archives.get(archive_name)?;
+        Some(format!("{previous_name}.{extension}"))
+    }
+
+    /// The secondary session, unless it failed.
+    pub fn secondary_mut(&mut self) -> Option<&mut BackupSession> {
+        self.secondary.as_deref_mut()
+    }
+
+    /// The error which aborted the secondary session, if any.
+    pub fn secondary_error(&self) -> Option<&Error> {
+        self.secondary_error.as_ref()
+    }
+
+    // puts back the secondary session after an upload, or aborts it if the upload failed
+    fn keep_secondary(&mut self, secondary: Box<BackupSession>, result: Result<(), Error>) {
+        match result {
+            Ok(()) => self.secondary = Some(secondary),
+            Err(err) => {
+                log::warn!("secondary backup session failed, continuing without it - {err}");
+                secondary.writer.cancel();
+                self.secondary_error = Some(err);
+            }
+        }
+    }
+
+    // a missing cache only costs hashing all chunks, so errors do not fail the backup
+    fn open_prehash_cache(&self, target: &str) -> Option<Arc<PrehashCache>> {
+        if !self.prehash_cache {
+            return None;
+        }
+        let crypt_config = self.crypt_config.as_deref().filter(|_| self.encrypt());
+        match PrehashCache::open(&self.ns, &self.snapshot, target, crypt_config) {
+            Ok(cache) => Some(Arc::new(cache)),
+            Err(err) => {
+                log::warn!("unable to open pre-hash cache for '{target}' - {err}");
+                None
+            }
+        }
+    }
+
+    fn archive_upload_options(&self, target: &str, image_size: Option<u64>) -> UploadOptions {
+        UploadOptions {
+            previous_manifest: self.previous_manifest.clone(),
+            compress: true,
+            encrypt: self.encrypt(),
+            fixed_size: image_size,
+            fixed_chunk_size: image_size.and(self.chunk_size),
+            skip_zero_chunks: image_size.is_some() && self.skip_zero_chunks,
+            previous_archive_name: self.previous_target(target),
+            prehash_cache: self.open_prehash_cache(target),
+        }
+    }
+
+    fn add_archive(&mut self, target: &str, stats: &BackupStats) -> Result<(), Error> {
+        if let Some(digest) = &stats.zero_chunk {
+            self.manifest.set_zero_chunk(target, digest);
+        }
+
+        // keep the names of earlier renames, so listings can show them
+        let previous_target = self.previous_target(target);
+        let mut aliases: Vec<String> = previous_target.iter().cloned().collect();
+        if let Some(previous_manifest) = &self.previous_manifest {
+            let previous_name = previous_target.as_deref().unwrap_or(target);
+            for alias in previous_manifest.archive_aliases(previous_name) {
+                if alias != target && !aliases.contains(&alias) {
+                    aliases.push(alias);
+                }
+            }
+        }
+        if !aliases.is_empty() {
+            self.manifest.set_archive_aliases(target, &aliases);
+        }
+
+        self.manifest
+            .add_file(target.to_string(), stats.size, stats.csum, self.crypt_mode)
+    }
+
+    // uploads the chunk stream of an index archive to this session and the secondary one
+    async fn upload_archive<S>(
+        &mut self,
+        target: &str,
+        stream: S,
+        image_size: Option<u64>,
+    ) -> Result<BackupStats, Error>
+    where
+        S: Stream<Item = Result<BytesMut, Error>> + Send + 'static,
+    {
+        let options = self.archive_upload_options(target, image_size);
+
+        let mut secondary = match self.secondary.take() {
+            Some(secondary) => secondary,
+            None => {
+                let stats = self.writer.upload_stream(target, stream, options).await?;
+                self.add_archive(target, &stats)?;
+                return Ok(stats);
+            }
+        };
+
+        let secondary_options = secondary.archive_upload_options(target, image_size);
+        let (stream, secondary_stream) = tee_chunk_stream(stream);
+
+        let (result, secondary_result) = futures::join!(
+            self.writer.upload_stream(target, stream, options),
+            secondary
+                .writer
+                .upload_stream(target, secondary_stream, secondary_options),
+        );
+
+        let secondary_result =
+            secondary_result.and_then(|stats| secondary.add_archive(target, &stats));
+        self.keep_secondary(secondary, secondary_result);
+
+        let stats = result?;
+        self.add_archive(target, &stats)?;
+        Ok(stats)
+    }
+
+    async fn upload_blob(&mut self, data: Vec<u8>, target: &str) -> Result<BackupStats, Error> {
+        let upload_options = UploadOptions {
+            compress: true,
+            encrypt: self.encrypt(),
+            ..UploadOptions::default()
+        };
+
+        let stats = self
+            .writer
+            .upload_blob_from_data(data, target, upload_options)
+            .await?;
+        self.manifest
+            .add_file(target.to_string(), stats.size, stats.csum, self.crypt_mode)?;
+        Ok(stats)
+    }
+
+    /// Upload a regular file as blob. `archive_name` must not contain the `.blob` extension.
+    pub async fn add_blob_from_file<P: AsRef<Path>>(
+        &mut self,
+        source: P,
+        archive_name: &str,
+    ) -> Result<BackupStats, Error> {
+        let source = source.as_ref();
+        let data = tokio::fs::read(source)
+            .await
+            .map_err(|err| format_err!("unable to read file {:?} - {}", source, err))?;
+
+        self.add_blob_from_data(data, archive_name).await
+    }
+
+    /// Upload in-memory data as blob. `archive_name` must not contain the `.blob` extension.
+    pub async fn add_blob_from_data(
+        &mut self,
+        data: Vec<u8>,
+        archive_name: &str,
+    ) -> Result<BackupStats, Error> {
+        let target = format!("{archive_name}.blob");
+
+        let mut secondary = match self.secondary.take() {
+            Some(secondary) => secondary,
+            None => return self.upload_blob(data, &target).await,
+        };
+
+        let (result, secondary_result) = futures::join!(
+            self.upload_blob(data.clone(), &target),
+            secondary.upload_blob(data, &target),
+        );
+        self.keep_secondary(secondary, secondary_result.map(|_| ()));
+
+        result
+    }
+
+    /// Archive a directory as pxar archive. `archive_name` must not contain the `.didx`
+    /// extension.
+    ///
+    /// The catalog upload is started on first use, and finalized in [`BackupSession::finish`].
+    pub async fn add_pxar<P: AsRef<Path>>(
+        &mut self,
+        source: P,
+        archive_name: &str,
+        pxar_options: PxarCreateOptions,
+    ) -> Result<BackupStats, Error> {
+        let target = format!("{archive_name}.didx");
+
+        if self.catalog.is_none() {
+            let secondary = self
+                .secondary
+                .as_ref()
+                .map(|secondary| (secondary.writer.clone(), secondary.encrypt()));
+            self.catalog = Some(spawn_catalog_upload(
+                self.writer.clone(),
+                self.encrypt(),
+                secondary,
+            )?);
+        }
+        let catalog = self.catalog.as_ref().unwrap().catalog_writer.clone();
+
+        catalog
+            .lock()
+            .unwrap()
+            .start_directory(std::ffi::CString::new(target.as_str())?.as_c_str())?;
+
+        let stream = pxar_chunk_stream(source, self.chunk_size, catalog.clone(), pxar_options)?;
+        let stats = self.upload_archive(&target, stream, None).await?;
+        catalog.lock().unwrap().end_directory()?;
+
+        Ok(stats)
+    }
+
+    /// Archive a regular file or block device of `size` bytes as fixed index image.
+    /// `archive_name` must not contain the `.fidx` extension.
+    pub async fn add_image<P: AsRef<Path>>(
+        &mut self,
+        source: P,
+        archive_name: &str,
+        size: u64,
+    ) -> Result<BackupStats, Error> {
+        let source = source.as_ref();
+        let target = format!("{archive_name}.fidx");
+
+        if size == 0 {
+            bail!("got zero-sized file {:?}", source);
+        }
+
+        let stream = image_chunk_stream(source, self.chunk_size).await?;
+        self.upload_archive(&target, stream, Some(size)).await
+    }
+
+    /// Archive the data read from `reader`, e.g. standard input, as dynamic index, without
+    /// knowing its size in advance. `archive_name` must not contain the `.didx` extension.
+    ///
+    /// The data is chunked like a pxar archive, so repeated dumps deduplicate well, and the size
+    /// is recorded in the manifest once the reader reached its end.
+    pub async fn add_stream<R>(
+        &mut self,
+        reader: R,
+        archive_name: &str,
+    ) -> Result<BackupStats, Error>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        let target = format!("{archive_name}.didx");
+        let stream = FramedRead::new(reader, BytesCodec::new()).map_err(Error::from);
+        let stream = ChunkStream::new(stream, self.chunk_size);
+        self.upload_archive(&target, stream, None).await
+    }
+
+    /// Finalize the catalog, upload the manifest and mark the backup as finished.
+    ///
+    /// The secondary session, if any, is finished as well. Returns the uploaded manifest.
+    pub async fn finish(self) -> Result<BackupManifest, Error> {
+        let (manifest, _) = self.finish_with_secondary().await?;
+        Ok(manifest)
+    }
+
+    /// Like [`BackupSession::finish`], but also returns the error which aborted the secondary
+    /// session, if any.
+    pub async fn finish_with_secondary(mut self) -> Result<(BackupManifest, Option<Error>), Error> {
+        if let Some(catalog) = self.catalog.take() {
+            let mutex = Arc::try_unwrap(catalog.catalog_writer)
+                .map_err(|_| format_err!("unable to get catalog (still used)"))?;
+            let mut catalog_writer = mutex.into_inner().unwrap();
+
+            catalog_writer.finish()?;
+
+            drop(catalog_writer); // close upload stream
+
+            if let Some(secondary_result) = catalog.secondary_result {
+                let result = secondary_result.await.map_err(Error::from);
+                if let Some(mut secondary) = self.secondary.take() {
+                    let result = result.and_then(|result| result).and_then(|stats| {
+                        secondary.manifest.add_file(
+                            CATALOG_NAME.to_owned(),
+                            stats.size,
+                            stats.csum,
+                            secondary.crypt_mode,
+                        )
+                    });
+                    self.keep_secondary(secondary, result);
+                }
+            }
+
+            let stats = catalog.result.await??;
+            self.manifest.add_file(
+                CATALOG_NAME.to_owned(),
+                stats.size,
+                stats.csum,
+                self.crypt_mode,
+            )?;
+        }
+
+        match self.secondary.take() {
+            Some(mut secondary) => {
+                let (result, secondary_result) =
+                    futures::join!(self.upload_manifest(), secondary.upload_manifest());
+                self.keep_secondary(secondary, secondary_result);
+                result?;
+            }
+            None => self.upload_manifest().await?,
+        }
+
+        Ok((self.manifest, self.secondary_error))
+    }
+
+    // uploads the encrypted key and the manifest, and marks the backup as finished
+    async fn upload_manifest(&mut self) -> Result<(), Error> {
+        if let Some(rsa_encrypted_key) = self.rsa_encrypted_key.take() {
+            let target = ENCRYPTED_KEY_BLOB_NAME;
+            log::debug!("Upload RSA encoded key as {}", target);
+            let options = UploadOptions {
+                compress: false,
+                encrypt: false,
+                ..UploadOptions::default()
+            };
+            let stats = self
+                .writer
+                .upload_blob_from_data(rsa_encrypted_key, target, options)
+                .await?;
+            self.manifest
+                .add_file(target.to_string(), stats.size, stats.csum, self.crypt_mode)?;
+        }
+
+        // create manifest (index.json)
+        // manifests are never encrypted, but include a signature
+        let manifest_data = self
+            .manifest
+            .to_string(self.crypt_config.as_deref())
+            .map_err(|err| format_err!("unable to format manifest - {}", err))?;
+
+        log::debug!("Upload index.json");
+
+        let options = UploadOptions {
+            compress: true,
+            encrypt: false,
+            ..UploadOptions::default()
+        };
+        self.writer
+            .upload_blob_from_data(manifest_data.into_bytes(), MANIFEST_BLOB_NAME, options)
+            .await?;
+
+        self.writer.clone().finish().await
+    }
+}
+
+// copies each chunk of `stream` to two streams, which are fed as long as one of them is still
+// consumed, so a failed upload does not stop the other one
+fn tee_chunk_stream<S>(
+    stream: S,
+) -> (
+    impl Stream<Item = Result<BytesMut, Error>>,
+    impl Stream<Item = Result<BytesMut, Error>>,
+)
+where
+    S: Stream<Item = Result<BytesMut, Error>> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(10); // allow to buffer 10 chunks
+    let (secondary_tx, secondary_rx) = mpsc::channel(10);
+
+    tokio::spawn(async move {
+        let mut stream = Box::pin(stream);
+        while let Some(v) = stream.next().await {
+            let copy = match &v {
+                Ok(data) => Ok(data.clone()),
+                Err(err) => Err(format_err!("{err}")),
+            };
+            let (result, secondary_result) = futures::join!(tx.send(v), secondary_tx.send(copy));
+            if result.is_err() && secondary_result.is_err() {
+                break;
+            }
+        }
+    });
+
+    (ReceiverStream::new(rx), ReceiverStream::new(secondary_rx))
+}
+
+fn spawn_catalog_upload(
+    client: Arc<BackupWriter>,
+    encrypt: bool,
+    secondary: Option<(Arc<BackupWriter>, bool)>,
+) -> Result<CatalogUploadResult, Error> {
+    let (catalog_tx, catalog_rx) = std::sync::mpsc::sync_channel(10); // allow to buffer 10 writes
+    let catalog_stream = proxmox_async::blocking::StdChannelStream(catalog_rx);
+    let catalog_chunk_size = 512 * 1024;
+    let catalog_chunk_stream = ChunkStream::new(catalog_stream, Some(catalog_chunk_size));
+
+    let catalog_writer = Arc::new(Mutex::new(CatalogWriter::new(TokioWriterAdapter::new(
+        StdChannelWriter::new(catalog_tx),
+    ))?));
+
+    let (result, secondary_result) = match secondary {
+        None => (
+            spawn_stream_upload(client, catalog_chunk_stream, encrypt),
+            None,
+        ),
+        Some((secondary, secondary_encrypt)) => {
+            let (stream, secondary_stream) = tee_chunk_stream(catalog_chunk_stream);
+            (
+                spawn_stream_upload(client, stream, encrypt),
+                Some(spawn_stream_upload(
+                    secondary,
+                    secondary_stream,
+                    secondary_encrypt,
+                )),
+            )
+        }
+    };
+
+    Ok(CatalogUploadResult {
+        catalog_writer,
+        result,
+        secondary_result,
+    })
+}
+
+fn spawn_stream_upload<S>(
+    client: Arc<BackupWriter>,
+    stream: S,
+    encrypt: bool,
+) -> oneshot::Receiver<Result<BackupStats, Error>>
+where
+    S: Stream<Item = Result<BytesMut, Error>> + Send + 'static,
+{
+    let (catalog_result_tx, catalog_result_rx) = oneshot::channel();
+
+    let upload_options = UploadOptions {
+        encrypt,
+        compress: true,
+        ..UploadOptions::default()
+    };
+
+    tokio::spawn(async move {
+        let catalog_upload_result = client
+            .upload_stream(CATALOG_NAME, stream, upload_options)
+            .await;
+
+        if let Err(ref err) = catalog_upload_result {
+            log::error!("catalog upload error - {}", err);
+            client.cancel();
+        }
+
+        let _ = catalog_result_tx.send(catalog_upload_result);
+    });
+
+    catalog_result_rx
+}
+
+fn pxar_chunk_stream<P: AsRef<Path>>(
+    dir_path: P,
+    chunk_size: Option<usize>,
+    catalog: Arc<Mutex<CatalogUploadWriter>>,
+    pxar_create_options: PxarCreateOptions,
+) -> Result<impl Stream<Item = Result<BytesMut, Error>> + Send + 'static, Error> {
+    let pxar_stream = PxarBackupStream::open(dir_path.as_ref(), catalog, pxar_create_options)?;
+    let mut chunk_stream = ChunkStream::new(pxar_stream, chunk_size);
+
+    let (tx, rx) = mpsc::channel(10); // allow to buffer 10 chunks
+
+    let stream = ReceiverStream::new(rx).map_err(Error::from);
+
+    // spawn chunker inside a separate task so that it can run parallel
+    tokio::spawn(async move {
+        while let Some(v) = chunk_stream.next().await {
+            let _ = tx.send(v).await;
+        }
+    });
+
+    Ok(stream)
+}
+
+async fn image_chunk_stream<P: AsRef<Path>>(
+    image_path: P,
+    chunk_size: Option<usize>,
+) -> Result<impl Stream<Item = Result<BytesMut, Error>> + Send + 'static, Error> {
+    let path = image_path.as_ref().to_owned();
+
+    let file = tokio::fs::File::open(path).await?;
+
+    let stream = tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new())
+        .map_err(Error::from);
+
+    Ok(FixedChunkStream::new(
+        stream,
+        chunk_size.unwrap_or(4 * 1024 * 1024),
+    ))
+}
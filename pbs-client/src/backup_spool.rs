@@ -0,0 +1,636 @@
+//! Local backup spool for offline operation
+//!
+//! If the server is not reachable, a backup can be chunked into a local spool directory and
+//! uploaded later on with [`BackupSpool::upload`]. Chunks are stored compressed, and are
+//! de-duplicated within the spool. Chunks and blobs of encrypted backups are encrypted with the
+//! backup's key before they are written, so the spool never contains their data in plain text.
+//! On upload, the spooled archives are re-assembled from their chunk lists and sent through the
+//! normal upload path, so chunks already known to the server are not transferred again.
+//!
+//! Spool layout:
+//!
+//! ```text
+//! <spool>/chunks/<4 hex digits>/<digest>    chunks, as data blobs
+//! <spool>/queue/<id>/spool.json              snapshot description, see SpooledBackup
+//! <spool>/queue/<id>/<archive>.didx.chunks   chunk digests of dynamic archives
+//! <spool>/queue/<id>/<archive>.fidx.chunks   chunk digests of fixed archives
+//! <spool>/queue/<id>/<archive>.blob          blob content, as data blob
+//! <spool>/queue/<id>/catalog.pcat1.chunks    chunk digests of the catalog
+//! ```
+//!
+//! Note that the server only accepts a backup if it is newer than the last snapshot of the
+//! group, so spooled backups need to be uploaded before the next online backup of that group.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+use bytes::BytesMut;
+use futures::stream::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::image_size;
+
+use pbs_api_types::{BackupDir, BackupNamespace, ChunkDigestAlgorithm, CryptMode, Fingerprint};
+use pbs_datastore::catalog::{BackupCatalogWriter, CatalogWriter};
+use pbs_datastore::data_blob::compute_chunk_digest;
+use pbs_datastore::{DataBlob, CATALOG_NAME};
+use pbs_tools::crypt_config::CryptConfig;
+
+use crate::pxar::PxarCreateOptions;
+use crate::tools::state_file::{create_state_dir, create_state_file, replace_state_file};
+use crate::{
+    BackupSession, BackupSessionOptions, ChunkStream, FixedChunkStream, HttpClient,
+    PxarBackupStream, UploadOptions,
+};
+
+const SPOOL_INFO_NAME: &str = "spool.json";
+const CATALOG_CHUNK_SIZE: usize = 512 * 1024;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A spooled archive.
+pub struct SpooledArchive {
+    /// Archive name including the extension (`.didx`, `.fidx` or `.blob`).
+    pub name: String,
+    /// Archive size in bytes.
+    pub size: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Description of a spooled backup snapshot.
+pub struct SpooledBackup {
+    /// Spool queue entry ID.
+    #[serde(skip)]
+    pub id: String,
+    /// Target repository.
+    pub repository: String,
+    /// Target namespace.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub ns: String,
+    /// The snapshot.
+    #[serde(flatten)]
+    pub snapshot: BackupDir,
+    /// The spooled archives, in upload order.
+    pub archives: Vec<SpooledArchive>,
+    /// Whether the snapshot has a catalog.
+    #[serde(default)]
+    pub catalog: bool,
+    /// Requested crypt mode, applied on upload.
+    pub crypt_mode: CryptMode,
+    /// Fingerprint of the key the spooled data is encrypted with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_fingerprint: Option<Fingerprint>,
+}
+
+fn chunk_path(chunk_dir: &Path, digest: &[u8; 32]) -> PathBuf {
+    let hex = hex::encode(digest);
+    chunk_dir.join(&hex[0..4]).join(hex)
+}
+
+/// Store a chunk in the spool, unless it is already there.
+fn insert_chunk(
+    chunk_dir: &Path,
+    data: &[u8],
+    crypt_config: Option<&CryptConfig>,
+) -> Result<[u8; 32], Error> {
+    // like on the server, the digest of encrypted chunks does not reveal their content
+    let digest = match crypt_config {
+        Some(crypt_config) => crypt_config.compute_digest(data),
+        None => compute_chunk_digest(ChunkDigestAlgorithm::default(), data),
+    };
+    let path = chunk_path(chunk_dir, &digest);
+    if path.exists() {
+        return Ok(digest);
+    }
+    create_state_dir(path.parent().unwrap())?;
+    let blob = DataBlob::encode(data, crypt_config, true)?;
+    replace_state_file(&path, blob.raw_data(), false)?;
+    Ok(digest)
+}
+
+fn load_chunk(
+    chunk_dir: &Path,
+    digest: &[u8; 32],
+    crypt_config: Option<&CryptConfig>,
+) -> Result<BytesMut, Error> {
+    let path = chunk_path(chunk_dir, digest);
+    let mut file = File::open(&path)
+        .map_err(|err| format_err!("unable to open spooled chunk {:?} - {}", path, err))?;
+    let blob = DataBlob::load_from_reader(&mut file)?;
+    let data = blob.decode(crypt_config, Some(digest))?;
+    Ok(BytesMut::from(&data[..]))
+}
+
+/// Spools the data written to it as chunks of `CATALOG_CHUNK_SIZE`, used for the catalog.
+struct SpoolChunkWriter {
+    chunk_dir: PathBuf,
+    crypt_config: Option<Arc<CryptConfig>>,
+    buffer: Vec<u8>,
+    list: File,
+}
+
+impl SpoolChunkWriter {
+    fn write_chunk(&mut self, len: usize) -> std::io::Result<()> {
+        let data: Vec<u8> = self.buffer.drain(..len).collect();
+        let digest = insert_chunk(&self.chunk_dir, &data, self.crypt_config.as_deref())
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+        writeln!(self.list, "{}", hex::encode(digest))
+    }
+}
+
+impl Write for SpoolChunkWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        while self.buffer.len() >= CATALOG_CHUNK_SIZE {
+            self.write_chunk(CATALOG_CHUNK_SIZE)?;
+        }
+        Ok(data.len())
+    }
+
+    // the catalog writer only flushes once it is finished, so the rest is the last chunk
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_chunk(self.buffer.len())?;
+        }
+        self.list.sync_all()
+    }
+}
+
+/// A local backup spool directory.
+pub struct BackupSpool {
+    base: PathBuf,
+}
+
+impl BackupSpool {
+    /// Open (and create if needed) the spool at `base`.
+    pub fn open<P: AsRef<Path>>(base: P) -> Result<Self, Error> {
+        let base = base.as_ref().to_owned();
+        for dir in ["chunks", "queue", "tmp"] {
+            create_state_dir(base.join(dir))
+                .map_err(|err| format_err!("unable to create spool {:?} - {}", base, err))?;
+        }
+        Ok(Self { base })
+    }
+
+    fn queue_dir(&self) -> PathBuf {
+        self.base.join("queue")
+    }
+
+    fn chunk_dir(&self) -> PathBuf {
+        self.base.join("chunks")
+    }
+
+    /// Start spooling a new backup of `snapshot`.
+    ///
+    /// With `crypt_mode` encrypt, all spooled data is encrypted with `crypt_config`, which the
+    /// upload then needs as well. Signing happens on upload only.
+    pub fn begin(
+        &self,
+        repository: &str,
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
+        crypt_mode: CryptMode,
+        crypt_config: Option<Arc<CryptConfig>>,
+    ) -> Result<SpoolWriter, Error> {
+        let crypt_config = match (crypt_mode, crypt_config) {
+            (CryptMode::Encrypt, None) => bail!("spooling an encrypted backup requires a key"),
+            (CryptMode::Encrypt, crypt_config) => crypt_config,
+            (_, _) => None,
+        };
+
+        let id = format!(
+            "{}-{}-{}",
+            snapshot.ty(),
+            snapshot.id(),
+            proxmox_time::epoch_to_rfc3339_utc(snapshot.time)?,
+        );
+        if self.queue_dir().join(&id).exists() {
+            bail!("backup '{}' is already spooled", id);
+        }
+
+        let tmp_dir = self.base.join("tmp").join(&id);
+        if tmp_dir.exists() {
+            // left over from an interrupted run
+            std::fs::remove_dir_all(&tmp_dir)?;
+        }
+        create_state_dir(&tmp_dir)?;
+
+        Ok(SpoolWriter {
+            spool: self,
+            dir: tmp_dir,
+            info: SpooledBackup {
+                id,
+                repository: repository.to_string(),
+                ns: ns.to_string(),
+                snapshot: snapshot.clone(),
+                archives: Vec::new(),
+                catalog: false,
+                crypt_mode,
+                key_fingerprint: crypt_config
+                    .as_ref()
+                    .map(|crypt_config| Fingerprint::new(crypt_config.fingerprint())),
+            },
+            crypt_config,
+            catalog: None,
+        })
+    }
+
+    /// List spooled backups, oldest first.
+    pub fn list(&self) -> Result<Vec<SpooledBackup>, Error> {
+        let mut list = Vec::new();
+        for entry in std::fs::read_dir(self.queue_dir())? {
+            let entry = entry?;
+            let id = match entry.file_name().into_string() {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            let path = entry.path().join(SPOOL_INFO_NAME);
+            let data = std::fs::read(&path)
+                .map_err(|err| format_err!("unable to read {:?} - {}", path, err))?;
+            let mut info: SpooledBackup = serde_json::from_slice(&data)
+                .map_err(|err| format_err!("unable to parse {:?} - {}", path, err))?;
+            info.id = id;
+            list.push(info);
+        }
+        list.sort_by_key(|info| info.snapshot.time);
+        Ok(list)
+    }
+
+    /// Remove a spooled backup, and all chunks no longer referenced by any other.
+    pub fn remove(&self, id: &str) -> Result<(), Error> {
+        let dir = self.queue_dir().join(id);
+        if !dir.exists() {
+            bail!("no such spooled backup '{}'", id);
+        }
+        std::fs::remove_dir_all(&dir)?;
+        self.cleanup_chunks()
+    }
+
+    fn read_chunk_list(path: &Path) -> Result<Vec<[u8; 32]>, Error> {
+        let file = File::open(path)
+            .map_err(|err| format_err!("unable to open chunk list {:?} - {}", path, err))?;
+        let mut list = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let mut digest = [0u8; 32];
+            hex::decode_to_slice(line?.trim(), &mut digest)
+                .map_err(|err| format_err!("invalid chunk list {:?} - {}", path, err))?;
+            list.push(digest);
+        }
+        Ok(list)
+    }
+
+    /// Remove chunks which are not referenced by any spooled backup (including backups which are
+    /// currently being spooled).
+    fn cleanup_chunks(&self) -> Result<(), Error> {
+        let mut used = std::collections::HashSet::new();
+        for parent in [self.queue_dir(), self.base.join("tmp")] {
+            for backup in std::fs::read_dir(parent)? {
+                for file in std::fs::read_dir(backup?.path())? {
+                    let path = file?.path();
+                    if path.extension().map(|ext| ext == "chunks").unwrap_or(false) {
+                        used.extend(Self::read_chunk_list(&path)?);
+                    }
+                }
+            }
+        }
+
+        for prefix in std::fs::read_dir(self.chunk_dir())? {
+            let prefix = prefix?;
+            for chunk in std::fs::read_dir(prefix.path())? {
+                let chunk = chunk?;
+                let mut digest = [0u8; 32];
+                match hex::decode_to_slice(chunk.file_name().as_bytes(), &mut digest) {
+                    Ok(()) if used.contains(&digest) => continue,
+                    _ => std::fs::remove_file(chunk.path())?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // reading and decoding chunks blocks, so it is done outside of the async executor
+    fn load_chunks(
+        &self,
+        chunks: Vec<[u8; 32]>,
+        crypt_config: Option<Arc<CryptConfig>>,
+    ) -> impl futures::Stream<Item = Result<BytesMut, Error>> {
+        let chunk_dir = self.chunk_dir();
+        futures::stream::iter(chunks).then(move |digest| {
+            let chunk_dir = chunk_dir.clone();
+            let crypt_config = crypt_config.clone();
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    load_chunk(&chunk_dir, &digest, crypt_config.as_deref())
+                })
+                .await?
+            }
+        })
+    }
+
+    /// Upload a spooled backup and remove it from the spool afterwards.
+    ///
+    /// `client` has to be connected to the backup's repository.
+    pub async fn upload(
+        &self,
+        client: &HttpClient,
+        store: &str,
+        info: &SpooledBackup,
+        options: BackupSessionOptions,
+    ) -> Result<(), Error> {
+        if options.crypt_mode != info.crypt_mode {
+            bail!(
+                "backup '{}' was spooled with crypt mode '{:?}', but upload uses '{:?}'",
+                info.id,
+                info.crypt_mode,
+                options.crypt_mode,
+            );
+        }
+
+        // spooled data can only be decrypted with the key it was spooled with
+        let crypt_config = match &info.key_fingerprint {
+            Some(fingerprint) => {
+                let crypt_config = options.crypt_config.clone().filter(|crypt_config| {
+                    Fingerprint::new(crypt_config.fingerprint()) == *fingerprint
+                });
+                if crypt_config.is_none() {
+                    bail!(
+                        "backup '{}' was spooled encrypted with key {}, which is not used for \
+                        the upload",
+                        info.id,
+                        fingerprint,
+                    );
+                }
+                crypt_config
+            }
+            None => None,
+        };
+
+        let dir = self.queue_dir().join(&info.id);
+        let ns: BackupNamespace = info.ns.parse()?;
+
+        let mut session = BackupSession::start(client, store, &ns, &info.snapshot, options).await?;
+        let crypt_mode = session.crypt_mode();
+        let encrypt = crypt_mode == CryptMode::Encrypt;
+
+        for archive in info.archives.iter() {
+            log::info!("Upload spooled archive '{}'", archive.name);
+
+            let path = dir.join(&archive.name);
+
+            let stats = if archive.name.ends_with(".blob") {
+                let raw_data = tokio::fs::read(&path)
+                    .await
+                    .map_err(|err| format_err!("unable to read {:?} - {}", path, err))?;
+                let data = DataBlob::load_from_reader(&mut &raw_data[..])?
+                    .decode(crypt_config.as_deref(), None)?;
+                let upload_options = UploadOptions {
+                    compress: true,
+                    encrypt,
+                    ..UploadOptions::default()
+                };
+                session
+                    .writer()
+                    .upload_blob_from_data(data, &archive.name, upload_options)
+                    .await?
+            } else {
+                let fixed_size = if archive.name.ends_with(".fidx") {
+                    Some(archive.size)
+                } else {
+                    None
+                };
+                let upload_options = UploadOptions {
+                    previous_manifest: session.previous_manifest().cloned(),
+                    compress: true,
+                    encrypt,
+                    fixed_size,
+                    fixed_chunk_size: None,
+                    skip_zero_chunks: false,
+                    previous_archive_name: None,
+                    prehash_cache: None,
+                };
+
+                let chunks = Self::read_chunk_list(&dir.join(format!("{}.chunks", archive.name)))?;
+                let stream = self.load_chunks(chunks, crypt_config.clone());
+
+                session
+                    .writer()
+                    .upload_stream(&archive.name, stream, upload_options)
+                    .await?
+            };
+
+            session.manifest_mut().add_file(
+                archive.name.clone(),
+                stats.size,
+                stats.csum,
+                crypt_mode,
+            )?;
+        }
+
+        if info.catalog {
+            let chunks = Self::read_chunk_list(&dir.join(format!("{CATALOG_NAME}.chunks")))?;
+            let stream = self.load_chunks(chunks, crypt_config.clone());
+            let upload_options = UploadOptions {
+                compress: true,
+                encrypt,
+                ..UploadOptions::default()
+            };
+            let stats = session
+                .writer()
+                .upload_stream(CATALOG_NAME, stream, upload_options)
+                .await?;
+            session.manifest_mut().add_file(
+                CATALOG_NAME.to_string(),
+                stats.size,
+                stats.csum,
+                crypt_mode,
+            )?;
+        }
+
+        session.finish().await?;
+
+        self.remove(&info.id)
+    }
+}
+
+/// Spools one backup snapshot, created with [`BackupSpool::begin`].
+///
+/// Archives are written into a temporary directory, which is only moved into the queue by
+/// [`SpoolWriter::finish`].
+pub struct SpoolWriter<'a> {
+    spool: &'a BackupSpool,
+    dir: PathBuf,
+    info: SpooledBackup,
+    crypt_config: Option<Arc<CryptConfig>>,
+    catalog: Option<Arc<Mutex<CatalogWriter<SpoolChunkWriter>>>>,
+}
+
+impl<'a> SpoolWriter<'a> {
+    fn check_name(&self, name: &str) -> Result<(), Error> {
+        if name.contains('/') || self.info.archives.iter().any(|a| a.name == name) {
+            bail!("invalid or duplicate archive name '{}'", name);
+        }
+        Ok(())
+    }
+
+    async fn spool_chunks<S>(
+        &mut self,
+        name: String,
+        size: u64,
+        mut stream: S,
+    ) -> Result<u64, Error>
+    where
+        S: futures::Stream<Item = Result<BytesMut, Error>> + Unpin,
+    {
+        let mut list = create_state_file(self.dir.join(format!("{}.chunks", name)))?;
+        let chunk_dir = self.spool.chunk_dir();
+        let mut total = 0;
+        while let Some(chunk) = stream.try_next().await? {
+            let digest = insert_chunk(&chunk_dir, &chunk, self.crypt_config.as_deref())?;
+            writeln!(list, "{}", hex::encode(digest))?;
+            total += chunk.len() as u64;
+        }
+        list.sync_all()?;
+
+        self.info.archives.push(SpooledArchive {
+            name,
+            size: if size > 0 { size } else { total },
+        });
+        Ok(total)
+    }
+
+    /// Spool a directory as pxar archive. `archive_name` must not contain the `.didx` extension.
+    pub async fn add_pxar<P: AsRef<Path>>(
+        &mut self,
+        source: P,
+        archive_name: &str,
+        chunk_size: Option<usize>,
+        pxar_options: PxarCreateOptions,
+    ) -> Result<u64, Error> {
+        let target = format!("{archive_name}.didx");
+        self.check_name(&target)?;
+
+        if self.catalog.is_none() {
+            let writer = SpoolChunkWriter {
+                chunk_dir: self.spool.chunk_dir(),
+                crypt_config: self.crypt_config.clone(),
+                buffer: Vec::new(),
+                list: create_state_file(self.dir.join(format!("{CATALOG_NAME}.chunks")))?,
+            };
+            self.catalog = Some(Arc::new(Mutex::new(CatalogWriter::new(writer)?)));
+            self.info.catalog = true;
+        }
+        let catalog = self.catalog.clone().unwrap();
+
+        catalog
+            .lock()
+            .unwrap()
+            .start_directory(std::ffi::CString::new(target.as_str())?.as_c_str())?;
+
+        let pxar_stream = PxarBackupStream::open(source.as_ref(), catalog.clone(), pxar_options)?;
+        let chunk_stream = ChunkStream::new(pxar_stream, chunk_size);
+
+        let size = self.spool_chunks(target, 0, chunk_stream).await?;
+
+        catalog.lock().unwrap().end_directory()?;
+
+        Ok(size)
+    }
+
+    /// Spool a regular file or block device as fixed index image. `archive_name` must not
+    /// contain the `.fidx` extension.
+    pub async fn add_image<P: AsRef<Path>>(
+        &mut self,
+        source: P,
+        archive_name: &str,
+        chunk_size: Option<usize>,
+    ) -> Result<u64, Error> {
+        let source = source.as_ref();
+        let target = format!("{archive_name}.fidx");
+        self.check_name(&target)?;
+
+        let size = image_size(source)?;
+        if size == 0 {
+            bail!("got zero-sized file {:?}", source);
+        }
+
+        let file = tokio::fs::File::open(source).await?;
+        let stream = tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new())
+            .map_err(Error::from);
+        let stream = FixedChunkStream::new(stream, chunk_size.unwrap_or(4 * 1024 * 1024));
+
+        self.spool_chunks(target, size, stream).await
+    }
+
+    /// Spool the data read from `reader`, e.g. standard input, as dynamic index.
+    /// `archive_name` must not contain the `.didx` extension.
+    pub async fn add_stream<R>(
+        &mut self,
+        reader: R,
+        archive_name: &str,
+        chunk_size: Option<usize>,
+    ) -> Result<u64, Error>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+    {
+        let target = format!("{archive_name}.didx");
+        self.check_name(&target)?;
+
+        let stream =
+            tokio_util::codec::FramedRead::new(reader, tokio_util::codec::BytesCodec::new())
+                .map_err(Error::from);
+        let chunk_stream = ChunkStream::new(stream, chunk_size);
+
+        self.spool_chunks(target, 0, chunk_stream).await
+    }
+
+    /// Spool a regular file as blob. `archive_name` must not contain the `.blob` extension.
+    pub fn add_blob_from_file<P: AsRef<Path>>(
+        &mut self,
+        source: P,
+        archive_name: &str,
+    ) -> Result<u64, Error> {
+        let target = format!("{archive_name}.blob");
+        self.check_name(&target)?;
+
+        let mut data = Vec::new();
+        File::open(source.as_ref())?.read_to_end(&mut data)?;
+        let blob = DataBlob::encode(&data, self.crypt_config.as_deref(), true)?;
+        replace_state_file(self.dir.join(&target), blob.raw_data(), true)?;
+
+        let size = data.len() as u64;
+        self.info
+            .archives
+            .push(SpooledArchive { name: target, size });
+        Ok(size)
+    }
+
+    /// Finalize the spooled backup and queue it for upload.
+    pub fn finish(mut self) -> Result<SpooledBackup, Error> {
+        if let Some(catalog) = self.catalog.take() {
+            let mutex = Arc::try_unwrap(catalog)
+                .map_err(|_| format_err!("unable to get catalog (still used)"))?;
+            mutex.into_inner().unwrap().finish()?;
+        }
+
+        let data = serde_json::to_vec_pretty(&self.info)?;
+        replace_state_file(self.dir.join(SPOOL_INFO_NAME), &data, true)?;
+
+        std::fs::rename(&self.dir, self.spool.queue_dir().join(&self.info.id))?;
+
+        Ok(self.info.clone())
+    }
+}
+
+impl<'a> Drop for SpoolWriter<'a> {
+    fn drop(&mut self) {
+        // unfinished backups are discarded, their chunks are removed by the next cleanup
+        if self.dir.exists() {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
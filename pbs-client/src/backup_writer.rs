@@ -1,19 +1,19 @@
 use std::collections::HashSet;
 use std::future::Future;
-use std::os::unix::fs::OpenOptionsExt;
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Error};
 use futures::future::{self, AbortHandle, Either, FutureExt, TryFutureExt};
 use futures::stream::{Stream, StreamExt, TryStreamExt};
+use hex::FromHex;
 use serde_json::{json, Value};
 use tokio::io::AsyncReadExt;
 use tokio::sync::{mpsc, oneshot};
 use tokio_stream::wrappers::ReceiverStream;
 
-use pbs_api_types::{BackupDir, BackupNamespace};
-use pbs_datastore::data_blob::{ChunkInfo, DataBlob, DataChunkBuilder};
+use pbs_api_types::{BackupDir, BackupNamespace, ChunkDigestAlgorithm};
+use pbs_datastore::data_blob::{compute_chunk_digest, ChunkInfo, DataBlob, DataChunkBuilder};
 use pbs_datastore::dynamic_index::DynamicIndexReader;
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
@@ -23,7 +23,9 @@ use pbs_tools::crypt_config::CryptConfig;
 
 use proxmox_human_byte::HumanByte;
 
+use super::chunk_stream::is_zero_chunk;
 use super::merge_known_chunks::{MergeKnownChunks, MergedChunkInfo};
+use super::prehash_cache::PrehashCache;
 
 use super::{H2Client, HttpClient};
 
@@ -42,6 +44,8 @@ impl Drop for BackupWriter {
 pub struct BackupStats {
     pub size: u64,
     pub csum: [u8; 32],
+    /// Digest of the zero chunk, if zero chunks were detected and skipped.
+    pub zero_chunk: Option<[u8; 32]>,
 }
 
 /// Options for uploading blobs/streams to the server
@@ -51,6 +55,23 @@ pub struct UploadOptions {
     pub compress: bool,
     pub encrypt: bool,
     pub fixed_size: Option<u64>,
+    /// Chunk size of fixed indexes, the server default (4 MiB) is used if not set.
+    pub fixed_chunk_size: Option<usize>,
+    /// Detect chunks of fixed indexes which contain only zeroes, and reference a prebuilt zero
+    /// chunk for them instead of hashing, compressing and encrypting each one.
+    pub skip_zero_chunks: bool,
+    /// Name of the archive in the previous manifest, if it was renamed since. Its chunks are
+    /// reused instead of those of an archive with the same name.
+    pub previous_archive_name: Option<String>,
+    /// Look up the digests of unchanged chunks by their BLAKE3 hash instead of computing them.
+    pub prehash_cache: Option<Arc<PrehashCache>>,
+}
+
+/// The prebuilt chunk used for all-zero chunks of a fixed index.
+struct ZeroChunk {
+    chunk: DataBlob,
+    digest: [u8; 32],
+    size: usize,
 }
 
 struct UploadStats {
@@ -59,10 +80,24 @@ struct UploadStats {
     size: usize,
     size_reused: usize,
     size_compressed: usize,
+    zero_chunks: usize,
     duration: std::time::Duration,
     csum: [u8; 32],
 }
 
+/// Number of chunks checked per known chunks query.
+const KNOWN_CHUNKS_QUERY_BATCH: usize = 64;
+
+/// Number of chunks hashed, compressed and encrypted in parallel while uploading.
+///
+/// This runs on the blocking thread pool, so that the async tasks only have to send the encoded
+/// chunks, and also limits how many chunks are held in memory before they are uploaded.
+fn chunk_encode_workers() -> usize {
+    std::thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(1)
+}
+
 type UploadQueueSender = mpsc::Sender<(MergedChunkInfo, Option<h2::client::ResponseFuture>)>;
 type UploadResultReceiver = oneshot::Receiver<Result<(), Error>>;
 
@@ -85,6 +120,7 @@ impl BackupWriter {
         backup: &BackupDir,
         debug: bool,
         benchmark: bool,
+        previous_ref: Option<&BackupDir>,
     ) -> Result<Arc<BackupWriter>, Error> {
         let mut param = json!({
             "backup-type": backup.ty(),
@@ -99,6 +135,10 @@ impl BackupWriter {
             param["ns"] = serde_json::to_value(ns)?;
         }
 
+        if let Some(previous_ref) = previous_ref {
+            param["previous-ref"] = previous_ref.to_string().into();
+        }
+
         let req = HttpClient::request_builder(
             client.server(),
             client.port(),
@@ -203,7 +243,11 @@ impl BackupWriter {
                 raw_data,
             )
             .await?;
-        Ok(BackupStats { size, csum })
+        Ok(BackupStats {
+            size,
+            csum,
+            zero_chunk: None,
+        })
     }
 
     pub async fn upload_blob_from_data(
@@ -235,7 +279,11 @@ impl BackupWriter {
                 raw_data,
             )
             .await?;
-        Ok(BackupStats { size, csum })
+        Ok(BackupStats {
+            size,
+            csum,
+            zero_chunk: None,
+        })
     }
 
     pub async fn upload_blob_from_file<P: AsRef<std::path::Path>>(
@@ -271,6 +319,9 @@ impl BackupWriter {
         let mut param = json!({ "archive-name": archive_name });
         let prefix = if let Some(size) = options.fixed_size {
             param["size"] = size.into();
+            if let Some(chunk_size) = options.fixed_chunk_size {
+                param["chunk-size"] = chunk_size.into();
+            }
             "fixed"
         } else {
             "dynamic"
@@ -283,20 +334,48 @@ impl BackupWriter {
         let index_path = format!("{}_index", prefix);
         let close_path = format!("{}_close", prefix);
 
+        let zero_chunk = match options.fixed_size {
+            Some(_) if options.skip_zero_chunks => {
+                let size = options.fixed_chunk_size.unwrap_or(4 * 1024 * 1024);
+                let crypt_config = if options.encrypt {
+                    self.crypt_config.as_deref()
+                } else {
+                    None
+                };
+                let (chunk, digest) =
+                    DataChunkBuilder::build_zero_chunk(crypt_config, size, options.compress)?;
+                Some(ZeroChunk {
+                    chunk,
+                    digest,
+                    size,
+                })
+            }
+            _ => None,
+        };
+        let zero_digest = zero_chunk.as_ref().map(|zero| zero.digest);
+
+        let previous_name = options
+            .previous_archive_name
+            .as_deref()
+            .unwrap_or(archive_name);
+
         if let Some(manifest) = options.previous_manifest {
             if !manifest
                 .files()
                 .iter()
-                .any(|file| file.filename == archive_name)
+                .any(|file| file.filename == previous_name)
             {
-                log::info!("Previous manifest does not contain an archive called '{archive_name}', skipping download..");
+                log::info!("Previous manifest does not contain an archive called '{previous_name}', skipping download..");
             } else {
+                if previous_name != archive_name {
+                    log::info!("Reusing chunks of renamed archive '{previous_name}'");
+                }
                 // try, but ignore errors
-                match ArchiveType::from_path(archive_name) {
+                match ArchiveType::from_path(previous_name) {
                     Ok(ArchiveType::FixedIndex) => {
                         if let Err(err) = self
                             .download_previous_fixed_index(
-                                archive_name,
+                                previous_name,
                                 &manifest,
                                 known_chunks.clone(),
                             )
@@ -308,7 +387,7 @@ impl BackupWriter {
                     Ok(ArchiveType::DynamicIndex) => {
                         if let Err(err) = self
                             .download_previous_dynamic_index(
-                                archive_name,
+                                previous_name,
                                 &manifest,
                                 known_chunks.clone(),
                             )
@@ -322,6 +401,9 @@ impl BackupWriter {
             }
         }
 
+        // without a previous index, ask the server which chunks it already has
+        let query_known_chunks = known_chunks.lock().unwrap().is_empty();
+
         let wid = self
             .h2
             .post(&index_path, Some(param))
@@ -341,9 +423,18 @@ impl BackupWriter {
                 None
             },
             options.compress,
+            query_known_chunks,
+            zero_chunk,
+            options.prehash_cache.clone(),
         )
         .await?;
 
+        if let Some(prehash_cache) = &options.prehash_cache {
+            if let Err(err) = prehash_cache.save() {
+                log::warn!("unable to save pre-hash cache - {err}");
+            }
+        }
+
         let size_dirty = upload_stats.size - upload_stats.size_reused;
         let size: HumanByte = upload_stats.size.into();
         let archive = if log::log_enabled!(log::Level::Debug) {
@@ -380,6 +471,13 @@ impl BackupWriter {
                 reused_percent
             );
         }
+        if upload_stats.zero_chunks > 0 {
+            log::info!(
+                "{}: skipped {} zero chunks",
+                archive,
+                upload_stats.zero_chunks
+            );
+        }
         if log::log_enabled!(log::Level::Debug) && upload_stats.chunk_count > 0 {
             log::debug!(
                 "{}: Reused {} from {} chunks.",
@@ -409,6 +507,7 @@ impl BackupWriter {
         Ok(BackupStats {
             size: upload_stats.size as u64,
             csum: upload_stats.csum,
+            zero_chunk: zero_digest.filter(|_| upload_stats.zero_chunks > 0),
         })
     }
 
@@ -523,11 +622,7 @@ impl BackupWriter {
         manifest: &BackupManifest,
         known_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
     ) -> Result<FixedIndexReader, Error> {
-        let mut tmpfile = std::fs::OpenOptions::new()
-            .write(true)
-            .read(true)
-            .custom_flags(libc::O_TMPFILE)
-            .open("/tmp")?;
+        let mut tmpfile = pbs_tools::fs::open_tmpfile("/tmp")?;
 
         let param = json!({ "archive-name": archive_name });
         self.h2
@@ -562,11 +657,7 @@ impl BackupWriter {
         manifest: &BackupManifest,
         known_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
     ) -> Result<DynamicIndexReader, Error> {
-        let mut tmpfile = std::fs::OpenOptions::new()
-            .write(true)
-            .read(true)
-            .custom_flags(libc::O_TMPFILE)
-            .open("/tmp")?;
+        let mut tmpfile = pbs_tools::fs::open_tmpfile("/tmp")?;
 
         let param = json!({ "archive-name": archive_name });
         self.h2
@@ -605,6 +696,52 @@ impl BackupWriter {
         })
     }
 
+    /// Query the chunk deduplication statistics of this backup session so far.
+    pub async fn session_stats(&self) -> Result<Value, Error> {
+        self.h2.get("session_stats", None).await
+    }
+
+    /// Log the deduplication ratio and the datastore growth of this session every `interval`,
+    /// until the returned handle is aborted.
+    ///
+    /// Stops silently if the server does not support querying the statistics.
+    pub fn log_session_stats(self: &Arc<Self>, interval: std::time::Duration) -> AbortHandle {
+        let writer = Arc::downgrade(self);
+        let (future, handle) = future::abortable(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await; // the first tick completes immediately
+            loop {
+                ticker.tick().await;
+                let writer = match writer.upgrade() {
+                    Some(writer) => writer,
+                    None => break,
+                };
+                let stats = match writer.session_stats().await {
+                    Ok(stats) => stats,
+                    Err(err) => {
+                        log::debug!("stop querying session statistics - {err}");
+                        break;
+                    }
+                };
+
+                let chunk_count = stats["chunk-count"].as_u64().unwrap_or(0);
+                if chunk_count == 0 {
+                    continue;
+                }
+                let new_chunks = stats["new-chunks"].as_u64().unwrap_or(0).min(chunk_count);
+                let new_size = stats["new-size"].as_u64().unwrap_or(0);
+                log::info!(
+                    "{} chunks, {:.1}% deduplicated, datastore grows by {}",
+                    chunk_count,
+                    (chunk_count - new_chunks) as f64 * 100.0 / chunk_count as f64,
+                    HumanByte::from(new_size),
+                );
+            }
+        });
+        tokio::spawn(future);
+        handle
+    }
+
     /// Download backup manifest (index.json) of last backup
     pub async fn download_previous_manifest(&self) -> Result<BackupManifest, Error> {
         let mut raw_data = Vec::with_capacity(64 * 1024);
@@ -636,6 +773,9 @@ impl BackupWriter {
         known_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
         crypt_config: Option<Arc<CryptConfig>>,
         compress: bool,
+        query_known_chunks: bool,
+        zero_chunk: Option<ZeroChunk>,
+        prehash_cache: Option<Arc<PrehashCache>>,
     ) -> impl Future<Output = Result<UploadStats, Error>> {
         let total_chunks = Arc::new(AtomicUsize::new(0));
         let total_chunks2 = total_chunks.clone();
@@ -648,6 +788,8 @@ impl BackupWriter {
         let compressed_stream_len2 = compressed_stream_len.clone();
         let reused_len = Arc::new(AtomicUsize::new(0));
         let reused_len2 = reused_len.clone();
+        let zero_chunk_count = Arc::new(AtomicUsize::new(0));
+        let zero_chunk_count2 = zero_chunk_count.clone();
 
         let append_chunk_path = format!("{}_index", prefix);
         let upload_chunk_path = format!("{}_chunk", prefix);
@@ -661,21 +803,62 @@ impl BackupWriter {
         let index_csum = Arc::new(Mutex::new(Some(openssl::sha::Sha256::new())));
         let index_csum_2 = index_csum.clone();
 
+        let stream = if query_known_chunks {
+            Either::Left(Self::query_known_chunks(
+                h2.clone(),
+                stream,
+                known_chunks.clone(),
+                crypt_config.clone(),
+            ))
+        } else {
+            Either::Right(stream)
+        };
+
+        let workers = chunk_encode_workers();
+        let zero_chunk = zero_chunk.map(Arc::new);
+        let crypt_config2 = crypt_config.clone();
+
         stream
-            .and_then(move |data| {
+            // hash in parallel, the order of the chunks is kept
+            .map_ok(move |data| {
+                let crypt_config = crypt_config.clone();
+                let zero_chunk = zero_chunk.clone();
+                let prehash_cache = prehash_cache.clone();
+                async move {
+                    let hashed = tokio::task::spawn_blocking(move || {
+                        let zero_chunk = zero_chunk
+                            .filter(|zero| zero.size == data.len() && is_zero_chunk(&data));
+                        let compute_digest = || {
+                            let mut chunk_builder = DataChunkBuilder::new(data.as_ref());
+                            if let Some(ref crypt_config) = crypt_config {
+                                chunk_builder = chunk_builder.crypt_config(crypt_config);
+                            }
+                            *chunk_builder.digest()
+                        };
+                        let digest = match (&zero_chunk, &prehash_cache) {
+                            (Some(zero), _) => zero.digest,
+                            (None, Some(cache)) => cache.digest(&data, compute_digest),
+                            (None, None) => compute_digest(),
+                        };
+                        (data, digest, zero_chunk)
+                    })
+                    .await?;
+                    Ok::<_, Error>(hashed)
+                }
+            })
+            .try_buffered(workers)
+            // decide in order which chunks are new, and encode those in parallel
+            .map_ok(move |(data, digest, zero_chunk)| {
                 let chunk_len = data.len();
 
                 total_chunks.fetch_add(1, Ordering::SeqCst);
                 let offset = stream_len.fetch_add(chunk_len, Ordering::SeqCst) as u64;
 
-                let mut chunk_builder = DataChunkBuilder::new(data.as_ref()).compress(compress);
-
-                if let Some(ref crypt_config) = crypt_config {
-                    chunk_builder = chunk_builder.crypt_config(crypt_config);
+                if zero_chunk.is_some() {
+                    zero_chunk_count.fetch_add(1, Ordering::SeqCst);
                 }
 
                 let mut known_chunks = known_chunks.lock().unwrap();
-                let digest = chunk_builder.digest();
 
                 let mut guard = index_csum.lock().unwrap();
                 let csum = guard.as_mut().unwrap();
@@ -685,27 +868,41 @@ impl BackupWriter {
                 if !is_fixed_chunk_size {
                     csum.update(&chunk_end.to_le_bytes());
                 }
-                csum.update(digest);
+                csum.update(&digest);
 
-                let chunk_is_known = known_chunks.contains(digest);
+                let chunk_is_known = known_chunks.contains(&digest);
                 if chunk_is_known {
                     known_chunk_count.fetch_add(1, Ordering::SeqCst);
                     reused_len.fetch_add(chunk_len, Ordering::SeqCst);
-                    future::ok(MergedChunkInfo::Known(vec![(offset, *digest)]))
-                } else {
-                    let compressed_stream_len2 = compressed_stream_len.clone();
-                    known_chunks.insert(*digest);
-                    future::ready(chunk_builder.build().map(move |(chunk, digest)| {
-                        compressed_stream_len2.fetch_add(chunk.raw_size(), Ordering::SeqCst);
-                        MergedChunkInfo::New(ChunkInfo {
-                            chunk,
-                            digest,
-                            chunk_len: chunk_len as u64,
-                            offset,
-                        })
-                    }))
+                    return Either::Left(future::ok(MergedChunkInfo::Known(vec![(
+                        offset, digest,
+                    )])));
                 }
+                known_chunks.insert(digest);
+
+                let compressed_stream_len = compressed_stream_len.clone();
+                let crypt_config = crypt_config2.clone();
+                Either::Right(async move {
+                    let chunk = match zero_chunk {
+                        // the zero chunk is only uploaded once, all others are known afterwards
+                        Some(zero) => DataBlob::from_raw(zero.chunk.raw_data().to_vec())?,
+                        None => {
+                            tokio::task::spawn_blocking(move || {
+                                DataBlob::encode(&data, crypt_config.as_deref(), compress)
+                            })
+                            .await??
+                        }
+                    };
+                    compressed_stream_len.fetch_add(chunk.raw_size(), Ordering::SeqCst);
+                    Ok::<_, Error>(MergedChunkInfo::New(ChunkInfo {
+                        chunk,
+                        digest,
+                        chunk_len: chunk_len as u64,
+                        offset,
+                    }))
+                })
             })
+            .try_buffered(workers)
             .merge_known_chunks()
             .try_for_each(move |merged_chunk_info| {
                 let upload_queue = upload_queue.clone();
@@ -770,6 +967,7 @@ impl BackupWriter {
                 let size = stream_len2.load(Ordering::SeqCst);
                 let size_reused = reused_len2.load(Ordering::SeqCst);
                 let size_compressed = compressed_stream_len2.load(Ordering::SeqCst) as usize;
+                let zero_chunks = zero_chunk_count2.load(Ordering::SeqCst);
 
                 let mut guard = index_csum_2.lock().unwrap();
                 let csum = guard.take().unwrap().finish();
@@ -780,12 +978,83 @@ impl BackupWriter {
                     size,
                     size_reused,
                     size_compressed,
+                    zero_chunks,
                     duration,
                     csum,
                 })
             })
     }
 
+    /// Pass `stream` through unchanged, but ask the server in batches which of its chunks are
+    /// already stored, and add those to `known_chunks`. This saves compressing, encrypting and
+    /// uploading them if there is no previous index, e.g. for the first backup of a clone.
+    ///
+    /// Querying is silently disabled if the server does not support it.
+    fn query_known_chunks(
+        h2: H2Client,
+        stream: impl Stream<Item = Result<bytes::BytesMut, Error>>,
+        known_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+        crypt_config: Option<Arc<CryptConfig>>,
+    ) -> impl Stream<Item = Result<bytes::BytesMut, Error>> {
+        let supported = Arc::new(AtomicBool::new(true));
+
+        stream
+            .try_chunks(KNOWN_CHUNKS_QUERY_BATCH)
+            .map_err(|err| err.1)
+            .and_then(move |batch| {
+                let h2 = h2.clone();
+                let known_chunks = known_chunks.clone();
+                let crypt_config = crypt_config.clone();
+                let supported = supported.clone();
+
+                async move {
+                    if !supported.load(Ordering::SeqCst) {
+                        return Ok::<_, Error>(futures::stream::iter(batch.into_iter().map(Ok)));
+                    }
+
+                    let mut digest_list = Vec::new();
+                    let mut size_list = Vec::new();
+                    {
+                        let known_chunks = known_chunks.lock().unwrap();
+                        let mut queued = HashSet::new();
+                        for data in batch.iter() {
+                            let digest = match &crypt_config {
+                                Some(crypt_config) => crypt_config.compute_digest(data),
+                                None => compute_chunk_digest(ChunkDigestAlgorithm::default(), data),
+                            };
+                            if !known_chunks.contains(&digest) && queued.insert(digest) {
+                                digest_list.push(hex::encode(digest));
+                                size_list.push(data.len());
+                            }
+                        }
+                    }
+
+                    if !digest_list.is_empty() {
+                        let param = json!({
+                            "digest-list": digest_list,
+                            "size-list": size_list,
+                        });
+                        match h2.post("known_chunks", Some(param)).await {
+                            Ok(result) => {
+                                let list: Vec<String> = serde_json::from_value(result)?;
+                                let mut known_chunks = known_chunks.lock().unwrap();
+                                for digest in list {
+                                    known_chunks.insert(<[u8; 32]>::from_hex(&digest)?);
+                                }
+                            }
+                            Err(err) => {
+                                log::debug!("querying known chunks failed, disabling - {err}");
+                                supported.store(false, Ordering::SeqCst);
+                            }
+                        }
+                    }
+
+                    Ok::<_, Error>(futures::stream::iter(batch.into_iter().map(Ok)))
+                }
+            })
+            .try_flatten()
+    }
+
     /// Upload speed test - prints result to stderr
     pub async fn upload_speedtest(&self) -> Result<f64, Error> {
         let mut data = vec![];
@@ -824,7 +824,7 @@ impl Shell {
             &self.position[0].catalog,
             &mut Vec::new(),
             &matches,
-            &mut |path: &[u8]| -> Result<(), Error> {
+            &mut |path: &[u8], _entry: &catalog::DirEntry| -> Result<(), Error> {
                 let mut out = std::io::stdout();
                 out.write_all(path)?;
                 out.write_all(b"\n")?;
@@ -845,7 +845,7 @@ impl Shell {
             &self.position[0].catalog,
             &mut Vec::new(),
             &[&pattern_entry],
-            &mut |path: &[u8]| -> Result<(), Error> {
+            &mut |path: &[u8], _entry: &catalog::DirEntry| -> Result<(), Error> {
                 found_some = true;
                 let mut out = std::io::stdout();
                 out.write_all(path)?;
@@ -0,0 +1,616 @@
+//! Journal of changed directories, recorded with fanotify
+//!
+//! A long-running watcher marks the filesystems of some directories with fanotify and records
+//! each directory in which an entry was created, removed, renamed, written to or had its
+//! attributes changed, together with the changed entry and the time of the change. A directory
+//! without any recorded change in its subtree since the previous backup does not need to be
+//! walked again to find out what changed.
+//!
+//! The journal only knows about the changes made while the watcher was running. It records the
+//! time since when it is complete, which moves forward when the watcher is started, when the
+//! kernel dropped events and when old entries expire.
+
+use std::collections::BTreeMap;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+
+use crate::tools::base_directories;
+use crate::tools::state_file::replace_state_file;
+
+// openssl::sha::sha256(b"Proxmox Backup Change Journal v1.0")[0..8]
+const CHANGE_JOURNAL_MAGIC_1_0: [u8; 8] = [119, 163, 232, 112, 132, 52, 53, 160];
+
+/// A journal without update for this long (in seconds) has no running watcher.
+const WATCHER_STALE_TIME: i64 = 60;
+
+/// Changed directories and entries below the watched directories, with the time of their last
+/// change.
+pub struct ChangeJournal {
+    roots: Vec<PathBuf>,
+    devices: Vec<u64>,
+    complete_since: i64,
+    updated: i64,
+    changes: BTreeMap<Vec<u8>, i64>,
+}
+
+impl ChangeJournal {
+    fn path(name: &str) -> Result<PathBuf, Error> {
+        if name.is_empty() || name.starts_with('.') || name.contains('/') {
+            bail!("invalid change journal name '{name}'");
+        }
+        // usually $HOME/.cache/proxmox-backup/changes/<name>
+        Ok(base_directories()?.place_cache_file(format!("changes/{name}"))?)
+    }
+
+    /// Load the journal `name`.
+    pub fn load(name: &str) -> Result<Self, Error> {
+        let path = Self::path(name)?;
+        let data = std::fs::read(&path)
+            .map_err(|err| format_err!("unable to read change journal {path:?} - {err}"))?;
+        Self::parse(&data)
+            .map_err(|err| format_err!("unable to parse change journal {path:?} - {err}"))
+    }
+
+    fn parse(data: &[u8]) -> Result<Self, Error> {
+        let mut reader = JournalReader { data };
+        if reader.bytes(8)? != CHANGE_JOURNAL_MAGIC_1_0 {
+            bail!("wrong magic number");
+        }
+
+        let complete_since = reader.i64()?;
+        let updated = reader.i64()?;
+
+        let mut devices = Vec::new();
+        for _ in 0..reader.u32()? {
+            devices.push(reader.u64()?);
+        }
+
+        let mut roots = Vec::new();
+        for _ in 0..reader.u32()? {
+            let len = reader.u32()? as usize;
+            roots.push(PathBuf::from(std::ffi::OsStr::from_bytes(
+                reader.bytes(len)?,
+            )));
+        }
+
+        let mut changes = BTreeMap::new();
+        while !reader.data.is_empty() {
+            let time = reader.i64()?;
+            let len = reader.u32()? as usize;
+            changes.insert(reader.bytes(len)?.to_vec(), time);
+        }
+
+        Ok(Self {
+            roots,
+            devices,
+            complete_since,
+            updated,
+            changes,
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&CHANGE_JOURNAL_MAGIC_1_0);
+        data.extend_from_slice(&self.complete_since.to_le_bytes());
+        data.extend_from_slice(&self.updated.to_le_bytes());
+
+        data.extend_from_slice(&(self.devices.len() as u32).to_le_bytes());
+        for device in &self.devices {
+            data.extend_from_slice(&device.to_le_bytes());
+        }
+
+        data.extend_from_slice(&(self.roots.len() as u32).to_le_bytes());
+        for root in &self.roots {
+            let root = root.as_os_str().as_bytes();
+            data.extend_from_slice(&(root.len() as u32).to_le_bytes());
+            data.extend_from_slice(root);
+        }
+
+        for (path, time) in &self.changes {
+            data.extend_from_slice(&time.to_le_bytes());
+            data.extend_from_slice(&(path.len() as u32).to_le_bytes());
+            data.extend_from_slice(path);
+        }
+
+        replace_state_file(path, &data, false)
+    }
+
+    /// The watched directories.
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// All changes made since this time are recorded.
+    pub fn complete_since(&self) -> i64 {
+        self.complete_since
+    }
+
+    /// Last time the watcher wrote the journal.
+    pub fn updated(&self) -> i64 {
+        self.updated
+    }
+
+    /// Number of recorded directories and entries.
+    pub fn change_count(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Whether the watcher is still running.
+    pub fn is_current(&self) -> bool {
+        proxmox_time::epoch_i64() - self.updated <= WATCHER_STALE_TIME
+    }
+
+    /// Whether all changes made since `since` below `path`, on device `device`, are recorded.
+    pub fn covers(&self, path: &Path, device: u64, since: i64) -> bool {
+        self.complete_since <= since
+            && self.is_current()
+            && self.devices.contains(&device)
+            && self.roots.iter().any(|root| path.starts_with(root))
+    }
+
+    /// Whether anything at or below `path` changed at or after `since`.
+    pub fn changed_below(&self, path: &Path, since: i64) -> bool {
+        let path = path.as_os_str().as_bytes();
+        self.changes
+            .range(path.to_vec()..)
+            .take_while(|(changed, _)| changed.starts_with(path))
+            .any(|(changed, time)| {
+                *time >= since
+                    && (changed.len() == path.len()
+                        || path.ends_with(b"/")
+                        || changed[path.len()] == b'/')
+            })
+    }
+
+    // record a change of `path`, if it is below a watched directory
+    fn record(&mut self, path: &Path, time: i64) -> bool {
+        if !self.roots.iter().any(|root| path.starts_with(root)) {
+            return false;
+        }
+        self.changes
+            .insert(path.as_os_str().as_bytes().to_vec(), time);
+        true
+    }
+
+    // drop the entries older than `horizon`, changes before it are unknown from now on
+    fn expire(&mut self, horizon: i64) {
+        self.changes.retain(|_, time| *time >= horizon);
+        self.complete_since = self.complete_since.max(horizon);
+    }
+}
+
+struct JournalReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> JournalReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            bail!("truncated file");
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, Error> {
+        Ok(i64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+/// Record the changes below `roots` into the journal `name`, until the process is terminated.
+///
+/// Changes are kept for `max_age` seconds. This requires the `CAP_SYS_ADMIN` and
+/// `CAP_DAC_READ_SEARCH` capabilities and Linux 5.9 or newer.
+#[cfg(target_os = "linux")]
+pub fn watch_changes(name: &str, roots: &[PathBuf], max_age: i64) -> Result<(), Error> {
+    use std::os::unix::io::AsRawFd;
+
+    use nix::errno::Errno;
+    use nix::poll::{poll, PollFd, PollFlags};
+
+    let path = ChangeJournal::path(name)?;
+
+    let mut fanotify = fanotify::Fanotify::new()?;
+
+    let mut journal = ChangeJournal {
+        roots: Vec::new(),
+        devices: Vec::new(),
+        complete_since: 0,
+        updated: 0,
+        changes: BTreeMap::new(),
+    };
+    for root in roots {
+        let root = std::fs::canonicalize(root)
+            .map_err(|err| format_err!("unable to resolve {root:?} - {err}"))?;
+        let device = fanotify.mark(&root)?;
+        if !journal.devices.contains(&device) {
+            journal.devices.push(device);
+        }
+        journal.roots.push(root);
+    }
+
+    let now = proxmox_time::epoch_i64();
+    journal.complete_since = now;
+    journal.updated = now;
+    journal.save(&path)?;
+    log::info!("recording changes below {:?} to {path:?}", journal.roots);
+
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut dirty = false;
+    loop {
+        let mut poll_fds = [PollFd::new(fanotify.fd.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut poll_fds, 1000) {
+            Ok(_) | Err(Errno::EINTR) => (),
+            Err(err) => bail!("polling fanotify events failed - {err}"),
+        }
+
+        let ready = poll_fds[0]
+            .revents()
+            .map_or(false, |events| events.contains(PollFlags::POLLIN));
+        if ready {
+            let len = match nix::unistd::read(fanotify.fd.as_raw_fd(), &mut buffer) {
+                Ok(len) => len,
+                Err(Errno::EINTR | Errno::EAGAIN) => 0,
+                Err(err) => bail!("reading fanotify events failed - {err}"),
+            };
+            let now = proxmox_time::epoch_i64();
+            for change in fanotify.changes(&buffer[..len]) {
+                match change {
+                    fanotify::Change::Path(path) => dirty |= journal.record(&path, now),
+                    fanotify::Change::Lost(reason) => {
+                        log::warn!("changes got lost ({reason}), journal is complete from now on");
+                        journal.complete_since = now;
+                        dirty = true;
+                    }
+                }
+            }
+        }
+
+        let now = proxmox_time::epoch_i64();
+        if (dirty && now > journal.updated) || now - journal.updated >= WATCHER_STALE_TIME / 3 {
+            journal.expire(now - max_age);
+            journal.updated = now;
+            journal.save(&path)?;
+            dirty = false;
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn watch_changes(_name: &str, _roots: &[PathBuf], _max_age: i64) -> Result<(), Error> {
+    bail!("change journals require fanotify, which is only available on Linux");
+}
+
+#[cfg(target_os = "linux")]
+mod fanotify {
+    use std::ffi::{CStr, OsStr};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::path::{Path, PathBuf};
+
+    use anyhow::{format_err, Error};
+    use nix::errno::Errno;
+    use nix::fcntl::OFlag;
+    use nix::sys::stat::Mode;
+
+    // from linux/fanotify.h
+    const FAN_CLOEXEC: libc::c_uint = 0x0000_0001;
+    const FAN_CLASS_NOTIF: libc::c_uint = 0x0000_0000;
+    const FAN_REPORT_DFID_NAME: libc::c_uint = 0x0000_0c00;
+
+    const FAN_MARK_ADD: libc::c_uint = 0x0000_0001;
+    const FAN_MARK_FILESYSTEM: libc::c_uint = 0x0000_0100;
+
+    const FAN_MODIFY: u64 = 0x0000_0002;
+    const FAN_ATTRIB: u64 = 0x0000_0004;
+    const FAN_MOVED_FROM: u64 = 0x0000_0040;
+    const FAN_MOVED_TO: u64 = 0x0000_0080;
+    const FAN_CREATE: u64 = 0x0000_0100;
+    const FAN_DELETE: u64 = 0x0000_0200;
+    const FAN_DELETE_SELF: u64 = 0x0000_0400;
+    const FAN_MOVE_SELF: u64 = 0x0000_0800;
+    const FAN_Q_OVERFLOW: u64 = 0x0000_4000;
+    const FAN_ONDIR: u64 = 0x4000_0000;
+
+    const FAN_EVENT_INFO_TYPE_DFID_NAME: u8 = 2;
+    const FAN_EVENT_INFO_TYPE_DFID: u8 = 3;
+
+    const EVENT_MASK: u64 = FAN_MODIFY
+        | FAN_ATTRIB
+        | FAN_MOVED_FROM
+        | FAN_MOVED_TO
+        | FAN_CREATE
+        | FAN_DELETE
+        | FAN_DELETE_SELF
+        | FAN_MOVE_SELF
+        | FAN_ONDIR;
+
+    const METADATA_LEN: usize = std::mem::size_of::<libc::fanotify_event_metadata>();
+
+    // info record header (4 bytes) and filesystem id (8 bytes), followed by a file handle
+    const INFO_FID_LEN: usize = 12;
+    // handle_bytes and handle_type of a struct file_handle
+    const FILE_HANDLE_LEN: usize = 8;
+
+    pub(super) enum Change {
+        /// A directory or entry below it changed.
+        Path(PathBuf),
+        /// Changes could not be recorded.
+        Lost(String),
+    }
+
+    pub(super) struct Fanotify {
+        pub(super) fd: OwnedFd,
+        // directories on the marked filesystems, by filesystem id, to open file handles with
+        mounts: Vec<([u8; 8], OwnedFd)>,
+    }
+
+    impl Fanotify {
+        pub(super) fn new() -> Result<Self, Error> {
+            let fd = unsafe {
+                libc::fanotify_init(
+                    FAN_CLOEXEC | FAN_CLASS_NOTIF | FAN_REPORT_DFID_NAME,
+                    (libc::O_RDONLY | libc::O_LARGEFILE) as libc::c_uint,
+                )
+            };
+            let fd = Errno::result(fd).map_err(|err| {
+                format_err!("unable to initialize fanotify (requires root and Linux 5.9) - {err}")
+            })?;
+
+            Ok(Self {
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+                mounts: Vec::new(),
+            })
+        }
+
+        /// Watch the filesystem of `path`, returns its device number.
+        pub(super) fn mark(&mut self, path: &Path) -> Result<u64, Error> {
+            let dir = proxmox_sys::fd::open(
+                path,
+                OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+                Mode::empty(),
+            )
+            .map_err(|err| format_err!("unable to open {path:?} - {err}"))?;
+
+            let res = unsafe {
+                libc::fanotify_mark(
+                    self.fd.as_raw_fd(),
+                    FAN_MARK_ADD | FAN_MARK_FILESYSTEM,
+                    EVENT_MASK,
+                    dir.as_raw_fd(),
+                    std::ptr::null(),
+                )
+            };
+            Errno::result(res).map_err(|err| format_err!("unable to watch {path:?} - {err}"))?;
+
+            let stat = nix::sys::stat::fstat(dir.as_raw_fd())?;
+            let fsid = filesystem_id(dir.as_raw_fd())?;
+            if !self.mounts.iter().any(|(id, _)| *id == fsid) {
+                self.mounts.push((fsid, dir));
+            }
+
+            Ok(stat.st_dev)
+        }
+
+        /// The changes reported by the events in `data`.
+        pub(super) fn changes(&self, mut data: &[u8]) -> Vec<Change> {
+            let mut changes = Vec::new();
+
+            while data.len() >= METADATA_LEN {
+                let metadata: libc::fanotify_event_metadata =
+                    unsafe { std::ptr::read_unaligned(data.as_ptr() as *const _) };
+                let event_len = metadata.event_len as usize;
+                let metadata_len = metadata.metadata_len as usize;
+                if event_len < METADATA_LEN || event_len > data.len() || metadata_len > event_len {
+                    changes.push(Change::Lost("malformed event".to_string()));
+                    break;
+                }
+
+                if metadata.fd >= 0 {
+                    unsafe { libc::close(metadata.fd) };
+                }
+
+                if metadata.mask & FAN_Q_OVERFLOW != 0 {
+                    changes.push(Change::Lost("event queue overflow".to_string()));
+                } else {
+                    self.parse_info(&data[metadata_len..event_len], &mut changes);
+                }
+
+                data = &data[event_len..];
+            }
+
+            changes
+        }
+
+        fn parse_info(&self, mut info: &[u8], changes: &mut Vec<Change>) {
+            while info.len() >= INFO_FID_LEN + FILE_HANDLE_LEN {
+                let info_type = info[0];
+                let len = u16::from_ne_bytes([info[2], info[3]]) as usize;
+                if len < INFO_FID_LEN + FILE_HANDLE_LEN || len > info.len() {
+                    changes.push(Change::Lost("malformed event info".to_string()));
+                    return;
+                }
+                let record = &info[..len];
+                info = &info[len..];
+
+                if info_type != FAN_EVENT_INFO_TYPE_DFID_NAME
+                    && info_type != FAN_EVENT_INFO_TYPE_DFID
+                {
+                    continue;
+                }
+
+                let fsid: [u8; 8] = record[4..12].try_into().unwrap();
+                let handle = &record[INFO_FID_LEN..];
+                let handle_bytes = u32::from_ne_bytes(handle[0..4].try_into().unwrap()) as usize;
+                if handle.len() < FILE_HANDLE_LEN + handle_bytes {
+                    changes.push(Change::Lost("malformed file handle".to_string()));
+                    return;
+                }
+                let (handle, name) = handle.split_at(FILE_HANDLE_LEN + handle_bytes);
+
+                let dir = match self.resolve(&fsid, handle) {
+                    Ok(Some(dir)) => dir,
+                    // removed in the meantime, which is reported as change of its parent
+                    Ok(None) => continue,
+                    Err(err) => {
+                        changes.push(Change::Lost(err.to_string()));
+                        continue;
+                    }
+                };
+
+                let name = match info_type {
+                    FAN_EVENT_INFO_TYPE_DFID_NAME => CStr::from_bytes_until_nul(name)
+                        .map(|name| name.to_bytes())
+                        .unwrap_or_default(),
+                    _ => b"",
+                };
+
+                if name.is_empty() || name == b"." {
+                    // the directory itself changed, which its parent lists
+                    if let Some(parent) = dir.parent() {
+                        changes.push(Change::Path(parent.to_path_buf()));
+                    }
+                } else {
+                    changes.push(Change::Path(dir.join(OsStr::from_bytes(name))));
+                }
+                changes.push(Change::Path(dir));
+            }
+        }
+
+        // the path of the directory with the file handle `handle`, `None` if it was removed
+        fn resolve(&self, fsid: &[u8; 8], handle: &[u8]) -> Result<Option<PathBuf>, Error> {
+            let mount = match self.mounts.iter().find(|(id, _)| id == fsid) {
+                Some((_, mount)) => mount,
+                None => return Err(format_err!("event from unknown filesystem")),
+            };
+
+            // struct file_handle needs to be aligned
+            let mut aligned = vec![0u64; handle.len().div_ceil(8)];
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    handle.as_ptr(),
+                    aligned.as_mut_ptr() as *mut u8,
+                    handle.len(),
+                );
+            }
+
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_open_by_handle_at,
+                    mount.as_raw_fd(),
+                    aligned.as_ptr(),
+                    libc::O_PATH | libc::O_CLOEXEC,
+                )
+            };
+            let fd = match Errno::result(fd) {
+                Ok(fd) => unsafe { OwnedFd::from_raw_fd(fd as RawFd) },
+                Err(Errno::ESTALE | Errno::ENOENT) => return Ok(None),
+                Err(err) => return Err(format_err!("unable to open file handle - {err}")),
+            };
+
+            let path = std::fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd()))
+                .map_err(|err| format_err!("unable to resolve file handle - {err}"))?;
+            Ok(Some(path))
+        }
+    }
+
+    fn filesystem_id(fd: RawFd) -> Result<[u8; 8], Error> {
+        let mut stat = std::mem::MaybeUninit::<libc::statfs>::uninit();
+        Errno::result(unsafe { libc::fstatfs(fd, stat.as_mut_ptr()) })?;
+        let stat = unsafe { stat.assume_init() };
+        // fsid_t is two opaque integers, as reported in the events
+        Ok(unsafe { std::mem::transmute::<libc::fsid_t, [u8; 8]>(stat.f_fsid) })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn journal(changes: &[(&str, i64)]) -> ChangeJournal {
+        ChangeJournal {
+            roots: vec![PathBuf::from("/srv")],
+            devices: vec![42],
+            complete_since: 100,
+            updated: proxmox_time::epoch_i64(),
+            changes: changes
+                .iter()
+                .map(|(path, time)| (path.as_bytes().to_vec(), *time))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_changed_below() {
+        let journal = journal(&[("/srv/a/b", 200), ("/srv/a/b-c", 300), ("/srv/d", 150)]);
+
+        assert!(journal.changed_below(Path::new("/srv"), 200));
+        assert!(journal.changed_below(Path::new("/srv/a"), 200));
+        assert!(journal.changed_below(Path::new("/srv/a/b"), 200));
+        assert!(!journal.changed_below(Path::new("/srv/a/b"), 201));
+        // a sibling with the same prefix is not below
+        assert!(journal.changed_below(Path::new("/srv/a/b-c"), 300));
+        assert!(!journal.changed_below(Path::new("/srv/a/b/x"), 0));
+        assert!(!journal.changed_below(Path::new("/srv/d"), 151));
+        assert!(!journal.changed_below(Path::new("/srv/e"), 0));
+        assert!(journal.changed_below(Path::new("/"), 300));
+    }
+
+    #[test]
+    fn test_covers() {
+        let mut journal = journal(&[]);
+
+        assert!(journal.covers(Path::new("/srv/a"), 42, 100));
+        assert!(!journal.covers(Path::new("/srv/a"), 42, 99));
+        assert!(!journal.covers(Path::new("/srv/a"), 43, 100));
+        assert!(!journal.covers(Path::new("/srvx"), 42, 100));
+
+        journal.updated -= WATCHER_STALE_TIME + 1;
+        assert!(!journal.covers(Path::new("/srv/a"), 42, 100));
+    }
+
+    #[test]
+    fn test_record_and_expire() {
+        let mut journal = journal(&[("/srv/a", 150)]);
+
+        assert!(journal.record(Path::new("/srv/b"), 200));
+        assert!(!journal.record(Path::new("/var/b"), 200));
+
+        journal.expire(180);
+        assert_eq!(journal.change_count(), 1);
+        assert_eq!(journal.complete_since(), 180);
+        assert!(!journal.changed_below(Path::new("/srv/a"), 0));
+    }
+
+    #[test]
+    fn test_save_and_parse() -> Result<(), Error> {
+        let journal = journal(&[("/srv/a", 150), ("/srv/\u{e4}", 160)]);
+        let path = std::env::temp_dir().join(format!("pbs-change-journal-{}", std::process::id()));
+        journal.save(&path)?;
+        let data = std::fs::read(&path)?;
+        std::fs::remove_file(&path)?;
+
+        let parsed = ChangeJournal::parse(&data)?;
+        assert_eq!(parsed.roots, journal.roots);
+        assert_eq!(parsed.devices, journal.devices);
+        assert_eq!(parsed.complete_since, journal.complete_since);
+        assert_eq!(parsed.updated, journal.updated);
+        assert_eq!(parsed.changes, journal.changes);
+
+        assert!(ChangeJournal::parse(&data[..data.len() - 1]).is_err());
+        assert!(ChangeJournal::parse(&data[1..]).is_err());
+        Ok(())
+    }
+}
@@ -77,6 +77,11 @@ where
     }
 }
 
+/// Check whether a chunk contains only zeroes, e.g. from unallocated parts of an image.
+pub fn is_zero_chunk(data: &[u8]) -> bool {
+    !data.iter().any(|b| *b != 0)
+}
+
 /// Split input stream into fixed sized chunks
 pub struct FixedChunkStream<S: Unpin> {
     input: S,
@@ -0,0 +1,264 @@
+//! Known server certificate fingerprints
+//!
+//! Similar to SSH's `known_hosts`, the client pins the certificate fingerprint of a server on
+//! first (confirmed) use and refuses to connect if it changes later on. A changed fingerprint
+//! has to be accepted explicitly with `proxmox-backup-client fingerprint update`.
+//!
+//! Entries are stored in `~/.config/<prefix>/fingerprints`, one per line:
+//!
+//! ```text
+//! <server>:<port> <fingerprint> [<pinned> [<not-after>]]
+//! ```
+//!
+//! `pinned` and `not-after` (the certificate's expiry) are epoch values. Entries written by
+//! older clients only consist of the host name and the fingerprint.
+
+use std::net::TcpStream;
+
+use anyhow::{bail, format_err, Error};
+use openssl::asn1::{Asn1Time, Asn1TimeRef};
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::x509::X509Ref;
+use serde::Serialize;
+use xdg::BaseDirectories;
+
+use proxmox_http::uri::build_authority;
+
+use crate::tools::state_file::replace_state_file;
+
+/// Warn if the pinned certificate expires within this time.
+const EXPIRY_WARN_TIME: i64 = 30 * 24 * 3600;
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// A pinned server certificate fingerprint.
+pub struct KnownFingerprint {
+    /// `host:port`, or only the host name for entries written by older clients.
+    pub server: String,
+    /// Certificate fingerprint (sha256, colon separated)
+    pub fingerprint: String,
+    /// Time the fingerprint was pinned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pinned: Option<i64>,
+    /// Expiry of the pinned certificate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<i64>,
+}
+
+impl KnownFingerprint {
+    fn parse(line: &str) -> Option<Self> {
+        let mut items = line.split_whitespace();
+        let server = items.next()?.to_string();
+        let fingerprint = items.next()?.to_string();
+        let pinned = items.next().and_then(|v| v.parse().ok());
+        let not_after = items.next().and_then(|v| v.parse().ok());
+        Some(Self {
+            server,
+            fingerprint,
+            pinned,
+            not_after,
+        })
+    }
+
+    fn to_line(&self) -> String {
+        let mut line = format!("{} {}", self.server, self.fingerprint);
+        if let Some(pinned) = self.pinned {
+            line.push_str(&format!(" {}", pinned));
+            if let Some(not_after) = self.not_after {
+                line.push_str(&format!(" {}", not_after));
+            }
+        }
+        line
+    }
+
+    /// Log a warning if the pinned certificate expired or expires soon.
+    pub fn warn_expiry(&self) {
+        let not_after = match self.not_after {
+            Some(not_after) => not_after,
+            None => return,
+        };
+        let now = proxmox_time::epoch_i64();
+        let date = proxmox_time::epoch_to_rfc3339_utc(not_after).unwrap_or_default();
+        if not_after <= now {
+            log::warn!(
+                "WARNING: pinned certificate of '{}' expired on {}",
+                self.server,
+                date
+            );
+        } else if not_after - now < EXPIRY_WARN_TIME {
+            log::warn!(
+                "WARNING: pinned certificate of '{}' expires on {}",
+                self.server,
+                date
+            );
+        } else {
+            return;
+        }
+        log::warn!("Once the certificate was renewed, use 'proxmox-backup-client fingerprint update' to accept the new fingerprint.");
+    }
+}
+
+fn server_key(server: &str, port: u16) -> Result<String, Error> {
+    Ok(build_authority(server, port)?.to_string())
+}
+
+fn fingerprint_file(prefix: &str) -> Result<std::path::PathBuf, Error> {
+    let base = BaseDirectories::with_prefix(prefix)?;
+    // usually ~/.config/<prefix>/fingerprints
+    Ok(base.place_config_file("fingerprints")?)
+}
+
+/// Load all known fingerprints.
+pub fn load_fingerprints(prefix: &str) -> Result<Vec<KnownFingerprint>, Error> {
+    let path = fingerprint_file(prefix)?;
+
+    let raw = match std::fs::read_to_string(&path) {
+        Ok(v) => v,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => bail!("unable to read fingerprints from {:?} - {}", path, err),
+    };
+
+    Ok(raw.lines().filter_map(KnownFingerprint::parse).collect())
+}
+
+fn save_fingerprints(prefix: &str, list: &[KnownFingerprint]) -> Result<(), Error> {
+    let path = fingerprint_file(prefix)?;
+
+    let mut raw = String::new();
+    for entry in list {
+        raw.push_str(&entry.to_line());
+        raw.push('\n');
+    }
+
+    replace_state_file(path, raw.as_bytes(), false)
+}
+
+/// Lookup the pinned fingerprint of `server:port`, falling back to host-only entries written
+/// by older clients.
+pub fn lookup_fingerprint(prefix: &str, server: &str, port: u16) -> Option<KnownFingerprint> {
+    let key = server_key(server, port).ok()?;
+    let list = load_fingerprints(prefix).ok()?;
+
+    list.iter()
+        .find(|entry| entry.server == key)
+        .or_else(|| list.iter().find(|entry| entry.server == server))
+        .cloned()
+}
+
+/// Pin `fingerprint` for `server:port`, replacing any existing entry.
+pub fn store_fingerprint(
+    prefix: &str,
+    server: &str,
+    port: u16,
+    fingerprint: &str,
+    not_after: Option<i64>,
+) -> Result<(), Error> {
+    let key = server_key(server, port)?;
+
+    let mut list = load_fingerprints(prefix)?;
+    list.retain(|entry| entry.server != key && entry.server != server);
+    list.push(KnownFingerprint {
+        server: key,
+        fingerprint: fingerprint.to_string(),
+        pinned: Some(proxmox_time::epoch_i64()),
+        not_after,
+    });
+
+    save_fingerprints(prefix, &list)
+}
+
+/// Remove the pinned fingerprint of `server:port`. Returns false if there was none.
+pub fn remove_fingerprint(prefix: &str, server: &str, port: u16) -> Result<bool, Error> {
+    let key = server_key(server, port)?;
+
+    let mut list = load_fingerprints(prefix)?;
+    let count = list.len();
+    list.retain(|entry| entry.server != key && entry.server != server);
+    if list.len() == count {
+        return Ok(false);
+    }
+
+    save_fingerprints(prefix, &list)?;
+    Ok(true)
+}
+
+/// Format a certificate's sha256 fingerprint as colon separated hex string.
+pub fn cert_fingerprint(cert: &X509Ref) -> Result<String, Error> {
+    let fp = cert
+        .digest(openssl::hash::MessageDigest::sha256())
+        .map_err(|err| format_err!("failed to calculate certificate FP - {}", err))?;
+    let fp_string = hex::encode(fp);
+    Ok(fp_string
+        .as_bytes()
+        .chunks(2)
+        .map(|v| std::str::from_utf8(v).unwrap())
+        .collect::<Vec<&str>>()
+        .join(":"))
+}
+
+/// Format the sha256 fingerprint of a DER encoded certificate as colon separated hex string.
+pub fn der_fingerprint(der: &[u8]) -> String {
+    let fp_string = hex::encode(openssl::sha::sha256(der));
+    fp_string
+        .as_bytes()
+        .chunks(2)
+        .map(|v| std::str::from_utf8(v).unwrap())
+        .collect::<Vec<&str>>()
+        .join(":")
+}
+
+fn asn1_time_to_epoch(time: &Asn1TimeRef) -> Result<i64, Error> {
+    let diff = Asn1Time::from_unix(0)?.diff(time)?;
+    Ok(diff.days as i64 * 24 * 3600 + diff.secs as i64)
+}
+
+/// Expiry of a certificate as epoch.
+pub fn cert_not_after(cert: &X509Ref) -> Option<i64> {
+    asn1_time_to_epoch(cert.not_after()).ok()
+}
+
+/// Expiry of a DER encoded certificate as epoch.
+pub fn der_not_after(der: &[u8]) -> Option<i64> {
+    let cert = openssl::x509::X509::from_der(der).ok()?;
+    cert_not_after(&cert)
+}
+
+/// Connect to `server:port` and return the (unverified) fingerprint and expiry of its
+/// certificate.
+pub fn fetch_server_fingerprint(server: &str, port: u16) -> Result<(String, Option<i64>), Error> {
+    let mut builder = SslConnector::builder(SslMethod::tls())?;
+    builder.set_verify(SslVerifyMode::NONE);
+    let connector = builder.build();
+
+    let stream = TcpStream::connect((server.trim_matches(|c| c == '[' || c == ']'), port))
+        .map_err(|err| format_err!("unable to connect to {}:{} - {}", server, port, err))?;
+
+    let stream = connector
+        .configure()?
+        .verify_hostname(false)
+        .connect(server, stream)
+        .map_err(|err| format_err!("TLS handshake with {} failed - {}", server, err))?;
+
+    let cert = stream
+        .ssl()
+        .peer_certificate()
+        .ok_or_else(|| format_err!("server did not send a certificate"))?;
+
+    Ok((cert_fingerprint(&cert)?, cert_not_after(&cert)))
+}
+
+#[test]
+fn test_known_fingerprint_parse() {
+    let entry = KnownFingerprint::parse("localhost ab:cd").unwrap();
+    assert_eq!(entry.server, "localhost");
+    assert_eq!(entry.fingerprint, "ab:cd");
+    assert_eq!(entry.pinned, None);
+    assert_eq!(entry.to_line(), "localhost ab:cd");
+
+    let entry = KnownFingerprint::parse("localhost:8007 ab:cd 100 200").unwrap();
+    assert_eq!(entry.pinned, Some(100));
+    assert_eq!(entry.not_after, Some(200));
+    assert_eq!(entry.to_line(), "localhost:8007 ab:cd 100 200");
+
+    assert!(KnownFingerprint::parse("").is_none());
+}
@@ -9,6 +9,7 @@ use http::Uri;
 use http::{Request, Response};
 use hyper::client::{Client, HttpConnector};
 use hyper::Body;
+#[cfg(not(feature = "rustls"))]
 use openssl::{
     ssl::{SslConnector, SslMethod},
     x509::X509StoreContextRef,
@@ -18,24 +19,77 @@ use serde_json::{json, Value};
 use xdg::BaseDirectories;
 
 use proxmox_router::HttpError;
-use proxmox_sys::fs::{file_get_json, replace_file, CreateOptions};
+use proxmox_sys::fs::file_get_json;
 use proxmox_sys::linux::tty;
 
 use proxmox_async::broadcast_future::BroadcastFuture;
+#[cfg(not(feature = "rustls"))]
 use proxmox_http::client::HttpsConnector;
 use proxmox_http::uri::{build_authority, json_object_to_query};
-use proxmox_http::{ProxyConfig, RateLimiter};
+use proxmox_http::ProxyConfig;
+#[cfg(not(feature = "rustls"))]
+use proxmox_http::RateLimiter;
 
 use pbs_api_types::percent_encoding::DEFAULT_ENCODE_SET;
 use pbs_api_types::{Authid, RateLimitConfig, Userid};
 
+use super::fingerprints;
 use super::pipe_to_stream::PipeToSendStream;
+#[cfg(feature = "rustls")]
+use super::rustls_connector::HttpsConnector;
+use super::tools::state_file::replace_secret_file;
+#[cfg(not(feature = "rustls"))]
 use super::PROXMOX_BACKUP_TCP_KEEPALIVE_TIME;
 
 /// Timeout used for several HTTP operations that are expected to finish quickly but may block in
 /// certain error conditions. Keep it generous, to avoid false-positive under high load.
 const HTTP_TIMEOUT: Duration = Duration::from_secs(2 * 60);
 
+/// Interval for renewing the ticket (which is valid for two hours).
+const TICKET_RENEWAL_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Retry interval after a failed ticket renewal.
+const TICKET_RENEWAL_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Idle connections are kept open for reuse by subsequent requests of the same client.
+const HTTP_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// HTTP header carrying the correlation ID of a client operation.
+pub const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
+lazy_static::lazy_static! {
+    static ref CORRELATION_ID: String = match std::env::var("PBS_CORRELATION_ID") {
+        Ok(id) if is_valid_correlation_id(&id) => id,
+        _ => generate_correlation_id(),
+    };
+}
+
+/// Checks whether `id` is usable as correlation ID, i.e. short and without special characters.
+pub fn is_valid_correlation_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+fn generate_correlation_id() -> String {
+    let mut buf = [0u8; 8];
+    match openssl::rand::rand_bytes(&mut buf) {
+        Ok(()) => hex::encode(buf),
+        Err(_) => format!("{:x}-{:x}", std::process::id(), proxmox_time::epoch_i64()),
+    }
+}
+
+/// Returns the correlation ID of this client process.
+///
+/// Every request sent by this process carries it in the [`CORRELATION_ID_HEADER`], so the
+/// operation can be traced in the server's task and access logs. It is generated randomly, unless
+/// set explicitly via the `PBS_CORRELATION_ID` environment variable.
+pub fn correlation_id() -> &'static str {
+    &CORRELATION_ID
+}
+
 #[derive(Clone)]
 pub struct AuthInfo {
     pub auth_id: Authid,
@@ -131,6 +185,93 @@ impl Default for HttpClientOptions {
     }
 }
 
+/// Verification of server certificates which are not signed by a trusted CA, by their
+/// fingerprint, shared by the TLS backends.
+pub(crate) struct CertVerification {
+    server: String,
+    port: u16,
+    expected_fingerprint: Option<String>,
+    pinned: bool,
+    interactive: bool,
+    fingerprint_cache: bool,
+    prefix: Option<String>,
+    verified_fingerprint: Arc<Mutex<Option<String>>>,
+}
+
+impl CertVerification {
+    /// Checks the fingerprint of the server's (leaf) certificate against the expected or pinned
+    /// one, or asks the user to confirm it in interactive mode.
+    ///
+    /// Returns the fingerprint to accept.
+    pub(crate) fn verify_fingerprint(&self, fp_string: String) -> Result<String, Error> {
+        if let Some(expected_fingerprint) = &self.expected_fingerprint {
+            let expected_fingerprint = expected_fingerprint.to_lowercase();
+            if expected_fingerprint == fp_string {
+                return Ok(fp_string);
+            } else if self.pinned {
+                bail!(
+                    "certificate fingerprint does not match pinned fingerprint!\n\
+                    pinned:   {}\n\
+                    received: {}\n\
+                    If the server certificate was changed on purpose, accept the new fingerprint \
+                    with 'proxmox-backup-client fingerprint update'.",
+                    expected_fingerprint,
+                    fp_string,
+                );
+            } else {
+                log::warn!("WARNING: certificate fingerprint does not match expected fingerprint!");
+                log::warn!("expected:    {}", expected_fingerprint);
+            }
+        }
+
+        // If we're on a TTY, query the user
+        if self.interactive && std::io::stdin().is_terminal() {
+            log::info!("fingerprint: {}", fp_string);
+            loop {
+                eprint!("Are you sure you want to continue connecting? (y/n): ");
+                let _ = std::io::stdout().flush();
+                use std::io::{BufRead, BufReader};
+                let mut line = String::new();
+                match BufReader::new(std::io::stdin()).read_line(&mut line) {
+                    Ok(_) => {
+                        let trimmed = line.trim();
+                        if trimmed == "y" || trimmed == "Y" {
+                            return Ok(fp_string);
+                        } else if trimmed == "n" || trimmed == "N" {
+                            bail!("Certificate fingerprint was not confirmed.");
+                        } else {
+                            continue;
+                        }
+                    }
+                    Err(err) => bail!("Certificate fingerprint was not confirmed - {}.", err),
+                }
+            }
+        }
+
+        bail!("Certificate fingerprint was not confirmed.");
+    }
+
+    /// Remembers the accepted `fingerprint` of the server's certificate, which expires at
+    /// `not_after`.
+    pub(crate) fn accept(&self, fingerprint: String, not_after: Option<i64>) {
+        // pin on first use, pinned fingerprints only change via explicit update
+        if self.fingerprint_cache && !self.pinned {
+            if let Some(prefix) = &self.prefix {
+                if let Err(err) = fingerprints::store_fingerprint(
+                    prefix,
+                    &self.server,
+                    self.port,
+                    &fingerprint,
+                    not_after,
+                ) {
+                    log::error!("{}", err);
+                }
+            }
+        }
+        *self.verified_fingerprint.lock().unwrap() = Some(fingerprint);
+    }
+}
+
 /// HTTP(S) API client
 pub struct HttpClient {
     client: Client<HttpsConnector>,
@@ -139,8 +280,9 @@ pub struct HttpClient {
     fingerprint: Arc<Mutex<Option<String>>>,
     first_auth: Option<BroadcastFuture<()>>,
     auth: Arc<RwLock<AuthInfo>>,
+    relogin_password: Option<String>,
     ticket_abort: futures::future::AbortHandle,
-    _options: HttpClientOptions,
+    options: HttpClientOptions,
 }
 
 /// Delete stored ticket data (logout)
@@ -150,83 +292,17 @@ pub fn delete_ticket_info(prefix: &str, server: &str, username: &Userid) -> Resu
     // usually /run/user/<uid>/...
     let path = base.place_runtime_file("tickets")?;
 
-    let mode = nix::sys::stat::Mode::from_bits_truncate(0o0600);
-
     let mut data = file_get_json(&path, Some(json!({})))?;
 
     if let Some(map) = data[server].as_object_mut() {
         map.remove(username.as_str());
     }
 
-    replace_file(
-        path,
-        data.to_string().as_bytes(),
-        CreateOptions::new().perm(mode),
-        false,
-    )?;
+    replace_secret_file(path, data.to_string().as_bytes())?;
 
     Ok(())
 }
 
-fn store_fingerprint(prefix: &str, server: &str, fingerprint: &str) -> Result<(), Error> {
-    let base = BaseDirectories::with_prefix(prefix)?;
-
-    // usually ~/.config/<prefix>/fingerprints
-    let path = base.place_config_file("fingerprints")?;
-
-    let raw = match std::fs::read_to_string(&path) {
-        Ok(v) => v,
-        Err(err) => {
-            if err.kind() == std::io::ErrorKind::NotFound {
-                String::new()
-            } else {
-                bail!("unable to read fingerprints from {:?} - {}", path, err);
-            }
-        }
-    };
-
-    let mut result = String::new();
-
-    raw.split('\n').for_each(|line| {
-        let items: Vec<String> = line.split_whitespace().map(String::from).collect();
-        if items.len() == 2 {
-            if items[0] == server {
-                // found, add later with new fingerprint
-            } else {
-                result.push_str(line);
-                result.push('\n');
-            }
-        }
-    });
-
-    result.push_str(server);
-    result.push(' ');
-    result.push_str(fingerprint);
-    result.push('\n');
-
-    replace_file(path, result.as_bytes(), CreateOptions::new(), false)?;
-
-    Ok(())
-}
-
-fn load_fingerprint(prefix: &str, server: &str) -> Option<String> {
-    let base = BaseDirectories::with_prefix(prefix).ok()?;
-
-    // usually ~/.config/<prefix>/fingerprints
-    let path = base.place_config_file("fingerprints").ok()?;
-
-    let raw = std::fs::read_to_string(path).ok()?;
-
-    for line in raw.split('\n') {
-        let items: Vec<String> = line.split_whitespace().map(String::from).collect();
-        if items.len() == 2 && items[0] == server {
-            return Some(items[1].clone());
-        }
-    }
-
-    None
-}
-
 fn store_ticket_info(
     prefix: &str,
     server: &str,
@@ -239,8 +315,6 @@ fn store_ticket_info(
     // usually /run/user/<uid>/...
     let path = base.place_runtime_file("tickets")?;
 
-    let mode = nix::sys::stat::Mode::from_bits_truncate(0o0600);
-
     let mut data = file_get_json(&path, Some(json!({})))?;
 
     let now = proxmox_time::epoch_i64();
@@ -263,12 +337,7 @@ fn store_ticket_info(
         }
     }
 
-    replace_file(
-        path,
-        new_data.to_string().as_bytes(),
-        CreateOptions::new().perm(mode),
-        false,
-    )?;
+    replace_secret_file(path, new_data.to_string().as_bytes())?;
 
     Ok(())
 }
@@ -294,6 +363,13 @@ fn load_ticket_info(prefix: &str, server: &str, userid: &Userid) -> Option<(Stri
     }
 }
 
+fn is_unauthorized(err: &Error) -> bool {
+    matches!(
+        err.downcast_ref::<HttpError>(),
+        Some(HttpError { code, .. }) if *code == http::StatusCode::UNAUTHORIZED
+    )
+}
+
 fn build_uri(server: &str, port: u16, path: &str, query: Option<String>) -> Result<Uri, Error> {
     Uri::builder()
         .scheme("https")
@@ -316,96 +392,67 @@ impl HttpClient {
         let verified_fingerprint = Arc::new(Mutex::new(None));
 
         let mut expected_fingerprint = options.fingerprint.take();
+        let mut pinned = false;
 
         if expected_fingerprint.is_some() {
             // do not store fingerprints passed via options in cache
             options.fingerprint_cache = false;
         } else if options.fingerprint_cache && options.prefix.is_some() {
-            expected_fingerprint = load_fingerprint(options.prefix.as_ref().unwrap(), server);
+            let prefix = options.prefix.as_ref().unwrap();
+            if let Some(known) = fingerprints::lookup_fingerprint(prefix, server, port) {
+                known.warn_expiry();
+                expected_fingerprint = Some(known.fingerprint);
+                pinned = true;
+            }
         }
 
-        let mut ssl_connector_builder = SslConnector::builder(SslMethod::tls()).unwrap();
-
-        if options.verify_cert {
-            let server = server.to_string();
-            let verified_fingerprint = verified_fingerprint.clone();
-            let interactive = options.interactive;
-            let fingerprint_cache = options.fingerprint_cache;
-            let prefix = options.prefix.clone();
-            let trust_openssl_valid = Arc::new(Mutex::new(true));
-            ssl_connector_builder.set_verify_callback(
-                openssl::ssl::SslVerifyMode::PEER,
-                move |valid, ctx| match Self::verify_callback(
-                    valid,
-                    ctx,
-                    expected_fingerprint.as_ref(),
-                    interactive,
-                    Arc::clone(&trust_openssl_valid),
-                ) {
-                    Ok(None) => true,
-                    Ok(Some(fingerprint)) => {
-                        if fingerprint_cache && prefix.is_some() {
-                            if let Err(err) =
-                                store_fingerprint(prefix.as_ref().unwrap(), &server, &fingerprint)
-                            {
-                                log::error!("{}", err);
-                            }
-                        }
-                        *verified_fingerprint.lock().unwrap() = Some(fingerprint);
-                        true
-                    }
-                    Err(err) => {
-                        log::error!("certificate validation failed - {}", err);
-                        false
-                    }
-                },
-            );
-        } else {
-            ssl_connector_builder.set_verify(openssl::ssl::SslVerifyMode::NONE);
-        }
+        let verification = CertVerification {
+            server: server.to_string(),
+            port,
+            expected_fingerprint,
+            pinned,
+            interactive: options.interactive,
+            fingerprint_cache: options.fingerprint_cache,
+            prefix: options.prefix.clone(),
+            verified_fingerprint: verified_fingerprint.clone(),
+        };
 
         let mut httpc = HttpConnector::new();
         httpc.set_nodelay(true); // important for h2 download performance!
         httpc.enforce_http(false); // we want https...
 
         httpc.set_connect_timeout(Some(std::time::Duration::new(10, 0)));
-        let mut https = HttpsConnector::with_connector(
-            httpc,
-            ssl_connector_builder.build(),
-            PROXMOX_BACKUP_TCP_KEEPALIVE_TIME,
-        );
-
-        if let Some(rate_in) = options.limit.rate_in {
-            let burst_in = options.limit.burst_in.unwrap_or(rate_in).as_u64();
-            https.set_read_limiter(Some(Arc::new(Mutex::new(RateLimiter::new(
-                rate_in.as_u64(),
-                burst_in,
-            )))));
-        }
-
-        if let Some(rate_out) = options.limit.rate_out {
-            let burst_out = options.limit.burst_out.unwrap_or(rate_out).as_u64();
-            https.set_write_limiter(Some(Arc::new(Mutex::new(RateLimiter::new(
-                rate_out.as_u64(),
-                burst_out,
-            )))));
-        }
 
         let proxy_config = ProxyConfig::from_proxy_env()?;
-        if let Some(config) = proxy_config {
-            log::info!("Using proxy connection: {}:{}", config.host, config.port);
-            https.set_proxy(config);
-        }
+
+        #[cfg(not(feature = "rustls"))]
+        let https = Self::openssl_connector(httpc, &options, verification, proxy_config);
+        #[cfg(feature = "rustls")]
+        let https = super::rustls_connector::connector(
+            httpc,
+            options.verify_cert.then_some(verification),
+            &options.limit,
+            proxy_config,
+        )?;
 
         let client = Client::builder()
             //.http2_initial_stream_window_size( (1 << 31) - 2)
             //.http2_initial_connection_window_size( (1 << 31) - 2)
+            .pool_idle_timeout(HTTP_POOL_IDLE_TIMEOUT)
+            .http2_keep_alive_interval(HTTP_POOL_IDLE_TIMEOUT / 2)
+            .http2_keep_alive_while_idle(true)
             .build::<_, Body>(https);
 
         let password = options.password.take();
         let use_ticket_cache = options.ticket_cache && options.prefix.is_some();
 
+        // the real password (not a cached ticket), used to log in again once the ticket expired
+        let mut relogin_password = None;
+
         let password = if let Some(password) = password {
+            if !auth_id.is_token() {
+                relogin_password = Some(password.clone());
+            }
             password
         } else {
             let userid = if auth_id.is_token() {
@@ -420,7 +467,9 @@ impl HttpClient {
             if let Some((ticket, _token)) = ticket_info {
                 ticket
             } else {
-                Self::get_password(userid, options.interactive)?
+                let password = Self::get_password(userid, options.interactive)?;
+                relogin_password = Some(password.clone());
+                password
             }
         };
 
@@ -434,20 +483,19 @@ impl HttpClient {
         let client2 = client.clone();
         let auth2 = auth.clone();
         let prefix2 = options.prefix.clone();
+        let relogin_password2 = relogin_password.clone();
 
         let renewal_future = async move {
+            let mut delay = TICKET_RENEWAL_INTERVAL;
             loop {
-                tokio::time::sleep(Duration::new(60 * 15, 0)).await; // 15 minutes
-                let (auth_id, ticket) = {
-                    let authinfo = auth2.read().unwrap().clone();
-                    (authinfo.auth_id, authinfo.ticket)
-                };
-                match Self::credentials(
+                tokio::time::sleep(delay).await;
+                let authinfo = auth2.read().unwrap().clone();
+                match Self::renew_credentials(
                     client2.clone(),
                     server2.clone(),
                     port,
-                    auth_id.user().clone(),
-                    ticket,
+                    authinfo,
+                    relogin_password2.clone(),
                 )
                 .await
                 {
@@ -466,10 +514,13 @@ impl HttpClient {
                             }
                         }
                         *auth2.write().unwrap() = auth;
+                        delay = TICKET_RENEWAL_INTERVAL;
                     }
                     Err(err) => {
+                        // keep trying, e.g. after a network outage or a suspended system, a
+                        // failed request triggers a re-login in the meantime
                         log::error!("re-authentication failed: {}", err);
-                        return;
+                        delay = TICKET_RENEWAL_RETRY_INTERVAL;
                     }
                 }
             }
@@ -521,12 +572,78 @@ impl HttpClient {
             port,
             fingerprint: verified_fingerprint,
             auth,
+            relogin_password,
             ticket_abort,
             first_auth,
-            _options: options,
+            options,
         })
     }
 
+    #[cfg(not(feature = "rustls"))]
+    fn openssl_connector(
+        httpc: HttpConnector,
+        options: &HttpClientOptions,
+        verification: CertVerification,
+        proxy_config: Option<ProxyConfig>,
+    ) -> HttpsConnector {
+        let mut ssl_connector_builder = SslConnector::builder(SslMethod::tls()).unwrap();
+
+        if options.verify_cert {
+            let trust_openssl_valid = Arc::new(Mutex::new(true));
+            ssl_connector_builder.set_verify_callback(
+                openssl::ssl::SslVerifyMode::PEER,
+                move |valid, ctx| match Self::verify_callback(
+                    valid,
+                    ctx,
+                    &verification,
+                    Arc::clone(&trust_openssl_valid),
+                ) {
+                    Ok(None) => true,
+                    Ok(Some(fingerprint)) => {
+                        let not_after = ctx.current_cert().and_then(fingerprints::cert_not_after);
+                        verification.accept(fingerprint, not_after);
+                        true
+                    }
+                    Err(err) => {
+                        log::error!("certificate validation failed - {}", err);
+                        false
+                    }
+                },
+            );
+        } else {
+            ssl_connector_builder.set_verify(openssl::ssl::SslVerifyMode::NONE);
+        }
+
+        let mut https = HttpsConnector::with_connector(
+            httpc,
+            ssl_connector_builder.build(),
+            PROXMOX_BACKUP_TCP_KEEPALIVE_TIME,
+        );
+
+        if let Some(rate_in) = options.limit.rate_in {
+            let burst_in = options.limit.burst_in.unwrap_or(rate_in).as_u64();
+            https.set_read_limiter(Some(Arc::new(Mutex::new(RateLimiter::new(
+                rate_in.as_u64(),
+                burst_in,
+            )))));
+        }
+
+        if let Some(rate_out) = options.limit.rate_out {
+            let burst_out = options.limit.burst_out.unwrap_or(rate_out).as_u64();
+            https.set_write_limiter(Some(Arc::new(Mutex::new(RateLimiter::new(
+                rate_out.as_u64(),
+                burst_out,
+            )))));
+        }
+
+        if let Some(config) = proxy_config {
+            log::info!("Using proxy connection: {}:{}", config.host, config.port);
+            https.set_proxy(config);
+        }
+
+        https
+    }
+
     /// Login
     ///
     /// Login is done on demand, so this is only required if you need
@@ -543,6 +660,64 @@ impl HttpClient {
         Ok(authinfo.clone())
     }
 
+    /// Authenticate again, e.g. after the server rejected an expired ticket.
+    ///
+    /// Tries to renew the current ticket first, then falls back to logging in with the password
+    /// (if known, or asking for it in interactive mode). Not possible for API tokens.
+    ///
+    /// Already established backup/reader protocol sessions are only authenticated on upgrade, so
+    /// they are not affected by the ticket lifetime.
+    pub async fn reauthenticate(&self) -> Result<AuthInfo, Error> {
+        let authinfo = self.login().await?;
+        if authinfo.auth_id.is_token() {
+            bail!("unable to re-authenticate API token");
+        }
+
+        let result = Self::renew_credentials(
+            self.client.clone(),
+            self.server.clone(),
+            self.port,
+            authinfo.clone(),
+            self.relogin_password.clone(),
+        )
+        .await;
+
+        let auth = match result {
+            Ok(auth) => auth,
+            Err(_) if self.relogin_password.is_none() && self.options.interactive => {
+                let userid = authinfo.auth_id.user().clone();
+                let password = Self::get_password(&userid, true)?;
+                Self::credentials(
+                    self.client.clone(),
+                    self.server.clone(),
+                    self.port,
+                    userid,
+                    password,
+                )
+                .await?
+            }
+            Err(err) => return Err(err),
+        };
+
+        if self.options.ticket_cache {
+            if let Some(ref prefix) = self.options.prefix {
+                if let Err(err) = store_ticket_info(
+                    prefix,
+                    &self.server,
+                    &auth.auth_id.to_string(),
+                    &auth.ticket,
+                    &auth.token,
+                ) {
+                    log::error!("storing login ticket failed: {}", err);
+                }
+            }
+        }
+
+        *self.auth.write().unwrap() = auth.clone();
+
+        Ok(auth)
+    }
+
     /// Returns the optional fingerprint passed to the new() constructor.
     pub fn fingerprint(&self) -> Option<String> {
         (*self.fingerprint.lock().unwrap()).clone()
@@ -558,11 +733,11 @@ impl HttpClient {
         bail!("no password input mechanism available");
     }
 
+    #[cfg(not(feature = "rustls"))]
     fn verify_callback(
         openssl_valid: bool,
         ctx: &mut X509StoreContextRef,
-        expected_fingerprint: Option<&String>,
-        interactive: bool,
+        verification: &CertVerification,
         trust_openssl: Arc<Mutex<bool>>,
     ) -> Result<Option<String>, Error> {
         let mut trust_openssl_valid = trust_openssl.lock().unwrap();
@@ -584,53 +759,9 @@ impl HttpClient {
         }
 
         // leaf certificate - if we end up here, we have to verify the fingerprint!
-        let fp = match cert.digest(openssl::hash::MessageDigest::sha256()) {
-            Ok(fp) => fp,
-            Err(err) => bail!("failed to calculate certificate FP - {}", err), // should not happen
-        };
-        let fp_string = hex::encode(fp);
-        let fp_string = fp_string
-            .as_bytes()
-            .chunks(2)
-            .map(|v| std::str::from_utf8(v).unwrap())
-            .collect::<Vec<&str>>()
-            .join(":");
-
-        if let Some(expected_fingerprint) = expected_fingerprint {
-            let expected_fingerprint = expected_fingerprint.to_lowercase();
-            if expected_fingerprint == fp_string {
-                return Ok(Some(fp_string));
-            } else {
-                log::warn!("WARNING: certificate fingerprint does not match expected fingerprint!");
-                log::warn!("expected:    {}", expected_fingerprint);
-            }
-        }
+        let fp_string = fingerprints::cert_fingerprint(cert)?;
 
-        // If we're on a TTY, query the user
-        if interactive && std::io::stdin().is_terminal() {
-            log::info!("fingerprint: {}", fp_string);
-            loop {
-                eprint!("Are you sure you want to continue connecting? (y/n): ");
-                let _ = std::io::stdout().flush();
-                use std::io::{BufRead, BufReader};
-                let mut line = String::new();
-                match BufReader::new(std::io::stdin()).read_line(&mut line) {
-                    Ok(_) => {
-                        let trimmed = line.trim();
-                        if trimmed == "y" || trimmed == "Y" {
-                            return Ok(Some(fp_string));
-                        } else if trimmed == "n" || trimmed == "N" {
-                            bail!("Certificate fingerprint was not confirmed.");
-                        } else {
-                            continue;
-                        }
-                    }
-                    Err(err) => bail!("Certificate fingerprint was not confirmed - {}.", err),
-                }
-            }
-        }
-
-        bail!("Certificate fingerprint was not confirmed.");
+        verification.verify_fingerprint(fp_string).map(Some)
     }
 
     pub async fn request(&self, mut req: Request<Body>) -> Result<Value, Error> {
@@ -663,24 +794,44 @@ impl HttpClient {
         Self::api_request(client, req).await
     }
 
+    // Like `request`, but authenticates again and retries once if the ticket was rejected.
+    async fn json_request(
+        &self,
+        method: &str,
+        path: &str,
+        data: Option<Value>,
+    ) -> Result<Value, Error> {
+        let req = Self::request_builder(&self.server, self.port, method, path, data.clone())?;
+        match self.request(req).await {
+            Err(err) if is_unauthorized(&err) && self.relogin_possible() => {
+                log::info!("ticket rejected by server, authenticating again");
+                self.reauthenticate().await?;
+                let req = Self::request_builder(&self.server, self.port, method, path, data)?;
+                self.request(req).await
+            }
+            result => result,
+        }
+    }
+
+    fn relogin_possible(&self) -> bool {
+        let auth_id = &self.auth.read().unwrap().auth_id;
+        !auth_id.is_token() && (self.relogin_password.is_some() || self.options.interactive)
+    }
+
     pub async fn get(&self, path: &str, data: Option<Value>) -> Result<Value, Error> {
-        let req = Self::request_builder(&self.server, self.port, "GET", path, data)?;
-        self.request(req).await
+        self.json_request("GET", path, data).await
     }
 
     pub async fn delete(&self, path: &str, data: Option<Value>) -> Result<Value, Error> {
-        let req = Self::request_builder(&self.server, self.port, "DELETE", path, data)?;
-        self.request(req).await
+        self.json_request("DELETE", path, data).await
     }
 
     pub async fn post(&self, path: &str, data: Option<Value>) -> Result<Value, Error> {
-        let req = Self::request_builder(&self.server, self.port, "POST", path, data)?;
-        self.request(req).await
+        self.json_request("POST", path, data).await
     }
 
     pub async fn put(&self, path: &str, data: Option<Value>) -> Result<Value, Error> {
-        let req = Self::request_builder(&self.server, self.port, "PUT", path, data)?;
-        self.request(req).await
+        self.json_request("PUT", path, data).await
     }
 
     pub async fn download(&self, path: &str, output: &mut (dyn Write + Send)) -> Result<(), Error> {
@@ -734,6 +885,7 @@ impl HttpClient {
             .method("POST")
             .uri(url)
             .header("User-Agent", "proxmox-backup-client/1.0")
+            .header(CORRELATION_ID_HEADER, correlation_id())
             .header("Content-Type", content_type)
             .body(body)
             .unwrap();
@@ -842,6 +994,33 @@ impl HttpClient {
         Ok(auth)
     }
 
+    // Renew the ticket, falling back to a login with `password` if that fails.
+    async fn renew_credentials(
+        client: Client<HttpsConnector>,
+        server: String,
+        port: u16,
+        authinfo: AuthInfo,
+        password: Option<String>,
+    ) -> Result<AuthInfo, Error> {
+        let userid = authinfo.auth_id.user().clone();
+        let result = Self::credentials(
+            client.clone(),
+            server.clone(),
+            port,
+            userid.clone(),
+            authinfo.ticket,
+        )
+        .await;
+
+        match (result, password) {
+            (Ok(auth), _) => Ok(auth),
+            (Err(_), Some(password)) => {
+                Self::credentials(client, server, port, userid, password).await
+            }
+            (Err(err), None) => Err(err),
+        }
+    }
+
     async fn api_response(response: Response<Body>) -> Result<Value, Error> {
         let status = response.status();
         let data = hyper::body::to_bytes(response.into_body()).await?;
@@ -894,6 +1073,7 @@ impl HttpClient {
                     .method(method)
                     .uri(url)
                     .header("User-Agent", "proxmox-backup-client/1.0")
+                    .header(CORRELATION_ID_HEADER, correlation_id())
                     .header(hyper::header::CONTENT_TYPE, "application/json")
                     .body(Body::from(data.to_string()))?;
                 Ok(request)
@@ -904,6 +1084,7 @@ impl HttpClient {
                     .method(method)
                     .uri(url)
                     .header("User-Agent", "proxmox-backup-client/1.0")
+                    .header(CORRELATION_ID_HEADER, correlation_id())
                     .header(
                         hyper::header::CONTENT_TYPE,
                         "application/x-www-form-urlencoded",
@@ -917,6 +1098,7 @@ impl HttpClient {
                 .method(method)
                 .uri(url)
                 .header("User-Agent", "proxmox-backup-client/1.0")
+                .header(CORRELATION_ID_HEADER, correlation_id())
                 .header(
                     hyper::header::CONTENT_TYPE,
                     "application/x-www-form-urlencoded",
@@ -4,6 +4,7 @@
 //! server using https.
 
 pub mod catalog_shell;
+pub mod fingerprints;
 pub mod pxar;
 pub mod tools;
 
@@ -13,6 +14,9 @@ pub mod pipe_to_stream;
 mod http_client;
 pub use http_client::*;
 
+#[cfg(feature = "rustls")]
+mod rustls_connector;
+
 mod vsock_client;
 pub use vsock_client::*;
 
@@ -25,6 +29,15 @@ pub use backup_reader::*;
 mod backup_writer;
 pub use backup_writer::*;
 
+mod backup_session;
+pub use backup_session::*;
+
+mod backup_spool;
+pub use backup_spool::*;
+
+mod restore_session;
+pub use restore_session::*;
+
 mod remote_chunk_reader;
 pub use remote_chunk_reader::*;
 
@@ -38,6 +51,12 @@ mod backup_specification;
 pub use backup_specification::*;
 
 mod chunk_stream;
-pub use chunk_stream::{ChunkStream, FixedChunkStream};
+pub use chunk_stream::{is_zero_chunk, ChunkStream, FixedChunkStream};
+
+mod prehash_cache;
+pub use prehash_cache::PrehashCache;
+
+mod change_journal;
+pub use change_journal::{watch_changes, ChangeJournal};
 
 pub const PROXMOX_BACKUP_TCP_KEEPALIVE_TIME: u32 = 120;
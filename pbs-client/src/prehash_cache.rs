@@ -0,0 +1,119 @@
+//! Local cache of chunk digests by BLAKE3 pre-hash
+//!
+//! Hashing unchanged data with SHA-256 on every backup is a noticeable part of the CPU load on
+//! devices without hardware support for it. The cache maps the BLAKE3 hash of the contents of a
+//! chunk, which is a lot cheaper to compute, to its chunk digest from an earlier backup of the
+//! same archive, so only changed chunks need to be hashed with SHA-256.
+//!
+//! The pre-hash is only used locally to detect unchanged chunks, chunks are still identified by
+//! their regular digest towards the server.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{bail, Error};
+
+use pbs_api_types::{BackupDir, BackupNamespace};
+use pbs_tools::crypt_config::CryptConfig;
+
+use crate::tools::base_directories;
+use crate::tools::state_file::replace_state_file;
+
+// openssl::sha::sha256(b"Proxmox Backup Prehash Cache v1.0")[0..8]
+const PREHASH_CACHE_MAGIC_1_0: [u8; 8] = [234, 172, 126, 124, 221, 132, 49, 50];
+
+const ENTRY_SIZE: usize = 64;
+
+/// Chunk digests by BLAKE3 hash of the chunk contents, for one archive.
+///
+/// Entries are looked up in the cache of the previous backup, and only the entries of the
+/// chunks of the current backup are written back by [`save`](Self::save), so the cache does not
+/// grow beyond the size of the archive.
+pub struct PrehashCache {
+    path: PathBuf,
+    previous: HashMap<[u8; 32], [u8; 32]>,
+    current: Mutex<HashMap<[u8; 32], [u8; 32]>>,
+}
+
+impl PrehashCache {
+    /// Open the cache of archive `archive_name` of the group of `snapshot`.
+    ///
+    /// Digests of encrypted chunks depend on the key, so each key has its own cache. A missing
+    /// or unreadable cache file is treated as empty.
+    pub fn open(
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
+        archive_name: &str,
+        crypt_config: Option<&CryptConfig>,
+    ) -> Result<Self, Error> {
+        let fingerprint = match crypt_config {
+            Some(crypt_config) => hex::encode(crypt_config.fingerprint()),
+            None => "none".to_string(),
+        };
+        let key = format!("{ns}/{}/{archive_name}/{fingerprint}", snapshot.group);
+        let name = hex::encode(openssl::sha::sha256(key.as_bytes()));
+
+        // usually $HOME/.cache/proxmox-backup/prehash/<name>
+        let path = base_directories()?.place_cache_file(format!("prehash/{name}"))?;
+
+        let previous = match std::fs::read(&path) {
+            Ok(data) => Self::parse(&data).unwrap_or_else(|err| {
+                log::warn!("ignoring pre-hash cache {path:?} - {err}");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        Ok(Self {
+            path,
+            previous,
+            current: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn parse(data: &[u8]) -> Result<HashMap<[u8; 32], [u8; 32]>, Error> {
+        if data.len() < 8 || data[0..8] != PREHASH_CACHE_MAGIC_1_0 {
+            bail!("wrong magic number");
+        }
+        let data = &data[8..];
+        if data.len() % ENTRY_SIZE != 0 {
+            bail!("truncated file");
+        }
+        Ok(data
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| {
+                let (prehash, digest) = entry.split_at(32);
+                (prehash.try_into().unwrap(), digest.try_into().unwrap())
+            })
+            .collect())
+    }
+
+    /// Returns the digest of a chunk with `data` from the previous backup, computing it with
+    /// `compute_digest` if the chunk is new or changed.
+    pub fn digest<F>(&self, data: &[u8], compute_digest: F) -> [u8; 32]
+    where
+        F: FnOnce() -> [u8; 32],
+    {
+        let prehash: [u8; 32] = blake3::hash(data).into();
+        let digest = match self.previous.get(&prehash) {
+            Some(digest) => *digest,
+            None => compute_digest(),
+        };
+        self.current.lock().unwrap().insert(prehash, digest);
+        digest
+    }
+
+    /// Replace the cache file with the entries of the chunks of the current backup.
+    pub fn save(&self) -> Result<(), Error> {
+        let current = self.current.lock().unwrap();
+        let mut data = Vec::with_capacity(8 + current.len() * ENTRY_SIZE);
+        data.extend_from_slice(&PREHASH_CACHE_MAGIC_1_0);
+        for (prehash, digest) in current.iter() {
+            data.extend_from_slice(prehash);
+            data.extend_from_slice(digest);
+        }
+
+        replace_state_file(&self.path, &data, false)
+    }
+}
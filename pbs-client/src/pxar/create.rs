@@ -5,7 +5,9 @@ use std::io::{self, Read};
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use anyhow::{bail, Context, Error};
 use futures::future::BoxFuture;
@@ -14,22 +16,42 @@ use nix::dir::Dir;
 use nix::errno::Errno;
 use nix::fcntl::OFlag;
 use nix::sys::stat::{FileStat, Mode};
+use serde::{Deserialize, Serialize};
 
 use pathpatterns::{MatchEntry, MatchFlag, MatchList, MatchType, PatternFlag};
+use proxmox_schema::api;
 use proxmox_sys::error::SysError;
 use pxar::encoder::{LinkOffset, SeqWrite};
 use pxar::Metadata;
 
 use proxmox_io::vec;
 use proxmox_lang::c_str;
+#[cfg(target_os = "linux")]
 use proxmox_sys::fs::{self, acl, xattr};
 
 use pbs_datastore::catalog::BackupCatalogWriter;
 
-use crate::pxar::metadata::errno_is_unsupported;
+#[cfg(target_os = "linux")]
+use crate::pxar::metadata::{errno_is_unsupported, is_foreign_xattr, FOREIGN_XATTRS};
+use crate::pxar::platform;
+use crate::pxar::profile::{ArchiveProfile, ProfilePhase};
 use crate::pxar::tools::assert_single_path_component;
 use crate::pxar::Flags;
 
+#[api]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// How to handle named pipes (FIFOs) or unix sockets when creating or extracting an archive.
+pub enum SpecialFilePolicy {
+    /// Archive or restore them.
+    #[default]
+    Include,
+    /// Leave them out silently.
+    Skip,
+    /// Leave them out, and report each one as warning.
+    Warn,
+}
+
 /// Pxar options for creating a pxar archive/stream
 #[derive(Default, Clone)]
 pub struct PxarCreateOptions {
@@ -43,9 +65,30 @@ pub struct PxarCreateOptions {
     pub skip_lost_and_found: bool,
     /// Skip xattrs of files that return E2BIG error
     pub skip_e2big_xattr: bool,
+    /// Counts warnings, e.g. about unreadable or vanished files
+    pub warnings: Option<Arc<AtomicUsize>>,
+    /// Do not read regular files, encode zeros instead (to only walk the file tree)
+    pub skip_file_contents: bool,
+    /// Records the time spent in the phases of archiving, per top-level directory
+    pub profile: Option<Arc<Mutex<ArchiveProfile>>>,
+    /// Whether named pipes are archived
+    pub fifos: SpecialFilePolicy,
+    /// Whether unix sockets are archived
+    pub sockets: SpecialFilePolicy,
+    /// Leave out the contents of unchanged directories, only for dry runs
+    pub skip_unchanged_dirs: Option<UnchangedDirFilter>,
 }
 
-fn detect_fs_type(fd: RawFd) -> Result<i64, Error> {
+/// Decides by path, relative to the archive root, and device number whether the contents of a
+/// directory are known to be unchanged. The archive misses the contents of these directories,
+/// so this is only useful to walk the file tree.
+pub type UnchangedDirFilter = Arc<dyn Fn(&Path, u64) -> bool + Send + Sync>;
+
+/// Get the Linux filesystem magic number of `fd`.
+///
+/// Other platforms have no such numbers and always get 0, which maps to the default feature set.
+#[cfg(target_os = "linux")]
+pub(crate) fn detect_fs_type(fd: RawFd) -> Result<i64, Error> {
     let mut fs_stat = std::mem::MaybeUninit::uninit();
     let res = unsafe { libc::fstatfs(fd, fs_stat.as_mut_ptr()) };
     Errno::result(res)?;
@@ -54,6 +97,11 @@ fn detect_fs_type(fd: RawFd) -> Result<i64, Error> {
     Ok(fs_stat.f_type)
 }
 
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn detect_fs_type(_fd: RawFd) -> Result<i64, Error> {
+    Ok(0)
+}
+
 fn strip_ascii_whitespace(line: &[u8]) -> &[u8] {
     let line = match line.iter().position(|&b| !b.is_ascii_whitespace()) {
         Some(n) => &line[n..],
@@ -131,6 +179,12 @@ struct Archiver {
     hardlinks: HashMap<HardLinkInfo, (PathBuf, LinkOffset)>,
     file_copy_buffer: Vec<u8>,
     skip_e2big_xattr: bool,
+    warnings: Option<Arc<AtomicUsize>>,
+    skip_file_contents: bool,
+    profile: Option<Arc<Mutex<ArchiveProfile>>>,
+    fifos: SpecialFilePolicy,
+    sockets: SpecialFilePolicy,
+    skip_unchanged_dirs: Option<UnchangedDirFilter>,
 }
 
 type Encoder<'a, T> = pxar::encoder::aio::Encoder<'a, T>;
@@ -147,6 +201,8 @@ where
     T: SeqWrite + Send,
     F: FnMut(&Path) -> Result<(), Error> + Send + 'static,
 {
+    let start_time = Instant::now();
+
     let fs_magic = detect_fs_type(source_dir.as_raw_fd())?;
     if is_virtual_file_system(fs_magic) {
         bail!("refusing to backup a virtual file system");
@@ -156,7 +212,7 @@ where
 
     let stat = nix::sys::stat::fstat(source_dir.as_raw_fd())?;
     let metadata = get_metadata(
-        source_dir.as_raw_fd(),
+        Some(source_dir.as_raw_fd()),
         &stat,
         feature_flags & fs_feature_flags,
         fs_magic,
@@ -167,7 +223,7 @@ where
 
     let mut device_set = options.device_set.clone();
     if let Some(ref mut set) = device_set {
-        set.insert(stat.st_dev);
+        set.insert(pbs_tools::fs::device_number(&stat));
     }
 
     let mut encoder = Encoder::new(&mut writer, &metadata).await?;
@@ -197,12 +253,22 @@ where
         hardlinks: HashMap::new(),
         file_copy_buffer: vec::undefined(4 * 1024 * 1024),
         skip_e2big_xattr: options.skip_e2big_xattr,
+        warnings: options.warnings,
+        skip_file_contents: options.skip_file_contents,
+        profile: options.profile,
+        fifos: options.fifos,
+        sockets: options.sockets,
+        skip_unchanged_dirs: options.skip_unchanged_dirs,
     };
 
     archiver
         .archive_dir_contents(&mut encoder, source_dir, true)
         .await?;
     encoder.finish().await?;
+
+    if let Some(profile) = &archiver.profile {
+        profile.lock().unwrap().set_duration(start_time.elapsed());
+    }
     Ok(())
 }
 
@@ -213,6 +279,23 @@ struct FileListEntry {
 }
 
 impl Archiver {
+    fn count_warning(&self) {
+        if let Some(warnings) = &self.warnings {
+            warnings.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // only query the clock when profiling
+    fn profile_start(&self) -> Option<Instant> {
+        self.profile.as_ref().map(|_| Instant::now())
+    }
+
+    fn profile_record(&self, path: &Path, phase: ProfilePhase, start: Option<Instant>) {
+        if let (Some(profile), Some(start)) = (&self.profile, start) {
+            profile.lock().unwrap().record(path, phase, start.elapsed());
+        }
+    }
+
     /// Get the currently effective feature flags. (Requested flags masked by the file system
     /// feature flags).
     fn flags(&self) -> Flags {
@@ -291,7 +374,7 @@ impl Archiver {
         // common flags we always want to use:
         let oflags = oflags | OFlag::O_CLOEXEC | OFlag::O_NOCTTY;
 
-        let mut noatime = OFlag::O_NOATIME;
+        let mut noatime = platform::O_NOATIME;
         loop {
             return match proxmox_sys::fd::openat(
                 &parent,
@@ -308,6 +391,7 @@ impl Archiver {
                 }
                 Err(Errno::EACCES) => {
                     log::warn!("failed to open file: {:?}: access denied", file_name);
+                    self.count_warning();
                     Ok(None)
                 }
                 Err(Errno::EPERM) if !noatime.is_empty() => {
@@ -343,42 +427,14 @@ impl Archiver {
                         err,
                     );
                     self.patterns.truncate(old_pattern_count);
+                    self.count_warning();
                     return Ok(());
                 }
             };
 
-            let line = strip_ascii_whitespace(&line);
-
-            if line.is_empty() || line[0] == b'#' {
-                continue;
-            }
-
-            let mut buf;
-            let (line, mode, anchored) = if line[0] == b'/' {
-                buf = Vec::with_capacity(path_bytes.len() + 1 + line.len());
-                buf.extend(path_bytes);
-                buf.extend(line);
-                (&buf[..], MatchType::Exclude, true)
-            } else if line.starts_with(b"!/") {
-                // inverted case with absolute path
-                buf = Vec::with_capacity(path_bytes.len() + line.len());
-                buf.extend(path_bytes);
-                buf.extend(&line[1..]); // without the '!'
-                (&buf[..], MatchType::Include, true)
-            } else if line.starts_with(b"!") {
-                (&line[1..], MatchType::Include, false)
-            } else {
-                (line, MatchType::Exclude, false)
-            };
-
-            match MatchEntry::parse_pattern(line, PatternFlag::PATH_NAME, mode) {
-                Ok(pattern) => {
-                    if anchored {
-                        self.patterns.push(pattern.add_flags(MatchFlag::ANCHORED));
-                    } else {
-                        self.patterns.push(pattern);
-                    }
-                }
+            match parse_pxarexclude_line(path_bytes, &line) {
+                Ok(Some(pattern)) => self.patterns.push(pattern),
+                Ok(None) => (),
                 Err(err) => {
                     log::error!("bad pattern in {:?}: {}", self.path, err);
                 }
@@ -444,7 +500,14 @@ impl Archiver {
             let mut stat_results: Option<FileStat> = None;
 
             let get_file_mode = || {
-                nix::sys::stat::fstatat(dir_fd, file_name, nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW)
+                let start = self.profile_start();
+                let stat = nix::sys::stat::fstatat(
+                    dir_fd,
+                    file_name,
+                    nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW,
+                );
+                self.profile_record(&full_path, ProfilePhase::Stat, start);
+                stat
             };
 
             let match_result = self
@@ -470,10 +533,14 @@ impl Archiver {
                 .unwrap_or_else(get_file_mode)
                 .with_context(|| format!("stat failed on {full_path:?}"))?;
 
+            if self.skip_special_file(&full_path, &stat) {
+                continue;
+            }
+
             self.entry_counter += 1;
             if self.entry_counter > self.entry_limit {
                 bail!(
-                    "exceeded allowed number of file entries (> {})",
+                    "exceeded allowed number of file entries (> {}), see option 'entries-max'",
                     self.entry_limit
                 );
             }
@@ -490,8 +557,27 @@ impl Archiver {
         Ok(file_list)
     }
 
+    // named pipes and sockets are left out if disabled by the feature flags or the policy
+    fn skip_special_file(&self, path: &Path, stat: &FileStat) -> bool {
+        let (policy, flag, kind) = match stat.st_mode & libc::S_IFMT {
+            libc::S_IFIFO => (self.fifos, Flags::WITH_FIFOS, "named pipe"),
+            libc::S_IFSOCK => (self.sockets, Flags::WITH_SOCKETS, "socket"),
+            _ => return false,
+        };
+        match policy {
+            SpecialFilePolicy::Include => !self.feature_flags.contains(flag),
+            SpecialFilePolicy::Skip => true,
+            SpecialFilePolicy::Warn => {
+                log::warn!("warning: skipping {kind} {path:?}");
+                self.count_warning();
+                true
+            }
+        }
+    }
+
     fn report_vanished_file(&mut self) -> Result<(), Error> {
         log::warn!("warning: file vanished while reading: {:?}", self.path);
+        self.count_warning();
         Ok(())
     }
 
@@ -500,6 +586,7 @@ impl Archiver {
             "warning: file size shrunk while reading: {:?}, file will be padded with zeros!",
             self.path,
         );
+        self.count_warning();
         Ok(())
     }
 
@@ -508,6 +595,7 @@ impl Archiver {
             "warning: file size increased while reading: {:?}, file will be truncated!",
             self.path,
         );
+        self.count_warning();
         Ok(())
     }
 
@@ -522,17 +610,24 @@ impl Archiver {
 
         let file_mode = stat.st_mode & libc::S_IFMT;
         let open_mode = if file_mode == libc::S_IFREG || file_mode == libc::S_IFDIR {
-            OFlag::empty()
+            Some(OFlag::empty())
         } else {
-            OFlag::O_PATH
+            platform::O_PATH
         };
 
-        let fd = self.open_file(
-            parent,
-            c_file_name,
-            open_mode | OFlag::O_RDONLY | OFlag::O_NOFOLLOW,
-            true,
-        )?;
+        let start = self.profile_start();
+        let fd = match open_mode {
+            Some(open_mode) => self
+                .open_file(
+                    parent,
+                    c_file_name,
+                    open_mode | OFlag::O_RDONLY | OFlag::O_NOFOLLOW,
+                    true,
+                )?
+                .map(Some),
+            None => Some(None),
+        };
+        self.profile_record(&self.path, ProfilePhase::Stat, start);
 
         let fd = match fd {
             Some(fd) => fd,
@@ -548,20 +643,27 @@ impl Archiver {
             return Ok(());
         }
 
+        if let Some(profile) = &self.profile {
+            profile.lock().unwrap().record_entry(&self.path);
+        }
+
+        let start = self.profile_start();
         let metadata = get_metadata(
-            fd.as_raw_fd(),
+            fd.as_ref().map(AsRawFd::as_raw_fd),
             stat,
             self.flags(),
             self.fs_magic,
             &mut self.fs_feature_flags,
             self.skip_e2big_xattr,
         )?;
+        self.profile_record(&self.path, ProfilePhase::Metadata, start);
 
         let file_name: &Path = OsStr::from_bytes(c_file_name.to_bytes()).as_ref();
         match metadata.file_type() {
             mode::IFREG => {
+                let fd = fd.context("regular file was not opened")?;
                 let link_info = HardLinkInfo {
-                    st_dev: stat.st_dev,
+                    st_dev: pbs_tools::fs::device_number(stat),
                     st_ino: stat.st_ino,
                 };
 
@@ -571,7 +673,9 @@ impl Archiver {
                             catalog.lock().unwrap().add_hardlink(c_file_name)?;
                         }
 
+                        let start = self.profile_start();
                         encoder.add_hardlink(file_name, path, *offset).await?;
+                        self.profile_record(&self.path, ProfilePhase::Encode, start);
 
                         return Ok(());
                     }
@@ -597,6 +701,7 @@ impl Archiver {
                 Ok(())
             }
             mode::IFDIR => {
+                let fd = fd.context("directory was not opened")?;
                 let dir = Dir::from_fd(fd.into_raw_fd())?;
 
                 if let Some(ref catalog) = self.catalog {
@@ -615,21 +720,28 @@ impl Archiver {
                     catalog.lock().unwrap().add_socket(c_file_name)?;
                 }
 
-                Ok(encoder.add_socket(&metadata, file_name).await?)
+                let start = self.profile_start();
+                encoder.add_socket(&metadata, file_name).await?;
+                self.profile_record(&self.path, ProfilePhase::Encode, start);
+                Ok(())
             }
             mode::IFIFO => {
                 if let Some(ref catalog) = self.catalog {
                     catalog.lock().unwrap().add_fifo(c_file_name)?;
                 }
 
-                Ok(encoder.add_fifo(&metadata, file_name).await?)
+                let start = self.profile_start();
+                encoder.add_fifo(&metadata, file_name).await?;
+                self.profile_record(&self.path, ProfilePhase::Encode, start);
+                Ok(())
             }
             mode::IFLNK => {
                 if let Some(ref catalog) = self.catalog {
                     catalog.lock().unwrap().add_symlink(c_file_name)?;
                 }
 
-                self.add_symlink(encoder, fd, file_name, &metadata).await
+                self.add_symlink(encoder, parent, c_file_name, &metadata)
+                    .await
             }
             mode::IFBLK => {
                 if let Some(ref catalog) = self.catalog {
@@ -663,7 +775,9 @@ impl Archiver {
     ) -> Result<(), Error> {
         let dir_name = OsStr::from_bytes(dir_name.to_bytes());
 
+        let start = self.profile_start();
         let mut encoder = encoder.create_directory(dir_name, metadata).await?;
+        self.profile_record(&self.path, ProfilePhase::Encode, start);
 
         let old_fs_magic = self.fs_magic;
         let old_fs_feature_flags = self.fs_feature_flags;
@@ -678,13 +792,23 @@ impl Archiver {
             if is_virtual_file_system(self.fs_magic) {
                 skip_contents = true;
             } else if let Some(set) = &self.device_set {
-                skip_contents = !set.contains(&stat.st_dev);
+                skip_contents = !set.contains(&pbs_tools::fs::device_number(stat));
             }
         }
 
+        let unchanged = match &self.skip_unchanged_dirs {
+            Some(is_unchanged) => {
+                !skip_contents && is_unchanged(&self.path, pbs_tools::fs::device_number(stat))
+            }
+            None => false,
+        };
+
         let result = if skip_contents {
             log::info!("skipping mount point: {:?}", self.path);
             Ok(())
+        } else if unchanged {
+            log::debug!("skipping unchanged directory: {:?}", self.path);
+            Ok(())
         } else {
             self.archive_dir_contents(&mut encoder, dir, false).await
         };
@@ -693,7 +817,10 @@ impl Archiver {
         self.fs_feature_flags = old_fs_feature_flags;
         self.current_st_dev = old_st_dev;
 
+        // writes the lookup table of the directory
+        let start = self.profile_start();
         encoder.finish().await?;
+        self.profile_record(&self.path, ProfilePhase::Encode, start);
         result
     }
 
@@ -707,23 +834,40 @@ impl Archiver {
     ) -> Result<LinkOffset, Error> {
         let mut file = unsafe { std::fs::File::from_raw_fd(fd.into_raw_fd()) };
         let mut remaining = file_size;
+
+        let start = self.profile_start();
         let mut out = encoder.create_file(metadata, file_name, file_size).await?;
-        while remaining != 0 {
+        self.profile_record(&self.path, ProfilePhase::Encode, start);
+
+        while remaining != 0 && !self.skip_file_contents {
+            let start = self.profile_start();
             let mut got = match file.read(&mut self.file_copy_buffer[..]) {
                 Ok(0) => break,
                 Ok(got) => got,
                 Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
                 Err(err) => bail!(err),
             };
+            self.profile_record(&self.path, ProfilePhase::Read, start);
             if got as u64 > remaining {
                 self.report_file_grew_while_reading()?;
                 got = remaining as usize;
             }
+
+            let start = self.profile_start();
             out.write_all(&self.file_copy_buffer[..got]).await?;
+            self.profile_record(&self.path, ProfilePhase::Encode, start);
             remaining -= got as u64;
         }
+        if let Some(profile) = &self.profile {
+            profile
+                .lock()
+                .unwrap()
+                .record_bytes(&self.path, file_size - remaining);
+        }
         if remaining > 0 {
-            self.report_file_shrunk_while_reading()?;
+            if !self.skip_file_contents {
+                self.report_file_shrunk_while_reading()?;
+            }
             let to_zero = remaining.min(self.file_copy_buffer.len() as u64) as usize;
             vec::clear(&mut self.file_copy_buffer[..to_zero]);
             while remaining != 0 {
@@ -739,12 +883,19 @@ impl Archiver {
     async fn add_symlink<T: SeqWrite + Send>(
         &mut self,
         encoder: &mut Encoder<'_, T>,
-        fd: OwnedFd,
-        file_name: &Path,
+        parent: RawFd,
+        c_file_name: &CStr,
         metadata: &Metadata,
     ) -> Result<(), Error> {
-        let dest = nix::fcntl::readlinkat(fd.as_raw_fd(), &b""[..])?;
+        let file_name: &Path = OsStr::from_bytes(c_file_name.to_bytes()).as_ref();
+
+        let start = self.profile_start();
+        let dest = nix::fcntl::readlinkat(parent, c_file_name)?;
+        self.profile_record(&self.path, ProfilePhase::Read, start);
+
+        let start = self.profile_start();
         encoder.add_symlink(metadata, file_name, dest).await?;
+        self.profile_record(&self.path, ProfilePhase::Encode, start);
         Ok(())
     }
 
@@ -755,27 +906,29 @@ impl Archiver {
         metadata: &Metadata,
         stat: &FileStat,
     ) -> Result<(), Error> {
-        Ok(encoder
+        let start = self.profile_start();
+        encoder
             .add_device(
                 metadata,
                 file_name,
                 pxar::format::Device::from_dev_t(stat.st_rdev),
             )
-            .await?)
+            .await?;
+        self.profile_record(&self.path, ProfilePhase::Encode, start);
+        Ok(())
     }
 }
 
+/// Get the metadata of an entry, `fd` is `None` for entries which could not be opened without
+/// accessing them, see [`platform::O_PATH`].
 fn get_metadata(
-    fd: RawFd,
+    fd: Option<RawFd>,
     stat: &FileStat,
     flags: Flags,
     fs_magic: i64,
     fs_feature_flags: &mut Flags,
     skip_e2big_xattr: bool,
 ) -> Result<Metadata, Error> {
-    // required for some of these
-    let proc_path = Path::new("/proc/self/fd/").join(fd.to_string());
-
     let mut meta = Metadata {
         stat: pxar::Stat {
             mode: u64::from(stat.st_mode),
@@ -787,20 +940,58 @@ fn get_metadata(
         ..Default::default()
     };
 
+    if let Some(fd) = fd {
+        get_linux_metadata(
+            &mut meta,
+            fd,
+            flags,
+            fs_magic,
+            fs_feature_flags,
+            skip_e2big_xattr,
+        )?;
+    }
+    Ok(meta)
+}
+
+/// Read the xattrs, file capabilities, ACLs, chattr and FAT flags and quota project id.
+#[cfg(target_os = "linux")]
+fn get_linux_metadata(
+    meta: &mut Metadata,
+    fd: RawFd,
+    flags: Flags,
+    fs_magic: i64,
+    fs_feature_flags: &mut Flags,
+    skip_e2big_xattr: bool,
+) -> Result<(), Error> {
+    // required for some of these
+    let proc_path = Path::new("/proc/self/fd/").join(fd.to_string());
+
     get_xattr_fcaps_acl(
-        &mut meta,
+        meta,
         fd,
         &proc_path,
         flags,
         fs_feature_flags,
         skip_e2big_xattr,
     )?;
-    get_chattr(&mut meta, fd)?;
-    get_fat_attr(&mut meta, fd, fs_magic)?;
-    get_quota_project_id(&mut meta, fd, flags, fs_magic)?;
-    Ok(meta)
+    get_chattr(meta, fd)?;
+    get_fat_attr(meta, fd, fs_magic)?;
+    get_quota_project_id(meta, fd, flags, fs_magic)
 }
 
+#[cfg(not(target_os = "linux"))]
+fn get_linux_metadata(
+    _meta: &mut Metadata,
+    _fd: RawFd,
+    _flags: Flags,
+    _fs_magic: i64,
+    _fs_feature_flags: &mut Flags,
+    _skip_e2big_xattr: bool,
+) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
 fn get_fcaps(
     meta: &mut Metadata,
     fd: RawFd,
@@ -826,6 +1017,7 @@ fn get_fcaps(
     }
 }
 
+#[cfg(target_os = "linux")]
 fn get_xattr_fcaps_acl(
     meta: &mut Metadata,
     fd: RawFd,
@@ -867,6 +1059,11 @@ fn get_xattr_fcaps_acl(
             continue;
         }
 
+        if flags.contains(Flags::WITH_FOREIGN_ATTRS) && is_foreign_xattr(attr) {
+            // queried by name below
+            continue;
+        }
+
         if !xattr::is_valid_xattr_name(attr) {
             continue;
         }
@@ -892,9 +1089,33 @@ fn get_xattr_fcaps_acl(
         }
     }
 
+    if flags.contains(Flags::WITH_FOREIGN_ATTRS) {
+        get_foreign_xattrs(meta, fd)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn get_foreign_xattrs(meta: &mut Metadata, fd: RawFd) -> Result<(), Error> {
+    for name in FOREIGN_XATTRS {
+        let c_name = CString::new(*name).unwrap();
+        match xattr::fgetxattr(fd, &c_name) {
+            Ok(data) => meta
+                .xattrs
+                .push(pxar::format::XAttr::new(c_name.to_bytes(), data)),
+            // not present, not supported or not readable by us - nothing to preserve
+            Err(Errno::ENODATA | Errno::EOPNOTSUPP | Errno::EACCES | Errno::EPERM) => (),
+            Err(Errno::EBADF) => (), // symlinks
+            Err(err) => {
+                return Err(err).context(format!("error reading foreign attribute {name:?}"))
+            }
+        }
+    }
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
 fn get_chattr(metadata: &mut Metadata, fd: RawFd) -> Result<(), Error> {
     let mut attr: libc::c_long = 0;
 
@@ -911,6 +1132,7 @@ fn get_chattr(metadata: &mut Metadata, fd: RawFd) -> Result<(), Error> {
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
 fn get_fat_attr(metadata: &mut Metadata, fd: RawFd, fs_magic: i64) -> Result<(), Error> {
     use proxmox_sys::linux::magic::*;
 
@@ -934,6 +1156,7 @@ fn get_fat_attr(metadata: &mut Metadata, fd: RawFd, fs_magic: i64) -> Result<(),
 }
 
 /// Read the quota project id for an inode, supported on ext4/XFS/FUSE/ZFS filesystems
+#[cfg(target_os = "linux")]
 fn get_quota_project_id(
     metadata: &mut Metadata,
     fd: RawFd,
@@ -975,6 +1198,7 @@ fn get_quota_project_id(
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
 fn get_acl(
     metadata: &mut Metadata,
     proc_path: &Path,
@@ -998,6 +1222,7 @@ fn get_acl(
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
 fn get_acl_do(
     metadata: &mut Metadata,
     proc_path: &Path,
@@ -1024,6 +1249,7 @@ fn get_acl_do(
     process_acl(metadata, acl, acl_type)
 }
 
+#[cfg(target_os = "linux")]
 fn process_acl(
     metadata: &mut Metadata,
     acl: acl::ACL,
@@ -1108,6 +1334,43 @@ fn process_acl(
     Ok(())
 }
 
+/// Parse a line of the `.pxarexclude` file of the directory `dir_path`, which is relative to the
+/// archive root.
+///
+/// Returns `None` for empty lines and comments.
+pub fn parse_pxarexclude_line(dir_path: &[u8], line: &[u8]) -> Result<Option<MatchEntry>, Error> {
+    let line = strip_ascii_whitespace(line);
+
+    if line.is_empty() || line[0] == b'#' {
+        return Ok(None);
+    }
+
+    let mut buf;
+    let (line, mode, anchored) = if line[0] == b'/' {
+        buf = Vec::with_capacity(dir_path.len() + 1 + line.len());
+        buf.extend(dir_path);
+        buf.extend(line);
+        (&buf[..], MatchType::Exclude, true)
+    } else if line.starts_with(b"!/") {
+        // inverted case with absolute path
+        buf = Vec::with_capacity(dir_path.len() + line.len());
+        buf.extend(dir_path);
+        buf.extend(&line[1..]); // without the '!'
+        (&buf[..], MatchType::Include, true)
+    } else if line.starts_with(b"!") {
+        (&line[1..], MatchType::Include, false)
+    } else {
+        (line, MatchType::Exclude, false)
+    };
+
+    let pattern = MatchEntry::parse_pattern(line, PatternFlag::PATH_NAME, mode)?;
+    if anchored {
+        Ok(Some(pattern.add_flags(MatchFlag::ANCHORED)))
+    } else {
+        Ok(Some(pattern))
+    }
+}
+
 /// Note that our pattern lists are "positive". `MatchType::Include` means the file is included.
 /// Since we are generating an *exclude* list, we need to invert this, so includes get a `'!'`
 /// prefix.
@@ -28,7 +28,7 @@ use proxmox_compression::zip::{ZipEncoder, ZipEntry};
 
 use crate::pxar::dir_stack::PxarDirStack;
 use crate::pxar::metadata;
-use crate::pxar::Flags;
+use crate::pxar::{Flags, SpecialFilePolicy};
 
 pub struct PxarExtractOptions<'a> {
     pub match_list: &'a [MatchEntry],
@@ -36,6 +36,10 @@ pub struct PxarExtractOptions<'a> {
     pub allow_existing_dirs: bool,
     pub overwrite_flags: OverwriteFlags,
     pub on_error: Option<ErrorHandler>,
+    /// Whether named pipes are restored, in addition to [`Flags::WITH_FIFOS`]
+    pub fifos: SpecialFilePolicy,
+    /// Whether unix sockets are restored, in addition to [`Flags::WITH_SOCKETS`]
+    pub sockets: SpecialFilePolicy,
 }
 
 bitflags! {
@@ -89,6 +93,8 @@ where
     extractor: Extractor,
     match_list: &'a [MatchEntry],
     state: ExtractorIterState,
+    fifos: SpecialFilePolicy,
+    sockets: SpecialFilePolicy,
 }
 
 impl ExtractorIterState {
@@ -117,10 +123,17 @@ where
     fn new(
         mut decoder: pxar::decoder::Decoder<T>,
         destination: &Path,
-        feature_flags: Flags,
+        mut feature_flags: Flags,
         callback: F,
         options: PxarExtractOptions<'a>,
     ) -> Result<Self, Error> {
+        if options.fifos != SpecialFilePolicy::Include {
+            feature_flags.remove(Flags::WITH_FIFOS);
+        }
+        if options.sockets != SpecialFilePolicy::Include {
+            feature_flags.remove(Flags::WITH_SOCKETS);
+        }
+
         // we use this to keep track of our directory-traversal
         decoder.enable_goodbye_entries(true);
 
@@ -168,6 +181,8 @@ where
             extractor,
             match_list: options.match_list,
             state,
+            fifos: options.fifos,
+            sockets: options.sockets,
         })
     }
 
@@ -175,6 +190,12 @@ where
     fn callback(&mut self, path: &Path) {
         (self.callback)(path)
     }
+
+    fn report_skipped(path: &Path, kind: &str, policy: SpecialFilePolicy) {
+        if policy == SpecialFilePolicy::Warn {
+            log::warn!("warning: skipping {kind} {path:?}");
+        }
+    }
 }
 
 impl<'a, T, F> Iterator for ExtractorIter<'a, T, F>
@@ -340,6 +361,7 @@ where
                         .extract_special(&file_name, metadata, 0)
                         .context(PxarExtractContext::ExtractFifo)
                 } else {
+                    Self::report_skipped(entry.path(), "named pipe", self.fifos);
                     Ok(())
                 }
             }
@@ -350,6 +372,7 @@ where
                         .extract_special(&file_name, metadata, 0)
                         .context(PxarExtractContext::ExtractSocket)
                 } else {
+                    Self::report_skipped(entry.path(), "socket", self.sockets);
                     Ok(())
                 }
             }
@@ -74,6 +74,10 @@ bitflags! {
         /// UNIX OWNERSHIP
         const WITH_OWNER                       = 0x0002_0000_0000;
 
+        /// Preserve Windows metadata of NTFS and SMB mounts (DOS attributes, security
+        /// descriptors, reparse data) exposed via "system.*" xattrs
+        const WITH_FOREIGN_ATTRS               = 0x0004_0000_0000;
+
         /// Support ".pxarexclude" files
         const EXCLUDE_FILE                     = 0x1000_0000_0000_0000;
         /// Exclude submounts
@@ -146,6 +150,7 @@ bitflags! {
             Flags::WITH_SELINUX.bits() |
             Flags::WITH_FCAPS.bits() |
             Flags::WITH_QUOTA_PROJID.bits() |
+            Flags::WITH_FOREIGN_ATTRS.bits() |
             Flags::EXCLUDE_NODUMP.bits() |
             Flags::EXCLUDE_FILE.bits();
     }
@@ -177,6 +182,11 @@ use libc::c_long;
     pub const ATTR_SYS: u32 =         4;
     pub const ATTR_ARCH: u32 =       32;
 
+    // from /usr/include/linux/magic.h and fs/smb/client/
+    pub const NTFS3_SUPER_MAGIC: i64 = 0x7366_746e;
+    pub const CIFS_SUPER_MAGIC: i64 =  0xff53_4d42;
+    pub const SMB2_SUPER_MAGIC: i64 =  0xfe53_4d42;
+
     pub(crate) const INITIAL_FS_FLAGS: c_long =
         FS_NOATIME_FL
         | FS_COMPR_FL
@@ -366,6 +376,14 @@ impl Flags {
                     | Flags::WITH_ACL
                     | Flags::WITH_SELINUX
             }
+            NTFS3_SUPER_MAGIC | CIFS_SUPER_MAGIC | SMB2_SUPER_MAGIC => {
+                Flags::WITH_2SEC_TIME
+                    | Flags::WITH_READ_ONLY
+                    | Flags::WITH_PERMISSIONS
+                    | Flags::WITH_SYMLINKS
+                    | Flags::WITH_XATTRS
+                    | Flags::WITH_FOREIGN_ATTRS
+            }
             // FUSE mounts are special as the supported feature set
             // is not clear a priori.
             FUSE_SUPER_MAGIC => Flags::WITH_FUSE,
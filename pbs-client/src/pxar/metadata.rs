@@ -1,21 +1,57 @@
-use std::ffi::{CStr, CString};
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::ffi::CStr;
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
 use std::path::Path;
 
-use anyhow::{anyhow, bail, Context, Error};
+#[cfg(target_os = "linux")]
+use anyhow::bail;
+use anyhow::{anyhow, Context, Error};
 use nix::errno::Errno;
+#[cfg(target_os = "linux")]
 use nix::fcntl::OFlag;
+#[cfg(target_os = "linux")]
 use nix::sys::stat::Mode;
 
 use pxar::Metadata;
 
 use proxmox_sys::c_result;
 use proxmox_sys::error::SysError;
+#[cfg(target_os = "linux")]
 use proxmox_sys::fs::{self, acl, xattr};
 
 use crate::pxar::tools::perms_from_metadata;
 use crate::pxar::Flags;
 
+/// Extended attributes holding Windows metadata on NTFS (ntfs3) and SMB mounts.
+///
+/// They are usually not listed by `listxattr`, so they are queried by name. Their values are
+/// stored opaquely and only make sense on the same filesystem type.
+#[cfg(target_os = "linux")]
+pub(crate) const FOREIGN_XATTRS: &[&str] = &[
+    "system.ntfs_attrib",
+    "system.ntfs_attrib_be",
+    "system.ntfs_acl",
+    "system.ntfs_security",
+    "system.ntfs_dos_name",
+    "system.ntfs_object_id",
+    "system.ntfs_reparse_data",
+    "system.ntfs_crtime",
+    "system.dos_attrib",
+    "system.cifs_acl",
+    "user.cifs.dosattrib",
+    "user.cifs.creationtime",
+];
+
+#[cfg(target_os = "linux")]
+pub(crate) fn is_foreign_xattr(name: &CStr) -> bool {
+    FOREIGN_XATTRS
+        .iter()
+        .any(|foreign| foreign.as_bytes() == name.to_bytes())
+}
+
 //
 // utility functions
 //
@@ -28,6 +64,7 @@ fn allow_notsupp<E: SysError>(err: E) -> Result<(), E> {
     }
 }
 
+#[cfg(target_os = "linux")]
 fn allow_notsupp_remember<E: SysError>(err: E, not_supp: &mut bool) -> Result<(), E> {
     if err.is_errno(Errno::EOPNOTSUPP) {
         *not_supp = true;
@@ -57,6 +94,7 @@ fn timestamp_to_update_timespec(mtime: &pxar::format::StatxTimestamp) -> [libc::
 // metadata application:
 //
 
+#[cfg(target_os = "linux")]
 pub fn apply_at(
     flags: Flags,
     metadata: &Metadata,
@@ -75,6 +113,7 @@ pub fn apply_at(
     apply(flags, metadata, fd.as_raw_fd(), path_info, on_error)
 }
 
+#[cfg(target_os = "linux")]
 pub fn apply_initial_flags(
     flags: Flags,
     metadata: &Metadata,
@@ -91,6 +130,7 @@ pub fn apply_initial_flags(
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
 pub fn apply(
     flags: Flags,
     metadata: &Metadata,
@@ -104,6 +144,8 @@ pub fn apply(
     let mut skip_xattrs = false;
     apply_xattrs(flags, c_proc_path.as_ptr(), metadata, &mut skip_xattrs)
         .or_else(&mut *on_error)?;
+    apply_foreign_xattrs(flags, fd, c_proc_path.as_ptr(), metadata, path_info)
+        .or_else(&mut *on_error)?;
     add_fcaps(flags, c_proc_path.as_ptr(), metadata, &mut skip_xattrs).or_else(&mut *on_error)?;
     apply_acls(flags, &c_proc_path, metadata, path_info)
         .context("failed to apply acls")
@@ -147,6 +189,7 @@ pub fn apply(
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
 pub fn apply_ownership(
     flags: Flags,
     c_proc_path: *const libc::c_char,
@@ -171,6 +214,7 @@ pub fn apply_ownership(
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
 fn add_fcaps(
     flags: Flags,
     c_proc_path: *const libc::c_char,
@@ -199,6 +243,7 @@ fn add_fcaps(
     .context("failed to apply file capabilities")
 }
 
+#[cfg(target_os = "linux")]
 fn apply_xattrs(
     flags: Flags,
     c_proc_path: *const libc::c_char,
@@ -214,6 +259,10 @@ fn apply_xattrs(
             return Ok(());
         }
 
+        if is_foreign_xattr(xattr.name()) {
+            continue;
+        }
+
         if !xattr::is_valid_xattr_name(xattr.name()) {
             log::info!("skipping invalid xattr named {:?}", xattr.name());
             continue;
@@ -236,6 +285,63 @@ fn apply_xattrs(
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
+fn apply_foreign_xattrs(
+    flags: Flags,
+    fd: RawFd,
+    c_proc_path: *const libc::c_char,
+    metadata: &Metadata,
+    path_info: &Path,
+) -> Result<(), Error> {
+    if !flags.contains(Flags::WITH_FOREIGN_ATTRS | Flags::WITH_XATTRS) {
+        return Ok(());
+    }
+
+    let mut foreign = metadata
+        .xattrs
+        .iter()
+        .filter(|xattr| is_foreign_xattr(xattr.name()))
+        .peekable();
+    if foreign.peek().is_none() {
+        return Ok(());
+    }
+
+    // only restore them to the same kind of filesystem they were taken from
+    let fs_magic = crate::pxar::create::detect_fs_type(fd)?;
+    if !Flags::from_magic(fs_magic).contains(Flags::WITH_FOREIGN_ATTRS) {
+        return Ok(());
+    }
+
+    for xattr in foreign {
+        let result = c_result!(unsafe {
+            libc::setxattr(
+                c_proc_path,
+                xattr.name().as_ptr() as *const libc::c_char,
+                xattr.value().as_ptr() as *const libc::c_void,
+                xattr.value().len(),
+                0,
+            )
+        });
+        match result {
+            Ok(_) => (),
+            Err(Errno::EOPNOTSUPP) => (),
+            // e.g. security descriptors, which usually require administrative privileges
+            Err(err @ (Errno::EPERM | Errno::EACCES)) => log::warn!(
+                "Warning: {path_info:?}: unable to apply foreign attribute {:?} - {err}",
+                xattr.name()
+            ),
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("failed to apply foreign attribute {:?}", xattr.name())
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
 fn apply_acls(
     flags: Flags,
     c_proc_path: &CStr,
@@ -332,6 +438,7 @@ fn apply_acls(
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
 fn apply_quota_project_id(flags: Flags, fd: RawFd, metadata: &Metadata) -> Result<(), Error> {
     if !flags.contains(Flags::WITH_QUOTA_PROJID) {
         return Ok(());
@@ -356,6 +463,7 @@ fn apply_quota_project_id(flags: Flags, fd: RawFd, metadata: &Metadata) -> Resul
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
 pub(crate) fn errno_is_unsupported(errno: Errno) -> bool {
     matches!(
         errno,
@@ -363,6 +471,7 @@ pub(crate) fn errno_is_unsupported(errno: Errno) -> bool {
     )
 }
 
+#[cfg(target_os = "linux")]
 fn apply_chattr(fd: RawFd, chattr: libc::c_long, mask: libc::c_long) -> Result<(), Error> {
     if chattr == 0 {
         return Ok(());
@@ -390,6 +499,7 @@ fn apply_chattr(fd: RawFd, chattr: libc::c_long, mask: libc::c_long) -> Result<(
     }
 }
 
+#[cfg(target_os = "linux")]
 fn apply_flags(flags: Flags, fd: RawFd, entry_flags: u64) -> Result<(), Error> {
     let entry_flags = Flags::from_bits_truncate(entry_flags);
 
@@ -406,3 +516,122 @@ fn apply_flags(flags: Flags, fd: RawFd, entry_flags: u64) -> Result<(), Error> {
 
     Ok(())
 }
+
+//
+// metadata application without Linux specific attributes:
+//
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply_at(
+    flags: Flags,
+    metadata: &Metadata,
+    parent: RawFd,
+    file_name: &CStr,
+    path_info: &Path,
+    on_error: &mut (dyn FnMut(Error) -> Result<(), Error> + Send),
+) -> Result<(), Error> {
+    // there is no O_PATH, so change the entry relative to its parent directory
+    if flags.contains(Flags::WITH_OWNER) {
+        c_result!(unsafe {
+            libc::fchownat(
+                parent,
+                file_name.as_ptr(),
+                metadata.stat.uid,
+                metadata.stat.gid,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })
+        .map(drop)
+        .or_else(allow_notsupp)
+        .context("failed to set ownership")
+        .or_else(&mut *on_error)?;
+    }
+
+    if !metadata.is_symlink() && flags.contains(Flags::WITH_PERMISSIONS) {
+        c_result!(unsafe {
+            libc::fchmodat(
+                parent,
+                file_name.as_ptr(),
+                perms_from_metadata(metadata)?.bits(),
+                0,
+            )
+        })
+        .map(drop)
+        .or_else(allow_notsupp)
+        .context("failed to change file mode")
+        .or_else(&mut *on_error)?;
+    }
+
+    let res = c_result!(unsafe {
+        libc::utimensat(
+            parent,
+            file_name.as_ptr(),
+            timestamp_to_update_timespec(&metadata.stat.mtime).as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    });
+    match res {
+        Ok(_) => (),
+        Err(ref err) if err.is_errno(Errno::EOPNOTSUPP) => (),
+        Err(err) => {
+            on_error(anyhow!(err).context(format!(
+                "failed to restore mtime attribute on {path_info:?}"
+            )))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply_initial_flags(
+    _flags: Flags,
+    _metadata: &Metadata,
+    _fd: RawFd,
+    _on_error: &mut (dyn FnMut(Error) -> Result<(), Error> + Send),
+) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply(
+    flags: Flags,
+    metadata: &Metadata,
+    fd: RawFd,
+    path_info: &Path,
+    on_error: &mut (dyn FnMut(Error) -> Result<(), Error> + Send),
+) -> Result<(), Error> {
+    if flags.contains(Flags::WITH_OWNER) {
+        c_result!(unsafe { libc::fchown(fd, metadata.stat.uid, metadata.stat.gid) })
+            .map(drop)
+            .or_else(allow_notsupp)
+            .context("failed to set ownership")
+            .or_else(&mut *on_error)?;
+    }
+
+    if flags.contains(Flags::WITH_PERMISSIONS) {
+        c_result!(unsafe { libc::fchmod(fd, perms_from_metadata(metadata)?.bits()) })
+            .map(drop)
+            .or_else(allow_notsupp)
+            .context("failed to change file mode")
+            .or_else(&mut *on_error)?;
+    }
+
+    let res = c_result!(unsafe {
+        libc::futimens(
+            fd,
+            timestamp_to_update_timespec(&metadata.stat.mtime).as_ptr(),
+        )
+    });
+    match res {
+        Ok(_) => (),
+        Err(ref err) if err.is_errno(Errno::EOPNOTSUPP) => (),
+        Err(err) => {
+            on_error(anyhow!(err).context(format!(
+                "failed to restore mtime attribute on {path_info:?}"
+            )))?;
+        }
+    }
+
+    Ok(())
+}
@@ -51,20 +51,26 @@ pub(crate) mod create;
 pub(crate) mod dir_stack;
 pub(crate) mod extract;
 pub(crate) mod metadata;
+pub(crate) mod platform;
+pub(crate) mod profile;
 pub(crate) mod tools;
 
 mod flags;
 pub use flags::Flags;
 
-pub use create::{create_archive, PxarCreateOptions};
+pub use create::{
+    create_archive, parse_pxarexclude_line, PxarCreateOptions, SpecialFilePolicy,
+    UnchangedDirFilter,
+};
 pub use extract::{
     create_tar, create_zip, extract_archive, extract_sub_dir, extract_sub_dir_seq, ErrorHandler,
     OverwriteFlags, PxarExtractContext, PxarExtractOptions,
 };
+pub use profile::{ArchiveProfile, BackupProfile};
 
 /// The format requires to build sorted directory lookup tables in
 /// memory, so we restrict the number of allowed entries to limit
 /// maximum memory usage.
-pub const ENCODER_MAX_ENTRIES: usize = 1024 * 1024;
+pub const ENCODER_MAX_ENTRIES: usize = pbs_api_types::PXAR_ENTRIES_MAX_DEFAULT as usize;
 
 pub use tools::{format_multi_line_entry, format_single_line_entry};
@@ -0,0 +1,23 @@
+//! Platform differences of the archiver
+//!
+//! Only the stat data, file contents and symlink targets are archived and restored on every
+//! unix platform. Extended attributes, ACLs, file capabilities, chattr and FAT flags and quota
+//! project ids are Linux specific and skipped elsewhere.
+
+use nix::fcntl::OFlag;
+
+/// Open files without updating their access time, where supported.
+#[cfg(target_os = "linux")]
+pub(crate) const O_NOATIME: OFlag = OFlag::O_NOATIME;
+#[cfg(not(target_os = "linux"))]
+pub(crate) const O_NOATIME: OFlag = OFlag::empty();
+
+/// Open flag to get a handle for symlinks, devices, fifos and sockets without following or
+/// accessing them.
+///
+/// Without it such entries are not opened at all, their metadata is taken from the stat data
+/// alone.
+#[cfg(target_os = "linux")]
+pub(crate) const O_PATH: Option<OFlag> = Some(OFlag::O_PATH);
+#[cfg(not(target_os = "linux"))]
+pub(crate) const O_PATH: Option<OFlag> = None;
@@ -0,0 +1,142 @@
+//! Profiling of archive creation
+//!
+//! Accounts the time spent in the phases of archiving an entry to the top-level directory of
+//! the archive containing it, to find out which parts of a source tree make a backup slow.
+
+use std::collections::HashMap;
+use std::path::{Component, Path};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+/// Phase of archiving an entry.
+#[derive(Clone, Copy)]
+pub(crate) enum ProfilePhase {
+    /// `stat` and `open` calls on the entry.
+    Stat,
+    /// Reading extended attributes, ACLs, file attributes and capabilities.
+    Metadata,
+    /// Reading the contents of regular files.
+    Read,
+    /// Encoding the entry, including waiting for the consumer of the archive, for example the
+    /// chunker and upload.
+    Encode,
+}
+
+#[derive(Default)]
+struct DirectoryProfile {
+    entries: u64,
+    bytes: u64,
+    phases: [Duration; 4],
+}
+
+impl DirectoryProfile {
+    fn total(&self) -> Duration {
+        self.phases.iter().sum()
+    }
+
+    fn to_value(&self) -> Value {
+        let [stat, metadata, read, encode] = self.phases.map(|phase| phase.as_secs_f64());
+        json!({
+            "entries": self.entries,
+            "bytes": self.bytes,
+            "stat": stat,
+            "metadata": metadata,
+            "read": read,
+            "encode": encode,
+        })
+    }
+}
+
+/// Time spent in the phases of creating an archive, per top-level directory.
+///
+/// Entries directly in the archive root, including the top-level directories themselves, are
+/// accounted to `/`.
+#[derive(Default)]
+pub struct ArchiveProfile {
+    directories: HashMap<String, DirectoryProfile>,
+    duration: Duration,
+}
+
+impl ArchiveProfile {
+    fn directory(&mut self, path: &Path) -> &mut DirectoryProfile {
+        let mut components = path
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)));
+        let key = match (components.next(), components.next()) {
+            (Some(top), Some(_)) => format!("/{}", top.as_os_str().to_string_lossy()),
+            _ => "/".to_string(),
+        };
+        self.directories.entry(key).or_default()
+    }
+
+    pub(crate) fn record(&mut self, path: &Path, phase: ProfilePhase, duration: Duration) {
+        self.directory(path).phases[phase as usize] += duration;
+    }
+
+    pub(crate) fn record_entry(&mut self, path: &Path) {
+        self.directory(path).entries += 1;
+    }
+
+    pub(crate) fn record_bytes(&mut self, path: &Path, bytes: u64) {
+        self.directory(path).bytes += bytes;
+    }
+
+    pub(crate) fn set_duration(&mut self, duration: Duration) {
+        self.duration = duration;
+    }
+
+    /// The profile as JSON object, with the directories sorted by the time spent in them,
+    /// slowest first.
+    pub fn to_value(&self) -> Value {
+        let mut directories: Vec<_> = self.directories.iter().collect();
+        directories.sort_unstable_by(|(_, a), (_, b)| b.total().cmp(&a.total()));
+
+        let mut total = DirectoryProfile::default();
+        let directories: Vec<Value> = directories
+            .into_iter()
+            .map(|(path, profile)| {
+                total.entries += profile.entries;
+                total.bytes += profile.bytes;
+                for (sum, phase) in total.phases.iter_mut().zip(profile.phases) {
+                    *sum += phase;
+                }
+
+                let mut value = profile.to_value();
+                value["path"] = path.as_str().into();
+                value
+            })
+            .collect();
+
+        let mut value = total.to_value();
+        value["seconds"] = self.duration.as_secs_f64().into();
+        value["directories"] = directories.into();
+        value
+    }
+}
+
+/// Profiles of the archives of a backup.
+#[derive(Default)]
+pub struct BackupProfile {
+    archives: Vec<(String, Arc<Mutex<ArchiveProfile>>)>,
+}
+
+impl BackupProfile {
+    /// Start the profile of the archive `name`, to be passed to the archiver via
+    /// [`PxarCreateOptions::profile`](crate::pxar::PxarCreateOptions::profile).
+    pub fn add_archive(&mut self, name: &str) -> Arc<Mutex<ArchiveProfile>> {
+        let profile = Arc::new(Mutex::new(ArchiveProfile::default()));
+        self.archives.push((name.to_string(), Arc::clone(&profile)));
+        profile
+    }
+
+    /// The archive profiles as JSON object, by archive name.
+    pub fn to_value(&self) -> Value {
+        let mut value = json!({});
+        for (name, profile) in &self.archives {
+            value[name] = profile.lock().unwrap().to_value();
+        }
+        value
+    }
+}
@@ -0,0 +1,310 @@
+//! High level restore interface
+//!
+//! [`RestoreSession`] wraps a [`BackupReader`] together with the (verified) manifest of the
+//! snapshot, and provides helpers to restore the different archive types.
+
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use futures::stream::{self, StreamExt};
+
+use pbs_api_types::{BackupDir, BackupNamespace, CryptMode};
+use pbs_datastore::data_blob_reader::DataBlobReader;
+use pbs_datastore::dynamic_index::BufferedDynamicReader;
+use pbs_datastore::fixed_index::FixedIndexReader;
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::manifest::BackupManifest;
+use pbs_datastore::read_chunk::AsyncReadChunk;
+use pbs_tools::crypt_config::CryptConfig;
+
+use crate::pxar::{Flags, PxarExtractOptions};
+use crate::{BackupReader, HttpClient, RemoteChunkReader};
+
+/// A reader session on a single snapshot, created via [`RestoreSession::start`].
+pub struct RestoreSession {
+    reader: Arc<BackupReader>,
+    crypt_config: Option<Arc<CryptConfig>>,
+    manifest: BackupManifest,
+    raw_manifest: Vec<u8>,
+}
+
+impl RestoreSession {
+    /// Open `snapshot` on datastore `store` for reading and download its manifest.
+    ///
+    /// The manifest signature is verified if a crypt config is passed, use
+    /// [`RestoreSession::check_fingerprint`] to also make sure the snapshot was created with the
+    /// same key.
+    pub async fn start(
+        client: &HttpClient,
+        store: &str,
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
+        crypt_config: Option<Arc<CryptConfig>>,
+        debug: bool,
+    ) -> Result<Self, Error> {
+        let reader =
+            BackupReader::start(client, crypt_config.clone(), store, ns, snapshot, debug).await?;
+
+        let (manifest, raw_manifest) = reader.download_manifest().await?;
+
+        Ok(Self {
+            reader,
+            crypt_config,
+            manifest,
+            raw_manifest,
+        })
+    }
+
+    /// The underlying protocol reader, for low level operations.
+    pub fn reader(&self) -> &Arc<BackupReader> {
+        &self.reader
+    }
+
+    /// The snapshot's manifest.
+    pub fn manifest(&self) -> &BackupManifest {
+        &self.manifest
+    }
+
+    /// The raw (decoded) manifest data, as stored on the server.
+    pub fn raw_manifest(&self) -> &[u8] {
+        &self.raw_manifest
+    }
+
+    /// Check that the manifest was created with the key of our crypt config (if any).
+    pub fn check_fingerprint(&self) -> Result<(), Error> {
+        self.manifest
+            .check_fingerprint(self.crypt_config.as_deref())
+    }
+
+    fn chunk_reader(
+        &self,
+        archive_name: &str,
+        index: &dyn IndexFile,
+    ) -> Result<RemoteChunkReader, Error> {
+        let file_info = self.manifest.lookup_file_info(archive_name)?;
+        Ok(RemoteChunkReader::new(
+            self.reader.clone(),
+            self.crypt_config.clone(),
+            file_info.chunk_crypt_mode(),
+            index.find_most_used_chunks(8),
+        ))
+    }
+
+    /// Download and decode a `.blob` archive.
+    pub async fn blob_reader(
+        &self,
+        archive_name: &str,
+    ) -> Result<DataBlobReader<'_, std::fs::File>, Error> {
+        self.reader
+            .download_blob(&self.manifest, archive_name)
+            .await
+    }
+
+    /// Open a `.didx` archive (e.g. pxar) as buffered, seekable reader.
+    pub async fn dynamic_reader(
+        &self,
+        archive_name: &str,
+    ) -> Result<BufferedDynamicReader<RemoteChunkReader>, Error> {
+        let index = self
+            .reader
+            .download_dynamic_index(&self.manifest, archive_name)
+            .await?;
+        let chunk_reader = self.chunk_reader(archive_name, &index)?;
+        Ok(BufferedDynamicReader::new(index, chunk_reader))
+    }
+
+    /// Extract a pxar archive into the `target` directory.
+    pub async fn restore_pxar(
+        &self,
+        archive_name: &str,
+        target: &Path,
+        feature_flags: Flags,
+        options: PxarExtractOptions<'_>,
+    ) -> Result<(), Error> {
+        let reader = self.dynamic_reader(archive_name).await?;
+
+        crate::pxar::extract_archive(
+            pxar::decoder::Decoder::from_std(reader)?,
+            target,
+            feature_flags,
+            |path| {
+                log::debug!("{:?}", path);
+            },
+            options,
+        )
+        .map_err(|err| format_err!("error extracting archive - {:#}", err))
+    }
+
+    /// Restore a `.fidx` image archive into `writer`, see [`dump_image`] for `read_ahead`.
+    pub async fn restore_image<W: Write>(
+        &self,
+        archive_name: &str,
+        writer: W,
+        read_ahead: usize,
+    ) -> Result<(), Error> {
+        let index = self
+            .reader
+            .download_fixed_index(&self.manifest, archive_name)
+            .await?;
+        let file_info = self.manifest.lookup_file_info(archive_name)?;
+        let zero_digest = self
+            .reader
+            .zero_chunk_digest(&self.manifest, archive_name, &index)?;
+
+        dump_image(
+            self.reader.clone(),
+            self.crypt_config.clone(),
+            file_info.chunk_crypt_mode(),
+            index,
+            zero_digest,
+            writer,
+            read_ahead,
+        )
+        .await
+    }
+
+    /// Restore only the byte `ranges` of a `.fidx` image archive into `writer`, one after the
+    /// other, e.g. to recover a single partition. Only the chunks overlapping the ranges are
+    /// downloaded, see [`dump_image`] for `read_ahead`.
+    ///
+    /// Returns the number of bytes written.
+    pub async fn restore_image_ranges<W: Write>(
+        &self,
+        archive_name: &str,
+        ranges: &[Range<u64>],
+        mut writer: W,
+        read_ahead: usize,
+    ) -> Result<u64, Error> {
+        let index = self
+            .reader
+            .download_fixed_index(&self.manifest, archive_name)
+            .await?;
+        let zero_digest = self
+            .reader
+            .zero_chunk_digest(&self.manifest, archive_name, &index)?;
+        let chunk_reader = self.chunk_reader(archive_name, &index)?;
+
+        // the parts of the chunks to write, as chunk position and range within the chunk
+        let chunk_size = index.chunk_size as u64;
+        let mut parts = Vec::new();
+        for range in ranges {
+            if range.start >= range.end || range.end > index.size {
+                bail!(
+                    "invalid range {}..{} for image of {} bytes",
+                    range.start,
+                    range.end,
+                    index.size
+                );
+            }
+            let mut offset = range.start;
+            while offset < range.end {
+                let pos = offset / chunk_size;
+                let chunk_start = pos * chunk_size;
+                let end = range.end.min(chunk_start + chunk_size);
+                let part = (offset - chunk_start) as usize..(end - chunk_start) as usize;
+                parts.push((pos as usize, part));
+                offset = end;
+            }
+        }
+
+        let mut chunks = stream::iter(parts)
+            .map(|(pos, part)| {
+                let digest = *index.index_digest(pos).unwrap();
+                let chunk_reader = chunk_reader.clone();
+                let handle = tokio::spawn(async move {
+                    if zero_digest == Some(digest) {
+                        return Ok(vec![0u8; part.len()]);
+                    }
+                    let data = chunk_reader.read_chunk(&digest).await?;
+                    match data.get(part) {
+                        Some(data) => Ok(data.to_vec()),
+                        None => bail!("chunk {pos} of the image is too short"),
+                    }
+                });
+                async move { handle.await? }
+            })
+            .buffered(read_ahead + 1);
+
+        let mut bytes = 0;
+        while let Some(data) = chunks.next().await {
+            let data = data?;
+            writer.write_all(&data)?;
+            bytes += data.len() as u64;
+        }
+        writer.flush()?;
+
+        Ok(bytes)
+    }
+}
+
+/// Write the content of a fixed index image to `writer`, chunk by chunk.
+///
+/// While a chunk is written, the next `read_ahead` chunks of the index are already fetched and
+/// decoded in the background, so network and disk IO overlap. Chunks with digest `zero_digest`
+/// are not downloaded, but written as zeroes.
+pub async fn dump_image<W: Write>(
+    client: Arc<BackupReader>,
+    crypt_config: Option<Arc<CryptConfig>>,
+    crypt_mode: CryptMode,
+    index: FixedIndexReader,
+    zero_digest: Option<[u8; 32]>,
+    mut writer: W,
+    read_ahead: usize,
+) -> Result<(), Error> {
+    let most_used = index.find_most_used_chunks(8);
+
+    let chunk_reader = RemoteChunkReader::new(client.clone(), crypt_config, crypt_mode, most_used);
+
+    // Note: we avoid using BufferedFixedReader, because that add an additional buffer/copy
+    // and thus slows down reading. Instead, directly use RemoteChunkReader
+    let mut per = 0;
+    let mut bytes = 0;
+    let start_time = std::time::Instant::now();
+
+    // `buffered` keeps the order, the spawned tasks make progress while we write
+    let mut chunks = stream::iter(0..index.index_count())
+        .map(|pos| {
+            let digest = *index.index_digest(pos).unwrap();
+            let chunk_size = index.chunk_size;
+            let chunk_reader = chunk_reader.clone();
+            let handle = tokio::spawn(async move {
+                if zero_digest == Some(digest) {
+                    return Ok(vec![0u8; chunk_size]);
+                }
+                chunk_reader.read_chunk(&digest).await
+            });
+            async move { handle.await? }
+        })
+        .buffered(read_ahead + 1)
+        .enumerate();
+
+    while let Some((pos, raw_data)) = chunks.next().await {
+        let raw_data = raw_data?;
+        writer.write_all(&raw_data)?;
+        bytes += raw_data.len();
+        let next_per = ((pos + 1) * 100) / index.index_count();
+        if per != next_per {
+            log::debug!(
+                "progress {}% (read {} bytes, duration {} sec)",
+                next_per,
+                bytes,
+                start_time.elapsed().as_secs()
+            );
+            per = next_per;
+        }
+    }
+
+    let end_time = std::time::Instant::now();
+    let elapsed = end_time.duration_since(start_time);
+    log::info!(
+        "restore image complete (bytes={}, duration={:.2}s, speed={:.2}MB/s)",
+        bytes,
+        elapsed.as_secs_f64(),
+        bytes as f64 / (1024.0 * 1024.0 * elapsed.as_secs_f64())
+    );
+
+    Ok(())
+}
@@ -0,0 +1,119 @@
+//! rustls based TLS backend of the [`HttpClient`](crate::HttpClient)
+//!
+//! Used instead of openssl if the `rustls` feature is enabled. Server certificates signed by a CA
+//! trusted by the system are accepted, all others are verified by their fingerprint, including
+//! the pinning of known fingerprints, just like with the openssl backend. HTTP proxies and rate
+//! limits are not supported by this backend.
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, format_err, Error};
+use hyper::client::HttpConnector;
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, ClientConfig, RootCertStore, ServerName};
+
+use proxmox_http::ProxyConfig;
+
+use pbs_api_types::RateLimitConfig;
+
+use super::fingerprints;
+use super::http_client::CertVerification;
+use super::PROXMOX_BACKUP_TCP_KEEPALIVE_TIME;
+
+/// Connector used by the [`HttpClient`](crate::HttpClient).
+pub type HttpsConnector = hyper_rustls::HttpsConnector<HttpConnector>;
+
+struct FingerprintVerifier {
+    webpki: WebPkiVerifier,
+    // not set if certificates are not verified at all
+    verification: Option<CertVerification>,
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verification = match &self.verification {
+            Some(verification) => verification,
+            None => return Ok(ServerCertVerified::assertion()),
+        };
+
+        let result = self.webpki.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        );
+        if result.is_ok() {
+            return result;
+        }
+
+        // not signed by a trusted CA (e.g. self-signed) - check the fingerprint instead
+        let fp_string = fingerprints::der_fingerprint(&end_entity.0);
+        match verification.verify_fingerprint(fp_string) {
+            Ok(fingerprint) => {
+                verification.accept(fingerprint, fingerprints::der_not_after(&end_entity.0));
+                Ok(ServerCertVerified::assertion())
+            }
+            Err(err) => {
+                log::error!("certificate validation failed - {}", err);
+                Err(rustls::Error::General(err.to_string()))
+            }
+        }
+    }
+}
+
+fn native_root_store() -> Result<RootCertStore, Error> {
+    let certs = rustls_native_certs::load_native_certs()
+        .map_err(|err| format_err!("unable to load trusted CA certificates - {}", err))?;
+
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(&certs);
+    Ok(roots)
+}
+
+/// Create the HTTPS connector with rustls. Server certificates are verified with `verification`,
+/// or not at all without it.
+pub(crate) fn connector(
+    mut httpc: HttpConnector,
+    verification: Option<CertVerification>,
+    limit: &RateLimitConfig,
+    proxy_config: Option<ProxyConfig>,
+) -> Result<HttpsConnector, Error> {
+    if proxy_config.is_some() {
+        bail!("HTTP proxies are not supported by the rustls TLS backend");
+    }
+    if limit.rate_in.is_some() || limit.rate_out.is_some() {
+        bail!("rate limits are not supported by the rustls TLS backend");
+    }
+
+    httpc.set_keepalive(Some(Duration::from_secs(
+        PROXMOX_BACKUP_TCP_KEEPALIVE_TIME.into(),
+    )));
+
+    let verifier = FingerprintVerifier {
+        webpki: WebPkiVerifier::new(native_root_store()?, None),
+        verification,
+    };
+
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+
+    // only HTTP/1.1, the backup and reader protocols are upgraded to HTTP/2 explicitly
+    Ok(hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(config)
+        .https_only()
+        .enable_http1()
+        .wrap_connector(httpc))
+}
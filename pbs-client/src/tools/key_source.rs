@@ -11,6 +11,8 @@ use proxmox_sys::linux::tty;
 
 use pbs_api_types::CryptMode;
 
+use super::state_file::check_secret_file_permissions;
+
 pub const DEFAULT_ENCRYPTION_KEY_FILE_NAME: &str = "encryption-key.json";
 pub const DEFAULT_MASTER_PUBKEY_FILE_NAME: &str = "master-public.pem";
 
@@ -32,11 +34,19 @@ pub const MASTER_PUBKEY_FD_SCHEMA: Schema =
         .minimum(0)
         .schema();
 
+pub const SECURE_PERMS_CHECK_SCHEMA: Schema = BooleanSchema::new(
+    "Refuse to run if the encryption key file is readable by its group or others.",
+)
+.default(false)
+.schema();
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum KeySource {
     DefaultKey,
     Fd,
     Path(String),
+    /// Passphrase protected key stored on the server for the backup group
+    Server,
 }
 
 pub fn format_key_source(source: &KeySource, key_type: &str) -> String {
@@ -44,6 +54,7 @@ pub fn format_key_source(source: &KeySource, key_type: &str) -> String {
         KeySource::DefaultKey => format!("Using default {} key..", key_type),
         KeySource::Fd => format!("Using {} key from file descriptor..", key_type),
         KeySource::Path(path) => format!("Using {} key from '{}'..", key_type, path),
+        KeySource::Server => format!("Using {} key stored on the server..", key_type),
     }
 }
 
@@ -74,6 +85,13 @@ impl KeyWithSource {
             key,
         }
     }
+
+    pub fn from_server(key: Vec<u8>) -> Self {
+        Self {
+            source: KeySource::Server,
+            key,
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -112,6 +130,17 @@ fn do_crypto_parameters(param: &Value, keep_keyfd_open: bool) -> Result<CryptoPa
         None => None,
     };
 
+    if param["secure-perms-check"].as_bool().unwrap_or(false) {
+        let keyfile = match (keyfile, key_fd) {
+            (Some(keyfile), _) => Some(PathBuf::from(keyfile)),
+            (None, None) => find_default_encryption_key()?,
+            (None, Some(_)) => None,
+        };
+        if let Some(keyfile) = keyfile {
+            check_secret_file_permissions(keyfile)?;
+        }
+    }
+
     let master_pubkey_file = match param.get("master-pubkey-file") {
         Some(Value::String(keyfile)) => Some(keyfile),
         Some(_) => bail!("bad --master-pubkey-file parameter type"),
@@ -5,6 +5,7 @@ use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::os::unix::io::FromRawFd;
 use std::process::Command;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Context, Error};
 use serde_json::{json, Value};
@@ -20,6 +21,7 @@ use pbs_api_types::{Authid, BackupNamespace, RateLimitConfig, UserWithTokens, BA
 use crate::{BackupRepository, HttpClient, HttpClientOptions};
 
 pub mod key_source;
+pub mod state_file;
 
 const ENV_VAR_PBS_FINGERPRINT: &str = "PBS_FINGERPRINT";
 const ENV_VAR_PBS_PASSWORD: &str = "PBS_PASSWORD";
@@ -165,15 +167,120 @@ fn connect_do(
     HttpClient::new(server, port, auth_id, options)
 }
 
+lazy_static::lazy_static! {
+    static ref SHARED_CLIENTS: Mutex<HashMap<String, Arc<HttpClient>>> =
+        Mutex::new(HashMap::new());
+}
+
+fn shared_client(repo: &BackupRepository, interactive: bool) -> Result<Arc<HttpClient>, Error> {
+    let key = format!(
+        "{} {} {} {} {interactive}",
+        repo.auth_id(),
+        repo.host(),
+        repo.port(),
+        repo.store(),
+    );
+
+    let mut clients = SHARED_CLIENTS.lock().unwrap();
+    if let Some(client) = clients.get(&key) {
+        return Ok(Arc::clone(client));
+    }
+
+    let fingerprint = std::env::var(ENV_VAR_PBS_FINGERPRINT).ok();
+    let password = get_secret_from_env(ENV_VAR_PBS_PASSWORD)?;
+    let options =
+        HttpClientOptions::new_interactive(password, fingerprint).interactive(interactive);
+
+    let client = Arc::new(HttpClient::new(
+        repo.host(),
+        repo.port(),
+        repo.auth_id(),
+        options,
+    )?);
+    clients.insert(key.clone(), Arc::clone(&client));
+
+    // do not hand out a client whose login failed (e.g. wrong password) to later callers
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        let client = Arc::clone(&client);
+        handle.spawn(async move {
+            if client.login().await.is_err() {
+                forget_shared_client(&key, &client);
+            }
+        });
+    }
+
+    Ok(client)
+}
+
+fn forget_shared_client(key: &str, client: &Arc<HttpClient>) {
+    let mut clients = SHARED_CLIENTS.lock().unwrap();
+    if matches!(clients.get(key), Some(shared) if Arc::ptr_eq(shared, client)) {
+        clients.remove(key);
+    }
+}
+
+/// Like `connect`, but returns a client shared by all callers for the same repository within
+/// this process.
+///
+/// Subsequent calls reuse the login and the client's pooled (keep-alive) connections instead of
+/// doing a new TLS handshake and login. Nothing is shared between separate client invocations,
+/// those still use the on-disk ticket cache. A client whose initial login failed is dropped, so
+/// the next call starts over. The rate limit of shared clients is always unlimited.
+pub fn connect_shared(repo: &BackupRepository) -> Result<Arc<HttpClient>, Error> {
+    shared_client(repo, true)
+        .map_err(|err| format_err!("error building client for repository {} - {}", repo, err))
+}
+
+/// Completion results are cached for this many seconds, as shells call the completion helpers
+/// over and over while typing.
+const COMPLETION_CACHE_TTL: i64 = 30;
+
+fn completion_cache_path() -> Option<std::path::PathBuf> {
+    // usually $HOME/.cache/proxmox-backup/completion-cache
+    base_directories()
+        .ok()?
+        .place_cache_file("completion-cache")
+        .ok()
+}
+
+fn completion_cache_lookup(key: &str) -> Option<Value> {
+    let path = completion_cache_path()?;
+    let mut cache = file_get_json(path, None).ok()?;
+    let time = cache[key]["time"].as_i64()?;
+    if proxmox_time::epoch_i64() - time > COMPLETION_CACHE_TTL {
+        return None;
+    }
+    Some(cache[key]["data"].take())
+}
+
+fn completion_cache_store(key: &str, data: &Value) {
+    let path = match completion_cache_path() {
+        Some(path) => path,
+        None => return,
+    };
+    let now = proxmox_time::epoch_i64();
+    let mut cache = file_get_json(&path, None).unwrap_or_else(|_| json!({}));
+    if let Some(map) = cache.as_object_mut() {
+        map.retain(|_, entry| {
+            matches!(entry["time"].as_i64(), Some(time) if now - time <= COMPLETION_CACHE_TTL)
+        });
+        map.insert(key.to_string(), json!({ "time": now, "data": data }));
+    }
+    let _ = state_file::replace_state_file(path, cache.to_string().as_bytes(), false);
+}
+
 /// like get, but simply ignore errors and return Null instead
+///
+/// Successful results are cached on disk for a short time, as this is used for shell
+/// completion.
 pub async fn try_get(repo: &BackupRepository, url: &str) -> Value {
-    let fingerprint = std::env::var(ENV_VAR_PBS_FINGERPRINT).ok();
-    let password = get_secret_from_env(ENV_VAR_PBS_PASSWORD).unwrap_or(None);
+    let cache_key = format!("{}@{}:{} {url}", repo.auth_id(), repo.host(), repo.port());
+    if let Some(data) = completion_cache_lookup(&cache_key) {
+        return data;
+    }
 
     // ticket cache, but no questions asked
-    let options = HttpClientOptions::new_interactive(password, fingerprint).interactive(false);
-
-    let client = match HttpClient::new(repo.host(), repo.port(), repo.auth_id(), options) {
+    let client = match shared_client(repo, false) {
         Ok(v) => v,
         _ => return Value::Null,
     };
@@ -185,6 +292,7 @@ pub async fn try_get(repo: &BackupRepository, url: &str) -> Value {
 
     if let Some(map) = resp.as_object_mut() {
         if let Some(data) = map.remove("data") {
+            completion_cache_store(&cache_key, &data);
             return data;
         }
     }
@@ -400,7 +508,17 @@ pub async fn complete_auth_id_do(param: &HashMap<String, String>) -> Vec<String>
     result
 }
 
-pub fn complete_repository(_arg: &str, _param: &HashMap<String, String>) -> Vec<String> {
+pub fn complete_repository(arg: &str, _param: &HashMap<String, String>) -> Vec<String> {
+    // complete the datastores of an already typed server
+    if let Some((server, _store)) = arg.rsplit_once(':') {
+        if let Ok(repo) = format!("{server}:store").parse::<BackupRepository>() {
+            let stores = proxmox_async::runtime::main(complete_repository_store_do(server, &repo));
+            if !stores.is_empty() {
+                return stores;
+            }
+        }
+    }
+
     let mut result = vec![];
 
     let base = match BaseDirectories::with_prefix("proxmox-backup") {
@@ -425,6 +543,19 @@ pub fn complete_repository(_arg: &str, _param: &HashMap<String, String>) -> Vec<
     result
 }
 
+async fn complete_repository_store_do(server: &str, repo: &BackupRepository) -> Vec<String> {
+    let mut result = Vec::new();
+    let data = try_get(repo, "api2/json/admin/datastore").await;
+    if let Value::Array(list) = data {
+        for item in list {
+            if let Some(store) = item["store"].as_str() {
+                result.push(format!("{server}:{store}"));
+            }
+        }
+    }
+    result
+}
+
 pub fn complete_backup_source(arg: &str, param: &HashMap<String, String>) -> Vec<String> {
     let mut result = vec![];
 
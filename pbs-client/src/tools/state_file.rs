@@ -0,0 +1,182 @@
+//! Creation of client state files
+//!
+//! Keys, tickets, caches and spooled backups written by the client are created through these
+//! helpers, so they all follow the same permission policy. By default, they are only accessible
+//! by the user running the client, regardless of the umask of the process. The policy can be
+//! changed with the `PBS_UMASK` and `PBS_STATE_OWNER` environment variables.
+
+use std::fs::File;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use anyhow::{bail, format_err, Context, Error};
+use nix::sys::stat::Mode;
+use nix::unistd::{Gid, Group, Uid, User};
+
+use proxmox_sys::fs::{create_path, replace_file, CreateOptions};
+
+const ENV_VAR_PBS_UMASK: &str = "PBS_UMASK";
+const ENV_VAR_PBS_STATE_OWNER: &str = "PBS_STATE_OWNER";
+
+const DEFAULT_UMASK: u32 = 0o077;
+
+/// Permissions and ownership of new client state files and directories.
+pub struct StateFilePolicy {
+    umask: Mode,
+    owner: Option<Uid>,
+    group: Option<Gid>,
+}
+
+impl StateFilePolicy {
+    /// Read the policy from the environment.
+    ///
+    /// `PBS_UMASK` is an octal umask applied to all new files and directories, defaulting to
+    /// `077`. `PBS_STATE_OWNER` is `[user][:group]`, by name or numeric ID, and changes the
+    /// ownership of new files and directories.
+    pub fn from_env() -> Result<Self, Error> {
+        let umask = match std::env::var(ENV_VAR_PBS_UMASK) {
+            Ok(umask) => u32::from_str_radix(umask.trim(), 8)
+                .ok()
+                .filter(|umask| *umask <= 0o777)
+                .ok_or_else(|| format_err!("invalid {ENV_VAR_PBS_UMASK} '{umask}'"))?,
+            Err(_) => DEFAULT_UMASK,
+        };
+
+        let (owner, group) = match std::env::var(ENV_VAR_PBS_STATE_OWNER) {
+            Ok(owner) => parse_owner(&owner)
+                .with_context(|| format!("invalid {ENV_VAR_PBS_STATE_OWNER} '{owner}'"))?,
+            Err(_) => (None, None),
+        };
+
+        Ok(Self {
+            umask: Mode::from_bits_truncate(umask),
+            owner,
+            group,
+        })
+    }
+
+    fn options(&self, mode: u32) -> CreateOptions {
+        let mut options = CreateOptions::new().perm(Mode::from_bits_truncate(mode) & !self.umask);
+        if let Some(owner) = self.owner {
+            options = options.owner(owner);
+        }
+        if let Some(group) = self.group {
+            options = options.group(group);
+        }
+        options
+    }
+
+    /// Options for files containing secrets, like keys and tickets. These are never accessible
+    /// by others than the owner, whatever the umask is.
+    pub fn secret_file_options(&self) -> CreateOptions {
+        self.options(0o600)
+    }
+
+    /// Options for other state files, like caches.
+    pub fn file_options(&self) -> CreateOptions {
+        self.options(0o666)
+    }
+
+    /// Options for state directories.
+    pub fn dir_options(&self) -> CreateOptions {
+        self.options(0o777)
+    }
+}
+
+// `[user][:group]`, by name or numeric ID
+fn parse_owner(owner: &str) -> Result<(Option<Uid>, Option<Gid>), Error> {
+    let (user, group) = match owner.split_once(':') {
+        Some((user, group)) => (user, group),
+        None => (owner, ""),
+    };
+
+    let user = match user {
+        "" => None,
+        user => Some(match user.parse() {
+            Ok(uid) => Uid::from_raw(uid),
+            Err(_) => {
+                User::from_name(user)?
+                    .ok_or_else(|| format_err!("no such user '{user}'"))?
+                    .uid
+            }
+        }),
+    };
+
+    let group = match group {
+        "" => None,
+        group => Some(match group.parse() {
+            Ok(gid) => Gid::from_raw(gid),
+            Err(_) => {
+                Group::from_name(group)?
+                    .ok_or_else(|| format_err!("no such group '{group}'"))?
+                    .gid
+            }
+        }),
+    };
+
+    Ok((user, group))
+}
+
+/// Atomically replace the file containing secrets at `path`.
+pub fn replace_secret_file<P: AsRef<Path>>(path: P, data: &[u8]) -> Result<(), Error> {
+    let options = StateFilePolicy::from_env()?.secret_file_options();
+    replace_file(path, data, options, true)
+}
+
+/// Atomically replace the state file at `path`.
+pub fn replace_state_file<P: AsRef<Path>>(path: P, data: &[u8], fsync: bool) -> Result<(), Error> {
+    let options = StateFilePolicy::from_env()?.file_options();
+    replace_file(path, data, options, fsync)
+}
+
+/// Create (or truncate) the state file at `path` for writing.
+pub fn create_state_file<P: AsRef<Path>>(path: P) -> Result<File, Error> {
+    let path = path.as_ref();
+    let options = StateFilePolicy::from_env()?.file_options();
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|err| format_err!("unable to create {path:?} - {err}"))?;
+    options.apply_to(&mut file, path)?;
+
+    Ok(file)
+}
+
+/// Create the state directory at `path`, including missing parents. Returns whether the
+/// directory was created.
+pub fn create_state_dir<P: AsRef<Path>>(path: P) -> Result<bool, Error> {
+    let options = StateFilePolicy::from_env()?.dir_options();
+    create_path(path, Some(options.clone()), Some(options))
+}
+
+/// Fail if the file containing secrets at `path` is readable by its group or others.
+pub fn check_secret_file_permissions<P: AsRef<Path>>(path: P) -> Result<(), Error> {
+    let path = path.as_ref();
+    let stat =
+        nix::sys::stat::stat(path).map_err(|err| format_err!("unable to stat {path:?} - {err}"))?;
+
+    let mode = stat.st_mode & 0o777;
+    if mode & 0o044 != 0 {
+        bail!("{path:?} is readable by group or others (mode {mode:03o}), refusing to use it");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_owner() -> Result<(), Error> {
+    assert_eq!(parse_owner("")?, (None, None));
+    assert_eq!(
+        parse_owner("1000:100")?,
+        (Some(Uid::from_raw(1000)), Some(Gid::from_raw(100)))
+    );
+    assert_eq!(parse_owner("1000")?, (Some(Uid::from_raw(1000)), None));
+    assert_eq!(parse_owner(":100")?, (None, Some(Gid::from_raw(100))));
+    assert_eq!(parse_owner("root:")?, (Some(Uid::from_raw(0)), None));
+    assert!(parse_owner("no-such-user-hopefully").is_err());
+    Ok(())
+}
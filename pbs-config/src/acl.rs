@@ -85,7 +85,16 @@ pub fn check_acl_path(path: &str) -> Result<(), Error> {
             if components_len <= 2 {
                 return Ok(());
             }
-            if components_len > 2 && components_len <= 2 + pbs_api_types::MAX_NAMESPACE_DEPTH {
+            // /datastore/{store}/{ns}*[/@group/{type}/{id}]
+            let ns_len = match components
+                .iter()
+                .position(|c| *c == pbs_api_types::ACL_PATH_GROUP_COMPONENT)
+            {
+                Some(pos) if pos >= 2 && pos + 3 == components_len => pos - 2,
+                Some(_) => bail!("invalid acl path '{}'.", path),
+                None => components_len - 2,
+            };
+            if ns_len <= pbs_api_types::MAX_NAMESPACE_DEPTH {
                 return Ok(());
             }
         }
@@ -1070,4 +1079,49 @@ acl:1:/storage/store1:user1@pbs:DatastoreBackup
 
         Ok(())
     }
+
+    #[test]
+    fn test_restore_role_is_read_only() -> Result<(), Error> {
+        use pbs_api_types::{
+            PRIV_DATASTORE_ALLOCATE, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_MODIFY,
+            PRIV_DATASTORE_PRUNE, PRIV_DATASTORE_READ, PRIV_DATASTORE_VERIFY,
+        };
+
+        let (privs, _) = super::ROLE_NAMES["DatastoreRestore"];
+
+        assert_ne!(privs & PRIV_DATASTORE_READ, 0);
+        assert_eq!(
+            privs
+                & (PRIV_DATASTORE_ALLOCATE
+                    | PRIV_DATASTORE_BACKUP
+                    | PRIV_DATASTORE_MODIFY
+                    | PRIV_DATASTORE_PRUNE
+                    | PRIV_DATASTORE_VERIFY),
+            0
+        );
+
+        let tree = AclTree::from_raw("acl:1:/datastore/store1:helpdesk@pbs:DatastoreRestore\n")?;
+        let helpdesk: Authid = "helpdesk@pbs".parse()?;
+        check_roles(
+            &tree,
+            &helpdesk,
+            "/datastore/store1/ns1",
+            "DatastoreRestore",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_acl_path_group() {
+        // groups in the root namespace and in one of the maximal depth
+        assert!(check_acl_path("/datastore/store1/@group/vm/100").is_ok());
+        assert!(check_acl_path("/datastore/store1/a/b/c/d/e/f/g/@group/vm/100").is_ok());
+        assert!(check_acl_path("/datastore/store1/a/b/c/d/e/f/g/h/@group/vm/100").is_err());
+        assert!(check_acl_path("/datastore/store1/@group/vm/100/foo").is_err());
+        assert!(check_acl_path("/datastore/store1/@group/vm").is_err());
+        assert!(check_acl_path("/datastore/@group/vm/100").is_err());
+        // namespaces are never mistaken for groups
+        assert!(check_acl_path("/datastore/store1/a/b/c/d/e/f/g/vm/100").is_err());
+    }
 }
@@ -0,0 +1,66 @@
+//! Datastore keys used for server-side encryption at rest
+//!
+//! The plain 256 bit keys are stored per datastore name. The file needs to be readable by the
+//! backup user, as the proxy reads and writes chunks.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, format_err, Error};
+use hex::FromHex;
+use serde_json::{from_value, Value};
+
+use crate::{open_backup_lockfile, replace_backup_config, BackupLockGuard};
+
+const LOCK_FILE: &str = pbs_buildcfg::configdir!("/.datastore-keys.lck");
+const CONF_FILE: &str = pbs_buildcfg::configdir!("/datastore-keys.json");
+
+// Get exclusive lock
+fn lock_config() -> Result<BackupLockGuard, Error> {
+    open_backup_lockfile(LOCK_FILE, None, true)
+}
+
+fn read_file() -> Result<HashMap<String, String>, Error> {
+    let json = proxmox_sys::fs::file_get_json(CONF_FILE, Some(Value::Null))?;
+
+    if json == Value::Null {
+        Ok(HashMap::new())
+    } else {
+        // swallow serde error which might contain sensitive data
+        from_value(json).map_err(|_err| format_err!("unable to parse '{}'", CONF_FILE))
+    }
+}
+
+/// Load the at-rest encryption key of datastore `store`, if one was created.
+pub fn load_key(store: &str) -> Result<Option<[u8; 32]>, Error> {
+    match read_file()?.get(store) {
+        Some(key) => Ok(Some(<[u8; 32]>::from_hex(key).map_err(|_err| {
+            format_err!("invalid at-rest encryption key for datastore '{}'", store)
+        })?)),
+        None => Ok(None),
+    }
+}
+
+/// Generate a new random at-rest encryption key for datastore `store`.
+///
+/// Fails if the datastore already has a key, as this would make existing data unreadable.
+pub fn create_key(store: &str) -> Result<[u8; 32], Error> {
+    let _lock = lock_config()?;
+
+    let mut data = read_file()?;
+    if data.contains_key(store) {
+        bail!(
+            "datastore '{}' already has an at-rest encryption key",
+            store
+        );
+    }
+
+    let mut key = [0u8; 32];
+    openssl::rand::rand_bytes(&mut key)?;
+
+    data.insert(store.to_string(), hex::encode(key));
+
+    let json = serde_json::to_vec(&data)?;
+    replace_backup_config(CONF_FILE, &json)?;
+
+    Ok(key)
+}
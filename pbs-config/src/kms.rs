@@ -0,0 +1,70 @@
+//! Key management service configuration
+//!
+//! This configuration module is based on [`SectionConfig`], and provides a type safe interface
+//! to store [`KmsConfig`]. The file may contain access tokens, so it is only readable by root.
+//!
+//! [KmsConfig]: pbs_api_types::KmsConfig
+//! [SectionConfig]: proxmox_section_config::SectionConfig
+
+use std::collections::HashMap;
+
+use anyhow::Error;
+use lazy_static::lazy_static;
+
+use proxmox_schema::*;
+use proxmox_section_config::{SectionConfig, SectionConfigData, SectionConfigPlugin};
+
+use pbs_api_types::{KmsConfig, KMS_ID_SCHEMA};
+
+use crate::{open_backup_lockfile, replace_secret_config, BackupLockGuard};
+
+lazy_static! {
+    /// Static [`SectionConfig`] to access parser/writer functions.
+    pub static ref CONFIG: SectionConfig = init();
+}
+
+fn init() -> SectionConfig {
+    let obj_schema = match KmsConfig::API_SCHEMA {
+        Schema::Object(ref obj_schema) => obj_schema,
+        _ => unreachable!(),
+    };
+
+    let plugin = SectionConfigPlugin::new("kms".to_string(), Some("id".to_string()), obj_schema);
+    let mut config = SectionConfig::new(&KMS_ID_SCHEMA);
+    config.register_plugin(plugin);
+
+    config
+}
+
+/// Configuration file name
+pub const KMS_CFG_FILENAME: &str = "/etc/proxmox-backup/kms.cfg";
+/// Lock file name (used to prevent concurrent access)
+pub const KMS_CFG_LOCKFILE: &str = "/etc/proxmox-backup/.kms.lck";
+
+/// Get exclusive lock
+pub fn lock_config() -> Result<BackupLockGuard, Error> {
+    open_backup_lockfile(KMS_CFG_LOCKFILE, None, true)
+}
+
+/// Read and parse the configuration file
+pub fn config() -> Result<(SectionConfigData, [u8; 32]), Error> {
+    let content = proxmox_sys::fs::file_read_optional_string(KMS_CFG_FILENAME)?.unwrap_or_default();
+
+    let digest = openssl::sha::sha256(content.as_bytes());
+    let data = CONFIG.parse(KMS_CFG_FILENAME, &content)?;
+    Ok((data, digest))
+}
+
+/// Save the configuration file (mode 0600, owned by root)
+pub fn save_config(config: &SectionConfigData) -> Result<(), Error> {
+    let raw = CONFIG.write(KMS_CFG_FILENAME, config)?;
+    replace_secret_config(KMS_CFG_FILENAME, raw.as_bytes())
+}
+
+// shell completion helper
+pub fn complete_kms_id(_arg: &str, _param: &HashMap<String, String>) -> Vec<String> {
+    match config() {
+        Ok((data, _digest)) => data.sections.keys().map(|id| id.to_string()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
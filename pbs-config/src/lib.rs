@@ -2,11 +2,16 @@ pub mod acl;
 mod cached_user_info;
 pub use cached_user_info::CachedUserInfo;
 pub mod datastore;
+pub mod datastore_keys;
 pub mod domains;
 pub mod drive;
+pub mod expected_backup;
+pub mod hook;
+pub mod kms;
 pub mod media_pool;
 pub mod metrics;
 pub mod network;
+pub mod network_share;
 pub mod notifications;
 pub mod prune;
 pub mod remote;
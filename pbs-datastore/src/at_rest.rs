@@ -0,0 +1,125 @@
+//! Server-side encryption of chunk and blob files at rest
+//!
+//! This is independent of the client side (end-to-end) encryption: the server encrypts the
+//! already encoded data blob with a per-datastore key before writing it to disk, and decrypts
+//! it transparently when reading. Files written without a datastore key (or before the key was
+//! configured) are detected by their magic number and returned unchanged.
+//!
+//! File layout:
+//!
+//! | Field     | Size |
+//! |-----------|------|
+//! | magic     | 8    |
+//! | iv        | 16   |
+//! | tag       | 16   |
+//! | data      | ...  |
+
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{bail, format_err, Error};
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+
+/// Magic number of files encrypted at rest.
+// openssl::sha::sha256(b"Proxmox Backup at-rest encrypted v1.0")[0..8]
+pub const AT_REST_MAGIC_1_0: [u8; 8] = [112, 41, 178, 61, 14, 209, 98, 253];
+
+const IV_SIZE: usize = 16;
+const TAG_SIZE: usize = 16;
+
+/// Size overhead of an encrypted file compared to the plain blob.
+pub const AT_REST_OVERHEAD: usize = 8 + IV_SIZE + TAG_SIZE;
+
+/// Encrypts/decrypts files with the datastore key (AES-256-GCM).
+pub struct AtRestCrypt {
+    cipher: Cipher,
+    key: [u8; 32],
+}
+
+impl AtRestCrypt {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: Cipher::aes_256_gcm(),
+            key,
+        }
+    }
+
+    /// Encrypt a raw (already encoded) blob.
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut iv = [0u8; IV_SIZE];
+        openssl::rand::rand_bytes(&mut iv)?;
+        let mut tag = [0u8; TAG_SIZE];
+
+        let enc_data = encrypt_aead(self.cipher, &self.key, Some(&iv), b"", data, &mut tag)?;
+
+        let mut raw = Vec::with_capacity(AT_REST_OVERHEAD + enc_data.len());
+        raw.extend_from_slice(&AT_REST_MAGIC_1_0);
+        raw.extend_from_slice(&iv);
+        raw.extend_from_slice(&tag);
+        raw.extend_from_slice(&enc_data);
+
+        Ok(raw)
+    }
+
+    /// Decrypt data produced by [`encrypt`](Self::encrypt).
+    pub fn decrypt(&self, raw: &[u8]) -> Result<Vec<u8>, Error> {
+        if !is_encrypted_at_rest(raw) {
+            bail!("data is not encrypted at rest");
+        }
+        let iv = &raw[8..(8 + IV_SIZE)];
+        let tag = &raw[(8 + IV_SIZE)..AT_REST_OVERHEAD];
+
+        decrypt_aead(
+            self.cipher,
+            &self.key,
+            Some(iv),
+            b"",
+            &raw[AT_REST_OVERHEAD..],
+            tag,
+        )
+        .map_err(|err| format_err!("at-rest decryption failed - {err}"))
+    }
+}
+
+/// Check if `raw` starts with the at-rest magic number.
+pub fn is_encrypted_at_rest(raw: &[u8]) -> bool {
+    raw.len() >= AT_REST_OVERHEAD && raw[0..8] == AT_REST_MAGIC_1_0
+}
+
+/// Return the plain blob data of a file's content.
+///
+/// Data not encrypted at rest is passed through unchanged.
+pub fn decode_at_rest(crypt: Option<&AtRestCrypt>, raw: Vec<u8>) -> Result<Vec<u8>, Error> {
+    if !is_encrypted_at_rest(&raw) {
+        return Ok(raw);
+    }
+    match crypt {
+        Some(crypt) => crypt.decrypt(&raw),
+        None => bail!("data is encrypted at rest, but no datastore key is available"),
+    }
+}
+
+/// Read a file and return its plain blob data, see [`decode_at_rest`].
+pub fn read_at_rest<P: AsRef<Path>>(
+    crypt: Option<&AtRestCrypt>,
+    path: P,
+) -> Result<Vec<u8>, Error> {
+    let mut raw = Vec::new();
+    std::fs::File::open(path.as_ref())?.read_to_end(&mut raw)?;
+    decode_at_rest(crypt, raw)
+}
+
+#[test]
+fn test_at_rest_roundtrip() {
+    let crypt = AtRestCrypt::new([7u8; 32]);
+    let data = b"some blob data".to_vec();
+
+    let enc = crypt.encrypt(&data).unwrap();
+    assert!(is_encrypted_at_rest(&enc));
+    assert_eq!(enc.len(), data.len() + AT_REST_OVERHEAD);
+    assert_eq!(decode_at_rest(Some(&crypt), enc.clone()).unwrap(), data);
+    assert!(decode_at_rest(None, enc).is_err());
+
+    // legacy files are passed through
+    assert_eq!(decode_at_rest(Some(&crypt), data.clone()).unwrap(), data);
+}
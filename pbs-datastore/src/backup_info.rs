@@ -1,22 +1,41 @@
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
 use std::os::unix::io::RawFd;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use proxmox_sys::fs::{lock_dir_noblock, replace_file, CreateOptions};
+use proxmox_sys::fs::{
+    file_read_optional_string, get_file_type, lock_dir_noblock, replace_file, CreateOptions,
+};
 
 use pbs_api_types::{
-    Authid, BackupNamespace, BackupType, GroupFilter, BACKUP_DATE_REGEX, BACKUP_FILE_REGEX,
+    backup_time_from_string, backup_time_to_string, Authid, BackupContent, BackupNamespace,
+    BackupType, CryptMode, GroupFilter, GroupHealth, SnapshotListItem, SnapshotVerifyState,
+    VerifyState, BACKUP_DATE_REGEX, BACKUP_FILE_REGEX,
 };
 use pbs_config::{open_backup_lockfile, BackupLockGuard};
 
+use crate::at_rest::read_at_rest;
+use crate::chunk_store::fsync_dir;
 use crate::manifest::{
     BackupManifest, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME, MANIFEST_LOCK_NAME,
 };
 use crate::{DataBlob, DataStore};
 
+/// File in the group directory holding the maximum number of snapshots to keep.
+const GROUP_MAX_SNAPSHOTS_FILE_NAME: &str = "max-snapshots";
+
+/// File in the group directory holding the client's passphrase protected encryption key.
+const GROUP_WRAPPED_KEY_FILE_NAME: &str = "wrapped-key.json";
+
+/// File in the group directory recording the last verification outcome of its snapshots.
+const GROUP_VERIFY_STATE_FILE_NAME: &str = "verify-state.json";
+const GROUP_VERIFY_STATE_LOCK_NAME: &str = "verify-state.lck";
+
 #[derive(Default)]
 pub struct BackupGroupDeleteStats {
     // Count of protected snapshots, therefore not removed
@@ -114,34 +133,15 @@ impl BackupGroup {
     }
 
     pub fn list_backups(&self) -> Result<Vec<BackupInfo>, Error> {
-        let mut list = vec![];
-
-        let path = self.full_group_path();
-
-        proxmox_sys::fs::scandir(
-            libc::AT_FDCWD,
-            &path,
-            &BACKUP_DATE_REGEX,
-            |l2_fd, backup_time, file_type| {
-                if file_type != nix::dir::Type::Directory {
-                    return Ok(());
-                }
-
-                let backup_dir = self.backup_dir_with_rfc3339(backup_time)?;
-                let files = list_backup_files(l2_fd, backup_time)?;
-
-                let protected = backup_dir.is_protected();
-
-                list.push(BackupInfo {
-                    backup_dir,
-                    files,
-                    protected,
-                });
+        if let Some(cache) = self.store.listing_cache() {
+            return cache.list_backups(self);
+        }
+        self.iter_backups()?.collect()
+    }
 
-                Ok(())
-            },
-        )?;
-        Ok(list)
+    /// Returns a streaming iterator over the snapshots of this group, including their files.
+    pub fn iter_backups(&self) -> Result<crate::ListBackupInfos, Error> {
+        crate::ListBackupInfos::new(self.clone())
     }
 
     /// Finds the latest backup inside a backup group
@@ -190,7 +190,7 @@ impl BackupGroup {
                     }
                 }
 
-                let timestamp = proxmox_time::parse_rfc3339(backup_time)?;
+                let timestamp = backup_time_from_string(backup_time)?;
                 if let Some(last_timestamp) = last {
                     if timestamp > last_timestamp {
                         last = Some(timestamp);
@@ -250,6 +250,10 @@ impl BackupGroup {
             std::fs::remove_dir_all(&path).map_err(|err| {
                 format_err!("removing group directory {:?} failed - {}", path, err)
             })?;
+            #[cfg(feature = "sqlite-index")]
+            if let Some(index) = self.store.content_index() {
+                index.group_removed(&self.ns, &self.group);
+            }
         }
 
         Ok(delete_stats)
@@ -267,6 +271,248 @@ impl BackupGroup {
         self.store
             .set_owner(&self.ns, self.as_ref(), auth_id, force)
     }
+
+    fn max_snapshots_path(&self) -> PathBuf {
+        let mut path = self.full_group_path();
+        path.push(GROUP_MAX_SNAPSHOTS_FILE_NAME);
+        path
+    }
+
+    /// Returns the maximum number of snapshots kept in this group, if limited.
+    pub fn max_snapshots(&self) -> Result<Option<usize>, Error> {
+        let path = self.max_snapshots_path();
+        match file_read_optional_string(&path)? {
+            Some(value) => {
+                Ok(Some(value.trim().parse().map_err(|err| {
+                    format_err!("unable to parse {:?} - {}", path, err)
+                })?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Set or clear the maximum number of snapshots kept in this group.
+    pub fn set_max_snapshots(&self, max_snapshots: Option<usize>) -> Result<(), Error> {
+        let path = self.max_snapshots_path();
+        match max_snapshots {
+            Some(max) => {
+                let data = format!("{max}\n");
+                replace_file(path, data.as_bytes(), CreateOptions::new(), false)
+            }
+            None => match std::fs::remove_file(&path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    bail!("unable to remove {:?} - {}", path, err)
+                }
+                _ => Ok(()),
+            },
+        }
+    }
+
+    fn wrapped_key_path(&self) -> PathBuf {
+        let mut path = self.full_group_path();
+        path.push(GROUP_WRAPPED_KEY_FILE_NAME);
+        path
+    }
+
+    /// Returns the passphrase protected encryption key stored for this group, if any.
+    ///
+    /// The key is stored as uploaded by the client, the server cannot unlock it.
+    pub fn wrapped_key(&self) -> Result<Option<String>, Error> {
+        file_read_optional_string(self.wrapped_key_path())
+    }
+
+    /// Store or remove the passphrase protected encryption key of this group.
+    pub fn set_wrapped_key(&self, key: Option<&str>) -> Result<(), Error> {
+        let path = self.wrapped_key_path();
+        match key {
+            Some(key) => {
+                let options =
+                    CreateOptions::new().perm(nix::sys::stat::Mode::from_bits_truncate(0o600));
+                replace_file(path, key.as_bytes(), options, true)
+            }
+            None => match std::fs::remove_file(&path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    bail!("unable to remove {:?} - {}", path, err)
+                }
+                _ => Ok(()),
+            },
+        }
+    }
+
+    /// Reads the verification outcomes of the snapshots from their manifests.
+    fn load_verify_states(&self) -> Result<BTreeMap<i64, VerifyState>, Error> {
+        let mut states = BTreeMap::new();
+        for info in self.list_backups()? {
+            if !info.is_finished() {
+                continue;
+            }
+            let manifest = match info.backup_dir.load_manifest() {
+                Ok((manifest, _)) => manifest,
+                Err(_) => continue,
+            };
+            let verify_state = manifest.unprotected["verify_state"].clone();
+            if let Ok(verify_state) = serde_json::from_value::<SnapshotVerifyState>(verify_state) {
+                states.insert(info.backup_dir.backup_time(), verify_state.state);
+            }
+        }
+        Ok(states)
+    }
+
+    fn read_verify_states(&self) -> Result<Option<BTreeMap<i64, VerifyState>>, Error> {
+        let mut path = self.full_group_path();
+        path.push(GROUP_VERIFY_STATE_FILE_NAME);
+        match file_read_optional_string(&path)? {
+            Some(data) => serde_json::from_str(&data)
+                .map(Some)
+                .map_err(|err| format_err!("unable to parse {path:?} - {err}")),
+            None => Ok(None),
+        }
+    }
+
+    fn save_verify_states(&self, states: &BTreeMap<i64, VerifyState>) -> Result<(), Error> {
+        let mut path = self.full_group_path();
+        path.push(GROUP_VERIFY_STATE_FILE_NAME);
+        replace_file(
+            path,
+            &serde_json::to_vec(states)?,
+            CreateOptions::new(),
+            false,
+        )
+    }
+
+    fn lock_verify_states(&self) -> Result<BackupLockGuard, Error> {
+        let mut lock_path = self.full_group_path();
+        lock_path.push(GROUP_VERIFY_STATE_LOCK_NAME);
+        open_backup_lockfile(&lock_path, Some(std::time::Duration::from_secs(5)), true)
+    }
+
+    /// Returns the last verification outcome of the snapshots, by backup time.
+    ///
+    /// If no outcome was recorded for the group yet, the record is seeded from the manifests
+    /// once, so that group listings do not need to read all manifests every time.
+    pub fn verify_states(&self) -> Result<BTreeMap<i64, VerifyState>, Error> {
+        if let Some(states) = self.read_verify_states()? {
+            return Ok(states);
+        }
+
+        let _lock = self.lock_verify_states()?;
+        // a verification might have recorded its outcome in the meantime
+        if let Some(states) = self.read_verify_states()? {
+            return Ok(states);
+        }
+        let states = self.load_verify_states()?;
+        if let Err(err) = self.save_verify_states(&states) {
+            log::warn!(
+                "unable to save verify states of group {} - {err}",
+                self.group
+            );
+        }
+        Ok(states)
+    }
+
+    /// Record the outcome of verifying the snapshot at `backup_time`, `None` if it is not
+    /// verified (anymore).
+    ///
+    /// Outcomes of removed snapshots are dropped from the group record on the way.
+    pub fn record_verify_state(
+        &self,
+        backup_time: i64,
+        state: Option<VerifyState>,
+    ) -> Result<(), Error> {
+        let _lock = self.lock_verify_states()?;
+
+        let mut states = match self.read_verify_states()? {
+            Some(states) => states,
+            None => self.load_verify_states()?,
+        };
+        match state {
+            Some(state) => states.insert(backup_time, state),
+            None => states.remove(&backup_time),
+        };
+
+        let existing: std::collections::HashSet<i64> = self
+            .list_backups()?
+            .iter()
+            .map(|info| info.backup_dir.backup_time())
+            .collect();
+        states.retain(|time, _| existing.contains(time));
+
+        self.save_verify_states(&states)
+    }
+
+    /// Aggregate the verification outcomes of the finished snapshots in `snapshots`.
+    pub fn health(&self, snapshots: &[BackupInfo]) -> Result<GroupHealth, Error> {
+        let states = self.verify_states()?;
+
+        let mut newest = None;
+        let mut any_verified = false;
+        for info in snapshots.iter().filter(|info| info.is_finished()) {
+            let time = info.backup_dir.backup_time();
+            match states.get(&time) {
+                Some(VerifyState::Failed) => return Ok(GroupHealth::Failed),
+                Some(VerifyState::Ok) => any_verified = true,
+                None => {}
+            }
+            if newest.map(|newest| time > newest).unwrap_or(true) {
+                newest = Some(time);
+            }
+        }
+
+        Ok(match newest {
+            Some(newest) if states.contains_key(&newest) => GroupHealth::Ok,
+            _ if any_verified => GroupHealth::Outdated,
+            _ => GroupHealth::None,
+        })
+    }
+
+    /// Remove the oldest unprotected, finished snapshots until at most `max_snapshots` are left.
+    ///
+    /// The latest snapshot and the `keep` snapshot, e.g. the base snapshot locked by the calling
+    /// backup, are always kept, other snapshots which are in use are skipped. Returns the removed
+    /// snapshots.
+    pub fn prune_exceeding_snapshots(
+        &self,
+        max_snapshots: usize,
+        keep: Option<&BackupDir>,
+    ) -> Result<Vec<BackupDir>, Error> {
+        let mut list: Vec<BackupInfo> = self
+            .list_backups()?
+            .into_iter()
+            .filter(|info| info.is_finished())
+            .collect();
+        BackupInfo::sort_list(&mut list, true);
+
+        let mut excess = list.len().saturating_sub(max_snapshots);
+        list.pop();
+        let mut removed = Vec::new();
+
+        for info in list {
+            if excess == 0 {
+                break;
+            }
+            if info.protected {
+                continue;
+            }
+            if keep.map_or(false, |keep| {
+                keep.full_path() == info.backup_dir.full_path()
+            }) {
+                continue;
+            }
+            match info.backup_dir.destroy(false) {
+                Ok(()) => {
+                    removed.push(info.backup_dir);
+                    excess -= 1;
+                }
+                Err(err) => log::warn!(
+                    "unable to remove snapshot {:?} - {}",
+                    info.backup_dir.relative_path(),
+                    err
+                ),
+            }
+        }
+
+        Ok(removed)
+    }
 }
 
 impl AsRef<pbs_api_types::BackupNamespace> for BackupGroup {
@@ -364,7 +610,7 @@ impl BackupDir {
         group: BackupGroup,
         backup_time_string: String,
     ) -> Result<Self, Error> {
-        let backup_time = proxmox_time::parse_rfc3339(&backup_time_string)?;
+        let backup_time = backup_time_from_string(&backup_time_string)?;
         Ok(Self {
             store: group.store,
             ns: group.ns,
@@ -429,9 +675,40 @@ impl BackupDir {
         path.exists()
     }
 
+    /// Marker file of snapshots synced in inventory mode, which lack the data of their archives.
+    pub fn inventory_file(&self) -> PathBuf {
+        let mut path = self.full_path();
+        path.push(".inventory");
+        path
+    }
+
+    /// Returns true if only the manifest and blobs of this snapshot are present locally.
+    pub fn is_inventory_only(&self) -> bool {
+        self.inventory_file().exists()
+    }
+
+    /// Returns where a snapshot synced in inventory mode was synced from, if recorded.
+    pub fn inventory_origin(&self) -> Result<Option<InventoryOrigin>, Error> {
+        let path = self.inventory_file();
+        match file_read_optional_string(&path)? {
+            Some(data) if !data.trim().is_empty() => {
+                Ok(Some(serde_json::from_str(&data).map_err(|err| {
+                    format_err!("unable to parse {path:?} - {err}")
+                })?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Format a backup time as snapshot directory name, see [`backup_time_to_string`].
     pub fn backup_time_to_string(backup_time: i64) -> Result<String, Error> {
         // fixme: can this fail? (avoid unwrap)
-        proxmox_time::epoch_to_rfc3339_utc(backup_time)
+        backup_time_to_string(backup_time)
+    }
+
+    /// Parse a snapshot directory name into a backup time, see [`backup_time_from_string`].
+    pub fn backup_time_from_string(backup_time: &str) -> Result<i64, Error> {
+        backup_time_from_string(backup_time)
     }
 
     /// load a `DataBlob` from this snapshot's backup dir.
@@ -440,8 +717,8 @@ impl BackupDir {
         path.push(filename);
 
         proxmox_lang::try_block!({
-            let mut file = std::fs::File::open(&path)?;
-            DataBlob::load_from_reader(&mut file)
+            let raw_data = read_at_rest(self.store.at_rest_crypt().as_deref(), &path)?;
+            DataBlob::load_from_reader(&mut &raw_data[..])
         })
         .map_err(|err| format_err!("unable to load blob '{:?}' - {}", path, err))
     }
@@ -496,6 +773,11 @@ impl BackupDir {
             let _ = std::fs::remove_file(path); // ignore errors
         }
 
+        #[cfg(feature = "sqlite-index")]
+        if let Some(index) = self.store.content_index() {
+            index.snapshot_removed(&self.ns, &self.dir);
+        }
+
         Ok(())
     }
 
@@ -524,8 +806,37 @@ impl BackupDir {
         Ok((manifest, raw_size))
     }
 
+    /// Load the manifest and list the files it references, including the manifest itself.
+    pub fn read_backup_index(&self) -> Result<(BackupManifest, Vec<BackupContent>), Error> {
+        let (manifest, index_size) = self.load_manifest()?;
+
+        let mut result = Vec::new();
+        for item in manifest.files() {
+            result.push(BackupContent {
+                filename: item.filename.clone(),
+                crypt_mode: Some(item.crypt_mode),
+                size: Some(item.size),
+                previous_names: manifest.archive_aliases(&item.filename),
+            });
+        }
+
+        result.push(BackupContent {
+            filename: MANIFEST_BLOB_NAME.to_string(),
+            crypt_mode: match manifest.signature {
+                Some(_) => Some(CryptMode::SignOnly),
+                None => Some(CryptMode::None),
+            },
+            size: Some(index_size),
+            previous_names: Vec::new(),
+        });
+
+        Ok((manifest, result))
+    }
+
     /// Update the manifest of the specified snapshot. Never write a manifest directly,
     /// only use this method - anything else may break locking guarantees.
+    ///
+    /// A changed verify state is recorded for the group as well.
     pub fn update_manifest(
         &self,
         update_fn: impl FnOnce(&mut BackupManifest),
@@ -533,21 +844,58 @@ impl BackupDir {
         let _guard = self.lock_manifest()?;
         let (mut manifest, _) = self.load_manifest()?;
 
+        let old_verify_state = manifest.unprotected["verify_state"].clone();
         update_fn(&mut manifest);
+        let verify_state = manifest.unprotected["verify_state"].clone();
 
         let manifest = serde_json::to_value(manifest)?;
         let manifest = serde_json::to_string_pretty(&manifest)?;
         let blob = DataBlob::encode(manifest.as_bytes(), None, true)?;
-        let raw_data = blob.raw_data();
+        let raw_data = self.store.encode_at_rest(blob.raw_data())?;
 
         let mut path = self.full_path();
         path.push(MANIFEST_BLOB_NAME);
 
+        let sync = self.store.sync_metadata();
+
         // atomic replace invalidates flock - no other writes past this point!
-        replace_file(&path, raw_data, CreateOptions::new(), false)?;
+        replace_file(&path, &raw_data, CreateOptions::new(), sync)?;
+
+        if sync {
+            // also persists the renames of the index files and blobs written before
+            fsync_dir(&self.full_path())?;
+        }
+
+        #[cfg(feature = "sqlite-index")]
+        if let Some(index) = self.store.content_index() {
+            index.snapshot_changed(self);
+        }
+
+        if verify_state != old_verify_state {
+            if let Err(err) = self.record_verify_state(&verify_state) {
+                log::warn!(
+                    "unable to record verify state of {:?} in its group - {err}",
+                    self
+                );
+            }
+        }
+
         Ok(())
     }
 
+    /// Record `verify_state`, as found in the manifest, in the verify record of the group.
+    ///
+    /// [`update_manifest`](Self::update_manifest) does this already, this is for manifests which
+    /// are replaced as a whole.
+    pub fn record_verify_state(&self, verify_state: &Value) -> Result<(), Error> {
+        let state = serde_json::from_value::<SnapshotVerifyState>(verify_state.clone())
+            .ok()
+            .map(|verify_state| verify_state.state);
+        self.store
+            .backup_group(self.ns.clone(), self.dir.group.clone())
+            .record_verify_state(self.backup_time(), state)
+    }
+
     /// Cleans up the backup directory by removing any file not mentioned in the manifest.
     pub fn cleanup_unreferenced_files(&self, manifest: &BackupManifest) -> Result<(), Error> {
         let full_path = self.full_path();
@@ -625,6 +973,20 @@ impl From<BackupDir> for pbs_api_types::BackupDir {
     }
 }
 
+/// The source of a snapshot synced in inventory mode, stored in its marker file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InventoryOrigin {
+    /// The remote, `None` if synced from a local datastore.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    /// The datastore on the remote or local source.
+    pub store: String,
+    /// The namespace of the snapshot on the source.
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+}
+
 /// Detailed Backup Information, lists files inside a BackupDir
 #[derive(Clone, Debug)]
 pub struct BackupInfo {
@@ -640,8 +1002,7 @@ impl BackupInfo {
     pub fn new(backup_dir: BackupDir) -> Result<BackupInfo, Error> {
         let path = backup_dir.full_path();
 
-        let files = list_backup_files(libc::AT_FDCWD, &path)?;
-        let protected = backup_dir.is_protected();
+        let (files, protected) = read_snapshot_dir(libc::AT_FDCWD, &path)?;
 
         Ok(BackupInfo {
             backup_dir,
@@ -650,6 +1011,107 @@ impl BackupInfo {
         })
     }
 
+    /// Load the manifest and list all files of the snapshot, including the ones not referenced
+    /// by the manifest.
+    pub fn all_files(&self) -> Result<(BackupManifest, Vec<BackupContent>), Error> {
+        let (manifest, mut files) = self.backup_dir.read_backup_index()?;
+
+        let file_set = files.iter().fold(HashSet::new(), |mut acc, item| {
+            acc.insert(item.filename.clone());
+            acc
+        });
+
+        for file in &self.files {
+            if file_set.contains(file) {
+                continue;
+            }
+            files.push(BackupContent {
+                filename: file.to_string(),
+                size: None,
+                crypt_mode: None,
+                previous_names: Vec::new(),
+            });
+        }
+
+        Ok((manifest, files))
+    }
+
+    /// Summarize the snapshot for snapshot listings.
+    pub fn to_list_item(&self, owner: Option<Authid>) -> SnapshotListItem {
+        let backup = self.backup_dir.dir().clone();
+        let protected = self.protected;
+        let inventory_only = self.backup_dir.is_inventory_only();
+
+        match self.all_files() {
+            Ok((manifest, files)) => {
+                // extract the first line from notes
+                let comment: Option<String> = manifest.unprotected["notes"]
+                    .as_str()
+                    .and_then(|notes| notes.lines().next())
+                    .map(String::from);
+
+                let fingerprint = match manifest.fingerprint() {
+                    Ok(fp) => fp,
+                    Err(err) => {
+                        log::error!("error parsing fingerprint: '{err}'");
+                        None
+                    }
+                };
+
+                let verification = manifest.unprotected["verify_state"].clone();
+                let verification: Option<SnapshotVerifyState> =
+                    match serde_json::from_value(verification) {
+                        Ok(verify) => verify,
+                        Err(err) => {
+                            log::error!("error parsing verification state : '{err}'");
+                            None
+                        }
+                    };
+
+                let size = Some(files.iter().map(|x| x.size.unwrap_or(0)).sum());
+
+                SnapshotListItem {
+                    backup,
+                    comment,
+                    verification,
+                    fingerprint,
+                    files,
+                    size,
+                    owner,
+                    protected,
+                    retention_tags: manifest.retention_tags(),
+                    inventory_only,
+                }
+            }
+            Err(err) => {
+                log::error!("error during snapshot file listing: '{err}'");
+                let files = self
+                    .files
+                    .iter()
+                    .map(|filename| BackupContent {
+                        filename: filename.clone(),
+                        size: None,
+                        crypt_mode: None,
+                        previous_names: Vec::new(),
+                    })
+                    .collect();
+
+                SnapshotListItem {
+                    backup,
+                    comment: None,
+                    verification: None,
+                    fingerprint: None,
+                    files,
+                    size: None,
+                    owner,
+                    protected,
+                    retention_tags: Vec::new(),
+                    inventory_only,
+                }
+            }
+        }
+    }
+
     pub fn sort_list(list: &mut [BackupInfo], ascendending: bool) {
         if ascendending {
             // oldest first
@@ -666,19 +1128,35 @@ impl BackupInfo {
     }
 }
 
-fn list_backup_files<P: ?Sized + nix::NixPath>(
+/// Read the data files and the protection state of a snapshot directory in a single pass.
+pub(crate) fn read_snapshot_dir<P: ?Sized + nix::NixPath>(
     dirfd: RawFd,
     path: &P,
-) -> Result<Vec<String>, Error> {
+) -> Result<(Vec<String>, bool), Error> {
     let mut files = vec![];
-
-    proxmox_sys::fs::scandir(dirfd, path, &BACKUP_FILE_REGEX, |_, filename, file_type| {
-        if file_type != nix::dir::Type::File {
-            return Ok(());
+    let mut protected = false;
+
+    for entry in proxmox_sys::fs::read_subdir(dirfd, path)? {
+        let entry = entry?;
+        let name = match entry.file_name().to_str() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if name == ".protected" {
+            protected = true;
+            continue;
         }
-        files.push(filename.to_owned());
-        Ok(())
-    })?;
+        if !BACKUP_FILE_REGEX.is_match(name) {
+            continue;
+        }
+        let file_type = match entry.file_type() {
+            Some(file_type) => file_type,
+            None => get_file_type(entry.parent_fd(), entry.file_name())?,
+        };
+        if file_type == nix::dir::Type::File {
+            files.push(name.to_owned());
+        }
+    }
 
-    Ok(files)
+    Ok((files, protected))
 }
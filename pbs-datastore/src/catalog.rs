@@ -8,6 +8,7 @@ use serde::{Deserialize, Serialize};
 
 use pathpatterns::{MatchList, MatchType};
 
+use proxmox_human_byte::HumanByte;
 use proxmox_io::ReadExt;
 use proxmox_schema::api;
 
@@ -522,6 +523,47 @@ impl<R: Read + Seek> CatalogReader<R> {
         }
     }
 
+    /// Print the whole catalog to stdout as a tree
+    pub fn dump_tree(&mut self) -> Result<(), Error> {
+        let root = self.root()?;
+        if !root.is_directory() {
+            bail!("unexpected root entry type, not a directory!");
+        }
+        log::info!(".");
+        self.dump_tree_dir(&root, "")
+    }
+
+    fn dump_tree_dir(&mut self, parent: &DirEntry, indent: &str) -> Result<(), Error> {
+        let entries = self.read_dir(parent)?;
+        let count = entries.len();
+
+        for (i, entry) in entries.iter().enumerate() {
+            let last = i + 1 == count;
+            let (branch, child_indent) = if last {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            let name = String::from_utf8_lossy(&entry.name);
+
+            match entry.attr {
+                DirEntryAttribute::Directory { .. } => {
+                    log::info!("{indent}{branch}{name}/");
+                    self.dump_tree_dir(entry, &format!("{indent}{child_indent}"))?;
+                }
+                DirEntryAttribute::File { size, .. } => {
+                    log::info!("{indent}{branch}{name} ({})", HumanByte::from(size));
+                }
+                DirEntryAttribute::Symlink => log::info!("{indent}{branch}{name}@"),
+                DirEntryAttribute::Fifo => log::info!("{indent}{branch}{name}|"),
+                DirEntryAttribute::Socket => log::info!("{indent}{branch}{name}="),
+                _ => log::info!("{indent}{branch}{name}"),
+            }
+        }
+
+        Ok(())
+    }
+
     /// Get the root DirEntry
     pub fn root(&mut self) -> Result<DirEntry, Error> {
         // Root dir is special
@@ -660,13 +702,13 @@ impl<R: Read + Seek> CatalogReader<R> {
     }
 
     /// Finds all entries matching the given match patterns and calls the
-    /// provided callback on them.
+    /// provided callback on them, with their path and the entry itself.
     pub fn find<'a>(
         &mut self,
         parent: &DirEntry,
         file_path: &mut Vec<u8>,
         match_list: &'a impl MatchList<'a>, //&[MatchEntry],
-        callback: &mut dyn FnMut(&[u8]) -> Result<(), Error>,
+        callback: &mut dyn FnMut(&[u8], &DirEntry) -> Result<(), Error>,
     ) -> Result<(), Error> {
         let file_len = file_path.len();
         for e in self.read_dir(parent)? {
@@ -679,7 +721,7 @@ impl<R: Read + Seek> CatalogReader<R> {
             file_path.extend(&e.name);
             match match_list.matches(&file_path, e.get_file_mode()) {
                 Ok(Some(MatchType::Exclude)) => continue,
-                Ok(Some(MatchType::Include)) => callback(file_path)?,
+                Ok(Some(MatchType::Include)) => callback(file_path, &e)?,
                 _ => (),
             }
             if is_dir {
@@ -0,0 +1,149 @@
+//! Quarantine index of corrupt chunks
+//!
+//! Verification renames corrupt chunks to `<digest>.<n>.bad` and records them here, together
+//! with the snapshots found referencing them. A later backup uploading the same data writes the
+//! chunk anew, which heals it and removes it from the quarantine.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Error};
+use hex::FromHex;
+use serde::{Deserialize, Serialize};
+
+use pbs_api_types::QuarantinedChunk;
+use proxmox_sys::fs::{file_read_optional_string, open_file_locked, replace_file, CreateOptions};
+
+const QUARANTINE_FILE: &str = ".chunk-quarantine.json";
+const QUARANTINE_LOCK_FILE: &str = ".chunk-quarantine.lck";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct QuarantineEntry {
+    detected: i64,
+    reason: String,
+    snapshots: Vec<String>,
+}
+
+/// The chunk quarantine of a datastore.
+pub struct ChunkQuarantine {
+    base: PathBuf,
+}
+
+impl ChunkQuarantine {
+    pub(crate) fn new<P: AsRef<Path>>(base: P) -> Self {
+        Self {
+            base: base.as_ref().to_owned(),
+        }
+    }
+
+    fn load(&self) -> Result<BTreeMap<String, QuarantineEntry>, Error> {
+        let path = self.base.join(QUARANTINE_FILE);
+        match file_read_optional_string(&path)? {
+            Some(data) => serde_json::from_str(&data)
+                .map_err(|err| format_err!("unable to parse {path:?} - {err}")),
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// Modify the quarantine while holding its lock.
+    fn update<R, F>(&self, func: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut BTreeMap<String, QuarantineEntry>) -> R,
+    {
+        let backup_user = pbs_config::backup_user()?;
+        let options = CreateOptions::new()
+            .owner(backup_user.uid)
+            .group(backup_user.gid)
+            .perm(nix::sys::stat::Mode::from_bits_truncate(0o660));
+
+        let timeout = std::time::Duration::new(10, 0);
+        let _lock = open_file_locked(
+            self.base.join(QUARANTINE_LOCK_FILE),
+            timeout,
+            true,
+            options.clone(),
+        )?;
+
+        let mut data = self.load()?;
+        let result = func(&mut data);
+
+        let path = self.base.join(QUARANTINE_FILE);
+        if data.is_empty() {
+            if let Err(err) = std::fs::remove_file(&path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    return Err(format_err!("unable to remove {path:?} - {err}"));
+                }
+            }
+        } else {
+            replace_file(path, &serde_json::to_vec(&data)?, options, true)?;
+        }
+
+        Ok(result)
+    }
+
+    /// List all quarantined chunks.
+    pub fn list(&self) -> Result<Vec<QuarantinedChunk>, Error> {
+        Ok(self
+            .load()?
+            .into_iter()
+            .map(|(digest, entry)| QuarantinedChunk {
+                digest,
+                detected: entry.detected,
+                reason: entry.reason,
+                snapshots: entry.snapshots,
+            })
+            .collect())
+    }
+
+    /// Returns the digests of all quarantined chunks.
+    pub fn digests(&self) -> Result<HashSet<[u8; 32]>, Error> {
+        Ok(self
+            .load()?
+            .keys()
+            .filter_map(|digest| <[u8; 32]>::from_hex(digest).ok())
+            .collect())
+    }
+
+    /// Returns whether any quarantined chunk is referenced by `snapshot`.
+    pub fn references_snapshot(&self, snapshot: &str) -> Result<bool, Error> {
+        Ok(self
+            .load()?
+            .values()
+            .any(|entry| entry.snapshots.iter().any(|s| s == snapshot)))
+    }
+
+    /// Record corrupt chunks found in `snapshot`.
+    ///
+    /// The reason is only used for chunks not already in the quarantine.
+    pub fn add(&self, chunks: &[([u8; 32], String)], snapshot: &str) -> Result<(), Error> {
+        if chunks.is_empty() {
+            return Ok(());
+        }
+        let now = proxmox_time::epoch_i64();
+        self.update(|data| {
+            for (digest, reason) in chunks {
+                let entry = data
+                    .entry(hex::encode(digest))
+                    .or_insert_with(|| QuarantineEntry {
+                        detected: now,
+                        reason: reason.clone(),
+                        snapshots: Vec::new(),
+                    });
+                if !entry.snapshots.iter().any(|s| s == snapshot) {
+                    entry.snapshots.push(snapshot.to_string());
+                }
+            }
+        })
+    }
+
+    /// Remove chunks from the quarantine, returns whether they were quarantined.
+    pub fn remove(&self, digests: &[[u8; 32]]) -> Result<Vec<bool>, Error> {
+        self.update(|data| {
+            digests
+                .iter()
+                .map(|digest| data.remove(&hex::encode(digest)).is_some())
+                .collect()
+        })
+    }
+}
@@ -1,6 +1,6 @@
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 
 use anyhow::{bail, format_err, Error};
 
@@ -13,9 +13,13 @@ use proxmox_sys::process_locker::{
 use proxmox_sys::task_log;
 use proxmox_sys::WorkerTaskContext;
 
+use crate::at_rest::{
+    decode_at_rest, read_at_rest, AtRestCrypt, AT_REST_MAGIC_1_0, AT_REST_OVERHEAD,
+};
 use crate::file_formats::{
     COMPRESSED_BLOB_MAGIC_1_0, ENCRYPTED_BLOB_MAGIC_1_0, UNCOMPRESSED_BLOB_MAGIC_1_0,
 };
+use crate::maintenance_throttle::MaintenanceThrottle;
 use crate::DataBlob;
 
 /// File system based chunk store
@@ -26,10 +30,17 @@ pub struct ChunkStore {
     mutex: Mutex<()>,
     locker: Option<Arc<Mutex<ProcessLocker>>>,
     sync_level: DatastoreFSyncLevel,
+    at_rest: RwLock<Option<Arc<AtRestCrypt>>>,
 }
 
 // TODO: what about sysctl setting vm.vfs_cache_pressure (0 - 100) ?
 
+/// Sync a directory, to persist renames of files within it.
+pub(crate) fn fsync_dir(path: &Path) -> Result<(), Error> {
+    let dir = std::fs::File::open(path)?;
+    nix::unistd::fsync(dir.as_raw_fd()).map_err(|err| format_err!("fsync failed: {err}"))
+}
+
 pub fn verify_chunk_size(size: usize) -> Result<(), Error> {
     static SIZES: [usize; 7] = [
         64 * 1024,
@@ -73,6 +84,7 @@ impl ChunkStore {
             mutex: Mutex::new(()),
             locker: None,
             sync_level: Default::default(),
+            at_rest: RwLock::new(None),
         }
     }
 
@@ -153,6 +165,63 @@ impl ChunkStore {
         Self::open(name, base, sync_level)
     }
 
+    /// Re-attach an existing chunk store, e.g. after reinstalling the server.
+    ///
+    /// Checks that all chunk subdirectories exist and are directories, and recreates the lock
+    /// file with the correct owner if it is missing. Existing chunks are left untouched.
+    pub fn import<P>(
+        name: &str,
+        path: P,
+        uid: nix::unistd::Uid,
+        gid: nix::unistd::Gid,
+        worker: Option<&dyn WorkerTaskContext>,
+        sync_level: DatastoreFSyncLevel,
+    ) -> Result<Self, Error>
+    where
+        P: Into<PathBuf>,
+    {
+        let base: PathBuf = path.into();
+
+        if !base.is_absolute() {
+            bail!("expected absolute path - got {base:?}");
+        }
+
+        let chunk_dir = Self::chunk_dir(&base);
+
+        match std::fs::metadata(&chunk_dir) {
+            Ok(meta) if meta.is_dir() => (),
+            Ok(_) => bail!("unable to import chunk store '{name}' - {chunk_dir:?} is no directory"),
+            Err(err) => bail!("unable to import chunk store '{name}' at {chunk_dir:?} - {err}"),
+        }
+
+        let mut last_percentage = 0;
+
+        for i in 0..64 * 1024 {
+            let mut l1path = chunk_dir.clone();
+            l1path.push(format!("{:04x}", i));
+            match std::fs::metadata(&l1path) {
+                Ok(meta) if meta.is_dir() => (),
+                Ok(_) => bail!("unable to import chunk store '{name}' - {l1path:?} is no dir"),
+                Err(err) => bail!("unable to import chunk store '{name}' - {l1path:?}: {err}"),
+            }
+            let percentage = (i * 100) / (64 * 1024);
+            if percentage != last_percentage {
+                if let Some(worker) = worker {
+                    task_log!(worker, "Chunkstore check: {}%", percentage)
+                }
+                last_percentage = percentage;
+            }
+        }
+
+        let lockfile_path = Self::lockfile_path(&base);
+        if !lockfile_path.exists() {
+            let options = CreateOptions::new().owner(uid).group(gid);
+            proxmox_sys::fs::replace_file(lockfile_path, b"", options, false)?;
+        }
+
+        Self::open(name, base, sync_level)
+    }
+
     fn lockfile_path<P: Into<PathBuf>>(base: P) -> PathBuf {
         let mut lockfile_path: PathBuf = base.into();
         lockfile_path.push(".lock");
@@ -192,6 +261,7 @@ impl ChunkStore {
             locker: Some(locker),
             mutex: Mutex::new(()),
             sync_level,
+            at_rest: RwLock::new(None),
         })
     }
 
@@ -353,6 +423,7 @@ impl ChunkStore {
         phase1_start_time: i64,
         status: &mut GarbageCollectionStatus,
         worker: &dyn WorkerTaskContext,
+        throttle: &mut MaintenanceThrottle,
     ) -> Result<(), Error> {
         // unwrap: only `None` in unit tests
         assert!(self.locker.is_some());
@@ -379,6 +450,7 @@ impl ChunkStore {
 
             worker.check_abort()?;
             worker.fail_on_shutdown()?;
+            throttle.pause(worker)?;
 
             let (dirfd, entry) = match entry {
                 Ok(entry) => (entry.parent_fd(), entry),
@@ -452,6 +524,12 @@ impl ChunkStore {
         let raw_data = chunk.raw_data();
         let encoded_size = raw_data.len() as u64;
 
+        let at_rest = self.at_rest_crypt();
+        let stored_size = match at_rest {
+            Some(_) => encoded_size + AT_REST_OVERHEAD as u64,
+            None => encoded_size,
+        };
+
         let name = &self.name;
 
         if let Ok(metadata) = std::fs::metadata(&chunk_path) {
@@ -459,15 +537,18 @@ impl ChunkStore {
                 bail!("got unexpected file type on store '{name}' for chunk {digest_str}");
             }
             let old_size = metadata.len();
-            if encoded_size == old_size {
+            if stored_size == old_size {
                 self.touch_chunk(digest)?;
                 return Ok((true, old_size));
             } else if old_size == 0 {
                 log::warn!("found empty chunk '{digest_str}' in store {name}, overwriting");
+            } else if self.existing_payload_size(&chunk_path, old_size)? == encoded_size {
+                // same chunk, only stored with a different at-rest encryption state
+                self.touch_chunk(digest)?;
+                return Ok((true, old_size));
             } else if chunk.is_encrypted() {
                 // incoming chunk is encrypted, possible attack or hash collision!
-                let mut existing_file = std::fs::File::open(&chunk_path)?;
-                let magic = existing_file.read_exact_allocated(8)?;
+                let magic = self.read_chunk_magic(&chunk_path)?;
 
                 // going from unencrypted to encrypted can never be right, since the digest
                 // includes data derived from the encryption key
@@ -487,7 +568,7 @@ impl ChunkStore {
                 // verification at some point..
                 self.touch_chunk(digest)?;
                 return Ok((true, old_size));
-            } else if old_size < encoded_size {
+            } else if old_size < stored_size {
                 log::debug!("Got another copy of chunk with digest '{digest_str}', existing chunk is smaller, discarding uploaded one.");
                 self.touch_chunk(digest)?;
                 return Ok((true, old_size));
@@ -500,9 +581,18 @@ impl ChunkStore {
             .parent()
             .ok_or_else(|| format_err!("unable to get chunk dir"))?;
 
+        let encrypted;
+        let data = match at_rest {
+            Some(crypt) => {
+                encrypted = crypt.encrypt(raw_data)?;
+                &encrypted[..]
+            }
+            None => raw_data,
+        };
+
         proxmox_sys::fs::replace_file(
             &chunk_path,
-            raw_data,
+            data,
             CreateOptions::new(),
             self.sync_level == DatastoreFSyncLevel::File,
         )
@@ -512,9 +602,7 @@ impl ChunkStore {
 
         if self.sync_level == DatastoreFSyncLevel::File {
             // fsync dir handle to persist the tmp rename
-            let dir = std::fs::File::open(chunk_dir_path)?;
-            nix::unistd::fsync(dir.as_raw_fd())
-                .map_err(|err| format_err!("fsync failed: {err}"))?;
+            fsync_dir(chunk_dir_path)?;
         }
 
         drop(lock);
@@ -522,6 +610,47 @@ impl ChunkStore {
         Ok((false, encoded_size))
     }
 
+    /// Set the datastore key used to encrypt newly written chunks at rest.
+    pub fn set_at_rest_crypt(&self, crypt: Option<Arc<AtRestCrypt>>) {
+        *self.at_rest.write().unwrap() = crypt;
+    }
+
+    /// The datastore key used for at-rest encryption, if configured.
+    pub fn at_rest_crypt(&self) -> Option<Arc<AtRestCrypt>> {
+        self.at_rest.read().unwrap().clone()
+    }
+
+    /// Read the raw chunk data, removing the at-rest encryption layer if present.
+    pub fn read_raw_chunk(&self, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        let (chunk_path, _digest_str) = self.chunk_path(digest);
+        read_at_rest(self.at_rest_crypt().as_deref(), chunk_path)
+    }
+
+    // size of the contained blob, without the at-rest encryption overhead
+    fn existing_payload_size(&self, path: &Path, size: u64) -> Result<u64, Error> {
+        let mut file = std::fs::File::open(path)?;
+        let magic = file.read_exact_allocated(8)?;
+        if magic == AT_REST_MAGIC_1_0 {
+            Ok(size.saturating_sub(AT_REST_OVERHEAD as u64))
+        } else {
+            Ok(size)
+        }
+    }
+
+    // blob magic of an existing chunk file
+    fn read_chunk_magic(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        let mut file = std::fs::File::open(path)?;
+        let magic = file.read_exact_allocated(8)?;
+        if magic != AT_REST_MAGIC_1_0 {
+            return Ok(magic);
+        }
+        drop(file);
+        let raw = std::fs::read(path)?;
+        let mut data = decode_at_rest(self.at_rest_crypt().as_deref(), raw)?;
+        data.truncate(8);
+        Ok(data)
+    }
+
     pub fn chunk_path(&self, digest: &[u8; 32]) -> (PathBuf, String) {
         // unwrap: only `None` in unit tests
         assert!(self.locker.is_some());
@@ -554,6 +683,14 @@ impl ChunkStore {
         self.base.clone()
     }
 
+    /// Returns whether index files, blobs and manifests have to be synced to disk when written.
+    pub fn sync_metadata(&self) -> bool {
+        matches!(
+            self.sync_level,
+            DatastoreFSyncLevel::File | DatastoreFSyncLevel::Index
+        )
+    }
+
     pub fn try_shared_lock(&self) -> Result<ProcessLockSharedGuard, Error> {
         // unwrap: only `None` in unit tests
         ProcessLocker::try_shared_lock(self.locker.clone().unwrap())
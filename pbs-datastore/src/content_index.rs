@@ -0,0 +1,427 @@
+//! Metadata index of the datastore content
+//!
+//! If enabled for a datastore, its snapshots, their files and sizes, the group owners and the
+//! verify states are recorded in an SQLite database. The index is updated in the same places
+//! where the datastore content changes, so snapshot listings and counts can be answered without
+//! reading the directory of every snapshot.
+//!
+//! The index is only used after it was built completely by a rebuild task. If recording a
+//! change fails, the index is marked as stale and callers fall back to scanning the datastore
+//! until the next rebuild.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use proxmox_sys::fs::{replace_file, CreateOptions};
+use proxmox_sys::{task_log, WorkerTaskContext};
+
+use pbs_api_types::{
+    Authid, BackupGroup, BackupNamespace, BackupType, SnapshotListItem, VerifyState,
+};
+
+use crate::backup_info::{BackupDir, BackupInfo};
+use crate::DataStore;
+
+const INDEX_FILE: &str = ".content-index.db";
+const STALE_MARKER_FILE: &str = ".content-index.stale";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS groups (
+        ns TEXT NOT NULL,
+        backup_type TEXT NOT NULL,
+        backup_id TEXT NOT NULL,
+        owner TEXT,
+        PRIMARY KEY (ns, backup_type, backup_id)
+    );
+    CREATE TABLE IF NOT EXISTS snapshots (
+        ns TEXT NOT NULL,
+        backup_type TEXT NOT NULL,
+        backup_id TEXT NOT NULL,
+        backup_time INTEGER NOT NULL,
+        protected INTEGER NOT NULL,
+        finished INTEGER NOT NULL,
+        verify_state TEXT,
+        size INTEGER,
+        item TEXT NOT NULL,
+        PRIMARY KEY (ns, backup_type, backup_id, backup_time)
+    );
+";
+
+// rebuilds are retried if the content changed while scanning the datastore
+const REBUILD_ATTEMPTS: usize = 3;
+
+struct SnapshotRow {
+    ns: String,
+    group: BackupGroup,
+    time: i64,
+    protected: bool,
+    finished: bool,
+    verify_state: Option<String>,
+    size: Option<u64>,
+    item: String,
+}
+
+impl SnapshotRow {
+    fn new(info: &BackupInfo) -> Result<Self, Error> {
+        let item = info.to_list_item(None);
+        Ok(Self {
+            ns: info.backup_dir.backup_ns().to_string(),
+            group: info.backup_dir.group().clone(),
+            time: info.backup_dir.backup_time(),
+            protected: info.protected,
+            finished: info.is_finished(),
+            verify_state: item.verification.as_ref().map(|verify| {
+                match verify.state {
+                    VerifyState::Ok => "ok",
+                    VerifyState::Failed => "failed",
+                }
+                .to_string()
+            }),
+            size: item.size,
+            item: serde_json::to_string(&item)?,
+        })
+    }
+
+    fn insert(&self, conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute(
+            "INSERT OR REPLACE INTO snapshots \
+            (ns, backup_type, backup_id, backup_time, protected, finished, verify_state, size, \
+            item) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                self.ns,
+                self.group.ty.as_str(),
+                self.group.id,
+                self.time,
+                self.protected,
+                self.finished,
+                self.verify_state,
+                self.size,
+                self.item,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+fn bump_changes(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO meta (key, value) VALUES ('changes', '1') \
+        ON CONFLICT (key) DO UPDATE SET value = CAST(value AS INTEGER) + 1",
+        [],
+    )?;
+    Ok(())
+}
+
+fn read_changes(conn: &Connection) -> rusqlite::Result<u64> {
+    let value: Option<String> = conn
+        .query_row("SELECT value FROM meta WHERE key = 'changes'", [], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    Ok(value.and_then(|value| value.parse().ok()).unwrap_or(0))
+}
+
+/// The content index of a datastore.
+pub struct ContentIndex {
+    base: PathBuf,
+    conn: Mutex<Option<Connection>>,
+}
+
+impl ContentIndex {
+    pub(crate) fn new<P: AsRef<Path>>(base: P) -> Self {
+        Self {
+            base: base.as_ref().to_owned(),
+            conn: Mutex::new(None),
+        }
+    }
+
+    fn open(&self) -> Result<Connection, Error> {
+        let path = self.base.join(INDEX_FILE);
+        let created = !path.exists();
+
+        let conn = Connection::open(&path)
+            .map_err(|err| format_err!("unable to open content index {path:?} - {err}"))?;
+        conn.busy_timeout(Duration::from_secs(10))?;
+        conn.execute_batch(SCHEMA)?;
+
+        if created {
+            // the API daemon runs as root, the proxy must still be able to write
+            let backup_user = pbs_config::backup_user()?;
+            nix::unistd::chown(&path, Some(backup_user.uid), Some(backup_user.gid))?;
+            nix::sys::stat::fchmodat(
+                None,
+                &path,
+                nix::sys::stat::Mode::from_bits_truncate(0o660),
+                nix::sys::stat::FchmodatFlags::FollowSymlink,
+            )?;
+        }
+
+        Ok(conn)
+    }
+
+    fn with_connection<R, F>(&self, func: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Connection) -> Result<R, Error>,
+    {
+        let mut conn = self.conn.lock().unwrap();
+        if conn.is_none() {
+            *conn = Some(self.open()?);
+        }
+        func(conn.as_mut().unwrap())
+    }
+
+    // a failed update leaves the index stale until the next rebuild
+    fn mark_stale(&self, err: Error) {
+        log::warn!("content index of {:?} is stale - {err}", self.base);
+        let options = CreateOptions::new().perm(nix::sys::stat::Mode::from_bits_truncate(0o660));
+        if let Err(err) = replace_file(self.base.join(STALE_MARKER_FILE), b"", options, false) {
+            log::error!("unable to mark content index as stale - {err}");
+        }
+    }
+
+    /// Returns whether the index was built and is up to date, so it can be used for queries.
+    pub fn is_complete(&self) -> bool {
+        if self.base.join(STALE_MARKER_FILE).exists() {
+            return false;
+        }
+        let complete = self.with_connection(|conn| {
+            Ok(conn
+                .query_row("SELECT value FROM meta WHERE key = 'complete'", [], |row| {
+                    row.get::<_, String>(0)
+                })
+                .optional()?)
+        });
+        matches!(complete, Ok(Some(_)))
+    }
+
+    /// Record the current state of a snapshot.
+    pub fn snapshot_changed(&self, dir: &BackupDir) {
+        let result = BackupInfo::new(dir.clone())
+            .and_then(|info| SnapshotRow::new(&info))
+            .and_then(|row| {
+                let owner = dir.get_owner().ok().map(|owner| owner.to_string());
+                self.with_connection(|conn| {
+                    let tx = conn.transaction()?;
+                    tx.execute(
+                        "INSERT OR IGNORE INTO groups (ns, backup_type, backup_id, owner) \
+                        VALUES (?1, ?2, ?3, ?4)",
+                        params![row.ns, row.group.ty.as_str(), row.group.id, owner],
+                    )?;
+                    row.insert(&tx)?;
+                    bump_changes(&tx)?;
+                    tx.commit()?;
+                    Ok(())
+                })
+            });
+        if let Err(err) = result {
+            self.mark_stale(err);
+        }
+    }
+
+    /// Record the removal of a snapshot.
+    pub fn snapshot_removed(&self, ns: &BackupNamespace, dir: &pbs_api_types::BackupDir) {
+        let result = self.with_connection(|conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "DELETE FROM snapshots \
+                WHERE ns = ?1 AND backup_type = ?2 AND backup_id = ?3 AND backup_time = ?4",
+                params![ns.to_string(), dir.ty().as_str(), dir.id(), dir.time],
+            )?;
+            bump_changes(&tx)?;
+            tx.commit()?;
+            Ok(())
+        });
+        if let Err(err) = result {
+            self.mark_stale(err);
+        }
+    }
+
+    /// Record the removal of a whole group.
+    pub(crate) fn group_removed(&self, ns: &BackupNamespace, group: &BackupGroup) {
+        let result = self.with_connection(|conn| {
+            let tx = conn.transaction()?;
+            let ns = ns.to_string();
+            for table in ["snapshots", "groups"] {
+                tx.execute(
+                    &format!(
+                        "DELETE FROM {table} WHERE ns = ?1 AND backup_type = ?2 AND backup_id = ?3"
+                    ),
+                    params![ns, group.ty.as_str(), group.id],
+                )?;
+            }
+            bump_changes(&tx)?;
+            tx.commit()?;
+            Ok(())
+        });
+        if let Err(err) = result {
+            self.mark_stale(err);
+        }
+    }
+
+    /// Record a new owner of a group.
+    pub(crate) fn owner_changed(&self, ns: &BackupNamespace, group: &BackupGroup, owner: &Authid) {
+        let result = self.with_connection(|conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "INSERT OR REPLACE INTO groups (ns, backup_type, backup_id, owner) \
+                VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    ns.to_string(),
+                    group.ty.as_str(),
+                    group.id,
+                    owner.to_string()
+                ],
+            )?;
+            bump_changes(&tx)?;
+            tx.commit()?;
+            Ok(())
+        });
+        if let Err(err) = result {
+            self.mark_stale(err);
+        }
+    }
+
+    /// List the snapshots of a namespace, optionally limited to a backup type or ID.
+    ///
+    /// The owner of each item is set from the group. Returns `None` if the index is not complete.
+    pub fn list_snapshots(
+        &self,
+        ns: &BackupNamespace,
+        backup_type: Option<BackupType>,
+        backup_id: Option<&str>,
+    ) -> Result<Option<Vec<SnapshotListItem>>, Error> {
+        if !self.is_complete() {
+            return Ok(None);
+        }
+        self.with_connection(|conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT s.item, g.owner FROM snapshots s \
+                LEFT JOIN groups g USING (ns, backup_type, backup_id) \
+                WHERE s.ns = ?1 AND (?2 IS NULL OR s.backup_type = ?2) \
+                AND (?3 IS NULL OR s.backup_id = ?3) \
+                ORDER BY s.backup_type, s.backup_id, s.backup_time",
+            )?;
+            let rows = stmt.query_map(
+                params![
+                    ns.to_string(),
+                    backup_type.as_ref().map(BackupType::as_str),
+                    backup_id
+                ],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+            )?;
+
+            let mut list = Vec::new();
+            for row in rows {
+                let (item, owner) = row?;
+                let mut item: SnapshotListItem = serde_json::from_str(&item)?;
+                item.owner = owner.and_then(|owner| owner.parse().ok());
+                list.push(item);
+            }
+            Ok(Some(list))
+        })
+    }
+
+    /// Returns the number of snapshots of each group. Returns `None` if the index is not
+    /// complete.
+    pub fn snapshot_counts(
+        &self,
+    ) -> Result<Option<HashMap<(BackupNamespace, BackupGroup), u64>>, Error> {
+        if !self.is_complete() {
+            return Ok(None);
+        }
+        self.with_connection(|conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT ns, backup_type, backup_id, COUNT(*) FROM snapshots \
+                GROUP BY ns, backup_type, backup_id",
+            )?;
+            let mut rows = stmt.query([])?;
+
+            let mut counts = HashMap::new();
+            while let Some(row) = rows.next()? {
+                let ns: BackupNamespace = row.get::<_, String>(0)?.parse()?;
+                let ty: BackupType = row.get::<_, String>(1)?.parse()?;
+                let group = BackupGroup::new(ty, row.get::<_, String>(2)?);
+                counts.insert((ns, group), row.get::<_, u64>(3)?);
+            }
+            Ok(Some(counts))
+        })
+    }
+
+    /// Rebuild the index from the datastore content.
+    ///
+    /// Returns the number of indexed groups and snapshots.
+    pub fn rebuild(
+        &self,
+        datastore: &Arc<DataStore>,
+        worker: &dyn WorkerTaskContext,
+    ) -> Result<(usize, usize), Error> {
+        for attempt in 1..=REBUILD_ATTEMPTS {
+            let changes = self.with_connection(|conn| Ok(read_changes(conn)?))?;
+
+            // changes recorded from here on are covered by the scan or detected below
+            if let Err(err) = std::fs::remove_file(self.base.join(STALE_MARKER_FILE)) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    bail!("unable to remove stale marker - {err}");
+                }
+            }
+
+            let mut groups = Vec::new();
+            let mut snapshots = Vec::new();
+
+            for ns in datastore.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+                for group in datastore.iter_backup_groups_ok(ns.clone())? {
+                    worker.check_abort()?;
+                    let owner = group.get_owner().ok().map(|owner| owner.to_string());
+                    groups.push((ns.to_string(), group.group().clone(), owner));
+                    for info in group.list_backups()? {
+                        snapshots.push(SnapshotRow::new(&info)?);
+                    }
+                }
+            }
+
+            let done = self.with_connection(|conn| {
+                let tx = conn.transaction()?;
+                if read_changes(&tx)? != changes {
+                    return Ok(false);
+                }
+                tx.execute("DELETE FROM snapshots", [])?;
+                tx.execute("DELETE FROM groups", [])?;
+                for (ns, group, owner) in &groups {
+                    tx.execute(
+                        "INSERT INTO groups (ns, backup_type, backup_id, owner) \
+                        VALUES (?1, ?2, ?3, ?4)",
+                        params![ns, group.ty.as_str(), group.id, owner],
+                    )?;
+                }
+                for row in &snapshots {
+                    row.insert(&tx)?;
+                }
+                tx.execute(
+                    "INSERT OR REPLACE INTO meta (key, value) VALUES ('complete', ?1)",
+                    params![proxmox_time::epoch_i64().to_string()],
+                )?;
+                tx.commit()?;
+                Ok(true)
+            })?;
+
+            if done {
+                return Ok((groups.len(), snapshots.len()));
+            }
+            task_log!(
+                worker,
+                "datastore content changed during scan, retrying ({attempt}/{REBUILD_ATTEMPTS})"
+            );
+        }
+
+        bail!("datastore content kept changing, unable to rebuild content index");
+    }
+}
@@ -5,13 +5,23 @@ use openssl::symm::{decrypt_aead, Mode};
 
 use proxmox_io::{ReadExt, WriteExt};
 
-use pbs_api_types::CryptMode;
+use pbs_api_types::{ChunkDigestAlgorithm, CryptMode};
 use pbs_tools::crypt_config::CryptConfig;
 
 use super::file_formats::*;
 
 const MAX_BLOB_SIZE: usize = 128 * 1024 * 1024;
 
+/// Compute the digest identifying the unencrypted chunk `data` with `algorithm`.
+///
+/// Encrypted chunks use a digest keyed with the encryption key instead, see
+/// [`CryptConfig::compute_digest`].
+pub fn compute_chunk_digest(algorithm: ChunkDigestAlgorithm, data: &[u8]) -> [u8; 32] {
+    match algorithm {
+        ChunkDigestAlgorithm::Sha256 => openssl::sha::sha256(data),
+    }
+}
+
 /// Encoded data chunk with digest and positional information
 pub struct ChunkInfo {
     pub chunk: DataBlob,
@@ -311,6 +321,21 @@ impl DataBlob {
         &self,
         expected_chunk_size: usize,
         expected_digest: &[u8; 32],
+    ) -> Result<(), Error> {
+        self.verify_unencrypted_with(
+            ChunkDigestAlgorithm::default(),
+            expected_chunk_size,
+            expected_digest,
+        )
+    }
+
+    /// Like [`verify_unencrypted`](Self::verify_unencrypted), for chunks identified by digests
+    /// computed with `algorithm`.
+    pub fn verify_unencrypted_with(
+        &self,
+        algorithm: ChunkDigestAlgorithm,
+        expected_chunk_size: usize,
+        expected_digest: &[u8; 32],
     ) -> Result<(), Error> {
         let magic = self.magic();
 
@@ -318,8 +343,11 @@ impl DataBlob {
             return Ok(());
         }
 
-        // verifies digest!
-        let data = self.decode(None, Some(expected_digest))?;
+        let data = self.decode(None, None)?;
+
+        if &compute_chunk_digest(algorithm, &data) != expected_digest {
+            bail!("detected chunk with wrong digest.");
+        }
 
         if expected_chunk_size != data.len() {
             bail!(
@@ -339,7 +367,7 @@ impl DataBlob {
     ) -> Result<(), Error> {
         let digest = match config {
             Some(config) => config.compute_digest(data),
-            None => openssl::sha::sha256(data),
+            None => compute_chunk_digest(ChunkDigestAlgorithm::default(), data),
         };
         if &digest != expected_digest {
             bail!("detected chunk with wrong digest.");
@@ -476,6 +504,7 @@ impl DataBlob {
 /// we always compute the correct one.
 pub struct DataChunkBuilder<'a, 'b> {
     config: Option<&'b CryptConfig>,
+    algorithm: ChunkDigestAlgorithm,
     orig_data: &'a [u8],
     digest_computed: bool,
     digest: [u8; 32],
@@ -488,6 +517,7 @@ impl<'a, 'b> DataChunkBuilder<'a, 'b> {
         Self {
             orig_data,
             config: None,
+            algorithm: ChunkDigestAlgorithm::default(),
             digest_computed: false,
             digest: [0u8; 32],
             compress: true,
@@ -513,12 +543,21 @@ impl<'a, 'b> DataChunkBuilder<'a, 'b> {
         self
     }
 
+    /// Set the digest algorithm for unencrypted chunks.
+    pub fn digest_algorithm(mut self, value: ChunkDigestAlgorithm) -> Self {
+        if self.digest_computed {
+            panic!("unable to set digest_algorithm after compute_digest().");
+        }
+        self.algorithm = value;
+        self
+    }
+
     fn compute_digest(&mut self) {
         if !self.digest_computed {
             if let Some(config) = self.config {
                 self.digest = config.compute_digest(self.orig_data);
             } else {
-                self.digest = openssl::sha::sha256(self.orig_data);
+                self.digest = compute_chunk_digest(self.algorithm, self.orig_data);
             }
             self.digest_computed = true;
         }
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::io::{self, Write};
 use std::os::unix::io::AsRawFd;
@@ -19,17 +20,26 @@ use proxmox_sys::WorkerTaskContext;
 use proxmox_sys::{task_log, task_warn};
 
 use pbs_api_types::{
-    Authid, BackupNamespace, BackupType, ChunkOrder, DataStoreConfig, DatastoreFSyncLevel,
-    DatastoreTuning, GarbageCollectionStatus, MaintenanceMode, MaintenanceType, Operation, UPID,
+    Authid, BackupNamespace, BackupType, ChunkDigestAlgorithm, ChunkOrder, DataStoreConfig,
+    DatastoreFSyncLevel, DatastoreTuning, GarbageCollectionStatus, MaintenanceMode,
+    MaintenanceType, Operation, TokenOwnership, UPID,
 };
 
+use crate::at_rest::{read_at_rest, AtRestCrypt};
 use crate::backup_info::{BackupDir, BackupGroup, BackupGroupDeleteStats};
+use crate::chunk_quarantine::ChunkQuarantine;
 use crate::chunk_store::ChunkStore;
+#[cfg(feature = "sqlite-index")]
+use crate::content_index::ContentIndex;
 use crate::dynamic_index::{DynamicIndexReader, DynamicIndexWriter};
 use crate::fixed_index::{FixedIndexReader, FixedIndexWriter};
+use crate::group_tombstones::GroupTombstones;
 use crate::hierarchy::{ListGroups, ListGroupsType, ListNamespaces, ListNamespacesRecursive};
 use crate::index::IndexFile;
+use crate::listing_cache::ListingCache;
+use crate::maintenance_throttle::MaintenanceThrottle;
 use crate::manifest::{archive_type, ArchiveType};
+use crate::network_share::check_datastore_path;
 use crate::task_tracking::{self, update_active_operations};
 use crate::DataBlob;
 
@@ -61,6 +71,18 @@ pub struct DataStoreImpl {
     chunk_order: ChunkOrder,
     last_digest: Option<[u8; 32]>,
     sync_level: DatastoreFSyncLevel,
+    merkle_roots: bool,
+    group_tombstone_lifetime: Option<i64>,
+    listing_cache: Option<ListingCache>,
+    #[cfg(feature = "sqlite-index")]
+    content_index: Option<ContentIndex>,
+    filename_index: bool,
+    token_ownership: TokenOwnership,
+    throttle_latency: Option<u64>,
+    throttle_load: Option<f64>,
+    digest_algorithm: ChunkDigestAlgorithm,
+    // device ID of the network share the datastore was opened on
+    share_device: Option<u64>,
 }
 
 impl DataStoreImpl {
@@ -75,8 +97,24 @@ impl DataStoreImpl {
             chunk_order: Default::default(),
             last_digest: None,
             sync_level: Default::default(),
+            merkle_roots: false,
+            group_tombstone_lifetime: None,
+            listing_cache: None,
+            #[cfg(feature = "sqlite-index")]
+            content_index: None,
+            filename_index: false,
+            token_ownership: Default::default(),
+            throttle_latency: None,
+            throttle_load: None,
+            digest_algorithm: Default::default(),
+            share_device: None,
         })
     }
+
+    // used by a datastore handle other than the cache entry, or by one of its writers
+    fn is_in_use(self: &Arc<Self>) -> bool {
+        Arc::strong_count(self) > 1 || Arc::strong_count(&self.chunk_store) > 1
+    }
 }
 
 pub struct DataStore {
@@ -165,9 +203,35 @@ impl DataStore {
         // Our operation is registered, unlock the config.
         drop(config_lock);
 
+        // mounts the network share the datastore is located on, if required
+        let share_device = match check_datastore_path(Path::new(&config.path)) {
+            Ok(share_device) => share_device,
+            Err(err) => {
+                if let Some(operation) = operation {
+                    update_active_operations(name, operation, -1)?;
+                }
+                bail!("datastore '{name}' unavailable - {err}");
+            }
+        };
+
         let mut datastore_cache = DATASTORE_MAP.lock().unwrap();
         let entry = datastore_cache.get(name);
 
+        // the chunk store of a remounted share needs to be opened again, which replaces its
+        // process locker - not possible while running operations rely on it to hold off GC
+        let remounted = entry.map_or(false, |datastore| datastore.share_device != share_device);
+        if remounted && entry.map_or(false, |datastore| datastore.is_in_use()) {
+            drop(datastore_cache);
+            if let Some(operation) = operation {
+                update_active_operations(name, operation, -1)?;
+            }
+            bail!(
+                "datastore '{name}' unavailable - its network share was mounted again, retry \
+                once the running operations finished"
+            );
+        }
+        let entry = entry.filter(|_| !remounted);
+
         // reuse chunk store so that we keep using the same process locker instance!
         let chunk_store = if let Some(datastore) = &entry {
             let last_digest = datastore.last_digest.as_ref();
@@ -190,7 +254,8 @@ impl DataStore {
             )?)
         };
 
-        let datastore = DataStore::with_store_and_config(chunk_store, config, Some(digest))?;
+        let mut datastore = DataStore::with_store_and_config(chunk_store, config, Some(digest))?;
+        datastore.share_device = share_device;
 
         let datastore = Arc::new(datastore);
         datastore_cache.insert(name.to_string(), datastore.clone());
@@ -305,6 +370,14 @@ impl DataStore {
                 .parse_property_string(config.tuning.as_deref().unwrap_or(""))?,
         )?;
 
+        chunk_store.set_at_rest_crypt(Self::load_at_rest_crypt(&config)?);
+
+        #[cfg(feature = "sqlite-index")]
+        let content_index = tuning
+            .content_index
+            .unwrap_or(false)
+            .then(|| ContentIndex::new(chunk_store.base_path()));
+
         Ok(DataStoreImpl {
             chunk_store,
             gc_mutex: Mutex::new(()),
@@ -313,9 +386,38 @@ impl DataStore {
             chunk_order: tuning.chunk_order.unwrap_or_default(),
             last_digest,
             sync_level: tuning.sync_level.unwrap_or_default(),
+            merkle_roots: tuning.merkle_roots.unwrap_or(false),
+            group_tombstone_lifetime: tuning
+                .group_tombstone_days
+                .map(|days| days as i64 * 24 * 3600),
+            listing_cache: tuning
+                .listing_cache
+                .unwrap_or(false)
+                .then(ListingCache::default),
+            #[cfg(feature = "sqlite-index")]
+            content_index,
+            filename_index: tuning.filename_index.unwrap_or(false),
+            token_ownership: tuning.token_ownership.unwrap_or_default(),
+            throttle_latency: tuning.throttle_latency,
+            throttle_load: tuning.throttle_load,
+            digest_algorithm: config.digest_algorithm.unwrap_or_default(),
+            share_device: None,
         })
     }
 
+    fn load_at_rest_crypt(config: &DataStoreConfig) -> Result<Option<Arc<AtRestCrypt>>, Error> {
+        if !config.encrypt_at_rest.unwrap_or(false) {
+            return Ok(None);
+        }
+        match pbs_config::datastore_keys::load_key(&config.name)? {
+            Some(key) => Ok(Some(Arc::new(AtRestCrypt::new(key)))),
+            None => bail!(
+                "datastore '{}' has at-rest encryption enabled, but no datastore key",
+                config.name
+            ),
+        }
+    }
+
     pub fn get_chunk_iterator(
         &self,
     ) -> Result<
@@ -492,7 +594,11 @@ impl DataStore {
     /// Does *not* descends into child-namespaces and doesn't remoes the namespace itself either.
     ///
     /// Returns true if all the groups were removed, and false if some were protected.
-    pub fn remove_namespace_groups(self: &Arc<Self>, ns: &BackupNamespace) -> Result<bool, Error> {
+    pub fn remove_namespace_groups(
+        self: &Arc<Self>,
+        ns: &BackupNamespace,
+        removed_by: &Authid,
+    ) -> Result<bool, Error> {
         // FIXME: locking? The single groups/snapshots are already protected, so may not be
         // necessary (depends on what we all allow to do with namespaces)
         log::info!("removing all groups in namespace {}:/{ns}", self.name());
@@ -500,13 +606,13 @@ impl DataStore {
         let mut removed_all_groups = true;
 
         for group in self.iter_backup_groups(ns.to_owned())? {
-            let delete_stats = group?.destroy()?;
+            let delete_stats = self.destroy_group(&group?, removed_by)?;
             removed_all_groups = removed_all_groups && delete_stats.all_removed();
         }
 
         let base_file = std::fs::File::open(self.base_path())?;
         let base_fd = base_file.as_raw_fd();
-        for ty in BackupType::iter() {
+        for ty in self.list_backup_types(ns)? {
             let mut ty_dir = ns.path();
             ty_dir.push(ty.to_string());
             // best effort only, but we probably should log the error
@@ -529,13 +635,14 @@ impl DataStore {
         self: &Arc<Self>,
         ns: &BackupNamespace,
         delete_groups: bool,
+        removed_by: &Authid,
     ) -> Result<bool, Error> {
         let store = self.name();
         let mut removed_all_requested = true;
         if delete_groups {
             log::info!("removing whole namespace recursively below {store}:/{ns}",);
             for ns in self.recursive_iter_backup_ns(ns.to_owned())? {
-                let removed_ns_groups = self.remove_namespace_groups(&ns?)?;
+                let removed_ns_groups = self.remove_namespace_groups(&ns?, removed_by)?;
                 removed_all_requested = removed_all_requested && removed_ns_groups;
             }
         } else {
@@ -581,16 +688,58 @@ impl DataStore {
 
     /// Remove a complete backup group including all snapshots.
     ///
+    /// If group tombstones are enabled, a tombstone naming `removed_by` is recorded.
+    ///
     /// Returns `BackupGroupDeleteStats`, containing the number of deleted snapshots
     /// and number of protected snaphsots, which therefore were not removed.
     pub fn remove_backup_group(
         self: &Arc<Self>,
         ns: &BackupNamespace,
         backup_group: &pbs_api_types::BackupGroup,
+        removed_by: &Authid,
     ) -> Result<BackupGroupDeleteStats, Error> {
         let backup_group = self.backup_group(ns.clone(), backup_group.clone());
 
-        backup_group.destroy()
+        self.destroy_group(&backup_group, removed_by)
+    }
+
+    // Destroy a group, leaving a tombstone if enabled and the group is gone completely. As the
+    // group is removed already, failing to record the tombstone is only logged.
+    fn destroy_group(
+        &self,
+        group: &BackupGroup,
+        removed_by: &Authid,
+    ) -> Result<BackupGroupDeleteStats, Error> {
+        let tombstones = self.group_tombstones();
+        let owner = if tombstones.enabled() {
+            match group.get_owner() {
+                Ok(owner) => Some(owner),
+                Err(err) => {
+                    log::warn!(
+                        "unable to get owner of group {}, no tombstone - {err}",
+                        group.group()
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let delete_stats = group.destroy()?;
+
+        if let Some(owner) = owner.filter(|_| delete_stats.all_removed()) {
+            if let Err(err) =
+                tombstones.add(group.backup_ns(), group.group(), owner, removed_by.clone())
+            {
+                log::warn!(
+                    "unable to record tombstone of group {} - {err}",
+                    group.group()
+                );
+            }
+        }
+
+        Ok(delete_stats)
     }
 
     /// Remove a backup directory including all content
@@ -653,7 +802,31 @@ impl DataStore {
     ) -> Result<bool, Error> {
         let owner = self.get_owner(ns, backup_group)?;
 
-        Ok(check_backup_owner(&owner, auth_id).is_ok())
+        Ok(self.check_backup_owner(&owner, auth_id).is_ok())
+    }
+
+    /// Checks if `auth_id` may access a group owned by `owner`.
+    ///
+    /// Like [`check_backup_owner`], but with the `user` token ownership policy any token also
+    /// has access to groups owned by its user or by other tokens of the same user.
+    pub fn check_backup_owner(&self, owner: &Authid, auth_id: &Authid) -> Result<(), Error> {
+        if self.inner.token_ownership == TokenOwnership::User
+            && auth_id.is_token()
+            && owner.user() == auth_id.user()
+        {
+            return Ok(());
+        }
+        check_backup_owner(owner, auth_id)
+    }
+
+    /// Returns the owner for a new backup group created by `auth_id`.
+    ///
+    /// With the `user` token ownership policy, groups created by a token are owned by its user.
+    pub fn new_group_owner(&self, auth_id: &Authid) -> Authid {
+        match self.inner.token_ownership {
+            TokenOwnership::User if auth_id.is_token() => Authid::from(auth_id.user().clone()),
+            _ => auth_id.clone(),
+        }
     }
 
     /// Set the backup owner.
@@ -683,6 +856,11 @@ impl DataStore {
         writeln!(file, "{}", auth_id)
             .map_err(|err| format_err!("unable to write owner file  {:?} - {}", path, err))?;
 
+        #[cfg(feature = "sqlite-index")]
+        if let Some(index) = self.content_index() {
+            index.owner_changed(ns, backup_group, auth_id);
+        }
+
         Ok(())
     }
 
@@ -709,6 +887,17 @@ impl DataStore {
 
         full_path.push(&backup_group.id);
 
+        if !full_path.exists() {
+            if let Some(tombstone) = self.group_tombstones().lookup(ns, backup_group)? {
+                bail!(
+                    "backup group {} was removed recently by {}, its name is reserved until {}",
+                    backup_group,
+                    tombstone.removed_by,
+                    proxmox_time::epoch_to_rfc3339_utc(tombstone.expires)?,
+                );
+            }
+        }
+
         // create the last component now
         match std::fs::create_dir(&full_path) {
             Ok(_) => {
@@ -857,6 +1046,33 @@ impl DataStore {
         Ok(self.iter_backup_type(ns, ty)?.ok())
     }
 
+    /// Returns the backup types which have a directory in a namespace, including custom ones.
+    pub fn list_backup_types(&self, ns: &BackupNamespace) -> Result<Vec<BackupType>, Error> {
+        let path = self.namespace_path(ns);
+        let entries = match std::fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => bail!("unable to read {path:?} - {err}"),
+        };
+
+        let mut types = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            if let Some(ty) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse().ok())
+            {
+                types.push(ty);
+            }
+        }
+        types.sort_unstable();
+        Ok(types)
+    }
+
     /// Get a streaming iter over top-level backup groups of a datatstore
     ///
     /// The iterated item is still a Result that can contain errors from rather unexptected FS or
@@ -889,7 +1105,7 @@ impl DataStore {
         ListGroups::new(Arc::clone(self), ns)?.collect()
     }
 
-    pub fn list_images(&self) -> Result<Vec<PathBuf>, Error> {
+    pub fn list_images(&self, worker: &dyn WorkerTaskContext) -> Result<Vec<PathBuf>, Error> {
         let base = self.base_path();
 
         let mut list = vec![];
@@ -929,6 +1145,10 @@ impl DataStore {
             }
         };
         for entry in walker.filter_entry(|e| !is_hidden(e)) {
+            // walking huge datastores can take a long time
+            worker.check_abort()?;
+            worker.fail_on_shutdown()?;
+
             let path = match entry {
                 Ok(entry) => entry.into_path(),
                 Err(err) => {
@@ -955,6 +1175,7 @@ impl DataStore {
         file_name: &Path, // only used for error reporting
         status: &mut GarbageCollectionStatus,
         worker: &dyn WorkerTaskContext,
+        throttle: &mut MaintenanceThrottle,
     ) -> Result<(), Error> {
         status.index_file_count += 1;
         status.index_data_bytes += index.index_bytes();
@@ -962,6 +1183,7 @@ impl DataStore {
         for pos in 0..index.index_count() {
             worker.check_abort()?;
             worker.fail_on_shutdown()?;
+            throttle.pause(worker)?;
             let digest = index.index_digest(pos).unwrap();
             if !self.inner.chunk_store.cond_touch_chunk(digest, false)? {
                 let hex = hex::encode(digest);
@@ -989,8 +1211,9 @@ impl DataStore {
         &self,
         status: &mut GarbageCollectionStatus,
         worker: &dyn WorkerTaskContext,
+        throttle: &mut MaintenanceThrottle,
     ) -> Result<(), Error> {
-        let image_list = self.list_images()?;
+        let image_list = self.list_images(worker)?;
         let image_count = image_list.len();
 
         let mut last_percentage: usize = 0;
@@ -1017,12 +1240,12 @@ impl DataStore {
                             let index = FixedIndexReader::new(file).map_err(|e| {
                                 format_err!("can't read index '{}' - {}", img.to_string_lossy(), e)
                             })?;
-                            self.index_mark_used_chunks(index, &img, status, worker)?;
+                            self.index_mark_used_chunks(index, &img, status, worker, throttle)?;
                         } else if archive_type == ArchiveType::DynamicIndex {
                             let index = DynamicIndexReader::new(file).map_err(|e| {
                                 format_err!("can't read index '{}' - {}", img.to_string_lossy(), e)
                             })?;
-                            self.index_mark_used_chunks(index, &img, status, worker)?;
+                            self.index_mark_used_chunks(index, &img, status, worker, throttle)?;
                         }
                     }
                 }
@@ -1085,9 +1308,11 @@ impl DataStore {
                 ..Default::default()
             };
 
+            let mut throttle = self.maintenance_throttle();
+
             task_log!(worker, "Start GC phase1 (mark used chunks)");
 
-            self.mark_used_chunks(&mut gc_status, worker)?;
+            self.mark_used_chunks(&mut gc_status, worker, &mut throttle)?;
 
             task_log!(worker, "Start GC phase2 (sweep unused chunks)");
             self.inner.chunk_store.sweep_unused_chunks(
@@ -1095,8 +1320,18 @@ impl DataStore {
                 phase1_start_time,
                 &mut gc_status,
                 worker,
+                &mut throttle,
             )?;
 
+            let throttled = throttle.total_delay();
+            if !throttled.is_zero() {
+                task_log!(
+                    worker,
+                    "Throttled for {:.1}s due to IO latency or load",
+                    throttled.as_secs_f64(),
+                );
+            }
+
             task_log!(
                 worker,
                 "Removed garbage: {}",
@@ -1184,6 +1419,16 @@ impl DataStore {
         self.inner.chunk_store.chunk_path(digest)
     }
 
+    /// Returns the quarantine of corrupt chunks of this datastore.
+    pub fn chunk_quarantine(&self) -> ChunkQuarantine {
+        ChunkQuarantine::new(self.base_path())
+    }
+
+    /// Returns the tombstones of removed backup groups of this datastore.
+    pub fn group_tombstones(&self) -> GroupTombstones {
+        GroupTombstones::new(self.base_path(), self.inner.group_tombstone_lifetime)
+    }
+
     pub fn cond_touch_chunk(&self, digest: &[u8; 32], assert_exists: bool) -> Result<bool, Error> {
         self.inner
             .chunk_store
@@ -1194,6 +1439,24 @@ impl DataStore {
         self.inner.chunk_store.insert_chunk(chunk, digest)
     }
 
+    /// Read the raw chunk data, removing the at-rest encryption layer if present.
+    pub fn read_raw_chunk(&self, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        self.inner.chunk_store.read_raw_chunk(digest)
+    }
+
+    /// The datastore key used for at-rest encryption, if configured.
+    pub fn at_rest_crypt(&self) -> Option<Arc<AtRestCrypt>> {
+        self.inner.chunk_store.at_rest_crypt()
+    }
+
+    /// Apply the at-rest encryption layer (if configured) to raw blob data before writing it.
+    pub fn encode_at_rest<'a>(&self, raw_data: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+        match self.at_rest_crypt() {
+            Some(crypt) => Ok(Cow::Owned(crypt.encrypt(raw_data)?)),
+            None => Ok(Cow::Borrowed(raw_data)),
+        }
+    }
+
     pub fn stat_chunk(&self, digest: &[u8; 32]) -> Result<std::fs::Metadata, Error> {
         let (chunk_path, _digest_str) = self.inner.chunk_store.chunk_path(digest);
         std::fs::metadata(chunk_path).map_err(Error::from)
@@ -1203,8 +1466,8 @@ impl DataStore {
         let (chunk_path, digest_str) = self.inner.chunk_store.chunk_path(digest);
 
         proxmox_lang::try_block!({
-            let mut file = std::fs::File::open(&chunk_path)?;
-            DataBlob::load_from_reader(&mut file)
+            let raw_data = read_at_rest(self.at_rest_crypt().as_deref(), &chunk_path)?;
+            DataBlob::load_from_reader(&mut &raw_data[..])
         })
         .map_err(|err| {
             format_err!(
@@ -1237,6 +1500,11 @@ impl DataStore {
             }
         }
 
+        #[cfg(feature = "sqlite-index")]
+        if let Some(index) = self.content_index() {
+            index.snapshot_changed(backup_dir);
+        }
+
         Ok(())
     }
 
@@ -1244,6 +1512,40 @@ impl DataStore {
         self.inner.verify_new
     }
 
+    /// Returns if Merkle roots over the chunk digests are recorded for new snapshots.
+    pub fn merkle_roots(&self) -> bool {
+        self.inner.merkle_roots
+    }
+
+    pub(crate) fn listing_cache(&self) -> Option<&ListingCache> {
+        self.inner.listing_cache.as_ref()
+    }
+
+    /// Returns the content index, if enabled for this datastore.
+    #[cfg(feature = "sqlite-index")]
+    pub fn content_index(&self) -> Option<&ContentIndex> {
+        self.inner.content_index.as_ref()
+    }
+
+    /// Returns a throttle for maintenance tasks, with the limits configured for this datastore.
+    pub fn maintenance_throttle(&self) -> MaintenanceThrottle {
+        MaintenanceThrottle::new(
+            &self.inner.chunk_store.base_path(),
+            self.inner.throttle_latency,
+            self.inner.throttle_load,
+        )
+    }
+
+    /// Returns the hash algorithm of the chunk digests of this datastore.
+    pub fn digest_algorithm(&self) -> ChunkDigestAlgorithm {
+        self.inner.digest_algorithm
+    }
+
+    /// Returns if the file names in the catalogs are indexed for searches.
+    pub fn filename_index(&self) -> bool {
+        self.inner.filename_index
+    }
+
     /// returns a list of chunks sorted by their inode number on disk chunks that couldn't get
     /// stat'ed are placed at the end of the list
     pub fn get_chunks_in_order<F, A>(
@@ -1362,6 +1664,12 @@ impl DataStore {
     }
     */
 
+    /// Returns whether index files, blobs and manifests have to be synced to disk when written,
+    /// see [`DatastoreFSyncLevel`].
+    pub fn sync_metadata(&self) -> bool {
+        self.inner.chunk_store.sync_metadata()
+    }
+
     /// Syncs the filesystem of the datastore if 'sync_level' is set to
     /// [`DatastoreFSyncLevel::Filesystem`]. Uses syncfs(2).
     pub fn try_ensure_sync_level(&self) -> Result<(), Error> {
@@ -18,7 +18,7 @@ use pxar::accessor::{MaybeReady, ReadAt, ReadAtOperation};
 use pbs_tools::lru_cache::LruCache;
 
 use crate::chunk_stat::ChunkStat;
-use crate::chunk_store::ChunkStore;
+use crate::chunk_store::{fsync_dir, ChunkStore};
 use crate::data_blob::{DataBlob, DataChunkBuilder};
 use crate::file_formats;
 use crate::index::{ChunkReadInfo, IndexFile};
@@ -362,10 +362,21 @@ impl DynamicIndexWriter {
         self.writer.write_all(&index_csum)?;
         self.writer.flush()?;
 
+        let sync = self.store.sync_metadata();
+        if sync {
+            self.writer.get_ref().sync_all()?;
+        }
+
         if let Err(err) = std::fs::rename(&self.tmp_filename, &self.filename) {
             bail!("Atomic rename file {:?} failed - {}", self.filename, err);
         }
 
+        if sync {
+            if let Some(parent) = self.filename.parent() {
+                fsync_dir(parent)?;
+            }
+        }
+
         Ok(index_csum)
     }
 
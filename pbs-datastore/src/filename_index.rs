@@ -0,0 +1,500 @@
+//! Inverted index of the file names in the catalogs of a backup group
+//!
+//! If enabled for a datastore, the entries of the catalogs of each backup group are recorded in
+//! an SQLite database in the group directory, so file searches do not need to decode the catalog
+//! of every snapshot. Entries are stored as intervals of consecutive snapshots in which a path
+//! exists unchanged, so unchanged files only take up space once per group.
+//!
+//! New snapshots are appended to the index after a backup finished or before searching a group.
+//! Snapshots added out of order, for example by a sync of older snapshots, cause a rebuild of
+//! the index of the group. Removed snapshots are only dropped from the index on the next update,
+//! queries always check which snapshots still exist.
+//!
+//! The index itself requires the `sqlite-index` feature, the catalog helpers are always available.
+
+use std::io::{Read, Seek};
+
+use anyhow::{bail, format_err, Error};
+use pathpatterns::MatchEntry;
+
+use pbs_api_types::CryptMode;
+
+use crate::backup_info::BackupDir;
+use crate::catalog::{CatalogEntryType, CatalogReader, DirEntry, DirEntryAttribute};
+use crate::dynamic_index::{BufferedDynamicReader, DynamicIndexReader};
+use crate::{LocalChunkReader, CATALOG_NAME};
+
+#[cfg(feature = "sqlite-index")]
+use std::collections::{BTreeSet, HashMap};
+#[cfg(feature = "sqlite-index")]
+use std::time::Duration;
+
+#[cfg(feature = "sqlite-index")]
+use pathpatterns::{MatchList, MatchType, PatternFlag};
+#[cfg(feature = "sqlite-index")]
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+
+#[cfg(feature = "sqlite-index")]
+use crate::backup_info::BackupGroup;
+
+#[cfg(feature = "sqlite-index")]
+const INDEX_FILE: &str = ".filename-index.db";
+
+#[cfg(feature = "sqlite-index")]
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS snapshots (
+        backup_time INTEGER PRIMARY KEY
+    );
+    CREATE TABLE IF NOT EXISTS names (
+        id INTEGER PRIMARY KEY,
+        name BLOB NOT NULL UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS entries (
+        id INTEGER PRIMARY KEY,
+        name_id INTEGER NOT NULL,
+        archive TEXT NOT NULL,
+        path BLOB NOT NULL,
+        entry_type TEXT NOT NULL,
+        size INTEGER,
+        mtime INTEGER,
+        first_time INTEGER NOT NULL,
+        last_time INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS entries_name ON entries (name_id);
+    CREATE INDEX IF NOT EXISTS entries_last ON entries (last_time, archive, path);
+";
+
+/// Open the catalog of a finished snapshot.
+pub fn open_catalog(
+    backup_dir: &BackupDir,
+) -> Result<CatalogReader<BufferedDynamicReader<LocalChunkReader>>, Error> {
+    let (manifest, files) = backup_dir.read_backup_index()?;
+    for file in files {
+        if file.filename == CATALOG_NAME && file.crypt_mode == Some(CryptMode::Encrypt) {
+            bail!("cannot decode '{CATALOG_NAME}' - is encrypted");
+        }
+    }
+
+    let path = backup_dir.full_path().join(CATALOG_NAME);
+
+    let index = DynamicIndexReader::open(&path)
+        .map_err(|err| format_err!("unable to read dynamic index '{:?}' - {}", &path, err))?;
+
+    let (csum, size) = index.compute_csum();
+    manifest.verify_file(CATALOG_NAME, &csum, size)?;
+
+    let chunk_reader = LocalChunkReader::new(backup_dir.datastore().clone(), None, CryptMode::None);
+    let reader = BufferedDynamicReader::new(index, chunk_reader);
+
+    Ok(CatalogReader::new(reader))
+}
+
+/// An entry of a catalog matching a search pattern.
+pub struct CatalogMatch {
+    /// The archive containing the entry, for example `root.pxar.didx`.
+    pub archive: String,
+    /// The path of the entry within the archive.
+    pub path: Vec<u8>,
+    /// Type of the entry.
+    pub entry_type: CatalogEntryType,
+    /// Size of regular files.
+    pub size: Option<u64>,
+    /// Modification time of regular files.
+    pub mtime: Option<i64>,
+}
+
+impl CatalogMatch {
+    fn new(archive: &str, path: &[u8], entry: &DirEntry) -> Self {
+        let (size, mtime) = match entry.attr {
+            DirEntryAttribute::File { size, mtime } => (Some(size), Some(mtime)),
+            _ => (None, None),
+        };
+        Self {
+            archive: archive.to_string(),
+            path: path.to_vec(),
+            entry_type: CatalogEntryType::from(&entry.attr),
+            size,
+            mtime,
+        }
+    }
+}
+
+/// Search a catalog for entries matching `pattern`, by decoding all of it.
+pub fn search_catalog<R: Read + Seek>(
+    catalog: &mut CatalogReader<R>,
+    pattern: &MatchEntry,
+) -> Result<Vec<CatalogMatch>, Error> {
+    let mut result = Vec::new();
+
+    let root = catalog.root()?;
+    for archive in catalog.read_dir(&root)? {
+        let archive_name = String::from_utf8_lossy(&archive.name).to_string();
+        catalog.find(
+            &archive,
+            &mut Vec::new(),
+            &[pattern],
+            &mut |path: &[u8], entry: &DirEntry| -> Result<(), Error> {
+                result.push(CatalogMatch::new(&archive_name, path, entry));
+                Ok(())
+            },
+        )?;
+    }
+
+    Ok(result)
+}
+
+/// Call `callback` for every entry below `parent`, with its path relative to `parent`.
+pub fn walk_catalog<R: Read + Seek>(
+    catalog: &mut CatalogReader<R>,
+    parent: &DirEntry,
+    path: &mut Vec<u8>,
+    callback: &mut dyn FnMut(&[u8], &DirEntry) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let path_len = path.len();
+    for entry in catalog.read_dir(parent)? {
+        path.truncate(path_len);
+        path.push(b'/');
+        path.extend(&entry.name);
+        callback(path, &entry)?;
+        if entry.is_directory() {
+            walk_catalog(catalog, &entry, path, callback)?;
+        }
+    }
+    path.truncate(path_len);
+    Ok(())
+}
+
+// The file name part of a search pattern, to preselect candidates by name. Returns `None` if
+// the pattern can match any name.
+#[cfg(feature = "sqlite-index")]
+fn name_pattern(pattern: &str) -> Result<Option<MatchEntry>, Error> {
+    let name = match pattern.trim_end_matches('/').rsplit('/').next() {
+        Some(name) if !name.is_empty() && !name.contains("**") => name,
+        _ => return Ok(None),
+    };
+    Ok(Some(MatchEntry::parse_pattern(
+        name,
+        PatternFlag::PATH_NAME,
+        MatchType::Include,
+    )?))
+}
+
+#[cfg(feature = "sqlite-index")]
+fn entry_type_mode(entry_type: CatalogEntryType) -> Option<u32> {
+    Some(match entry_type {
+        CatalogEntryType::Directory => pxar::mode::IFDIR,
+        CatalogEntryType::File => pxar::mode::IFREG,
+        CatalogEntryType::Symlink => pxar::mode::IFLNK,
+        CatalogEntryType::Hardlink => return None,
+        CatalogEntryType::BlockDevice => pxar::mode::IFBLK,
+        CatalogEntryType::CharDevice => pxar::mode::IFCHR,
+        CatalogEntryType::Fifo => pxar::mode::IFIFO,
+        CatalogEntryType::Socket => pxar::mode::IFSOCK,
+    } as u32)
+}
+
+/// The file name index of a backup group.
+#[cfg(feature = "sqlite-index")]
+pub struct FilenameIndex {
+    group: BackupGroup,
+    conn: Connection,
+}
+
+#[cfg(feature = "sqlite-index")]
+impl FilenameIndex {
+    /// Open the index of `group`, creating it if it does not exist yet.
+    pub fn open(group: &BackupGroup) -> Result<Self, Error> {
+        let path = group.full_group_path().join(INDEX_FILE);
+        let created = !path.exists();
+
+        let conn = Connection::open(&path)
+            .map_err(|err| format_err!("unable to open filename index {path:?} - {err}"))?;
+        conn.busy_timeout(Duration::from_secs(10))?;
+        conn.execute_batch(SCHEMA)?;
+
+        if created {
+            // the API daemon runs as root, the proxy must still be able to write
+            let backup_user = pbs_config::backup_user()?;
+            nix::unistd::chown(&path, Some(backup_user.uid), Some(backup_user.gid))?;
+            nix::sys::stat::fchmodat(
+                None,
+                &path,
+                nix::sys::stat::Mode::from_bits_truncate(0o660),
+                nix::sys::stat::FchmodatFlags::FollowSymlink,
+            )?;
+        }
+
+        Ok(Self {
+            group: group.clone(),
+            conn,
+        })
+    }
+
+    /// Remove the index of `group`, so that it is built anew from the catalogs on the next
+    /// update. Required after entries were removed from the catalogs of indexed snapshots.
+    pub fn remove(group: &BackupGroup) -> Result<(), Error> {
+        let path = group.full_group_path().join(INDEX_FILE);
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => bail!("unable to remove filename index {path:?} - {err}"),
+        }
+    }
+
+    // finished snapshots with a catalog, oldest first
+    fn indexable_snapshots(&self) -> Result<Vec<BackupDir>, Error> {
+        let mut list = self.group.list_backups()?;
+        list.retain(|info| info.is_finished() && info.files.iter().any(|f| f == CATALOG_NAME));
+        list.sort_unstable_by_key(|info| info.backup_dir.backup_time());
+        Ok(list.into_iter().map(|info| info.backup_dir).collect())
+    }
+
+    /// Add all snapshots of the group which are not indexed yet, and drop removed ones.
+    ///
+    /// Returns the number of newly indexed snapshots.
+    pub fn update(&mut self) -> Result<usize, Error> {
+        let snapshots = self.indexable_snapshots()?;
+        let existing: BTreeSet<i64> = snapshots.iter().map(|s| s.backup_time()).collect();
+
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let indexed = read_indexed_snapshots(&tx)?;
+
+        let removed: Vec<i64> = indexed.difference(&existing).copied().collect();
+        if !removed.is_empty() {
+            for time in removed {
+                tx.execute(
+                    "DELETE FROM snapshots WHERE backup_time = ?1",
+                    params![time],
+                )?;
+            }
+            // drop intervals which do not cover any indexed snapshot anymore
+            tx.execute(
+                "DELETE FROM entries WHERE NOT EXISTS (SELECT 1 FROM snapshots \
+                WHERE backup_time BETWEEN entries.first_time AND entries.last_time)",
+                [],
+            )?;
+            tx.execute(
+                "DELETE FROM names WHERE id NOT IN (SELECT name_id FROM entries)",
+                [],
+            )?;
+        }
+
+        let mut newest = indexed.intersection(&existing).next_back().copied();
+
+        let mut missing: Vec<&BackupDir> = snapshots
+            .iter()
+            .filter(|s| !indexed.contains(&s.backup_time()))
+            .collect();
+
+        // intervals can only be extended at the end, start over if older snapshots appeared
+        if missing.iter().any(|s| Some(s.backup_time()) <= newest) {
+            log::info!("rebuilding filename index of {:?}", self.group);
+            for table in ["snapshots", "entries", "names"] {
+                tx.execute(&format!("DELETE FROM {table}"), [])?;
+            }
+            newest = None;
+            missing = snapshots.iter().collect();
+        }
+
+        let count = missing.len();
+        for snapshot in missing {
+            add_snapshot(&tx, snapshot, newest)?;
+            newest = Some(snapshot.backup_time());
+        }
+
+        tx.commit()?;
+
+        Ok(count)
+    }
+
+    /// Search the indexed snapshots for entries matching `pattern`.
+    ///
+    /// Returns the matching entries with the snapshot containing them, newest snapshots first.
+    pub fn search(&self, pattern: &str) -> Result<Vec<(BackupDir, CatalogMatch)>, Error> {
+        let full_pattern =
+            MatchEntry::parse_pattern(pattern, PatternFlag::PATH_NAME, MatchType::Include)?;
+        let name_pattern = name_pattern(pattern)?;
+
+        let existing: HashMap<i64, BackupDir> = self
+            .indexable_snapshots()?
+            .into_iter()
+            .map(|s| (s.backup_time(), s))
+            .collect();
+        let snapshots: Vec<i64> = read_indexed_snapshots(&self.conn)?
+            .into_iter()
+            .filter(|time| existing.contains_key(time))
+            .collect();
+
+        let mut name_ids = Vec::new();
+        let mut stmt = self.conn.prepare("SELECT id, name FROM names")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let name: Vec<u8> = row.get(1)?;
+            let matches = match &name_pattern {
+                Some(name_pattern) => {
+                    let mut path = vec![b'/'];
+                    path.extend(&name);
+                    matches!(
+                        [name_pattern].matches(&path, None),
+                        Ok(Some(MatchType::Include))
+                    )
+                }
+                None => true,
+            };
+            if matches {
+                name_ids.push(row.get::<_, i64>(0)?);
+            }
+        }
+
+        let mut result = Vec::new();
+        let mut stmt = self.conn.prepare(
+            "SELECT archive, path, entry_type, size, mtime, first_time, last_time \
+            FROM entries WHERE name_id = ?1",
+        )?;
+        for name_id in name_ids {
+            let mut rows = stmt.query(params![name_id])?;
+            while let Some(row) = rows.next()? {
+                let path: Vec<u8> = row.get(1)?;
+                let entry_type: String = row.get(2)?;
+                let entry_type = CatalogEntryType::try_from(entry_type.as_bytes()[0])?;
+                if !matches!(
+                    [&full_pattern].matches(&path, entry_type_mode(entry_type)),
+                    Ok(Some(MatchType::Include))
+                ) {
+                    continue;
+                }
+                let archive: String = row.get(0)?;
+                let size: Option<i64> = row.get(3)?;
+                let mtime: Option<i64> = row.get(4)?;
+                let first_time: i64 = row.get(5)?;
+                let last_time: i64 = row.get(6)?;
+
+                let start = snapshots.partition_point(|time| *time < first_time);
+                for time in snapshots[start..]
+                    .iter()
+                    .take_while(|time| **time <= last_time)
+                {
+                    result.push((
+                        existing[time].clone(),
+                        CatalogMatch {
+                            archive: archive.clone(),
+                            path: path.clone(),
+                            entry_type,
+                            size: size.map(|size| size as u64),
+                            mtime,
+                        },
+                    ));
+                }
+            }
+        }
+
+        result.sort_by(|(a, _), (b, _)| b.backup_time().cmp(&a.backup_time()));
+
+        Ok(result)
+    }
+}
+
+#[cfg(feature = "sqlite-index")]
+fn read_indexed_snapshots(conn: &Connection) -> Result<BTreeSet<i64>, Error> {
+    let mut stmt = conn.prepare("SELECT backup_time FROM snapshots")?;
+    let times = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<BTreeSet<i64>, _>>()?;
+    Ok(times)
+}
+
+// Record the entries of the catalog of `snapshot`, extending the intervals of entries which are
+// unchanged since the `previous` indexed snapshot.
+#[cfg(feature = "sqlite-index")]
+fn add_snapshot(
+    tx: &Transaction,
+    snapshot: &BackupDir,
+    previous: Option<i64>,
+) -> Result<(), Error> {
+    let time = snapshot.backup_time();
+
+    tx.execute(
+        "INSERT INTO snapshots (backup_time) VALUES (?1)",
+        params![time],
+    )?;
+
+    // snapshots with unreadable catalogs stay recorded, so they are not retried on every update
+    let mut catalog = match open_catalog(snapshot) {
+        Ok(catalog) => catalog,
+        Err(err) => {
+            log::warn!(
+                "not indexing catalog of {:?} - {err}",
+                snapshot.relative_path()
+            );
+            return Ok(());
+        }
+    };
+
+    let mut lookup_name = tx.prepare_cached("SELECT id FROM names WHERE name = ?1")?;
+    let mut insert_name = tx.prepare_cached("INSERT INTO names (name) VALUES (?1)")?;
+    let mut lookup_entry = tx.prepare_cached(
+        "SELECT id FROM entries WHERE last_time = ?1 AND archive = ?2 AND path = ?3 \
+        AND entry_type = ?4 AND size IS ?5 AND mtime IS ?6",
+    )?;
+    let mut extend_entry = tx.prepare_cached("UPDATE entries SET last_time = ?1 WHERE id = ?2")?;
+    let mut insert_entry = tx.prepare_cached(
+        "INSERT INTO entries \
+        (name_id, archive, path, entry_type, size, mtime, first_time, last_time) \
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)",
+    )?;
+
+    let root = catalog.root()?;
+    for archive in catalog.read_dir(&root)? {
+        let archive_name = String::from_utf8_lossy(&archive.name).to_string();
+        walk_catalog(
+            &mut catalog,
+            &archive,
+            &mut Vec::new(),
+            &mut |path: &[u8], entry: &DirEntry| -> Result<(), Error> {
+                let item = CatalogMatch::new(&archive_name, path, entry);
+                let entry_type = item.entry_type.to_string();
+                let size = item.size.map(|size| size as i64);
+
+                if let Some(previous) = previous {
+                    let id: Option<i64> = lookup_entry
+                        .query_row(
+                            params![previous, archive_name, path, entry_type, size, item.mtime],
+                            |row| row.get(0),
+                        )
+                        .optional()?;
+                    if let Some(id) = id {
+                        extend_entry.execute(params![time, id])?;
+                        return Ok(());
+                    }
+                }
+
+                let name_id: Option<i64> = lookup_name
+                    .query_row(params![entry.name], |row| row.get(0))
+                    .optional()?;
+                let name_id = match name_id {
+                    Some(id) => id,
+                    None => {
+                        insert_name.execute(params![entry.name])?;
+                        tx.last_insert_rowid()
+                    }
+                };
+
+                insert_entry.execute(params![
+                    name_id,
+                    archive_name,
+                    path,
+                    entry_type,
+                    size,
+                    item.mtime,
+                    time
+                ])?;
+                Ok(())
+            },
+        )?;
+    }
+
+    Ok(())
+}
@@ -12,7 +12,7 @@ use proxmox_sys::process_locker::ProcessLockSharedGuard;
 use proxmox_uuid::Uuid;
 
 use crate::chunk_stat::ChunkStat;
-use crate::chunk_store::ChunkStore;
+use crate::chunk_store::{fsync_dir, ChunkStore};
 use crate::data_blob::ChunkInfo;
 use crate::file_formats;
 use crate::index::{ChunkReadInfo, IndexFile};
@@ -354,10 +354,21 @@ impl FixedIndexWriter {
         self.file.write_all(&index_csum)?;
         self.file.flush()?;
 
+        let sync = self.store.sync_metadata();
+        if sync {
+            self.file.sync_all()?;
+        }
+
         if let Err(err) = std::fs::rename(&self.tmp_filename, &self.filename) {
             bail!("Atomic rename file {:?} failed - {}", self.filename, err);
         }
 
+        if sync {
+            if let Some(parent) = self.filename.parent() {
+                fsync_dir(parent)?;
+            }
+        }
+
         Ok(index_csum)
     }
 
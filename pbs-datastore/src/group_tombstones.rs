@@ -0,0 +1,150 @@
+//! Tombstones of removed backup groups
+//!
+//! Removing a backup group leaves a tombstone with its former owner, if enabled for the
+//! datastore. Until the tombstone expires, the group name cannot be used for new backups, so a
+//! backup or sync job does not silently start over in a group whose history was just removed.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Error};
+
+use pbs_api_types::{Authid, BackupGroup, BackupNamespace, GroupTombstone};
+use proxmox_sys::fs::{file_read_optional_string, open_file_locked, replace_file, CreateOptions};
+
+const TOMBSTONE_FILE: &str = ".group-tombstones.json";
+const TOMBSTONE_LOCK_FILE: &str = ".group-tombstones.lck";
+
+fn matches(tombstone: &GroupTombstone, ns: &BackupNamespace, group: &BackupGroup) -> bool {
+    let tombstone_ns = tombstone.ns.clone().unwrap_or_default();
+    &tombstone_ns == ns && &tombstone.group == group
+}
+
+/// The group tombstones of a datastore.
+pub struct GroupTombstones {
+    base: PathBuf,
+    /// Lifetime of new tombstones in seconds, `None` if disabled.
+    lifetime: Option<i64>,
+}
+
+impl GroupTombstones {
+    pub(crate) fn new<P: AsRef<Path>>(base: P, lifetime: Option<i64>) -> Self {
+        Self {
+            base: base.as_ref().to_owned(),
+            lifetime,
+        }
+    }
+
+    /// Returns whether removed groups get a tombstone.
+    pub fn enabled(&self) -> bool {
+        self.lifetime.is_some()
+    }
+
+    // expired tombstones are dropped on load
+    fn load(&self) -> Result<Vec<GroupTombstone>, Error> {
+        let path = self.base.join(TOMBSTONE_FILE);
+        let mut list: Vec<GroupTombstone> = match file_read_optional_string(&path)? {
+            Some(data) => serde_json::from_str(&data)
+                .map_err(|err| format_err!("unable to parse {path:?} - {err}"))?,
+            None => Vec::new(),
+        };
+        let now = proxmox_time::epoch_i64();
+        list.retain(|tombstone| tombstone.expires > now);
+        Ok(list)
+    }
+
+    /// Modify the tombstones while holding their lock.
+    fn update<R, F>(&self, func: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Vec<GroupTombstone>) -> R,
+    {
+        let backup_user = pbs_config::backup_user()?;
+        let options = CreateOptions::new()
+            .owner(backup_user.uid)
+            .group(backup_user.gid)
+            .perm(nix::sys::stat::Mode::from_bits_truncate(0o660));
+
+        let timeout = std::time::Duration::new(10, 0);
+        let _lock = open_file_locked(
+            self.base.join(TOMBSTONE_LOCK_FILE),
+            timeout,
+            true,
+            options.clone(),
+        )?;
+
+        let mut data = self.load()?;
+        let result = func(&mut data);
+
+        let path = self.base.join(TOMBSTONE_FILE);
+        if data.is_empty() {
+            if let Err(err) = std::fs::remove_file(&path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    return Err(format_err!("unable to remove {path:?} - {err}"));
+                }
+            }
+        } else {
+            replace_file(path, &serde_json::to_vec(&data)?, options, true)?;
+        }
+
+        Ok(result)
+    }
+
+    /// List all tombstones which did not expire yet.
+    pub fn list(&self) -> Result<Vec<GroupTombstone>, Error> {
+        self.load()
+    }
+
+    /// Returns the tombstone of a group, if it was removed recently.
+    ///
+    /// Always returns `None` if tombstones are disabled, which lifts all reservations.
+    pub fn lookup(
+        &self,
+        ns: &BackupNamespace,
+        group: &BackupGroup,
+    ) -> Result<Option<GroupTombstone>, Error> {
+        if !self.enabled() {
+            return Ok(None);
+        }
+        Ok(self
+            .load()?
+            .into_iter()
+            .find(|tombstone| matches(tombstone, ns, group)))
+    }
+
+    /// Record the removal of a group, does nothing if tombstones are disabled.
+    pub fn add(
+        &self,
+        ns: &BackupNamespace,
+        group: &BackupGroup,
+        owner: Authid,
+        removed_by: Authid,
+    ) -> Result<(), Error> {
+        let lifetime = match self.lifetime {
+            Some(lifetime) => lifetime,
+            None => return Ok(()),
+        };
+        let now = proxmox_time::epoch_i64();
+        self.update(|data| {
+            data.retain(|tombstone| !matches(tombstone, ns, group));
+            data.push(GroupTombstone {
+                ns: (!ns.is_root()).then(|| ns.clone()),
+                group: group.clone(),
+                owner,
+                removed: now,
+                removed_by,
+                expires: now + lifetime,
+            });
+        })
+    }
+
+    /// Remove all tombstones matching `filter`, returns how many were removed.
+    pub fn purge<F>(&self, filter: F) -> Result<usize, Error>
+    where
+        F: Fn(&GroupTombstone) -> bool,
+    {
+        self.update(|data| {
+            let count = data.len();
+            data.retain(|tombstone| !filter(tombstone));
+            count - data.len()
+        })
+    }
+}
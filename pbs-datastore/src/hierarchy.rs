@@ -5,10 +5,12 @@ use std::sync::Arc;
 
 use anyhow::{bail, format_err, Error};
 
-use pbs_api_types::{BackupNamespace, BackupType, BACKUP_DATE_REGEX, BACKUP_ID_REGEX};
+use pbs_api_types::{
+    backup_time_from_string, BackupNamespace, BackupType, BACKUP_DATE_REGEX, BACKUP_ID_REGEX,
+};
 use proxmox_sys::fs::get_file_type;
 
-use crate::backup_info::{BackupDir, BackupGroup};
+use crate::backup_info::{read_snapshot_dir, BackupDir, BackupGroup, BackupInfo};
 use crate::DataStore;
 
 /// A iterator for all BackupDir's (Snapshots) in a BackupGroup
@@ -56,7 +58,7 @@ impl Iterator for ListSnapshots {
             };
             if let Ok(name) = entry.file_name().to_str() {
                 if BACKUP_DATE_REGEX.is_match(name) {
-                    let backup_time = match proxmox_time::parse_rfc3339(name) {
+                    let backup_time = match backup_time_from_string(name) {
                         Ok(time) => time,
                         Err(err) => return Some(Err(err)),
                     };
@@ -68,6 +70,74 @@ impl Iterator for ListSnapshots {
     }
 }
 
+/// A streaming iterator over the snapshots of a group, including their files.
+///
+/// Snapshot directories are read relative to the open group directory, and the protection marker
+/// is taken from the same listing, so each snapshot costs a single directory read. All entries
+/// are read via `readdir`, which fetches them from the kernel in `getdents64` batches.
+pub struct ListBackupInfos {
+    group: BackupGroup,
+    fd: proxmox_sys::fs::ReadDir,
+}
+
+impl ListBackupInfos {
+    pub fn new(group: BackupGroup) -> Result<Self, Error> {
+        let group_path = group.full_group_path();
+        Ok(ListBackupInfos {
+            fd: proxmox_sys::fs::read_subdir(libc::AT_FDCWD, &group_path)
+                .map_err(|err| format_err!("read dir {group_path:?} - {err}"))?,
+            group,
+        })
+    }
+}
+
+impl Iterator for ListBackupInfos {
+    type Item = Result<BackupInfo, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let item = self.fd.next()?;
+            let entry = match item {
+                Ok(ref entry) => {
+                    match entry.file_type() {
+                        Some(nix::dir::Type::Directory) => entry, // OK
+                        None => match get_file_type(entry.parent_fd(), entry.file_name()) {
+                            Ok(nix::dir::Type::Directory) => entry,
+                            Ok(_) => continue,
+                            Err(err) => {
+                                log::info!(
+                                    "error listing snapshots for {}: {err}",
+                                    self.group.group()
+                                );
+                                continue;
+                            }
+                        },
+                        _ => continue,
+                    }
+                }
+                Err(err) => return Some(Err(err)),
+            };
+            if let Ok(name) = entry.file_name().to_str() {
+                if BACKUP_DATE_REGEX.is_match(name) {
+                    return Some(
+                        self.group
+                            .backup_dir_with_rfc3339(name)
+                            .and_then(|backup_dir| {
+                                let (files, protected) =
+                                    read_snapshot_dir(entry.parent_fd(), entry.file_name())?;
+                                Ok(BackupInfo {
+                                    backup_dir,
+                                    files,
+                                    protected,
+                                })
+                            }),
+                    );
+                }
+            }
+        }
+    }
+}
+
 /// An iterator for a single backup group type.
 pub struct ListGroupsType {
     store: Arc<DataStore>,
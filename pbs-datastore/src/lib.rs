@@ -167,22 +167,31 @@ macro_rules! PROXMOX_BACKUP_READER_PROTOCOL_ID_V1 {
     };
 }
 
+pub mod at_rest;
 pub mod backup_info;
 pub mod cached_chunk_reader;
 pub mod catalog;
 pub mod checksum_reader;
 pub mod checksum_writer;
+pub mod chunk_quarantine;
 pub mod chunk_stat;
 pub mod chunk_store;
 pub mod chunker;
+#[cfg(feature = "sqlite-index")]
+pub mod content_index;
 pub mod crypt_reader;
 pub mod crypt_writer;
 pub mod data_blob;
 pub mod data_blob_reader;
 pub mod data_blob_writer;
 pub mod file_formats;
+pub mod filename_index;
+pub mod group_tombstones;
 pub mod index;
+pub mod maintenance_throttle;
 pub mod manifest;
+pub mod merkle;
+pub mod network_share;
 pub mod paperkey;
 pub mod prune;
 pub mod read_chunk;
@@ -192,7 +201,7 @@ pub mod task_tracking;
 pub mod dynamic_index;
 pub mod fixed_index;
 
-pub use backup_info::{BackupDir, BackupGroup, BackupInfo};
+pub use backup_info::{BackupDir, BackupGroup, BackupInfo, InventoryOrigin};
 pub use checksum_reader::ChecksumReader;
 pub use checksum_writer::ChecksumWriter;
 pub use chunk_store::ChunkStore;
@@ -210,9 +219,12 @@ pub use datastore::{check_backup_owner, DataStore};
 
 mod hierarchy;
 pub use hierarchy::{
-    ListGroups, ListGroupsType, ListNamespaces, ListNamespacesRecursive, ListSnapshots,
+    ListBackupInfos, ListGroups, ListGroupsType, ListNamespaces, ListNamespacesRecursive,
+    ListSnapshots,
 };
 
+mod listing_cache;
+
 mod snapshot_reader;
 pub use snapshot_reader::SnapshotReader;
 
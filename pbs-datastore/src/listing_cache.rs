@@ -0,0 +1,165 @@
+//! In-memory cache of snapshot listings
+//!
+//! Listing a backup group reads the directory of every snapshot in it, which adds up for
+//! datastores with many thousand snapshots. With the cache enabled, the snapshot names of a group
+//! and the file lists of its snapshots are kept in memory, keyed by the inode and modification
+//! time of their directory, so only directories which changed need to be read again.
+//!
+//! Directories modified within the last few seconds are never cached, as a later change within
+//! the same timestamp tick could go unnoticed otherwise.
+
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+use nix::dir::Dir;
+use nix::fcntl::{AtFlags, OFlag};
+use nix::sys::stat::{fstat, fstatat, FileStat, Mode};
+
+use pbs_api_types::BACKUP_DATE_REGEX;
+
+use crate::backup_info::{read_snapshot_dir, BackupGroup, BackupInfo};
+
+// directories modified less than this many seconds ago are not cached
+const RACY_SECONDS: i64 = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct DirStamp {
+    ino: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl DirStamp {
+    fn new(stat: &FileStat) -> Self {
+        Self {
+            ino: stat.st_ino,
+            mtime: stat.st_mtime,
+            mtime_nsec: stat.st_mtime_nsec,
+        }
+    }
+
+    fn is_stable(&self, now: i64) -> bool {
+        self.mtime < now - RACY_SECONDS
+    }
+}
+
+struct CachedSnapshot {
+    stamp: DirStamp,
+    files: Vec<String>,
+    protected: bool,
+}
+
+#[derive(Default)]
+struct CachedGroup {
+    /// Stamp of the group directory, `None` if the snapshot names must be read again.
+    stamp: Option<DirStamp>,
+    names: Vec<String>,
+    snapshots: HashMap<String, CachedSnapshot>,
+}
+
+/// Snapshot listings of the groups of a datastore.
+#[derive(Default)]
+pub(crate) struct ListingCache {
+    groups: Mutex<HashMap<PathBuf, Arc<CachedGroup>>>,
+}
+
+impl ListingCache {
+    /// List the snapshots of a group, only reading directories which changed since last time.
+    pub(crate) fn list_backups(&self, group: &BackupGroup) -> Result<Vec<BackupInfo>, Error> {
+        let key = group.relative_group_path();
+        let result = self.list_backups_do(&key, group);
+        if result.is_err() {
+            self.groups.lock().unwrap().remove(&key);
+        }
+        result
+    }
+
+    fn list_backups_do(&self, key: &Path, group: &BackupGroup) -> Result<Vec<BackupInfo>, Error> {
+        let path = group.full_group_path();
+        let mut dir = Dir::open(
+            &path,
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .map_err(|err| format_err!("read dir {path:?} - {err}"))?;
+
+        let now = proxmox_time::epoch_i64();
+        let group_stamp = DirStamp::new(&fstat(dir.as_raw_fd())?);
+
+        // don't hold the lock while reading directories
+        let cached = self.groups.lock().unwrap().get(key).cloned();
+
+        let names = match &cached {
+            Some(cached) if cached.stamp == Some(group_stamp) => cached.names.clone(),
+            _ => {
+                let mut names = Vec::new();
+                for entry in dir.iter() {
+                    let entry = entry?;
+                    if let Ok(name) = entry.file_name().to_str() {
+                        if BACKUP_DATE_REGEX.is_match(name) {
+                            names.push(name.to_owned());
+                        }
+                    }
+                }
+                names
+            }
+        };
+
+        let fd = dir.as_raw_fd();
+        let mut list = Vec::with_capacity(names.len());
+        let mut update = CachedGroup {
+            stamp: group_stamp.is_stable(now).then_some(group_stamp),
+            ..Default::default()
+        };
+
+        for name in names {
+            let stat = match fstatat(fd, name.as_str(), AtFlags::AT_SYMLINK_NOFOLLOW) {
+                Ok(stat) => stat,
+                Err(nix::errno::Errno::ENOENT) => continue, // removed in the meantime
+                Err(err) => bail!("unable to stat snapshot {path:?}/{name} - {err}"),
+            };
+            if stat.st_mode & libc::S_IFMT != libc::S_IFDIR {
+                continue;
+            }
+            let stamp = DirStamp::new(&stat);
+
+            let snapshot = cached
+                .as_ref()
+                .and_then(|cached| cached.snapshots.get(&name));
+            let (files, protected) = match snapshot {
+                Some(snapshot) if snapshot.stamp == stamp => {
+                    (snapshot.files.clone(), snapshot.protected)
+                }
+                _ => read_snapshot_dir(fd, name.as_str())?,
+            };
+
+            if stamp.is_stable(now) {
+                update.snapshots.insert(
+                    name.clone(),
+                    CachedSnapshot {
+                        stamp,
+                        files: files.clone(),
+                        protected,
+                    },
+                );
+            }
+
+            list.push(BackupInfo {
+                backup_dir: group.backup_dir_with_rfc3339(name.as_str())?,
+                files,
+                protected,
+            });
+            update.names.push(name);
+        }
+
+        self.groups
+            .lock()
+            .unwrap()
+            .insert(key.to_owned(), Arc::new(update));
+
+        Ok(list)
+    }
+}
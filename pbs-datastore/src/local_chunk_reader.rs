@@ -69,9 +69,10 @@ impl AsyncReadChunk for LocalChunkReader {
         digest: &'a [u8; 32],
     ) -> Pin<Box<dyn Future<Output = Result<DataBlob, Error>> + Send + 'a>> {
         Box::pin(async move {
-            let (path, _) = self.store.chunk_path(digest);
-
-            let raw_data = tokio::fs::read(&path).await?;
+            let store = Arc::clone(&self.store);
+            let chunk_digest = *digest;
+            let raw_data =
+                tokio::task::spawn_blocking(move || store.read_raw_chunk(&chunk_digest)).await??;
 
             let chunk = DataBlob::load_from_reader(&mut &raw_data[..])?;
             self.ensure_crypt_mode(chunk.crypt_mode()?)?;
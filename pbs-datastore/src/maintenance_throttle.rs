@@ -0,0 +1,171 @@
+//! Adaptive throttling of maintenance tasks
+//!
+//! Garbage collection and verification touch or read all chunks of a datastore, which slows down
+//! backups and restores using the same disks. If limits are configured for the datastore, these
+//! tasks regularly sample the IO latency of the disk holding the datastore and the load average,
+//! and pause while either exceeds its limit. The pauses grow as long as the limits are exceeded,
+//! and shrink again once the system has recovered.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+
+use proxmox_sys::linux::procfs;
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+/// How often the latency and load are sampled, and thus how much work is done between pauses.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// The first pause after a limit was exceeded, shorter pauses end the throttling.
+const MIN_DELAY: Duration = Duration::from_millis(50);
+/// The longest pause, limiting the task to a sixth of its normal speed.
+const MAX_DELAY: Duration = Duration::from_secs(5);
+/// Granularity of pauses, so aborting the task stays responsive.
+const SLEEP_STEP: Duration = Duration::from_millis(100);
+
+// completed IOs and milliseconds spent on them, see Documentation/block/stat.rst of the kernel
+fn read_disk_stat(path: &Path) -> Option<(u64, u64)> {
+    let data = std::fs::read_to_string(path).ok()?;
+    let fields: Vec<u64> = data
+        .split_ascii_whitespace()
+        .take(8)
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    if fields.len() < 8 {
+        return None;
+    }
+    Some((fields[0] + fields[4], fields[3] + fields[7]))
+}
+
+/// Throttle for a maintenance task on a datastore.
+///
+/// Call [`pause`](Self::pause) between operations, it only sleeps if a limit is exceeded.
+pub struct MaintenanceThrottle {
+    max_latency: Option<u64>,
+    max_load: Option<f64>,
+    /// Block device statistics of the disk holding the datastore, if it is a block device.
+    disk_stat: Option<PathBuf>,
+    last_disk_stat: Option<(u64, u64)>,
+    last_sample: Instant,
+    started: bool,
+    delay: Duration,
+    total_delay: Duration,
+}
+
+impl MaintenanceThrottle {
+    /// Create a throttle for the datastore at `base_path`, with the maximum average IO latency
+    /// in milliseconds and the maximum load average per CPU.
+    pub fn new(base_path: &Path, max_latency: Option<u64>, max_load: Option<f64>) -> Self {
+        // datastores on ZFS or network file systems have no block device of their own
+        let disk_stat = max_latency
+            .and_then(|_| nix::sys::stat::stat(base_path).ok())
+            .map(|stat| {
+                let (major, minor) =
+                    unsafe { (libc::major(stat.st_dev), libc::minor(stat.st_dev)) };
+                PathBuf::from(format!("/sys/dev/block/{major}:{minor}/stat"))
+            })
+            .filter(|path| path.exists());
+
+        Self {
+            max_latency,
+            max_load,
+            disk_stat,
+            last_disk_stat: None,
+            last_sample: Instant::now(),
+            started: false,
+            delay: Duration::ZERO,
+            total_delay: Duration::ZERO,
+        }
+    }
+
+    /// Total time spent in pauses.
+    pub fn total_delay(&self) -> Duration {
+        self.total_delay
+    }
+
+    // returns a description of the first exceeded limit
+    fn exceeded_limit(&mut self) -> Option<String> {
+        if let (Some(max_latency), Some(path)) = (self.max_latency, &self.disk_stat) {
+            let stat = read_disk_stat(path);
+            let last_stat = std::mem::replace(&mut self.last_disk_stat, stat);
+            if let (Some((ios, ticks)), Some((last_ios, last_ticks))) = (stat, last_stat) {
+                let ios = ios.saturating_sub(last_ios).max(1);
+                let latency = ticks.saturating_sub(last_ticks) as f64 / ios as f64;
+                if latency > max_latency as f64 {
+                    return Some(format!(
+                        "IO latency {latency:.1} ms above limit of {max_latency} ms"
+                    ));
+                }
+            }
+        }
+
+        if let Some(max_load) = self.max_load {
+            if let (Ok(loadavg), Ok(cpuinfo)) = (procfs::Loadavg::read(), procfs::read_cpuinfo()) {
+                let load = loadavg.one() / cpuinfo.cpus.max(1) as f64;
+                if load > max_load {
+                    return Some(format!(
+                        "load average per CPU {load:.2} above limit of {max_load}"
+                    ));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Pause the calling task if the IO latency or load exceeded the limits recently.
+    pub fn pause(&mut self, worker: &dyn WorkerTaskContext) -> Result<(), Error> {
+        if self.max_latency.is_none() && self.max_load.is_none() {
+            return Ok(());
+        }
+
+        if !self.started {
+            self.started = true;
+            if self.max_latency.is_some() && self.disk_stat.is_none() {
+                task_warn!(
+                    worker,
+                    "datastore is not on a block device, not throttling by IO latency"
+                );
+            }
+            self.last_disk_stat = self.disk_stat.as_deref().and_then(read_disk_stat);
+            self.last_sample = Instant::now();
+            return Ok(());
+        }
+
+        if self.last_sample.elapsed() < SAMPLE_INTERVAL {
+            return Ok(());
+        }
+
+        match self.exceeded_limit() {
+            Some(reason) => {
+                if self.delay.is_zero() {
+                    task_log!(worker, "{reason}, slowing down");
+                }
+                self.delay = (self.delay * 2).clamp(MIN_DELAY, MAX_DELAY);
+            }
+            None if !self.delay.is_zero() => {
+                self.delay /= 2;
+                if self.delay < MIN_DELAY {
+                    self.delay = Duration::ZERO;
+                    task_log!(
+                        worker,
+                        "IO latency and load within limits, resuming full speed"
+                    );
+                }
+            }
+            None => {}
+        }
+
+        let mut remaining = self.delay;
+        while !remaining.is_zero() {
+            worker.check_abort()?;
+            let step = remaining.min(SLEEP_STEP);
+            std::thread::sleep(step);
+            remaining -= step;
+        }
+        self.total_delay += self.delay;
+        self.last_sample = Instant::now();
+
+        Ok(())
+    }
+}
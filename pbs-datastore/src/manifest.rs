@@ -1,18 +1,44 @@
 use std::path::Path;
 
 use anyhow::{bail, format_err, Error};
+use hex::FromHex;
 
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use pbs_api_types::{BackupType, CryptMode, Fingerprint};
+use pbs_api_types::{BackupType, ChunkDigestAlgorithm, CryptMode, Fingerprint};
 use pbs_tools::crypt_config::CryptConfig;
 
+use crate::merkle::MERKLE_ROOTS_KEY;
+
 pub const MANIFEST_BLOB_NAME: &str = "index.json.blob";
 pub const MANIFEST_LOCK_NAME: &str = ".index.json.lck";
 pub const CLIENT_LOG_BLOB_NAME: &str = "client.log.blob";
 pub const ENCRYPTED_KEY_BLOB_NAME: &str = "rsa-encrypted.key.blob";
 
+/// Current version of the manifest format.
+///
+/// Manifests written before the version was recorded are version 0.
+pub const MANIFEST_VERSION: u64 = 1;
+
+/// Key of the format version in the unprotected section.
+///
+/// The version is not covered by the signature, so that older clients can still verify
+/// manifests carrying it, and older servers keep it when updating the unprotected section.
+const MANIFEST_VERSION_KEY: &str = "manifest-version";
+
+/// Key of the digests of the zero chunks elided by the client, by archive name.
+const ZERO_CHUNKS_KEY: &str = "zero-chunks";
+
+/// Key of the previous names of renamed archives, by archive name.
+const ARCHIVE_ALIASES_KEY: &str = "archive-aliases";
+
+/// Key of the keep options which kept the snapshot in the last prune run.
+const RETENTION_TAGS_KEY: &str = "retention-tags";
+
+/// Key of the records of entries removed from the archives by the server.
+const REDACTIONS_KEY: &str = "redactions";
+
 fn crypt_mode_none() -> CryptMode {
     CryptMode::None
 }
@@ -51,9 +77,16 @@ pub struct BackupManifest {
     backup_id: String,
     backup_time: i64,
     files: Vec<FileInfo>,
+    /// Hash algorithm of the chunk digests, only recorded if it is not the default, so that
+    /// manifests stay readable and their signatures valid for older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    digest_algorithm: Option<ChunkDigestAlgorithm>,
     #[serde(default = "empty_value")] // to be compatible with < 0.8.0 backups
     pub unprotected: Value,
     pub signature: Option<String>,
+    /// Properties added by newer versions, kept when rewriting the manifest.
+    #[serde(flatten)]
+    unknown: serde_json::Map<String, Value>,
 }
 
 #[derive(PartialEq, Eq)]
@@ -76,6 +109,41 @@ impl ArchiveType {
     }
 }
 
+/// Returns the format version of a manifest in json representation.
+pub fn manifest_version(json: &Value) -> u64 {
+    json["unprotected"][MANIFEST_VERSION_KEY]
+        .as_u64()
+        .unwrap_or(0)
+}
+
+/// Migrate a manifest in json representation to the current format version.
+///
+/// Migrations only touch the unprotected section, so existing signatures stay valid. Manifests
+/// of newer versions are left as they are. Returns the version the manifest had.
+pub fn migrate_manifest(json: &mut Value) -> Result<u64, Error> {
+    let version = manifest_version(json);
+    if version >= MANIFEST_VERSION {
+        return Ok(version);
+    }
+
+    let manifest = json
+        .as_object_mut()
+        .ok_or_else(|| format_err!("manifest is not a json object"))?;
+
+    // version 0: < 0.8.0 backups have no unprotected section
+    let unprotected = manifest.entry("unprotected").or_insert_with(empty_value);
+    if unprotected.is_null() {
+        *unprotected = empty_value();
+    }
+    if !unprotected.is_object() {
+        bail!("unprotected section of manifest is not a json object");
+    }
+
+    unprotected[MANIFEST_VERSION_KEY] = MANIFEST_VERSION.into();
+
+    Ok(version)
+}
+
 //#[deprecated(note = "use ArchivType::from_path instead")] later...
 pub fn archive_type<P: AsRef<Path>>(archive_name: P) -> Result<ArchiveType, Error> {
     ArchiveType::from_path(archive_name)
@@ -88,11 +156,32 @@ impl BackupManifest {
             backup_id: snapshot.group.id,
             backup_time: snapshot.time,
             files: Vec::new(),
-            unprotected: json!({}),
+            digest_algorithm: None,
+            unprotected: json!({ MANIFEST_VERSION_KEY: MANIFEST_VERSION }),
             signature: None,
+            unknown: serde_json::Map::new(),
         }
     }
 
+    /// Returns the format version of the manifest.
+    pub fn version(&self) -> u64 {
+        self.unprotected[MANIFEST_VERSION_KEY].as_u64().unwrap_or(0)
+    }
+
+    /// Returns the hash algorithm of the chunk digests of the archives.
+    pub fn digest_algorithm(&self) -> ChunkDigestAlgorithm {
+        self.digest_algorithm.unwrap_or_default()
+    }
+
+    /// Set the hash algorithm of the chunk digests, this is covered by the signature.
+    pub fn set_digest_algorithm(&mut self, algorithm: ChunkDigestAlgorithm) {
+        self.digest_algorithm = if algorithm == ChunkDigestAlgorithm::default() {
+            None
+        } else {
+            Some(algorithm)
+        };
+    }
+
     pub fn add_file(
         &mut self,
         filename: String,
@@ -114,6 +203,18 @@ impl BackupManifest {
         &self.files[..]
     }
 
+    /// Replace the size and checksum of file `name`, after the server rewrote it.
+    pub fn update_file(&mut self, name: &str, size: u64, csum: [u8; 32]) -> Result<(), Error> {
+        match self.files.iter_mut().find(|item| item.filename == name) {
+            None => bail!("manifest does not contain file '{}'", name),
+            Some(info) => {
+                info.size = size;
+                info.csum = csum;
+                Ok(())
+            }
+        }
+    }
+
     pub fn lookup_file_info(&self, name: &str) -> Result<&FileInfo, Error> {
         let info = self.files.iter().find(|item| item.filename == name);
 
@@ -178,6 +279,94 @@ impl BackupManifest {
         Ok(manifest)
     }
 
+    /// Returns the Merkle root over the chunk digests of archive `name`, if one was recorded.
+    pub fn merkle_root(&self, name: &str) -> Result<Option<[u8; 32]>, Error> {
+        match self.unprotected[MERKLE_ROOTS_KEY][name].as_str() {
+            Some(root) => Ok(Some(<[u8; 32]>::from_hex(root)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Record the Merkle root over the chunk digests of archive `name`.
+    pub fn set_merkle_root(&mut self, name: &str, root: &[u8; 32]) {
+        if !self.unprotected[MERKLE_ROOTS_KEY].is_object() {
+            self.unprotected[MERKLE_ROOTS_KEY] = empty_value();
+        }
+        self.unprotected[MERKLE_ROOTS_KEY][name] = hex::encode(root).into();
+    }
+
+    /// Returns the digest of the zero chunk of fixed index archive `name`, if the client elided
+    /// zero chunks when uploading it.
+    ///
+    /// This is not covered by the signature, so it must only be trusted if it matches the digest
+    /// of a locally built zero chunk.
+    pub fn zero_chunk(&self, name: &str) -> Result<Option<[u8; 32]>, Error> {
+        match self.unprotected[ZERO_CHUNKS_KEY][name].as_str() {
+            Some(digest) => Ok(Some(<[u8; 32]>::from_hex(digest)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Record that zero chunks of archive `name` were elided, and their digest.
+    pub fn set_zero_chunk(&mut self, name: &str, digest: &[u8; 32]) {
+        if !self.unprotected[ZERO_CHUNKS_KEY].is_object() {
+            self.unprotected[ZERO_CHUNKS_KEY] = empty_value();
+        }
+        self.unprotected[ZERO_CHUNKS_KEY][name] = hex::encode(digest).into();
+    }
+
+    /// Returns the previous names of archive `name` in its backup group, most recent first.
+    pub fn archive_aliases(&self, name: &str) -> Vec<String> {
+        match self.unprotected[ARCHIVE_ALIASES_KEY][name].as_array() {
+            Some(aliases) => aliases
+                .iter()
+                .filter_map(|alias| alias.as_str().map(String::from))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Record the previous names of archive `name`, most recent first.
+    pub fn set_archive_aliases(&mut self, name: &str, aliases: &[String]) {
+        if !self.unprotected[ARCHIVE_ALIASES_KEY].is_object() {
+            self.unprotected[ARCHIVE_ALIASES_KEY] = empty_value();
+        }
+        self.unprotected[ARCHIVE_ALIASES_KEY][name] = aliases.into();
+    }
+
+    /// Returns the keep options (e.g. `keep-weekly`) for whose periods the snapshot was kept in
+    /// the last prune run.
+    pub fn retention_tags(&self) -> Vec<String> {
+        match self.unprotected[RETENTION_TAGS_KEY].as_array() {
+            Some(tags) => tags
+                .iter()
+                .filter_map(|tag| tag.as_str().map(String::from))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Record the retention tags of the snapshot, an empty list removes them.
+    pub fn set_retention_tags(&mut self, tags: &[String]) {
+        match self.unprotected.as_object_mut() {
+            Some(unprotected) if tags.is_empty() => {
+                unprotected.remove(RETENTION_TAGS_KEY);
+            }
+            _ => self.unprotected[RETENTION_TAGS_KEY] = tags.into(),
+        }
+    }
+
+    /// Record that the task `upid` removed `entries` entries from the archives of the snapshot.
+    ///
+    /// Only the task is referenced, the removed paths are listed in its log.
+    pub fn add_redaction(&mut self, upid: &str, time: i64, entries: u64) {
+        let record = json!({ "upid": upid, "time": time, "entries": entries });
+        match self.unprotected[REDACTIONS_KEY].as_array_mut() {
+            Some(records) => records.push(record),
+            None => self.unprotected[REDACTIONS_KEY] = json!([record]),
+        }
+    }
+
     pub fn fingerprint(&self) -> Result<Option<Fingerprint>, Error> {
         match &self.unprotected["key-fingerprint"] {
             Value::Null => Ok(None),
@@ -217,7 +406,7 @@ impl BackupManifest {
         data: &[u8],
         crypt_config: Option<&CryptConfig>,
     ) -> Result<BackupManifest, Error> {
-        let json: Value = serde_json::from_slice(data)?;
+        let mut json: Value = serde_json::from_slice(data)?;
         let signature = json["signature"].as_str().map(String::from);
 
         if let Some(crypt_config) = crypt_config {
@@ -244,6 +433,8 @@ impl BackupManifest {
             }
         }
 
+        migrate_manifest(&mut json)?;
+
         let manifest: BackupManifest = serde_json::from_value(json)?;
         Ok(manifest)
     }
@@ -257,8 +448,9 @@ impl TryFrom<super::DataBlob> for BackupManifest {
         let data = blob
             .decode(None, None)
             .map_err(|err| format_err!("decode backup manifest blob failed - {}", err))?;
-        let json: Value = serde_json::from_slice(&data[..])
+        let mut json: Value = serde_json::from_slice(&data[..])
             .map_err(|err| format_err!("unable to parse backup manifest json - {}", err))?;
+        migrate_manifest(&mut json)?;
         let manifest: BackupManifest = serde_json::from_value(json)?;
         Ok(manifest)
     }
@@ -305,3 +497,31 @@ fn test_manifest_signature() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[test]
+fn test_manifest_migration() -> Result<(), Error> {
+    // < 0.8.0 manifest without crypt mode and unprotected section
+    let data = br#"{
+        "backup-type": "host",
+        "backup-id": "elsa",
+        "backup-time": 1593179765,
+        "files": [{
+            "filename": "abc.blob",
+            "size": 200,
+            "csum": "0202020202020202020202020202020202020202020202020202020202020202"
+        }],
+        "signature": null,
+        "some-future-property": [1, 2, 3]
+    }"#;
+
+    let manifest = BackupManifest::from_data(data, None)?;
+    assert_eq!(manifest.version(), MANIFEST_VERSION);
+    assert_eq!(manifest.files()[0].crypt_mode, CryptMode::None);
+
+    let text = manifest.to_string(None)?;
+    let json: Value = serde_json::from_str(&text)?;
+    assert_eq!(manifest_version(&json), MANIFEST_VERSION);
+    assert_eq!(json["some-future-property"], json!([1, 2, 3]));
+
+    Ok(())
+}
@@ -0,0 +1,152 @@
+//! Merkle trees over the chunk digests of an archive
+//!
+//! The tree follows RFC 6962: leaves and inner nodes are hashed with distinct prefixes, and a
+//! tree of `n` leaves is split at the largest power of two smaller than `n`. The root commits
+//! to the chunk digests and their order, so an inclusion proof for a chunk only needs the
+//! sibling hashes along its path instead of the whole index.
+//!
+//! Chunk sizes and offsets are not part of the tree, they are covered by the index checksum in
+//! the manifest.
+
+use anyhow::{bail, Error};
+use openssl::sha::Sha256;
+
+use crate::index::IndexFile;
+
+/// Key of the per-archive Merkle roots in the unprotected section of the manifest.
+pub const MERKLE_ROOTS_KEY: &str = "merkle-roots";
+
+/// Hash of a leaf, i.e. of a chunk digest.
+pub fn leaf_hash(digest: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(&[0u8]);
+    hasher.update(digest);
+    hasher.finish()
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(&[1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finish()
+}
+
+// largest power of two smaller than `n`, requires `n > 1`
+fn split_point(n: usize) -> usize {
+    1 << (usize::BITS - (n - 1).leading_zeros() - 1)
+}
+
+fn subtree_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    match leaves.len() {
+        0 => openssl::sha::sha256(&[]),
+        1 => leaf_hash(&leaves[0]),
+        n => {
+            let k = split_point(n);
+            node_hash(&subtree_root(&leaves[..k]), &subtree_root(&leaves[k..]))
+        }
+    }
+}
+
+/// Compute the Merkle root over a list of chunk digests.
+pub fn merkle_root(digests: &[[u8; 32]]) -> [u8; 32] {
+    subtree_root(digests)
+}
+
+/// Returns the chunk digests of an index, in order.
+pub fn index_digests(index: &dyn IndexFile) -> Vec<[u8; 32]> {
+    (0..index.index_count())
+        .map(|pos| *index.index_digest(pos).unwrap())
+        .collect()
+}
+
+/// Compute the Merkle root over the chunk digests of an index.
+pub fn index_merkle_root(index: &dyn IndexFile) -> [u8; 32] {
+    merkle_root(&index_digests(index))
+}
+
+/// Returns the inclusion proof for the digest at `pos`, ordered from the leaf up to the root.
+pub fn merkle_proof(digests: &[[u8; 32]], pos: usize) -> Result<Vec<[u8; 32]>, Error> {
+    if pos >= digests.len() {
+        bail!("chunk {pos} out of range ({} chunks)", digests.len());
+    }
+
+    fn path(leaves: &[[u8; 32]], pos: usize, proof: &mut Vec<[u8; 32]>) {
+        if leaves.len() <= 1 {
+            return;
+        }
+        let k = split_point(leaves.len());
+        if pos < k {
+            path(&leaves[..k], pos, proof);
+            proof.push(subtree_root(&leaves[k..]));
+        } else {
+            path(&leaves[k..], pos - k, proof);
+            proof.push(subtree_root(&leaves[..k]));
+        }
+    }
+
+    let mut proof = Vec::new();
+    path(digests, pos, &mut proof);
+    Ok(proof)
+}
+
+/// Check that `digest` is the chunk at `pos` of a tree with `count` leaves and the given root.
+pub fn verify_merkle_proof(
+    root: &[u8; 32],
+    digest: &[u8; 32],
+    pos: usize,
+    count: usize,
+    proof: &[[u8; 32]],
+) -> bool {
+    if pos >= count {
+        return false;
+    }
+
+    // see RFC 9162, section 2.1.3.2
+    let mut fnode = pos;
+    let mut snode = count - 1;
+    let mut hash = leaf_hash(digest);
+
+    for sibling in proof {
+        if snode == 0 {
+            return false;
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            hash = node_hash(sibling, &hash);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            hash = node_hash(&hash, sibling);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+
+    snode == 0 && &hash == root
+}
+
+#[test]
+fn test_merkle_proofs() -> Result<(), Error> {
+    for count in 1..=17usize {
+        let digests: Vec<[u8; 32]> = (0..count).map(|i| [i as u8; 32]).collect();
+        let root = merkle_root(&digests);
+
+        for (pos, digest) in digests.iter().enumerate() {
+            let proof = merkle_proof(&digests, pos)?;
+            assert!(verify_merkle_proof(&root, digest, pos, count, &proof));
+
+            // wrong digest or position must not verify
+            assert!(!verify_merkle_proof(&root, &[0xff; 32], pos, count, &proof));
+            if count > 1 {
+                let other = (pos + 1) % count;
+                assert!(!verify_merkle_proof(&root, digest, other, count, &proof));
+            }
+        }
+    }
+
+    assert!(merkle_proof(&[[0u8; 32]], 1).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,154 @@
+//! Availability checks for datastores located on network shares
+//!
+//! Network shares are mounted via systemd automount units, so accessing the mount point mounts
+//! the share again if it was unmounted, for example after the server was not reachable, without
+//! requiring any privileges. A remount is detected by a changed device ID of the mount point, so
+//! that cached datastores can reopen their chunk store.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+use lazy_static::lazy_static;
+
+use pbs_api_types::NetworkShareConfig;
+
+// from linux/magic.h
+const NFS_SUPER_MAGIC: u32 = 0x6969;
+const CIFS_SUPER_MAGIC: u32 = 0xFF534D42;
+const SMB2_SUPER_MAGIC: u32 = 0xFE534D42;
+
+/// A share not answering within this time is considered unavailable.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The result of a probe is reused for this long, as every datastore lookup checks the share.
+const PROBE_CACHE_TIME: Duration = Duration::from_secs(5);
+
+// a probe of a share, running in its own thread, as accessing an unreachable share can block
+// for a long time
+struct Probe {
+    started: Instant,
+    result: Mutex<Option<Result<u64, String>>>,
+    done: Condvar,
+}
+
+lazy_static! {
+    // the last probe by share name, a probe still running is shared by all callers
+    static ref PROBES: Mutex<HashMap<String, Arc<Probe>>> = Mutex::new(HashMap::new());
+}
+
+/// Returns the configured network share `path` is located on, if any.
+pub fn network_share_for_path(path: &Path) -> Result<Option<NetworkShareConfig>, Error> {
+    let (config, _digest) = pbs_config::network_share::config()?;
+    let shares: Vec<NetworkShareConfig> = config.convert_to_typed_array("share")?;
+
+    Ok(shares
+        .into_iter()
+        .find(|share| path.starts_with(share.mount_point())))
+}
+
+fn probe_network_share(share: &NetworkShareConfig) -> Result<u64, Error> {
+    let mount_point = share.mount_point();
+
+    // statfs follows the automount point and so triggers mounting the share
+    let stat = nix::sys::statfs::statfs(mount_point.as_str())
+        .map_err(|err| format_err!("network share '{}' is not available - {err}", share.name))?;
+
+    let fs_type = stat.filesystem_type().0 as u32;
+    if !matches!(
+        fs_type,
+        NFS_SUPER_MAGIC | CIFS_SUPER_MAGIC | SMB2_SUPER_MAGIC
+    ) {
+        bail!("network share '{}' is not mounted", share.name);
+    }
+
+    let stat = nix::sys::stat::stat(mount_point.as_str())
+        .map_err(|err| format_err!("network share '{}' is not available - {err}", share.name))?;
+
+    Ok(stat.st_dev)
+}
+
+fn start_probe(share: &NetworkShareConfig) -> Arc<Probe> {
+    let mut probes = PROBES.lock().unwrap();
+    if let Some(probe) = probes.get(&share.name) {
+        let running = probe.result.lock().unwrap().is_none();
+        // a hanging probe is not started again, so threads do not pile up
+        if running || probe.started.elapsed() < PROBE_CACHE_TIME {
+            return Arc::clone(probe);
+        }
+    }
+
+    let probe = Arc::new(Probe {
+        started: Instant::now(),
+        result: Mutex::new(None),
+        done: Condvar::new(),
+    });
+    probes.insert(share.name.clone(), Arc::clone(&probe));
+
+    let share = share.clone();
+    let thread_probe = Arc::clone(&probe);
+    std::thread::spawn(move || {
+        let result = probe_network_share(&share).map_err(|err| err.to_string());
+        *thread_probe.result.lock().unwrap() = Some(result);
+        thread_probe.done.notify_all();
+    });
+
+    probe
+}
+
+fn wait_for_probe(share: &NetworkShareConfig, probe: &Probe) -> Result<u64, Error> {
+    let timeout = PROBE_TIMEOUT.saturating_sub(probe.started.elapsed());
+    let result = probe.result.lock().unwrap();
+    let (result, _) = probe
+        .done
+        .wait_timeout_while(result, timeout, |result| result.is_none())
+        .unwrap();
+
+    match &*result {
+        Some(Ok(device)) => Ok(*device),
+        Some(Err(err)) => bail!("{err}"),
+        None => bail!("network share '{}' is not responding", share.name),
+    }
+}
+
+/// Checks that a network share is mounted and responding, mounting it if required.
+///
+/// The share is accessed in a separate thread, waiting at most [`PROBE_TIMEOUT`] for it, and the
+/// result is cached for a few seconds. Returns the device ID of the mounted share.
+pub fn check_network_share(share: &NetworkShareConfig) -> Result<u64, Error> {
+    let probe = start_probe(share);
+
+    let in_worker_thread = tokio::runtime::Handle::try_current().map_or(false, |handle| {
+        handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread
+    });
+    if in_worker_thread {
+        tokio::task::block_in_place(|| wait_for_probe(share, &probe))
+    } else {
+        wait_for_probe(share, &probe)
+    }
+}
+
+/// Forgets the cached probe result of a share, e.g. after it was unmounted.
+///
+/// A probe still running is kept, to not start another one hanging on the same share.
+pub fn forget_network_share_probe(name: &str) {
+    let mut probes = PROBES.lock().unwrap();
+    if let Some(probe) = probes.get(name) {
+        if probe.result.lock().unwrap().is_some() {
+            probes.remove(name);
+        }
+    }
+}
+
+/// Makes sure the network share the datastore at `path` is located on is available.
+///
+/// Returns the device ID of the share, or `None` if the path is not on a network share. A
+/// changed device ID means the share was mounted again.
+pub fn check_datastore_path(path: &Path) -> Result<Option<u64>, Error> {
+    match network_share_for_path(path)? {
+        Some(share) => Ok(Some(check_network_share(&share)?)),
+        None => Ok(None),
+    }
+}
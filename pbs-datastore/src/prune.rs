@@ -5,7 +5,7 @@ use anyhow::Error;
 
 use pbs_api_types::KeepOptions;
 
-use super::BackupInfo;
+use super::{BackupDir, BackupInfo};
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum PruneMark {
@@ -36,8 +36,12 @@ impl std::fmt::Display for PruneMark {
     }
 }
 
+// `tag` is recorded in `tags` for the snapshots kept for each period, including those already
+// kept by an earlier selection
 fn mark_selections<F: Fn(&BackupInfo) -> Result<String, Error>>(
     mark: &mut HashMap<PathBuf, PruneMark>,
+    tags: &mut HashMap<PathBuf, Vec<String>>,
+    tag: &str,
     list: &[BackupInfo],
     keep: usize,
     select_id: F,
@@ -49,7 +53,10 @@ fn mark_selections<F: Fn(&BackupInfo) -> Result<String, Error>>(
         let backup_id = info.backup_dir.relative_path();
         if let Some(PruneMark::Keep) = mark.get(&backup_id) {
             let sel_id: String = select_id(info)?;
-            already_included.insert(sel_id);
+            // the newest snapshot of the period represents it
+            if already_included.insert(sel_id) {
+                tags.entry(backup_id).or_default().push(tag.to_string());
+            }
         }
     }
 
@@ -73,6 +80,9 @@ fn mark_selections<F: Fn(&BackupInfo) -> Result<String, Error>>(
                 break;
             }
             include_hash.insert(sel_id);
+            tags.entry(backup_id.clone())
+                .or_default()
+                .push(tag.to_string());
             mark.insert(backup_id, PruneMark::Keep);
         } else {
             mark.insert(backup_id, PruneMark::Remove);
@@ -105,56 +115,105 @@ fn remove_incomplete_snapshots(mark: &mut HashMap<PathBuf, PruneMark>, list: &[B
 
 /// This filters incomplete and kept backups.
 pub fn compute_prune_info(
-    mut list: Vec<BackupInfo>,
+    list: Vec<BackupInfo>,
     options: &KeepOptions,
 ) -> Result<Vec<(BackupInfo, PruneMark)>, Error> {
+    let prune_info = compute_prune_info_with_tags(list, options)?
+        .into_iter()
+        .map(|(info, mark, _tags)| (info, mark))
+        .collect();
+
+    Ok(prune_info)
+}
+
+/// Like [`compute_prune_info`], but also returns the retention tags of each snapshot, that is
+/// the keep options (e.g. `keep-weekly`) for whose periods it is kept.
+pub fn compute_prune_info_with_tags(
+    mut list: Vec<BackupInfo>,
+    options: &KeepOptions,
+) -> Result<Vec<(BackupInfo, PruneMark, Vec<String>)>, Error> {
     let mut mark = HashMap::new();
+    let mut tags = HashMap::new();
 
     BackupInfo::sort_list(&mut list, false);
 
     remove_incomplete_snapshots(&mut mark, &list);
 
     if let Some(keep_last) = options.keep_last {
-        mark_selections(&mut mark, &list, keep_last as usize, |info| {
-            Ok(info.backup_dir.backup_time_string().to_owned())
-        })?;
+        mark_selections(
+            &mut mark,
+            &mut tags,
+            "keep-last",
+            &list,
+            keep_last as usize,
+            |info| Ok(info.backup_dir.backup_time_string().to_owned()),
+        )?;
     }
 
     use proxmox_time::strftime_local;
 
     if let Some(keep_hourly) = options.keep_hourly {
-        mark_selections(&mut mark, &list, keep_hourly as usize, |info| {
-            strftime_local("%Y/%m/%d/%H", info.backup_dir.backup_time()).map_err(Error::from)
-        })?;
+        mark_selections(
+            &mut mark,
+            &mut tags,
+            "keep-hourly",
+            &list,
+            keep_hourly as usize,
+            |info| {
+                strftime_local("%Y/%m/%d/%H", info.backup_dir.backup_time()).map_err(Error::from)
+            },
+        )?;
     }
 
     if let Some(keep_daily) = options.keep_daily {
-        mark_selections(&mut mark, &list, keep_daily as usize, |info| {
-            strftime_local("%Y/%m/%d", info.backup_dir.backup_time()).map_err(Error::from)
-        })?;
+        mark_selections(
+            &mut mark,
+            &mut tags,
+            "keep-daily",
+            &list,
+            keep_daily as usize,
+            |info| strftime_local("%Y/%m/%d", info.backup_dir.backup_time()).map_err(Error::from),
+        )?;
     }
 
     if let Some(keep_weekly) = options.keep_weekly {
-        mark_selections(&mut mark, &list, keep_weekly as usize, |info| {
-            // Note: Use iso-week year/week here. This year number
-            // might not match the calendar year number.
-            strftime_local("%G/%V", info.backup_dir.backup_time()).map_err(Error::from)
-        })?;
+        mark_selections(
+            &mut mark,
+            &mut tags,
+            "keep-weekly",
+            &list,
+            keep_weekly as usize,
+            |info| {
+                // Note: Use iso-week year/week here. This year number
+                // might not match the calendar year number.
+                strftime_local("%G/%V", info.backup_dir.backup_time()).map_err(Error::from)
+            },
+        )?;
     }
 
     if let Some(keep_monthly) = options.keep_monthly {
-        mark_selections(&mut mark, &list, keep_monthly as usize, |info| {
-            strftime_local("%Y/%m", info.backup_dir.backup_time()).map_err(Error::from)
-        })?;
+        mark_selections(
+            &mut mark,
+            &mut tags,
+            "keep-monthly",
+            &list,
+            keep_monthly as usize,
+            |info| strftime_local("%Y/%m", info.backup_dir.backup_time()).map_err(Error::from),
+        )?;
     }
 
     if let Some(keep_yearly) = options.keep_yearly {
-        mark_selections(&mut mark, &list, keep_yearly as usize, |info| {
-            strftime_local("%Y", info.backup_dir.backup_time()).map_err(Error::from)
-        })?;
+        mark_selections(
+            &mut mark,
+            &mut tags,
+            "keep-yearly",
+            &list,
+            keep_yearly as usize,
+            |info| strftime_local("%Y", info.backup_dir.backup_time()).map_err(Error::from),
+        )?;
     }
 
-    let prune_info: Vec<(BackupInfo, PruneMark)> = list
+    let prune_info: Vec<(BackupInfo, PruneMark, Vec<String>)> = list
         .into_iter()
         .map(|info| {
             let backup_id = info.backup_dir.relative_path();
@@ -163,10 +222,27 @@ pub fn compute_prune_info(
             } else {
                 mark.get(&backup_id).copied().unwrap_or(PruneMark::Remove)
             };
+            let tags = match mark {
+                PruneMark::Keep => tags.remove(&backup_id).unwrap_or_default(),
+                _ => Vec::new(),
+            };
 
-            (info, mark)
+            (info, mark, tags)
         })
         .collect();
 
     Ok(prune_info)
 }
+
+/// Record the retention tags of a kept snapshot in its manifest, if they changed since the last
+/// prune run. Returns whether the manifest was updated.
+pub fn update_retention_tags(backup_dir: &BackupDir, tags: &[String]) -> Result<bool, Error> {
+    let (manifest, _) = backup_dir.load_manifest()?;
+    if manifest.retention_tags() == tags {
+        return Ok(false);
+    }
+
+    backup_dir.update_manifest(|manifest| manifest.set_retention_tags(tags))?;
+
+    Ok(true)
+}
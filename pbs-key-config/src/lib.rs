@@ -236,14 +236,24 @@ impl KeyConfig {
 
     /// Store a KeyConfig to path
     pub fn store<P: AsRef<Path>>(&self, path: P, replace: bool) -> Result<(), Error> {
+        let mode = nix::sys::stat::Mode::S_IRUSR | nix::sys::stat::Mode::S_IWUSR;
+        self.store_with_options(path, replace, CreateOptions::new().perm(mode))
+    }
+
+    /// Store a KeyConfig to path, with the permissions and ownership of `options`
+    pub fn store_with_options<P: AsRef<Path>>(
+        &self,
+        path: P,
+        replace: bool,
+        options: CreateOptions,
+    ) -> Result<(), Error> {
         let path: &Path = path.as_ref();
 
         let data = serde_json::to_string(self)?;
 
         try_block!({
             if replace {
-                let mode = nix::sys::stat::Mode::S_IRUSR | nix::sys::stat::Mode::S_IWUSR;
-                replace_file(path, data.as_bytes(), CreateOptions::new().perm(mode), true)?;
+                replace_file(path, data.as_bytes(), options, true)?;
             } else {
                 use std::os::unix::fs::OpenOptionsExt;
 
@@ -253,6 +263,7 @@ impl KeyConfig {
                     .create_new(true)
                     .open(path)?;
 
+                options.apply_to(&mut file, path)?;
                 file.write_all(data.as_bytes())?;
             }
 
@@ -0,0 +1,93 @@
+//! Detection of CPU extensions accelerating chunk hashing and encryption
+//!
+//! Chunks are hashed with SHA-256 and encrypted with AES-256-GCM via OpenSSL, which selects its
+//! implementation at runtime based on the same CPU features. It falls back to slower generic
+//! code if they are missing, or masked via the `OPENSSL_ia32cap`/`OPENSSL_armcap` environment
+//! variables.
+
+use std::fmt;
+
+/// Hardware acceleration available for chunk hashing and encryption.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CryptoAcceleration {
+    /// SHA-256 instructions (SHA-NI, ARMv8 SHA2).
+    pub sha256: bool,
+    /// AES instructions (AES-NI, ARMv8 AES).
+    pub aes: bool,
+    /// Carry-less multiplication used for GCM (PCLMULQDQ, PMULL).
+    pub clmul: bool,
+    /// Environment variable masking CPU features from OpenSSL, if set.
+    pub masked_by: Option<&'static str>,
+}
+
+#[cfg(target_arch = "x86_64")]
+const FEATURE_NAMES: [&str; 3] = ["SHA-NI", "AES-NI", "PCLMULQDQ"];
+#[cfg(target_arch = "x86_64")]
+const OPENSSL_CAP_VAR: Option<&str> = Some("OPENSSL_ia32cap");
+
+#[cfg(target_arch = "aarch64")]
+const FEATURE_NAMES: [&str; 3] = ["SHA2", "AES", "PMULL"];
+#[cfg(target_arch = "aarch64")]
+const OPENSSL_CAP_VAR: Option<&str> = Some("OPENSSL_armcap");
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const FEATURE_NAMES: [&str; 3] = ["SHA", "AES", "CLMUL"];
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const OPENSSL_CAP_VAR: Option<&str> = None;
+
+impl CryptoAcceleration {
+    /// Detect the features of the running CPU.
+    pub fn detect() -> Self {
+        let masked_by = OPENSSL_CAP_VAR.filter(|name| std::env::var_os(name).is_some());
+
+        #[cfg(target_arch = "x86_64")]
+        let (sha256, aes, clmul) = (
+            std::arch::is_x86_feature_detected!("sha"),
+            std::arch::is_x86_feature_detected!("aes"),
+            std::arch::is_x86_feature_detected!("pclmulqdq"),
+        );
+
+        // the "aes" feature includes PMULL
+        #[cfg(target_arch = "aarch64")]
+        let (sha256, aes, clmul) = (
+            std::arch::is_aarch64_feature_detected!("sha2"),
+            std::arch::is_aarch64_feature_detected!("aes"),
+            std::arch::is_aarch64_feature_detected!("aes"),
+        );
+
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        let (sha256, aes, clmul) = (false, false, false);
+
+        Self {
+            sha256,
+            aes,
+            clmul,
+            masked_by,
+        }
+    }
+
+    /// Whether AES-256-GCM encryption can use the accelerated implementation.
+    pub fn aes_gcm(&self) -> bool {
+        self.aes && self.clmul && self.masked_by.is_none()
+    }
+}
+
+impl fmt::Display for CryptoAcceleration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let features: Vec<&str> = [self.sha256, self.aes, self.clmul]
+            .into_iter()
+            .zip(FEATURE_NAMES)
+            .filter_map(|(available, name)| available.then_some(name))
+            .collect();
+
+        if features.is_empty() {
+            write!(f, "none, using generic implementations")?;
+        } else {
+            write!(f, "{}", features.join(", "))?;
+        }
+        if let Some(name) = self.masked_by {
+            write!(f, " (possibly masked by {name})")?;
+        }
+        Ok(())
+    }
+}
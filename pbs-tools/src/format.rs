@@ -67,6 +67,14 @@ pub fn render_bytes_human_readable(value: &Value, _record: &Value) -> Result<Str
     Ok(text)
 }
 
+pub fn render_string_list(value: &Value, _record: &Value) -> Result<String, Error> {
+    let list: Vec<&str> = match value.as_array() {
+        Some(list) => list.iter().filter_map(Value::as_str).collect(),
+        None => return Ok(String::new()),
+    };
+    Ok(list.join(", "))
+}
+
 pub fn render_duration(val: &Value, _record: &Value) -> Result<String, Error> {
     if val.is_null() {
         return Ok(String::new());
@@ -0,0 +1,56 @@
+//! File system helpers which differ between the supported platforms
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Open an anonymous temporary file for reading and writing in `dir`.
+///
+/// On Linux this uses `O_TMPFILE`, so the file never shows up in the directory. Other platforms
+/// create a uniquely named file and unlink it right away.
+#[cfg(target_os = "linux")]
+pub fn open_tmpfile<P: AsRef<Path>>(dir: P) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .read(true)
+        .custom_flags(libc::O_TMPFILE)
+        .open(dir)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn open_tmpfile<P: AsRef<Path>>(dir: P) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    loop {
+        let path = dir.as_ref().join(format!(
+            ".pbs-tmp-{}-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            proxmox_time::epoch_i64(),
+        ));
+        let file = match std::fs::OpenOptions::new()
+            .write(true)
+            .read(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        };
+        std::fs::remove_file(&path)?;
+        return Ok(file);
+    }
+}
+
+/// The device number of `stat`, as used for device sets. `dev_t` is signed on macOS.
+#[allow(clippy::unnecessary_cast)]
+pub fn device_number(stat: &libc::stat) -> u64 {
+    stat.st_dev as u64
+}
@@ -1,6 +1,8 @@
 pub mod cert;
+pub mod cpu_features;
 pub mod crypt_config;
 pub mod format;
+pub mod fs;
 pub mod json;
 pub mod lru_cache;
 pub mod nom;
@@ -21,8 +23,14 @@ pub mod async_lru_cache;
 /// lower latency, which reduces the peak *and* average RSS size by an order of magnitude when
 /// running backup jobs. We measured a reduction by a factor of 10-20 in experiments and see much
 /// less erratic behavior in the overall's runtime RSS size.
+///
+/// This is a no-op on platforms without glibc.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
 pub fn setup_libc_malloc_opts() {
     unsafe {
         libc::mallopt(libc::M_MMAP_THRESHOLD, 4096 * 32);
     }
 }
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+pub fn setup_libc_malloc_opts() {}
@@ -19,10 +19,12 @@ use pbs_client::tools::key_source::get_encryption_key_password;
 use pbs_client::{BackupRepository, BackupWriter};
 use pbs_datastore::data_blob::{DataBlob, DataChunkBuilder};
 use pbs_key_config::{load_and_decrypt_key, KeyDerivationConfig};
+use pbs_tools::cpu_features::CryptoAcceleration;
 use pbs_tools::crypt_config::CryptConfig;
 
 use crate::{
-    connect, extract_repository_from_value, record_repository, KEYFILE_SCHEMA, REPO_URL_SCHEMA,
+    connect_shared, extract_repository_from_value, record_repository, KEYFILE_SCHEMA,
+    REPO_URL_SCHEMA,
 };
 
 #[api()]
@@ -224,7 +226,7 @@ async fn test_upload_speed(
 ) -> Result<(), Error> {
     let backup_time = proxmox_time::epoch_i64();
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
     record_repository(&repo);
 
     log::debug!("Connecting to backup server");
@@ -236,6 +238,7 @@ async fn test_upload_speed(
         &(BackupType::Host, "benchmark".to_string(), backup_time).into(),
         false,
         true,
+        None,
     )
     .await?;
 
@@ -251,6 +254,9 @@ async fn test_upload_speed(
 
 // test hash/crypt/compress speed
 fn test_crypt_speed(benchmark_result: &mut BenchmarkResult) -> Result<(), Error> {
+    let acceleration = CryptoAcceleration::detect();
+    log::info!("Hardware acceleration: {acceleration}");
+
     let pw = b"test";
 
     let kdf = KeyDerivationConfig::Scrypt {
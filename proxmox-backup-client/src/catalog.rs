@@ -1,25 +1,28 @@
 use std::io::{Seek, SeekFrom};
-use std::os::unix::fs::OpenOptionsExt;
 use std::sync::Arc;
 
 use anyhow::{bail, format_err, Error};
-use serde_json::Value;
+use serde_json::{json, Value};
+
+use pxar::accessor::aio::Accessor;
+use pxar::accessor::ReadAt;
 
 use proxmox_router::cli::*;
 use proxmox_schema::api;
 
-use pbs_api_types::BackupNamespace;
+use pbs_api_types::{BackupGroup, BackupNamespace, FileSearchResult};
 use pbs_client::tools::key_source::get_encryption_key_password;
 use pbs_client::{BackupReader, RemoteChunkReader};
 use pbs_tools::crypt_config::CryptConfig;
 use pbs_tools::json::required_string_param;
 
 use crate::{
-    complete_backup_snapshot, complete_group_or_snapshot, complete_namespace,
-    complete_pxar_archive_name, complete_repository, connect, crypto_parameters, decrypt_key,
-    dir_or_last_from_group, extract_repository_from_value, format_key_source, optional_ns_param,
-    record_repository, BackupDir, BufferedDynamicReadAt, BufferedDynamicReader, CatalogReader,
-    DynamicIndexReader, IndexFile, Shell, CATALOG_NAME, KEYFD_SCHEMA, REPO_URL_SCHEMA,
+    complete_backup_group, complete_backup_snapshot, complete_group_or_snapshot,
+    complete_namespace, complete_pxar_archive_name, complete_repository, connect_shared,
+    crypto_parameters, decrypt_key, dir_or_last_from_group, extract_repository_from_value,
+    format_key_source, optional_ns_param, record_repository, BackupDir, BufferedDynamicReadAt,
+    BufferedDynamicReader, CatalogReader, DynamicIndexReader, IndexFile, Shell, CATALOG_NAME,
+    KEYFD_SCHEMA, REPO_URL_SCHEMA,
 };
 
 #[api(
@@ -46,6 +49,12 @@ use crate::{
                 schema: KEYFD_SCHEMA,
                 optional: true,
             },
+            tree: {
+                type: bool,
+                description: "Print the catalog as a tree, with file sizes.",
+                optional: true,
+                default: false,
+            },
         }
    }
 )]
@@ -72,7 +81,7 @@ async fn dump_catalog(param: Value) -> Result<Value, Error> {
         }
     };
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let client = BackupReader::start(
         &client,
@@ -104,11 +113,7 @@ async fn dump_catalog(param: Value) -> Result<Value, Error> {
 
     let mut reader = BufferedDynamicReader::new(index, chunk_reader);
 
-    let mut catalogfile = std::fs::OpenOptions::new()
-        .write(true)
-        .read(true)
-        .custom_flags(libc::O_TMPFILE)
-        .open("/tmp")?;
+    let mut catalogfile = pbs_tools::fs::open_tmpfile("/tmp")?;
 
     std::io::copy(&mut reader, &mut catalogfile)
         .map_err(|err| format_err!("unable to download catalog - {}", err))?;
@@ -117,7 +122,11 @@ async fn dump_catalog(param: Value) -> Result<Value, Error> {
 
     let mut catalog_reader = CatalogReader::new(catalogfile);
 
-    catalog_reader.dump()?;
+    if param["tree"].as_bool().unwrap_or(false) {
+        catalog_reader.dump_tree()?;
+    } else {
+        catalog_reader.dump()?;
+    }
 
     record_repository(&repo);
 
@@ -158,7 +167,7 @@ async fn dump_catalog(param: Value) -> Result<Value, Error> {
 /// Shell to interactively inspect and restore snapshots.
 async fn catalog_shell(param: Value) -> Result<(), Error> {
     let repo = extract_repository_from_value(&param)?;
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
     let backup_ns = optional_ns_param(&param)?;
     let path = required_string_param(&param, "snapshot")?;
     let archive_name = required_string_param(&param, "archive-name")?;
@@ -196,11 +205,7 @@ async fn catalog_shell(param: Value) -> Result<(), Error> {
     )
     .await?;
 
-    let mut tmpfile = std::fs::OpenOptions::new()
-        .write(true)
-        .read(true)
-        .custom_flags(libc::O_TMPFILE)
-        .open("/tmp")?;
+    let mut tmpfile = pbs_tools::fs::open_tmpfile("/tmp")?;
 
     let (manifest, _) = client.download_manifest().await?;
     manifest.check_fingerprint(crypt_config.as_ref().map(Arc::as_ref))?;
@@ -219,8 +224,8 @@ async fn catalog_shell(param: Value) -> Result<(), Error> {
     );
     let reader = BufferedDynamicReader::new(index, chunk_reader);
     let archive_size = reader.archive_size();
-    let reader: pbs_pxar_fuse::Reader = Arc::new(BufferedDynamicReadAt::new(reader));
-    let decoder = pbs_pxar_fuse::Accessor::new(reader, archive_size).await?;
+    let reader: Arc<dyn ReadAt + Send + Sync> = Arc::new(BufferedDynamicReadAt::new(reader));
+    let decoder = Accessor::new(reader, archive_size).await?;
 
     client.download(CATALOG_NAME, &mut tmpfile).await?;
     let index = DynamicIndexReader::new(tmpfile)
@@ -240,11 +245,7 @@ async fn catalog_shell(param: Value) -> Result<(), Error> {
         most_used,
     );
     let mut reader = BufferedDynamicReader::new(index, chunk_reader);
-    let mut catalogfile = std::fs::OpenOptions::new()
-        .write(true)
-        .read(true)
-        .custom_flags(libc::O_TMPFILE)
-        .open("/tmp")?;
+    let mut catalogfile = pbs_tools::fs::open_tmpfile("/tmp")?;
 
     std::io::copy(&mut reader, &mut catalogfile)
         .map_err(|err| format_err!("unable to download catalog - {}", err))?;
@@ -261,6 +262,94 @@ async fn catalog_shell(param: Value) -> Result<(), Error> {
     Ok(())
 }
 
+#[api(
+   input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            pattern: {
+                type: String,
+                description: "Glob pattern matched against the entry names, or against the \
+                    paths within the archives if it contains a slash.",
+            },
+            group: {
+                type: String,
+                description: "Only search the snapshots of this backup group.",
+                optional: true,
+            },
+            limit: {
+                type: Integer,
+                description: "Show at most this many entries.",
+                minimum: 1,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+   }
+)]
+/// Search the catalogs of all snapshots on the server, newest snapshots first.
+async fn search_catalogs(param: Value) -> Result<Value, Error> {
+    let repo = extract_repository_from_value(&param)?;
+
+    let output_format = get_output_format(&param);
+
+    let backup_ns = optional_ns_param(&param)?;
+    let pattern = required_string_param(&param, "pattern")?;
+
+    let mut args = json!({ "pattern": pattern });
+    if !backup_ns.is_root() {
+        args["ns"] = serde_json::to_value(&backup_ns)?;
+    }
+    if let Some(group) = param["group"].as_str() {
+        let group: BackupGroup = group.parse()?;
+        args["backup-type"] = serde_json::to_value(group.ty)?;
+        args["backup-id"] = group.id.into();
+    }
+    if let Some(limit) = param["limit"].as_u64() {
+        args["limit"] = limit.into();
+    }
+
+    let client = connect_shared(&repo)?;
+
+    let path = format!("api2/json/admin/datastore/{}/file-search", repo.store());
+    let mut result = client.get(&path, Some(args)).await?;
+
+    record_repository(&repo);
+
+    let render_snapshot_path = |_v: &Value, record: &Value| -> Result<String, Error> {
+        let item: FileSearchResult = serde_json::from_value(record.to_owned())?;
+        Ok(item.backup.to_string())
+    };
+
+    let options = default_table_format_options()
+        .column(
+            ColumnConfig::new("backup-id")
+                .renderer(render_snapshot_path)
+                .header("snapshot"),
+        )
+        .column(ColumnConfig::new("archive"))
+        .column(ColumnConfig::new("path"))
+        .column(ColumnConfig::new("size").renderer(pbs_tools::format::render_bytes_human_readable))
+        .column(ColumnConfig::new("mtime").renderer(pbs_tools::format::render_epoch));
+
+    let return_type = &pbs_api_types::ADMIN_DATASTORE_SEARCH_FILES_RETURN_TYPE;
+
+    let mut data: Value = result["data"].take();
+
+    format_and_print_result_full(&mut data, return_type, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
 pub fn catalog_mgmt_cli() -> CliCommandMap {
     let catalog_shell_cmd_def = CliCommand::new(&API_METHOD_CATALOG_SHELL)
         .arg_param(&["snapshot", "archive-name"])
@@ -275,7 +364,14 @@ pub fn catalog_mgmt_cli() -> CliCommandMap {
         .completion_cb("ns", complete_namespace)
         .completion_cb("snapshot", complete_backup_snapshot);
 
+    let catalog_search_cmd_def = CliCommand::new(&API_METHOD_SEARCH_CATALOGS)
+        .arg_param(&["pattern"])
+        .completion_cb("repository", complete_repository)
+        .completion_cb("ns", complete_namespace)
+        .completion_cb("group", complete_backup_group);
+
     CliCommandMap::new()
         .insert("dump", catalog_dump_cmd_def)
+        .insert("search", catalog_search_cmd_def)
         .insert("shell", catalog_shell_cmd_def)
 }
@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+
+use anyhow::Error;
+use serde_json::{json, Value};
+
+use proxmox_router::cli::{
+    complete_file_name, format_and_print_result, get_output_format, CliCommand, CliCommandMap,
+    OUTPUT_FORMAT,
+};
+use proxmox_schema::api;
+
+use pbs_client::ChangeJournal;
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                type: String,
+                description: "Name of the change journal.",
+            },
+            path: {
+                type: Array,
+                description: "Directories to watch.",
+                items: {
+                    type: String,
+                    description: "Directory path.",
+                },
+            },
+            "max-age": {
+                type: Integer,
+                description: "Keep the recorded changes for this many days.",
+                optional: true,
+                minimum: 1,
+                default: 7,
+            },
+        }
+    }
+)]
+/// Record the changed directories below the given paths until terminated, so that dry runs with
+/// '--change-journal <name>' can skip unchanged directories. Requires root and Linux 5.9 or
+/// newer.
+fn watch_change_journal(
+    name: String,
+    path: Vec<String>,
+    max_age: Option<i64>,
+) -> Result<(), Error> {
+    let max_age = max_age.unwrap_or(7);
+    let roots: Vec<PathBuf> = path.into_iter().map(PathBuf::from).collect();
+
+    pbs_client::watch_changes(&name, &roots, max_age * 24 * 3600)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                type: String,
+                description: "Name of the change journal.",
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show the state of a change journal.
+fn show_change_journal(param: Value, name: String) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+
+    let journal = ChangeJournal::load(&name)?;
+
+    if output_format == "text" {
+        let roots: Vec<String> = journal
+            .roots()
+            .iter()
+            .map(|root| root.to_string_lossy().into_owned())
+            .collect();
+        println!("Watched: {}", roots.join(", "));
+        println!(
+            "Complete since: {}",
+            proxmox_time::epoch_to_rfc3339_utc(journal.complete_since())?
+        );
+        println!(
+            "Updated: {}",
+            proxmox_time::epoch_to_rfc3339_utc(journal.updated())?
+        );
+        println!("Recorded changes: {}", journal.change_count());
+        println!(
+            "Watcher running: {}",
+            if journal.is_current() { "yes" } else { "no" }
+        );
+    } else {
+        let data = json!({
+            "roots": journal.roots(),
+            "complete-since": journal.complete_since(),
+            "updated": journal.updated(),
+            "changes": journal.change_count(),
+            "running": journal.is_current(),
+        });
+        format_and_print_result(&data, &output_format);
+    }
+
+    Ok(())
+}
+
+pub fn cli() -> CliCommandMap {
+    CliCommandMap::new()
+        .insert(
+            "watch",
+            CliCommand::new(&API_METHOD_WATCH_CHANGE_JOURNAL)
+                .arg_param(&["name", "path"])
+                .completion_cb("path", complete_file_name),
+        )
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_CHANGE_JOURNAL).arg_param(&["name"]),
+        )
+}
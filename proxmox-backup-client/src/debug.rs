@@ -0,0 +1,202 @@
+use std::io::BufRead;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use serde_json::Value;
+
+use pathpatterns::{MatchEntry, MatchType, PatternFlag};
+use proxmox_router::cli::*;
+use proxmox_schema::api;
+
+use pbs_client::pxar::parse_pxarexclude_line;
+
+/// A pattern of the effective exclude list, with where it was defined.
+struct Rule {
+    entry: MatchEntry,
+    pattern: String,
+    origin: String,
+}
+
+impl Rule {
+    fn describe(&self) -> String {
+        format!("'{}' ({})", self.pattern, self.origin)
+    }
+}
+
+// adds the patterns of the `.pxarexclude` file in `dir`, like a backup does
+fn read_pxar_excludes(dir: &Path, relative: &Path, rules: &mut Vec<Rule>) -> Result<(), Error> {
+    let path = dir.join(".pxarexclude");
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => bail!("unable to open {path:?} - {err}"),
+    };
+
+    for (number, line) in std::io::BufReader::new(file).split(b'\n').enumerate() {
+        let line = line.map_err(|err| format_err!("unable to read {path:?} - {err}"))?;
+        let origin = format!("{} line {}", path.display(), number + 1);
+        match parse_pxarexclude_line(relative.as_os_str().as_bytes(), &line) {
+            Ok(Some(entry)) => rules.push(Rule {
+                entry,
+                pattern: String::from_utf8_lossy(&line).trim().to_string(),
+                origin,
+            }),
+            Ok(None) => (),
+            Err(err) => log::warn!("ignoring bad pattern in {origin}: {err}"),
+        }
+    }
+
+    Ok(())
+}
+
+// the last rule matching `path`, which decides whether it is included
+fn matching_rule<'a>(rules: &'a [Rule], path: &Path, mode: u32) -> Option<&'a Rule> {
+    let match_path = Path::new("/").join(path);
+    rules.iter().rev().find(|rule| {
+        rule.entry
+            .matches(match_path.as_os_str().as_bytes(), Some(mode))
+    })
+}
+
+// resolves symlinks in the parent directories of `path`, but not in its file name
+fn resolve_parent(path: &Path) -> Result<PathBuf, Error> {
+    let resolved = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => std::fs::canonicalize(parent)?.join(name),
+        _ => std::fs::canonicalize(path)?,
+    };
+    Ok(resolved)
+}
+
+fn check_path(root: &Path, path: &Path, cli_rules: &[&str]) -> Result<String, Error> {
+    let path =
+        resolve_parent(path).map_err(|err| format_err!("unable to access {path:?} - {err}"))?;
+    let relative = path
+        .strip_prefix(root)
+        .map_err(|_| format_err!("{path:?} is not below {root:?}"))?;
+
+    let mut rules = Vec::new();
+    for pattern in cli_rules {
+        rules.push(Rule {
+            entry: MatchEntry::parse_pattern(*pattern, PatternFlag::PATH_NAME, MatchType::Exclude)
+                .map_err(|err| format_err!("invalid exclude pattern entry: {err}"))?,
+            pattern: pattern.to_string(),
+            origin: "command line".to_string(),
+        });
+    }
+
+    let mut current = PathBuf::new();
+    read_pxar_excludes(root, &current, &mut rules)?;
+
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
+        current.push(component);
+        let metadata = std::fs::symlink_metadata(root.join(&current))
+            .map_err(|err| format_err!("unable to access {:?} - {err}", root.join(&current)))?;
+
+        let rule = matching_rule(&rules, &current, metadata.mode());
+        if let Some(rule) = rule {
+            if rule.entry.match_type() == MatchType::Exclude {
+                if components.peek().is_some() {
+                    return Ok(format!(
+                        "excluded, parent directory /{} excluded by {}",
+                        current.display(),
+                        rule.describe(),
+                    ));
+                }
+                return Ok(format!("excluded by {}", rule.describe()));
+            }
+        }
+
+        if components.peek().is_none() {
+            return Ok(match rule {
+                Some(rule) => format!("included by {}", rule.describe()),
+                None => "included, no pattern matched".to_string(),
+            });
+        }
+
+        if !metadata.is_dir() {
+            bail!("/{} is not a directory", current.display());
+        }
+        read_pxar_excludes(&root.join(&current), &current, &mut rules)?;
+    }
+
+    Ok("included, backup source directory".to_string())
+}
+
+#[api(
+    input: {
+        properties: {
+            paths: {
+                type: Array,
+                description: "Paths to check.",
+                items: {
+                    type: String,
+                    description: "Path to check.",
+                },
+            },
+            root: {
+                type: String,
+                description: "Directory used as backup source.",
+                optional: true,
+                default: "/",
+            },
+            exclude: {
+                type: Array,
+                description: "List of paths or patterns excluded on the command line, like for \
+                    the backup command.",
+                optional: true,
+                items: {
+                    type: String,
+                    description: "Path or match pattern.",
+                },
+            },
+        }
+    }
+)]
+/// Check whether a backup of a directory would include the given paths, and print the
+/// exclude pattern which decided it.
+///
+/// Evaluates the patterns given on the command line and those of the `.pxarexclude` files in
+/// the backup source directory and the parent directories of each path, like a backup does.
+fn match_paths(param: Value) -> Result<Value, Error> {
+    let root = param["root"].as_str().unwrap_or("/");
+    let root = std::fs::canonicalize(root)
+        .map_err(|err| format_err!("unable to access {root:?} - {err}"))?;
+
+    let empty = Vec::new();
+    let cli_rules = param["exclude"]
+        .as_array()
+        .unwrap_or(&empty)
+        .iter()
+        .map(|pattern| {
+            pattern
+                .as_str()
+                .ok_or_else(|| format_err!("Invalid pattern string slice"))
+        })
+        .collect::<Result<Vec<&str>, Error>>()?;
+
+    let cwd = std::env::current_dir()?;
+    for path in param["paths"].as_array().unwrap_or(&empty) {
+        let path = path
+            .as_str()
+            .ok_or_else(|| format_err!("Invalid path string slice"))?;
+        match check_path(&root, &cwd.join(path), &cli_rules) {
+            Ok(result) => println!("{path}: {result}"),
+            Err(err) => log::error!("{path}: {err}"),
+        }
+    }
+
+    Ok(Value::Null)
+}
+
+pub fn cli() -> CliCommandMap {
+    CliCommandMap::new().insert(
+        "match",
+        CliCommand::new(&API_METHOD_MATCH_PATHS)
+            .arg_param(&["paths"])
+            .completion_cb("paths", complete_file_name)
+            .completion_cb("root", complete_file_name),
+    )
+}
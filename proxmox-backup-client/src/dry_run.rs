@@ -0,0 +1,484 @@
+//! Dry runs of backups
+//!
+//! Walks the backup sources like a real backup does, including the exclude patterns, but instead
+//! of reading and uploading any file contents, compares the file tree with the catalog of the
+//! previous snapshot to estimate how much new data a backup would upload.
+//!
+//! With a change journal, directories without changes since the previous snapshot are not walked
+//! again, their entries are taken from the previous catalog instead.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::CStr;
+use std::io::{Seek, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{format_err, Error};
+use nix::fcntl::OFlag;
+use nix::sys::stat::Mode;
+
+use proxmox_human_byte::HumanByte;
+
+use pbs_api_types::BackupNamespace;
+use pbs_client::pxar::{BackupProfile, Flags, PxarCreateOptions};
+use pbs_client::{
+    BackupReader, BackupRepository, BackupSpecificationType, ChangeJournal, HttpClient,
+    RemoteChunkReader,
+};
+use pbs_datastore::catalog::{BackupCatalogWriter, DirEntryAttribute};
+use pbs_datastore::filename_index::walk_catalog;
+use pbs_tools::crypt_config::CryptConfig;
+
+use crate::{
+    api_datastore_latest_snapshot, BackupDir, BufferedDynamicReader, CatalogReader, CATALOG_NAME,
+};
+
+/// Entries of the previous archive by path, with size and modification time for regular files.
+type PreviousEntries = BTreeMap<Vec<u8>, Option<(u64, i64)>>;
+
+/// Directories of an archive whose subtree did not change since the previous snapshot according
+/// to a change journal.
+struct UnchangedDirs {
+    journal: Arc<ChangeJournal>,
+    source: PathBuf,
+    since: i64,
+    /// Directories of the previous archive.
+    previous_dirs: HashSet<Vec<u8>>,
+    /// Directories which were not walked, to take their entries from the previous archive.
+    skipped: Mutex<HashSet<Vec<u8>>>,
+}
+
+impl UnchangedDirs {
+    // `path` is relative to the archive root
+    fn check(&self, path: &Path, device: u64) -> bool {
+        let mut catalog_path = b"/".to_vec();
+        catalog_path.extend(path.as_os_str().as_bytes());
+
+        let source_path = self.source.join(path);
+        if !self.previous_dirs.contains(&catalog_path)
+            || !self.journal.covers(&source_path, device, self.since)
+            || self.journal.changed_below(&source_path, self.since)
+        {
+            return false;
+        }
+
+        self.skipped.lock().unwrap().insert(catalog_path);
+        true
+    }
+}
+
+/// Compares the entries of a pxar archive, as reported to its catalog, with the regular files
+/// of the same archive in the previous snapshot.
+#[derive(Default)]
+struct ChangeCollector {
+    path: Vec<u8>,
+    dir_stack: Vec<usize>,
+    previous: PreviousEntries,
+    unchanged: Option<Arc<UnchangedDirs>>,
+    entries: u64,
+    files: u64,
+    size: u64,
+    new_files: u64,
+    new_size: u64,
+    changed_files: u64,
+    changed_size: u64,
+    unchanged_dirs: u64,
+}
+
+impl ChangeCollector {
+    fn new(previous: PreviousEntries, unchanged: Option<Arc<UnchangedDirs>>) -> Self {
+        Self {
+            previous,
+            unchanged,
+            ..Default::default()
+        }
+    }
+
+    fn entry_path(&self, name: &CStr) -> Vec<u8> {
+        let mut path = self.path.clone();
+        path.push(b'/');
+        path.extend(name.to_bytes());
+        path
+    }
+
+    fn add_entry(&mut self, name: &CStr) -> Result<(), Error> {
+        self.entries += 1;
+        self.previous.remove(&self.entry_path(name));
+        Ok(())
+    }
+
+    // take the entries below the current directory from the previous archive
+    fn add_unchanged_subtree(&mut self) {
+        let mut start = self.path.clone();
+        start.push(b'/');
+        let mut end = self.path.clone();
+        end.push(b'/' + 1);
+
+        let mut subtree = self.previous.split_off(&start);
+        let mut rest = subtree.split_off(&end);
+        self.previous.append(&mut rest);
+
+        for entry in subtree.into_values() {
+            self.entries += 1;
+            if let Some((size, _mtime)) = entry {
+                self.files += 1;
+                self.size += size;
+            }
+        }
+        self.unchanged_dirs += 1;
+    }
+
+    // regular files of the previous archive which are gone
+    fn removed_files(&self) -> usize {
+        self.previous
+            .values()
+            .filter(|entry| entry.is_some())
+            .count()
+    }
+}
+
+impl BackupCatalogWriter for ChangeCollector {
+    fn start_directory(&mut self, name: &CStr) -> Result<(), Error> {
+        self.add_entry(name)?;
+        self.dir_stack.push(self.path.len());
+        self.path = self.entry_path(name);
+        Ok(())
+    }
+
+    fn end_directory(&mut self) -> Result<(), Error> {
+        let skipped = match &self.unchanged {
+            Some(unchanged) => unchanged.skipped.lock().unwrap().remove(&self.path),
+            None => false,
+        };
+        if skipped {
+            self.add_unchanged_subtree();
+        }
+
+        let len = self
+            .dir_stack
+            .pop()
+            .ok_or_else(|| format_err!("got unexpected end of directory"))?;
+        self.path.truncate(len);
+        Ok(())
+    }
+
+    fn add_file(&mut self, name: &CStr, size: u64, mtime: i64) -> Result<(), Error> {
+        let path = self.entry_path(name);
+        self.entries += 1;
+        self.files += 1;
+        self.size += size;
+
+        match self.previous.remove(&path).flatten() {
+            None => {
+                log::debug!("new: {}", String::from_utf8_lossy(&path));
+                self.new_files += 1;
+                self.new_size += size;
+            }
+            Some(previous) if previous != (size, mtime) => {
+                log::debug!("changed: {}", String::from_utf8_lossy(&path));
+                self.changed_files += 1;
+                self.changed_size += size;
+            }
+            Some(_) => {}
+        }
+        Ok(())
+    }
+
+    fn add_symlink(&mut self, name: &CStr) -> Result<(), Error> {
+        self.add_entry(name)
+    }
+
+    fn add_hardlink(&mut self, name: &CStr) -> Result<(), Error> {
+        self.add_entry(name)
+    }
+
+    fn add_block_device(&mut self, name: &CStr) -> Result<(), Error> {
+        self.add_entry(name)
+    }
+
+    fn add_char_device(&mut self, name: &CStr) -> Result<(), Error> {
+        self.add_entry(name)
+    }
+
+    fn add_fifo(&mut self, name: &CStr) -> Result<(), Error> {
+        self.add_entry(name)
+    }
+
+    fn add_socket(&mut self, name: &CStr) -> Result<(), Error> {
+        self.add_entry(name)
+    }
+}
+
+async fn download_catalog(
+    client: &HttpClient,
+    repo: &BackupRepository,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+    crypt_config: Option<Arc<CryptConfig>>,
+) -> Result<CatalogReader<std::fs::File>, Error> {
+    let client = BackupReader::start(
+        client,
+        crypt_config.clone(),
+        repo.store(),
+        ns,
+        snapshot,
+        true,
+    )
+    .await?;
+
+    let (manifest, _) = client.download_manifest().await?;
+    manifest.check_fingerprint(crypt_config.as_ref().map(Arc::as_ref))?;
+
+    let index = client
+        .download_dynamic_index(&manifest, CATALOG_NAME)
+        .await?;
+    let most_used = index.find_most_used_chunks(8);
+    let file_info = manifest.lookup_file_info(CATALOG_NAME)?;
+    let chunk_reader = RemoteChunkReader::new(
+        client.clone(),
+        crypt_config,
+        file_info.chunk_crypt_mode(),
+        most_used,
+    );
+    let mut reader = BufferedDynamicReader::new(index, chunk_reader);
+
+    let mut catalogfile = pbs_tools::fs::open_tmpfile("/tmp")?;
+
+    std::io::copy(&mut reader, &mut catalogfile)
+        .map_err(|err| format_err!("unable to download catalog - {}", err))?;
+
+    catalogfile.seek(SeekFrom::Start(0))?;
+
+    Ok(CatalogReader::new(catalogfile))
+}
+
+// the entries of `archive` in the catalog, and its directories
+fn previous_entries(
+    catalog: &mut CatalogReader<std::fs::File>,
+    archive: &str,
+) -> Result<Option<(PreviousEntries, HashSet<Vec<u8>>)>, Error> {
+    let root = catalog.root()?;
+    let archive = match catalog.lookup(&root, archive.as_bytes())? {
+        Some(archive) => archive,
+        None => return Ok(None),
+    };
+
+    let mut entries = BTreeMap::new();
+    let mut dirs = HashSet::new();
+    walk_catalog(catalog, &archive, &mut Vec::new(), &mut |path, entry| {
+        match entry.attr {
+            DirEntryAttribute::File { size, mtime } => {
+                entries.insert(path.to_vec(), Some((size, mtime)));
+            }
+            DirEntryAttribute::Directory { .. } => {
+                entries.insert(path.to_vec(), None);
+                dirs.insert(path.to_vec());
+            }
+            _ => {
+                entries.insert(path.to_vec(), None);
+            }
+        }
+        Ok(())
+    })?;
+
+    Ok(Some((entries, dirs)))
+}
+
+// the directories of `source` which are unchanged since `since` according to `journal`
+fn unchanged_dirs(
+    journal: Arc<ChangeJournal>,
+    source: &str,
+    since: i64,
+    previous_dirs: HashSet<Vec<u8>>,
+) -> Result<Option<UnchangedDirs>, Error> {
+    let source = std::fs::canonicalize(source)?;
+    let stat = nix::sys::stat::stat(&source)?;
+    if !journal.covers(&source, pbs_tools::fs::device_number(&stat), since) {
+        log::warn!(
+            "change journal does not cover {source:?} since the previous snapshot, walking all \
+            directories"
+        );
+        return Ok(None);
+    }
+
+    Ok(Some(UnchangedDirs {
+        journal,
+        source,
+        since,
+        previous_dirs,
+        skipped: Mutex::new(HashSet::new()),
+    }))
+}
+
+async fn collect_changes(
+    source: &str,
+    previous: PreviousEntries,
+    unchanged: Option<UnchangedDirs>,
+    mut options: PxarCreateOptions,
+) -> Result<ChangeCollector, Error> {
+    let dir = nix::dir::Dir::open(source, OFlag::O_DIRECTORY, Mode::empty())?;
+
+    let unchanged = unchanged.map(Arc::new);
+    if let Some(unchanged) = unchanged.clone() {
+        options.skip_unchanged_dirs =
+            Some(Arc::new(move |path, device| unchanged.check(path, device)));
+    }
+    let collector = Arc::new(Mutex::new(ChangeCollector::new(previous, unchanged)));
+
+    pbs_client::pxar::create_archive(
+        dir,
+        pxar::encoder::sync::StandardWriter::new(std::io::sink()),
+        Flags::DEFAULT,
+        |_| Ok(()),
+        Some(collector.clone()),
+        options,
+    )
+    .await?;
+
+    let collector = std::mem::take(&mut *collector.lock().unwrap());
+    Ok(collector)
+}
+
+/// Walk the sources of a backup without uploading anything, and print a summary of the changes
+/// compared to `previous_ref`, or the last snapshot of the group of `snapshot`.
+///
+/// Archives in `renamed_archives` are compared with their previous name, by new name.
+///
+/// Only regular files are compared by size and modification time, so the amount of new data is
+/// an upper bound of what a backup would upload after deduplication. Images are not compared.
+/// `pxar_options` should skip the file contents. If `profile` is set, the walks of the directory
+/// archives are profiled.
+///
+/// With `change_journal`, directories without recorded changes since the previous snapshot are
+/// not walked. Changes to such directories made by other means than file system calls on this
+/// host, like writes through a memory mapping or through another hardlink, are missed then.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn dry_run_backup(
+    client: &HttpClient,
+    repo: &BackupRepository,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+    previous_ref: Option<BackupDir>,
+    crypt_config: Option<Arc<CryptConfig>>,
+    upload_list: Vec<(BackupSpecificationType, String, String, &'static str, u64)>,
+    renamed_archives: &HashMap<String, String>,
+    pxar_options: PxarCreateOptions,
+    change_journal: Option<Arc<ChangeJournal>>,
+    mut profile: Option<&mut BackupProfile>,
+) -> Result<(), Error> {
+    let mut catalog = None;
+    let mut previous_time = None;
+    let has_pxar = upload_list
+        .iter()
+        .any(|(spec_type, ..)| matches!(spec_type, BackupSpecificationType::PXAR));
+    if has_pxar {
+        let previous = match previous_ref {
+            Some(previous) => Ok(previous),
+            None => {
+                let group = snapshot.group.clone();
+                api_datastore_latest_snapshot(client, repo.store(), ns, group).await
+            }
+        };
+        let result = match previous {
+            Ok(previous) => {
+                log::info!("Comparing with previous snapshot {previous}");
+                previous_time = Some(previous.time);
+                download_catalog(client, repo, ns, &previous, crypt_config).await
+            }
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(reader) => catalog = Some(reader),
+            Err(err) => log::warn!("unable to load previous catalog, all data is new - {err}"),
+        }
+    }
+
+    let mut total_size = 0;
+    let mut new_size = 0;
+
+    for (spec_type, filename, target_base, extension, size) in upload_list {
+        let target = format!("{target_base}.{extension}");
+        match spec_type {
+            BackupSpecificationType::CONFIG | BackupSpecificationType::LOGFILE => {
+                log::info!("{target}: {} from '{filename}'", HumanByte::from(size));
+                total_size += size;
+                new_size += size;
+            }
+            BackupSpecificationType::STREAM => {
+                log::info!("{target}: read from standard input, size unknown in a dry run");
+            }
+            BackupSpecificationType::IMAGE => {
+                log::info!(
+                    "{target}: image of {} from '{filename}', not compared with the previous \
+                    snapshot",
+                    HumanByte::from(size)
+                );
+                total_size += size;
+                new_size += size;
+            }
+            BackupSpecificationType::PXAR => {
+                let previous_target = match renamed_archives.get(&target_base) {
+                    Some(previous_name) => format!("{previous_name}.{extension}"),
+                    None => target.clone(),
+                };
+                let previous = match catalog.as_mut() {
+                    Some(catalog) => previous_entries(catalog, &previous_target)?,
+                    None => None,
+                };
+                let has_previous = previous.is_some();
+
+                let (previous, previous_dirs) = previous.unwrap_or_default();
+                let unchanged = match (change_journal.clone(), previous_time) {
+                    (Some(journal), Some(since)) if has_previous => {
+                        unchanged_dirs(journal, &filename, since, previous_dirs)?
+                    }
+                    _ => None,
+                };
+
+                let mut options = pxar_options.clone();
+                if let Some(profile) = profile.as_deref_mut() {
+                    options.profile = Some(profile.add_archive(&target));
+                }
+
+                let changes = collect_changes(&filename, previous, unchanged, options).await?;
+
+                log::info!(
+                    "{target}: {} entries, {} files with {} from '{filename}'",
+                    changes.entries,
+                    changes.files,
+                    HumanByte::from(changes.size),
+                );
+                total_size += changes.size;
+                if has_previous {
+                    log::info!(
+                        "{target}: {} new files ({}), {} changed files ({}), {} removed files",
+                        changes.new_files,
+                        HumanByte::from(changes.new_size),
+                        changes.changed_files,
+                        HumanByte::from(changes.changed_size),
+                        changes.removed_files(),
+                    );
+                    if changes.unchanged_dirs > 0 {
+                        log::info!(
+                            "{target}: {} unchanged directories taken from the previous snapshot",
+                            changes.unchanged_dirs,
+                        );
+                    }
+                    new_size += changes.new_size + changes.changed_size;
+                } else {
+                    log::info!("{target}: not part of the previous snapshot");
+                    new_size += changes.size;
+                }
+            }
+        }
+    }
+
+    log::info!(
+        "dry-run: estimated new data {} of {} total - no upload happened",
+        HumanByte::from(new_size),
+        HumanByte::from(total_size),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use std::io::{BufRead, IsTerminal, Write};
+
+use anyhow::{bail, Error};
+use serde_json::Value;
+
+use proxmox_router::cli::{
+    format_and_print_result, get_output_format, CliCommand, CliCommandMap, OUTPUT_FORMAT,
+};
+use proxmox_schema::api;
+
+use pbs_api_types::CERT_FINGERPRINT_SHA256_SCHEMA;
+use pbs_client::fingerprints::{
+    fetch_server_fingerprint, load_fingerprints, lookup_fingerprint, remove_fingerprint,
+    store_fingerprint,
+};
+
+use crate::{complete_repository, extract_repository_from_value, REPO_URL_SCHEMA};
+
+const PREFIX: &str = "proxmox-backup";
+
+fn render_time(epoch: Option<i64>) -> String {
+    epoch
+        .and_then(|epoch| proxmox_time::epoch_to_rfc3339_utc(epoch).ok())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List pinned server certificate fingerprints.
+fn list_fingerprints(param: Value) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+
+    let list = load_fingerprints(PREFIX)?;
+
+    if output_format == "text" {
+        for entry in list {
+            println!(
+                "{} {} (pinned: {}, expires: {})",
+                entry.server,
+                entry.fingerprint,
+                render_time(entry.pinned),
+                render_time(entry.not_after),
+            );
+        }
+    } else {
+        format_and_print_result(&serde_json::to_value(list)?, &output_format);
+    }
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            fingerprint: {
+                schema: CERT_FINGERPRINT_SHA256_SCHEMA,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Accept the current certificate fingerprint of the repository's server, for example after
+/// the certificate was renewed.
+///
+/// Without 'fingerprint', the new fingerprint has to be confirmed interactively.
+fn update_fingerprint(param: Value, fingerprint: Option<String>) -> Result<(), Error> {
+    let repo = extract_repository_from_value(&param)?;
+    let (server, port) = (repo.host(), repo.port());
+
+    let (current, not_after) = fetch_server_fingerprint(server, port)?;
+
+    let old = lookup_fingerprint(PREFIX, server, port);
+    if let Some(ref old) = old {
+        if old.fingerprint == current {
+            println!("fingerprint of '{}' did not change", old.server);
+            return Ok(());
+        }
+        println!("pinned:   {}", old.fingerprint);
+    }
+    println!("received: {}", current);
+    println!("expires:  {}", render_time(not_after));
+
+    match fingerprint {
+        Some(expected) => {
+            if expected.to_lowercase() != current {
+                bail!("received fingerprint does not match the given fingerprint");
+            }
+        }
+        None => {
+            if !std::io::stdin().is_terminal() {
+                bail!("no terminal - use the 'fingerprint' parameter to confirm the fingerprint");
+            }
+            loop {
+                print!("Accept new fingerprint? (y/n): ");
+                std::io::stdout().flush()?;
+                let mut line = String::new();
+                std::io::stdin().lock().read_line(&mut line)?;
+                match line.trim() {
+                    "y" | "Y" => break,
+                    "n" | "N" => bail!("fingerprint was not accepted"),
+                    _ => continue,
+                }
+            }
+        }
+    }
+
+    store_fingerprint(PREFIX, server, port, &current, not_after)?;
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Remove the pinned fingerprint of the repository's server.
+fn remove_pinned_fingerprint(param: Value) -> Result<(), Error> {
+    let repo = extract_repository_from_value(&param)?;
+
+    if !remove_fingerprint(PREFIX, repo.host(), repo.port())? {
+        bail!("no fingerprint pinned for '{}'", repo.host());
+    }
+
+    Ok(())
+}
+
+pub fn cli() -> CliCommandMap {
+    CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_FINGERPRINTS))
+        .insert(
+            "update",
+            CliCommand::new(&API_METHOD_UPDATE_FINGERPRINT)
+                .completion_cb("repository", complete_repository),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&API_METHOD_REMOVE_PINNED_FINGERPRINT)
+                .completion_cb("repository", complete_repository),
+        )
+}
@@ -0,0 +1,252 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, format_err, Error};
+use serde_json::{json, Value};
+
+use proxmox_router::cli::{
+    complete_file_name, format_and_print_result_full, get_output_format, CliCommand, CliCommandMap,
+    ColumnConfig, OUTPUT_FORMAT,
+};
+use proxmox_schema::{api, ApiType, ReturnType};
+use proxmox_sys::fs::file_get_contents;
+
+use pbs_api_types::{BackupGroup, BackupNamespace, CryptMode, KeyInfo};
+use pbs_client::tools::key_source::{find_default_encryption_key, CryptoParams, KeyWithSource};
+use pbs_client::tools::{complete_backup_group, complete_namespace, complete_repository};
+use pbs_client::tools::{connect_shared, extract_repository_from_value, REPO_URL_SCHEMA};
+use pbs_client::HttpClient;
+use pbs_key_config::KeyConfig;
+
+use crate::{merge_group_into, optional_ns_param, record_repository};
+
+fn group_key_param(ns: &BackupNamespace, group: &BackupGroup) -> Result<Value, Error> {
+    let mut param = json!({});
+    merge_group_into(param.as_object_mut().unwrap(), group.clone());
+    if !ns.is_root() {
+        param["ns"] = serde_json::to_value(ns)?;
+    }
+    Ok(param)
+}
+
+/// Fetch the passphrase protected encryption key stored on the server for a backup group.
+pub async fn fetch_group_key(
+    client: &HttpClient,
+    store: &str,
+    ns: &BackupNamespace,
+    group: &BackupGroup,
+) -> Result<KeyWithSource, Error> {
+    let path = format!("api2/json/admin/datastore/{store}/group-key");
+    let mut result = client
+        .get(&path, Some(group_key_param(ns, group)?))
+        .await
+        .map_err(|err| format_err!("unable to fetch key of group {group} - {err}"))?;
+
+    match result["data"].take() {
+        Value::String(key) => Ok(KeyWithSource::from_server(key.into_bytes())),
+        _ => bail!("got unexpected key data for group {group}"),
+    }
+}
+
+/// Replace the encryption key of `crypto` by the one stored on the server, if `server-key` is set.
+///
+/// The key is unlocked with the usual passphrase prompt or `PBS_ENCRYPTION_PASSWORD` later on,
+/// so no key file needs to exist on the client.
+pub async fn apply_server_key(
+    param: &Value,
+    client: &HttpClient,
+    store: &str,
+    ns: &BackupNamespace,
+    group: &BackupGroup,
+    crypto: &mut CryptoParams,
+) -> Result<(), Error> {
+    if !param["server-key"].as_bool().unwrap_or(false) {
+        return Ok(());
+    }
+
+    if param.get("keyfile").is_some() || param.get("keyfd").is_some() {
+        bail!("--server-key and --keyfile/--keyfd are mutually exclusive");
+    }
+    if param["crypt-mode"].as_str() == Some("none") {
+        bail!("--server-key and --crypt-mode=none are mutually exclusive");
+    }
+
+    crypto.enc_key = Some(fetch_group_key(client, store, ns, group).await?);
+    if crypto.mode == CryptMode::None {
+        crypto.mode = CryptMode::Encrypt;
+    }
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            group: {
+                type: String,
+                description: "Backup group.",
+            },
+            path: {
+                description: "Key file. Without this the default key will be uploaded.",
+                optional: true,
+            },
+        },
+    },
+)]
+/// Store a passphrase protected encryption key on the server for a backup group.
+///
+/// Clients can then use it with '--server-key' instead of a local key file. The server only
+/// stores the key as it is, it cannot unlock it without the passphrase.
+async fn upload_group_key(group: String, path: Option<String>, param: Value) -> Result<(), Error> {
+    let repo = extract_repository_from_value(&param)?;
+    let ns = optional_ns_param(&param)?;
+    let group: BackupGroup = group.parse()?;
+
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => find_default_encryption_key()?
+            .ok_or_else(|| format_err!("no encryption file provided and no default file found"))?,
+    };
+
+    let data = String::from_utf8(file_get_contents(&path)?)?;
+    let config: KeyConfig = serde_json::from_str(&data)?;
+    if config.kdf.is_none() {
+        bail!("key {path:?} is not protected by a passphrase, refusing to upload it");
+    }
+
+    let client = connect_shared(&repo)?;
+
+    let mut param = group_key_param(&ns, &group)?;
+    param["key"] = data.into();
+
+    let path = format!("api2/json/admin/datastore/{}/group-key", repo.store());
+    client.put(&path, Some(param)).await?;
+
+    record_repository(&repo);
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            group: {
+                type: String,
+                description: "Backup group.",
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Print the metadata of the encryption key stored on the server for a backup group.
+async fn show_group_key(group: String, param: Value) -> Result<(), Error> {
+    let repo = extract_repository_from_value(&param)?;
+    let ns = optional_ns_param(&param)?;
+    let group: BackupGroup = group.parse()?;
+    let output_format = get_output_format(&param);
+
+    let client = connect_shared(&repo)?;
+    let key = fetch_group_key(&client, repo.store(), &ns, &group).await?;
+    record_repository(&repo);
+
+    let config: KeyConfig = serde_json::from_slice(&key.key)?;
+    let info: KeyInfo = (&config).into();
+
+    let options = proxmox_router::cli::default_table_format_options()
+        .column(ColumnConfig::new("kdf"))
+        .column(ColumnConfig::new("created").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("modified").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("fingerprint"))
+        .column(ColumnConfig::new("hint"));
+
+    let return_type = ReturnType::new(false, &KeyInfo::API_SCHEMA);
+
+    format_and_print_result_full(
+        &mut serde_json::to_value(info)?,
+        &return_type,
+        &output_format,
+        &options,
+    );
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            group: {
+                type: String,
+                description: "Backup group.",
+            },
+        },
+    },
+)]
+/// Remove the encryption key stored on the server for a backup group.
+async fn remove_group_key(group: String, param: Value) -> Result<(), Error> {
+    let repo = extract_repository_from_value(&param)?;
+    let ns = optional_ns_param(&param)?;
+    let group: BackupGroup = group.parse()?;
+
+    let client = connect_shared(&repo)?;
+
+    let path = format!("api2/json/admin/datastore/{}/group-key", repo.store());
+    client
+        .delete(&path, Some(group_key_param(&ns, &group)?))
+        .await?;
+
+    record_repository(&repo);
+
+    Ok(())
+}
+
+pub fn cli() -> CliCommandMap {
+    let upload_cmd_def = CliCommand::new(&API_METHOD_UPLOAD_GROUP_KEY)
+        .arg_param(&["group", "path"])
+        .completion_cb("repository", complete_repository)
+        .completion_cb("ns", complete_namespace)
+        .completion_cb("group", complete_backup_group)
+        .completion_cb("path", complete_file_name);
+
+    let show_cmd_def = CliCommand::new(&API_METHOD_SHOW_GROUP_KEY)
+        .arg_param(&["group"])
+        .completion_cb("repository", complete_repository)
+        .completion_cb("ns", complete_namespace)
+        .completion_cb("group", complete_backup_group);
+
+    let remove_cmd_def = CliCommand::new(&API_METHOD_REMOVE_GROUP_KEY)
+        .arg_param(&["group"])
+        .completion_cb("repository", complete_repository)
+        .completion_cb("ns", complete_namespace)
+        .completion_cb("group", complete_backup_group);
+
+    CliCommandMap::new()
+        .insert("upload", upload_cmd_def)
+        .insert("show", show_cmd_def)
+        .insert("remove", remove_cmd_def)
+}
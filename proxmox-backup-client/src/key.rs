@@ -1,5 +1,5 @@
 use std::io::IsTerminal;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, format_err, Error};
 use serde::{Deserialize, Serialize};
@@ -10,7 +10,7 @@ use proxmox_router::cli::{
     ColumnConfig, OUTPUT_FORMAT,
 };
 use proxmox_schema::{api, ApiType, ReturnType};
-use proxmox_sys::fs::{file_get_contents, replace_file, CreateOptions};
+use proxmox_sys::fs::file_get_contents;
 use proxmox_sys::linux::tty;
 
 use pbs_api_types::{Kdf, KeyInfo, PASSWORD_HINT_SCHEMA};
@@ -18,6 +18,7 @@ use pbs_client::tools::key_source::{
     find_default_encryption_key, find_default_master_pubkey, get_encryption_key_password,
     place_default_encryption_key, place_default_master_pubkey,
 };
+use pbs_client::tools::state_file::{replace_secret_file, replace_state_file, StateFilePolicy};
 use pbs_datastore::paperkey::{generate_paper_key, PaperkeyFormat};
 use pbs_key_config::{rsa_decrypt_key_config, KeyConfig};
 
@@ -54,6 +55,16 @@ impl std::convert::TryFrom<openssl::rsa::Rsa<openssl::pkey::Public>> for RsaPubK
     }
 }
 
+// key files are created according to the state file policy of the client
+fn store_key_config<P: AsRef<Path>>(
+    key_config: &KeyConfig,
+    path: P,
+    replace: bool,
+) -> Result<(), Error> {
+    let options = StateFilePolicy::from_env()?.secret_file_options();
+    key_config.store_with_options(path, replace, options)
+}
+
 #[api(
     input: {
         properties: {
@@ -97,7 +108,7 @@ fn create(kdf: Option<Kdf>, path: Option<String>, hint: Option<String>) -> Resul
 
             let key_config = KeyConfig::without_password(key)?;
 
-            key_config.store(path, false)?;
+            store_key_config(&key_config, path, false)?;
         }
         Kdf::Scrypt | Kdf::PBKDF2 => {
             // always read passphrase from tty
@@ -110,7 +121,7 @@ fn create(kdf: Option<Kdf>, path: Option<String>, hint: Option<String>) -> Resul
             let mut key_config = KeyConfig::with_key(&key, &password, kdf)?;
             key_config.hint = hint;
 
-            key_config.store(&path, false)?;
+            store_key_config(&key_config, &path, false)?;
         }
     }
 
@@ -184,7 +195,7 @@ async fn import_with_master_key(
             let mut key_config = KeyConfig::without_password(key)?;
             key_config.created = created; // keep original value
 
-            key_config.store(path, true)?;
+            store_key_config(&key_config, path, true)?;
         }
         Kdf::Scrypt | Kdf::PBKDF2 => {
             let password = tty::read_and_verify_password("New Password: ")?;
@@ -193,7 +204,7 @@ async fn import_with_master_key(
             new_key_config.created = created; // keep original value
             new_key_config.hint = hint;
 
-            new_key_config.store(path, true)?;
+            store_key_config(&new_key_config, path, true)?;
         }
     }
 
@@ -253,7 +264,7 @@ fn change_passphrase(
             let mut key_config = KeyConfig::without_password(key)?;
             key_config.created = created; // keep original value
 
-            key_config.store(&path, true)?;
+            store_key_config(&key_config, &path, true)?;
         }
         Kdf::Scrypt | Kdf::PBKDF2 => {
             let password = tty::read_and_verify_password("New Password: ")?;
@@ -262,7 +273,7 @@ fn change_passphrase(
             new_key_config.created = created; // keep original value
             new_key_config.hint = hint;
 
-            new_key_config.store(&path, true)?;
+            store_key_config(&new_key_config, &path, true)?;
         }
     }
 
@@ -348,7 +359,7 @@ fn import_master_pubkey(path: String) -> Result<(), Error> {
 
     let target_path = place_default_master_pubkey()?;
 
-    replace_file(&target_path, &pem_data, CreateOptions::new(), true)?;
+    replace_state_file(&target_path, &pem_data, true)?;
 
     log::info!("Imported public master key to {:?}", target_path);
 
@@ -380,7 +391,7 @@ fn create_master_key() -> Result<(), Error> {
     let pub_key: Vec<u8> = pkey.public_key_to_pem()?;
     let filename_pub = "master-public.pem";
     log::info!("Writing public master key to {}", filename_pub);
-    replace_file(filename_pub, pub_key.as_slice(), CreateOptions::new(), true)?;
+    replace_state_file(filename_pub, pub_key.as_slice(), true)?;
 
     let cipher = openssl::symm::Cipher::aes_256_cbc();
     let priv_key: Vec<u8> =
@@ -388,12 +399,7 @@ fn create_master_key() -> Result<(), Error> {
 
     let filename_priv = "master-private.pem";
     log::info!("Writing private master key to {}", filename_priv);
-    replace_file(
-        filename_priv,
-        priv_key.as_slice(),
-        CreateOptions::new(),
-        true,
-    )?;
+    replace_secret_file(filename_priv, priv_key.as_slice())?;
 
     Ok(())
 }
@@ -1,79 +1,88 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::task::Context;
 
 use anyhow::{bail, format_err, Error};
-use futures::stream::{StreamExt, TryStreamExt};
 use serde::Deserialize;
 use serde_json::{json, Value};
-use tokio::sync::mpsc;
-use tokio_stream::wrappers::ReceiverStream;
 use xdg::BaseDirectories;
 
 use pathpatterns::{MatchEntry, MatchType, PatternFlag};
-use proxmox_async::blocking::TokioWriterAdapter;
 use proxmox_human_byte::HumanByte;
-use proxmox_io::StdChannelWriter;
-use proxmox_router::{cli::*, ApiMethod, RpcEnvironment};
+use proxmox_router::{cli::*, ApiMethod, HttpError, RpcEnvironment};
 use proxmox_schema::api;
 use proxmox_sys::fs::{file_get_json, image_size, replace_file, CreateOptions};
 use proxmox_time::{epoch_i64, strftime_local};
 use pxar::accessor::{MaybeReady, ReadAt, ReadAtOperation};
 
 use pbs_api_types::{
-    Authid, BackupDir, BackupGroup, BackupNamespace, BackupPart, BackupType, CryptMode,
-    Fingerprint, GroupListItem, PruneJobOptions, PruneListItem, RateLimitConfig, SnapshotListItem,
-    StorageStatus, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA,
-    BACKUP_TYPE_SCHEMA, TRAFFIC_CONTROL_BURST_SCHEMA, TRAFFIC_CONTROL_RATE_SCHEMA,
+    Authid, BackupDir, BackupGroup, BackupNamespace, BackupPart, BackupType, ClientDefaults,
+    CryptMode, Fingerprint, GroupListItem, PruneJobOptions, PruneListItem, RateLimitConfig,
+    SnapshotListItem, StorageStatus, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA,
+    BACKUP_TYPE_SCHEMA, PXAR_ENTRIES_MAX_SCHEMA, TRAFFIC_CONTROL_BURST_SCHEMA,
+    TRAFFIC_CONTROL_RATE_SCHEMA,
 };
 use pbs_client::catalog_shell::Shell;
-use pbs_client::pxar::ErrorHandler as PxarErrorHandler;
+use pbs_client::pxar::{BackupProfile, ErrorHandler as PxarErrorHandler, SpecialFilePolicy};
 use pbs_client::tools::{
     complete_archive_name, complete_auth_id, complete_backup_group, complete_backup_snapshot,
     complete_backup_source, complete_chunk_size, complete_group_or_snapshot,
     complete_img_archive_name, complete_namespace, complete_pxar_archive_name, complete_repository,
-    connect, connect_rate_limited, extract_repository_from_value,
+    connect_rate_limited, connect_shared, extract_repository_from_value,
     key_source::{
-        crypto_parameters, format_key_source, get_encryption_key_password, KEYFD_SCHEMA,
-        KEYFILE_SCHEMA, MASTER_PUBKEY_FD_SCHEMA, MASTER_PUBKEY_FILE_SCHEMA,
+        crypto_parameters, format_key_source, get_encryption_key_password, CryptoParams,
+        KEYFD_SCHEMA, KEYFILE_SCHEMA, MASTER_PUBKEY_FD_SCHEMA, MASTER_PUBKEY_FILE_SCHEMA,
+        SECURE_PERMS_CHECK_SCHEMA,
     },
+    state_file::replace_state_file,
     CHUNK_SIZE_SCHEMA, REPO_URL_SCHEMA,
 };
 use pbs_client::{
-    delete_ticket_info, parse_backup_specification, view_task_result, BackupReader,
-    BackupRepository, BackupSpecificationType, BackupStats, BackupWriter, ChunkStream,
-    FixedChunkStream, HttpClient, PxarBackupStream, RemoteChunkReader, UploadOptions,
-    BACKUP_SOURCE_SCHEMA,
+    delete_ticket_info, parse_backup_specification, view_task_result, BackupRepository,
+    BackupSession, BackupSessionOptions, BackupSpecificationType, BackupSpool, HttpClient,
+    RemoteChunkReader, RestoreSession, BACKUP_SOURCE_SCHEMA,
 };
-use pbs_datastore::catalog::{BackupCatalogWriter, CatalogReader, CatalogWriter};
+use pbs_datastore::catalog::CatalogReader;
 use pbs_datastore::chunk_store::verify_chunk_size;
 use pbs_datastore::dynamic_index::{BufferedDynamicReader, DynamicIndexReader};
-use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{
-    archive_type, ArchiveType, BackupManifest, ENCRYPTED_KEY_BLOB_NAME, MANIFEST_BLOB_NAME,
+    archive_type, ArchiveType, ENCRYPTED_KEY_BLOB_NAME, MANIFEST_BLOB_NAME,
 };
-use pbs_datastore::read_chunk::AsyncReadChunk;
 use pbs_datastore::CATALOG_NAME;
 use pbs_key_config::{decrypt_key, rsa_encrypt_key_config, KeyConfig};
+use pbs_tools::cpu_features::CryptoAcceleration;
 use pbs_tools::crypt_config::CryptConfig;
 use pbs_tools::json;
 
 mod benchmark;
 pub use benchmark::*;
+#[cfg(feature = "fuse")]
 mod mount;
+#[cfg(feature = "fuse")]
 pub use mount::*;
 mod task;
 pub use task::*;
 mod catalog;
 pub use catalog::*;
+mod change_journal;
+mod dry_run;
+mod merkle;
+pub use merkle::*;
 mod snapshot;
 pub use snapshot::*;
+mod verify_local;
+pub use verify_local::*;
+pub mod debug;
+pub mod fingerprint;
+pub mod group_key;
 pub mod key;
 pub mod namespace;
+pub mod spool;
 
 fn record_repository(repo: &BackupRepository) {
     let base = match BaseDirectories::with_prefix("proxmox-backup") {
@@ -122,12 +131,7 @@ fn record_repository(repo: &BackupRepository) {
 
     let new_data = json!(map);
 
-    let _ = replace_file(
-        path,
-        new_data.to_string().as_bytes(),
-        CreateOptions::new(),
-        false,
-    );
+    let _ = replace_state_file(path, new_data.to_string().as_bytes(), false);
 }
 
 async fn api_datastore_list_snapshots(
@@ -169,6 +173,41 @@ pub async fn api_datastore_latest_snapshot(
     Ok((group, list[0].backup.time).into())
 }
 
+/// Fetch the default options for clients configured on the server for the datastore.
+///
+/// Without support on the server, or if they cannot be fetched, no defaults apply. Defaults
+/// the server returned but which cannot be parsed are an error, as silently ignoring them
+/// could e.g. create unencrypted backups where encryption is required.
+async fn api_datastore_client_defaults(
+    repo: &BackupRepository,
+    ns: &BackupNamespace,
+) -> Result<ClientDefaults, Error> {
+    let result = async {
+        let client = connect_shared(repo)?;
+        let path = format!("api2/json/admin/datastore/{}/client-defaults", repo.store());
+        let param = (!ns.is_root()).then(|| json!({ "ns": ns }));
+        client.get(&path, param).await
+    }
+    .await;
+
+    let mut result = match result {
+        Ok(result) => result,
+        Err(err) => {
+            match err.downcast_ref::<HttpError>() {
+                // older server without client defaults
+                Some(HttpError { code, .. }) if *code == hyper::StatusCode::NOT_FOUND => {
+                    log::debug!("server does not provide client defaults - {err}")
+                }
+                _ => log::warn!("unable to fetch client defaults from server - {err}"),
+            }
+            return Ok(ClientDefaults::default());
+        }
+    };
+
+    serde_json::from_value(result["data"].take())
+        .map_err(|err| format_err!("unable to parse client defaults of the server - {err}"))
+}
+
 pub async fn dir_or_last_from_group(
     client: &HttpClient,
     repo: &BackupRepository,
@@ -183,67 +222,6 @@ pub async fn dir_or_last_from_group(
     }
 }
 
-async fn backup_directory<P: AsRef<Path>>(
-    client: &BackupWriter,
-    dir_path: P,
-    archive_name: &str,
-    chunk_size: Option<usize>,
-    catalog: Arc<Mutex<CatalogWriter<TokioWriterAdapter<StdChannelWriter<Error>>>>>,
-    pxar_create_options: pbs_client::pxar::PxarCreateOptions,
-    upload_options: UploadOptions,
-) -> Result<BackupStats, Error> {
-    if upload_options.fixed_size.is_some() {
-        bail!("cannot backup directory with fixed chunk size!");
-    }
-
-    let pxar_stream = PxarBackupStream::open(dir_path.as_ref(), catalog, pxar_create_options)?;
-    let mut chunk_stream = ChunkStream::new(pxar_stream, chunk_size);
-
-    let (tx, rx) = mpsc::channel(10); // allow to buffer 10 chunks
-
-    let stream = ReceiverStream::new(rx).map_err(Error::from);
-
-    // spawn chunker inside a separate task so that it can run parallel
-    tokio::spawn(async move {
-        while let Some(v) = chunk_stream.next().await {
-            let _ = tx.send(v).await;
-        }
-    });
-
-    let stats = client
-        .upload_stream(archive_name, stream, upload_options)
-        .await?;
-
-    Ok(stats)
-}
-
-async fn backup_image<P: AsRef<Path>>(
-    client: &BackupWriter,
-    image_path: P,
-    archive_name: &str,
-    chunk_size: Option<usize>,
-    upload_options: UploadOptions,
-) -> Result<BackupStats, Error> {
-    let path = image_path.as_ref().to_owned();
-
-    let file = tokio::fs::File::open(path).await?;
-
-    let stream = tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new())
-        .map_err(Error::from);
-
-    let stream = FixedChunkStream::new(stream, chunk_size.unwrap_or(4 * 1024 * 1024));
-
-    if upload_options.fixed_size.is_none() {
-        bail!("cannot backup image with dynamic chunk size!");
-    }
-
-    let stats = client
-        .upload_stream(archive_name, stream, upload_options)
-        .await?;
-
-    Ok(stats)
-}
-
 pub fn optional_ns_param(param: &Value) -> Result<BackupNamespace, Error> {
     Ok(match param.get("ns") {
         Some(Value::String(ns)) => ns.parse()?,
@@ -276,7 +254,7 @@ async fn list_backup_groups(param: Value) -> Result<Value, Error> {
 
     let repo = extract_repository_from_value(&param)?;
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let path = format!("api2/json/admin/datastore/{}/groups", repo.store());
 
@@ -327,6 +305,7 @@ async fn list_backup_groups(param: Value) -> Result<Value, Error> {
                 .right_align(false),
         )
         .column(ColumnConfig::new("backup-count"))
+        .column(ColumnConfig::new("health"))
         .column(ColumnConfig::new("files").renderer(render_files));
 
     let mut data: Value = result["data"].take();
@@ -371,7 +350,7 @@ async fn change_backup_owner(group: String, mut param: Value) -> Result<(), Erro
     let repo = extract_repository_from_value(&param)?;
     let ns = optional_ns_param(&param)?;
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     param.as_object_mut().unwrap().remove("repository");
 
@@ -404,7 +383,7 @@ async fn change_backup_owner(group: String, mut param: Value) -> Result<(), Erro
 async fn api_login(param: Value) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
     client.login().await?;
 
     record_repository(&repo);
@@ -459,7 +438,7 @@ async fn api_version(param: Value) -> Result<(), Error> {
 
     let repo = extract_repository_from_value(&param);
     if let Ok(repo) = repo {
-        let client = connect(&repo)?;
+        let client = connect_shared(&repo)?;
 
         match client.get("api2/json/version", None).await {
             Ok(mut result) => version_info["server"] = result["data"].take(),
@@ -504,7 +483,7 @@ async fn start_garbage_collection(param: Value) -> Result<Value, Error> {
 
     let output_format = get_output_format(&param);
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let path = format!("api2/json/admin/datastore/{}/gc", repo.store());
 
@@ -517,51 +496,109 @@ async fn start_garbage_collection(param: Value) -> Result<Value, Error> {
     Ok(Value::Null)
 }
 
-struct CatalogUploadResult {
-    catalog_writer: Arc<Mutex<CatalogWriter<TokioWriterAdapter<StdChannelWriter<Error>>>>>,
-    result: tokio::sync::oneshot::Receiver<Result<BackupStats, Error>>,
-}
+/// Build the backup session options for the given crypto parameters, decrypting the encryption
+/// key if needed.
+fn backup_session_options(
+    crypto: CryptoParams,
+    chunk_size: Option<usize>,
+) -> Result<BackupSessionOptions, Error> {
+    let (crypt_config, rsa_encrypted_key) = match crypto.enc_key {
+        None => (None, None),
+        Some(key_with_source) => {
+            log::info!(
+                "{}",
+                format_key_source(&key_with_source.source, "encryption")
+            );
 
-fn spawn_catalog_upload(
-    client: Arc<BackupWriter>,
-    encrypt: bool,
-) -> Result<CatalogUploadResult, Error> {
-    let (catalog_tx, catalog_rx) = std::sync::mpsc::sync_channel(10); // allow to buffer 10 writes
-    let catalog_stream = proxmox_async::blocking::StdChannelStream(catalog_rx);
-    let catalog_chunk_size = 512 * 1024;
-    let catalog_chunk_stream = ChunkStream::new(catalog_stream, Some(catalog_chunk_size));
-
-    let catalog_writer = Arc::new(Mutex::new(CatalogWriter::new(TokioWriterAdapter::new(
-        StdChannelWriter::new(catalog_tx),
-    ))?));
-
-    let (catalog_result_tx, catalog_result_rx) = tokio::sync::oneshot::channel();
-
-    let upload_options = UploadOptions {
-        encrypt,
-        compress: true,
-        ..UploadOptions::default()
-    };
+            let (key, created, fingerprint) =
+                decrypt_key(&key_with_source.key, &get_encryption_key_password)?;
+            log::info!("Encryption key fingerprint: {}", fingerprint);
 
-    tokio::spawn(async move {
-        let catalog_upload_result = client
-            .upload_stream(CATALOG_NAME, catalog_chunk_stream, upload_options)
-            .await;
+            let acceleration = CryptoAcceleration::detect();
+            if !acceleration.aes_gcm() {
+                log::warn!(
+                    "no hardware accelerated AES-GCM available ({acceleration}), encryption will \
+                    be slow"
+                );
+            }
 
-        if let Err(ref err) = catalog_upload_result {
-            log::error!("catalog upload error - {}", err);
-            client.cancel();
-        }
+            let crypt_config = CryptConfig::new(key)?;
 
-        let _ = catalog_result_tx.send(catalog_upload_result);
-    });
+            match crypto.master_pubkey {
+                Some(pem_with_source) => {
+                    log::info!("{}", format_key_source(&pem_with_source.source, "master"));
 
-    Ok(CatalogUploadResult {
-        catalog_writer,
-        result: catalog_result_rx,
+                    let rsa = openssl::rsa::Rsa::public_key_from_pem(&pem_with_source.key)?;
+
+                    let mut key_config = KeyConfig::without_password(key)?;
+                    key_config.created = created; // keep original value
+
+                    let enc_key = rsa_encrypt_key_config(rsa, &key_config)?;
+
+                    (Some(Arc::new(crypt_config)), Some(enc_key))
+                }
+                _ => (Some(Arc::new(crypt_config)), None),
+            }
+        }
+    };
+
+    Ok(BackupSessionOptions {
+        crypt_config,
+        crypt_mode: crypto.mode,
+        rsa_encrypted_key,
+        chunk_size,
+        debug: true,
+        previous_ref: None,
+        skip_zero_chunks: false,
+        prehash_cache: false,
     })
 }
 
+// parses the '<previous-name>:<archive-name>' pairs of the 'rename-archive' option, by new name
+fn parse_archive_renames(
+    param: &Value,
+    targets: &HashSet<String>,
+) -> Result<HashMap<String, String>, Error> {
+    let mut renames = HashMap::new();
+    let empty = Vec::new();
+    for rename in param["rename-archive"].as_array().unwrap_or(&empty) {
+        let rename = rename
+            .as_str()
+            .ok_or_else(|| format_err!("Invalid rename string slice"))?;
+        let (previous, name) = rename.split_once(':').ok_or_else(|| {
+            format_err!("invalid rename '{rename}', expected <previous-name>:<archive-name>")
+        })?;
+        if !targets.contains(name) {
+            bail!("renamed archive '{name}' is not part of the backup");
+        }
+        let same_type = [".pxar", ".img"]
+            .iter()
+            .any(|ext| previous.ends_with(ext) && name.ends_with(ext));
+        if !same_type {
+            bail!("cannot rename archive '{previous}' to '{name}' of a different type");
+        }
+        renames.insert(name.to_string(), previous.to_string());
+    }
+    Ok(renames)
+}
+
+/// Exit code of a backup which completed with warnings, if requested via `--on-warning`.
+const EXIT_CODE_WARNINGS: i32 = 2;
+
+#[api]
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+/// How to handle warnings like unreadable or vanished files
+pub enum WarningPolicy {
+    /// Finish the backup and exit successfully.
+    #[default]
+    Ignore,
+    /// Finish the backup, but exit with code 2.
+    ExitCode,
+    /// Abort the backup, so no snapshot is created.
+    Fail,
+}
+
 #[api(
    input: {
        properties: {
@@ -576,6 +613,12 @@ fn spawn_catalog_upload(
                schema: REPO_URL_SCHEMA,
                optional: true,
            },
+           "secondary-repository": {
+               type: String,
+               description: "Upload the backup to this repository as well, reading the sources \
+                   only once. A failure of the secondary repository does not abort the backup.",
+               optional: true,
+           },
            "include-dev": {
                description: "Include mountpoints with same st_dev number (see ``man fstat``) as specified files.",
                optional: true,
@@ -598,6 +641,10 @@ fn spawn_catalog_upload(
                schema: KEYFD_SCHEMA,
                optional: true,
            },
+           "secure-perms-check": {
+               schema: SECURE_PERMS_CHECK_SCHEMA,
+               optional: true,
+           },
            "master-pubkey-file": {
                schema: MASTER_PUBKEY_FILE_SCHEMA,
                optional: true,
@@ -610,12 +657,25 @@ fn spawn_catalog_upload(
                type: CryptMode,
                optional: true,
            },
+           "server-key": {
+               type: Boolean,
+               description: "Use the passphrase protected encryption key stored on the server for \
+                   the backup group instead of a local key file.",
+               optional: true,
+               default: false,
+           },
            "skip-lost-and-found": {
                type: Boolean,
                description: "Skip lost+found directory.",
                optional: true,
                default: false,
            },
+           "previous-ref": {
+               type: String,
+               description: "Snapshot of another group in the same namespace (<type>/<id>/<time>) \
+                   to use as base for deduplication, e.g. the template a clone was created from.",
+               optional: true,
+           },
            "ns": {
                schema: BACKUP_NAMESPACE_SCHEMA,
                optional: true,
@@ -654,23 +714,85 @@ fn spawn_catalog_upload(
                 }
            },
            "entries-max": {
-               type: Integer,
-               description: "Max number of entries to hold in memory.",
+               schema: PXAR_ENTRIES_MAX_SCHEMA,
                optional: true,
-               default: pbs_client::pxar::ENCODER_MAX_ENTRIES as isize,
            },
            "dry-run": {
                type: Boolean,
-               description: "Just show what backup would do, but do not upload anything.",
+               description: "Walk the sources and print a summary of the changes compared to the \
+                   previous snapshot, but do not upload anything.",
                optional: true,
                default: false,
            },
+           "change-journal": {
+               type: String,
+               description: "Skip the directories without changes since the previous snapshot \
+                   in a dry run, according to this change journal. See \
+                   'proxmox-backup-client change-journal watch'.",
+               optional: true,
+           },
+           "spool-dir": {
+               type: String,
+               description: "Spool the backup to this local directory if the server is not reachable. Use 'proxmox-backup-client spool upload' to upload it later on.",
+               optional: true,
+           },
            "skip-e2big-xattr": {
                type: Boolean,
                description: "Ignore the E2BIG error when retrieving xattrs. This includes the file, but discards the metadata.",
                optional: true,
                default: false,
            },
+           "skip-zero-chunks": {
+               type: Boolean,
+               description: "Do not hash and upload each chunk of an image which contains only \
+                   zeroes, and skip downloading them on restore.",
+               optional: true,
+               default: false,
+           },
+           fifos: {
+               type: SpecialFilePolicy,
+               optional: true,
+           },
+           sockets: {
+               type: SpecialFilePolicy,
+               optional: true,
+           },
+           "prehash-cache": {
+               type: Boolean,
+               description: "Keep a local cache of the chunk digests by BLAKE3 hash, so that \
+                   unchanged chunks do not need to be hashed with SHA-256 again. Reduces the CPU \
+                   load on systems without hardware support for SHA-256.",
+               optional: true,
+               default: false,
+           },
+           "stats-interval": {
+               type: Integer,
+               description: "Interval in seconds for printing the deduplication statistics of \
+                   the running backup, 0 disables them.",
+               optional: true,
+               minimum: 0,
+               default: 60,
+           },
+           "on-warning": {
+               type: WarningPolicy,
+               optional: true,
+           },
+           "rename-archive": {
+               type: Array,
+               description: "Archives which were renamed since the previous snapshot, so that \
+                   their data is reused and the previous name is recorded.",
+               optional: true,
+               items: {
+                   type: String,
+                   description: "Previous and new archive name (<previous-name>:<archive-name>).",
+               },
+           },
+           profile: {
+               type: String,
+               description: "Write the time spent in the phases of archiving each directory \
+                   archive, per top-level directory, as JSON to this file.",
+               optional: true,
+           },
        }
    }
 )]
@@ -681,16 +803,42 @@ async fn create_backup(
     skip_lost_and_found: bool,
     dry_run: bool,
     skip_e2big_xattr: bool,
+    skip_zero_chunks: bool,
+    prehash_cache: bool,
+    fifos: Option<SpecialFilePolicy>,
+    sockets: Option<SpecialFilePolicy>,
+    on_warning: Option<WarningPolicy>,
     _info: &ApiMethod,
     _rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
 
+    let secondary_repo = match param["secondary-repository"].as_str() {
+        Some(url) => Some(url.parse::<BackupRepository>()?),
+        None => None,
+    };
+
     let backupspec_list = json::required_array_param(&param, "backupspec")?;
 
+    let profile_path = param["profile"].as_str();
+
+    let change_journal = param["change-journal"].as_str();
+    if change_journal.is_some() && !dry_run {
+        bail!("option 'change-journal' requires 'dry-run'");
+    }
+
+    let fifos = fifos.unwrap_or_default();
+    let sockets = sockets.unwrap_or_default();
+
     let backup_time_opt = param["backup-time"].as_i64();
 
-    let chunk_size_opt = param["chunk-size"].as_u64().map(|v| (v * 1024) as usize);
+    // options given locally take precedence over the defaults configured on the server
+    let defaults = api_datastore_client_defaults(&repo, &optional_ns_param(&param)?).await?;
+
+    let chunk_size_opt = param["chunk-size"]
+        .as_u64()
+        .or(defaults.chunk_size)
+        .map(|v| (v * 1024) as usize);
 
     if let Some(size) = chunk_size_opt {
         verify_chunk_size(size)?;
@@ -698,16 +846,23 @@ async fn create_backup(
 
     let rate = match param["rate"].as_str() {
         Some(s) => Some(s.parse::<HumanByte>()?),
-        None => None,
+        None => defaults.rate,
     };
     let burst = match param["burst"].as_str() {
         Some(s) => Some(s.parse::<HumanByte>()?),
-        None => None,
+        None => defaults.burst,
     };
 
     let rate_limit = RateLimitConfig::with_same_inout(rate, burst);
 
-    let crypto = crypto_parameters(&param)?;
+    let mut crypto = crypto_parameters(&param)?;
+
+    if defaults.require_encryption.unwrap_or(false)
+        && crypto.mode == CryptMode::None
+        && !param["server-key"].as_bool().unwrap_or(false)
+    {
+        bail!("the datastore requires encrypted backups, but no encryption key was given");
+    }
 
     let backup_id = param["backup-id"]
         .as_str()
@@ -721,8 +876,15 @@ async fn create_backup(
 
     let entries_max = param["entries-max"]
         .as_u64()
+        .or(defaults.entries_max)
         .unwrap_or(pbs_client::pxar::ENCODER_MAX_ENTRIES as u64);
 
+    if let Some(limit) = defaults.entries_max_limit {
+        if entries_max > limit {
+            bail!("entries-max {entries_max} exceeds the limit of {limit} set for the datastore");
+        }
+    }
+
     let empty = Vec::new();
     let exclude_args = param["exclude"].as_array().unwrap_or(&empty);
 
@@ -753,7 +915,7 @@ async fn create_backup(
             let path = path.as_str().unwrap();
             let stat = nix::sys::stat::stat(path)
                 .map_err(|err| format_err!("fstat {:?} failed - {}", path, err))?;
-            set.insert(stat.st_dev);
+            set.insert(pbs_tools::fs::device_number(&stat));
         }
         devices = Some(set);
     }
@@ -771,6 +933,26 @@ async fn create_backup(
         }
         target_set.insert(target.to_string());
 
+        if let BackupSpecificationType::STREAM = spec.spec_type {
+            if filename != "-" {
+                bail!("stream archive '{target}' can only be read from standard input ('-')");
+            }
+            if upload_list
+                .iter()
+                .any(|(spec_type, ..)| matches!(spec_type, BackupSpecificationType::STREAM))
+            {
+                bail!("only one stream archive can be read from standard input");
+            }
+            upload_list.push((
+                BackupSpecificationType::STREAM,
+                filename.to_owned(),
+                target.to_owned(),
+                "didx",
+                0,
+            ));
+            continue;
+        }
+
         use std::os::unix::fs::FileTypeExt;
 
         let metadata = std::fs::metadata(filename)
@@ -833,12 +1015,15 @@ async fn create_backup(
                     metadata.len(),
                 ));
             }
+            BackupSpecificationType::STREAM => unreachable!(),
         }
     }
 
+    let renamed_archives = parse_archive_renames(&param, &target_set)?;
+
     let backup_time = backup_time_opt.unwrap_or_else(epoch_i64);
 
-    let http_client = connect_rate_limited(&repo, rate_limit)?;
+    let http_client = connect_rate_limited(&repo, rate_limit.clone())?;
     record_repository(&repo);
 
     let snapshot = BackupDir::from((backup_type, backup_id.to_owned(), backup_time));
@@ -857,148 +1042,182 @@ async fn create_backup(
         strftime_local("%c", epoch_i64())?
     );
 
-    let (crypt_config, rsa_encrypted_key) = match crypto.enc_key {
-        None => (None, None),
-        Some(key_with_source) => {
-            log::info!(
-                "{}",
-                format_key_source(&key_with_source.source, "encryption")
-            );
+    if let Some(spool_dir) = param["spool-dir"].as_str() {
+        if param["server-key"].as_bool().unwrap_or(false) {
+            bail!("option 'spool-dir' conflicts with option 'server-key'");
+        }
+        if secondary_repo.is_some() {
+            bail!("option 'spool-dir' conflicts with option 'secondary-repository'");
+        }
+        if !dry_run {
+            if let Err(err) = http_client.get("api2/json/version", None).await {
+                log::warn!("server not reachable ({err}) - spooling backup to '{spool_dir}'");
 
-            let (key, created, fingerprint) =
-                decrypt_key(&key_with_source.key, &get_encryption_key_password)?;
-            log::info!("Encryption key fingerprint: {}", fingerprint);
+                let pxar_options = pbs_client::pxar::PxarCreateOptions {
+                    device_set: devices,
+                    patterns: pattern_list,
+                    entries_max: entries_max as usize,
+                    skip_lost_and_found,
+                    skip_e2big_xattr,
+                    warnings: None,
+                    skip_file_contents: false,
+                    profile: None,
+                    fifos,
+                    sockets,
+                    skip_unchanged_dirs: None,
+                };
 
-            let crypt_config = CryptConfig::new(key)?;
+                let crypt_mode = crypto.mode;
+                let session_options = backup_session_options(crypto, chunk_size_opt)?;
 
-            match crypto.master_pubkey {
-                Some(pem_with_source) => {
-                    log::info!("{}", format_key_source(&pem_with_source.source, "master"));
+                let spool = BackupSpool::open(spool_dir)?;
+                let writer = spool.begin(
+                    &repo.to_string(),
+                    &backup_ns,
+                    &snapshot,
+                    crypt_mode,
+                    session_options.crypt_config,
+                )?;
+                spool::spool_backup(writer, upload_list, chunk_size_opt, pxar_options).await?;
 
-                    let rsa = openssl::rsa::Rsa::public_key_from_pem(&pem_with_source.key)?;
+                return Ok(Value::Null);
+            }
+        }
+    }
 
-                    let mut key_config = KeyConfig::without_password(key)?;
-                    key_config.created = created; // keep original value
+    group_key::apply_server_key(
+        &param,
+        &http_client,
+        repo.store(),
+        &backup_ns,
+        &snapshot.group,
+        &mut crypto,
+    )
+    .await?;
 
-                    let enc_key = rsa_encrypt_key_config(rsa, &key_config)?;
+    let mut session_options = backup_session_options(crypto, chunk_size_opt)?;
+    session_options.previous_ref = match param["previous-ref"].as_str() {
+        Some(previous_ref) => Some(previous_ref.parse()?),
+        None => None,
+    };
+    session_options.skip_zero_chunks = skip_zero_chunks;
+    session_options.prehash_cache = prehash_cache;
 
-                    (Some(Arc::new(crypt_config)), Some(enc_key))
-                }
-                _ => (Some(Arc::new(crypt_config)), None),
-            }
+    if dry_run {
+        let pxar_options = pbs_client::pxar::PxarCreateOptions {
+            device_set: devices,
+            patterns: pattern_list,
+            entries_max: entries_max as usize,
+            skip_lost_and_found,
+            skip_e2big_xattr,
+            warnings: None,
+            skip_file_contents: true,
+            profile: None,
+            fifos,
+            sockets,
+            skip_unchanged_dirs: None,
+        };
+
+        let change_journal = match change_journal {
+            Some(name) => Some(Arc::new(pbs_client::ChangeJournal::load(name)?)),
+            None => None,
+        };
+
+        let mut profile = profile_path.map(|_| BackupProfile::default());
+        dry_run::dry_run_backup(
+            &http_client,
+            &repo,
+            &backup_ns,
+            &snapshot,
+            session_options.previous_ref,
+            session_options.crypt_config,
+            upload_list,
+            &renamed_archives,
+            pxar_options,
+            change_journal,
+            profile.as_mut(),
+        )
+        .await?;
+
+        if let (Some(path), Some(profile)) = (profile_path, profile) {
+            write_backup_profile(path, &profile)?;
         }
-    };
 
-    let client = BackupWriter::start(
+        return Ok(Value::Null);
+    }
+
+    let has_master_key = session_options.rsa_encrypted_key.is_some();
+
+    // the previous reference snapshot only exists in the primary repository
+    let mut secondary_options = session_options.clone();
+    secondary_options.previous_ref = None;
+
+    let mut session = BackupSession::start(
         &http_client,
-        crypt_config.clone(),
         repo.store(),
         &backup_ns,
         &snapshot,
-        true,
-        false,
+        session_options,
     )
     .await?;
 
-    let download_previous_manifest = match client.previous_backup_time().await {
-        Ok(Some(backup_time)) => {
-            log::info!(
-                "Downloading previous manifest ({})",
-                strftime_local("%c", backup_time)?
-            );
-            true
-        }
-        Ok(None) => {
-            log::info!("No previous manifest available.");
-            false
-        }
-        Err(_) => {
-            // Fallback for outdated server, TODO remove/bubble up with 2.0
-            true
-        }
+    let mut secondary_error = None;
+    let secondary_client = match &secondary_repo {
+        Some(secondary_repo) => Some(connect_rate_limited(secondary_repo, rate_limit)?),
+        None => None,
     };
-
-    let previous_manifest = if download_previous_manifest {
-        match client.download_previous_manifest().await {
-            Ok(previous_manifest) => {
-                match previous_manifest.check_fingerprint(crypt_config.as_ref().map(Arc::as_ref)) {
-                    Ok(()) => Some(Arc::new(previous_manifest)),
-                    Err(err) => {
-                        log::error!("Couldn't re-use previous manifest - {}", err);
-                        None
-                    }
-                }
-            }
+    if let (Some(secondary_repo), Some(secondary_client)) = (&secondary_repo, &secondary_client) {
+        log::info!("Starting backup on secondary repository '{secondary_repo}'");
+        let secondary = BackupSession::start(
+            secondary_client,
+            secondary_repo.store(),
+            &backup_ns,
+            &snapshot,
+            secondary_options,
+        )
+        .await;
+        match secondary {
+            Ok(secondary) => session.set_secondary(secondary)?,
             Err(err) => {
-                log::error!("Couldn't download previous manifest - {}", err);
-                None
+                log::warn!("unable to start backup on secondary repository - {err}");
+                secondary_error = Some(err);
             }
         }
-    } else {
-        None
-    };
+    }
 
-    let mut manifest = BackupManifest::new(snapshot);
+    for (name, previous_name) in &renamed_archives {
+        log::info!("Archive '{name}' was renamed from '{previous_name}'");
+        session.rename_archive(name, previous_name);
+    }
+
+    let stats_interval = param["stats-interval"].as_u64().unwrap_or(60);
+    let stats_logger = (stats_interval > 0).then(|| {
+        session
+            .writer()
+            .log_session_stats(std::time::Duration::from_secs(stats_interval))
+    });
 
-    let mut catalog = None;
-    let mut catalog_result_rx = None;
+    let warnings = Arc::new(AtomicUsize::new(0));
+    let mut profile = BackupProfile::default();
 
     let log_file = |desc: &str, file: &str, target: &str| {
-        let what = if dry_run { "Would upload" } else { "Upload" };
-        log::info!("{} {} '{}' to '{}' as {}", what, desc, file, repo, target);
+        log::info!("Upload {} '{}' to '{}' as {}", desc, file, repo, target);
     };
 
     for (backup_type, filename, target_base, extension, size) in upload_list {
         let target = format!("{target_base}.{extension}");
-        match (backup_type, dry_run) {
-            // dry-run
-            (BackupSpecificationType::CONFIG, true) => log_file("config file", &filename, &target),
-            (BackupSpecificationType::LOGFILE, true) => log_file("log file", &filename, &target),
-            (BackupSpecificationType::PXAR, true) => log_file("directory", &filename, &target),
-            (BackupSpecificationType::IMAGE, true) => log_file("image", &filename, &target),
-            // no dry-run
-            (BackupSpecificationType::CONFIG, false) => {
-                let upload_options = UploadOptions {
-                    compress: true,
-                    encrypt: crypto.mode == CryptMode::Encrypt,
-                    ..UploadOptions::default()
-                };
-
+        match backup_type {
+            BackupSpecificationType::CONFIG => {
                 log_file("config file", &filename, &target);
-                let stats = client
-                    .upload_blob_from_file(&filename, &target, upload_options)
-                    .await?;
-                manifest.add_file(target, stats.size, stats.csum, crypto.mode)?;
+                session.add_blob_from_file(&filename, &target_base).await?;
             }
-            (BackupSpecificationType::LOGFILE, false) => {
+            BackupSpecificationType::LOGFILE => {
                 // fixme: remove - not needed anymore ?
-                let upload_options = UploadOptions {
-                    compress: true,
-                    encrypt: crypto.mode == CryptMode::Encrypt,
-                    ..UploadOptions::default()
-                };
-
                 log_file("log file", &filename, &target);
-                let stats = client
-                    .upload_blob_from_file(&filename, &target, upload_options)
-                    .await?;
-                manifest.add_file(target, stats.size, stats.csum, crypto.mode)?;
+                session.add_blob_from_file(&filename, &target_base).await?;
             }
-            (BackupSpecificationType::PXAR, false) => {
-                // start catalog upload on first use
-                if catalog.is_none() {
-                    let catalog_upload_res =
-                        spawn_catalog_upload(client.clone(), crypto.mode == CryptMode::Encrypt)?;
-                    catalog = Some(catalog_upload_res.catalog_writer);
-                    catalog_result_rx = Some(catalog_upload_res.result);
-                }
-                let catalog = catalog.as_ref().unwrap();
-
+            BackupSpecificationType::PXAR => {
                 log_file("directory", &filename, &target);
-                catalog
-                    .lock()
-                    .unwrap()
-                    .start_directory(std::ffi::CString::new(target.as_str())?.as_c_str())?;
 
                 let pxar_options = pbs_client::pxar::PxarCreateOptions {
                     device_set: devices.clone(),
@@ -1006,156 +1225,95 @@ async fn create_backup(
                     entries_max: entries_max as usize,
                     skip_lost_and_found,
                     skip_e2big_xattr,
+                    warnings: Some(Arc::clone(&warnings)),
+                    skip_file_contents: false,
+                    profile: profile_path.map(|_| profile.add_archive(&target)),
+                    fifos,
+                    sockets,
+                    skip_unchanged_dirs: None,
                 };
 
-                let upload_options = UploadOptions {
-                    previous_manifest: previous_manifest.clone(),
-                    compress: true,
-                    encrypt: crypto.mode == CryptMode::Encrypt,
-                    ..UploadOptions::default()
-                };
-
-                let stats = backup_directory(
-                    &client,
-                    &filename,
-                    &target,
-                    chunk_size_opt,
-                    catalog.clone(),
-                    pxar_options,
-                    upload_options,
-                )
-                .await?;
-                manifest.add_file(target, stats.size, stats.csum, crypto.mode)?;
-                catalog.lock().unwrap().end_directory()?;
+                session
+                    .add_pxar(&filename, &target_base, pxar_options)
+                    .await?;
             }
-            (BackupSpecificationType::IMAGE, false) => {
+            BackupSpecificationType::IMAGE => {
                 log_file("image", &filename, &target);
-
-                let upload_options = UploadOptions {
-                    previous_manifest: previous_manifest.clone(),
-                    fixed_size: Some(size),
-                    compress: true,
-                    encrypt: crypto.mode == CryptMode::Encrypt,
-                };
-
-                let stats =
-                    backup_image(&client, &filename, &target, chunk_size_opt, upload_options)
-                        .await?;
-                manifest.add_file(target, stats.size, stats.csum, crypto.mode)?;
+                session.add_image(&filename, &target_base, size).await?;
+            }
+            BackupSpecificationType::STREAM => {
+                log_file("standard input", &filename, &target);
+                let stats = session.add_stream(tokio::io::stdin(), &target_base).await?;
+                log::info!(
+                    "{target}: read {} from standard input",
+                    HumanByte::from(stats.size)
+                );
             }
         }
     }
 
-    if dry_run {
-        log::info!("dry-run: no upload happened");
-        return Ok(Value::Null);
-    }
-
-    // finalize and upload catalog
-    if let Some(catalog) = catalog {
-        let mutex = Arc::try_unwrap(catalog)
-            .map_err(|_| format_err!("unable to get catalog (still used)"))?;
-        let mut catalog = mutex.into_inner().unwrap();
-
-        catalog.finish()?;
-
-        drop(catalog); // close upload stream
-
-        if let Some(catalog_result_rx) = catalog_result_rx {
-            let stats = catalog_result_rx.await??;
-            manifest.add_file(CATALOG_NAME.to_owned(), stats.size, stats.csum, crypto.mode)?;
+    let on_warning = on_warning.unwrap_or_default();
+    let warnings = warnings.load(Ordering::SeqCst);
+    if warnings > 0 {
+        log::warn!("backup completed with {warnings} warning(s)");
+        if on_warning == WarningPolicy::Fail {
+            bail!("aborting backup due to {warnings} warning(s)");
         }
     }
 
-    if let Some(rsa_encrypted_key) = rsa_encrypted_key {
-        let target = ENCRYPTED_KEY_BLOB_NAME;
-        log::info!("Upload RSA encoded key to '{}' as {}", repo, target);
-        let options = UploadOptions {
-            compress: false,
-            encrypt: false,
-            ..UploadOptions::default()
-        };
-        let stats = client
-            .upload_blob_from_data(rsa_encrypted_key, target, options)
-            .await?;
-        manifest.add_file(target.to_string(), stats.size, stats.csum, crypto.mode)?;
+    let backup_result = json!({
+        "status": if warnings > 0 { "partial" } else { "ok" },
+        "warnings": warnings,
+    });
+    if let Some(secondary) = session.secondary_mut() {
+        secondary.manifest_mut().unprotected["backup-result"] = backup_result.clone();
     }
-    // create manifest (index.json)
-    // manifests are never encrypted, but include a signature
-    let manifest = manifest
-        .to_string(crypt_config.as_ref().map(Arc::as_ref))
-        .map_err(|err| format_err!("unable to format manifest - {}", err))?;
-
-    log::debug!("Upload index.json to '{}'", repo);
-
-    let options = UploadOptions {
-        compress: true,
-        encrypt: false,
-        ..UploadOptions::default()
-    };
-    client
-        .upload_blob_from_data(manifest.into_bytes(), MANIFEST_BLOB_NAME, options)
-        .await?;
+    session.manifest_mut().unprotected["backup-result"] = backup_result;
 
-    client.finish().await?;
+    if let Some(stats_logger) = stats_logger {
+        stats_logger.abort();
+    }
+    if has_master_key {
+        log::info!(
+            "Upload RSA encoded key to '{}' as {}",
+            repo,
+            ENCRYPTED_KEY_BLOB_NAME
+        );
+    }
+    let (_manifest, error) = session.finish_with_secondary().await?;
+    secondary_error = secondary_error.or(error);
 
     let end_time = std::time::Instant::now();
     let elapsed = end_time.duration_since(start_time);
     log::info!("Duration: {:.2}s", elapsed.as_secs_f64());
     log::info!("End Time: {}", strftime_local("%c", epoch_i64())?);
-    Ok(Value::Null)
-}
 
-async fn dump_image<W: Write>(
-    client: Arc<BackupReader>,
-    crypt_config: Option<Arc<CryptConfig>>,
-    crypt_mode: CryptMode,
-    index: FixedIndexReader,
-    mut writer: W,
-) -> Result<(), Error> {
-    let most_used = index.find_most_used_chunks(8);
-
-    let chunk_reader = RemoteChunkReader::new(client.clone(), crypt_config, crypt_mode, most_used);
-
-    // Note: we avoid using BufferedFixedReader, because that add an additional buffer/copy
-    // and thus slows down reading. Instead, directly use RemoteChunkReader
-    let mut per = 0;
-    let mut bytes = 0;
-    let start_time = std::time::Instant::now();
+    if let Some(path) = profile_path {
+        write_backup_profile(path, &profile)?;
+    }
 
-    for pos in 0..index.index_count() {
-        let digest = index.index_digest(pos).unwrap();
-        let raw_data = chunk_reader.read_chunk(digest).await?;
-        writer.write_all(&raw_data)?;
-        bytes += raw_data.len();
-        let next_per = ((pos + 1) * 100) / index.index_count();
-        if per != next_per {
-            log::debug!(
-                "progress {}% (read {} bytes, duration {} sec)",
-                next_per,
-                bytes,
-                start_time.elapsed().as_secs()
-            );
-            per = next_per;
-        }
+    if let Some(err) = secondary_error {
+        log::error!("backup to secondary repository failed - {err}");
+        std::process::exit(EXIT_CODE_WARNINGS);
     }
 
-    let end_time = std::time::Instant::now();
-    let elapsed = end_time.duration_since(start_time);
-    log::info!(
-        "restore image complete (bytes={}, duration={:.2}s, speed={:.2}MB/s)",
-        bytes,
-        elapsed.as_secs_f64(),
-        bytes as f64 / (1024.0 * 1024.0 * elapsed.as_secs_f64())
-    );
+    if warnings > 0 && on_warning == WarningPolicy::ExitCode {
+        std::process::exit(EXIT_CODE_WARNINGS);
+    }
 
-    Ok(())
+    Ok(Value::Null)
+}
+
+fn write_backup_profile(path: &str, profile: &BackupProfile) -> Result<(), Error> {
+    let data = serde_json::to_string_pretty(&profile.to_value())?;
+    std::fs::write(path, data)
+        .map_err(|err| format_err!("unable to write profile {path:?} - {err}"))
 }
 
 fn parse_archive_type(name: &str) -> (String, ArchiveType) {
     if name.ends_with(".didx") || name.ends_with(".fidx") || name.ends_with(".blob") {
         (name.into(), archive_type(name).unwrap())
-    } else if name.ends_with(".pxar") {
+    } else if name.ends_with(".pxar") || name.ends_with(".stream") {
         (format!("{}.didx", name), ArchiveType::DynamicIndex)
     } else if name.ends_with(".img") {
         (format!("{}.fidx", name), ArchiveType::FixedIndex)
@@ -1164,6 +1322,44 @@ fn parse_archive_type(name: &str) -> (String, ArchiveType) {
     }
 }
 
+// the byte ranges of an image given to restore, in the given order (they may overlap)
+fn parse_image_ranges(param: &Value) -> Result<Vec<std::ops::Range<u64>>, Error> {
+    let mut ranges = Vec::new();
+    for range in param["range"].as_array().into_iter().flatten() {
+        let range = range
+            .as_str()
+            .ok_or_else(|| format_err!("invalid range string"))?;
+        let (offset, length) = range
+            .split_once(':')
+            .ok_or_else(|| format_err!("range '{range}' is not in the form '<offset>:<length>'"))?;
+        let offset = offset.trim().parse::<HumanByte>()?.as_u64();
+        let length = length.trim().parse::<HumanByte>()?.as_u64();
+        if length == 0 {
+            bail!("range '{range}' is empty");
+        }
+        let end = offset
+            .checked_add(length)
+            .ok_or_else(|| format_err!("range '{range}' exceeds the maximum offset"))?;
+        ranges.push(offset..end);
+    }
+    Ok(ranges)
+}
+
+// open the target of a range restore, which may be an existing block device
+fn open_image_range_target(target: &str) -> Result<std::fs::File, Error> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let is_block_device = std::fs::metadata(target)
+        .map(|metadata| metadata.file_type().is_block_device())
+        .unwrap_or(false);
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(!is_block_device)
+        .open(target)
+        .map_err(|err| format_err!("unable to open target {target:?} - {err}"))
+}
+
 #[api(
     input: {
         properties: {
@@ -1213,10 +1409,21 @@ We do not extract '.pxar' archives when writing to standard output.
                 schema: KEYFD_SCHEMA,
                 optional: true,
             },
+            "secure-perms-check": {
+                schema: SECURE_PERMS_CHECK_SCHEMA,
+                optional: true,
+            },
             "crypt-mode": {
                 type: CryptMode,
                 optional: true,
             },
+            "server-key": {
+                type: Boolean,
+                description: "Use the passphrase protected encryption key stored on the server for \
+                    the backup group instead of a local key file.",
+                optional: true,
+                default: false,
+            },
             "ignore-acls": {
                 type: Boolean,
                 description: "ignore acl settings",
@@ -1267,7 +1474,41 @@ We do not extract '.pxar' archives when writing to standard output.
                 description: "ignore errors that occur during device node extraction",
                 optional: true,
                 default: false,
-            }
+            },
+            parallel: {
+                type: Integer,
+                description: "Number of chunks fetched in parallel when restoring an image. \
+                    When writing to stdout, this is the number of chunks read ahead.",
+                minimum: 1,
+                maximum: 64,
+                optional: true,
+                default: 8,
+            },
+            resume: {
+                type: Boolean,
+                description: "Resume an interrupted restore of an image into a file.",
+                optional: true,
+                default: false,
+            },
+            fifos: {
+                type: SpecialFilePolicy,
+                optional: true,
+            },
+            sockets: {
+                type: SpecialFilePolicy,
+                optional: true,
+            },
+            range: {
+                type: Array,
+                description: "Only restore these byte ranges of an image, one after the other. \
+                    The target may be an existing block device.",
+                optional: true,
+                items: {
+                    type: String,
+                    description: "Byte range as '<offset>:<length>', both with optional unit, \
+                        e.g. '1MiB:512MiB'.",
+                },
+            },
         }
     }
 )]
@@ -1284,6 +1525,10 @@ async fn restore(
     overwrite_symlinks: bool,
     overwrite_hardlinks: bool,
     ignore_extract_device_errors: bool,
+    parallel: usize,
+    resume: bool,
+    fifos: Option<SpecialFilePolicy>,
+    sockets: Option<SpecialFilePolicy>,
 ) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
 
@@ -1311,7 +1556,16 @@ async fn restore(
     let target = json::required_string_param(&param, "target")?;
     let target = if target == "-" { None } else { Some(target) };
 
-    let crypto = crypto_parameters(&param)?;
+    let mut crypto = crypto_parameters(&param)?;
+    group_key::apply_server_key(
+        &param,
+        &client,
+        repo.store(),
+        &ns,
+        &backup_dir.group,
+        &mut crypto,
+    )
+    .await?;
 
     let crypt_config = match crypto.enc_key {
         None => None,
@@ -1325,19 +1579,29 @@ async fn restore(
         }
     };
 
-    let client = BackupReader::start(
+    let session = RestoreSession::start(
         &client,
-        crypt_config.clone(),
         repo.store(),
         &ns,
         &backup_dir,
+        crypt_config.clone(),
         true,
     )
     .await?;
 
     let (archive_name, archive_type) = parse_archive_type(archive_name);
 
-    let (manifest, backup_index_data) = client.download_manifest().await?;
+    let ranges = parse_image_ranges(&param)?;
+    if !ranges.is_empty() {
+        if archive_type != ArchiveType::FixedIndex {
+            bail!("byte ranges can only be restored from image archives");
+        }
+        if resume {
+            bail!("restoring byte ranges cannot be resumed");
+        }
+    }
+
+    let manifest = session.manifest();
 
     if archive_name == ENCRYPTED_KEY_BLOB_NAME && crypt_config.is_none() {
         log::info!("Restoring encrypted key blob without original key - skipping manifest fingerprint check!")
@@ -1350,27 +1614,27 @@ async fn restore(
                 log::info!("Fingerprint: {}", Fingerprint::new(config.fingerprint()));
             }
         }
-        manifest.check_fingerprint(crypt_config.as_ref().map(Arc::as_ref))?;
+        session.check_fingerprint()?;
     }
 
     if archive_name == MANIFEST_BLOB_NAME {
         if let Some(target) = target {
-            replace_file(target, &backup_index_data, CreateOptions::new(), false)?;
+            replace_file(target, session.raw_manifest(), CreateOptions::new(), false)?;
         } else {
             let stdout = std::io::stdout();
             let mut writer = stdout.lock();
             writer
-                .write_all(&backup_index_data)
+                .write_all(session.raw_manifest())
                 .map_err(|err| format_err!("unable to pipe data - {}", err))?;
         }
 
         return Ok(Value::Null);
     }
 
-    let file_info = manifest.lookup_file_info(&archive_name)?;
+    manifest.lookup_file_info(&archive_name)?;
 
     if archive_type == ArchiveType::Blob {
-        let mut reader = client.download_blob(&manifest, &archive_name).await?;
+        let mut reader = session.blob_reader(&archive_name).await?;
 
         if let Some(target) = target {
             let mut writer = std::fs::OpenOptions::new()
@@ -1389,21 +1653,6 @@ async fn restore(
                 .map_err(|err| format_err!("unable to pipe data - {}", err))?;
         }
     } else if archive_type == ArchiveType::DynamicIndex {
-        let index = client
-            .download_dynamic_index(&manifest, &archive_name)
-            .await?;
-
-        let most_used = index.find_most_used_chunks(8);
-
-        let chunk_reader = RemoteChunkReader::new(
-            client.clone(),
-            crypt_config,
-            file_info.chunk_crypt_mode(),
-            most_used,
-        );
-
-        let mut reader = BufferedDynamicReader::new(index, chunk_reader);
-
         let on_error = if ignore_extract_device_errors {
             let handler: PxarErrorHandler = Box::new(move |err: Error| {
                 use pbs_client::pxar::PxarExtractContext;
@@ -1439,6 +1688,8 @@ async fn restore(
             allow_existing_dirs,
             overwrite_flags,
             on_error,
+            fifos: fifos.unwrap_or_default(),
+            sockets: sockets.unwrap_or_default(),
         };
 
         let mut feature_flags = pbs_client::pxar::Flags::DEFAULT;
@@ -1456,18 +1707,23 @@ async fn restore(
             feature_flags.remove(pbs_client::pxar::Flags::WITH_PERMISSIONS);
         }
 
-        if let Some(target) = target {
-            pbs_client::pxar::extract_archive(
-                pxar::decoder::Decoder::from_std(reader)?,
-                Path::new(target),
-                feature_flags,
-                |path| {
-                    log::debug!("{:?}", path);
-                },
-                options,
-            )
-            .map_err(|err| format_err!("error extracting archive - {:#}", err))?;
+        if let (Some(target), true) = (target, archive_name.ends_with(".stream.didx")) {
+            // raw data read from standard input on backup
+            let mut reader = session.dynamic_reader(&archive_name).await?;
+            let mut writer = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(target)
+                .map_err(|err| {
+                    format_err!("unable to create target file {:?} - {}", target, err)
+                })?;
+            std::io::copy(&mut reader, &mut writer)?;
+        } else if let Some(target) = target {
+            session
+                .restore_pxar(&archive_name, Path::new(target), feature_flags, options)
+                .await?;
         } else {
+            let mut reader = session.dynamic_reader(&archive_name).await?;
             let mut writer = std::fs::OpenOptions::new()
                 .write(true)
                 .open("/dev/stdout")
@@ -1476,33 +1732,62 @@ async fn restore(
             std::io::copy(&mut reader, &mut writer)
                 .map_err(|err| format_err!("unable to pipe data - {}", err))?;
         }
+    } else if archive_type == ArchiveType::FixedIndex && !ranges.is_empty() {
+        let start_time = std::time::Instant::now();
+        let bytes = match target {
+            Some(target) => {
+                let file = open_image_range_target(target)?;
+                session
+                    .restore_image_ranges(&archive_name, &ranges, file, parallel)
+                    .await?
+            }
+            None => {
+                let writer = std::fs::OpenOptions::new()
+                    .write(true)
+                    .open("/dev/stdout")
+                    .map_err(|err| format_err!("unable to open /dev/stdout - {}", err))?;
+                session
+                    .restore_image_ranges(&archive_name, &ranges, writer, parallel)
+                    .await?
+            }
+        };
+        let elapsed = start_time.elapsed().as_secs_f64();
+        log::info!(
+            "restore of {} ranges complete (bytes={}, duration={:.2}s)",
+            ranges.len(),
+            bytes,
+            elapsed
+        );
     } else if archive_type == ArchiveType::FixedIndex {
-        let index = client
-            .download_fixed_index(&manifest, &archive_name)
-            .await?;
-
-        let mut writer = if let Some(target) = target {
-            std::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .create_new(true)
-                .open(target)
-                .map_err(|err| format_err!("unable to create target file {:?} - {}", target, err))?
+        if let Some(target) = target {
+            let start_time = std::time::Instant::now();
+            let bytes = session
+                .reader()
+                .download_archive_to_path(
+                    manifest,
+                    &archive_name,
+                    Path::new(target),
+                    parallel,
+                    resume,
+                )
+                .await?;
+            let elapsed = start_time.elapsed().as_secs_f64();
+            log::info!(
+                "restore image complete (bytes={}, duration={:.2}s, speed={:.2}MB/s)",
+                bytes,
+                elapsed,
+                bytes as f64 / (1024.0 * 1024.0 * elapsed)
+            );
         } else {
-            std::fs::OpenOptions::new()
+            let mut writer = std::fs::OpenOptions::new()
                 .write(true)
                 .open("/dev/stdout")
-                .map_err(|err| format_err!("unable to open /dev/stdout - {}", err))?
-        };
+                .map_err(|err| format_err!("unable to open /dev/stdout - {}", err))?;
 
-        dump_image(
-            client.clone(),
-            crypt_config.clone(),
-            file_info.chunk_crypt_mode(),
-            index,
-            &mut writer,
-        )
-        .await?;
+            session
+                .restore_image(&archive_name, &mut writer, parallel)
+                .await?;
+        }
     }
 
     Ok(Value::Null)
@@ -1551,7 +1836,7 @@ async fn prune(
 ) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let path = format!("api2/json/admin/datastore/{}/prune", repo.store());
 
@@ -1601,6 +1886,11 @@ async fn prune(
             ColumnConfig::new("keep")
                 .renderer(render_prune_action)
                 .header("action"),
+        )
+        .column(
+            ColumnConfig::new("retention-tags")
+                .renderer(pbs_tools::format::render_string_list)
+                .header("retained by"),
         );
 
     let return_type = &pbs_api_types::ADMIN_DATASTORE_PRUNE_RETURN_TYPE;
@@ -1646,7 +1936,7 @@ async fn status(param: Value) -> Result<Value, Error> {
 
     let output_format = get_output_format(&param);
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let path = format!("api2/json/admin/datastore/{}/status", repo.store());
 
@@ -1730,6 +2020,7 @@ fn main() {
         .completion_cb("backupspec", complete_backup_source)
         .completion_cb("keyfile", complete_file_name)
         .completion_cb("master-pubkey-file", complete_file_name)
+        .completion_cb("spool-dir", complete_file_name)
         .completion_cb("chunk-size", complete_chunk_size);
 
     let benchmark_cmd_def = CliCommand::new(&API_METHOD_BENCHMARK)
@@ -1787,24 +2078,77 @@ fn main() {
         .insert("snapshot", snapshot_mgtm_cli())
         .insert("status", status_cmd_def)
         .insert("key", key::cli())
-        .insert("mount", mount_cmd_def())
-        .insert("map", map_cmd_def())
-        .insert("unmap", unmap_cmd_def())
+        .insert("group-key", group_key::cli())
+        .insert("fingerprint", fingerprint::cli())
+        .insert("spool", spool::cli())
         .insert("catalog", catalog_mgmt_cli())
+        .insert("change-journal", change_journal::cli())
+        .insert("merkle", merkle_mgmt_cli())
         .insert("task", task_mgmt_cli())
         .insert("version", version_cmd_def)
         .insert("benchmark", benchmark_cmd_def)
         .insert("change-owner", change_owner_cmd_def)
         .insert("namespace", namespace::cli_map())
+        .insert("verify-local", verify_local_cmd_def())
+        .insert("debug", debug::cli())
         .alias(&["files"], &["snapshot", "files"])
         .alias(&["forget"], &["snapshot", "forget"])
         .alias(&["upload-log"], &["snapshot", "upload-log"])
         .alias(&["snapshots"], &["snapshot", "list"]);
 
+    #[cfg(feature = "fuse")]
+    let cmd_def = cmd_def
+        .insert("mount", mount_cmd_def())
+        .insert("map", map_cmd_def())
+        .insert("unmap", unmap_cmd_def());
+
     let rpcenv = CliEnvironment::new();
     run_cli_command(
         cmd_def,
         rpcenv,
-        Some(|future| proxmox_async::runtime::main(future)),
+        Some(|future| {
+            proxmox_async::runtime::main(async move {
+                future.await.map_err(|err| {
+                    format_err!("{err}\ncorrelation ID: {}", pbs_client::correlation_id())
+                })
+            })
+        }),
     );
 }
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::parse_image_ranges;
+
+    #[test]
+    fn test_parse_image_ranges() {
+        let ranges = |list: &[&str]| parse_image_ranges(&json!({ "range": list }));
+
+        assert!(ranges(&[]).unwrap().is_empty());
+        assert!(parse_image_ranges(&json!({})).unwrap().is_empty());
+        assert_eq!(
+            ranges(&["1MiB:512MiB", "0:512"]).unwrap(),
+            vec![1 << 20..(1 << 20) + (512 << 20), 0..512],
+        );
+
+        // kept in the given order, the ranges are written one after the other
+        assert_eq!(
+            ranges(&["4096:4096", "0:8192", "2048:1024"]).unwrap(),
+            vec![4096..8192, 0..8192, 2048..3072],
+        );
+
+        let max = u64::MAX.to_string();
+        assert_eq!(ranges(&[&format!("0:{max}")]).unwrap(), vec![0..u64::MAX]);
+        assert!(ranges(&[&format!("1:{max}")]).is_err());
+        assert!(ranges(&[&format!("{max}:1")]).is_err());
+        assert!(ranges(&[&format!("{}:{}", 1u64 << 63, 1u64 << 63)]).is_err());
+
+        assert!(ranges(&["0:0"]).is_err());
+        assert!(ranges(&["1MiB:0B"]).is_err());
+        assert!(ranges(&["512"]).is_err());
+        assert!(ranges(&["a:1"]).is_err());
+        assert!(parse_image_ranges(&json!({ "range": [1] })).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use hex::FromHex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use pxar::accessor::aio::Accessor;
+use pxar::accessor::ReadAt;
+
+use proxmox_router::cli::*;
+use proxmox_schema::api;
+use proxmox_sys::fs::{file_get_contents, replace_file, CreateOptions};
+
+use pbs_api_types::BackupNamespace;
+use pbs_client::tools::key_source::get_encryption_key_password;
+use pbs_client::{BackupReader, RemoteChunkReader};
+use pbs_datastore::manifest::ArchiveType;
+use pbs_datastore::merkle::{index_digests, merkle_proof, merkle_root, verify_merkle_proof};
+use pbs_tools::crypt_config::CryptConfig;
+use pbs_tools::json::required_string_param;
+
+use crate::{
+    complete_archive_name, complete_backup_snapshot, complete_namespace, complete_repository,
+    connect_shared, crypto_parameters, decrypt_key, extract_repository_from_value,
+    format_key_source, optional_ns_param, parse_archive_type, record_repository, BackupDir,
+    BufferedDynamicReadAt, BufferedDynamicReader, IndexFile, KEYFD_SCHEMA, REPO_URL_SCHEMA,
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Inclusion proof of a single chunk.
+struct ChunkProof {
+    index: usize,
+    digest: String,
+    proof: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Inclusion proof of the chunks holding a file or byte range of an archive.
+struct InclusionProof {
+    snapshot: String,
+    archive: String,
+    /// Merkle root over all chunk digests of the archive
+    root: String,
+    /// Whether the root was recorded in the manifest by the server
+    recorded: bool,
+    chunk_count: usize,
+    /// Byte range within the archive covered by the chunks
+    start: u64,
+    end: u64,
+    chunks: Vec<ChunkProof>,
+}
+
+// returns the range of chunks holding the bytes in `range`
+fn chunks_for_range(ends: &[u64], range: &Range<u64>) -> Result<Range<usize>, Error> {
+    let size = ends.last().copied().unwrap_or(0);
+    if range.start >= range.end || range.end > size {
+        bail!("range {range:?} out of archive bounds (size {size})");
+    }
+    let first = ends.partition_point(|end| *end <= range.start);
+    let last = ends.partition_point(|end| *end < range.end);
+    Ok(first..(last + 1))
+}
+
+fn parse_digest(hex: &str) -> Result<[u8; 32], Error> {
+    <[u8; 32]>::from_hex(hex).map_err(|err| format_err!("invalid digest '{hex}' - {err}"))
+}
+
+#[api(
+   input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            snapshot: {
+                type: String,
+                description: "Snapshot path.",
+            },
+            "archive-name": {
+                type: String,
+                description: "Backup archive name.",
+            },
+            path: {
+                type: String,
+                description: "Path of a file within a pxar archive.",
+                optional: true,
+            },
+            offset: {
+                type: Integer,
+                description: "Start of a byte range within the archive.",
+                minimum: 0,
+                optional: true,
+            },
+            length: {
+                type: Integer,
+                description: "Length of the byte range.",
+                minimum: 1,
+                optional: true,
+                default: 1,
+            },
+            "output-file": {
+                type: String,
+                description: "Write the proof to this file instead of stdout.",
+                optional: true,
+            },
+            "keyfile": {
+                optional: true,
+                type: String,
+                description: "Path to encryption key.",
+            },
+            "keyfd": {
+                schema: KEYFD_SCHEMA,
+                optional: true,
+            },
+        }
+   }
+)]
+/// Create an inclusion proof for the chunks holding a file or byte range of an archive.
+async fn create_proof(param: Value) -> Result<Value, Error> {
+    let repo = extract_repository_from_value(&param)?;
+
+    let backup_ns = optional_ns_param(&param)?;
+    let snapshot: BackupDir = required_string_param(&param, "snapshot")?.parse()?;
+    let (archive_name, archive_type) =
+        parse_archive_type(required_string_param(&param, "archive-name")?);
+
+    let file_path = param["path"].as_str();
+    let range = match (file_path, param["offset"].as_u64()) {
+        (Some(_), Some(_)) => bail!("'path' and 'offset' are mutually exclusive"),
+        (None, None) => bail!("either 'path' or 'offset' is required"),
+        (Some(_), None) => None,
+        (None, Some(offset)) => {
+            let length = param["length"].as_u64().unwrap_or(1);
+            let end = offset
+                .checked_add(length)
+                .ok_or_else(|| format_err!("byte range exceeds the maximum offset"))?;
+            Some(offset..end)
+        }
+    };
+
+    let crypto = crypto_parameters(&param)?;
+
+    let crypt_config = match crypto.enc_key {
+        None => None,
+        Some(key) => {
+            let (key, _created, _fingerprint) = decrypt_key(&key.key, &get_encryption_key_password)
+                .map_err(|err| {
+                    log::error!("{}", format_key_source(&key.source, "encryption"));
+                    err
+                })?;
+            let crypt_config = CryptConfig::new(key)?;
+            Some(Arc::new(crypt_config))
+        }
+    };
+
+    let client = connect_shared(&repo)?;
+
+    let client = BackupReader::start(
+        &client,
+        crypt_config.clone(),
+        repo.store(),
+        &backup_ns,
+        &snapshot,
+        true,
+    )
+    .await?;
+
+    let (manifest, _) = client.download_manifest().await?;
+    manifest.check_fingerprint(crypt_config.as_ref().map(Arc::as_ref))?;
+
+    // the indices are verified against the manifest on download
+    let (digests, ends, range) = match archive_type {
+        ArchiveType::FixedIndex => {
+            if file_path.is_some() {
+                bail!("'path' can only be used with pxar archives");
+            }
+            let index = client
+                .download_fixed_index(&manifest, &archive_name)
+                .await?;
+            let ends: Vec<u64> = (0..index.index_count())
+                .map(|pos| index.chunk_info(pos).unwrap().range.end)
+                .collect();
+            (index_digests(&index), ends, range.unwrap())
+        }
+        ArchiveType::DynamicIndex => {
+            let index = client
+                .download_dynamic_index(&manifest, &archive_name)
+                .await?;
+            let digests = index_digests(&index);
+            let ends: Vec<u64> = (0..index.index_count())
+                .map(|pos| index.chunk_info(pos).unwrap().range.end)
+                .collect();
+
+            let range = match (range, file_path) {
+                (Some(range), _) => range,
+                (None, Some(file_path)) => {
+                    if !archive_name.ends_with(".pxar.didx") {
+                        bail!("'path' can only be used with pxar archives");
+                    }
+                    let most_used = index.find_most_used_chunks(8);
+                    let file_info = manifest.lookup_file_info(&archive_name)?;
+                    let chunk_reader = RemoteChunkReader::new(
+                        client.clone(),
+                        crypt_config.clone(),
+                        file_info.chunk_crypt_mode(),
+                        most_used,
+                    );
+                    let reader = BufferedDynamicReader::new(index, chunk_reader);
+                    let archive_size = reader.archive_size();
+                    let reader: Arc<dyn ReadAt + Send + Sync> =
+                        Arc::new(BufferedDynamicReadAt::new(reader));
+                    let accessor = Accessor::new(reader, archive_size).await?;
+                    let root = accessor.open_root().await?;
+                    let entry = root
+                        .lookup(file_path)
+                        .await?
+                        .ok_or_else(|| format_err!("'{file_path}' not found in archive"))?;
+                    entry.entry_range_info().entry_range.clone()
+                }
+                (None, None) => unreachable!(),
+            };
+
+            (digests, ends, range)
+        }
+        ArchiveType::Blob => bail!("proofs are only available for index archives"),
+    };
+
+    let chunks = chunks_for_range(&ends, &range)?;
+
+    let root = merkle_root(&digests);
+    let recorded = match manifest.merkle_root(&archive_name)? {
+        Some(recorded) if recorded != root => {
+            bail!("merkle root of '{archive_name}' does not match the one in the manifest")
+        }
+        Some(_) => true,
+        None => false,
+    };
+
+    let start = if chunks.start == 0 {
+        0
+    } else {
+        ends[chunks.start - 1]
+    };
+
+    let mut proof = InclusionProof {
+        snapshot: snapshot.to_string(),
+        archive: archive_name,
+        root: hex::encode(root),
+        recorded,
+        chunk_count: digests.len(),
+        start,
+        end: ends[chunks.end - 1],
+        chunks: Vec::new(),
+    };
+
+    for pos in chunks {
+        proof.chunks.push(ChunkProof {
+            index: pos,
+            digest: hex::encode(digests[pos]),
+            proof: merkle_proof(&digests, pos)?
+                .iter()
+                .map(hex::encode)
+                .collect(),
+        });
+    }
+
+    let text = serde_json::to_string_pretty(&proof)?;
+    match param["output-file"].as_str() {
+        Some(target) => replace_file(target, text.as_bytes(), CreateOptions::new(), false)?,
+        None => println!("{text}"),
+    }
+
+    record_repository(&repo);
+
+    Ok(Value::Null)
+}
+
+#[api(
+   input: {
+        properties: {
+            "proof-file": {
+                type: String,
+                description: "File containing the proof.",
+            },
+            root: {
+                type: String,
+                description: "Expected Merkle root, e.g. taken from a trusted copy of the manifest.",
+                optional: true,
+            },
+        }
+   }
+)]
+/// Verify an inclusion proof. Does not need access to the server.
+fn verify_proof(param: Value) -> Result<Value, Error> {
+    let path = required_string_param(&param, "proof-file")?;
+    let proof: InclusionProof = serde_json::from_slice(&file_get_contents(path)?)
+        .map_err(|err| format_err!("unable to parse proof '{path}' - {err}"))?;
+
+    let root = parse_digest(&proof.root)?;
+    if let Some(expected) = param["root"].as_str() {
+        if parse_digest(expected)? != root {
+            bail!("proof was created for a different merkle root");
+        }
+    }
+
+    if proof.chunks.is_empty() {
+        bail!("proof does not contain any chunks");
+    }
+
+    for chunk in &proof.chunks {
+        let digest = parse_digest(&chunk.digest)?;
+        let path = chunk
+            .proof
+            .iter()
+            .map(String::as_str)
+            .map(parse_digest)
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        if !verify_merkle_proof(&root, &digest, chunk.index, proof.chunk_count, &path) {
+            bail!("proof for chunk {} is invalid", chunk.index);
+        }
+    }
+
+    println!(
+        "proof valid: {} chunks of '{}' in {} belong to merkle root {}",
+        proof.chunks.len(),
+        proof.archive,
+        proof.snapshot,
+        proof.root,
+    );
+
+    Ok(Value::Null)
+}
+
+pub fn merkle_mgmt_cli() -> CliCommandMap {
+    let proof_cmd_def = CliCommand::new(&API_METHOD_CREATE_PROOF)
+        .arg_param(&["snapshot", "archive-name"])
+        .completion_cb("repository", complete_repository)
+        .completion_cb("ns", complete_namespace)
+        .completion_cb("snapshot", complete_backup_snapshot)
+        .completion_cb("archive-name", complete_archive_name);
+
+    let verify_cmd_def = CliCommand::new(&API_METHOD_VERIFY_PROOF)
+        .arg_param(&["proof-file"])
+        .completion_cb("proof-file", complete_file_name);
+
+    CliCommandMap::new()
+        .insert("proof", proof_cmd_def)
+        .insert("verify", verify_cmd_def)
+}
@@ -29,7 +29,7 @@ use pbs_tools::json::required_string_param;
 
 use crate::{
     complete_group_or_snapshot, complete_img_archive_name, complete_namespace,
-    complete_pxar_archive_name, complete_repository, connect, dir_or_last_from_group,
+    complete_pxar_archive_name, complete_repository, connect_shared, dir_or_last_from_group,
     extract_repository_from_value, optional_ns_param, record_repository, BufferedDynamicReadAt,
     REPO_URL_SCHEMA,
 };
@@ -198,7 +198,7 @@ fn mount(
 async fn mount_do(param: Value, pipe: Option<OwnedFd>) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
     let archive_name = required_string_param(&param, "archive-name")?;
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let target = param["target"].as_str();
 
@@ -10,7 +10,7 @@ use proxmox_router::cli::{
 use proxmox_schema::api;
 
 use crate::{
-    complete_namespace, connect, extract_repository_from_value, optional_ns_param,
+    complete_namespace, connect_shared, extract_repository_from_value, optional_ns_param,
     record_repository,
 };
 
@@ -54,7 +54,7 @@ async fn list_namespaces(param: Value, max_depth: Option<usize>) -> Result<(), E
         param["parent"] = serde_json::to_value(backup_ns)?;
     }
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let mut result = client.get(&path, Some(param)).await?;
 
@@ -112,7 +112,7 @@ async fn create_namespace(param: Value) -> Result<(), Error> {
         "name": name,
     });
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let _result = client.post(&path, Some(param)).await?;
 
@@ -155,7 +155,7 @@ async fn delete_namespace(param: Value, delete_groups: Option<bool>) -> Result<(
         param["delete-groups"] = serde_json::to_value(value)?;
     }
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let _result = client.delete(&path, Some(param)).await?;
 
@@ -3,12 +3,17 @@ use std::sync::Arc;
 use anyhow::Error;
 use serde_json::{json, Value};
 
+use proxmox_human_byte::HumanByte;
 use proxmox_router::cli::*;
 use proxmox_schema::api;
 use proxmox_sys::fs::file_get_contents;
 
-use pbs_api_types::{BackupGroup, BackupNamespace, CryptMode, SnapshotListItem};
+use pbs_api_types::{
+    BackupGroup, BackupNamespace, CryptMode, ManifestInfo, RemovalPreview, SnapshotListItem,
+    CONFIRMATION_TOKEN_SCHEMA,
+};
 use pbs_client::tools::key_source::get_encryption_key_password;
+use pbs_client::view_task_result;
 use pbs_datastore::DataBlob;
 use pbs_key_config::decrypt_key;
 use pbs_tools::crypt_config::CryptConfig;
@@ -16,9 +21,9 @@ use pbs_tools::json::required_string_param;
 
 use crate::{
     api_datastore_list_snapshots, complete_backup_group, complete_backup_snapshot,
-    complete_namespace, complete_repository, connect, crypto_parameters,
+    complete_namespace, complete_repository, connect_shared, crypto_parameters,
     extract_repository_from_value, optional_ns_param, record_repository, BackupDir, KEYFD_SCHEMA,
-    KEYFILE_SCHEMA, REPO_URL_SCHEMA,
+    KEYFILE_SCHEMA, REPO_URL_SCHEMA, SECURE_PERMS_CHECK_SCHEMA,
 };
 
 fn snapshot_args(ns: &BackupNamespace, snapshot: &BackupDir) -> Result<Value, Error> {
@@ -58,7 +63,7 @@ async fn list_snapshots(param: Value) -> Result<Value, Error> {
 
     let output_format = get_output_format(&param);
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let group: Option<BackupGroup> = param["group"]
         .as_str()
@@ -96,7 +101,12 @@ async fn list_snapshots(param: Value) -> Result<Value, Error> {
                 .header("snapshot"),
         )
         .column(ColumnConfig::new("size").renderer(pbs_tools::format::render_bytes_human_readable))
-        .column(ColumnConfig::new("files").renderer(render_files));
+        .column(ColumnConfig::new("files").renderer(render_files))
+        .column(
+            ColumnConfig::new("retention-tags")
+                .renderer(pbs_tools::format::render_string_list)
+                .header("retained by"),
+        );
 
     let return_type = &pbs_api_types::ADMIN_DATASTORE_LIST_SNAPSHOTS_RETURN_TYPE;
 
@@ -127,7 +137,9 @@ async fn list_snapshots(param: Value) -> Result<Value, Error> {
         }
    }
 )]
-/// List snapshot files.
+/// List snapshot files, with their size and crypt mode from the manifest.
+///
+/// The text output additionally shows the verification state of the snapshot.
 async fn list_snapshot_files(param: Value) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
 
@@ -137,7 +149,7 @@ async fn list_snapshot_files(param: Value) -> Result<Value, Error> {
 
     let output_format = get_output_format(&param);
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let path = format!("api2/json/admin/datastore/{}/files", repo.store());
 
@@ -151,10 +163,127 @@ async fn list_snapshot_files(param: Value) -> Result<Value, Error> {
 
     let mut data: Value = result["data"].take();
 
-    let options = default_table_format_options();
+    let render_previous_names = |value: &Value, _record: &Value| -> Result<String, Error> {
+        let names: Vec<&str> = match value.as_array() {
+            Some(names) => names.iter().filter_map(Value::as_str).collect(),
+            None => Vec::new(),
+        };
+        Ok(names.join(", "))
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("filename"))
+        .column(ColumnConfig::new("size").renderer(pbs_tools::format::render_bytes_human_readable))
+        .column(ColumnConfig::new("crypt-mode"))
+        .column(ColumnConfig::new("previous-names").renderer(render_previous_names));
 
     format_and_print_result_full(&mut data, return_type, &output_format, &options);
 
+    if output_format == "text" {
+        let list =
+            api_datastore_list_snapshots(&client, repo.store(), &backup_ns, Some(&snapshot.group))
+                .await?;
+        let list: Vec<SnapshotListItem> = serde_json::from_value(list)?;
+        let verification = list
+            .into_iter()
+            .find(|item| item.backup.time == snapshot.time)
+            .and_then(|item| item.verification);
+
+        match verification {
+            Some(verify) => println!(
+                "verify state: {} ({})",
+                serde_json::to_value(verify.state)?
+                    .as_str()
+                    .unwrap_or("unknown"),
+                verify.upid,
+            ),
+            None => println!("verify state: none"),
+        }
+    }
+
+    Ok(Value::Null)
+}
+
+#[api(
+   input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            snapshot: {
+                type: String,
+                description: "Snapshot path.",
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+   }
+)]
+/// Show the manifest of a snapshot, and whether it is signed with the key stored on the server
+/// for the backup group.
+async fn show_manifest(param: Value) -> Result<Value, Error> {
+    let repo = extract_repository_from_value(&param)?;
+
+    let backup_ns = optional_ns_param(&param)?;
+    let path = required_string_param(&param, "snapshot")?;
+    let snapshot: BackupDir = path.parse()?;
+
+    let output_format = get_output_format(&param);
+
+    let client = connect_shared(&repo)?;
+
+    let path = format!("api2/json/admin/datastore/{}/manifest", repo.store());
+
+    let mut result = client
+        .get(&path, Some(snapshot_args(&backup_ns, &snapshot)?))
+        .await?;
+
+    record_repository(&repo);
+
+    let data: Value = result["data"].take();
+
+    if output_format == "text" {
+        let info: ManifestInfo = serde_json::from_value(data)?;
+        println!("snapshot: {}", info.backup);
+        println!("manifest version: {}", info.version);
+        println!(
+            "signature state: {}",
+            serde_json::to_value(info.signature_state)?
+                .as_str()
+                .unwrap_or("unknown"),
+        );
+        if let Some(fingerprint) = info.fingerprint {
+            println!("key fingerprint: {fingerprint}");
+        }
+        if let Some(fingerprint) = info.known_fingerprint {
+            println!("group key fingerprint: {fingerprint}");
+        }
+        println!("files:");
+        for file in info.files {
+            let crypt_mode = match file.crypt_mode {
+                Some(mode) => serde_json::to_value(mode)?,
+                None => Value::Null,
+            };
+            println!(
+                "  {} {} {}",
+                file.filename,
+                file.size
+                    .map(|size| HumanByte::from(size).to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                crypt_mode.as_str().unwrap_or("-"),
+            );
+        }
+    } else {
+        format_and_print_result(&data, &output_format);
+    }
+
     Ok(Value::Null)
 }
 
@@ -173,6 +302,17 @@ async fn list_snapshot_files(param: Value) -> Result<Value, Error> {
                 type: String,
                 description: "Snapshot path.",
             },
+            preview: {
+                type: bool,
+                description: "Only show what would be removed, and the token to confirm it \
+                    if safe mode is enabled on the server.",
+                optional: true,
+                default: false,
+            },
+            "confirm-token": {
+                schema: CONFIRMATION_TOKEN_SCHEMA,
+                optional: true,
+            },
         }
     }
 )]
@@ -184,13 +324,28 @@ async fn forget_snapshots(param: Value) -> Result<(), Error> {
     let path = required_string_param(&param, "snapshot")?;
     let snapshot: BackupDir = path.parse()?;
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
+
+    let mut args = snapshot_args(&backup_ns, &snapshot)?;
+
+    if param["preview"].as_bool().unwrap_or(false) {
+        let path = format!("api2/json/admin/datastore/{}/forget-preview", repo.store());
+        let mut result = client.get(&path, Some(args)).await?;
+        let preview: RemovalPreview = serde_json::from_value(result["data"].take())?;
+        for item in preview.items {
+            println!("would remove {item}");
+        }
+        println!("confirmation token: {}", preview.token);
+        return Ok(());
+    }
+
+    if let Some(token) = param["confirm-token"].as_str() {
+        args["confirm-token"] = token.into();
+    }
 
     let path = format!("api2/json/admin/datastore/{}/snapshots", repo.store());
 
-    client
-        .delete(&path, Some(snapshot_args(&backup_ns, &snapshot)?))
-        .await?;
+    client.delete(&path, Some(args)).await?;
 
     record_repository(&repo);
 
@@ -224,6 +379,10 @@ async fn forget_snapshots(param: Value) -> Result<(), Error> {
                 schema: KEYFD_SCHEMA,
                 optional: true,
             },
+            "secure-perms-check": {
+                schema: SECURE_PERMS_CHECK_SCHEMA,
+                optional: true,
+            },
             "crypt-mode": {
                 type: CryptMode,
                 optional: true,
@@ -240,7 +399,7 @@ async fn upload_log(param: Value) -> Result<Value, Error> {
     let snapshot = required_string_param(&param, "snapshot")?;
     let snapshot: BackupDir = snapshot.parse()?;
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let crypto = crypto_parameters(&param)?;
 
@@ -278,6 +437,66 @@ async fn upload_log(param: Value) -> Result<Value, Error> {
         .await
 }
 
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            snapshot: {
+                type: String,
+                description: "Snapshot path.",
+            },
+            target: {
+                type: String,
+                description: "Backup group to create the clone in, for example 'host/template'.",
+            },
+            "target-ns": {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Clone a snapshot into another backup group on the server, without copying any chunks.
+async fn clone_snapshot(param: Value) -> Result<Value, Error> {
+    let repo = extract_repository_from_value(&param)?;
+
+    let output_format = get_output_format(&param);
+
+    let backup_ns = optional_ns_param(&param)?;
+    let snapshot: BackupDir = required_string_param(&param, "snapshot")?.parse()?;
+    let target: BackupGroup = required_string_param(&param, "target")?.parse()?;
+
+    let client = connect_shared(&repo)?;
+
+    let mut args = snapshot_args(&backup_ns, &snapshot)?;
+    args["target-type"] = serde_json::to_value(target.ty)?;
+    args["target-id"] = target.id.into();
+    if let Some(target_ns) = param["target-ns"].as_str() {
+        args["target-ns"] = target_ns.into();
+    }
+
+    let path = format!("api2/json/admin/datastore/{}/clone-snapshot", repo.store());
+
+    let result = client.post(&path, Some(args)).await?;
+
+    record_repository(&repo);
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
 #[api(
     input: {
         properties: {
@@ -307,7 +526,7 @@ async fn show_notes(param: Value) -> Result<Value, Error> {
 
     let backup_ns = optional_ns_param(&param)?;
     let snapshot: BackupDir = path.parse()?;
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let path = format!("api2/json/admin/datastore/{}/notes", repo.store());
 
@@ -365,7 +584,7 @@ async fn update_notes(param: Value) -> Result<Value, Error> {
 
     let backup_ns = optional_ns_param(&param)?;
     let snapshot: BackupDir = path.parse()?;
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let path = format!("api2/json/admin/datastore/{}/notes", repo.store());
 
@@ -406,7 +625,7 @@ async fn show_protection(param: Value) -> Result<(), Error> {
 
     let backup_ns = optional_ns_param(&param)?;
     let snapshot: BackupDir = path.parse()?;
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let path = format!("api2/json/admin/datastore/{}/protected", repo.store());
 
@@ -463,7 +682,7 @@ async fn update_protection(protected: bool, param: Value) -> Result<(), Error> {
 
     let backup_ns = optional_ns_param(&param)?;
     let snapshot: BackupDir = path.parse()?;
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let path = format!("api2/json/admin/datastore/{}/protected", repo.store());
 
@@ -531,6 +750,14 @@ pub fn snapshot_mgtm_cli() -> CliCommandMap {
                 .completion_cb("repository", complete_repository)
                 .completion_cb("snapshot", complete_backup_snapshot),
         )
+        .insert(
+            "manifest",
+            CliCommand::new(&API_METHOD_SHOW_MANIFEST)
+                .arg_param(&["snapshot"])
+                .completion_cb("ns", complete_namespace)
+                .completion_cb("repository", complete_repository)
+                .completion_cb("snapshot", complete_backup_snapshot),
+        )
         .insert(
             "forget",
             CliCommand::new(&API_METHOD_FORGET_SNAPSHOTS)
@@ -539,6 +766,16 @@ pub fn snapshot_mgtm_cli() -> CliCommandMap {
                 .completion_cb("repository", complete_repository)
                 .completion_cb("snapshot", complete_backup_snapshot),
         )
+        .insert(
+            "clone",
+            CliCommand::new(&API_METHOD_CLONE_SNAPSHOT)
+                .arg_param(&["snapshot", "target"])
+                .completion_cb("ns", complete_namespace)
+                .completion_cb("target-ns", complete_namespace)
+                .completion_cb("snapshot", complete_backup_snapshot)
+                .completion_cb("target", complete_backup_group)
+                .completion_cb("repository", complete_repository),
+        )
         .insert(
             "upload-log",
             CliCommand::new(&API_METHOD_UPLOAD_LOG)
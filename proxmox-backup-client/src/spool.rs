@@ -0,0 +1,233 @@
+use anyhow::{bail, Error};
+use serde_json::{json, Value};
+
+use proxmox_router::cli::{
+    complete_file_name, format_and_print_result, get_output_format, CliCommand, CliCommandMap,
+    OUTPUT_FORMAT,
+};
+use proxmox_schema::api;
+
+use pbs_api_types::CryptMode;
+use pbs_client::pxar::PxarCreateOptions;
+use pbs_client::tools::key_source::{
+    crypto_parameters, KEYFD_SCHEMA, KEYFILE_SCHEMA, MASTER_PUBKEY_FD_SCHEMA,
+    MASTER_PUBKEY_FILE_SCHEMA, SECURE_PERMS_CHECK_SCHEMA,
+};
+use pbs_client::{BackupRepository, BackupSpecificationType, BackupSpool, SpoolWriter};
+
+use crate::{backup_session_options, connect_shared, record_repository};
+
+/// Spool the archives of `upload_list` (as built by `create_backup`) and queue the backup.
+pub(crate) async fn spool_backup(
+    mut writer: SpoolWriter<'_>,
+    upload_list: Vec<(BackupSpecificationType, String, String, &'static str, u64)>,
+    chunk_size: Option<usize>,
+    pxar_options: PxarCreateOptions,
+) -> Result<(), Error> {
+    for (backup_type, filename, target_base, extension, _size) in upload_list {
+        log::info!("Spool '{}' as {}.{}", filename, target_base, extension);
+        match backup_type {
+            BackupSpecificationType::CONFIG | BackupSpecificationType::LOGFILE => {
+                writer.add_blob_from_file(&filename, &target_base)?;
+            }
+            BackupSpecificationType::PXAR => {
+                writer
+                    .add_pxar(&filename, &target_base, chunk_size, pxar_options.clone())
+                    .await?;
+            }
+            BackupSpecificationType::IMAGE => {
+                writer
+                    .add_image(&filename, &target_base, chunk_size)
+                    .await?;
+            }
+            BackupSpecificationType::STREAM => {
+                writer
+                    .add_stream(tokio::io::stdin(), &target_base, chunk_size)
+                    .await?;
+            }
+        }
+    }
+
+    let info = writer.finish()?;
+    log::info!(
+        "Spooled backup '{}' - use 'proxmox-backup-client spool upload' once the server is reachable again.",
+        info.id
+    );
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            "spool-dir": {
+                type: String,
+                description: "Spool directory.",
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List spooled backups.
+fn list_spool(param: Value, spool_dir: String) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+
+    let spool = BackupSpool::open(spool_dir)?;
+    let list = spool.list()?;
+
+    if output_format == "text" {
+        for info in list {
+            let archives: Vec<&str> = info.archives.iter().map(|a| a.name.as_str()).collect();
+            println!(
+                "{} -> {} ({})",
+                info.id,
+                info.repository,
+                archives.join(", ")
+            );
+        }
+    } else {
+        let list: Vec<Value> = list
+            .into_iter()
+            .map(|info| {
+                let mut data = serde_json::to_value(&info).unwrap_or(Value::Null);
+                data["id"] = json!(info.id);
+                data
+            })
+            .collect();
+        format_and_print_result(&Value::from(list), &output_format);
+    }
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            "spool-dir": {
+                type: String,
+                description: "Spool directory.",
+            },
+            id: {
+                type: String,
+                description: "Only upload this spooled backup.",
+                optional: true,
+            },
+            keyfile: {
+                schema: KEYFILE_SCHEMA,
+                optional: true,
+            },
+            "keyfd": {
+                schema: KEYFD_SCHEMA,
+                optional: true,
+            },
+            "secure-perms-check": {
+                schema: SECURE_PERMS_CHECK_SCHEMA,
+                optional: true,
+            },
+            "master-pubkey-file": {
+                schema: MASTER_PUBKEY_FILE_SCHEMA,
+                optional: true,
+            },
+            "master-pubkey-fd": {
+                schema: MASTER_PUBKEY_FD_SCHEMA,
+                optional: true,
+            },
+            "crypt-mode": {
+                type: CryptMode,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Upload spooled backups, oldest first. Successfully uploaded backups are removed from the
+/// spool.
+///
+/// Only chunks which are not already known to the server are transferred.
+async fn upload_spool(param: Value, spool_dir: String, id: Option<String>) -> Result<(), Error> {
+    let spool = BackupSpool::open(spool_dir)?;
+
+    let mut list = spool.list()?;
+    if let Some(id) = id {
+        list.retain(|info| info.id == id);
+        if list.is_empty() {
+            bail!("no such spooled backup '{}'", id);
+        }
+    }
+
+    let mut errors = 0;
+    for info in list {
+        log::info!(
+            "Uploading spooled backup '{}' to '{}'",
+            info.id,
+            info.repository
+        );
+
+        let result = async {
+            let repo: BackupRepository = info.repository.parse()?;
+            let client = connect_shared(&repo)?;
+            record_repository(&repo);
+
+            let options = backup_session_options(crypto_parameters(&param)?, None)?;
+
+            spool.upload(&client, repo.store(), &info, options).await
+        }
+        .await;
+
+        if let Err(err) = result {
+            log::error!("upload of spooled backup '{}' failed - {}", info.id, err);
+            errors += 1;
+        }
+    }
+
+    if errors > 0 {
+        bail!("{} spooled backup(s) could not be uploaded", errors);
+    }
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            "spool-dir": {
+                type: String,
+                description: "Spool directory.",
+            },
+            id: {
+                type: String,
+                description: "Spooled backup ID.",
+            },
+        }
+    }
+)]
+/// Discard a spooled backup without uploading it.
+fn discard_spool(spool_dir: String, id: String) -> Result<(), Error> {
+    BackupSpool::open(spool_dir)?.remove(&id)
+}
+
+pub fn cli() -> CliCommandMap {
+    CliCommandMap::new()
+        .insert(
+            "list",
+            CliCommand::new(&API_METHOD_LIST_SPOOL)
+                .arg_param(&["spool-dir"])
+                .completion_cb("spool-dir", complete_file_name),
+        )
+        .insert(
+            "upload",
+            CliCommand::new(&API_METHOD_UPLOAD_SPOOL)
+                .arg_param(&["spool-dir"])
+                .completion_cb("spool-dir", complete_file_name)
+                .completion_cb("keyfile", complete_file_name)
+                .completion_cb("master-pubkey-file", complete_file_name),
+        )
+        .insert(
+            "discard",
+            CliCommand::new(&API_METHOD_DISCARD_SPOOL)
+                .arg_param(&["spool-dir", "id"])
+                .completion_cb("spool-dir", complete_file_name),
+        )
+}
@@ -10,7 +10,7 @@ use pbs_tools::json::required_string_param;
 
 use pbs_api_types::UPID;
 
-use crate::{complete_repository, connect, extract_repository_from_value, REPO_URL_SCHEMA};
+use crate::{complete_repository, connect_shared, extract_repository_from_value, REPO_URL_SCHEMA};
 
 #[api(
     input: {
@@ -44,7 +44,7 @@ async fn task_list(param: Value) -> Result<Value, Error> {
     let output_format = get_output_format(&param);
 
     let repo = extract_repository_from_value(&param)?;
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let limit = param["limit"].as_u64().unwrap_or(50) as usize;
     let running = !param["all"].as_bool().unwrap_or(false);
@@ -102,7 +102,7 @@ async fn task_log(param: Value) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
     let upid = required_string_param(&param, "upid")?;
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     display_task_log(&client, upid, true, false).await?;
 
@@ -127,7 +127,7 @@ async fn task_stop(param: Value) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
     let upid_str = required_string_param(&param, "upid")?;
 
-    let client = connect(&repo)?;
+    let client = connect_shared(&repo)?;
 
     let path = format!(
         "api2/json/nodes/localhost/tasks/{}",
@@ -0,0 +1,369 @@
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::io::Read;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use serde_json::Value;
+use tokio::io::AsyncReadExt;
+
+use pxar::accessor::aio::Accessor;
+use pxar::accessor::ReadAt;
+use pxar::EntryKind;
+
+use proxmox_router::cli::*;
+use proxmox_schema::api;
+
+use pbs_api_types::BackupNamespace;
+use pbs_client::tools::key_source::get_encryption_key_password;
+use pbs_client::{BackupReader, RemoteChunkReader};
+use pbs_tools::crypt_config::CryptConfig;
+use pbs_tools::json::required_string_param;
+
+use crate::{
+    complete_backup_snapshot, complete_namespace, complete_pxar_archive_name, complete_repository,
+    connect_shared, crypto_parameters, decrypt_key, extract_repository_from_value,
+    format_key_source, optional_ns_param, record_repository, BackupDir, BufferedDynamicReadAt,
+    BufferedDynamicReader, KEYFD_SCHEMA, REPO_URL_SCHEMA,
+};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+fn kind_name(kind: &EntryKind) -> &'static str {
+    match kind {
+        EntryKind::Directory => "directory",
+        EntryKind::File { .. } | EntryKind::Hardlink(_) => "file",
+        EntryKind::Symlink(_) => "symlink",
+        EntryKind::Device(_) => "device",
+        EntryKind::Fifo => "fifo",
+        EntryKind::Socket => "socket",
+        EntryKind::GoodbyeTable => "goodbye table",
+    }
+}
+
+fn local_kind_name(metadata: &Metadata) -> &'static str {
+    let file_type = metadata.file_type();
+    if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_file() {
+        "file"
+    } else if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_block_device() || file_type.is_char_device() {
+        "device"
+    } else if file_type.is_fifo() {
+        "fifo"
+    } else {
+        "socket"
+    }
+}
+
+fn local_file_digest(path: &Path) -> Result<[u8; 32], Error> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = openssl::sha::Sha256::new();
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    loop {
+        let count = file.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+    }
+    Ok(hasher.finish())
+}
+
+/// Differences found between an archive and the local file system.
+#[derive(Default)]
+struct DriftReport {
+    missing: usize,
+    added: usize,
+    changed: usize,
+}
+
+impl DriftReport {
+    fn missing(&mut self, path: &Path) {
+        println!("missing: {}", path.display());
+        self.missing += 1;
+    }
+
+    fn added(&mut self, path: &Path) {
+        println!("added: {}", path.display());
+        self.added += 1;
+    }
+
+    fn changed(&mut self, path: &Path, details: &str) {
+        println!("changed: {} ({details})", path.display());
+        self.changed += 1;
+    }
+
+    fn total(&self) -> usize {
+        self.missing + self.added + self.changed
+    }
+}
+
+// reports files below `dir` which are not part of the archive, without crossing file systems
+fn find_added_files(
+    base: &Path,
+    dir: &Path,
+    device: u64,
+    seen: &HashSet<PathBuf>,
+    report: &mut DriftReport,
+) -> Result<(), Error> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|err| format_err!("unable to read directory {dir:?} - {err}"))?;
+
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let relative = Path::new("/").join(path.strip_prefix(base)?);
+
+        if !seen.contains(&relative) {
+            report.added(&relative);
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() && metadata.dev() == device {
+            find_added_files(base, &path, device, seen, report)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[api(
+   input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            snapshot: {
+                type: String,
+                description: "Snapshot path.",
+            },
+            "archive-name": {
+                type: String,
+                description: "Name of the pxar archive.",
+            },
+            path: {
+                type: String,
+                description: "Local directory to compare the archive with.",
+            },
+            "metadata-only": {
+                type: Boolean,
+                description: "Only compare metadata, do not read the file contents.",
+                optional: true,
+                default: false,
+            },
+            "ignore-ownership": {
+                type: Boolean,
+                description: "Do not compare owner and group, for example after restoring as \
+                    a different user.",
+                optional: true,
+                default: false,
+            },
+            "keyfile": {
+                optional: true,
+                type: String,
+                description: "Path to encryption key.",
+            },
+            "keyfd": {
+                schema: KEYFD_SCHEMA,
+                optional: true,
+            },
+        }
+   }
+)]
+/// Compare a pxar archive with a local directory and report any differences.
+///
+/// Reports files missing locally or added locally, as well as changed types, permissions,
+/// ownership, modification times, sizes, symlink targets and contents. Fails if any
+/// difference was found.
+async fn verify_local(param: Value) -> Result<Value, Error> {
+    let repo = extract_repository_from_value(&param)?;
+
+    let backup_ns = optional_ns_param(&param)?;
+    let snapshot: BackupDir = required_string_param(&param, "snapshot")?.parse()?;
+    let archive_name = required_string_param(&param, "archive-name")?;
+    let base = PathBuf::from(required_string_param(&param, "path")?);
+    let metadata_only = param["metadata-only"].as_bool().unwrap_or(false);
+    let ignore_ownership = param["ignore-ownership"].as_bool().unwrap_or(false);
+
+    let archive_name = if archive_name.ends_with(".pxar") {
+        format!("{archive_name}.didx")
+    } else {
+        archive_name.to_string()
+    };
+    if !archive_name.ends_with(".pxar.didx") {
+        bail!("can only compare pxar archives with a local directory");
+    }
+
+    let base_metadata =
+        std::fs::metadata(&base).map_err(|err| format_err!("unable to access {base:?} - {err}"))?;
+    if !base_metadata.is_dir() {
+        bail!("{base:?} is not a directory");
+    }
+
+    let crypto = crypto_parameters(&param)?;
+
+    let crypt_config = match crypto.enc_key {
+        None => None,
+        Some(key) => {
+            let (key, _created, _fingerprint) = decrypt_key(&key.key, &get_encryption_key_password)
+                .map_err(|err| {
+                    log::error!("{}", format_key_source(&key.source, "encryption"));
+                    err
+                })?;
+            let crypt_config = CryptConfig::new(key)?;
+            Some(Arc::new(crypt_config))
+        }
+    };
+
+    let client = connect_shared(&repo)?;
+
+    let client = BackupReader::start(
+        &client,
+        crypt_config.clone(),
+        repo.store(),
+        &backup_ns,
+        &snapshot,
+        true,
+    )
+    .await?;
+
+    let (manifest, _) = client.download_manifest().await?;
+    manifest.check_fingerprint(crypt_config.as_ref().map(Arc::as_ref))?;
+
+    let index = client
+        .download_dynamic_index(&manifest, &archive_name)
+        .await?;
+    let most_used = index.find_most_used_chunks(8);
+    let file_info = manifest.lookup_file_info(&archive_name)?;
+    let chunk_reader = RemoteChunkReader::new(
+        client.clone(),
+        crypt_config.clone(),
+        file_info.chunk_crypt_mode(),
+        most_used,
+    );
+    let reader = BufferedDynamicReader::new(index, chunk_reader);
+    let archive_size = reader.archive_size();
+    let reader: Arc<dyn ReadAt + Send + Sync> = Arc::new(BufferedDynamicReadAt::new(reader));
+    let accessor = Accessor::new(reader, archive_size).await?;
+
+    let root = accessor.open_root().await?;
+    let mut decoder = root.decode_full().await?;
+    decoder.enable_goodbye_entries(false);
+
+    let mut report = DriftReport::default();
+    let mut seen = HashSet::new();
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+
+    while let Some(entry) = decoder.next().await {
+        let entry = entry?;
+        let path = entry.path().to_owned();
+        let local_path = base.join(path.strip_prefix("/")?);
+
+        seen.insert(path.clone());
+
+        let local = match std::fs::symlink_metadata(&local_path) {
+            Ok(local) => local,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                report.missing(&path);
+                continue;
+            }
+            Err(err) => bail!("unable to access {local_path:?} - {err}"),
+        };
+
+        let archive_kind = kind_name(entry.kind());
+        let local_kind = local_kind_name(&local);
+        if archive_kind != local_kind {
+            report.changed(&path, &format!("type {archive_kind} -> {local_kind}"));
+            continue;
+        }
+
+        // hardlinks share the metadata and contents of the file they point to
+        if let EntryKind::Hardlink(_) = entry.kind() {
+            continue;
+        }
+
+        let mut changes = Vec::new();
+
+        let stat = &entry.metadata().stat;
+        if (stat.mode & 0o7777) as u32 != local.mode() & 0o7777 {
+            changes.push("mode");
+        }
+        if !ignore_ownership && (stat.uid != local.uid() || stat.gid != local.gid()) {
+            changes.push("ownership");
+        }
+        // the modification time of symlinks is not restored
+        if !local.file_type().is_symlink()
+            && (stat.mtime.secs != local.mtime() || stat.mtime.nanos as i64 != local.mtime_nsec())
+        {
+            changes.push("mtime");
+        }
+
+        match entry.kind() {
+            EntryKind::File { size, .. } if *size != local.len() => changes.push("size"),
+            EntryKind::File { .. } if !metadata_only => {
+                let mut hasher = openssl::sha::Sha256::new();
+                if let Some(mut contents) = decoder.contents() {
+                    loop {
+                        let count = contents.read(&mut buffer).await?;
+                        if count == 0 {
+                            break;
+                        }
+                        hasher.update(&buffer[..count]);
+                    }
+                }
+                if hasher.finish() != local_file_digest(&local_path)? {
+                    changes.push("content");
+                }
+            }
+            EntryKind::Symlink(link) => {
+                if std::fs::read_link(&local_path)?.as_os_str() != link.as_os_str() {
+                    changes.push("target");
+                }
+            }
+            _ => {}
+        }
+
+        if !changes.is_empty() {
+            report.changed(&path, &changes.join(", "));
+        }
+    }
+
+    find_added_files(&base, &base, base_metadata.dev(), &seen, &mut report)?;
+
+    record_repository(&repo);
+
+    if report.total() > 0 {
+        bail!(
+            "found {} differences ({} missing, {} added, {} changed)",
+            report.total(),
+            report.missing,
+            report.added,
+            report.changed,
+        );
+    }
+
+    log::info!("no differences found");
+
+    Ok(Value::Null)
+}
+
+pub fn verify_local_cmd_def() -> CliCommand {
+    CliCommand::new(&API_METHOD_VERIFY_LOCAL)
+        .arg_param(&["snapshot", "archive-name", "path"])
+        .completion_cb("repository", complete_repository)
+        .completion_cb("ns", complete_namespace)
+        .completion_cb("snapshot", complete_backup_snapshot)
+        .completion_cb("archive-name", complete_pxar_archive_name)
+        .completion_cb("path", complete_file_name)
+}
@@ -26,7 +26,7 @@ use pbs_client::tools::{
     complete_group_or_snapshot, complete_repository, connect, extract_repository_from_value,
     key_source::{
         crypto_parameters_keep_fd, format_key_source, get_encryption_key_password, KEYFD_SCHEMA,
-        KEYFILE_SCHEMA,
+        KEYFILE_SCHEMA, SECURE_PERMS_CHECK_SCHEMA,
     },
     REPO_URL_SCHEMA,
 };
@@ -208,6 +208,10 @@ async fn list_files(
                 schema: KEYFD_SCHEMA,
                 optional: true,
             },
+            "secure-perms-check": {
+                schema: SECURE_PERMS_CHECK_SCHEMA,
+                optional: true,
+            },
             "crypt-mode": {
                 type: CryptMode,
                 optional: true,
@@ -373,6 +377,10 @@ async fn list(
                 schema: KEYFD_SCHEMA,
                 optional: true,
             },
+            "secure-perms-check": {
+                schema: SECURE_PERMS_CHECK_SCHEMA,
+                optional: true,
+            },
             "crypt-mode": {
                 type: CryptMode,
                 optional: true,
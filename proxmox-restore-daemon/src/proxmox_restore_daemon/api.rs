@@ -358,6 +358,12 @@ fn extract(
                         patterns,
                         skip_lost_and_found: false,
                         skip_e2big_xattr: false,
+                        warnings: None,
+                        skip_file_contents: false,
+                        profile: None,
+                        fifos: Default::default(),
+                        sockets: Default::default(),
+                        skip_unchanged_dirs: None,
                     };
 
                     let pxar_writer = TokioWriter::new(writer);
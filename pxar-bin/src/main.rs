@@ -4,7 +4,7 @@ use std::fs::OpenOptions;
 use std::os::unix::fs::OpenOptionsExt;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Error};
 use futures::future::FutureExt;
@@ -12,8 +12,9 @@ use futures::select;
 use tokio::signal::unix::{signal, SignalKind};
 
 use pathpatterns::{MatchEntry, MatchType, PatternFlag};
+use pbs_api_types::{PXAR_ENTRIES_MAX_DEFAULT, PXAR_ENTRIES_MAX_SCHEMA};
 use pbs_client::pxar::{
-    format_single_line_entry, Flags, OverwriteFlags, PxarExtractOptions, ENCODER_MAX_ENTRIES,
+    format_single_line_entry, ArchiveProfile, Flags, OverwriteFlags, PxarExtractOptions,
 };
 
 use proxmox_router::cli::*;
@@ -214,6 +215,8 @@ fn extract_archive(
         overwrite_flags,
         extract_match_default,
         on_error,
+        fifos: Default::default(),
+        sockets: Default::default(),
     };
 
     if archive == "-" {
@@ -288,11 +291,13 @@ fn extract_archive(
                 },
             },
             "entries-max": {
-                description: "Max number of entries loaded at once into memory",
+                schema: PXAR_ENTRIES_MAX_SCHEMA,
+                optional: true,
+            },
+            profile: {
+                description: "Write the time spent in the phases of archiving, per top-level \
+                    directory, as JSON to this file.",
                 optional: true,
-                default: ENCODER_MAX_ENTRIES as isize,
-                minimum: 0,
-                maximum: isize::MAX,
             },
         },
     },
@@ -310,7 +315,8 @@ async fn create_archive(
     no_fifos: bool,
     no_sockets: bool,
     exclude: Option<Vec<String>>,
-    entries_max: isize,
+    entries_max: Option<u64>,
+    profile: Option<String>,
 ) -> Result<(), Error> {
     let patterns = {
         let input = exclude.unwrap_or_default();
@@ -331,12 +337,21 @@ async fn create_archive(
     };
 
     let options = pbs_client::pxar::PxarCreateOptions {
-        entries_max: entries_max as usize,
+        entries_max: entries_max.unwrap_or(PXAR_ENTRIES_MAX_DEFAULT) as usize,
         device_set,
         patterns,
         skip_lost_and_found: false,
         skip_e2big_xattr: false,
+        warnings: None,
+        skip_file_contents: false,
+        profile: profile
+            .as_ref()
+            .map(|_| Arc::new(Mutex::new(ArchiveProfile::default()))),
+        fifos: Default::default(),
+        sockets: Default::default(),
+        skip_unchanged_dirs: None,
     };
+    let archive_profile = options.profile.clone();
 
     let source = PathBuf::from(source);
 
@@ -387,6 +402,12 @@ async fn create_archive(
     )
     .await?;
 
+    if let (Some(path), Some(archive_profile)) = (profile, archive_profile) {
+        let data = serde_json::to_string_pretty(&archive_profile.lock().unwrap().to_value())?;
+        std::fs::write(&path, data)
+            .map_err(|err| format_err!("unable to write profile {path:?} - {err}"))?;
+    }
+
     Ok(())
 }
 
@@ -0,0 +1,284 @@
+//! Chunks found corrupt by verification
+
+use anyhow::{bail, format_err, Error};
+use hex::FromHex;
+
+use proxmox_rest_server::WorkerTask;
+use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType, SubdirMap};
+use proxmox_schema::api;
+use proxmox_sortable_macro::sortable;
+use proxmox_sys::{task_log, task_warn};
+
+use pbs_api_types::{
+    print_ns_and_snapshot, Authid, CryptMode, Operation, QuarantinedChunk, SyncJobConfig,
+    CHUNK_DIGEST_SCHEMA, DATASTORE_SCHEMA, JOB_ID_SCHEMA, PRIV_DATASTORE_AUDIT,
+    PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_VERIFY, UPID_SCHEMA,
+};
+use pbs_config::CachedUserInfo;
+use pbs_datastore::{DataBlob, DataStore};
+
+use crate::api2::config::sync::check_sync_job_modify_access;
+use crate::backup::{verify_backup_dir, VerifyWorker};
+use crate::server::pull::{repair_quarantined_chunks, PullParameters};
+
+/// Returns the given digest, or all quarantined ones.
+fn selected_digests(datastore: &DataStore, digest: Option<String>) -> Result<Vec<[u8; 32]>, Error> {
+    match digest {
+        Some(digest) => Ok(vec![<[u8; 32]>::from_hex(&digest)?]),
+        None => Ok(datastore
+            .chunk_quarantine()
+            .digests()?
+            .into_iter()
+            .collect()),
+    }
+}
+
+fn check_chunk(chunk: &DataBlob, digest: &[u8; 32]) -> Result<(), Error> {
+    match chunk.crypt_mode()? {
+        // without the key, only the CRC can be checked
+        CryptMode::Encrypt => chunk.verify_crc(),
+        CryptMode::None | CryptMode::SignOnly => chunk.decode(None, Some(digest)).map(drop),
+    }
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "List of quarantined chunks.",
+        type: Array,
+        items: { type: QuarantinedChunk },
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, false),
+    },
+)]
+/// List the chunks found corrupt by verification, with the snapshots referencing them.
+pub fn list_quarantined_chunks(store: String) -> Result<Vec<QuarantinedChunk>, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Lookup))?;
+    datastore.chunk_quarantine().list()
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            digest: {
+                schema: CHUNK_DIGEST_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "Digests of the chunks found intact and removed from the quarantine.",
+        type: Array,
+        items: { schema: CHUNK_DIGEST_SCHEMA },
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_VERIFY, false),
+    },
+)]
+/// Check quarantined chunks again, or only the given one.
+///
+/// Chunks which got written again in the meantime, e.g. by a backup or sync, and are intact
+/// are removed from the quarantine.
+pub fn recheck_quarantined_chunks(
+    store: String,
+    digest: Option<String>,
+) -> Result<Vec<String>, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+
+    let mut healed = Vec::new();
+    for digest in selected_digests(&datastore, digest)? {
+        let (path, _digest_str) = datastore.chunk_path(&digest);
+        if !path.exists() {
+            continue;
+        }
+        let intact = datastore
+            .load_chunk(&digest)
+            .and_then(|chunk| check_chunk(&chunk, &digest))
+            .is_ok();
+        if intact {
+            healed.push(digest);
+        }
+    }
+
+    datastore.chunk_quarantine().remove(&healed)?;
+
+    Ok(healed.iter().map(hex::encode).collect())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            digest: {
+                schema: CHUNK_DIGEST_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Remove chunks from the quarantine, or only the given one, and delete their corrupt copies.
+///
+/// This does not repair the snapshots referencing them.
+pub fn purge_quarantined_chunks(store: String, digest: Option<String>) -> Result<(), Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+    let digests = selected_digests(&datastore, digest)?;
+    for digest in &digests {
+        let (path, digest_str) = datastore.chunk_path(digest);
+        for counter in 0..10 {
+            let bad_path = path.with_file_name(format!("{digest_str}.{counter}.bad"));
+            match std::fs::remove_file(&bad_path) {
+                Ok(()) => log::info!("removed corrupt chunk {bad_path:?}"),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(format_err!("unable to remove {bad_path:?} - {err}")),
+            }
+        }
+    }
+
+    datastore.chunk_quarantine().remove(&digests)?;
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            id: {
+                schema: JOB_ID_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        description: "Requires Datastore.Verify on the datastore. Only sync jobs the user may run \
+            are used as source.",
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_VERIFY, false),
+    },
+)]
+/// Fetch quarantined chunks from the source of the sync jobs pulling into the datastore, or
+/// only from the given one, and verify the snapshots referencing them again.
+pub fn repair_chunks(
+    store: String,
+    id: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let (config, _digest) = pbs_config::sync::config()?;
+    let jobs: Vec<SyncJobConfig> = config
+        .convert_to_typed_array("sync")?
+        .into_iter()
+        .filter(|job: &SyncJobConfig| job.store == store)
+        .filter(|job| id.as_ref().map(|id| &job.id == id).unwrap_or(true))
+        .filter(|job| check_sync_job_modify_access(&user_info, &auth_id, job))
+        .collect();
+
+    if jobs.is_empty() {
+        match id {
+            Some(id) => bail!("no sync job '{id}' pulling into datastore '{store}'"),
+            None => bail!("no sync job pulling into datastore '{store}'"),
+        }
+    }
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::spawn(
+        "chunkrepair",
+        Some(store),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| async move {
+            let mut affected = Vec::new();
+            for job in jobs {
+                if datastore.chunk_quarantine().list()?.is_empty() {
+                    break;
+                }
+                task_log!(
+                    worker,
+                    "fetching quarantined chunks using sync job '{}'",
+                    job.id
+                );
+                let params = PullParameters::try_from(&job)?;
+                affected.extend(repair_quarantined_chunks(&worker, &params).await?);
+            }
+
+            let remaining = datastore.chunk_quarantine().list()?.len();
+            if remaining > 0 {
+                task_warn!(worker, "{remaining} chunks could not be repaired");
+            }
+
+            if affected.is_empty() {
+                task_log!(worker, "no snapshot got repaired");
+                return Ok(());
+            }
+
+            task_log!(worker, "verifying {} affected snapshots", affected.len());
+            let worker2 = worker.clone();
+            let failed_dirs = tokio::task::spawn_blocking(move || {
+                let verify_worker = VerifyWorker::new(worker2.clone(), datastore);
+                let mut failed_dirs = Vec::new();
+                for backup_dir in affected {
+                    if !verify_backup_dir(
+                        &verify_worker,
+                        &backup_dir,
+                        worker2.upid().clone(),
+                        None,
+                    )? {
+                        failed_dirs.push(print_ns_and_snapshot(
+                            backup_dir.backup_ns(),
+                            backup_dir.as_ref(),
+                        ));
+                    }
+                }
+                Ok::<_, Error>(failed_dirs)
+            })
+            .await??;
+
+            if !failed_dirs.is_empty() {
+                task_log!(worker, "Failed to verify the following snapshots:");
+                for dir in &failed_dirs {
+                    task_log!(worker, "\t{dir}");
+                }
+                bail!("verification failed - please check the log for details");
+            }
+
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[sortable]
+const SUBDIRS: SubdirMap = &sorted!([("repair", &Router::new().post(&API_METHOD_REPAIR_CHUNKS)),]);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_QUARANTINED_CHUNKS)
+    .post(&API_METHOD_RECHECK_QUARANTINED_CHUNKS)
+    .delete(&API_METHOD_PURGE_QUARANTINED_CHUNKS)
+    .subdirs(SUBDIRS);
@@ -1,6 +1,5 @@
 //! Datastore Management
 
-use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::os::unix::ffi::OsStrExt;
 use std::path::PathBuf;
@@ -14,17 +13,20 @@ use serde::Deserialize;
 use serde_json::{json, Value};
 use tokio_stream::wrappers::ReceiverStream;
 
+use pathpatterns::{MatchEntry, MatchType, PatternFlag};
 use proxmox_async::blocking::WrappedReaderStream;
 use proxmox_async::{io::AsyncChannelWriter, stream::AsyncReaderStream};
 use proxmox_compression::zstd::ZstdEncoder;
+use proxmox_human_byte::HumanByte;
 use proxmox_router::{
-    http_err, list_subdirs_api_method, ApiHandler, ApiMethod, ApiResponseFuture, Permission,
-    Router, RpcEnvironment, RpcEnvironmentType, SubdirMap,
+    http_bail, http_err, list_subdirs_api_method, ApiHandler, ApiMethod, ApiResponseFuture,
+    Permission, Router, RpcEnvironment, RpcEnvironmentType, SubdirMap,
 };
 use proxmox_schema::*;
 use proxmox_sortable_macro::sortable;
 use proxmox_sys::fs::{
-    file_read_firstline, file_read_optional_string, replace_file, CreateOptions,
+    file_read_firstline, file_read_optional_string, lock_dir_noblock_shared, replace_file,
+    CreateOptions,
 };
 use proxmox_sys::{task_log, task_warn};
 use proxmox_time::CalendarEvent;
@@ -34,42 +36,51 @@ use pxar::EntryKind;
 
 use pbs_api_types::{
     print_ns_and_snapshot, print_store_and_ns, Authid, BackupContent, BackupNamespace, BackupType,
-    Counts, CryptMode, DataStoreConfig, DataStoreListItem, DataStoreStatus,
-    GarbageCollectionJobStatus, GroupListItem, JobScheduleStatus, KeepOptions, Operation,
-    PruneJobOptions, RRDMode, RRDTimeFrame, SnapshotListItem, SnapshotVerifyState,
-    BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA,
-    BACKUP_TYPE_SCHEMA, DATASTORE_SCHEMA, IGNORE_VERIFIED_BACKUPS_SCHEMA, MAX_NAMESPACE_DEPTH,
+    ClientDefaults, ComplianceReport, Counts, CryptMode, DataStoreConfig, DataStoreListItem,
+    DataStoreStatus, FileSearchResult, GarbageCollectionJobStatus, GroupHealth, GroupListItem,
+    JobScheduleStatus, KeepOptions, ManifestInfo, ManifestSignatureState, Operation,
+    PruneJobOptions, RRDMode, RRDTimeFrame, RemovalPreview, SnapshotListItem, VerifyState,
+    VerifyStateFilter, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA,
+    BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, CONFIRMATION_TOKEN_SCHEMA, DATASTORE_SCHEMA,
+    GROUP_MAX_SNAPSHOTS_SCHEMA, IGNORE_VERIFIED_BACKUPS_SCHEMA, MAX_NAMESPACE_DEPTH,
     NS_MAX_DEPTH_SCHEMA, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_MODIFY,
     PRIV_DATASTORE_PRUNE, PRIV_DATASTORE_READ, PRIV_DATASTORE_VERIFY, UPID, UPID_SCHEMA,
     VERIFICATION_OUTDATED_AFTER_SCHEMA,
 };
 use pbs_client::pxar::{create_tar, create_zip};
 use pbs_config::CachedUserInfo;
+use pbs_datastore::at_rest::read_at_rest;
 use pbs_datastore::backup_info::BackupInfo;
 use pbs_datastore::cached_chunk_reader::CachedChunkReader;
-use pbs_datastore::catalog::{ArchiveEntry, CatalogReader};
+use pbs_datastore::catalog::ArchiveEntry;
 use pbs_datastore::data_blob::DataBlob;
 use pbs_datastore::data_blob_reader::DataBlobReader;
 use pbs_datastore::dynamic_index::{BufferedDynamicReader, DynamicIndexReader, LocalDynamicReadAt};
+use pbs_datastore::filename_index::{open_catalog, search_catalog, CatalogMatch, FilenameIndex};
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
-use pbs_datastore::manifest::{BackupManifest, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME};
-use pbs_datastore::prune::compute_prune_info;
+use pbs_datastore::manifest::{BackupManifest, CLIENT_LOG_BLOB_NAME};
+use pbs_datastore::prune::{compute_prune_info_with_tags, update_retention_tags};
 use pbs_datastore::{
-    check_backup_owner, task_tracking, BackupDir, BackupGroup, DataStore, LocalChunkReader,
-    StoreProgress, CATALOG_NAME,
+    task_tracking, BackupDir, DataStore, LocalChunkReader, StoreProgress, CATALOG_NAME,
 };
+use pbs_key_config::KeyConfig;
 use pbs_tools::json::required_string_param;
 use proxmox_rest_server::{formatter, WorkerTask};
 
-use crate::api2::backup::optional_ns_param;
+use crate::api2::backup::{check_backup_type_registered, optional_ns_param};
 use crate::api2::node::rrd::create_value_from_rrd;
 use crate::backup::{
-    check_ns_privs_full, verify_all_backups, verify_backup_dir, verify_backup_group, verify_filter,
-    ListAccessibleBackupGroups, NS_PRIVS_OK,
+    check_group_privs_full, check_ns_privs, check_ns_privs_full, lookup_group_privs,
+    verify_all_backups, verify_backup_dir, verify_backup_group, verify_filter,
+    ListAccessibleBackupGroups, GROUP_MODIFY_PRIVS, GROUP_REMOVE_PRIVS, NS_PRIVS_OK,
 };
 
+use crate::server::io_priority::{apply_task_io_priority, TaskIoClass};
 use crate::server::jobstate::{compute_schedule_status, Job, JobState};
+use crate::server::task_cgroup::{enter_task_cgroup, TaskCgroup};
+use crate::server::ComplianceReportAccess;
+use crate::tools::confirmation;
 
 const GROUP_NOTES_FILE_NAME: &str = "notes";
 
@@ -96,68 +107,25 @@ fn check_privs_and_load_store(
     operation: Option<Operation>,
     backup_group: &pbs_api_types::BackupGroup,
 ) -> Result<Arc<DataStore>, Error> {
-    let limited = check_ns_privs_full(store, ns, auth_id, full_access_privs, partial_access_privs)?;
+    let limited = check_group_privs_full(
+        store,
+        ns,
+        backup_group,
+        auth_id,
+        full_access_privs,
+        partial_access_privs,
+    )?;
 
     let datastore = DataStore::lookup_datastore(store, operation)?;
 
     if limited {
         let owner = datastore.get_owner(ns, backup_group)?;
-        check_backup_owner(&owner, auth_id)?;
+        datastore.check_backup_owner(&owner, auth_id)?;
     }
 
     Ok(datastore)
 }
 
-fn read_backup_index(
-    backup_dir: &BackupDir,
-) -> Result<(BackupManifest, Vec<BackupContent>), Error> {
-    let (manifest, index_size) = backup_dir.load_manifest()?;
-
-    let mut result = Vec::new();
-    for item in manifest.files() {
-        result.push(BackupContent {
-            filename: item.filename.clone(),
-            crypt_mode: Some(item.crypt_mode),
-            size: Some(item.size),
-        });
-    }
-
-    result.push(BackupContent {
-        filename: MANIFEST_BLOB_NAME.to_string(),
-        crypt_mode: match manifest.signature {
-            Some(_) => Some(CryptMode::SignOnly),
-            None => Some(CryptMode::None),
-        },
-        size: Some(index_size),
-    });
-
-    Ok((manifest, result))
-}
-
-fn get_all_snapshot_files(
-    info: &BackupInfo,
-) -> Result<(BackupManifest, Vec<BackupContent>), Error> {
-    let (manifest, mut files) = read_backup_index(&info.backup_dir)?;
-
-    let file_set = files.iter().fold(HashSet::new(), |mut acc, item| {
-        acc.insert(item.filename.clone());
-        acc
-    });
-
-    for file in &info.files {
-        if file_set.contains(file) {
-            continue;
-        }
-        files.push(BackupContent {
-            filename: file.to_string(),
-            size: None,
-            crypt_mode: None,
-        });
-    }
-
-    Ok((manifest, files))
-}
-
 #[api(
     input: {
         properties: {
@@ -168,31 +136,43 @@ fn get_all_snapshot_files(
                 type: BackupNamespace,
                 optional: true,
             },
+            health: {
+                type: GroupHealth,
+                optional: true,
+            },
         },
     },
     returns: pbs_api_types::ADMIN_DATASTORE_LIST_GROUPS_RETURN_TYPE,
     access: {
         permission: &Permission::Anybody,
         description: "Requires DATASTORE_AUDIT for all or DATASTORE_BACKUP for owned groups on \
-            /datastore/{store}[/{namespace}]",
+            /datastore/{store}[/{namespace}], or on the group itself.",
     },
 )]
-/// List backup groups.
+/// List backup groups, optionally only those with the given health.
 pub fn list_groups(
     store: String,
     ns: Option<BackupNamespace>,
+    health: Option<GroupHealth>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Vec<GroupListItem>, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let ns = ns.unwrap_or_default();
 
-    let list_all = !check_ns_privs_full(
+    let user_info = CachedUserInfo::new()?;
+    if let Err(err) = check_ns_privs_full(
         &store,
         &ns,
         &auth_id,
         PRIV_DATASTORE_AUDIT,
         PRIV_DATASTORE_BACKUP,
-    )?;
+    ) {
+        // privileges may be granted on single groups only
+        let privs = PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_BACKUP;
+        if !user_info.any_privs_below(&auth_id, &ns.acl_path(&store), privs)? {
+            return Err(err);
+        }
+    }
 
     let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
 
@@ -213,7 +193,11 @@ pub fn list_groups(
                     return Ok(group_info);
                 }
             };
-            if !list_all && check_backup_owner(&owner, &auth_id).is_err() {
+            let privs = lookup_group_privs(&user_info, &auth_id, &store, &ns, group.as_ref());
+            if privs & PRIV_DATASTORE_AUDIT == 0
+                && (privs & PRIV_DATASTORE_BACKUP == 0
+                    || datastore.check_backup_owner(&owner, &auth_id).is_err())
+            {
                 return Ok(group_info);
             }
 
@@ -238,6 +222,17 @@ pub fn list_groups(
                 })
                 .to_owned();
 
+            let group_health = match group.health(&snapshots) {
+                Ok(group_health) => Some(group_health),
+                Err(err) => {
+                    eprintln!("Failed to get health of group '{}' - {err}", group.group());
+                    None
+                }
+            };
+            if health.is_some() && group_health != health {
+                return Ok(group_info);
+            }
+
             let note_path = get_group_note_path(&datastore, &ns, group.as_ref());
             let comment = file_read_firstline(note_path).ok();
 
@@ -248,12 +243,100 @@ pub fn list_groups(
                 backup_count,
                 files: last_backup.files,
                 comment,
+                health: group_health,
             });
 
             Ok(group_info)
         })
 }
 
+// Operation name and the snapshots removed when forgetting a snapshot or a whole group, used
+// for the safe mode confirmation.
+fn forget_items(
+    datastore: &Arc<DataStore>,
+    ns: &BackupNamespace,
+    group: &pbs_api_types::BackupGroup,
+    backup_time: Option<i64>,
+) -> Result<(String, Vec<String>), Error> {
+    let store = datastore.name();
+
+    if let Some(time) = backup_time {
+        let dir = pbs_api_types::BackupDir {
+            group: group.clone(),
+            time,
+        };
+        let operation = format!("forget-snapshot:{store}:{ns}:{dir}");
+        return Ok((operation, vec![print_ns_and_snapshot(ns, &dir)]));
+    }
+
+    let mut list = datastore
+        .backup_group(ns.clone(), group.clone())
+        .list_backups()?;
+    BackupInfo::sort_list(&mut list, true);
+
+    let items = list
+        .iter()
+        .filter(|info| !info.protected)
+        .map(|info| print_ns_and_snapshot(ns, info.backup_dir.dir()))
+        .collect();
+
+    Ok((format!("forget-group:{store}:{ns}:{group}"), items))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "backup-type": { type: BackupType },
+            "backup-id": { schema: BACKUP_ID_SCHEMA },
+            "backup-time": {
+                schema: BACKUP_TIME_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: { type: RemovalPreview },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_MODIFY for any\
+            or DATASTORE_PRUNE and being the owner of the group",
+    },
+)]
+/// Preview forgetting a snapshot, or a whole group if no backup time is given.
+///
+/// Lists the snapshots which would be removed and returns the confirmation token required by
+/// the delete calls in safe mode. Protected snapshots are not removed and thus not listed.
+pub fn forget_preview(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_type: BackupType,
+    backup_id: String,
+    backup_time: Option<i64>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<RemovalPreview, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+    let group = pbs_api_types::BackupGroup::new(backup_type, backup_id);
+
+    let datastore = check_privs_and_load_store(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_MODIFY,
+        PRIV_DATASTORE_PRUNE,
+        Some(Operation::Read),
+        &group,
+    )?;
+
+    let (operation, items) = forget_items(&datastore, &ns, &group, backup_time)?;
+
+    confirmation::create_preview(&auth_id, &operation, items)
+}
+
 #[api(
     input: {
         properties: {
@@ -266,6 +349,10 @@ pub fn list_groups(
                 type: pbs_api_types::BackupGroup,
                 flatten: true,
             },
+            "confirm-token": {
+                schema: CONFIRMATION_TOKEN_SCHEMA,
+                optional: true,
+            },
         },
     },
     access: {
@@ -279,6 +366,7 @@ pub async fn delete_group(
     store: String,
     ns: Option<BackupNamespace>,
     group: pbs_api_types::BackupGroup,
+    confirm_token: Option<String>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
@@ -290,13 +378,16 @@ pub async fn delete_group(
             &store,
             &ns,
             &auth_id,
-            PRIV_DATASTORE_MODIFY,
-            PRIV_DATASTORE_PRUNE,
+            GROUP_REMOVE_PRIVS.full,
+            GROUP_REMOVE_PRIVS.partial,
             Some(Operation::Write),
             &group,
         )?;
 
-        let delete_stats = datastore.remove_backup_group(&ns, &group)?;
+        let (operation, items) = forget_items(&datastore, &ns, &group, None)?;
+        confirmation::check_confirmation(confirm_token.as_deref(), &auth_id, &operation, &items)?;
+
+        let delete_stats = datastore.remove_backup_group(&ns, &group, &auth_id)?;
         if !delete_stats.all_removed() {
             bail!("group only partially deleted due to protected snapshots");
         }
@@ -354,7 +445,7 @@ pub async fn list_snapshot_files(
 
         let info = BackupInfo::new(snapshot)?;
 
-        let (_manifest, files) = get_all_snapshot_files(&info)?;
+        let (_manifest, files) = info.all_files()?;
 
         Ok(files)
     })
@@ -375,6 +466,92 @@ pub async fn list_snapshot_files(
             },
         },
     },
+    returns: { type: ManifestInfo },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT or \
+            DATASTORE_READ for any or DATASTORE_BACKUP and being the owner of the group",
+    },
+)]
+/// Get the manifest of a snapshot, and check its key fingerprint against the key stored for the
+/// backup group, if any.
+pub async fn get_manifest(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_dir: pbs_api_types::BackupDir,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<ManifestInfo, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    tokio::task::spawn_blocking(move || {
+        let ns = ns.unwrap_or_default();
+
+        let datastore = check_privs_and_load_store(
+            &store,
+            &ns,
+            &auth_id,
+            PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_READ,
+            PRIV_DATASTORE_BACKUP,
+            Some(Operation::Read),
+            &backup_dir.group,
+        )?;
+
+        let snapshot = datastore.backup_dir(ns.clone(), backup_dir.clone())?;
+        let (manifest, files) = BackupInfo::new(snapshot)?.all_files()?;
+
+        let fingerprint = manifest.fingerprint()?;
+        let known_fingerprint = match datastore
+            .backup_group(ns, backup_dir.group.clone())
+            .wrapped_key()?
+        {
+            Some(key) => {
+                let key_config: KeyConfig = serde_json::from_str(&key)
+                    .map_err(|err| format_err!("invalid key config stored for group - {err}"))?;
+                key_config.fingerprint
+            }
+            None => None,
+        };
+
+        let signature_state = match (&manifest.signature, &known_fingerprint) {
+            (None, _) => ManifestSignatureState::Unsigned,
+            (Some(_), None) => ManifestSignatureState::UnknownKey,
+            (Some(_), Some(known)) if fingerprint.as_ref() == Some(known) => {
+                ManifestSignatureState::KeyMatches
+            }
+            (Some(_), Some(_)) => ManifestSignatureState::KeyMismatch,
+        };
+
+        Ok(ManifestInfo {
+            backup: backup_dir,
+            version: manifest.version(),
+            files,
+            signature: manifest.signature.clone(),
+            fingerprint,
+            known_fingerprint,
+            signature_state,
+        })
+    })
+    .await?
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_dir: {
+                type: pbs_api_types::BackupDir,
+                flatten: true,
+            },
+            "confirm-token": {
+                schema: CONFIRMATION_TOKEN_SCHEMA,
+                optional: true,
+            },
+        },
+    },
     access: {
         permission: &Permission::Anybody,
         description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_MODIFY for any\
@@ -386,6 +563,7 @@ pub async fn delete_snapshot(
     store: String,
     ns: Option<BackupNamespace>,
     backup_dir: pbs_api_types::BackupDir,
+    confirm_token: Option<String>,
     _info: &ApiMethod,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
@@ -398,12 +576,16 @@ pub async fn delete_snapshot(
             &store,
             &ns,
             &auth_id,
-            PRIV_DATASTORE_MODIFY,
-            PRIV_DATASTORE_PRUNE,
+            GROUP_REMOVE_PRIVS.full,
+            GROUP_REMOVE_PRIVS.partial,
             Some(Operation::Write),
             &backup_dir.group,
         )?;
 
+        let (operation, items) =
+            forget_items(&datastore, &ns, &backup_dir.group, Some(backup_dir.time))?;
+        confirmation::check_confirmation(confirm_token.as_deref(), &auth_id, &operation, &items)?;
+
         let snapshot = datastore.backup_dir(ns, backup_dir)?;
 
         snapshot.destroy(false)?;
@@ -430,6 +612,10 @@ pub async fn delete_snapshot(
                 optional: true,
                 schema: BACKUP_ID_SCHEMA,
             },
+            "verify-state": {
+                optional: true,
+                type: VerifyStateFilter,
+            },
         },
     },
     returns: pbs_api_types::ADMIN_DATASTORE_LIST_SNAPSHOTS_RETURN_TYPE,
@@ -445,6 +631,7 @@ pub async fn list_snapshots(
     ns: Option<BackupNamespace>,
     backup_type: Option<BackupType>,
     backup_id: Option<String>,
+    verify_state: Option<VerifyStateFilter>,
     _param: Value,
     _info: &ApiMethod,
     rpcenv: &mut dyn RpcEnvironment,
@@ -452,32 +639,73 @@ pub async fn list_snapshots(
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
     tokio::task::spawn_blocking(move || unsafe {
-        list_snapshots_blocking(store, ns, backup_type, backup_id, auth_id)
+        list_snapshots_blocking(store, ns, backup_type, backup_id, verify_state, auth_id)
     })
     .await
     .map_err(|err| format_err!("failed to await blocking task: {err}"))?
 }
 
+fn verify_state_matches(item: &SnapshotListItem, filter: Option<VerifyStateFilter>) -> bool {
+    let state = item.verification.as_ref().map(|v| v.state);
+    match filter {
+        None => true,
+        Some(VerifyStateFilter::Ok) => state == Some(VerifyState::Ok),
+        Some(VerifyStateFilter::Failed) => state == Some(VerifyState::Failed),
+        Some(VerifyStateFilter::None) => state.is_none(),
+    }
+}
+
 /// This must not run in a main worker thread as it potentially does tons of I/O.
 unsafe fn list_snapshots_blocking(
     store: String,
     ns: Option<BackupNamespace>,
     backup_type: Option<BackupType>,
     backup_id: Option<String>,
+    verify_state: Option<VerifyStateFilter>,
     auth_id: Authid,
 ) -> Result<Vec<SnapshotListItem>, Error> {
     let ns = ns.unwrap_or_default();
 
-    let list_all = !check_ns_privs_full(
-        &store,
-        &ns,
-        &auth_id,
-        PRIV_DATASTORE_AUDIT,
-        PRIV_DATASTORE_BACKUP,
-    )?;
+    let list_all = match (backup_type, &backup_id) {
+        // allow listing a single group with privileges granted on the group only
+        (Some(backup_type), Some(backup_id)) => !check_group_privs_full(
+            &store,
+            &ns,
+            &pbs_api_types::BackupGroup::new(backup_type, backup_id),
+            &auth_id,
+            PRIV_DATASTORE_AUDIT,
+            PRIV_DATASTORE_BACKUP,
+        )?,
+        _ => !check_ns_privs_full(
+            &store,
+            &ns,
+            &auth_id,
+            PRIV_DATASTORE_AUDIT,
+            PRIV_DATASTORE_BACKUP,
+        )?,
+    };
 
     let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
 
+    if let Some(index) = datastore.content_index() {
+        match index.list_snapshots(&ns, backup_type, backup_id.as_deref()) {
+            Ok(Some(items)) => {
+                return Ok(items
+                    .into_iter()
+                    .filter(|item| match &item.owner {
+                        Some(owner) => {
+                            list_all || datastore.check_backup_owner(owner, &auth_id).is_ok()
+                        }
+                        None => false,
+                    })
+                    .filter(|item| verify_state_matches(item, verify_state))
+                    .collect());
+            }
+            Ok(None) => (), // not built yet, scan the datastore instead
+            Err(err) => log::warn!("unable to query content index of '{store}' - {err}"),
+        }
+    }
+
     // FIXME: filter also owner before collecting, for doing that nicely the owner should move into
     // backup group and provide an error free (Err -> None) accessor
     let groups = match (backup_type, backup_id) {
@@ -489,7 +717,9 @@ unsafe fn list_snapshots_blocking(
             .iter_backup_type_ok(ns.clone(), backup_type)?
             .collect(),
         // FIXME: Recursion
-        (None, Some(backup_id)) => BackupType::iter()
+        (None, Some(backup_id)) => datastore
+            .list_backup_types(&ns)?
+            .into_iter()
             .filter_map(|backup_type| {
                 let group =
                     datastore.backup_group_from_parts(ns.clone(), backup_type, backup_id.clone());
@@ -500,78 +730,6 @@ unsafe fn list_snapshots_blocking(
         (None, None) => datastore.list_backup_groups(ns.clone())?,
     };
 
-    let info_to_snapshot_list_item = |group: &BackupGroup, owner, info: BackupInfo| {
-        let backup = pbs_api_types::BackupDir {
-            group: group.into(),
-            time: info.backup_dir.backup_time(),
-        };
-        let protected = info.backup_dir.is_protected();
-
-        match get_all_snapshot_files(&info) {
-            Ok((manifest, files)) => {
-                // extract the first line from notes
-                let comment: Option<String> = manifest.unprotected["notes"]
-                    .as_str()
-                    .and_then(|notes| notes.lines().next())
-                    .map(String::from);
-
-                let fingerprint = match manifest.fingerprint() {
-                    Ok(fp) => fp,
-                    Err(err) => {
-                        eprintln!("error parsing fingerprint: '{}'", err);
-                        None
-                    }
-                };
-
-                let verification = manifest.unprotected["verify_state"].clone();
-                let verification: Option<SnapshotVerifyState> =
-                    match serde_json::from_value(verification) {
-                        Ok(verify) => verify,
-                        Err(err) => {
-                            eprintln!("error parsing verification state : '{}'", err);
-                            None
-                        }
-                    };
-
-                let size = Some(files.iter().map(|x| x.size.unwrap_or(0)).sum());
-
-                SnapshotListItem {
-                    backup,
-                    comment,
-                    verification,
-                    fingerprint,
-                    files,
-                    size,
-                    owner,
-                    protected,
-                }
-            }
-            Err(err) => {
-                eprintln!("error during snapshot file listing: '{}'", err);
-                let files = info
-                    .files
-                    .into_iter()
-                    .map(|filename| BackupContent {
-                        filename,
-                        size: None,
-                        crypt_mode: None,
-                    })
-                    .collect();
-
-                SnapshotListItem {
-                    backup,
-                    comment: None,
-                    verification: None,
-                    fingerprint: None,
-                    files,
-                    size: None,
-                    owner,
-                    protected,
-                }
-            }
-        }
-    };
-
     groups.iter().try_fold(Vec::new(), |mut snapshots, group| {
         let owner = match group.get_owner() {
             Ok(auth_id) => auth_id,
@@ -586,7 +744,7 @@ unsafe fn list_snapshots_blocking(
             }
         };
 
-        if !list_all && check_backup_owner(&owner, &auth_id).is_err() {
+        if !list_all && datastore.check_backup_owner(&owner, &auth_id).is_err() {
             return Ok(snapshots);
         }
 
@@ -595,7 +753,8 @@ unsafe fn list_snapshots_blocking(
         snapshots.extend(
             group_backups
                 .into_iter()
-                .map(|info| info_to_snapshot_list_item(group, Some(owner.clone()), info)),
+                .map(|info| info.to_list_item(Some(owner.clone())))
+                .filter(|item| verify_state_matches(item, verify_state)),
         );
 
         Ok(snapshots)
@@ -609,6 +768,19 @@ async fn get_snapshots_count(
     let store = Arc::clone(store);
     let owner = owner.cloned();
     tokio::task::spawn_blocking(move || {
+        // the group listing still checks privileges and ownership, only the counting is skipped
+        let index_counts = match store.content_index().map(|index| index.snapshot_counts()) {
+            Some(Ok(counts)) => counts,
+            Some(Err(err)) => {
+                log::warn!(
+                    "unable to query content index of '{}' - {err}",
+                    store.name()
+                );
+                None
+            }
+            None => None,
+        };
+
         let root_ns = Default::default();
         ListAccessibleBackupGroups::new_with_privs(
             &store,
@@ -623,7 +795,13 @@ async fn get_snapshots_count(
                 Ok(group) => group,
                 Err(_) => return Ok(counts), // TODO: add this as error counts?
             };
-            let snapshot_count = group.list_backups()?.len() as u64;
+            let snapshot_count = match &index_counts {
+                Some(index_counts) => {
+                    let key = (group.backup_ns().clone(), group.group().clone());
+                    index_counts.get(&key).copied().unwrap_or(0)
+                }
+                None => group.list_backups()?.len() as u64,
+            };
 
             // only include groups with snapshots, counting/displaying empty groups can confuse
             if snapshot_count > 0 {
@@ -631,6 +809,7 @@ async fn get_snapshots_count(
                     BackupType::Ct => counts.ct.get_or_insert(Default::default()),
                     BackupType::Vm => counts.vm.get_or_insert(Default::default()),
                     BackupType::Host => counts.host.get_or_insert(Default::default()),
+                    BackupType::Custom(_) => counts.other.get_or_insert(Default::default()),
                 };
 
                 type_count.groups += 1;
@@ -827,7 +1006,7 @@ pub fn verify(
 
             if owner_check_required {
                 let owner = datastore.get_owner(dir.backup_ns(), dir.as_ref())?;
-                check_backup_owner(&owner, &auth_id)?;
+                datastore.check_backup_owner(&owner, &auth_id)?;
             }
 
             backup_dir = Some(dir);
@@ -845,7 +1024,7 @@ pub fn verify(
 
             if owner_check_required {
                 let owner = datastore.get_owner(&ns, &group)?;
-                check_backup_owner(&owner, &auth_id)?;
+                datastore.check_backup_owner(&owner, &auth_id)?;
             }
 
             backup_group = Some(datastore.backup_group(ns.clone(), group));
@@ -869,53 +1048,74 @@ pub fn verify(
         auth_id.to_string(),
         to_stdout,
         move |worker| {
-            let verify_worker = crate::backup::VerifyWorker::new(worker.clone(), datastore);
-            let failed_dirs = if let Some(backup_dir) = backup_dir {
-                let mut res = Vec::new();
-                if !verify_backup_dir(
-                    &verify_worker,
-                    &backup_dir,
-                    worker.upid().clone(),
-                    Some(&move |manifest| verify_filter(ignore_verified, outdated_after, manifest)),
-                )? {
-                    res.push(print_ns_and_snapshot(
-                        backup_dir.backup_ns(),
-                        backup_dir.as_ref(),
-                    ));
-                }
-                res
-            } else if let Some(backup_group) = backup_group {
-                verify_backup_group(
-                    &verify_worker,
-                    &backup_group,
-                    &mut StoreProgress::new(1),
-                    worker.upid(),
-                    Some(&move |manifest| verify_filter(ignore_verified, outdated_after, manifest)),
-                )?
-            } else {
-                let owner = if owner_check_required {
-                    Some(&auth_id)
+            let hook_env = vec![("PBS_DATASTORE", datastore.name().to_string())];
+            let upid = worker.upid().clone();
+            let result = (|| -> Result<(), Error> {
+                apply_task_io_priority(&worker, TaskIoClass::Verify);
+                enter_task_cgroup(&worker, TaskCgroup::Verify);
+                let verify_worker = crate::backup::VerifyWorker::new(worker.clone(), datastore);
+                let failed_dirs = if let Some(backup_dir) = backup_dir {
+                    let mut res = Vec::new();
+                    if !verify_backup_dir(
+                        &verify_worker,
+                        &backup_dir,
+                        worker.upid().clone(),
+                        Some(&move |manifest| {
+                            verify_filter(ignore_verified, outdated_after, manifest)
+                        }),
+                    )? {
+                        res.push(print_ns_and_snapshot(
+                            backup_dir.backup_ns(),
+                            backup_dir.as_ref(),
+                        ));
+                    }
+                    res
+                } else if let Some(backup_group) = backup_group {
+                    verify_backup_group(
+                        &verify_worker,
+                        &backup_group,
+                        &mut StoreProgress::new(1),
+                        worker.upid(),
+                        Some(&move |manifest| {
+                            verify_filter(ignore_verified, outdated_after, manifest)
+                        }),
+                    )?
                 } else {
-                    None
-                };
+                    let owner = if owner_check_required {
+                        Some(&auth_id)
+                    } else {
+                        None
+                    };
 
-                verify_all_backups(
-                    &verify_worker,
-                    worker.upid(),
-                    ns,
-                    max_depth,
-                    owner,
-                    Some(&move |manifest| verify_filter(ignore_verified, outdated_after, manifest)),
-                )?
-            };
-            if !failed_dirs.is_empty() {
-                task_log!(worker, "Failed to verify the following snapshots/groups:");
-                for dir in failed_dirs {
-                    task_log!(worker, "\t{}", dir);
+                    verify_all_backups(
+                        &verify_worker,
+                        worker.upid(),
+                        ns,
+                        max_depth,
+                        owner,
+                        Some(&move |manifest| {
+                            verify_filter(ignore_verified, outdated_after, manifest)
+                        }),
+                    )?
+                };
+                if !failed_dirs.is_empty() {
+                    task_log!(worker, "Failed to verify the following snapshots/groups:");
+                    for dir in failed_dirs {
+                        task_log!(worker, "\t{}", dir);
+                    }
+                    bail!("verification failed - please check the log for details");
                 }
-                bail!("verification failed - please check the log for details");
-            }
-            Ok(())
+                Ok(())
+            })();
+
+            crate::server::hooks::run_task_hooks(
+                HookEvent::VerifyFinish,
+                HookEvent::VerifyFailure,
+                &upid,
+                &result,
+                hook_env,
+            );
+            result
         },
     )?;
 
@@ -998,19 +1198,21 @@ pub fn prune(
         protected: bool,
         #[serde(skip_serializing_if = "Option::is_none")]
         ns: Option<BackupNamespace>,
+        #[serde(rename = "retention-tags", skip_serializing_if = "Vec::is_empty")]
+        retention_tags: Vec<String>,
     }
     let mut prune_result: Vec<PruneResult> = Vec::new();
 
     let list = group.list_backups()?;
 
-    let mut prune_info = compute_prune_info(list, &keep_options)?;
+    let mut prune_info = compute_prune_info_with_tags(list, &keep_options)?;
 
     prune_info.reverse(); // delete older snapshots first
 
     let keep_all = !keep_options.keeps_something();
 
     if dry_run {
-        for (info, mark) in prune_info {
+        for (info, mark, retention_tags) in prune_info {
             let keep = keep_all || mark.keep();
             let backup_dir = &info.backup_dir;
 
@@ -1021,6 +1223,7 @@ pub fn prune(
                 keep,
                 protected: mark.protected(),
                 ns: None,
+                retention_tags,
             };
             let prune_ns = backup_dir.backup_ns();
             if !prune_ns.is_root() {
@@ -1050,7 +1253,7 @@ pub fn prune(
             );
         }
 
-        for (info, mark) in prune_info {
+        for (info, mark, retention_tags) in prune_info {
             let keep = keep_all || mark.keep();
             let backup_dir = &info.backup_dir;
 
@@ -1058,10 +1261,24 @@ pub fn prune(
             let timestamp = backup_dir.backup_time_string();
             let group: &pbs_api_types::BackupGroup = backup_dir.as_ref();
 
-            let msg = format!("{}/{}/{timestamp} {mark}", group.ty, group.id);
+            let mut msg = format!("{}/{}/{timestamp} {mark}", group.ty, group.id);
+            if !retention_tags.is_empty() {
+                msg.push_str(&format!(" ({})", retention_tags.join(", ")));
+            }
 
             task_log!(worker, "{msg}");
 
+            if keep && !keep_all && info.is_finished() {
+                if let Err(err) = update_retention_tags(backup_dir, &retention_tags) {
+                    task_warn!(
+                        worker,
+                        "failed to update retention tags of {}/{}/{timestamp} - {err}",
+                        group.ty,
+                        group.id,
+                    );
+                }
+            }
+
             prune_result.push(PruneResult {
                 backup_type: group.ty,
                 backup_id: group.id.clone(),
@@ -1069,6 +1286,7 @@ pub fn prune(
                 keep,
                 protected: mark.protected(),
                 ns: None,
+                retention_tags,
             });
 
             if !keep {
@@ -1162,6 +1380,7 @@ pub fn prune_datastore(
         auth_id.to_string(),
         to_stdout,
         move |worker| {
+            apply_task_io_priority(&worker, TaskIoClass::Prune);
             crate::server::prune_datastore(worker, auth_id, prune_options, datastore, dry_run)
         },
     )?;
@@ -1170,11 +1389,20 @@ pub fn prune_datastore(
 }
 
 #[api(
+    protected: true,
     input: {
         properties: {
             store: {
                 schema: DATASTORE_SCHEMA,
             },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
         },
     },
     returns: {
@@ -1184,41 +1412,278 @@ pub fn prune_datastore(
         permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
     },
 )]
-/// Start garbage collection.
-pub fn start_garbage_collection(
+/// Upgrade the manifests of the snapshots to the current format version.
+pub fn upgrade_manifests(
     store: String,
-    _info: &ApiMethod,
+    ns: Option<BackupNamespace>,
+    max_depth: Option<usize>,
     rpcenv: &mut dyn RpcEnvironment,
-) -> Result<Value, Error> {
-    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+) -> Result<String, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
-    let job = Job::new("garbage_collection", &store)
-        .map_err(|_| format_err!("garbage collection already running"))?;
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+    let ns = ns.unwrap_or_default();
+    let worker_id = format!("{store}:{ns}");
 
     let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
 
-    let upid_str =
-        crate::server::do_garbage_collection_job(job, datastore, &auth_id, None, to_stdout)
-            .map_err(|err| {
-                format_err!(
-                    "unable to start garbage collection job on datastore {} - {}",
-                    store,
-                    err
-                )
-            })?;
+    let upid_str = WorkerTask::new_thread(
+        "manifestupgrade",
+        Some(worker_id),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (upgraded, failed) = crate::server::manifest_upgrade::upgrade_manifests(
+                &*worker, &datastore, ns, max_depth,
+            )?;
+            task_log!(worker, "upgraded {upgraded} manifests");
+            if failed > 0 {
+                bail!("failed to upgrade {failed} manifests");
+            }
+            Ok(())
+        },
+    )?;
 
-    Ok(json!(upid_str))
+    Ok(upid_str)
 }
 
 #[api(
+    protected: true,
     input: {
         properties: {
             store: {
                 schema: DATASTORE_SCHEMA,
             },
-        },
-    },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "min-size": {
+                type: HumanByte,
+                description: "Only report duplicate files of at least this size (default 16 MiB).",
+                optional: true,
+            },
+            limit: {
+                type: Integer,
+                description: "Number of duplicate files and groups to report.",
+                minimum: 1,
+                default: 10,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_READ, false),
+    },
+)]
+/// Analyze the storage efficiency of a datastore.
+///
+/// Reports the largest files stored in several places and the backup groups which deduplicate
+/// worst.
+pub fn analyze_storage(
+    store: String,
+    ns: Option<BackupNamespace>,
+    max_depth: Option<usize>,
+    min_size: Option<HumanByte>,
+    limit: Option<usize>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    let ns = ns.unwrap_or_default();
+    let worker_id = format!("{store}:{ns}");
+
+    let min_size = min_size
+        .map(|size| size.as_u64())
+        .unwrap_or(16 * 1024 * 1024);
+    let limit = limit.unwrap_or(10);
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "storageanalysis",
+        Some(worker_id),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            crate::server::storage_analysis::analyze_storage(
+                &*worker, &datastore, ns, max_depth, min_size, limit,
+            )
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Rebuild the content index of a datastore from its snapshots.
+pub fn rebuild_content_index(
+    store: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    if datastore.content_index().is_none() {
+        bail!("content index is not enabled for datastore '{store}'");
+    }
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "contentindex",
+        Some(store),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let index = datastore.content_index().unwrap();
+            let (groups, snapshots) = index.rebuild(&datastore, &*worker)?;
+            task_log!(worker, "indexed {snapshots} snapshots in {groups} groups");
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Add the snapshots which are not indexed yet to the filename indexes of all backup groups.
+pub fn update_filename_index(
+    store: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    if !datastore.filename_index() {
+        bail!("filename index is not enabled for datastore '{store}'");
+    }
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "filenameindex",
+        Some(store),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let mut snapshots = 0;
+            let mut failed = 0;
+            for ns in datastore.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+                for group in datastore.iter_backup_groups_ok(ns)? {
+                    worker.check_abort()?;
+                    match FilenameIndex::open(&group).and_then(|mut index| index.update()) {
+                        Ok(count) => snapshots += count,
+                        Err(err) => {
+                            task_warn!(
+                                worker,
+                                "unable to update filename index of {:?} - {err}",
+                                group.relative_group_path()
+                            );
+                            failed += 1;
+                        }
+                    }
+                }
+            }
+            task_log!(worker, "indexed {snapshots} snapshots");
+            if failed > 0 {
+                bail!("failed to update the filename index of {failed} groups");
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Start garbage collection.
+pub fn start_garbage_collection(
+    store: String,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let job = Job::new("garbage_collection", &store)
+        .map_err(|_| format_err!("garbage collection already running"))?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str =
+        crate::server::do_garbage_collection_job(job, datastore, &auth_id, None, to_stdout)
+            .map_err(|err| {
+                format_err!(
+                    "unable to start garbage collection job on datastore {} - {}",
+                    store,
+                    err
+                )
+            })?;
+
+    Ok(json!(upid_str))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
     returns: {
         type: GarbageCollectionJobStatus,
     },
@@ -1364,7 +1829,7 @@ pub const API_METHOD_DOWNLOAD_FILE: ApiMethod = ApiMethod::new(
 );
 
 pub fn download_file(
-    _parts: Parts,
+    parts: Parts,
     _req_body: Body,
     param: Value,
     _info: &ApiMethod,
@@ -1402,25 +1867,12 @@ pub fn download_file(
         path.push(backup_dir.relative_path());
         path.push(&file_name);
 
-        let file = tokio::fs::File::open(&path)
-            .await
-            .map_err(|err| http_err!(BAD_REQUEST, "File open failed: {}", err))?;
-
-        let payload =
-            tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new())
-                .map_ok(|bytes| bytes.freeze())
-                .map_err(move |err| {
-                    eprintln!("error during streaming of '{:?}' - {}", &path, err);
-                    err
-                });
-        let body = Body::wrap_stream(payload);
-
-        // fixme: set other headers ?
-        Ok(Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, "application/octet-stream")
-            .body(body)
-            .unwrap())
+        crate::api2::helpers::create_snapshot_file_download_response(
+            &datastore,
+            path,
+            &parts.headers,
+        )
+        .await
     }
     .boxed()
 }
@@ -1474,7 +1926,7 @@ pub fn download_file_decoded(
         let file_name = required_string_param(&param, "file-name")?.to_owned();
         let backup_dir = datastore.backup_dir(backup_ns.clone(), backup_dir_api.clone())?;
 
-        let (manifest, files) = read_backup_index(&backup_dir)?;
+        let (manifest, files) = backup_dir.read_backup_index()?;
         for file in files {
             if file.filename == file_name && file.crypt_mode == Some(CryptMode::Encrypt) {
                 bail!("cannot decode '{}' - is encrypted", file_name);
@@ -1530,19 +1982,16 @@ pub fn download_file_decoded(
                 )
             }
             "blob" => {
-                let file = std::fs::File::open(&path)
-                    .map_err(|err| http_err!(BAD_REQUEST, "File open failed: {}", err))?;
+                let data = read_at_rest(datastore.at_rest_crypt().as_deref(), &path)
+                    .map_err(|err| http_err!(BAD_REQUEST, "File read failed: {}", err))?;
 
                 // FIXME: load full blob to verify index checksum?
 
-                Body::wrap_stream(
-                    WrappedReaderStream::new(DataBlobReader::new(file, None)?).map_err(
-                        move |err| {
-                            eprintln!("error during streaming of '{:?}' - {}", path, err);
-                            err
-                        },
-                    ),
-                )
+                let reader = DataBlobReader::new(std::io::Cursor::new(data), None)?;
+                Body::wrap_stream(WrappedReaderStream::new(reader).map_err(move |err| {
+                    eprintln!("error during streaming of '{:?}' - {}", path, err);
+                    err
+                }))
             }
             extension => {
                 bail!("cannot download '{}' files", extension);
@@ -1685,29 +2134,7 @@ pub async fn catalog(
 
         let backup_dir = datastore.backup_dir(ns, backup_dir)?;
 
-        let file_name = CATALOG_NAME;
-
-        let (manifest, files) = read_backup_index(&backup_dir)?;
-        for file in files {
-            if file.filename == file_name && file.crypt_mode == Some(CryptMode::Encrypt) {
-                bail!("cannot decode '{}' - is encrypted", file_name);
-            }
-        }
-
-        let mut path = datastore.base_path();
-        path.push(backup_dir.relative_path());
-        path.push(file_name);
-
-        let index = DynamicIndexReader::open(&path)
-            .map_err(|err| format_err!("unable to read dynamic index '{:?}' - {}", &path, err))?;
-
-        let (csum, size) = index.compute_csum();
-        manifest.verify_file(file_name, &csum, size)?;
-
-        let chunk_reader = LocalChunkReader::new(datastore, None, CryptMode::None);
-        let reader = BufferedDynamicReader::new(index, chunk_reader);
-
-        let mut catalog_reader = CatalogReader::new(reader);
+        let mut catalog_reader = open_catalog(&backup_dir)?;
 
         let path = if filepath != "root" && filepath != "/" {
             base64::decode(filepath)?
@@ -1720,54 +2147,209 @@ pub async fn catalog(
     .await?
 }
 
-#[sortable]
-pub const API_METHOD_PXAR_FILE_DOWNLOAD: ApiMethod = ApiMethod::new(
-    &ApiHandler::AsyncHttp(&pxar_file_download),
-    &ObjectSchema::new(
-        "Download single file from pxar file of a backup snapshot. Only works if it's not encrypted.",
-        &sorted!([
-            ("store", false, &DATASTORE_SCHEMA),
-            ("ns", true, &BACKUP_NAMESPACE_SCHEMA),
-            ("backup-type", false, &BACKUP_TYPE_SCHEMA),
-            ("backup-id", false,  &BACKUP_ID_SCHEMA),
-            ("backup-time", false, &BACKUP_TIME_SCHEMA),
-            ("filepath", false, &StringSchema::new("Base64 encoded path").schema()),
-            ("tar", true, &BooleanSchema::new("Download as .tar.zst").schema()),
-        ]),
-    )
-).access(
-    Some(
-        "Requires on /datastore/{store}[/{namespace}] either DATASTORE_READ for any or \
-        DATASTORE_BACKUP and being the owner of the group",
-    ),
-    &Permission::Anybody,
-);
-
-fn get_local_pxar_reader(
-    datastore: Arc<DataStore>,
-    manifest: &BackupManifest,
-    backup_dir: &BackupDir,
-    pxar_name: &str,
-) -> Result<(LocalDynamicReadAt<LocalChunkReader>, u64), Error> {
-    let mut path = datastore.base_path();
-    path.push(backup_dir.relative_path());
-    path.push(pxar_name);
-
-    let index = DynamicIndexReader::open(&path)
-        .map_err(|err| format_err!("unable to read dynamic index '{:?}' - {}", &path, err))?;
-
-    let (csum, size) = index.compute_csum();
-    manifest.verify_file(pxar_name, &csum, size)?;
-
-    let chunk_reader = LocalChunkReader::new(datastore, None, CryptMode::None);
-    let reader = BufferedDynamicReader::new(index, chunk_reader);
-    let archive_size = reader.archive_size();
-
-    Ok((LocalDynamicReadAt::new(reader), archive_size))
-}
-
-pub fn pxar_file_download(
-    _parts: Parts,
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "backup-type": {
+                type: BackupType,
+                optional: true,
+            },
+            "backup-id": {
+                schema: BACKUP_ID_SCHEMA,
+                optional: true,
+            },
+            pattern: {
+                type: String,
+                description: "Glob pattern matched against the entry names, or against the \
+                    paths within the archives if it contains a slash.",
+            },
+            limit: {
+                type: Integer,
+                description: "Return at most this many entries.",
+                minimum: 1,
+                optional: true,
+            },
+        },
+    },
+    returns: pbs_api_types::ADMIN_DATASTORE_SEARCH_FILES_RETURN_TYPE,
+    access: {
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_READ for any or \
+            DATASTORE_BACKUP and being the owner of the group",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Search the catalogs of all snapshots of a namespace or backup group for matching entries.
+///
+/// Snapshots without catalog, or with an encrypted one, are skipped. If enabled, the filename
+/// indexes of the groups are used instead of reading all catalogs.
+#[allow(clippy::too_many_arguments)]
+pub async fn search_files(
+    store: String,
+    ns: Option<BackupNamespace>,
+    max_depth: Option<usize>,
+    backup_type: Option<BackupType>,
+    backup_id: Option<String>,
+    pattern: String,
+    limit: Option<usize>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<FileSearchResult>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let match_entry =
+        MatchEntry::parse_pattern(&pattern, PatternFlag::PATH_NAME, MatchType::Include)?;
+
+    tokio::task::spawn_blocking(move || {
+        let ns = ns.unwrap_or_default();
+        let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+
+        let groups = ListAccessibleBackupGroups::new_with_privs(
+            &datastore,
+            ns,
+            max_depth.unwrap_or(0),
+            Some(PRIV_DATASTORE_READ),
+            Some(PRIV_DATASTORE_BACKUP),
+            Some(&auth_id),
+        )?;
+
+        let mut result = Vec::new();
+        let mut push_result = |backup_dir: &BackupDir, item: CatalogMatch| {
+            let mut filepath = item.archive.as_bytes().to_vec();
+            filepath.extend(&item.path);
+            result.push(FileSearchResult {
+                ns: (!backup_dir.backup_ns().is_root()).then(|| backup_dir.backup_ns().clone()),
+                backup: backup_dir.dir().clone(),
+                archive: item.archive,
+                path: String::from_utf8_lossy(&item.path).to_string(),
+                filepath: base64::encode(filepath),
+                entry_type: item.entry_type.to_string(),
+                size: item.size,
+                mtime: item.mtime,
+            });
+        };
+
+        for group in groups {
+            let group = group?;
+            if backup_type
+                .map(|ty| ty != group.backup_type())
+                .unwrap_or(false)
+                || backup_id
+                    .as_ref()
+                    .map(|id| id != group.backup_id())
+                    .unwrap_or(false)
+            {
+                continue;
+            }
+
+            if datastore.filename_index() {
+                let indexed = FilenameIndex::open(&group).and_then(|mut index| {
+                    index.update()?;
+                    index.search(&pattern)
+                });
+                match indexed {
+                    Ok(list) => {
+                        for (backup_dir, item) in list {
+                            push_result(&backup_dir, item);
+                        }
+                        continue;
+                    }
+                    Err(err) => log::warn!(
+                        "unable to use filename index of {:?}, searching catalogs - {err}",
+                        group.relative_group_path()
+                    ),
+                }
+            }
+
+            for info in group.list_backups()? {
+                if !info.is_finished() || !info.files.iter().any(|file| file == CATALOG_NAME) {
+                    continue;
+                }
+                let backup_dir = info.backup_dir;
+
+                let mut catalog = match open_catalog(&backup_dir) {
+                    Ok(catalog) => catalog,
+                    Err(err) => {
+                        log::warn!(
+                            "skipping catalog of {:?} - {err}",
+                            backup_dir.relative_path()
+                        );
+                        continue;
+                    }
+                };
+
+                for item in search_catalog(&mut catalog, &match_entry)? {
+                    push_result(&backup_dir, item);
+                }
+            }
+        }
+
+        // stable, keeps the order within the snapshots
+        result.sort_by(|a, b| b.backup.time.cmp(&a.backup.time));
+        if let Some(limit) = limit {
+            result.truncate(limit);
+        }
+
+        Ok(result)
+    })
+    .await?
+}
+
+#[sortable]
+pub const API_METHOD_PXAR_FILE_DOWNLOAD: ApiMethod = ApiMethod::new(
+    &ApiHandler::AsyncHttp(&pxar_file_download),
+    &ObjectSchema::new(
+        "Download single file from pxar file of a backup snapshot. Only works if it's not encrypted.",
+        &sorted!([
+            ("store", false, &DATASTORE_SCHEMA),
+            ("ns", true, &BACKUP_NAMESPACE_SCHEMA),
+            ("backup-type", false, &BACKUP_TYPE_SCHEMA),
+            ("backup-id", false,  &BACKUP_ID_SCHEMA),
+            ("backup-time", false, &BACKUP_TIME_SCHEMA),
+            ("filepath", false, &StringSchema::new("Base64 encoded path").schema()),
+            ("tar", true, &BooleanSchema::new("Download as .tar.zst").schema()),
+        ]),
+    )
+).access(
+    Some(
+        "Requires on /datastore/{store}[/{namespace}] either DATASTORE_READ for any or \
+        DATASTORE_BACKUP and being the owner of the group",
+    ),
+    &Permission::Anybody,
+);
+
+fn get_local_pxar_reader(
+    datastore: Arc<DataStore>,
+    manifest: &BackupManifest,
+    backup_dir: &BackupDir,
+    pxar_name: &str,
+) -> Result<(LocalDynamicReadAt<LocalChunkReader>, u64), Error> {
+    let mut path = datastore.base_path();
+    path.push(backup_dir.relative_path());
+    path.push(pxar_name);
+
+    let index = DynamicIndexReader::open(&path)
+        .map_err(|err| format_err!("unable to read dynamic index '{:?}' - {}", &path, err))?;
+
+    let (csum, size) = index.compute_csum();
+    manifest.verify_file(pxar_name, &csum, size)?;
+
+    let chunk_reader = LocalChunkReader::new(datastore, None, CryptMode::None);
+    let reader = BufferedDynamicReader::new(index, chunk_reader);
+    let archive_size = reader.archive_size();
+
+    Ok((LocalDynamicReadAt::new(reader), archive_size))
+}
+
+pub fn pxar_file_download(
+    _parts: Parts,
     _req_body: Body,
     param: Value,
     _info: &ApiMethod,
@@ -1803,7 +2385,7 @@ pub fn pxar_file_download(
         let mut split = components.splitn(2, |c| *c == b'/');
         let pxar_name = std::str::from_utf8(split.next().unwrap())?;
         let file_path = split.next().unwrap_or(b"/");
-        let (manifest, files) = read_backup_index(&backup_dir)?;
+        let (manifest, files) = backup_dir.read_backup_index()?;
         for file in files {
             if file.filename == pxar_name && file.crypt_mode == Some(CryptMode::Encrypt) {
                 bail!("cannot decode '{}' - is encrypted", pxar_name);
@@ -1913,34 +2495,397 @@ pub fn get_rrd_stats(
         "write_bytes",
     ];
 
-    // we do not have io_ticks for zpools, so don't include them
-    match disk_manager.find_mounted_device(&datastore.base_path()) {
-        Ok(Some((fs_type, _, _))) if fs_type.as_str() == "zfs" => {}
-        _ => rrd_fields.push("io_ticks"),
-    };
+    // we do not have io_ticks for zpools, so don't include them
+    match disk_manager.find_mounted_device(&datastore.base_path()) {
+        Ok(Some((fs_type, _, _))) if fs_type.as_str() == "zfs" => {}
+        _ => rrd_fields.push("io_ticks"),
+    };
+
+    create_value_from_rrd(&format!("datastore/{}", store), &rrd_fields, timeframe, cf)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, true),
+    },
+)]
+/// Read datastore stats
+pub fn get_active_operations(store: String, _param: Value) -> Result<Value, Error> {
+    let active_operations = task_tracking::get_active_operations(&store)?;
+    Ok(json!({
+        "read": active_operations.read,
+        "write": active_operations.write,
+    }))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            since: {
+                type: i64,
+                description: "Start of the reported period (epoch), defaults to 7 days before 'until'.",
+                optional: true,
+            },
+            until: {
+                type: i64,
+                description: "End of the reported period (epoch), defaults to now.",
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: ComplianceReport,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires DATASTORE_AUDIT on /datastore/{store}[/{namespace}]. Only groups \
+            the user may audit are included, backup task counts only with DATASTORE_AUDIT on \
+            /datastore/{store}.",
+    },
+)]
+/// Generate a compliance report (backup success rate, verify coverage, missed backups and RPO
+/// violations) for a datastore or namespace.
+pub fn get_compliance_report(
+    store: String,
+    ns: Option<BackupNamespace>,
+    since: Option<i64>,
+    until: Option<i64>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<ComplianceReport, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    check_ns_privs(&store, &ns, &auth_id, PRIV_DATASTORE_AUDIT)?;
+
+    let until = until.unwrap_or_else(proxmox_time::epoch_i64);
+    let since = since.unwrap_or(until - 7 * 86400);
+    if since > until {
+        param_bail!("since", "'since' must not be after 'until'");
+    }
+
+    let access = ComplianceReportAccess::new(auth_id)?;
+    crate::server::generate_compliance_report(&store, Some(ns), since, until, Some(&access))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: ClientDefaults,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] any of DATASTORE_AUDIT, \
+            DATASTORE_BACKUP or DATASTORE_READ",
+    },
+)]
+/// Get the default options for backup clients of the datastore.
+pub fn get_client_defaults(
+    store: String,
+    ns: Option<BackupNamespace>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<ClientDefaults, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    check_ns_privs(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_BACKUP | PRIV_DATASTORE_READ,
+    )?;
+
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let store_config: DataStoreConfig = config.lookup("datastore", &store)?;
+
+    Ok(serde_json::from_value(
+        ClientDefaults::API_SCHEMA
+            .parse_property_string(store_config.client_defaults.as_deref().unwrap_or(""))?,
+    )?)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_group: {
+                type: pbs_api_types::BackupGroup,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT for any \
+            or DATASTORE_BACKUP and being the owner of the group",
+    },
+)]
+/// Get "notes" for a backup group
+pub fn get_group_notes(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_group: pbs_api_types::BackupGroup,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    let datastore = check_privs_and_load_store(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_AUDIT,
+        PRIV_DATASTORE_BACKUP,
+        Some(Operation::Read),
+        &backup_group,
+    )?;
+
+    let note_path = get_group_note_path(&datastore, &ns, &backup_group);
+    Ok(file_read_optional_string(note_path)?.unwrap_or_else(|| "".to_owned()))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_group: {
+                type: pbs_api_types::BackupGroup,
+                flatten: true,
+            },
+            notes: {
+                description: "A multiline text.",
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_MODIFY for any \
+            or DATASTORE_BACKUP and being the owner of the group",
+    },
+)]
+/// Set "notes" for a backup group
+pub fn set_group_notes(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_group: pbs_api_types::BackupGroup,
+    notes: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    let datastore = check_privs_and_load_store(
+        &store,
+        &ns,
+        &auth_id,
+        GROUP_MODIFY_PRIVS.full,
+        GROUP_MODIFY_PRIVS.partial,
+        Some(Operation::Write),
+        &backup_group,
+    )?;
+
+    let note_path = get_group_note_path(&datastore, &ns, &backup_group);
+    replace_file(note_path, notes.as_bytes(), CreateOptions::new(), false)?;
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_group: {
+                type: pbs_api_types::BackupGroup,
+                flatten: true,
+            },
+        },
+    },
+    returns: {
+        description: "The passphrase protected key config, as uploaded by the client.",
+        type: String,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_READ for any \
+            or DATASTORE_BACKUP and being the owner of the group",
+    },
+)]
+/// Get the passphrase protected encryption key stored for a backup group.
+pub fn get_group_key(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_group: pbs_api_types::BackupGroup,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    let datastore = check_privs_and_load_store(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_READ,
+        PRIV_DATASTORE_BACKUP,
+        Some(Operation::Read),
+        &backup_group,
+    )?;
+
+    match datastore
+        .backup_group(ns, backup_group.clone())
+        .wrapped_key()?
+    {
+        Some(key) => Ok(key),
+        None => http_bail!(NOT_FOUND, "no key stored for backup group {backup_group}"),
+    }
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_group: {
+                type: pbs_api_types::BackupGroup,
+                flatten: true,
+            },
+            key: {
+                description: "Passphrase protected key config, as created by \
+                    'proxmox-backup-client key create'.",
+                type: String,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_MODIFY for any \
+            or DATASTORE_BACKUP and being the owner of the group",
+    },
+)]
+/// Store the passphrase protected encryption key of a backup group.
+///
+/// The group is created if it does not exist yet, so that the key can be stored before the first
+/// backup. Keys without passphrase protection are refused, the server must never be able to
+/// unlock them.
+pub fn set_group_key(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_group: pbs_api_types::BackupGroup,
+    key: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    let key_config: KeyConfig =
+        serde_json::from_str(&key).map_err(|err| format_err!("invalid key config - {err}"))?;
+    if key_config.kdf.is_none() {
+        param_bail!(
+            "key",
+            "refusing to store a key without passphrase protection"
+        );
+    }
+
+    let limited = check_ns_privs_full(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_MODIFY,
+        PRIV_DATASTORE_BACKUP,
+    )?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+    let (owner, _group_guard) = datastore.create_locked_backup_group(
+        &ns,
+        &backup_group,
+        &datastore.new_group_owner(&auth_id),
+    )?;
+    if limited {
+        datastore.check_backup_owner(&owner, &auth_id)?;
+    }
 
-    create_value_from_rrd(&format!("datastore/{}", store), &rrd_fields, timeframe, cf)
+    datastore
+        .backup_group(ns, backup_group)
+        .set_wrapped_key(Some(&key))
 }
 
 #[api(
     input: {
         properties: {
-            store: {
-                schema: DATASTORE_SCHEMA,
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_group: {
+                type: pbs_api_types::BackupGroup,
+                flatten: true,
             },
         },
     },
     access: {
-        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, true),
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_MODIFY for any \
+            or DATASTORE_BACKUP and being the owner of the group",
     },
 )]
-/// Read datastore stats
-pub fn get_active_operations(store: String, _param: Value) -> Result<Value, Error> {
-    let active_operations = task_tracking::get_active_operations(&store)?;
-    Ok(json!({
-        "read": active_operations.read,
-        "write": active_operations.write,
-    }))
+/// Remove the encryption key stored for a backup group.
+pub fn delete_group_key(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_group: pbs_api_types::BackupGroup,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    let datastore = check_privs_and_load_store(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_MODIFY,
+        PRIV_DATASTORE_BACKUP,
+        Some(Operation::Write),
+        &backup_group,
+    )?;
+
+    datastore
+        .backup_group(ns, backup_group)
+        .set_wrapped_key(None)
 }
 
 #[api(
@@ -1957,19 +2902,23 @@ pub fn get_active_operations(store: String, _param: Value) -> Result<Value, Erro
             },
         },
     },
+    returns: {
+        schema: GROUP_MAX_SNAPSHOTS_SCHEMA,
+        optional: true,
+    },
     access: {
         permission: &Permission::Anybody,
         description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT for any \
             or DATASTORE_BACKUP and being the owner of the group",
     },
 )]
-/// Get "notes" for a backup group
-pub fn get_group_notes(
+/// Get the maximum number of snapshots of a backup group.
+pub fn get_group_max_snapshots(
     store: String,
     ns: Option<BackupNamespace>,
     backup_group: pbs_api_types::BackupGroup,
     rpcenv: &mut dyn RpcEnvironment,
-) -> Result<String, Error> {
+) -> Result<Option<usize>, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let ns = ns.unwrap_or_default();
 
@@ -1983,8 +2932,7 @@ pub fn get_group_notes(
         &backup_group,
     )?;
 
-    let note_path = get_group_note_path(&datastore, &ns, &backup_group);
-    Ok(file_read_optional_string(note_path)?.unwrap_or_else(|| "".to_owned()))
+    datastore.backup_group(ns, backup_group).max_snapshots()
 }
 
 #[api(
@@ -1999,23 +2947,26 @@ pub fn get_group_notes(
                 type: pbs_api_types::BackupGroup,
                 flatten: true,
             },
-            notes: {
-                description: "A multiline text.",
+            "max-snapshots": {
+                schema: GROUP_MAX_SNAPSHOTS_SCHEMA,
+                optional: true,
             },
         },
     },
     access: {
         permission: &Permission::Anybody,
         description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_MODIFY for any \
-            or DATASTORE_BACKUP and being the owner of the group",
+            or DATASTORE_PRUNE and being the owner of the group",
     },
 )]
-/// Set "notes" for a backup group
-pub fn set_group_notes(
+/// Set or clear the maximum number of snapshots of a backup group.
+///
+/// The limit is enforced when the next backup of the group finishes.
+pub fn set_group_max_snapshots(
     store: String,
     ns: Option<BackupNamespace>,
     backup_group: pbs_api_types::BackupGroup,
-    notes: String,
+    max_snapshots: Option<usize>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<(), Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
@@ -2026,15 +2977,17 @@ pub fn set_group_notes(
         &ns,
         &auth_id,
         PRIV_DATASTORE_MODIFY,
-        PRIV_DATASTORE_BACKUP,
+        PRIV_DATASTORE_PRUNE,
         Some(Operation::Write),
         &backup_group,
     )?;
 
-    let note_path = get_group_note_path(&datastore, &ns, &backup_group);
-    replace_file(note_path, notes.as_bytes(), CreateOptions::new(), false)?;
+    let group = datastore.backup_group(ns, backup_group);
+    if !group.exists() {
+        return Err(http_err!(NOT_FOUND, "backup group does not exist"));
+    }
 
-    Ok(())
+    group.set_max_snapshots(max_snapshots)
 }
 
 #[api(
@@ -2124,8 +3077,8 @@ pub fn set_notes(
         &store,
         &ns,
         &auth_id,
-        PRIV_DATASTORE_MODIFY,
-        PRIV_DATASTORE_BACKUP,
+        GROUP_MODIFY_PRIVS.full,
+        GROUP_MODIFY_PRIVS.partial,
         Some(Operation::Write),
         &backup_dir.group,
     )?;
@@ -2224,8 +3177,8 @@ pub async fn set_protection(
             &store,
             &ns,
             &auth_id,
-            PRIV_DATASTORE_MODIFY,
-            PRIV_DATASTORE_BACKUP,
+            GROUP_MODIFY_PRIVS.full,
+            GROUP_MODIFY_PRIVS.partial,
             Some(Operation::Write),
             &backup_dir.group,
         )?;
@@ -2340,6 +3293,259 @@ pub async fn set_backup_owner(
     .await?
 }
 
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_dir: {
+                type: pbs_api_types::BackupDir,
+                flatten: true,
+            },
+            "target-ns": {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "target-type": {
+                type: BackupType,
+                optional: true,
+            },
+            "target-id": {
+                schema: BACKUP_ID_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Datastore.Read on the source, or Datastore.Backup for owned groups, and \
+            Datastore.Backup on the target group, which needs to be owned by the caller if it \
+            already exists",
+    },
+)]
+/// Create a copy of a snapshot in another backup group, referencing the same chunks.
+///
+/// The clone keeps the backup time of the source and is owned by the caller. The target
+/// namespace defaults to the one of the source, the target backup type to the one of the source.
+pub fn clone_snapshot(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_dir: pbs_api_types::BackupDir,
+    target_ns: Option<BackupNamespace>,
+    target_type: Option<BackupType>,
+    target_id: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+    let target_ns = target_ns.unwrap_or_else(|| ns.clone());
+    let target_group =
+        pbs_api_types::BackupGroup::new(target_type.unwrap_or(backup_dir.group.ty), target_id);
+
+    let datastore = check_privs_and_load_store(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_READ,
+        PRIV_DATASTORE_BACKUP,
+        Some(Operation::Write),
+        &backup_dir.group,
+    )?;
+
+    let user_info = CachedUserInfo::new()?;
+    let target_privs = lookup_group_privs(&user_info, &auth_id, &store, &target_ns, &target_group);
+    if target_privs & PRIV_DATASTORE_BACKUP == 0 {
+        http_bail!(
+            FORBIDDEN,
+            "permission check failed - missing Datastore.Backup on /{}",
+            target_ns.group_acl_path(&store, &target_group).join("/")
+        );
+    }
+
+    crate::server::failover::check_backup_allowed()?;
+    check_backup_type_registered(target_group.ty)?;
+
+    if !datastore.namespace_path(&target_ns).exists() {
+        http_bail!(NOT_FOUND, "namespace not found");
+    }
+
+    let source = datastore.backup_dir(ns, backup_dir)?;
+    let target_dir = pbs_api_types::BackupDir::from((target_group, source.backup_time()));
+    let target = datastore.backup_dir(target_ns, target_dir)?;
+
+    if source.backup_ns() == target.backup_ns() && source.group() == target.group() {
+        bail!("cannot clone a snapshot into its own backup group");
+    }
+
+    let worker_id = format!(
+        "{store}:{}",
+        print_ns_and_snapshot(source.backup_ns(), source.dir())
+    );
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "clone-snapshot",
+        Some(worker_id),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let _source_guard = lock_dir_noblock_shared(
+                &source.full_path(),
+                "snapshot",
+                "source snapshot is locked by another operation",
+            )?;
+
+            let (owner, _group_guard) = datastore.create_locked_backup_group(
+                target.backup_ns(),
+                target.group(),
+                &datastore.new_group_owner(&auth_id),
+            )?;
+            datastore.check_backup_owner(&owner, &auth_id)?;
+
+            if let Some(last) =
+                datastore.last_successful_backup(target.backup_ns(), target.group())?
+            {
+                if last >= target.backup_time() {
+                    bail!(
+                        "backup time {} of the source is not newer than the last snapshot of \
+                        the target group",
+                        source.backup_time_string(),
+                    );
+                }
+            }
+
+            let (_path, is_new, _snap_guard) =
+                datastore.create_locked_backup_dir(target.backup_ns(), target.as_ref())?;
+            if !is_new {
+                bail!("snapshot {} already exists", target.dir());
+            }
+
+            task_log!(
+                worker,
+                "cloning {} to {}",
+                print_ns_and_snapshot(source.backup_ns(), source.dir()),
+                print_ns_and_snapshot(target.backup_ns(), target.dir()),
+            );
+
+            if let Err(err) =
+                crate::server::snapshot_clone::clone_snapshot(&*worker, &source, &target)
+            {
+                if let Err(cleanup_err) =
+                    datastore.remove_backup_dir(target.backup_ns(), target.as_ref(), true)
+                {
+                    task_warn!(worker, "cleanup error - {cleanup_err}");
+                }
+                return Err(err);
+            }
+
+            task_log!(worker, "clone finished");
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            group: {
+                type: pbs_api_types::BackupGroup,
+                flatten: true,
+            },
+            patterns: {
+                type: Array,
+                description: "Paths or match patterns of the entries to remove.",
+                items: {
+                    type: String,
+                    description: "Path or match pattern.",
+                },
+            },
+            "dry-run": {
+                type: bool,
+                optional: true,
+                default: false,
+                description: "Only log the entries which would be removed.",
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires DATASTORE_MODIFY on /datastore/{store}[/{namespace}]",
+    },
+)]
+/// Remove files from the pxar archives of all snapshots of a backup group.
+///
+/// The archives and catalogs are rewritten without the matching entries, also in protected
+/// snapshots. Each removed path is logged by the task. Snapshots with a signed manifest cannot
+/// be rewritten by the server and are reported as failed.
+pub fn redact_group(
+    store: String,
+    ns: Option<BackupNamespace>,
+    group: pbs_api_types::BackupGroup,
+    patterns: Vec<String>,
+    dry_run: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    let datastore = check_privs_and_load_store(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_MODIFY,
+        0,
+        Some(Operation::Write),
+        &group,
+    )?;
+
+    if patterns.is_empty() {
+        bail!("no patterns given");
+    }
+    let patterns = patterns
+        .iter()
+        .map(|pattern| {
+            MatchEntry::parse_pattern(pattern, PatternFlag::PATH_NAME, MatchType::Include)
+                .map_err(|err| format_err!("invalid pattern '{pattern}' - {err}"))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let worker_id = format!("{store}:{ns}:{group}");
+    let group = datastore.backup_group(ns, group);
+    if !group.exists() {
+        http_bail!(NOT_FOUND, "backup group not found");
+    }
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "redact",
+        Some(worker_id),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let upid = worker.upid().to_string();
+            crate::server::redact::redact_group(&*worker, &upid, &group, &patterns, dry_run)
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
 #[sortable]
 const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
     (
@@ -2351,6 +3557,26 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
         "change-owner",
         &Router::new().post(&API_METHOD_SET_BACKUP_OWNER),
     ),
+    (
+        "chunk-quarantine",
+        &crate::api2::admin::chunk_quarantine::ROUTER,
+    ),
+    (
+        "client-defaults",
+        &Router::new().get(&API_METHOD_GET_CLIENT_DEFAULTS),
+    ),
+    (
+        "clone-snapshot",
+        &Router::new().post(&API_METHOD_CLONE_SNAPSHOT),
+    ),
+    (
+        "compliance-report",
+        &Router::new().get(&API_METHOD_GET_COMPLIANCE_REPORT),
+    ),
+    (
+        "content-index",
+        &Router::new().post(&API_METHOD_REBUILD_CONTENT_INDEX),
+    ),
     (
         "download",
         &Router::new().download(&API_METHOD_DOWNLOAD_FILE),
@@ -2359,25 +3585,52 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
         "download-decoded",
         &Router::new().download(&API_METHOD_DOWNLOAD_FILE_DECODED),
     ),
+    ("file-search", &Router::new().get(&API_METHOD_SEARCH_FILES)),
+    (
+        "filename-index",
+        &Router::new().post(&API_METHOD_UPDATE_FILENAME_INDEX),
+    ),
     ("files", &Router::new().get(&API_METHOD_LIST_SNAPSHOT_FILES)),
+    (
+        "forget-preview",
+        &Router::new().get(&API_METHOD_FORGET_PREVIEW),
+    ),
     (
         "gc",
         &Router::new()
             .get(&API_METHOD_GARBAGE_COLLECTION_STATUS)
             .post(&API_METHOD_START_GARBAGE_COLLECTION),
     ),
+    (
+        "group-key",
+        &Router::new()
+            .get(&API_METHOD_GET_GROUP_KEY)
+            .put(&API_METHOD_SET_GROUP_KEY)
+            .delete(&API_METHOD_DELETE_GROUP_KEY),
+    ),
+    (
+        "group-max-snapshots",
+        &Router::new()
+            .get(&API_METHOD_GET_GROUP_MAX_SNAPSHOTS)
+            .put(&API_METHOD_SET_GROUP_MAX_SNAPSHOTS),
+    ),
     (
         "group-notes",
         &Router::new()
             .get(&API_METHOD_GET_GROUP_NOTES)
             .put(&API_METHOD_SET_GROUP_NOTES),
     ),
+    (
+        "group-tombstones",
+        &crate::api2::admin::group_tombstones::ROUTER,
+    ),
     (
         "groups",
         &Router::new()
             .get(&API_METHOD_LIST_GROUPS)
             .delete(&API_METHOD_DELETE_GROUP),
     ),
+    ("manifest", &Router::new().get(&API_METHOD_GET_MANIFEST)),
     (
         "namespace",
         // FIXME: move into datastore:: sub-module?!
@@ -2404,6 +3657,7 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
         "pxar-file-download",
         &Router::new().download(&API_METHOD_PXAR_FILE_DOWNLOAD),
     ),
+    ("redact", &Router::new().post(&API_METHOD_REDACT_GROUP)),
     ("rrd", &Router::new().get(&API_METHOD_GET_RRD_STATS)),
     (
         "snapshots",
@@ -2412,6 +3666,18 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
             .delete(&API_METHOD_DELETE_SNAPSHOT),
     ),
     ("status", &Router::new().get(&API_METHOD_STATUS)),
+    (
+        "storage-analysis",
+        &Router::new().post(&API_METHOD_ANALYZE_STORAGE),
+    ),
+    (
+        "unfinished-snapshots",
+        &crate::api2::admin::unfinished_snapshots::ROUTER,
+    ),
+    (
+        "upgrade-manifests",
+        &Router::new().post(&API_METHOD_UPGRADE_MANIFESTS),
+    ),
     (
         "upload-backup-log",
         &Router::new().upload(&API_METHOD_UPLOAD_BACKUP_LOG),
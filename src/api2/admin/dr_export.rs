@@ -0,0 +1,31 @@
+//! Disaster Recovery Metadata Export
+
+use anyhow::Error;
+
+use proxmox_router::{ApiMethod, Permission, Router, RpcEnvironment, RpcEnvironmentType};
+use proxmox_schema::api;
+
+use pbs_api_types::{Authid, PRIV_SYS_MODIFY};
+
+use crate::server::{do_dr_export_job, jobstate::Job};
+
+#[api(
+    protected: true,
+    access: {
+        permission: &Permission::Privilege(&["system"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Run the disaster recovery metadata export now.
+pub fn run_dr_export(_info: &ApiMethod, rpcenv: &mut dyn RpcEnvironment) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let job = Job::new("drexport", "dr-export")?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = do_dr_export_job(job, &auth_id, to_stdout)?;
+
+    Ok(upid_str)
+}
+
+pub const ROUTER: Router = Router::new().post(&API_METHOD_RUN_DR_EXPORT);
@@ -0,0 +1,108 @@
+//! Expected Backup Monitoring
+
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{ApiMethod, Permission, Router, RpcEnvironment, RpcEnvironmentType};
+use proxmox_schema::api;
+
+use pbs_api_types::{
+    Authid, ExpectedBackupConfig, ExpectedBackupGroupStatus, DATASTORE_SCHEMA,
+    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY, PRIV_SYS_MODIFY,
+};
+use pbs_config::expected_backup;
+use pbs_config::CachedUserInfo;
+
+use crate::server::{check_expected_backups, do_expected_backup_check, jobstate::Job};
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            "overdue-only": {
+                description: "Only list groups which missed their backup window.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    returns: {
+        description: "Status of all monitored groups (filtered by access).",
+        type: Array,
+        items: { type: ExpectedBackupGroupStatus },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Audit or Datastore.Modify on datastore.",
+    },
+)]
+/// List the status of all groups monitored by expected backup entries.
+pub fn list_expected_backup_status(
+    store: Option<String>,
+    overdue_only: bool,
+    _param: Value,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<ExpectedBackupGroupStatus>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let required_privs = PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_MODIFY;
+
+    let (config, _digest) = expected_backup::config()?;
+
+    let now = proxmox_time::epoch_i64();
+
+    let mut list = Vec::new();
+
+    for entry in config.convert_to_typed_array::<ExpectedBackupConfig>("expected")? {
+        if entry.disable {
+            continue;
+        }
+        if let Some(store) = &store {
+            if &entry.store != store {
+                continue;
+            }
+        }
+        let privs = user_info.lookup_privs(&auth_id, &entry.acl_path());
+        if privs & required_privs == 0 {
+            continue;
+        }
+
+        list.extend(
+            check_expected_backups(&entry, now)?
+                .into_iter()
+                .filter(|status| !overdue_only || status.overdue),
+        );
+    }
+
+    Ok(list)
+}
+
+#[api(
+    access: {
+        permission: &Permission::Privilege(&["system"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Check all expected backups now and send notifications for missed backups.
+pub fn run_expected_backup_check(
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let job = Job::new("expectedbackupcheck", "expected-backups")?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = do_expected_backup_check(job, &auth_id, to_stdout)?;
+
+    Ok(upid_str)
+}
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_EXPECTED_BACKUP_STATUS)
+    .post(&API_METHOD_RUN_EXPECTED_BACKUP_CHECK);
@@ -0,0 +1,88 @@
+//! Active-Standby Failover
+
+use anyhow::Error;
+
+use proxmox_router::{
+    list_subdirs_api_method, Permission, Router, RpcEnvironment, RpcEnvironmentType, SubdirMap,
+};
+use proxmox_schema::api;
+use proxmox_sortable_macro::sortable;
+
+use pbs_api_types::{Authid, FailoverStatus, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY, UPID_SCHEMA};
+
+use crate::server::failover;
+
+#[api(
+    returns: {
+        type: FailoverStatus,
+    },
+    access: {
+        permission: &Permission::Privilege(&["system"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// Get the failover role of this node.
+pub fn get_failover_status() -> Result<FailoverStatus, Error> {
+    failover::failover_status()
+}
+
+#[api(
+    input: {
+        properties: {
+            force: {
+                description: "Promote even if the peer is not reachable or the catch-up sync \
+                    failed.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["system"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Promote this node to the active node, demoting the peer and running a catch-up sync first.
+pub fn promote(force: bool, rpcenv: &mut dyn RpcEnvironment) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    failover::do_failover_promote(&auth_id, force, to_stdout)
+}
+
+#[api(
+    input: {
+        properties: {
+            epoch: {
+                description: "Failover epoch of the promoted peer.",
+                type: Integer,
+                minimum: 0,
+                optional: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Demote this node to standby, refusing new backups.
+pub fn demote(epoch: Option<u64>) -> Result<(), Error> {
+    failover::demote(epoch)
+}
+
+#[sortable]
+const SUBDIRS: SubdirMap = &sorted!([
+    ("demote", &Router::new().post(&API_METHOD_DEMOTE)),
+    ("promote", &Router::new().post(&API_METHOD_PROMOTE)),
+    (
+        "status",
+        &Router::new().get(&API_METHOD_GET_FAILOVER_STATUS)
+    ),
+]);
+
+pub const ROUTER: Router = Router::new()
+    .get(&list_subdirs_api_method!(SUBDIRS))
+    .subdirs(SUBDIRS);
@@ -0,0 +1,98 @@
+//! Tombstones of removed backup groups
+
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router};
+use proxmox_schema::api;
+
+use pbs_api_types::{
+    BackupNamespace, BackupType, GroupTombstone, Operation, BACKUP_ID_SCHEMA, DATASTORE_SCHEMA,
+    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY,
+};
+use pbs_datastore::DataStore;
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "List of group tombstones.",
+        type: Array,
+        items: { type: GroupTombstone },
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, false),
+    },
+)]
+/// List the tombstones of recently removed backup groups.
+pub fn list_tombstones(store: String) -> Result<Vec<GroupTombstone>, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    datastore.group_tombstones().list()
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "backup-type": {
+                type: BackupType,
+                optional: true,
+            },
+            "backup-id": {
+                schema: BACKUP_ID_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "Number of purged tombstones.",
+        type: Integer,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Purge group tombstones, which allows reusing the group names right away.
+///
+/// Without filter, all tombstones of the datastore are purged.
+pub fn purge_tombstones(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_type: Option<BackupType>,
+    backup_id: Option<String>,
+) -> Result<usize, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+    datastore.group_tombstones().purge(|tombstone| {
+        if let Some(ns) = &ns {
+            if tombstone.ns.clone().unwrap_or_default() != *ns {
+                return false;
+            }
+        }
+        if let Some(ty) = backup_type {
+            if tombstone.group.ty != ty {
+                return false;
+            }
+        }
+        if let Some(id) = &backup_id {
+            if tombstone.group.id != *id {
+                return false;
+            }
+        }
+        true
+    })
+}
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_TOMBSTONES)
+    .delete(&API_METHOD_PURGE_TOMBSTONES);
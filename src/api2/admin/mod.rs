@@ -4,21 +4,34 @@ use proxmox_router::list_subdirs_api_method;
 use proxmox_router::{Router, SubdirMap};
 use proxmox_sortable_macro::sortable;
 
+pub mod chunk_quarantine;
 pub mod datastore;
+pub mod dr_export;
+pub mod expected_backup;
+pub mod failover;
 pub mod gc;
+pub mod group_tombstones;
 pub mod metrics;
 pub mod namespace;
 pub mod prune;
+pub mod schedule_preview;
+pub mod sessions;
 pub mod sync;
 pub mod traffic_control;
+pub mod unfinished_snapshots;
 pub mod verify;
 
 #[sortable]
 const SUBDIRS: SubdirMap = &sorted!([
     ("datastore", &datastore::ROUTER),
+    ("dr-export", &dr_export::ROUTER),
+    ("expected-backup", &expected_backup::ROUTER),
+    ("failover", &failover::ROUTER),
     ("metrics", &metrics::ROUTER),
     ("prune", &prune::ROUTER),
     ("gc", &gc::ROUTER),
+    ("schedule-preview", &schedule_preview::ROUTER),
+    ("sessions", &sessions::ROUTER),
     ("sync", &sync::ROUTER),
     ("traffic-control", &traffic_control::ROUTER),
     ("verify", &verify::ROUTER),
@@ -158,7 +158,7 @@ pub fn delete_namespace(
 
     let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
 
-    if !datastore.remove_namespace_recursive(&ns, delete_groups)? {
+    if !datastore.remove_namespace_recursive(&ns, delete_groups, &auth_id)? {
         if delete_groups {
             bail!("group only partially deleted due to protected snapshots");
         } else {
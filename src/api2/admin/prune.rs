@@ -18,7 +18,7 @@ use pbs_config::CachedUserInfo;
 
 use crate::server::{
     do_prune_job,
-    jobstate::{compute_schedule_status, Job, JobState},
+    jobstate::{add_run_history, compute_schedule_status, Job, JobState},
 };
 
 #[api(
@@ -77,6 +77,7 @@ pub fn list_prune_jobs(
             .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
 
         let mut status = compute_schedule_status(&last_state, Some(&job.schedule))?;
+        add_run_history(&mut status, "prunejob", &job.id);
         if job.disable {
             status.next_run = None;
         }
@@ -0,0 +1,145 @@
+//! Preview the next run times of a calendar event or job schedule.
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::api;
+use proxmox_time::CalendarEvent;
+
+use pbs_api_types::{
+    Authid, DataStoreConfig, PruneJobConfig, ScheduledJobType, SyncJobConfig, TapeBackupJobConfig,
+    VerificationJobConfig, JOB_ID_SCHEMA, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY,
+};
+use pbs_config::CachedUserInfo;
+
+use crate::server::jobstate;
+
+/// Returns the schedule and the datastore of a job.
+fn job_schedule(job_type: ScheduledJobType, id: &str) -> Result<(Option<String>, String), Error> {
+    Ok(match job_type {
+        ScheduledJobType::Sync => {
+            let (config, _digest) = pbs_config::sync::config()?;
+            let job: SyncJobConfig = config.lookup("sync", id)?;
+            (job.schedule, job.store)
+        }
+        ScheduledJobType::Verify => {
+            let (config, _digest) = pbs_config::verify::config()?;
+            let job: VerificationJobConfig = config.lookup("verification", id)?;
+            (job.schedule, job.store)
+        }
+        ScheduledJobType::Prune => {
+            let (config, _digest) = pbs_config::prune::config()?;
+            let job: PruneJobConfig = config.lookup("prune", id)?;
+            (Some(job.schedule), job.store)
+        }
+        ScheduledJobType::TapeBackup => {
+            let (config, _digest) = pbs_config::tape_job::config()?;
+            let job: TapeBackupJobConfig = config.lookup("backup", id)?;
+            (job.schedule, job.setup.store)
+        }
+        ScheduledJobType::GarbageCollection => {
+            let (config, _digest) = pbs_config::datastore::config()?;
+            let store: DataStoreConfig = config.lookup("datastore", id)?;
+            (store.gc_schedule, store.name)
+        }
+    })
+}
+
+#[api(
+    input: {
+        properties: {
+            schedule: {
+                description: "Calendar event to preview.",
+                type: String,
+                optional: true,
+            },
+            "job-type": {
+                type: ScheduledJobType,
+                optional: true,
+            },
+            id: {
+                schema: JOB_ID_SCHEMA,
+                optional: true,
+            },
+            start: {
+                description: "Compute the run times after this time (UNIX epoch). Defaults to \
+                    the last run of the job, or the current time.",
+                type: Integer,
+                optional: true,
+            },
+            count: {
+                description: "Number of run times to compute.",
+                type: Integer,
+                minimum: 1,
+                maximum: 100,
+                default: 10,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "The next run times (UNIX epoch).",
+        type: Array,
+        items: {
+            description: "Run time (UNIX epoch).",
+            type: Integer,
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Previewing the schedule of a job requires Datastore.Audit or \
+            Datastore.Modify on the job's datastore.",
+    },
+)]
+/// Compute the next run times of a calendar event, or of the schedule of a job.
+///
+/// For a job, the preview starts at its last run, so the first time returned is the next
+/// time the scheduler starts the job.
+pub fn schedule_preview(
+    schedule: Option<String>,
+    job_type: Option<ScheduledJobType>,
+    id: Option<String>,
+    start: Option<i64>,
+    count: Option<u64>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<i64>, Error> {
+    let (schedule, last) = match (schedule, job_type, id) {
+        (Some(schedule), None, None) => (schedule, proxmox_time::epoch_i64()),
+        (None, Some(job_type), Some(id)) => {
+            let (schedule, store) = job_schedule(job_type, &id)?;
+
+            let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+            let user_info = CachedUserInfo::new()?;
+            let privs = user_info.lookup_privs(&auth_id, &["datastore", &store]);
+            if privs & (PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_MODIFY) == 0 {
+                http_bail!(FORBIDDEN, "permission check failed");
+            }
+
+            let schedule = schedule.ok_or_else(|| format_err!("job '{id}' has no schedule"))?;
+            let last = jobstate::last_run_time(job_type.jobstate_type(), &id)
+                .unwrap_or_else(|_| proxmox_time::epoch_i64());
+            (schedule, last)
+        }
+        _ => bail!("either 'schedule', or 'job-type' and 'id' are required"),
+    };
+
+    let event: CalendarEvent = schedule
+        .parse()
+        .map_err(|err| format_err!("invalid schedule '{schedule}' - {err}"))?;
+
+    let mut last = start.unwrap_or(last);
+    let mut result = Vec::new();
+    for _ in 0..count.unwrap_or(10) {
+        match event.compute_next_event(last)? {
+            Some(next) => {
+                result.push(next);
+                last = next;
+            }
+            None => break,
+        }
+    }
+
+    Ok(result)
+}
+
+pub const ROUTER: Router = Router::new().get(&API_METHOD_SCHEDULE_PREVIEW);
@@ -0,0 +1,110 @@
+//! Active backup and reader sessions
+
+use anyhow::Error;
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::{
+    ActiveSession, Authid, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY, PRIV_SYS_AUDIT,
+    PRIV_SYS_MODIFY, UPID, UPID_SCHEMA,
+};
+use pbs_config::CachedUserInfo;
+
+use crate::server::session_limits::{active_sessions, lookup_active_session};
+
+// the owner of a session, or the user of the token owning it
+fn is_session_owner(auth_id: &Authid, session: &ActiveSession) -> bool {
+    &session.auth_id == auth_id
+        || (session.auth_id.is_token() && &Authid::from(session.auth_id.user().clone()) == auth_id)
+}
+
+fn check_session_privs(
+    user_info: &CachedUserInfo,
+    auth_id: &Authid,
+    session: &ActiveSession,
+    sys_priv: u64,
+    store_priv: u64,
+) -> bool {
+    is_session_owner(auth_id, session)
+        || user_info.lookup_privs(auth_id, &["system", "tasks"]) & sys_priv != 0
+        || user_info.lookup_privs(auth_id, &["datastore", &session.store]) & store_priv != 0
+}
+
+#[api(
+    returns: {
+        description: "List of active sessions.",
+        type: Array,
+        items: { type: ActiveSession },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Users can see their own sessions, other sessions require Sys.Audit on \
+            /system/tasks or Datastore.Audit on the datastore.",
+    },
+)]
+/// List the active backup and reader sessions.
+pub fn list_sessions(rpcenv: &mut dyn RpcEnvironment) -> Result<Vec<ActiveSession>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    Ok(active_sessions()
+        .into_iter()
+        .filter(|session| {
+            check_session_privs(
+                &user_info,
+                &auth_id,
+                session,
+                PRIV_SYS_AUDIT,
+                PRIV_DATASTORE_AUDIT,
+            )
+        })
+        .collect())
+}
+
+#[api(
+    input: {
+        properties: {
+            upid: {
+                schema: UPID_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Users can terminate their own sessions, other sessions require \
+            Sys.Modify on /system/tasks or Datastore.Modify on the datastore.",
+    },
+)]
+/// Terminate an active session by aborting its task.
+pub fn terminate_session(upid: String, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let session = match lookup_active_session(&upid) {
+        Some(session) => session,
+        None => http_bail!(NOT_FOUND, "no active session with task '{upid}'"),
+    };
+
+    if !check_session_privs(
+        &user_info,
+        &auth_id,
+        &session,
+        PRIV_SYS_MODIFY,
+        PRIV_DATASTORE_MODIFY,
+    ) {
+        http_bail!(FORBIDDEN, "permission check failed");
+    }
+
+    let upid: UPID = upid.parse()?;
+    log::info!("terminating session {upid} on request of {auth_id}");
+    proxmox_rest_server::abort_worker_nowait(upid);
+
+    Ok(())
+}
+
+const SESSION_ROUTER: Router = Router::new().delete(&API_METHOD_TERMINATE_SESSION);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_SESSIONS)
+    .match_all("upid", &SESSION_ROUTER);
@@ -17,9 +17,9 @@ use pbs_config::CachedUserInfo;
 use crate::{
     api2::{
         config::sync::{check_sync_job_modify_access, check_sync_job_read_access},
-        pull::do_sync_job,
+        pull::{do_sync_compare, do_sync_job},
     },
-    server::jobstate::{compute_schedule_status, Job, JobState},
+    server::jobstate::{add_run_history, compute_schedule_status, Job, JobState},
 };
 
 #[api(
@@ -70,7 +70,8 @@ pub fn list_sync_jobs(
         let last_state = JobState::load("syncjob", &job.id)
             .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
 
-        let status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+        let mut status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+        add_run_history(&mut status, "syncjob", &job.id);
 
         list.push(SyncJobStatus {
             config: job,
@@ -121,8 +122,45 @@ pub fn run_sync_job(
     Ok(upid_str)
 }
 
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            }
+        }
+    },
+    access: {
+        description: "Same as for running the sync job.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Compare the manifests and index checksums of the synced snapshots with those on the source.
+pub fn compare_sync_job(
+    id: String,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let (config, _digest) = sync::config()?;
+    let sync_job: SyncJobConfig = config.lookup("sync", &id)?;
+
+    if !check_sync_job_modify_access(&user_info, &auth_id, &sync_job) {
+        bail!("permission check failed");
+    }
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    do_sync_compare(sync_job, &auth_id, to_stdout)
+}
+
 #[sortable]
-const SYNC_INFO_SUBDIRS: SubdirMap = &[("run", &Router::new().post(&API_METHOD_RUN_SYNC_JOB))];
+const SYNC_INFO_SUBDIRS: SubdirMap = &[
+    ("compare", &Router::new().post(&API_METHOD_COMPARE_SYNC_JOB)),
+    ("run", &Router::new().post(&API_METHOD_RUN_SYNC_JOB)),
+];
 
 const SYNC_INFO_ROUTER: Router = Router::new()
     .get(&list_subdirs_api_method!(SYNC_INFO_SUBDIRS))
@@ -0,0 +1,109 @@
+//! Snapshots left behind by aborted or crashed backups
+
+use anyhow::Error;
+
+use proxmox_rest_server::WorkerTask;
+use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType};
+use proxmox_schema::api;
+use proxmox_sys::task_log;
+
+use pbs_api_types::{
+    Authid, Operation, UnfinishedSnapshot, UnfinishedSnapshotAction, DATASTORE_SCHEMA,
+    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY, UPID_SCHEMA,
+};
+use pbs_datastore::DataStore;
+
+use crate::server::unfinished_snapshots::{
+    cleanup_unfinished_snapshots, configured_action, list_unfinished_snapshots,
+    DEFAULT_UNFINISHED_MIN_AGE,
+};
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "List of unfinished snapshots.",
+        type: Array,
+        items: { type: UnfinishedSnapshot },
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, false),
+    },
+)]
+/// List the snapshots without manifest, including those of running backups.
+pub fn list_unfinished(store: String) -> Result<Vec<UnfinishedSnapshot>, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    list_unfinished_snapshots(&datastore)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            action: {
+                type: UnfinishedSnapshotAction,
+                optional: true,
+            },
+            "min-age": {
+                description: "Only handle snapshots not modified for this many hours.",
+                type: Integer,
+                minimum: 0,
+                optional: true,
+                default: 24,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Report, remove or quarantine stale unfinished snapshots.
+///
+/// Without action, the one configured in the datastore tuning options is used.
+pub fn cleanup_unfinished(
+    store: String,
+    action: Option<UnfinishedSnapshotAction>,
+    min_age: Option<i64>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let action = match action {
+        Some(action) => action,
+        None => configured_action(&store)?,
+    };
+    let min_age = min_age
+        .map(|hours| hours * 3600)
+        .unwrap_or(DEFAULT_UNFINISHED_MIN_AGE);
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "unfinishedcleanup",
+        Some(store),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let count = cleanup_unfinished_snapshots(&*worker, &datastore, action, min_age)?;
+            task_log!(worker, "found {count} stale unfinished snapshots");
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_UNFINISHED)
+    .post(&API_METHOD_CLEANUP_UNFINISHED);
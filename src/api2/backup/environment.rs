@@ -1,7 +1,9 @@
 use anyhow::{bail, format_err, Error};
 use nix::dir::Dir;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use ::serde::Serialize;
 use serde_json::{json, Value};
@@ -10,13 +12,17 @@ use proxmox_router::{RpcEnvironment, RpcEnvironmentType};
 use proxmox_sys::fs::{lock_dir_noblock_shared, replace_file, CreateOptions};
 
 use pbs_api_types::Authid;
-use pbs_datastore::backup_info::{BackupDir, BackupInfo};
+use pbs_datastore::backup_info::{BackupDir, BackupGroup, BackupInfo};
 use pbs_datastore::dynamic_index::DynamicIndexWriter;
+use pbs_datastore::filename_index::FilenameIndex;
 use pbs_datastore::fixed_index::FixedIndexWriter;
+use pbs_datastore::manifest::ArchiveType;
+use pbs_datastore::merkle::index_merkle_root;
 use pbs_datastore::{DataBlob, DataStore};
 use proxmox_rest_server::{formatter::*, WorkerTask};
 
 use crate::backup::verify_backup_dir_with_lock;
+use crate::server::session_limits::{SessionUploadMemory, UploadMemoryGuard};
 
 use hyper::{Body, Response};
 
@@ -26,6 +32,9 @@ struct UploadStatistic {
     size: u64,
     compressed_size: u64,
     duplicates: u64,
+    /// Compressed size of the chunks not yet present in the datastore
+    #[serde(skip)]
+    written_size: u64,
 }
 
 impl UploadStatistic {
@@ -35,6 +44,7 @@ impl UploadStatistic {
             size: 0,
             compressed_size: 0,
             duplicates: 0,
+            written_size: 0,
         }
     }
 }
@@ -48,16 +58,60 @@ impl std::ops::Add for UploadStatistic {
             size: self.size + other.size,
             compressed_size: self.compressed_size + other.compressed_size,
             duplicates: self.duplicates + other.duplicates,
+            written_size: self.written_size + other.written_size,
         }
     }
 }
 
+/// Statistics of a single archive written during a backup session.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct WriterStatistic {
+    name: String,
+    /// Bytes received from the client, after compression
+    bytes_received: u64,
+    /// Chunks not yet present in the datastore
+    new_chunks: u64,
+    /// Chunks reused from the previous snapshot or already present in the datastore
+    known_chunks: u64,
+    /// Wall time in seconds from opening to closing the writer
+    duration: f64,
+}
+
+impl WriterStatistic {
+    fn new(name: &str, chunk_count: u64, upload_stat: &UploadStatistic, started: Instant) -> Self {
+        let new_chunks = upload_stat.count - upload_stat.duplicates;
+        Self {
+            name: name.to_string(),
+            bytes_received: upload_stat.compressed_size,
+            new_chunks,
+            known_chunks: chunk_count.saturating_sub(new_chunks),
+            duration: started.elapsed().as_secs_f64(),
+        }
+    }
+}
+
+/// Deduplication statistics of a running backup session, including the open writers.
+#[derive(Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SessionStatistic {
+    /// Chunks appended to the indexes so far
+    chunk_count: u64,
+    /// Chunks not yet present in the datastore
+    new_chunks: u64,
+    /// Compressed size of the new chunks, which the datastore grows by
+    new_size: u64,
+    /// Bytes of chunks received from the client, after compression
+    bytes_received: u64,
+}
+
 struct DynamicWriterState {
     name: String,
     index: DynamicIndexWriter,
     offset: u64,
     chunk_count: u64,
     upload_stat: UploadStatistic,
+    started: Instant,
 }
 
 struct FixedWriterState {
@@ -69,6 +123,7 @@ struct FixedWriterState {
     small_chunk_count: usize, // allow 0..1 small chunks (last chunk may be smaller)
     upload_stat: UploadStatistic,
     incremental: bool,
+    started: Instant,
 }
 
 // key=digest, value=length
@@ -83,6 +138,9 @@ struct SharedBackupState {
     known_chunks: KnownChunksMap,
     backup_size: u64, // sums up size of all files
     backup_stat: UploadStatistic,
+    writer_stats: Vec<WriterStatistic>,
+    blob_bytes_received: u64,
+    started: Instant,
 }
 
 impl SharedBackupState {
@@ -99,6 +157,18 @@ impl SharedBackupState {
         self.uid_counter += 1;
         self.uid_counter
     }
+
+    // Summary of the whole session, stored in the manifest and logged on finish
+    fn session_statistics(&self) -> Value {
+        let sum = |f: fn(&WriterStatistic) -> u64| self.writer_stats.iter().map(f).sum::<u64>();
+        json!({
+            "bytes-received": sum(|stat| stat.bytes_received) + self.blob_bytes_received,
+            "new-chunks": sum(|stat| stat.new_chunks),
+            "known-chunks": sum(|stat| stat.known_chunks),
+            "duration": self.started.elapsed().as_secs_f64(),
+            "writers": self.writer_stats,
+        })
+    }
 }
 
 /// `RpcEnvironmet` implementation for backup service
@@ -114,6 +184,11 @@ pub struct BackupEnvironment {
     pub backup_dir: BackupDir,
     pub last_backup: Option<BackupInfo>,
     state: Arc<Mutex<SharedBackupState>>,
+    /// digests of the chunks in the chunk quarantine when the backup started
+    quarantined_chunks: Arc<HashSet<[u8; 32]>>,
+    upload_memory: Arc<SessionUploadMemory>,
+    /// bytes received in this session, shown in the list of active sessions
+    pub transferred: Arc<AtomicU64>,
 }
 
 impl BackupEnvironment {
@@ -133,8 +208,19 @@ impl BackupEnvironment {
             known_chunks: HashMap::new(),
             backup_size: 0,
             backup_stat: UploadStatistic::new(),
+            writer_stats: Vec::new(),
+            blob_bytes_received: 0,
+            started: Instant::now(),
         };
 
+        let quarantined_chunks = datastore
+            .chunk_quarantine()
+            .digests()
+            .unwrap_or_else(|err| {
+                log::warn!("could not read chunk quarantine - {err}");
+                HashSet::new()
+            });
+
         Self {
             result_attributes: json!({}),
             env_type,
@@ -146,6 +232,40 @@ impl BackupEnvironment {
             backup_dir,
             last_backup: None,
             state: Arc::new(Mutex::new(state)),
+            quarantined_chunks: Arc::new(quarantined_chunks),
+            upload_memory: Arc::new(SessionUploadMemory::new()),
+            transferred: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Memory limit for buffered uploads of this session in bytes.
+    pub fn upload_memory_limit(&self) -> usize {
+        self.upload_memory.limit()
+    }
+
+    /// Wait until `size` bytes of upload buffer are available.
+    pub async fn reserve_upload_memory(&self, size: usize) -> Result<UploadMemoryGuard, Error> {
+        self.upload_memory.reserve(size).await
+    }
+
+    /// Remove an uploaded chunk from the chunk quarantine.
+    ///
+    /// A quarantined chunk got renamed by verify, so uploading it again writes a good copy.
+    pub fn heal_quarantined_chunk(&self, digest: &[u8; 32]) {
+        if !self.quarantined_chunks.contains(digest) {
+            return;
+        }
+        let digest_str = hex::encode(digest);
+        match self.datastore.chunk_quarantine().remove(&[*digest]) {
+            Ok(removed) if removed.first() == Some(&true) => {
+                self.log(format!("healed quarantined chunk {digest_str}"));
+            }
+            Ok(_) => {}
+            Err(err) => {
+                self.log(format!(
+                    "could not remove chunk {digest_str} from quarantine - {err}"
+                ));
+            }
         }
     }
 
@@ -207,8 +327,12 @@ impl BackupEnvironment {
         data.upload_stat.count += 1;
         data.upload_stat.size += size as u64;
         data.upload_stat.compressed_size += compressed_size as u64;
+        self.transferred
+            .fetch_add(compressed_size as u64, Ordering::Relaxed);
         if is_duplicate {
             data.upload_stat.duplicates += 1;
+        } else {
+            data.upload_stat.written_size += compressed_size as u64;
         }
 
         // register chunk
@@ -242,8 +366,12 @@ impl BackupEnvironment {
         data.upload_stat.count += 1;
         data.upload_stat.size += size as u64;
         data.upload_stat.compressed_size += compressed_size as u64;
+        self.transferred
+            .fetch_add(compressed_size as u64, Ordering::Relaxed);
         if is_duplicate {
             data.upload_stat.duplicates += 1;
+        } else {
+            data.upload_stat.written_size += compressed_size as u64;
         }
 
         // register chunk
@@ -252,6 +380,33 @@ impl BackupEnvironment {
         Ok(())
     }
 
+    /// Deduplication statistics of the session so far.
+    pub fn session_statistic(&self) -> SessionStatistic {
+        let state = self.state.lock().unwrap();
+
+        let mut chunk_count: u64 = state
+            .writer_stats
+            .iter()
+            .map(|stat| stat.new_chunks + stat.known_chunks)
+            .sum();
+        let mut upload_stat = state.backup_stat;
+        for data in state.dynamic_writers.values() {
+            chunk_count += data.chunk_count;
+            upload_stat = upload_stat + data.upload_stat;
+        }
+        for data in state.fixed_writers.values() {
+            chunk_count += data.chunk_count;
+            upload_stat = upload_stat + data.upload_stat;
+        }
+
+        SessionStatistic {
+            chunk_count,
+            new_chunks: upload_stat.count - upload_stat.duplicates,
+            new_size: upload_stat.written_size,
+            bytes_received: upload_stat.compressed_size,
+        }
+    }
+
     pub fn lookup_chunk(&self, digest: &[u8; 32]) -> Option<u32> {
         let state = self.state.lock().unwrap();
 
@@ -278,6 +433,7 @@ impl BackupEnvironment {
                 offset: 0,
                 chunk_count: 0,
                 upload_stat: UploadStatistic::new(),
+                started: Instant::now(),
             },
         );
 
@@ -310,6 +466,7 @@ impl BackupEnvironment {
                 small_chunk_count: 0,
                 upload_stat: UploadStatistic::new(),
                 incremental,
+                started: Instant::now(),
             },
         );
 
@@ -482,6 +639,12 @@ impl BackupEnvironment {
             &data.upload_stat,
         );
 
+        state.writer_stats.push(WriterStatistic::new(
+            &data.name,
+            chunk_count,
+            &data.upload_stat,
+            data.started,
+        ));
         state.file_counter += 1;
         state.backup_size += size;
         state.backup_stat = state.backup_stat + data.upload_stat;
@@ -556,6 +719,12 @@ impl BackupEnvironment {
             &data.upload_stat,
         );
 
+        state.writer_stats.push(WriterStatistic::new(
+            &data.name,
+            chunk_count,
+            &data.upload_stat,
+            data.started,
+        ));
         state.file_counter += 1;
         state.backup_size += size;
         state.backup_stat = state.backup_stat + data.upload_stat;
@@ -574,8 +743,13 @@ impl BackupEnvironment {
         // always verify blob/CRC at server side
         let blob = DataBlob::load_from_reader(&mut &data[..])?;
 
-        let raw_data = blob.raw_data();
-        replace_file(&path, raw_data, CreateOptions::new(), false)?;
+        let raw_data = self.datastore.encode_at_rest(blob.raw_data())?;
+        replace_file(
+            &path,
+            &raw_data,
+            CreateOptions::new(),
+            self.datastore.sync_metadata(),
+        )?;
 
         self.log(format!(
             "add blob {:?} ({} bytes, comp: {})",
@@ -586,6 +760,9 @@ impl BackupEnvironment {
         state.file_counter += 1;
         state.backup_size += orig_len as u64;
         state.backup_stat.size += blob_len as u64;
+        state.blob_bytes_received += blob_len as u64;
+        self.transferred
+            .fetch_add(blob_len as u64, Ordering::Relaxed);
 
         Ok(())
     }
@@ -605,11 +782,25 @@ impl BackupEnvironment {
             bail!("backup does not contain valid files (file count == 0)");
         }
 
+        self.check_digest_algorithm()?;
+
+        let merkle_roots = if self.datastore.merkle_roots() {
+            self.compute_merkle_roots()
+                .map_err(|err| format_err!("unable to compute merkle roots - {}", err))?
+        } else {
+            Vec::new()
+        };
+
         // check for valid manifest and store stats
         let stats = serde_json::to_value(state.backup_stat)?;
+        let session_stats = state.session_statistics();
         self.backup_dir
             .update_manifest(|manifest| {
                 manifest.unprotected["chunk_upload_stats"] = stats;
+                manifest.unprotected["session_stats"] = session_stats.clone();
+                for (name, root) in merkle_roots {
+                    manifest.set_merkle_root(&name, &root);
+                }
             })
             .map_err(|err| format_err!("unable to update manifest blob - {}", err))?;
 
@@ -628,9 +819,108 @@ impl BackupEnvironment {
         // marks the backup as successful
         state.finished = true;
 
+        self.log(format!("session summary: {}", session_stats));
+
+        self.enforce_group_max_snapshots();
+
         Ok(())
     }
 
+    // chunks with digests of another algorithm would never be deduplicated or found again
+    fn check_digest_algorithm(&self) -> Result<(), Error> {
+        let (manifest, _) = self.backup_dir.load_manifest()?;
+        let algorithm = self.datastore.digest_algorithm();
+        if manifest.digest_algorithm() != algorithm {
+            bail!(
+                "chunk digest algorithm {} of the backup does not match the datastore ({})",
+                manifest.digest_algorithm(),
+                algorithm,
+            );
+        }
+        Ok(())
+    }
+
+    // Merkle roots over the chunk digests of the index archives, by archive name
+    fn compute_merkle_roots(&self) -> Result<Vec<(String, [u8; 32])>, Error> {
+        let (manifest, _) = self.backup_dir.load_manifest()?;
+        let mut roots = Vec::new();
+        for file in manifest.files() {
+            if ArchiveType::from_path(&file.filename)? == ArchiveType::Blob {
+                continue;
+            }
+            let mut path = self.backup_dir.relative_path();
+            path.push(&file.filename);
+            let index = self.datastore.open_index(&path)?;
+            roots.push((file.filename.clone(), index_merkle_root(&*index)));
+        }
+        Ok(roots)
+    }
+
+    // Remove the oldest snapshots if the group exceeds its snapshot limit. Failures are only
+    // logged, the backup itself already finished successfully.
+    fn enforce_group_max_snapshots(&self) {
+        let group = self.datastore.backup_group(
+            self.backup_dir.backup_ns().clone(),
+            self.backup_dir.group().clone(),
+        );
+
+        let max_snapshots = match group.max_snapshots() {
+            Ok(Some(max)) => max,
+            Ok(None) => return,
+            Err(err) => {
+                self.log(format!("unable to read group snapshot limit - {}", err));
+                return;
+            }
+        };
+
+        // the base snapshot is still locked by this backup
+        let base = self.last_backup.as_ref().map(|base| &base.backup_dir);
+        match group.prune_exceeding_snapshots(max_snapshots, base) {
+            Ok(removed) => {
+                for snapshot in removed {
+                    self.log(format!(
+                        "removed snapshot {} (group limit of {} snapshots)",
+                        snapshot.dir(),
+                        max_snapshots
+                    ));
+                }
+            }
+            Err(err) => self.log(format!("enforcing group snapshot limit failed - {}", err)),
+        }
+    }
+
+    /// If the filename index is enabled on the datastore, this will run a task adding the new
+    /// snapshot to the index of its group.
+    pub fn update_filename_index_after_complete(&self) -> Result<(), Error> {
+        self.ensure_finished()?;
+
+        if !self.datastore.filename_index() {
+            return Ok(());
+        }
+
+        let worker_id = format!(
+            "{}:{}/{}",
+            self.datastore.name(),
+            self.backup_dir.backup_type(),
+            self.backup_dir.backup_id(),
+        );
+
+        let group = BackupGroup::from(&self.backup_dir);
+
+        WorkerTask::new_thread(
+            "filenameindex",
+            Some(worker_id),
+            self.auth_id.to_string(),
+            false,
+            move |worker| {
+                let count = FilenameIndex::open(&group)?.update()?;
+                worker.log_message(format!("indexed {count} snapshots"));
+                Ok(())
+            },
+        )
+        .map(|_| ())
+    }
+
     /// If verify-new is set on the datastore, this will run a new verify task
     /// for the backup. If not, this will return and also drop the passed lock
     /// immediately.
@@ -660,6 +950,10 @@ impl BackupEnvironment {
 
         let datastore = self.datastore.clone();
         let backup_dir = self.backup_dir.clone();
+        let last_backup = self
+            .last_backup
+            .as_ref()
+            .map(|info| info.backup_dir.clone());
 
         WorkerTask::new_thread(
             "verify",
@@ -670,6 +964,22 @@ impl BackupEnvironment {
                 worker.log_message("Automatically verifying newly added snapshot");
 
                 let verify_worker = crate::backup::VerifyWorker::new(worker.clone(), datastore);
+
+                // chunks reused from a successfully verified base snapshot were readable before
+                if let Some(last_backup) = last_backup {
+                    match verify_worker.assume_verified_snapshot(&last_backup) {
+                        Ok(0) => {}
+                        Ok(count) => worker.log_message(format!(
+                            "skipping {count} chunks already verified with snapshot {}",
+                            last_backup.dir()
+                        )),
+                        Err(err) => worker.log_warning(format!(
+                            "unable to load chunks of snapshot {} - {err}",
+                            last_backup.dir()
+                        )),
+                    }
+                }
+
                 if !verify_backup_dir_with_lock(
                     &verify_worker,
                     &backup_dir,
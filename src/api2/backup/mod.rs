@@ -9,7 +9,7 @@ use hyper::{Body, Request, Response, StatusCode};
 use serde::Deserialize;
 use serde_json::{json, Value};
 
-use proxmox_router::{http_err, list_subdirs_api_method};
+use proxmox_router::{http_bail, list_subdirs_api_method};
 use proxmox_router::{
     ApiHandler, ApiMethod, ApiResponseFuture, Permission, Router, RpcEnvironment, SubdirMap,
 };
@@ -17,11 +17,14 @@ use proxmox_schema::*;
 use proxmox_sortable_macro::sortable;
 
 use pbs_api_types::{
-    Authid, BackupNamespace, BackupType, Operation, SnapshotVerifyState, VerifyState,
-    BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA,
-    BACKUP_TYPE_SCHEMA, CHUNK_DIGEST_SCHEMA, DATASTORE_SCHEMA, PRIV_DATASTORE_BACKUP,
+    print_ns_and_snapshot, Authid, BackupNamespace, BackupType, HookEvent, Operation, SessionType,
+    SnapshotVerifyState, VerifyState, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA,
+    BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, CHUNK_DIGEST_SCHEMA,
+    DATASTORE_SCHEMA, PRIV_DATASTORE_READ, SNAPSHOT_PATH_REGEX,
 };
 use pbs_config::CachedUserInfo;
+use pbs_datastore::backup_info::{BackupDir, BackupInfo};
+use pbs_datastore::chunk_store::verify_chunk_size;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{archive_type, ArchiveType};
 use pbs_datastore::{DataStore, PROXMOX_BACKUP_PROTOCOL_ID_V1};
@@ -29,6 +32,8 @@ use pbs_tools::json::{required_array_param, required_integer_param, required_str
 use proxmox_rest_server::{H2Service, WorkerTask};
 use proxmox_sys::fs::lock_dir_noblock_shared;
 
+use crate::backup::{check_group_privs_with, lookup_group_privs, GROUP_BACKUP_PRIVS};
+
 mod environment;
 use environment::*;
 
@@ -37,6 +42,13 @@ use upload_chunk::*;
 
 pub const ROUTER: Router = Router::new().upgrade(&API_METHOD_UPGRADE_BACKUP);
 
+const PREVIOUS_REF_SCHEMA: Schema = StringSchema::new(
+    "Snapshot of another group in the same namespace, used instead of the last snapshot of the \
+    group as base for deduplication (e.g. the template a clone was created from).",
+)
+.format(&ApiStringFormat::Pattern(&SNAPSHOT_PATH_REGEX))
+.schema();
+
 #[sortable]
 pub const API_METHOD_UPGRADE_BACKUP: ApiMethod = ApiMethod::new(
     &ApiHandler::AsyncHttp(&upgrade_to_backup_protocol),
@@ -50,14 +62,85 @@ pub const API_METHOD_UPGRADE_BACKUP: ApiMethod = ApiMethod::new(
             ("backup-time", false, &BACKUP_TIME_SCHEMA),
             ("debug", true, &BooleanSchema::new("Enable verbose debug logging.").schema()),
             ("benchmark", true, &BooleanSchema::new("Job is a benchmark (do not keep data).").schema()),
+            ("previous-ref", true, &PREVIOUS_REF_SCHEMA),
         ]),
     )
 ).access(
     // Note: parameter 'store' is no uri parameter, so we need to test inside function body
-    Some("Requires on /datastore/{store}[/{namespace}] DATASTORE_BACKUP and being the owner of the group"),
+    Some("Requires on /datastore/{store}[/{namespace}] DATASTORE_BACKUP and being the owner of the group. \
+        Referencing a snapshot of another group additionally requires DATASTORE_READ or being its owner."),
     &Permission::Anybody
 );
 
+/// Returns `info` unless its last verification failed or it references quarantined chunks.
+fn usable_as_base(info: BackupInfo) -> Result<Option<BackupInfo>, Error> {
+    let snapshot = print_ns_and_snapshot(info.backup_dir.backup_ns(), info.backup_dir.as_ref());
+    let quarantine = info.backup_dir.datastore().chunk_quarantine();
+    if quarantine.references_snapshot(&snapshot)? {
+        return Ok(None);
+    }
+
+    let (manifest, _) = info.backup_dir.load_manifest()?;
+    let verify = manifest.unprotected["verify_state"].clone();
+    match serde_json::from_value::<SnapshotVerifyState>(verify) {
+        Ok(verify) => match verify.state {
+            VerifyState::Ok => Ok(Some(info)),
+            VerifyState::Failed => Ok(None),
+        },
+        Err(_) => {
+            // no verify state found, treat as valid
+            Ok(Some(info))
+        }
+    }
+}
+
+/// Seeding known chunks from another group allows reading its data, so require read access.
+fn check_previous_ref_access(
+    user_info: &CachedUserInfo,
+    auth_id: &Authid,
+    store: &str,
+    ref_dir: &BackupDir,
+) -> Result<(), Error> {
+    let privs = lookup_group_privs(
+        user_info,
+        auth_id,
+        store,
+        ref_dir.backup_ns(),
+        ref_dir.as_ref(),
+    );
+    if privs & PRIV_DATASTORE_READ != 0 {
+        return Ok(());
+    }
+    let owner = ref_dir.get_owner()?;
+    if ref_dir
+        .datastore()
+        .check_backup_owner(&owner, auth_id)
+        .is_ok()
+    {
+        return Ok(());
+    }
+    http_bail!(
+        FORBIDDEN,
+        "no permission to use '{}' as previous-ref",
+        ref_dir.dir()
+    );
+}
+
+/// Custom backup types must be registered in the node config before they can be used.
+pub(crate) fn check_backup_type_registered(ty: BackupType) -> Result<(), Error> {
+    if ty.is_builtin() {
+        return Ok(());
+    }
+    let (config, _digest) = crate::config::node::config()?;
+    if !config.registered_backup_types()?.contains(&ty) {
+        http_bail!(
+            BAD_REQUEST,
+            "backup type '{ty}' is not registered on this node"
+        );
+    }
+    Ok(())
+}
+
 pub(crate) fn optional_ns_param(param: &Value) -> Result<BackupNamespace, Error> {
     match param.get("ns") {
         Some(Value::String(ns)) => ns.parse(),
@@ -85,14 +168,17 @@ fn upgrade_to_backup_protocol(
 
         let user_info = CachedUserInfo::new()?;
 
-        user_info
-            .check_privs(
-                &auth_id,
-                &backup_ns.acl_path(&store),
-                PRIV_DATASTORE_BACKUP,
-                false,
-            )
-            .map_err(|err| http_err!(FORBIDDEN, "{err}"))?;
+        // the owner is checked when locking the group
+        check_group_privs_with(
+            &user_info,
+            &store,
+            &backup_ns,
+            &backup_dir_arg.group,
+            &auth_id,
+            &GROUP_BACKUP_PRIVS,
+        )?;
+
+        crate::server::failover::check_backup_allowed()?;
 
         let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
 
@@ -114,14 +200,19 @@ fn upgrade_to_backup_protocol(
         }
 
         if !datastore.namespace_path(&backup_ns).exists() {
-            proxmox_router::http_bail!(NOT_FOUND, "namespace not found");
+            http_bail!(NOT_FOUND, "namespace not found");
         }
 
+        check_backup_type_registered(backup_dir_arg.ty())?;
+
         // FIXME: include namespace here?
         let worker_id = format!("{}:{}/{}", store, backup_dir_arg.ty(), backup_dir_arg.id());
 
         let env_type = rpcenv.env_type();
 
+        let correlation_id =
+            crate::api2::helpers::correlation_id(&parts.headers).map(|id| id.to_string());
+
         let backup_group = datastore.backup_group(backup_ns, backup_dir_arg.group.clone());
 
         let worker_type = if backup_group.backup_type() == BackupType::Host
@@ -142,45 +233,46 @@ fn upgrade_to_backup_protocol(
         let (owner, _group_guard) = datastore.create_locked_backup_group(
             backup_group.backup_ns(),
             backup_group.as_ref(),
-            &auth_id,
+            &datastore.new_group_owner(&auth_id),
         )?;
 
         // permission check
-        let correct_owner =
-            owner == auth_id || (owner.is_token() && Authid::from(owner.user().clone()) == auth_id);
+        let correct_owner = datastore.check_backup_owner(&owner, &auth_id).is_ok();
         if !correct_owner && worker_type != "benchmark" {
             // only the owner is allowed to create additional snapshots
             bail!("backup owner check failed ({} != {})", auth_id, owner);
         }
 
-        let last_backup = {
-            let info = backup_group.last_backup(true).unwrap_or(None);
-            if let Some(info) = info {
-                let (manifest, _) = info.backup_dir.load_manifest()?;
-                let verify = manifest.unprotected["verify_state"].clone();
-                match serde_json::from_value::<SnapshotVerifyState>(verify) {
-                    Ok(verify) => match verify.state {
-                        VerifyState::Ok => Some(info),
-                        VerifyState::Failed => None,
-                    },
-                    Err(_) => {
-                        // no verify state found, treat as valid
-                        Some(info)
-                    }
-                }
-            } else {
-                None
-            }
+        let last_backup = match backup_group.last_backup(true).unwrap_or(None) {
+            Some(info) => usable_as_base(info)?,
+            None => None,
         };
 
         let backup_dir = backup_group.backup_dir(backup_dir_arg.time)?;
 
-        let _last_guard = if let Some(last) = &last_backup {
+        if let Some(last) = &last_backup {
             if backup_dir.backup_time() <= last.backup_dir.backup_time() {
                 bail!("backup timestamp is older than last backup.");
             }
+        }
+
+        let last_backup = match param["previous-ref"].as_str() {
+            Some(previous_ref) => {
+                let previous_ref: pbs_api_types::BackupDir = previous_ref.parse()?;
+                if previous_ref.group == backup_dir_arg.group {
+                    bail!("previous-ref must reference a snapshot of another group");
+                }
+                let ref_dir = datastore.backup_dir(backup_group.backup_ns().clone(), previous_ref)?;
+                check_previous_ref_access(&user_info, &auth_id, &store, &ref_dir)?;
+                let info = BackupInfo::new(ref_dir)
+                    .map_err(|err| format_err!("unable to open previous-ref - {err}"))?;
+                usable_as_base(info)?
+            }
+            None => last_backup,
+        };
 
-            // lock last snapshot to prevent forgetting/pruning it during backup
+        let _last_guard = if let Some(last) = &last_backup {
+            // lock base snapshot to prevent forgetting/pruning it during backup
             let full_path = last.backup_dir.full_path();
             Some(lock_dir_noblock_shared(
                 &full_path,
@@ -191,6 +283,11 @@ fn upgrade_to_backup_protocol(
             None
         };
 
+        let session_guard = crate::server::session_limits::start_session(
+            &auth_id,
+            rpcenv.get_client_ip().map(|addr| addr.ip()),
+        )?;
+
         let (path, is_new, snap_guard) =
             datastore.create_locked_backup_dir(backup_dir.backup_ns(), backup_dir.as_ref())?;
         if !is_new {
@@ -214,6 +311,10 @@ fn upgrade_to_backup_protocol(
                 env.debug = debug;
                 env.last_backup = last_backup;
 
+                let mut session_guard = session_guard;
+                env.transferred =
+                    session_guard.register(&worker, SessionType::Backup, &env.backup_dir);
+
                 let origin = match rpcenv.get_client_ip().map(|addr| addr.ip()) {
                     Some(ip) => format!(" from {ip}"),
                     None => "".into(),
@@ -221,6 +322,9 @@ fn upgrade_to_backup_protocol(
                 env.log(format!(
                     "starting new {worker_type} on datastore '{store}'{origin}: {path:?}",
                 ));
+                if let Some(id) = &correlation_id {
+                    env.log(format!("correlation ID: {id}"));
+                }
 
                 let service =
                     H2Service::new(env.clone(), worker.clone(), &BACKUP_API_ROUTER, debug);
@@ -237,7 +341,9 @@ fn upgrade_to_backup_protocol(
                         let mut http = hyper::server::conn::Http::new();
                         http.http2_only(true);
                         // increase window size: todo - find optiomal size
-                        let window_size = 32 * 1024 * 1024; // max = (1 << 31) - 2
+                        // max = (1 << 31) - 2, but the client must not send more than we buffer
+                        let window_size =
+                            env2.upload_memory_limit().clamp(65535, 32 * 1024 * 1024) as u32;
                         http.http2_initial_stream_window_size(window_size);
                         http.http2_initial_connection_window_size(window_size);
                         http.http2_max_frame_size(4 * 1024 * 1024);
@@ -263,7 +369,8 @@ fn upgrade_to_backup_protocol(
                 let mut abort_future = abort_future.map(|_| Err(format_err!("task aborted")));
 
                 async move {
-                    // keep flock until task ends
+                    // keep flock and session slot until task ends
+                    let _session_guard = session_guard;
                     let _group_guard = _group_guard;
                     let snap_guard = snap_guard;
                     let _last_guard = _last_guard;
@@ -279,6 +386,12 @@ fn upgrade_to_backup_protocol(
                     }
 
                     let verify = |env: BackupEnvironment| {
+                        if let Err(err) = env.update_filename_index_after_complete() {
+                            env.log(format!(
+                                "backup finished, but starting the filename index update failed: {}",
+                                err
+                            ));
+                        }
                         if let Err(err) = env.verify_after_complete(snap_guard) {
                             env.log(format!(
                                 "backup finished, but starting the requested verify task failed: {}",
@@ -287,31 +400,50 @@ fn upgrade_to_backup_protocol(
                         }
                     };
 
-                    match (res, env.ensure_finished()) {
-                        (Ok(_), Ok(())) => {
-                            env.log("backup finished successfully");
-                            verify(env);
-                            Ok(())
-                        }
-                        (Err(err), Ok(())) => {
-                            // ignore errors after finish
-                            env.log(format!("backup had errors but finished: {}", err));
-                            verify(env);
-                            Ok(())
-                        }
-                        (Ok(_), Err(err)) => {
-                            env.log(format!("backup ended and finish failed: {}", err));
-                            env.log("removing unfinished backup");
-                            proxmox_async::runtime::block_in_place(|| env.remove_backup())?;
-                            Err(err)
-                        }
-                        (Err(err), Err(_)) => {
-                            env.log(format!("backup failed: {}", err));
-                            env.log("removing failed backup");
-                            proxmox_async::runtime::block_in_place(|| env.remove_backup())?;
-                            Err(err)
+                    let hook_env = vec![
+                        ("PBS_DATASTORE", env.datastore.name().to_string()),
+                        ("PBS_NAMESPACE", env.backup_dir.backup_ns().to_string()),
+                        ("PBS_SNAPSHOT", env.backup_dir.dir().to_string()),
+                    ];
+                    let upid = env.worker.upid().clone();
+
+                    let result = (move || -> Result<(), Error> {
+                        match (res, env.ensure_finished()) {
+                            (Ok(_), Ok(())) => {
+                                env.log("backup finished successfully");
+                                verify(env);
+                                Ok(())
+                            }
+                            (Err(err), Ok(())) => {
+                                // ignore errors after finish
+                                env.log(format!("backup had errors but finished: {}", err));
+                                verify(env);
+                                Ok(())
+                            }
+                            (Ok(_), Err(err)) => {
+                                env.log(format!("backup ended and finish failed: {}", err));
+                                env.log("removing unfinished backup");
+                                proxmox_async::runtime::block_in_place(|| env.remove_backup())?;
+                                Err(err)
+                            }
+                            (Err(err), Err(_)) => {
+                                env.log(format!("backup failed: {}", err));
+                                env.log("removing failed backup");
+                                proxmox_async::runtime::block_in_place(|| env.remove_backup())?;
+                                Err(err)
+                            }
                         }
-                    }
+                    })();
+
+                    crate::server::hooks::run_task_hooks(
+                        HookEvent::BackupFinish,
+                        HookEvent::BackupFailure,
+                        &upid,
+                        &result,
+                        hook_env,
+                    );
+
+                    result
                 }
             },
         )?;
@@ -367,6 +499,10 @@ const BACKUP_API_SUBDIRS: SubdirMap = &[
             .post(&API_METHOD_CREATE_FIXED_INDEX)
             .put(&API_METHOD_FIXED_APPEND),
     ),
+    (
+        "known_chunks",
+        &Router::new().post(&API_METHOD_QUERY_KNOWN_CHUNKS),
+    ),
     (
         "previous",
         &Router::new().download(&API_METHOD_DOWNLOAD_PREVIOUS),
@@ -375,6 +511,10 @@ const BACKUP_API_SUBDIRS: SubdirMap = &[
         "previous_backup_time",
         &Router::new().get(&API_METHOD_GET_PREVIOUS_BACKUP_TIME),
     ),
+    (
+        "session_stats",
+        &Router::new().get(&API_METHOD_GET_SESSION_STATS),
+    ),
     (
         "speedtest",
         &Router::new().upload(&API_METHOD_UPLOAD_SPEEDTEST),
@@ -440,6 +580,15 @@ pub const API_METHOD_CREATE_FIXED_INDEX: ApiMethod = ApiMethod::new(
                 )
                 .schema()
             ),
+            (
+                "chunk-size",
+                true,
+                &IntegerSchema::new("Chunk size in bytes (power of two, 64 KiB to 4 MiB).")
+                    .minimum(64 * 1024)
+                    .maximum(4096 * 1024)
+                    .default(4096 * 1024)
+                    .schema()
+            ),
         ]),
     ),
 );
@@ -463,7 +612,8 @@ fn create_fixed_index(
     let mut path = env.backup_dir.relative_path();
     path.push(&archive_name);
 
-    let chunk_size = 4096 * 1024; // todo: ??
+    let chunk_size = param["chunk-size"].as_u64().unwrap_or(4096 * 1024) as usize;
+    verify_chunk_size(chunk_size)?;
 
     // do incremental backup if csum is set
     let mut reader = None;
@@ -487,6 +637,14 @@ fn create_fixed_index(
             }
         };
 
+        if index.chunk_size != chunk_size {
+            bail!(
+                "cannot reuse index - chunk size changed ({} != {})",
+                index.chunk_size,
+                chunk_size
+            );
+        }
+
         let (old_csum, _) = index.compute_csum();
         let old_csum = hex::encode(old_csum);
         if old_csum != csum {
@@ -508,7 +666,10 @@ fn create_fixed_index(
 
     let wid = env.register_fixed_writer(writer, name, size, chunk_size as u32, incremental)?;
 
-    env.log(format!("created new fixed index {} ({:?})", wid, path));
+    env.log(format!(
+        "created new fixed index {} ({:?}, chunk size {})",
+        wid, path, chunk_size
+    ));
 
     Ok(json!(wid))
 }
@@ -775,6 +936,79 @@ fn close_fixed_index(
     Ok(Value::Null)
 }
 
+#[sortable]
+pub const API_METHOD_QUERY_KNOWN_CHUNKS: ApiMethod = ApiMethod::new(
+    &ApiHandler::Sync(&query_known_chunks),
+    &ObjectSchema::new(
+        "Query which chunks already exist in the datastore. Existing chunks are registered \
+        for this session and can be appended to indexes without uploading them.",
+        &sorted!([
+            (
+                "digest-list",
+                false,
+                &ArraySchema::new("Chunk digest list.", &CHUNK_DIGEST_SCHEMA).schema()
+            ),
+            (
+                "size-list",
+                false,
+                &ArraySchema::new(
+                    "Chunk size list.",
+                    &IntegerSchema::new("Corresponding chunk sizes.")
+                        .minimum(1)
+                        .maximum(1024 * 1024 * 16)
+                        .schema()
+                )
+                .schema()
+            ),
+        ]),
+    ),
+);
+
+fn query_known_chunks(
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let digest_list = required_array_param(&param, "digest-list")?;
+    let size_list = required_array_param(&param, "size-list")?;
+
+    if size_list.len() != digest_list.len() {
+        bail!(
+            "size list has wrong length ({} != {})",
+            size_list.len(),
+            digest_list.len()
+        );
+    }
+
+    let env: &BackupEnvironment = rpcenv.as_ref();
+
+    let mut known = Vec::new();
+    for (item, size) in digest_list.iter().zip(size_list) {
+        let digest_str = item.as_str().unwrap();
+        let digest = <[u8; 32]>::from_hex(digest_str)?;
+        let size = size.as_u64().unwrap() as u32;
+
+        if env.lookup_chunk(&digest).is_some() {
+            known.push(digest_str);
+            continue;
+        }
+
+        // touching protects the chunk from a concurrent garbage collection
+        if env.datastore.cond_touch_chunk(&digest, false)? {
+            env.register_chunk(digest, size)?;
+            known.push(digest_str);
+        }
+    }
+
+    env.debug(format!(
+        "query_known_chunks: {} of {} chunks known",
+        known.len(),
+        digest_list.len()
+    ));
+
+    Ok(json!(known))
+}
+
 fn finish_backup(
     _param: Value,
     _info: &ApiMethod,
@@ -809,6 +1043,25 @@ fn get_previous_backup_time(
     Ok(json!(backup_time))
 }
 
+#[sortable]
+pub const API_METHOD_GET_SESSION_STATS: ApiMethod = ApiMethod::new(
+    &ApiHandler::Sync(&get_session_stats),
+    &ObjectSchema::new(
+        "Get the chunk deduplication statistics of the backup session so far.",
+        &[],
+    ),
+);
+
+fn get_session_stats(
+    _param: Value,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let env: &BackupEnvironment = rpcenv.as_ref();
+
+    Ok(serde_json::to_value(env.session_statistic())?)
+}
+
 #[sortable]
 pub const API_METHOD_DOWNLOAD_PREVIOUS: ApiMethod = ApiMethod::new(
     &ApiHandler::AsyncHttp(&download_previous),
@@ -819,7 +1072,7 @@ pub const API_METHOD_DOWNLOAD_PREVIOUS: ApiMethod = ApiMethod::new(
 );
 
 fn download_previous(
-    _parts: Parts,
+    parts: Parts,
     _req_body: Body,
     param: Value,
     _info: &ApiMethod,
@@ -865,7 +1118,12 @@ fn download_previous(
         }
 
         env.log(format!("download '{}' from previous backup.", archive_name));
-        crate::api2::helpers::create_download_response(path).await
+        crate::api2::helpers::create_snapshot_file_download_response(
+            &env.datastore,
+            path,
+            &parts.headers,
+        )
+        .await
     }
     .boxed()
 }
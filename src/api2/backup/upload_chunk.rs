@@ -77,7 +77,11 @@ impl Future for UploadChunk {
                             let mut chunk = DataBlob::from_raw(raw_data)?;
 
                             proxmox_async::runtime::block_in_place(|| {
-                                chunk.verify_unencrypted(this.size as usize, &this.digest)?;
+                                chunk.verify_unencrypted_with(
+                                    this.store.digest_algorithm(),
+                                    this.size as usize,
+                                    &this.digest,
+                                )?;
 
                                 // always comput CRC at server side
                                 chunk.set_crc(chunk.compute_crc());
@@ -161,10 +165,14 @@ fn upload_fixed_chunk(
 
         let env: &BackupEnvironment = rpcenv.as_ref();
 
+        let _memory = env.reserve_upload_memory(encoded_size as usize).await?;
         let (digest, size, compressed_size, is_duplicate) =
             UploadChunk::new(req_body, env.datastore.clone(), digest, size, encoded_size).await?;
 
         env.register_fixed_chunk(wid, digest, size, compressed_size, is_duplicate)?;
+        if !is_duplicate {
+            env.heal_quarantined_chunk(&digest);
+        }
         let digest_str = hex::encode(digest);
         env.debug(format!("upload_chunk done: {} bytes, {}", size, digest_str));
 
@@ -230,10 +238,14 @@ fn upload_dynamic_chunk(
 
         let env: &BackupEnvironment = rpcenv.as_ref();
 
+        let _memory = env.reserve_upload_memory(encoded_size as usize).await?;
         let (digest, size, compressed_size, is_duplicate) =
             UploadChunk::new(req_body, env.datastore.clone(), digest, size, encoded_size).await?;
 
         env.register_dynamic_chunk(wid, digest, size, compressed_size, is_duplicate)?;
+        if !is_duplicate {
+            env.heal_quarantined_chunk(&digest);
+        }
         let digest_str = hex::encode(digest);
         env.debug(format!("upload_chunk done: {} bytes, {}", size, digest_str));
 
@@ -318,9 +330,13 @@ fn upload_blob(
             bail!("wrong blob file extension: '{}'", file_name);
         }
 
+        let _memory = env.reserve_upload_memory(encoded_size).await?;
         let data = req_body
             .map_err(Error::from)
             .try_fold(Vec::new(), |mut acc, chunk| {
+                if acc.len() + chunk.len() > encoded_size {
+                    return future::err(format_err!("uploaded blob is larger than announced."));
+                }
                 acc.extend_from_slice(&chunk);
                 future::ok::<_, Error>(acc)
             })
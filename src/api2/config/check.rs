@@ -0,0 +1,195 @@
+//! Check all configuration files without applying anything.
+
+use std::path::Path;
+
+use anyhow::Error;
+use serde::de::DeserializeOwned;
+
+use proxmox_router::{Permission, Router};
+use proxmox_schema::api;
+use proxmox_section_config::SectionConfigData;
+use proxmox_time::CalendarEvent;
+
+use pbs_api_types::{
+    ConfigProblem, DataStoreConfig, PruneJobConfig, SyncJobConfig, TapeBackupJobConfig,
+    VerificationJobConfig, PRIV_SYS_AUDIT,
+};
+
+#[derive(Default)]
+struct Problems(Vec<ConfigProblem>);
+
+impl Problems {
+    fn push(&mut self, config: &str, id: Option<&str>, message: String) {
+        self.0.push(ConfigProblem {
+            config: config.to_string(),
+            id: id.map(str::to_string),
+            message,
+        });
+    }
+
+    /// Parse a section config, recording the error if it fails.
+    fn parse(
+        &mut self,
+        config: &str,
+        result: Result<(SectionConfigData, [u8; 32]), Error>,
+    ) -> Option<SectionConfigData> {
+        match result {
+            Ok((data, _digest)) => Some(data),
+            Err(err) => {
+                self.push(config, None, format!("parsing failed - {err}"));
+                None
+            }
+        }
+    }
+
+    fn typed<T: DeserializeOwned>(
+        &mut self,
+        config: &str,
+        data: &Option<SectionConfigData>,
+        section_type: &str,
+    ) -> Vec<T> {
+        match data
+            .as_ref()
+            .map(|data| data.convert_to_typed_array(section_type))
+        {
+            Some(Ok(list)) => list,
+            Some(Err(err)) => {
+                self.push(
+                    config,
+                    None,
+                    format!("invalid '{section_type}' entry - {err}"),
+                );
+                Vec::new()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn check_schedule(&mut self, config: &str, id: &str, what: &str, schedule: Option<&str>) {
+        if let Some(schedule) = schedule {
+            if let Err(err) = schedule.parse::<CalendarEvent>() {
+                self.push(
+                    config,
+                    Some(id),
+                    format!("invalid {what} '{schedule}' - {err}"),
+                );
+            }
+        }
+    }
+
+    /// Check that `id` references an existing entry of another config.
+    fn check_reference(
+        &mut self,
+        config: &str,
+        id: &str,
+        referenced: &Option<SectionConfigData>,
+        what: &str,
+        value: &str,
+    ) {
+        if let Some(referenced) = referenced {
+            if !referenced.sections.contains_key(value) {
+                self.push(config, Some(id), format!("{what} '{value}' does not exist"));
+            }
+        }
+    }
+}
+
+/// Parse all configuration files and cross-check the references between them.
+pub fn check_configuration() -> Vec<ConfigProblem> {
+    let mut problems = Problems::default();
+
+    if let Err(err) = crate::config::node::config() {
+        problems.push("node.cfg", None, format!("parsing failed - {err}"));
+    }
+    if let Err(err) = pbs_config::acl::config() {
+        problems.push("acl.cfg", None, format!("parsing failed - {err}"));
+    }
+    if let Err(err) = pbs_config::notifications::config() {
+        problems.push("notifications.cfg", None, format!("parsing failed - {err}"));
+    }
+    problems.parse("domains.cfg", pbs_config::domains::config());
+    problems.parse("traffic-control.cfg", pbs_config::traffic_control::config());
+    problems.parse("metricserver.cfg", pbs_config::metrics::config());
+    problems.parse(
+        "expected-backups.cfg",
+        pbs_config::expected_backup::config(),
+    );
+
+    let datastores = problems.parse("datastore.cfg", pbs_config::datastore::config());
+    let remotes = problems.parse("remote.cfg", pbs_config::remote::config());
+    let users = problems.parse("user.cfg", pbs_config::user::config());
+    let pools = problems.parse("media-pool.cfg", pbs_config::media_pool::config());
+    let drives = problems.parse("tape.cfg", pbs_config::drive::config());
+    let sync_jobs = problems.parse("sync.cfg", pbs_config::sync::config());
+    let verify_jobs = problems.parse("verification.cfg", pbs_config::verify::config());
+    let prune_jobs = problems.parse("prune.cfg", pbs_config::prune::config());
+    let tape_jobs = problems.parse("tape-job.cfg", pbs_config::tape_job::config());
+
+    for store in problems.typed::<DataStoreConfig>("datastore.cfg", &datastores, "datastore") {
+        let schedule = store.gc_schedule.as_deref();
+        problems.check_schedule("datastore.cfg", &store.name, "gc-schedule", schedule);
+        let schedule = store.prune_schedule.as_deref();
+        problems.check_schedule("datastore.cfg", &store.name, "prune-schedule", schedule);
+        if store.get_maintenance_mode().is_none() && !Path::new(&store.path).is_dir() {
+            let message = format!("path '{}' is not a directory", store.path);
+            problems.push("datastore.cfg", Some(&store.name), message);
+        }
+    }
+
+    for job in problems.typed::<SyncJobConfig>("sync.cfg", &sync_jobs, "sync") {
+        let id = &job.id;
+        problems.check_schedule("sync.cfg", id, "schedule", job.schedule.as_deref());
+        problems.check_reference("sync.cfg", id, &datastores, "datastore", &job.store);
+        match &job.remote {
+            Some(remote) => problems.check_reference("sync.cfg", id, &remotes, "remote", remote),
+            None => {
+                let store = &job.remote_store;
+                problems.check_reference("sync.cfg", id, &datastores, "source datastore", store)
+            }
+        }
+        if let Some(owner) = &job.owner {
+            let owner = owner.to_string();
+            problems.check_reference("sync.cfg", id, &users, "owner", &owner);
+        }
+    }
+
+    let config = "verification.cfg";
+    for job in problems.typed::<VerificationJobConfig>(config, &verify_jobs, "verification") {
+        problems.check_schedule(config, &job.id, "schedule", job.schedule.as_deref());
+        problems.check_reference(config, &job.id, &datastores, "datastore", &job.store);
+    }
+
+    for job in problems.typed::<PruneJobConfig>("prune.cfg", &prune_jobs, "prune") {
+        problems.check_schedule("prune.cfg", &job.id, "schedule", Some(&job.schedule));
+        problems.check_reference("prune.cfg", &job.id, &datastores, "datastore", &job.store);
+    }
+
+    let config = "tape-job.cfg";
+    for job in problems.typed::<TapeBackupJobConfig>(config, &tape_jobs, "backup") {
+        let (id, setup) = (&job.id, &job.setup);
+        problems.check_schedule(config, id, "schedule", job.schedule.as_deref());
+        problems.check_reference(config, id, &datastores, "datastore", &setup.store);
+        problems.check_reference(config, id, &pools, "media pool", &setup.pool);
+        problems.check_reference(config, id, &drives, "drive", &setup.drive);
+    }
+
+    problems.0
+}
+
+#[api(
+    returns: {
+        description: "List of problems found, empty if the configuration is fine.",
+        type: Array,
+        items: { type: ConfigProblem },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// Parse all configuration files and check the references between them (e.g. sync jobs to
+/// remotes and datastores) and all job schedules, without applying anything.
+pub fn check_config() -> Result<Vec<ConfigProblem>, Error> {
+    Ok(check_configuration())
+}
+
+pub const ROUTER: Router = Router::new().get(&API_METHOD_CHECK_CONFIG);
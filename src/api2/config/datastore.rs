@@ -1,23 +1,28 @@
 use std::path::PathBuf;
 
 use ::serde::{Deserialize, Serialize};
-use anyhow::Error;
+use anyhow::{bail, Error};
 use hex::FromHex;
 use serde_json::Value;
 
-use proxmox_router::{http_bail, Permission, Router, RpcEnvironment, RpcEnvironmentType};
+use proxmox_router::{
+    http_bail, Permission, Router, RpcEnvironment, RpcEnvironmentType, SubdirMap,
+};
 use proxmox_schema::{api, param_bail, ApiType};
 use proxmox_section_config::SectionConfigData;
-use proxmox_sys::{task_warn, WorkerTaskContext};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
 use proxmox_uuid::Uuid;
 
 use pbs_api_types::{
-    Authid, DataStoreConfig, DataStoreConfigUpdater, DatastoreNotify, DatastoreTuning, KeepOptions,
-    MaintenanceMode, PruneJobConfig, PruneJobOptions, DATASTORE_SCHEMA, PRIV_DATASTORE_ALLOCATE,
-    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA, UPID_SCHEMA,
+    Authid, BackupNamespace, ClientDefaults, DataStoreConfig, DataStoreConfigUpdater,
+    DatastoreNotify, DatastoreTuning, KeepOptions, MaintenanceMode, Operation, PruneJobConfig,
+    PruneJobOptions, RemovalPreview, CONFIRMATION_TOKEN_SCHEMA, DATASTORE_SCHEMA,
+    PRIV_DATASTORE_ALLOCATE, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA, UPID_SCHEMA,
 };
 use pbs_config::BackupLockGuard;
 use pbs_datastore::chunk_store::ChunkStore;
+use pbs_datastore::DataStore;
 
 use crate::api2::admin::{
     prune::list_prune_jobs, sync::list_sync_jobs, verify::list_verification_jobs,
@@ -31,6 +36,7 @@ use pbs_config::CachedUserInfo;
 use proxmox_rest_server::WorkerTask;
 
 use crate::server::jobstate;
+use crate::tools::confirmation;
 
 #[api(
     input: {
@@ -88,6 +94,10 @@ pub(crate) fn do_create_datastore(
         tuning.sync_level.unwrap_or_default(),
     )?;
 
+    if datastore.encrypt_at_rest.unwrap_or(false) {
+        ensure_at_rest_key(&datastore.name)?;
+    }
+
     config.set_data(&datastore.name, "datastore", &datastore)?;
 
     pbs_config::datastore::save_config(&config)?;
@@ -95,6 +105,99 @@ pub(crate) fn do_create_datastore(
     jobstate::create_state_file("garbage_collection", &datastore.name)
 }
 
+/// Register an existing, previously used datastore directory.
+///
+/// Validates the chunk store and sets `owner` for all backup groups whose owner file is missing.
+pub(crate) fn do_import_datastore(
+    _lock: BackupLockGuard,
+    mut config: SectionConfigData,
+    datastore: DataStoreConfig,
+    owner: &Authid,
+    worker: &dyn WorkerTaskContext,
+) -> Result<(), Error> {
+    let path: PathBuf = datastore.path.clone().into();
+
+    let tuning: DatastoreTuning = serde_json::from_value(
+        DatastoreTuning::API_SCHEMA
+            .parse_property_string(datastore.tuning.as_deref().unwrap_or(""))?,
+    )?;
+    let backup_user = pbs_config::backup_user()?;
+    let _store = ChunkStore::import(
+        &datastore.name,
+        path,
+        backup_user.uid,
+        backup_user.gid,
+        Some(worker),
+        tuning.sync_level.unwrap_or_default(),
+    )?;
+
+    if datastore.encrypt_at_rest.unwrap_or(false)
+        && pbs_config::datastore_keys::load_key(&datastore.name)?.is_none()
+    {
+        bail!(
+            "datastore '{}' is encrypted at rest, but its key is missing - restore the key first",
+            datastore.name
+        );
+    }
+
+    config.set_data(&datastore.name, "datastore", &datastore)?;
+
+    pbs_config::datastore::save_config(&config)?;
+
+    jobstate::create_state_file("garbage_collection", &datastore.name)?;
+
+    let store = DataStore::lookup_datastore(&datastore.name, Some(Operation::Write))?;
+
+    let (mut groups, mut snapshots, mut restored) = (0, 0, 0);
+    for ns in store.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+        for group in store.iter_backup_groups_ok(ns)? {
+            groups += 1;
+            snapshots += group.list_backups()?.len();
+            if group.get_owner().is_err() {
+                group.set_owner(owner, false)?;
+                restored += 1;
+            }
+        }
+    }
+
+    task_log!(
+        worker,
+        "imported datastore '{}' with {} groups and {} snapshots",
+        datastore.name,
+        groups,
+        snapshots
+    );
+    if restored > 0 {
+        task_log!(worker, "set owner '{}' for {} groups", owner, restored);
+    }
+
+    Ok(())
+}
+
+// the client defaults must not exceed the limits configured along with them
+fn check_client_defaults(client_defaults: Option<&str>) -> Result<(), Error> {
+    let defaults: ClientDefaults = serde_json::from_value(
+        ClientDefaults::API_SCHEMA.parse_property_string(client_defaults.unwrap_or(""))?,
+    )?;
+    if let (Some(entries_max), Some(limit)) = (defaults.entries_max, defaults.entries_max_limit) {
+        if entries_max > limit {
+            param_bail!(
+                "client-defaults",
+                "entries-max {entries_max} exceeds entries-max-limit {limit}"
+            );
+        }
+    }
+    Ok(())
+}
+
+// a datastore key might already exist when re-adding an existing datastore
+fn ensure_at_rest_key(store: &str) -> Result<(), Error> {
+    if pbs_config::datastore_keys::load_key(store)?.is_none() {
+        pbs_config::datastore_keys::create_key(store)?;
+    }
+    Ok(())
+}
+
 #[api(
     protected: true,
     input: {
@@ -103,6 +206,19 @@ pub(crate) fn do_create_datastore(
                 type: DataStoreConfig,
                 flatten: true,
             },
+            "import-existing": {
+                description: "Register an existing datastore directory instead of creating a \
+                    new chunk store, e.g. after reinstalling the server.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            owner: {
+                type: Authid,
+                optional: true,
+                description: "Owner for imported backup groups without owner file, defaults to \
+                    the calling user.",
+            },
         },
     },
     access: {
@@ -112,6 +228,8 @@ pub(crate) fn do_create_datastore(
 /// Create new datastore config.
 pub fn create_datastore(
     config: DataStoreConfig,
+    import_existing: bool,
+    owner: Option<Authid>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<String, Error> {
     let lock = pbs_config::datastore::lock_config()?;
@@ -122,9 +240,19 @@ pub fn create_datastore(
         param_bail!("name", "datastore '{}' already exists.", config.name);
     }
 
+    check_client_defaults(config.client_defaults.as_deref())?;
+
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
 
+    if owner.is_some() && !import_existing {
+        param_bail!(
+            "owner",
+            "owner can only be set when importing an existing datastore"
+        );
+    }
+    let owner = owner.unwrap_or_else(|| auth_id.clone());
+
     let prune_job_config = config.prune_schedule.as_ref().map(|schedule| {
         let mut id = format!("default-{}-{}", config.name, Uuid::generate());
         id.truncate(32);
@@ -140,6 +268,8 @@ pub fn create_datastore(
                 max_depth: None,
                 ns: None,
             },
+            notify: None,
+            notify_user: None,
         }
     });
 
@@ -156,7 +286,11 @@ pub fn create_datastore(
         auth_id.to_string(),
         to_stdout,
         move |worker| {
-            do_create_datastore(lock, section_config, config, Some(&worker))?;
+            if import_existing {
+                do_import_datastore(lock, section_config, config, &owner, &*worker)?;
+            } else {
+                do_create_datastore(lock, section_config, config, Some(&worker))?;
+            }
 
             if let Some(prune_job_config) = prune_job_config {
                 do_create_prune_job(prune_job_config, Some(&worker))
@@ -224,8 +358,12 @@ pub enum DeletableProperty {
     Notify,
     /// Delete the notification-mode property
     NotificationMode,
+    /// Delete the compliance-report property
+    ComplianceReport,
     /// Delete the tuning property
     Tuning,
+    /// Delete the client-defaults property
+    ClientDefaults,
     /// Delete the maintenance-mode property
     MaintenanceMode,
 }
@@ -320,9 +458,15 @@ pub fn update_datastore(
                 DeletableProperty::NotificationMode => {
                     data.notification_mode = None;
                 }
+                DeletableProperty::ComplianceReport => {
+                    data.compliance_report = None;
+                }
                 DeletableProperty::Tuning => {
                     data.tuning = None;
                 }
+                DeletableProperty::ClientDefaults => {
+                    data.client_defaults = None;
+                }
                 DeletableProperty::MaintenanceMode => {
                     data.set_maintenance_mode(None)?;
                 }
@@ -386,6 +530,20 @@ pub fn update_datastore(
         data.verify_new = update.verify_new;
     }
 
+    if let Some(encrypt_at_rest) = update.encrypt_at_rest {
+        let enabled = data.encrypt_at_rest.unwrap_or(false);
+        if enabled && !encrypt_at_rest {
+            param_bail!(
+                "encrypt-at-rest",
+                "at-rest encryption cannot be disabled once enabled"
+            );
+        }
+        if encrypt_at_rest && !enabled {
+            ensure_at_rest_key(&name)?;
+        }
+        data.encrypt_at_rest = Some(encrypt_at_rest);
+    }
+
     if update.notify_user.is_some() {
         data.notify_user = update.notify_user;
     }
@@ -394,10 +552,19 @@ pub fn update_datastore(
         data.notification_mode = update.notification_mode;
     }
 
+    if update.compliance_report.is_some() {
+        data.compliance_report = update.compliance_report;
+    }
+
     if update.tuning.is_some() {
         data.tuning = update.tuning;
     }
 
+    if update.client_defaults.is_some() {
+        check_client_defaults(update.client_defaults.as_deref())?;
+        data.client_defaults = update.client_defaults;
+    }
+
     let mut maintenance_mode_changed = false;
     if update.maintenance_mode.is_some() {
         maintenance_mode_changed = data.maintenance_mode != update.maintenance_mode;
@@ -443,6 +610,89 @@ pub fn update_datastore(
     Ok(())
 }
 
+// Everything removed together with a datastore, used for the safe mode confirmation.
+fn removal_items(
+    name: &str,
+    config: &SectionConfigData,
+    keep_job_configs: bool,
+    destroy_data: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<String>, Error> {
+    let mut items = vec![format!("datastore configuration '{name}'")];
+
+    if destroy_data {
+        let store: DataStoreConfig = config.lookup("datastore", name)?;
+        items.push(format!("all contents of '{}'", store.path));
+    }
+
+    if !keep_job_configs {
+        for job in list_verification_jobs(Some(name.to_string()), Value::Null, rpcenv)? {
+            items.push(format!("verification job '{}'", job.config.id));
+        }
+        for job in list_sync_jobs(Some(name.to_string()), Value::Null, rpcenv)? {
+            items.push(format!("sync job '{}'", job.config.id));
+        }
+        for job in list_prune_jobs(Some(name.to_string()), Value::Null, rpcenv)? {
+            items.push(format!("prune job '{}'", job.config.id));
+        }
+        for job in list_tape_backup_jobs(Value::Null, rpcenv)? {
+            if job.setup.store == name {
+                items.push(format!("tape backup job '{}'", job.id));
+            }
+        }
+        items.push(format!("permissions on '/datastore/{name}'"));
+    }
+
+    Ok(items)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "keep-job-configs": {
+                description: "If enabled, the job configurations related to this datastore will be kept.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "destroy-data": {
+                description: "Delete the datastore's underlying contents",
+                optional: true,
+                type: bool,
+                default: false,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{name}"], PRIV_DATASTORE_ALLOCATE, false),
+    },
+    returns: { type: RemovalPreview },
+)]
+/// Preview the removal of a datastore, listing everything which would be removed.
+///
+/// Returns the confirmation token required to remove the datastore in safe mode.
+pub fn preview_delete_datastore(
+    name: String,
+    keep_job_configs: bool,
+    destroy_data: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<RemovalPreview, Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+
+    if !config.sections.contains_key(&name) {
+        http_bail!(NOT_FOUND, "datastore '{}' does not exist.", name);
+    }
+
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let items = removal_items(&name, &config, keep_job_configs, destroy_data, rpcenv)?;
+
+    confirmation::create_preview(&auth_id, &format!("delete-datastore:{name}"), items)
+}
+
 #[api(
     protected: true,
     input: {
@@ -466,6 +716,10 @@ pub fn update_datastore(
                 optional: true,
                 schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
             },
+            "confirm-token": {
+                schema: CONFIRMATION_TOKEN_SCHEMA,
+                optional: true,
+            },
         },
     },
     access: {
@@ -481,6 +735,7 @@ pub async fn delete_datastore(
     keep_job_configs: bool,
     destroy_data: bool,
     digest: Option<String>,
+    confirm_token: Option<String>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<String, Error> {
     let _lock = pbs_config::datastore::lock_config()?;
@@ -496,6 +751,15 @@ pub async fn delete_datastore(
         http_bail!(NOT_FOUND, "datastore '{}' does not exist.", name);
     }
 
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let items = removal_items(&name, &config, keep_job_configs, destroy_data, rpcenv)?;
+    confirmation::check_confirmation(
+        confirm_token.as_deref(),
+        &auth_id,
+        &format!("delete-datastore:{name}"),
+        &items,
+    )?;
+
     if !keep_job_configs {
         for job in list_verification_jobs(Some(name.clone()), Value::Null, rpcenv)? {
             delete_verification_job(job.config.id, None, rpcenv)?
@@ -520,7 +784,6 @@ pub async fn delete_datastore(
         }
     }
 
-    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
 
     let upid = WorkerTask::new_thread(
@@ -548,10 +811,16 @@ pub async fn delete_datastore(
     Ok(upid)
 }
 
+const ITEM_SUBDIRS: SubdirMap = &[(
+    "remove-preview",
+    &Router::new().get(&API_METHOD_PREVIEW_DELETE_DATASTORE),
+)];
+
 const ITEM_ROUTER: Router = Router::new()
     .get(&API_METHOD_READ_DATASTORE)
     .put(&API_METHOD_UPDATE_DATASTORE)
-    .delete(&API_METHOD_DELETE_DATASTORE);
+    .delete(&API_METHOD_DELETE_DATASTORE)
+    .subdirs(ITEM_SUBDIRS);
 
 pub const ROUTER: Router = Router::new()
     .get(&API_METHOD_LIST_DATASTORES)
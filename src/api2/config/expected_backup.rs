@@ -0,0 +1,303 @@
+use anyhow::Error;
+use hex::FromHex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    Authid, ExpectedBackupConfig, ExpectedBackupConfigUpdater, JOB_ID_SCHEMA, PRIV_DATASTORE_AUDIT,
+    PRIV_DATASTORE_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+use pbs_config::expected_backup;
+
+use pbs_config::CachedUserInfo;
+
+#[api(
+    input: {
+        properties: {},
+    },
+    returns: {
+        description: "List configured expected backups.",
+        type: Array,
+        items: { type: ExpectedBackupConfig },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Audit.",
+    },
+)]
+/// List all expected backup entries.
+pub fn list_expected_backups(
+    _param: Value,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<ExpectedBackupConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let required_privs = PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_MODIFY;
+
+    let (config, digest) = expected_backup::config()?;
+
+    let list = config.convert_to_typed_array("expected")?;
+
+    let list = list
+        .into_iter()
+        .filter(|entry: &ExpectedBackupConfig| {
+            let privs = user_info.lookup_privs(&auth_id, &entry.acl_path());
+            privs & required_privs != 0
+        })
+        .collect();
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            config: {
+                type: ExpectedBackupConfig,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Modify on the datastore.",
+    },
+)]
+/// Create a new expected backup entry.
+pub fn create_expected_backup(
+    config: ExpectedBackupConfig,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    user_info.check_privs(&auth_id, &config.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
+
+    let _lock = expected_backup::lock_config()?;
+
+    let (mut section_config, _digest) = expected_backup::config()?;
+
+    if section_config.sections.get(&config.id).is_some() {
+        param_bail!("id", "expected backup '{}' already exists.", config.id);
+    }
+
+    section_config.set_data(&config.id, "expected", &config)?;
+
+    expected_backup::save_config(&section_config)?;
+
+    Ok(())
+}
+
+#[api(
+   input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: ExpectedBackupConfig },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Audit on the datastore.",
+    },
+)]
+/// Read an expected backup entry.
+pub fn read_expected_backup(
+    id: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<ExpectedBackupConfig, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let (config, digest) = expected_backup::config()?;
+
+    let entry: ExpectedBackupConfig = config.lookup("expected", &id)?;
+
+    user_info.check_privs(&auth_id, &entry.acl_path(), PRIV_DATASTORE_AUDIT, true)?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(entry)
+}
+
+#[api]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the comment.
+    Comment,
+    /// Unset the disable flag.
+    Disable,
+    /// Reset the namespace to the root namespace.
+    Ns,
+    /// Delete the group filters, i.e. expect backups of all groups.
+    GroupFilter,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            update: {
+                type: ExpectedBackupConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Modify on the datastore.",
+    },
+)]
+/// Update an expected backup entry.
+pub fn update_expected_backup(
+    id: String,
+    update: ExpectedBackupConfigUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let _lock = expected_backup::lock_config()?;
+
+    let (mut config, expected_digest) = expected_backup::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let mut data: ExpectedBackupConfig = config.lookup("expected", &id)?;
+
+    user_info.check_privs(&auth_id, &data.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Comment => data.comment = None,
+                DeletableProperty::Disable => data.disable = false,
+                DeletableProperty::Ns => data.ns = None,
+                DeletableProperty::GroupFilter => data.group_filter = None,
+            }
+        }
+    }
+
+    let mut recheck_privs = false;
+    if let Some(store) = update.store {
+        recheck_privs = true;
+        data.store = store;
+    }
+
+    if let Some(ns) = update.ns {
+        recheck_privs = true;
+        data.ns = if ns.is_root() { None } else { Some(ns) };
+    }
+
+    if recheck_privs {
+        user_info.check_privs(&auth_id, &data.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
+    }
+
+    if update.group_filter.is_some() {
+        data.group_filter = update.group_filter;
+    }
+    if let Some(max_age) = update.max_age {
+        data.max_age = max_age;
+    }
+    if let Some(value) = update.disable {
+        data.disable = value;
+    }
+    if let Some(value) = update.comment {
+        data.comment = Some(value);
+    }
+
+    config.set_data(&id, "expected", &data)?;
+
+    expected_backup::save_config(&config)?;
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Modify on the datastore.",
+    },
+)]
+/// Remove an expected backup entry.
+pub fn delete_expected_backup(
+    id: String,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let _lock = expected_backup::lock_config()?;
+
+    let (mut config, expected_digest) = expected_backup::config()?;
+
+    let entry: ExpectedBackupConfig = config.lookup("expected", &id)?;
+
+    user_info.check_privs(&auth_id, &entry.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    if config.sections.remove(&id).is_none() {
+        http_bail!(NOT_FOUND, "expected backup '{}' does not exist.", id);
+    }
+
+    expected_backup::save_config(&config)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_EXPECTED_BACKUP)
+    .put(&API_METHOD_UPDATE_EXPECTED_BACKUP)
+    .delete(&API_METHOD_DELETE_EXPECTED_BACKUP);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_EXPECTED_BACKUPS)
+    .post(&API_METHOD_CREATE_EXPECTED_BACKUP)
+    .match_all("id", &ITEM_ROUTER);
@@ -0,0 +1,247 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::Error;
+use hex::FromHex;
+use serde_json::Value;
+
+use proxmox_router::{http_bail, ApiMethod, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    HookConfig, HookConfigUpdater, HOOK_ID_SCHEMA, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+
+use crate::server::hooks::check_hook_script;
+
+#[api(
+    input: {
+        properties: {},
+    },
+    returns: {
+        description: "The list of configured hooks (with config digest).",
+        type: Array,
+        items: { type: HookConfig },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// List hooks
+pub fn list_hooks(
+    _param: Value,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<HookConfig>, Error> {
+    let (config, digest) = pbs_config::hook::config()?;
+
+    let list: Vec<HookConfig> = config.convert_to_typed_array("hook")?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            config: {
+                type: HookConfig,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Create a new hook.
+pub fn create_hook(config: HookConfig) -> Result<(), Error> {
+    check_hook_script(&config.script)?;
+
+    let _lock = pbs_config::hook::lock_config()?;
+
+    let (mut section_config, _digest) = pbs_config::hook::config()?;
+
+    if section_config.sections.get(&config.id).is_some() {
+        param_bail!("id", "hook '{}' already exists.", config.id);
+    }
+
+    section_config.set_data(&config.id, "hook", &config)?;
+
+    pbs_config::hook::save_config(&section_config)?;
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: HOOK_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: HookConfig },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// Read a hook.
+pub fn read_hook(
+    id: String,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<HookConfig, Error> {
+    let (config, digest) = pbs_config::hook::config()?;
+    let data: HookConfig = config.lookup("hook", &id)?;
+    rpcenv["digest"] = hex::encode(digest).into();
+    Ok(data)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the timeout property.
+    Timeout,
+    /// Unset the disable flag.
+    Disable,
+    /// Delete the comment property.
+    Comment,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: HOOK_ID_SCHEMA,
+            },
+            update: {
+                type: HookConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Update a hook.
+pub fn update_hook(
+    id: String,
+    update: HookConfigUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+) -> Result<(), Error> {
+    let _lock = pbs_config::hook::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::hook::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let mut data: HookConfig = config.lookup("hook", &id)?;
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Timeout => data.timeout = None,
+                DeletableProperty::Disable => data.disable = false,
+                DeletableProperty::Comment => data.comment = None,
+            }
+        }
+    }
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim().to_string();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment);
+        }
+    }
+
+    if let Some(event) = update.event {
+        data.event = event;
+    }
+    if let Some(script) = update.script {
+        check_hook_script(&script)?;
+        data.script = script;
+    }
+    if update.timeout.is_some() {
+        data.timeout = update.timeout;
+    }
+    if let Some(disable) = update.disable {
+        data.disable = disable;
+    }
+
+    config.set_data(&id, "hook", &data)?;
+
+    pbs_config::hook::save_config(&config)?;
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: HOOK_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Remove a hook.
+pub fn delete_hook(id: String, digest: Option<String>) -> Result<(), Error> {
+    let _lock = pbs_config::hook::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::hook::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    if config.sections.remove(&id).is_none() {
+        http_bail!(NOT_FOUND, "hook '{}' does not exist.", id);
+    }
+
+    pbs_config::hook::save_config(&config)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_HOOK)
+    .put(&API_METHOD_UPDATE_HOOK)
+    .delete(&API_METHOD_DELETE_HOOK);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_HOOKS)
+    .post(&API_METHOD_CREATE_HOOK)
+    .match_all("id", &ITEM_ROUTER);
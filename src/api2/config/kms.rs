@@ -0,0 +1,292 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::Error;
+use hex::FromHex;
+use serde_json::Value;
+
+use proxmox_router::{http_bail, ApiMethod, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    KmsConfig, KmsConfigUpdater, KMS_ID_SCHEMA, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+
+#[api(
+    protected: true,
+    input: {
+        properties: {},
+    },
+    returns: {
+        description: "The list of configured key management services (with config digest).",
+        type: Array,
+        items: { type: KmsConfig },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// List configured key management services. Access tokens are not returned.
+pub fn list_kms(
+    _param: Value,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<KmsConfig>, Error> {
+    let (config, digest) = pbs_config::kms::config()?;
+
+    let mut list: Vec<KmsConfig> = config.convert_to_typed_array("kms")?;
+    for kms in list.iter_mut() {
+        kms.token = None;
+    }
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            config: {
+                type: KmsConfig,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Create a new key management service entry.
+pub fn create_kms(config: KmsConfig) -> Result<(), Error> {
+    let _lock = pbs_config::kms::lock_config()?;
+
+    let (mut section_config, _digest) = pbs_config::kms::config()?;
+
+    if section_config.sections.get(&config.id).is_some() {
+        param_bail!(
+            "id",
+            "key management service '{}' already exists.",
+            config.id
+        );
+    }
+
+    crate::tools::kms::kms_backend(&config)?;
+
+    section_config.set_data(&config.id, "kms", &config)?;
+
+    pbs_config::kms::save_config(&section_config)?;
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: KMS_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: KmsConfig },
+    access: {
+        permission: &Permission::Privilege(&["system"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// Read a key management service configuration. The access token is not returned.
+pub fn read_kms(
+    id: String,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<KmsConfig, Error> {
+    let (config, digest) = pbs_config::kms::config()?;
+    let mut data: KmsConfig = config.lookup("kms", &id)?;
+    data.token = None;
+    rpcenv["digest"] = hex::encode(digest).into();
+    Ok(data)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the comment property.
+    Comment,
+    /// Delete the url property.
+    Url,
+    /// Delete the mount property.
+    Mount,
+    /// Delete the path property.
+    Path,
+    /// Delete the token property.
+    Token,
+    /// Delete the command property.
+    Command,
+    /// Delete the cache-time property.
+    CacheTime,
+    /// Delete the failure-policy property.
+    FailurePolicy,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: KMS_ID_SCHEMA,
+            },
+            update: {
+                type: KmsConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Update a key management service configuration.
+pub fn update_kms(
+    id: String,
+    update: KmsConfigUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+) -> Result<(), Error> {
+    let _lock = pbs_config::kms::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::kms::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let mut data: KmsConfig = config.lookup("kms", &id)?;
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Comment => data.comment = None,
+                DeletableProperty::Url => data.url = None,
+                DeletableProperty::Mount => data.mount = None,
+                DeletableProperty::Path => data.path = None,
+                DeletableProperty::Token => data.token = None,
+                DeletableProperty::Command => data.command = None,
+                DeletableProperty::CacheTime => data.cache_time = None,
+                DeletableProperty::FailurePolicy => data.failure_policy = None,
+            }
+        }
+    }
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim().to_string();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment);
+        }
+    }
+    if let Some(backend) = update.backend {
+        data.backend = backend;
+    }
+    if update.url.is_some() {
+        data.url = update.url;
+    }
+    if update.mount.is_some() {
+        data.mount = update.mount;
+    }
+    if update.path.is_some() {
+        data.path = update.path;
+    }
+    if update.token.is_some() {
+        data.token = update.token;
+    }
+    if update.command.is_some() {
+        data.command = update.command;
+    }
+    if update.cache_time.is_some() {
+        data.cache_time = update.cache_time;
+    }
+    if update.failure_policy.is_some() {
+        data.failure_policy = update.failure_policy;
+    }
+
+    crate::tools::kms::kms_backend(&data)?;
+
+    config.set_data(&id, "kms", &data)?;
+
+    pbs_config::kms::save_config(&config)?;
+
+    // cached keys may originate from the old settings
+    crate::tools::kms::clear_key_cache()?;
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: KMS_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Remove a key management service from the configuration file.
+///
+/// Keys only stored in this service are no longer accessible.
+pub fn delete_kms(id: String, digest: Option<String>) -> Result<(), Error> {
+    let _lock = pbs_config::kms::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::kms::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    match config.sections.get(&id) {
+        Some(_) => {
+            config.sections.remove(&id);
+        }
+        None => http_bail!(NOT_FOUND, "key management service '{}' does not exist.", id),
+    }
+
+    pbs_config::kms::save_config(&config)?;
+
+    crate::tools::kms::clear_key_cache()?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_KMS)
+    .put(&API_METHOD_UPDATE_KMS)
+    .delete(&API_METHOD_DELETE_KMS);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_KMS)
+    .post(&API_METHOD_CREATE_KMS)
+    .match_all("id", &ITEM_ROUTER);
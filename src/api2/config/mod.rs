@@ -7,10 +7,15 @@ use proxmox_sortable_macro::sortable;
 pub mod access;
 pub mod acme;
 pub mod changer;
+pub mod check;
 pub mod datastore;
 pub mod drive;
+pub mod expected_backup;
+pub mod hook;
+pub mod kms;
 pub mod media_pool;
 pub mod metrics;
+pub mod network_share;
 pub mod notifications;
 pub mod prune;
 pub mod remote;
@@ -25,10 +30,15 @@ const SUBDIRS: SubdirMap = &sorted!([
     ("access", &access::ROUTER),
     ("acme", &acme::ROUTER),
     ("changer", &changer::ROUTER),
+    ("check", &check::ROUTER),
     ("datastore", &datastore::ROUTER),
     ("drive", &drive::ROUTER),
+    ("expected-backup", &expected_backup::ROUTER),
+    ("hook", &hook::ROUTER),
+    ("kms", &kms::ROUTER),
     ("media-pool", &media_pool::ROUTER),
     ("metrics", &metrics::ROUTER),
+    ("network-share", &network_share::ROUTER),
     ("notifications", &notifications::ROUTER),
     ("prune", &prune::ROUTER),
     ("remote", &remote::ROUTER),
@@ -0,0 +1,305 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::{bail, Error};
+use hex::FromHex;
+use serde_json::Value;
+
+use proxmox_router::{http_bail, ApiMethod, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    DataStoreConfig, NetworkShareConfig, NetworkShareConfigUpdater, NetworkShareType,
+    NETWORK_SHARE_ID_SCHEMA, NETWORK_SHARE_PASSWORD_SCHEMA, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+
+use crate::tools::network_share;
+
+#[api(
+    input: {
+        properties: {},
+    },
+    returns: {
+        description: "The list of configured network shares (with config digest).",
+        type: Array,
+        items: { type: NetworkShareConfig },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "disks"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// List network shares.
+pub fn list_network_shares(
+    _param: Value,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<NetworkShareConfig>, Error> {
+    let (config, digest) = pbs_config::network_share::config()?;
+
+    let list: Vec<NetworkShareConfig> = config.convert_to_typed_array("share")?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+fn check_share_options(share: &NetworkShareConfig) -> Result<(), Error> {
+    if share.share_type == NetworkShareType::Nfs {
+        if share.username.is_some() || share.domain.is_some() {
+            bail!("user name and domain are only supported for CIFS shares");
+        }
+        if !share.path.starts_with('/') {
+            bail!("the exported path of an NFS share needs to be absolute");
+        }
+    }
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            config: {
+                type: NetworkShareConfig,
+                flatten: true,
+            },
+            password: {
+                schema: NETWORK_SHARE_PASSWORD_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "disks"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Create a new network share and mount it on first access.
+pub fn create_network_share(
+    config: NetworkShareConfig,
+    password: Option<String>,
+) -> Result<(), Error> {
+    check_share_options(&config)?;
+
+    let _lock = pbs_config::network_share::lock_config()?;
+
+    let (mut section_config, _digest) = pbs_config::network_share::config()?;
+
+    if section_config.sections.get(&config.name).is_some() {
+        param_bail!("name", "network share '{}' already exists.", config.name);
+    }
+
+    network_share::update_credentials(&config, password)?;
+    network_share::write_units(&config)?;
+    network_share::activate(&config)?;
+
+    section_config.set_data(&config.name, "share", &config)?;
+
+    pbs_config::network_share::save_config(&section_config)?;
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: NETWORK_SHARE_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: NetworkShareConfig },
+    access: {
+        permission: &Permission::Privilege(&["system", "disks"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// Read a network share configuration. The password is not returned.
+pub fn read_network_share(
+    name: String,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<NetworkShareConfig, Error> {
+    let (config, digest) = pbs_config::network_share::config()?;
+    let data: NetworkShareConfig = config.lookup("share", &name)?;
+    rpcenv["digest"] = hex::encode(digest).into();
+    Ok(data)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the username property.
+    Username,
+    /// Delete the domain property.
+    Domain,
+    /// Delete the options property.
+    Options,
+    /// Delete the comment property.
+    Comment,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: NETWORK_SHARE_ID_SCHEMA,
+            },
+            update: {
+                type: NetworkShareConfigUpdater,
+                flatten: true,
+            },
+            password: {
+                schema: NETWORK_SHARE_PASSWORD_SCHEMA,
+                optional: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "disks"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Update a network share. It is mounted again with the new settings on the next access.
+pub fn update_network_share(
+    name: String,
+    update: NetworkShareConfigUpdater,
+    password: Option<String>,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+) -> Result<(), Error> {
+    let _lock = pbs_config::network_share::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::network_share::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let mut data: NetworkShareConfig = config.lookup("share", &name)?;
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Username => data.username = None,
+                DeletableProperty::Domain => data.domain = None,
+                DeletableProperty::Options => data.options = None,
+                DeletableProperty::Comment => data.comment = None,
+            }
+        }
+    }
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim().to_string();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment);
+        }
+    }
+
+    if let Some(server) = update.server {
+        data.server = server;
+    }
+    if let Some(path) = update.path {
+        data.path = path;
+    }
+    if update.username.is_some() {
+        data.username = update.username;
+    }
+    if update.domain.is_some() {
+        data.domain = update.domain;
+    }
+    if update.options.is_some() {
+        data.options = update.options;
+    }
+
+    check_share_options(&data)?;
+
+    network_share::update_credentials(&data, password)?;
+    network_share::write_units(&data)?;
+    network_share::unmount(&data)?;
+
+    config.set_data(&name, "share", &data)?;
+
+    pbs_config::network_share::save_config(&config)?;
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: NETWORK_SHARE_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "disks"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Unmount and remove a network share.
+pub fn delete_network_share(name: String, digest: Option<String>) -> Result<(), Error> {
+    let _lock = pbs_config::network_share::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::network_share::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let share: NetworkShareConfig = match config.lookup("share", &name) {
+        Ok(share) => share,
+        Err(_) => http_bail!(NOT_FOUND, "network share '{}' does not exist.", name),
+    };
+
+    let mount_point = share.mount_point();
+    let (datastore_config, _) = pbs_config::datastore::config()?;
+    let datastores: Vec<DataStoreConfig> = datastore_config.convert_to_typed_array("datastore")?;
+    if let Some(datastore) = datastores
+        .iter()
+        .find(|datastore| std::path::Path::new(&datastore.path).starts_with(&mount_point))
+    {
+        bail!(
+            "network share '{name}' is used by datastore '{}'",
+            datastore.name
+        );
+    }
+
+    network_share::remove(&share)?;
+
+    config.sections.remove(&name);
+
+    pbs_config::network_share::save_config(&config)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_NETWORK_SHARE)
+    .put(&API_METHOD_UPDATE_NETWORK_SHARE)
+    .delete(&API_METHOD_DELETE_NETWORK_SHARE);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_NETWORK_SHARES)
+    .post(&API_METHOD_CREATE_NETWORK_SHARE)
+    .match_all("name", &ITEM_ROUTER);
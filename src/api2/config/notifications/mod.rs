@@ -19,6 +19,7 @@ use crate::api2::tape::backup::list_tape_backup_jobs;
 
 pub mod gotify;
 pub mod matchers;
+pub mod queue;
 pub mod sendmail;
 pub mod smtp;
 pub mod targets;
@@ -30,6 +31,7 @@ const SUBDIRS: SubdirMap = &sorted!([
     ("matcher-field-values", &VALUE_ROUTER),
     ("targets", &targets::ROUTER),
     ("matchers", &matchers::ROUTER),
+    ("queue", &queue::ROUTER),
 ]);
 
 pub const ROUTER: Router = Router::new()
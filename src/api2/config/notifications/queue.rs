@@ -0,0 +1,75 @@
+use anyhow::Error;
+
+use proxmox_router::{list_subdirs_api_method, Permission, Router, SubdirMap};
+use proxmox_schema::api;
+use proxmox_sortable_macro::sortable;
+
+use pbs_api_types::{
+    QueuedNotificationInfo, NOTIFICATION_ID_SCHEMA, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY,
+};
+
+use crate::server::notifications;
+
+#[api(
+    protected: true,
+    returns: {
+        description: "List of queued and failed notifications.",
+        type: Array,
+        items: { type: QueuedNotificationInfo },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "notifications"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// List notifications waiting to be sent, and those which failed too often.
+pub fn list_queue() -> Result<Vec<QueuedNotificationInfo>, Error> {
+    notifications::list_queued_notifications()
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: NOTIFICATION_ID_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "notifications"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Retry sending a queued or failed notification right away.
+pub fn retry_notification(id: String) -> Result<(), Error> {
+    notifications::retry_queued_notification(&id)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: NOTIFICATION_ID_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "notifications"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Drop a queued or failed notification without sending it.
+pub fn drop_notification(id: String) -> Result<(), Error> {
+    notifications::drop_queued_notification(&id)
+}
+
+#[sortable]
+const SUBDIRS: SubdirMap = &sorted!([("retry", &RETRY_ROUTER),]);
+const RETRY_ROUTER: Router = Router::new().post(&API_METHOD_RETRY_NOTIFICATION);
+const ITEM_ROUTER: Router = Router::new()
+    .get(&list_subdirs_api_method!(SUBDIRS))
+    .delete(&API_METHOD_DROP_NOTIFICATION)
+    .subdirs(SUBDIRS);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_QUEUE)
+    .match_all("id", &ITEM_ROUTER);
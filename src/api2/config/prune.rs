@@ -170,6 +170,10 @@ pub enum DeletableProperty {
     KeepMonthly,
     /// Delete number of yearly backups to keep.
     KeepYearly,
+    /// Delete the notify property, inheriting the datastore's setting.
+    Notify,
+    /// Delete the notify-user property, inheriting the datastore's setting.
+    NotifyUser,
 }
 
 #[api(
@@ -261,6 +265,12 @@ pub fn update_prune_job(
                 DeletableProperty::KeepYearly => {
                     data.options.keep.keep_yearly = None;
                 }
+                DeletableProperty::Notify => {
+                    data.notify = None;
+                }
+                DeletableProperty::NotifyUser => {
+                    data.notify_user = None;
+                }
             }
         }
     }
@@ -317,6 +327,12 @@ pub fn update_prune_job(
     if let Some(value) = update.options.keep.keep_yearly {
         data.options.keep.keep_yearly = Some(value);
     }
+    if let Some(value) = update.notify {
+        data.notify = Some(value);
+    }
+    if let Some(value) = update.notify_user {
+        data.notify_user = Some(value);
+    }
 
     config.set_data(&id, "prune", &data)?;
 
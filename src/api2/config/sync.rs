@@ -54,6 +54,13 @@ pub fn check_sync_job_modify_access(
         }
     }
 
+    // same permission as changing ownership, notes and protection locally
+    if let Some(true) = job.sync_metadata {
+        if ns_anchor_privs & PRIV_DATASTORE_MODIFY == 0 {
+            return false;
+        }
+    }
+
     let correct_owner = match job.owner {
         Some(ref owner) => {
             owner == auth_id
@@ -231,6 +238,14 @@ pub enum DeletableProperty {
     MaxDepth,
     /// Delete the transfer_last property,
     TransferLast,
+    /// Delete the sync_metadata property,
+    SyncMetadata,
+    /// Delete the inventory_only property,
+    InventoryOnly,
+    /// Delete the notify property, inheriting the datastore's setting.
+    Notify,
+    /// Delete the notify-user property, inheriting the datastore's setting.
+    NotifyUser,
 }
 
 #[api(
@@ -331,6 +346,18 @@ pub fn update_sync_job(
                 DeletableProperty::TransferLast => {
                     data.transfer_last = None;
                 }
+                DeletableProperty::SyncMetadata => {
+                    data.sync_metadata = None;
+                }
+                DeletableProperty::InventoryOnly => {
+                    data.inventory_only = None;
+                }
+                DeletableProperty::Notify => {
+                    data.notify = None;
+                }
+                DeletableProperty::NotifyUser => {
+                    data.notify_user = None;
+                }
             }
         }
     }
@@ -368,6 +395,18 @@ pub fn update_sync_job(
     if let Some(transfer_last) = update.transfer_last {
         data.transfer_last = Some(transfer_last);
     }
+    if let Some(sync_metadata) = update.sync_metadata {
+        data.sync_metadata = Some(sync_metadata);
+    }
+    if let Some(inventory_only) = update.inventory_only {
+        data.inventory_only = Some(inventory_only);
+    }
+    if let Some(notify) = update.notify {
+        data.notify = Some(notify);
+    }
+    if let Some(notify_user) = update.notify_user {
+        data.notify_user = Some(notify_user);
+    }
 
     if update.limit.rate_in.is_some() {
         data.limit.rate_in = update.limit.rate_in;
@@ -533,6 +572,10 @@ acl:1:/remote/remote1/remotestore1:write@pbs:RemoteSyncOperator
         schedule: None,
         limit: pbs_api_types::RateLimitConfig::default(), // no limit
         transfer_last: None,
+        sync_metadata: None,
+        inventory_only: None,
+        notify: None,
+        notify_user: None,
     };
 
     // should work without ACLs
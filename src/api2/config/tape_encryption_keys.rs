@@ -6,8 +6,8 @@ use proxmox_router::{http_bail, ApiMethod, Permission, Router, RpcEnvironment};
 use proxmox_schema::{api, param_bail};
 
 use pbs_api_types::{
-    Authid, Fingerprint, Kdf, KeyInfo, PASSWORD_HINT_SCHEMA, PRIV_TAPE_AUDIT, PRIV_TAPE_MODIFY,
-    PROXMOX_CONFIG_DIGEST_SCHEMA, TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+    Authid, Fingerprint, Kdf, KeyInfo, KMS_ID_SCHEMA, PASSWORD_HINT_SCHEMA, PRIV_TAPE_AUDIT,
+    PRIV_TAPE_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA, TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
 };
 
 use pbs_config::CachedUserInfo;
@@ -16,7 +16,8 @@ use pbs_config::open_backup_lockfile;
 use pbs_key_config::KeyConfig;
 
 use crate::tape::encryption_keys::{
-    insert_key, load_key_configs, load_keys, save_key_configs, save_keys, TAPE_KEYS_LOCKFILE,
+    insert_key, load_key, load_key_configs, load_keys, save_key_configs, save_keys,
+    TAPE_KEYS_LOCKFILE,
 };
 
 #[api(
@@ -144,16 +145,9 @@ pub fn change_passphrase(
         (false, None) => param_bail!("password", format_err!("missing parameter: password")),
         (false, Some(pass)) => key_config.decrypt(&|| Ok(pass.as_bytes().to_vec()))?,
         (true, None) => {
-            let key = load_keys()?
-                .0
-                .get(&fingerprint)
-                .ok_or_else(|| {
-                    format_err!(
-                        "failed to reset passphrase, could not find key '{}'",
-                        fingerprint
-                    )
-                })?
-                .key;
+            let key = load_key(&fingerprint).map_err(|err| {
+                format_err!("failed to reset passphrase, could not find key - {}", err)
+            })?;
 
             (key, key_config.created, fingerprint)
         }
@@ -193,6 +187,10 @@ pub fn change_passphrase(
                 max_length: 600,
                 optional: true,
             },
+            kms: {
+                schema: KMS_ID_SCHEMA,
+                optional: true,
+            },
         },
     },
     returns: {
@@ -203,11 +201,15 @@ pub fn change_passphrase(
     },
 )]
 /// Create a new encryption key
+///
+/// If `kms` is set, the plain key is stored in that key management service instead of the
+/// local key file.
 pub fn create_key(
     kdf: Option<Kdf>,
     password: String,
     hint: Option<String>,
     key: Option<String>,
+    kms: Option<String>,
     _rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Fingerprint, Error> {
     let kdf = kdf.unwrap_or_default();
@@ -240,7 +242,7 @@ pub fn create_key(
     }
 
     let fingerprint = key_config.fingerprint.clone().unwrap();
-    insert_key(key_decrypt, key_config, false)?;
+    insert_key(key_decrypt, key_config, false, kms.as_deref())?;
 
     Ok(fingerprint)
 }
@@ -149,6 +149,10 @@ pub enum DeletableProperty {
     Ns,
     /// Delete max-depth property, defaulting to full recursion again
     MaxDepth,
+    /// Delete the notify property, inheriting the datastore's setting.
+    Notify,
+    /// Delete the notify-user property, inheriting the datastore's setting.
+    NotifyUser,
 }
 
 #[api(
@@ -229,6 +233,12 @@ pub fn update_verification_job(
                 DeletableProperty::MaxDepth => {
                     data.max_depth = None;
                 }
+                DeletableProperty::Notify => {
+                    data.notify = None;
+                }
+                DeletableProperty::NotifyUser => {
+                    data.notify_user = None;
+                }
             }
         }
     }
@@ -252,6 +262,12 @@ pub fn update_verification_job(
     if update.outdated_after.is_some() {
         data.outdated_after = update.outdated_after;
     }
+    if update.notify.is_some() {
+        data.notify = update.notify;
+    }
+    if update.notify_user.is_some() {
+        data.notify_user = update.notify_user;
+    }
     let schedule_changed = data.schedule != update.schedule;
     if update.schedule.is_some() {
         data.schedule = update.schedule;
@@ -0,0 +1,177 @@
+//! Health check for load balancers and monitoring probes.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{format_err, Error};
+use lazy_static::lazy_static;
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::{
+    Authid, DataStoreConfig, HealthComponent, HealthState, HealthStatus, PRIV_SYS_AUDIT,
+};
+use pbs_config::CachedUserInfo;
+
+/// The scheduler runs every minute, allow for some delay caused by load.
+const SCHEDULER_MAX_DELAY: i64 = 3 * 60;
+
+fn check_config() -> Result<(), Error> {
+    crate::config::node::config()?;
+    pbs_config::datastore::config()?;
+    pbs_config::user::cached_config()?;
+    pbs_config::acl::cached_config()?;
+    Ok(())
+}
+
+fn check_scheduler() -> Result<(), Error> {
+    let last = crate::server::last_scheduler_heartbeat()?
+        .ok_or_else(|| format_err!("task scheduler did not run yet"))?;
+    let delay = proxmox_time::epoch_i64() - last;
+    if delay > SCHEDULER_MAX_DELAY {
+        return Err(format_err!(
+            "task scheduler did not run for {delay} seconds"
+        ));
+    }
+    Ok(())
+}
+
+fn probe_datastore_writable(store: &DataStoreConfig) -> Result<(), Error> {
+    static PROBE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    // unique per request, so concurrent probes do not remove each other's files
+    let counter = PROBE_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let path =
+        Path::new(&store.path).join(format!(".health-check-{}-{counter}", std::process::id()));
+    std::fs::write(&path, b"")
+        .map_err(|err| format_err!("unable to write to {:?} - {err}", store.path))?;
+    std::fs::remove_file(&path).map_err(|err| format_err!("unable to remove {path:?} - {err}"))?;
+    Ok(())
+}
+
+/// Probe results are reused for this long, so that (unauthenticated) health requests cannot
+/// cause arbitrary write load on the datastores.
+const DATASTORE_PROBE_INTERVAL: i64 = 30;
+
+fn check_datastore_writable(store: &DataStoreConfig) -> Result<(), Error> {
+    lazy_static! {
+        static ref CACHE: Mutex<HashMap<String, (i64, Result<(), String>)>> =
+            Mutex::new(HashMap::new());
+    }
+
+    let now = proxmox_time::epoch_i64();
+    if let Some((checked, result)) = CACHE.lock().unwrap().get(&store.name) {
+        if (now - checked).abs() < DATASTORE_PROBE_INTERVAL {
+            return result.clone().map_err(|err| format_err!("{err}"));
+        }
+    }
+
+    let result = probe_datastore_writable(store).map_err(|err| err.to_string());
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(store.name.clone(), (now, result.clone()));
+    result.map_err(|err| format_err!("{err}"))
+}
+
+fn component(name: &str, result: Result<(), Error>, details: bool) -> HealthComponent {
+    match result {
+        Ok(()) => HealthComponent {
+            name: name.to_string(),
+            status: HealthState::Ok,
+            message: None,
+        },
+        Err(err) => HealthComponent {
+            name: name.to_string(),
+            status: HealthState::Error,
+            message: details.then(|| err.to_string()),
+        },
+    }
+}
+
+#[api(
+    input: {
+        properties: {
+            strict: {
+                description: "Fail with HTTP status 503 if any component is unhealthy.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    returns: {
+        type: HealthStatus,
+    },
+    access: {
+        description: "Anyone can access this, as it is used by load balancers. Datastore names \
+            and error messages are only included for users with Sys.Audit on /system/status.",
+        permission: &Permission::World,
+    }
+)]
+/// Check whether the configuration is readable, the task scheduler is alive and all datastores
+/// (not in a maintenance mode) are writable.
+pub fn health(strict: bool, rpcenv: &mut dyn RpcEnvironment) -> Result<HealthStatus, Error> {
+    let details = match rpcenv.get_auth_id() {
+        Some(auth_id) => {
+            let auth_id: Authid = auth_id.parse()?;
+            let user_info = CachedUserInfo::new()?;
+            user_info.lookup_privs(&auth_id, &["system", "status"]) & PRIV_SYS_AUDIT != 0
+        }
+        None => false,
+    };
+
+    let mut components = vec![
+        component("config", check_config(), details),
+        component("scheduler", check_scheduler(), details),
+    ];
+
+    let stores: Result<Vec<DataStoreConfig>, Error> = pbs_config::datastore::config()
+        .and_then(|(config, _digest)| config.convert_to_typed_array("datastore"));
+
+    let mut failed_stores = 0;
+    for store in stores.unwrap_or_default() {
+        if store.get_maintenance_mode().is_some() {
+            continue;
+        }
+        let result = check_datastore_writable(&store);
+        if details {
+            components.push(component(
+                &format!("datastore/{}", store.name),
+                result,
+                true,
+            ));
+        } else if result.is_err() {
+            failed_stores += 1;
+        }
+    }
+    if !details {
+        let result = match failed_stores {
+            0 => Ok(()),
+            n => Err(format_err!("{n} datastore(s) not writable")),
+        };
+        components.push(component("datastores", result, false));
+    }
+
+    let status = if components.iter().all(|c| c.status == HealthState::Ok) {
+        HealthState::Ok
+    } else {
+        HealthState::Error
+    };
+
+    if strict && status != HealthState::Ok {
+        let failed: Vec<&str> = components
+            .iter()
+            .filter(|c| c.status != HealthState::Ok)
+            .map(|c| c.name.as_str())
+            .collect();
+        http_bail!(SERVICE_UNAVAILABLE, "unhealthy: {}", failed.join(", "));
+    }
+
+    Ok(HealthStatus { status, components })
+}
+
+pub const ROUTER: Router = Router::new().get(&API_METHOD_HEALTH);
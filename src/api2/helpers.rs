@@ -1,13 +1,108 @@
-use std::path::PathBuf;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
 
-use anyhow::Error;
+use anyhow::{format_err, Error};
 use futures::stream::TryStreamExt;
-use hyper::{header, Body, Response, StatusCode};
+use hyper::{header, Body, HeaderMap, Response, StatusCode};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
-use proxmox_router::http_bail;
+use proxmox_router::{http_bail, http_err};
+
+use pbs_datastore::at_rest::read_at_rest;
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::manifest::{archive_type, ArchiveType};
+use pbs_datastore::DataStore;
+
+/// Returns the correlation ID sent by the client, if any.
+pub fn correlation_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(pbs_client::CORRELATION_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| pbs_client::is_valid_correlation_id(id))
+}
 
 pub async fn create_download_response(path: PathBuf) -> Result<Response<Body>, Error> {
-    let file = match tokio::fs::File::open(path.clone()).await {
+    create_ranged_download_response(path, None).await
+}
+
+/// Byte range of a partial download, `end` is exclusive.
+#[derive(Debug, PartialEq, Eq)]
+struct ByteRange {
+    start: u64,
+    end: u64,
+}
+
+/// Parse a `Range` header for a file of `size` bytes.
+///
+/// Only single byte ranges are supported, for anything else `Ok(None)` is returned and the whole
+/// file is sent. Unsatisfiable ranges are an error.
+fn parse_range(value: &str, size: u64) -> Result<Option<ByteRange>, Error> {
+    let spec = match value.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => ByteRange {
+            start,
+            end: end.saturating_add(1).min(size),
+        },
+        (Ok(start), Err(_)) if end.is_empty() => ByteRange { start, end: size },
+        (Err(_), Ok(suffix)) if start.is_empty() => ByteRange {
+            start: size.saturating_sub(suffix),
+            end: size,
+        },
+        _ => return Ok(None),
+    };
+
+    if range.start >= range.end {
+        http_bail!(RANGE_NOT_SATISFIABLE, "requested range not satisfiable");
+    }
+
+    Ok(Some(range))
+}
+
+fn requested_range(headers: &HeaderMap, size: u64) -> Result<Option<ByteRange>, Error> {
+    match headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(value) => parse_range(value, size),
+        None => Ok(None),
+    }
+}
+
+fn download_response_builder(
+    range: &Option<ByteRange>,
+    size: u64,
+) -> hyper::http::response::Builder {
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::ACCEPT_RANGES, "bytes");
+
+    match range {
+        Some(range) => builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+            )
+            .header(header::CONTENT_LENGTH, range.end - range.start),
+        None => builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, size),
+    }
+}
+
+async fn create_ranged_download_response(
+    path: PathBuf,
+    headers: Option<&HeaderMap>,
+) -> Result<Response<Body>, Error> {
+    let mut file = match tokio::fs::File::open(path.clone()).await {
         Ok(file) => file,
         Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
             http_bail!(NOT_FOUND, "open file {:?} failed - not found", path);
@@ -15,15 +110,149 @@ pub async fn create_download_response(path: PathBuf) -> Result<Response<Body>, E
         Err(err) => http_bail!(BAD_REQUEST, "open file {:?} failed: {}", path, err),
     };
 
-    let payload = tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new())
+    let size = file.metadata().await?.len();
+    let range = match headers {
+        Some(headers) => requested_range(headers, size)?,
+        None => None,
+    };
+
+    let reader = match &range {
+        Some(range) => {
+            file.seek(SeekFrom::Start(range.start)).await?;
+            file.take(range.end - range.start)
+        }
+        None => file.take(size),
+    };
+
+    let payload = tokio_util::codec::FramedRead::new(reader, tokio_util::codec::BytesCodec::new())
         .map_ok(|bytes| bytes.freeze());
 
     let body = Body::wrap_stream(payload);
 
-    // fixme: set other headers ?
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/octet-stream")
-        .body(body)
-        .unwrap())
+    Ok(download_response_builder(&range, size).body(body).unwrap())
+}
+
+/// Entity tag of a snapshot file, matching its checksum in the manifest: the index checksum for
+/// index files and the SHA-256 of the (decoded) content for blobs.
+fn snapshot_file_etag(
+    datastore: &DataStore,
+    path: &Path,
+    data: Option<&[u8]>,
+) -> Result<String, Error> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format_err!("invalid file name {path:?}"))?;
+
+    let csum = match (archive_type(file_name)?, data) {
+        (ArchiveType::FixedIndex, _) => datastore.open_fixed_reader(path)?.compute_csum().0,
+        (ArchiveType::DynamicIndex, _) => datastore.open_dynamic_reader(path)?.compute_csum().0,
+        (ArchiveType::Blob, Some(data)) => openssl::sha::sha256(data),
+        (ArchiveType::Blob, None) => openssl::sha::sha256(&std::fs::read(path)?),
+    };
+
+    Ok(format!("\"{}\"", hex::encode(csum)))
+}
+
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    match headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(value) => value
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag),
+        None => false,
+    }
+}
+
+/// Download response for a file of a snapshot, removing the datastore's at-rest encryption layer
+/// from blobs.
+///
+/// Supports conditional (`If-None-Match`) and partial (`Range`) requests. The entity tag of a file
+/// is its checksum as listed in the manifest.
+pub async fn create_snapshot_file_download_response(
+    datastore: &DataStore,
+    path: PathBuf,
+    headers: &HeaderMap,
+) -> Result<Response<Body>, Error> {
+    let crypt = match datastore.at_rest_crypt() {
+        Some(crypt) if path.extension() == Some("blob".as_ref()) => Some(crypt),
+        _ => None,
+    };
+
+    let data = match crypt {
+        Some(crypt) => Some(
+            proxmox_async::runtime::block_in_place(|| read_at_rest(Some(&crypt), &path))
+                .map_err(|err| http_err!(BAD_REQUEST, "reading file {:?} failed: {}", path, err))?,
+        ),
+        None => None,
+    };
+
+    let etag = match path.exists() {
+        true => proxmox_async::runtime::block_in_place(|| {
+            snapshot_file_etag(datastore, &path, data.as_deref())
+        })
+        .ok(),
+        false => None,
+    };
+
+    if let Some(etag) = &etag {
+        if etag_matches(headers, etag) {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header(header::ETAG, etag)
+                .body(Body::empty())
+                .unwrap());
+        }
+    }
+
+    let mut response = match data {
+        Some(data) => {
+            let size = data.len() as u64;
+            let range = requested_range(headers, size)?;
+            let body = match &range {
+                Some(range) => data[range.start as usize..range.end as usize].to_vec(),
+                None => data,
+            };
+            download_response_builder(&range, size)
+                .body(Body::from(body))
+                .unwrap()
+        }
+        None => create_ranged_download_response(path, Some(headers)).await?,
+    };
+
+    if let Some(etag) = etag {
+        response
+            .headers_mut()
+            .insert(header::ETAG, header::HeaderValue::from_str(&etag)?);
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        let range = |start, end| Some(ByteRange { start, end });
+
+        assert_eq!(parse_range("bytes=0-99", 1000).unwrap(), range(0, 100));
+        assert_eq!(parse_range("bytes=500-", 1000).unwrap(), range(500, 1000));
+        assert_eq!(parse_range("bytes=-100", 1000).unwrap(), range(900, 1000));
+        assert_eq!(
+            parse_range("bytes=900-2000", 1000).unwrap(),
+            range(900, 1000)
+        );
+        assert_eq!(
+            parse_range("bytes=0-18446744073709551615", 1000).unwrap(),
+            range(0, 1000)
+        );
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000).unwrap(), None);
+        assert_eq!(parse_range("items=0-1", 1000).unwrap(), None);
+        assert!(parse_range("bytes=1000-", 1000).is_err());
+    }
 }
@@ -6,6 +6,7 @@ pub mod access;
 pub mod admin;
 pub mod backup;
 pub mod config;
+pub mod health;
 pub mod helpers;
 pub mod node;
 pub mod ping;
@@ -24,6 +25,7 @@ const SUBDIRS: SubdirMap = &sorted!([
     ("admin", &admin::ROUTER),
     ("backup", &backup::ROUTER),
     ("config", &config::ROUTER),
+    ("health", &health::ROUTER),
     ("nodes", &node::ROUTER),
     ("ping", &ping::ROUTER),
     ("pull", &pull::ROUTER),
@@ -67,6 +67,38 @@ pub enum DeletableProperty {
     Description,
     /// Delete the task-log-max-days property
     TaskLogMaxDays,
+    /// Delete the task-log-max-files property
+    TaskLogMaxFiles,
+    /// Delete the max-sessions-per-authid property
+    MaxSessionsPerAuthid,
+    /// Delete the max-sessions-per-ip property
+    MaxSessionsPerIp,
+    /// Delete the reader-rate-per-authid property
+    ReaderRatePerAuthid,
+    /// Delete the upload-buffer-limit property
+    UploadBufferLimit,
+    /// Delete the upload-buffer-per-session property
+    UploadBufferPerSession,
+    /// Delete the structured-access-log property
+    StructuredAccessLog,
+    /// Delete the access-log-anonymize-ip property
+    AccessLogAnonymizeIp,
+    /// Delete the safe-mode property
+    SafeMode,
+    /// Delete the dr-export property
+    DrExport,
+    /// Delete the failover property
+    Failover,
+    /// Delete the task-io-priority property
+    TaskIoPriority,
+    /// Delete the task-limits-gc property
+    TaskLimitsGc,
+    /// Delete the task-limits-verify property
+    TaskLimitsVerify,
+    /// Delete the task-limits-tape property
+    TaskLimitsTape,
+    /// Delete the custom-backup-types property
+    CustomBackupTypes,
 }
 
 #[api(
@@ -155,6 +187,54 @@ pub fn update_node_config(
                 DeletableProperty::TaskLogMaxDays => {
                     config.task_log_max_days = None;
                 }
+                DeletableProperty::TaskLogMaxFiles => {
+                    config.task_log_max_files = None;
+                }
+                DeletableProperty::MaxSessionsPerAuthid => {
+                    config.max_sessions_per_authid = None;
+                }
+                DeletableProperty::MaxSessionsPerIp => {
+                    config.max_sessions_per_ip = None;
+                }
+                DeletableProperty::ReaderRatePerAuthid => {
+                    config.reader_rate_per_authid = None;
+                }
+                DeletableProperty::UploadBufferLimit => {
+                    config.upload_buffer_limit = None;
+                }
+                DeletableProperty::UploadBufferPerSession => {
+                    config.upload_buffer_per_session = None;
+                }
+                DeletableProperty::StructuredAccessLog => {
+                    config.structured_access_log = None;
+                }
+                DeletableProperty::AccessLogAnonymizeIp => {
+                    config.access_log_anonymize_ip = None;
+                }
+                DeletableProperty::SafeMode => {
+                    config.safe_mode = None;
+                }
+                DeletableProperty::DrExport => {
+                    config.dr_export = None;
+                }
+                DeletableProperty::Failover => {
+                    config.failover = None;
+                }
+                DeletableProperty::TaskIoPriority => {
+                    config.task_io_priority = None;
+                }
+                DeletableProperty::TaskLimitsGc => {
+                    config.task_limits_gc = None;
+                }
+                DeletableProperty::TaskLimitsVerify => {
+                    config.task_limits_verify = None;
+                }
+                DeletableProperty::TaskLimitsTape => {
+                    config.task_limits_tape = None;
+                }
+                DeletableProperty::CustomBackupTypes => {
+                    config.custom_backup_types = None;
+                }
             }
         }
     }
@@ -198,6 +278,54 @@ pub fn update_node_config(
     if update.task_log_max_days.is_some() {
         config.task_log_max_days = update.task_log_max_days;
     }
+    if update.task_log_max_files.is_some() {
+        config.task_log_max_files = update.task_log_max_files;
+    }
+    if update.max_sessions_per_authid.is_some() {
+        config.max_sessions_per_authid = update.max_sessions_per_authid;
+    }
+    if update.max_sessions_per_ip.is_some() {
+        config.max_sessions_per_ip = update.max_sessions_per_ip;
+    }
+    if update.reader_rate_per_authid.is_some() {
+        config.reader_rate_per_authid = update.reader_rate_per_authid;
+    }
+    if update.upload_buffer_limit.is_some() {
+        config.upload_buffer_limit = update.upload_buffer_limit;
+    }
+    if update.upload_buffer_per_session.is_some() {
+        config.upload_buffer_per_session = update.upload_buffer_per_session;
+    }
+    if update.structured_access_log.is_some() {
+        config.structured_access_log = update.structured_access_log;
+    }
+    if update.access_log_anonymize_ip.is_some() {
+        config.access_log_anonymize_ip = update.access_log_anonymize_ip;
+    }
+    if update.safe_mode.is_some() {
+        config.safe_mode = update.safe_mode;
+    }
+    if update.dr_export.is_some() {
+        config.dr_export = update.dr_export;
+    }
+    if update.failover.is_some() {
+        config.failover = update.failover;
+    }
+    if update.task_io_priority.is_some() {
+        config.task_io_priority = update.task_io_priority;
+    }
+    if update.task_limits_gc.is_some() {
+        config.task_limits_gc = update.task_limits_gc;
+    }
+    if update.task_limits_verify.is_some() {
+        config.task_limits_verify = update.task_limits_verify;
+    }
+    if update.task_limits_tape.is_some() {
+        config.task_limits_tape = update.task_limits_tape;
+    }
+    if update.custom_backup_types.is_some() {
+        config.custom_backup_types = update.custom_backup_types;
+    }
 
     crate::config::node::save_config(&config)?;
 
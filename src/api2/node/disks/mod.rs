@@ -20,6 +20,7 @@ use crate::tools::disks::{
 use proxmox_rest_server::WorkerTask;
 
 pub mod directory;
+pub mod network_share;
 pub mod zfs;
 
 #[api(
@@ -228,6 +229,7 @@ pub fn wipe_disk(disk: String, rpcenv: &mut dyn RpcEnvironment) -> Result<Value,
 const SUBDIRS: SubdirMap = &sorted!([
     //    ("lvm", &lvm::ROUTER),
     ("directory", &directory::ROUTER),
+    ("network-share", &network_share::ROUTER),
     ("zfs", &zfs::ROUTER),
     ("initgpt", &Router::new().post(&API_METHOD_INITIALIZE_DISK)),
     ("list", &Router::new().get(&API_METHOD_LIST_DISKS)),
@@ -0,0 +1,86 @@
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router};
+use proxmox_schema::api;
+
+use pbs_api_types::{
+    NetworkShareConfig, NetworkShareStatus, NETWORK_SHARE_ID_SCHEMA, NODE_SCHEMA, PRIV_SYS_AUDIT,
+    PRIV_SYS_MODIFY,
+};
+use pbs_datastore::network_share::check_network_share;
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            node: {
+                schema: NODE_SCHEMA,
+            },
+        }
+    },
+    returns: {
+        description: "Mount status of the configured network shares.",
+        type: Array,
+        items: {
+            type: NetworkShareStatus,
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "disks"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// List network shares with their mount status. Unmounted shares get mounted by this.
+pub fn list_network_share_status() -> Result<Vec<NetworkShareStatus>, Error> {
+    let (config, _digest) = pbs_config::network_share::config()?;
+    let shares: Vec<NetworkShareConfig> = config.convert_to_typed_array("share")?;
+
+    let list = shares
+        .into_iter()
+        .map(|share| {
+            let error = check_network_share(&share).err().map(|err| err.to_string());
+            NetworkShareStatus {
+                source: share.mount_source(),
+                mount_point: share.mount_point(),
+                available: error.is_none(),
+                error,
+                name: share.name,
+                share_type: share.share_type,
+            }
+        })
+        .collect();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            node: {
+                schema: NODE_SCHEMA,
+            },
+            name: {
+                schema: NETWORK_SHARE_ID_SCHEMA,
+            },
+        }
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "disks"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Unmount a network share and mount it again, for example after its server was unreachable.
+pub fn remount_network_share(name: String) -> Result<(), Error> {
+    let (config, _digest) = pbs_config::network_share::config()?;
+    let share: NetworkShareConfig = config.lookup("share", &name)?;
+
+    crate::tools::network_share::unmount(&share)?;
+    check_network_share(&share)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new().post(&API_METHOD_REMOUNT_NETWORK_SHARE);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_NETWORK_SHARE_STATUS)
+    .match_all("name", &ITEM_ROUTER);
@@ -36,6 +36,7 @@ pub mod disks;
 pub mod dns;
 pub mod network;
 pub mod subscription;
+pub mod task_archive;
 pub mod tasks;
 
 pub(crate) mod rrd;
@@ -335,6 +336,7 @@ pub const SUBDIRS: SubdirMap = &[
     ("status", &status::ROUTER),
     ("subscription", &subscription::ROUTER),
     ("syslog", &syslog::ROUTER),
+    ("task-archive", &task_archive::ROUTER),
     ("tasks", &tasks::ROUTER),
     ("termproxy", &Router::new().post(&API_METHOD_TERMPROXY)),
     ("time", &time::ROUTER),
@@ -0,0 +1,52 @@
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType};
+use proxmox_schema::api;
+
+use pbs_api_types::{NODE_SCHEMA, PRIV_SYS_MODIFY, UPID_SCHEMA};
+
+use crate::server::task_archive::do_purge_task_archive;
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            node: {
+                schema: NODE_SCHEMA,
+            },
+            "max-days": {
+                description: "Remove tasks older than this many days. Defaults to \
+                    'task-log-max-days' of the node configuration.",
+                type: Integer,
+                minimum: 0,
+                optional: true,
+            },
+            "max-files": {
+                description: "Maximum number of rotated archive files to keep. Defaults to \
+                    'task-log-max-files' of the node configuration.",
+                type: Integer,
+                minimum: 1,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "tasks"], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Rotate the task archive now and remove old entries and their task logs.
+pub fn purge_task_archive(
+    max_days: Option<usize>,
+    max_files: Option<usize>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id = rpcenv.get_auth_id().unwrap();
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    do_purge_task_archive(auth_id, max_days, max_files, to_stdout)
+}
+
+pub const ROUTER: Router = Router::new().delete(&API_METHOD_PURGE_TASK_ARCHIVE);
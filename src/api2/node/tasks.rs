@@ -444,6 +444,98 @@ fn stop_task(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Err
     Ok(Value::Null)
 }
 
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            node: {
+                schema: NODE_SCHEMA,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            userfilter: {
+                optional: true,
+                type: String,
+                description: "Only stop tasks from this user.",
+            },
+            typefilter: {
+                optional: true,
+                type: String,
+                description: "Only stop tasks whose type contains this.",
+            },
+        },
+    },
+    returns: {
+        description: "List of tasks which were requested to stop.",
+        type: Array,
+        items: {
+            schema: UPID_SCHEMA,
+        },
+    },
+    access: {
+        description: "Users can stop their own tasks, or need Sys.Modify on /system/tasks. \
+            Tasks which may not be stopped are skipped.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Try to stop all running tasks matching the filters.
+fn stop_tasks(
+    userfilter: Option<String>,
+    typefilter: Option<String>,
+    param: Value,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<String>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+    let user_privs = user_info.lookup_privs(&auth_id, &["system", "tasks"]);
+
+    let stop_all = (user_privs & PRIV_SYS_MODIFY) != 0;
+
+    let store = param["store"].as_str();
+
+    let mut stopped = Vec::new();
+
+    for info in TaskListInfoIterator::new(true)? {
+        let info = match info {
+            Ok(info) => info,
+            Err(_) => break,
+        };
+
+        if info.state.is_some() {
+            continue;
+        }
+
+        if !stop_all && auth_id != info.upid.auth_id {
+            continue;
+        }
+
+        if let Some(needle) = &userfilter {
+            if !info.upid.auth_id.to_string().contains(needle) {
+                continue;
+            }
+        }
+
+        if let Some(store) = store {
+            if !check_job_store(&info.upid, store) {
+                continue;
+            }
+        }
+
+        if let Some(typefilter) = &typefilter {
+            if !info.upid.worker_type.contains(typefilter) {
+                continue;
+            }
+        }
+
+        stopped.push(info.upid_str);
+        proxmox_rest_server::abort_worker_nowait(info.upid);
+    }
+
+    Ok(stopped)
+}
+
 #[api(
     streaming: true,
     input: {
@@ -642,4 +734,5 @@ pub const UPID_API_ROUTER: Router = Router::new()
 
 pub const ROUTER: Router = Router::new()
     .get(&API_METHOD_LIST_TASKS)
+    .delete(&API_METHOD_STOP_TASKS)
     .match_all("upid", &UPID_API_ROUTER);
@@ -9,7 +9,8 @@ use proxmox_sys::task_log;
 use pbs_api_types::{
     Authid, BackupNamespace, GroupFilter, RateLimitConfig, SyncJobConfig, DATASTORE_SCHEMA,
     GROUP_FILTER_LIST_SCHEMA, NS_MAX_DEPTH_REDUCED_SCHEMA, PRIV_DATASTORE_BACKUP,
-    PRIV_DATASTORE_PRUNE, PRIV_REMOTE_READ, REMOTE_ID_SCHEMA, REMOVE_VANISHED_BACKUPS_SCHEMA,
+    PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE, PRIV_REMOTE_READ, REMOTE_ID_SCHEMA,
+    REMOVE_VANISHED_BACKUPS_SCHEMA, SYNC_INVENTORY_ONLY_SCHEMA, SYNC_METADATA_SCHEMA,
     TRANSFER_LAST_SCHEMA,
 };
 use pbs_config::CachedUserInfo;
@@ -17,7 +18,7 @@ use proxmox_human_byte::HumanByte;
 use proxmox_rest_server::WorkerTask;
 
 use crate::server::jobstate::Job;
-use crate::server::pull::{pull_store, PullParameters};
+use crate::server::pull::{compare_store, pull_store, PullParameters};
 
 pub fn check_pull_privs(
     auth_id: &Authid,
@@ -89,6 +90,8 @@ impl TryFrom<&SyncJobConfig> for PullParameters {
             sync_job.group_filter.clone(),
             sync_job.limit.clone(),
             sync_job.transfer_last,
+            sync_job.sync_metadata.unwrap_or(false),
+            sync_job.inventory_only.unwrap_or(false),
         )
     }
 }
@@ -114,6 +117,10 @@ pub fn do_sync_job(
         bail!("can't sync to same datastore");
     }
 
+    if !crate::server::failover::sync_job_allowed(job.jobname())? {
+        bail!("sync job pulls from the failover peer, it only runs while this node is the standby");
+    }
+
     let upid_str = WorkerTask::spawn(
         &worker_type,
         Some(job_id.clone()),
@@ -203,6 +210,52 @@ pub fn do_sync_job(
     Ok(upid_str)
 }
 
+/// Compares the snapshots synced by `sync_job` with their copies on the source.
+///
+/// Only manifests and index checksums get compared, so this is a cheap check for silent
+/// corruption on either side which does not need to read any chunk.
+pub fn do_sync_compare(
+    sync_job: SyncJobConfig,
+    auth_id: &Authid,
+    to_stdout: bool,
+) -> Result<String, Error> {
+    let upid_str = WorkerTask::spawn(
+        "synccompare",
+        Some(sync_job.id.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| async move {
+            let pull_params = PullParameters::try_from(&sync_job)?;
+
+            task_log!(
+                worker,
+                "comparing datastore '{}' with '{}{}'",
+                sync_job.store,
+                sync_job
+                    .remote
+                    .as_deref()
+                    .map_or(String::new(), |remote| format!("{remote}/")),
+                sync_job.remote_store,
+            );
+
+            let (compared, diverged) = compare_store(&worker, &pull_params).await?;
+
+            task_log!(worker, "compared {compared} snapshots");
+            if !diverged.is_empty() {
+                task_log!(worker, "the following snapshots diverge:");
+                for snapshot in &diverged {
+                    task_log!(worker, "\t{snapshot}");
+                }
+                bail!("{} snapshots diverge from the source", diverged.len());
+            }
+
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
 #[api(
     input: {
         properties: {
@@ -244,13 +297,22 @@ pub fn do_sync_job(
                 schema: TRANSFER_LAST_SCHEMA,
                 optional: true,
             },
+            "sync-metadata": {
+                schema: SYNC_METADATA_SCHEMA,
+                optional: true,
+            },
+            "inventory-only": {
+                schema: SYNC_INVENTORY_ONLY_SCHEMA,
+                optional: true,
+            },
         },
     },
     access: {
         // Note: used parameters are no uri parameters, so we need to test inside function body
         description: r###"The user needs Datastore.Backup privilege on '/datastore/{store}',
 and needs to own the backup group. Remote.Read is required on '/remote/{remote}/{remote-store}'.
-The delete flag additionally requires the Datastore.Prune privilege on '/datastore/{store}'.
+The delete flag additionally requires the Datastore.Prune privilege on '/datastore/{store}',
+syncing metadata the Datastore.Modify privilege.
 "###,
         permission: &Permission::Anybody,
     },
@@ -268,6 +330,8 @@ async fn pull(
     group_filter: Option<Vec<GroupFilter>>,
     limit: RateLimitConfig,
     transfer_last: Option<usize>,
+    sync_metadata: Option<bool>,
+    inventory_only: Option<bool>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<String, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
@@ -293,6 +357,12 @@ async fn pull(
         delete,
     )?;
 
+    let sync_metadata = sync_metadata.unwrap_or(false);
+    if sync_metadata {
+        let user_info = CachedUserInfo::new()?;
+        user_info.check_privs(&auth_id, &ns.acl_path(&store), PRIV_DATASTORE_MODIFY, false)?;
+    }
+
     let pull_params = PullParameters::new(
         &store,
         ns,
@@ -305,6 +375,8 @@ async fn pull(
         group_filter,
         limit,
         transfer_last,
+        sync_metadata,
+        inventory_only.unwrap_or(false),
     )?;
 
     // fixme: set to_stdout to false?
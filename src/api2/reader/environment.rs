@@ -1,4 +1,5 @@
 use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 
 use serde_json::{json, Value};
@@ -11,6 +12,9 @@ use pbs_datastore::DataStore;
 use proxmox_rest_server::formatter::*;
 use proxmox_rest_server::WorkerTask;
 
+use crate::server::inventory_fetch::InventoryFetcher;
+use crate::traffic_control_cache::SharedRateLimit;
+
 /// `RpcEnvironmet` implementation for backup reader service
 #[derive(Clone)]
 pub struct ReaderEnvironment {
@@ -23,6 +27,11 @@ pub struct ReaderEnvironment {
     pub datastore: Arc<DataStore>,
     pub backup_dir: BackupDir,
     allowed_chunks: Arc<RwLock<HashSet<[u8; 32]>>>,
+    rate_limiters: Vec<SharedRateLimit>,
+    /// bytes sent in this session, shown in the list of active sessions
+    pub transferred: Arc<AtomicU64>,
+    /// fetches missing data of snapshots synced in inventory mode
+    pub inventory: Option<Arc<InventoryFetcher>>,
 }
 
 impl ReaderEnvironment {
@@ -32,6 +41,7 @@ impl ReaderEnvironment {
         worker: Arc<WorkerTask>,
         datastore: Arc<DataStore>,
         backup_dir: BackupDir,
+        rate_limiters: Vec<SharedRateLimit>,
     ) -> Self {
         Self {
             result_attributes: json!({}),
@@ -43,6 +53,9 @@ impl ReaderEnvironment {
             formatter: JSON_FORMATTER,
             backup_dir,
             allowed_chunks: Arc::new(RwLock::new(HashSet::new())),
+            rate_limiters,
+            transferred: Arc::new(AtomicU64::new(0)),
+            inventory: None,
         }
     }
 
@@ -64,6 +77,12 @@ impl ReaderEnvironment {
     pub fn check_chunk_access(&self, digest: [u8; 32]) -> bool {
         self.allowed_chunks.read().unwrap().contains(&digest)
     }
+
+    /// Wait until the rate limits of the session allow sending `len` more bytes.
+    pub async fn throttle(&self, len: u64) {
+        self.transferred.fetch_add(len, Ordering::Relaxed);
+        crate::server::session_limits::throttle(&self.rate_limiters, len).await;
+    }
 }
 
 impl RpcEnvironment for ReaderEnvironment {
@@ -1,5 +1,7 @@
 //! Backup reader/restore protocol (HTTP2 upgrade)
 
+use std::sync::Arc;
+
 use anyhow::{bail, format_err, Error};
 use futures::*;
 use hex::FromHex;
@@ -17,9 +19,9 @@ use proxmox_schema::{BooleanSchema, ObjectSchema};
 use proxmox_sortable_macro::sortable;
 
 use pbs_api_types::{
-    Authid, Operation, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA,
-    BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, CHUNK_DIGEST_SCHEMA, DATASTORE_SCHEMA,
-    PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ,
+    Authid, Operation, SessionType, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA,
+    BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, CHUNK_DIGEST_SCHEMA,
+    DATASTORE_SCHEMA,
 };
 use pbs_config::CachedUserInfo;
 use pbs_datastore::index::IndexFile;
@@ -31,6 +33,8 @@ use proxmox_sys::fs::lock_dir_noblock_shared;
 
 use crate::api2::backup::optional_ns_param;
 use crate::api2::helpers;
+use crate::backup::{check_group_privs_with, GROUP_READ_PRIVS};
+use crate::server::inventory_fetch::InventoryFetcher;
 
 mod environment;
 use environment::*;
@@ -79,23 +83,21 @@ fn upgrade_to_backup_reader_protocol(
         let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
         let store = required_string_param(&param, "store")?.to_owned();
         let backup_ns = optional_ns_param(&param)?;
+        let backup_dir = pbs_api_types::BackupDir::deserialize(&param)?;
 
         let user_info = CachedUserInfo::new()?;
-        let acl_path = backup_ns.acl_path(&store);
-        let privs = user_info.lookup_privs(&auth_id, &acl_path);
-
-        let priv_read = privs & PRIV_DATASTORE_READ != 0;
-        let priv_backup = privs & PRIV_DATASTORE_BACKUP != 0;
-
-        // priv_backup needs owner check further down below!
-        if !priv_read && !priv_backup {
-            bail!("no permissions on /{}", acl_path.join("/"));
-        }
+        // limited access needs owner check further down below!
+        let priv_read = !check_group_privs_with(
+            &user_info,
+            &store,
+            &backup_ns,
+            &backup_dir.group,
+            &auth_id,
+            &GROUP_READ_PRIVS,
+        )?;
 
         let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
 
-        let backup_dir = pbs_api_types::BackupDir::deserialize(&param)?;
-
         let protocols = parts
             .headers
             .get("UPGRADE")
@@ -115,12 +117,13 @@ fn upgrade_to_backup_reader_protocol(
 
         let env_type = rpcenv.env_type();
 
+        let correlation_id =
+            crate::api2::helpers::correlation_id(&parts.headers).map(|id| id.to_string());
+
         let backup_dir = datastore.backup_dir(backup_ns, backup_dir)?;
         if !priv_read {
             let owner = backup_dir.get_owner()?;
-            let correct_owner = owner == auth_id
-                || (owner.is_token() && Authid::from(owner.user().clone()) == auth_id);
-            if !correct_owner {
+            if datastore.check_backup_owner(&owner, &auth_id).is_err() {
                 bail!("backup owner check failed!");
             }
         }
@@ -129,6 +132,12 @@ fn upgrade_to_backup_reader_protocol(
             bail!("snapshot {} does not exist.", backup_dir.dir());
         }
 
+        let session_guard = crate::server::session_limits::start_session(
+            &auth_id,
+            rpcenv.get_client_ip().map(|addr| addr.ip()),
+        )?;
+        let rate_limiters = crate::server::session_limits::reader_rate_limiters(&auth_id, &store);
+
         let _guard = lock_dir_noblock_shared(
             &backup_dir.full_path(),
             "snapshot",
@@ -155,6 +164,7 @@ fn upgrade_to_backup_reader_protocol(
             true,
             move |worker| async move {
                 let _guard = _guard;
+                let mut session_guard = session_guard;
 
                 let mut env = ReaderEnvironment::new(
                     env_type,
@@ -162,14 +172,33 @@ fn upgrade_to_backup_reader_protocol(
                     worker.clone(),
                     datastore,
                     backup_dir,
+                    rate_limiters,
                 );
 
                 env.debug = debug;
+                env.transferred =
+                    session_guard.register(&worker, SessionType::Reader, &env.backup_dir);
 
                 env.log(format!(
                     "starting new backup reader datastore '{}': {:?}",
                     store, path
                 ));
+                if let Some(id) = &correlation_id {
+                    env.log(format!("correlation ID: {id}"));
+                }
+
+                match InventoryFetcher::new(&env.backup_dir) {
+                    Ok(Some(fetcher)) => {
+                        env.log(format!(
+                            "snapshot was synced in inventory mode, missing data is fetched from \
+                            {}",
+                            fetcher.origin(),
+                        ));
+                        env.inventory = Some(Arc::new(fetcher));
+                    }
+                    Ok(None) => (),
+                    Err(err) => env.log(format!("unable to read inventory origin - {err}")),
+                }
 
                 let service =
                     H2Service::new(env.clone(), worker.clone(), &READER_API_ROUTER, debug);
@@ -244,7 +273,7 @@ pub const API_METHOD_DOWNLOAD_FILE: ApiMethod = ApiMethod::new(
 );
 
 fn download_file(
-    _parts: Parts,
+    parts: Parts,
     _req_body: Body,
     param: Value,
     _info: &ApiMethod,
@@ -261,6 +290,35 @@ fn download_file(
 
         env.log(format!("download {:?}", path.clone()));
 
+        if !path.exists() {
+            if let Some(fetcher) = &env.inventory {
+                env.log(format!("fetching '{file_name}' from {}", fetcher.origin()));
+                let (data, digests) = fetcher.fetch_index(&file_name).await?;
+                env.log(format!(
+                    "register chunks in '{}' as downloadable.",
+                    file_name
+                ));
+                for digest in digests {
+                    env.register_chunk(digest);
+                }
+                env.throttle(data.len() as u64).await;
+
+                return Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/octet-stream")
+                    .body(Body::from(data))
+                    .unwrap());
+            }
+        }
+
+        if !path.exists() && env.backup_dir.is_inventory_only() {
+            bail!(
+                "archive '{file_name}' is not available, only the inventory of snapshot {} was \
+                synced",
+                env.backup_dir.dir(),
+            );
+        }
+
         let index: Option<Box<dyn IndexFile + Send>> = match archive_type(&file_name)? {
             ArchiveType::FixedIndex => {
                 let index = env.datastore.open_fixed_reader(&path)?;
@@ -285,7 +343,11 @@ fn download_file(
             }
         }
 
-        helpers::create_download_response(path).await
+        if let Ok(metadata) = std::fs::metadata(&path) {
+            env.throttle(metadata.len()).await;
+        }
+
+        helpers::create_snapshot_file_download_response(&env.datastore, path, &parts.headers).await
     }
     .boxed()
 }
@@ -325,14 +387,22 @@ fn download_chunk(
         }
 
         let (path, _) = env.datastore.chunk_path(&digest);
-        let path2 = path.clone();
 
         env.debug(format!("download chunk {:?}", path));
 
-        let data =
-            proxmox_async::runtime::block_in_place(|| std::fs::read(path)).map_err(move |err| {
-                http_err!(BAD_REQUEST, "reading file {:?} failed: {}", path2, err)
-            })?;
+        let data = match &env.inventory {
+            Some(fetcher) if !path.exists() => {
+                fetcher.fetch_chunk(&digest).await.map_err(|err| {
+                    http_err!(BAD_REQUEST, "fetching chunk {digest_str} failed: {err}")
+                })?
+            }
+            _ => proxmox_async::runtime::block_in_place(|| env.datastore.read_raw_chunk(&digest))
+                .map_err(move |err| {
+                http_err!(BAD_REQUEST, "reading file {:?} failed: {}", path, err)
+            })?,
+        };
+
+        env.throttle(data.len() as u64).await;
 
         let body = Body::from(data);
 
@@ -22,7 +22,8 @@ use proxmox_rest_server::WorkerTask;
 use crate::tape::TapeNotificationMode;
 use crate::{
     server::{
-        jobstate::{compute_schedule_status, Job, JobState},
+        jobstate::{add_run_history, compute_schedule_status, Job, JobState},
+        task_cgroup::{enter_task_cgroup, TaskCgroup},
         TapeBackupJobSummary,
     },
     tape::{
@@ -99,7 +100,8 @@ pub fn list_tape_backup_jobs(
         let last_state = JobState::load("tape-backup-job", &job.id)
             .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
 
-        let status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+        let mut status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+        add_run_history(&mut status, "tape-backup-job", &job.id);
 
         let next_run = status.next_run.unwrap_or(current_time);
 
@@ -170,6 +172,7 @@ pub fn do_tape_backup_job(
         to_stdout,
         move |worker| {
             job.start(&worker.upid().to_string())?;
+            enter_task_cgroup(&worker, TaskCgroup::Tape);
             let mut drive_lock = drive_lock;
 
             let mut summary = Default::default();
@@ -326,6 +329,7 @@ pub fn backup(
         to_stdout,
         move |worker| {
             let _drive_lock = drive_lock; // keep lock guard
+            enter_task_cgroup(&worker, TaskCgroup::Tape);
             set_tape_device_state(&setup.drive, &worker.upid().to_string())?;
 
             let mut summary = Default::default();
@@ -578,6 +582,15 @@ fn backup_snapshot(
     let snapshot_path = snapshot.relative_path();
     task_log!(worker, "backup snapshot {:?}", snapshot_path);
 
+    if snapshot.is_inventory_only() {
+        task_log!(
+            worker,
+            "skipping snapshot {:?} - only its inventory was synced",
+            snapshot_path
+        );
+        return Ok(SnapshotBackupResult::Ignored);
+    }
+
     let snapshot_reader = match snapshot.locked_reader() {
         Ok(reader) => reader,
         Err(err) => {
@@ -638,7 +638,7 @@ pub async fn restore_key(drive: String, password: String) -> Result<(), Error> {
         if let Some(key_config) = key_config {
             let password_fn = || Ok(password.as_bytes().to_vec());
             let (key, ..) = key_config.decrypt(&password_fn)?;
-            insert_key(key, key_config, true)?;
+            insert_key(key, key_config, true, None)?;
         } else {
             bail!("media does not contain any encryption key configuration");
         }
@@ -35,6 +35,7 @@ use pbs_tape::{
 use proxmox_rest_server::WorkerTask;
 
 use crate::backup::check_ns_modification_privs;
+use crate::server::task_cgroup::{enter_task_cgroup, TaskCgroup};
 use crate::tape::TapeNotificationMode;
 use crate::{
     tape::{
@@ -397,6 +398,7 @@ pub fn restore(
         to_stdout,
         move |worker| {
             let _drive_lock = drive_lock; // keep lock guard
+            enter_task_cgroup(&worker, TaskCgroup::Tape);
 
             set_tape_device_state(&drive, &worker.upid().to_string())?;
 
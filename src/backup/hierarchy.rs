@@ -52,6 +52,96 @@ pub fn check_ns_privs_full(
     let acl_path = ns.acl_path(store);
     let privs = user_info.lookup_privs(auth_id, &acl_path);
 
+    check_privs_full(privs, &acl_path, full_access_privs, partial_access_privs)
+}
+
+/// Returns the privileges of `auth_id` on a backup group.
+///
+/// These are the privileges on the namespace, plus the ones granted on the ACL path of the group
+/// itself, `/datastore/{store}[/{namespace}]/@group/{type}/{id}`.
+pub fn lookup_group_privs(
+    user_info: &CachedUserInfo,
+    auth_id: &Authid,
+    store: &str,
+    ns: &BackupNamespace,
+    group: &pbs_api_types::BackupGroup,
+) -> u64 {
+    user_info.lookup_privs(auth_id, &ns.acl_path(store))
+        | user_info.lookup_privs(auth_id, &ns.group_acl_path(store, group))
+}
+
+/// Privileges required for an operation on a backup group, `full` for any group and `partial`
+/// for owned groups only.
+pub struct GroupPrivs {
+    pub full: u64,
+    pub partial: u64,
+}
+
+/// Creating backups, the owner is checked when locking the group.
+pub const GROUP_BACKUP_PRIVS: GroupPrivs = GroupPrivs {
+    full: 0,
+    partial: PRIV_DATASTORE_BACKUP,
+};
+
+/// Reading backups, for restores.
+pub const GROUP_READ_PRIVS: GroupPrivs = GroupPrivs {
+    full: PRIV_DATASTORE_READ,
+    partial: PRIV_DATASTORE_BACKUP,
+};
+
+/// Removing snapshots or whole groups.
+pub const GROUP_REMOVE_PRIVS: GroupPrivs = GroupPrivs {
+    full: PRIV_DATASTORE_MODIFY,
+    partial: PRIV_DATASTORE_PRUNE,
+};
+
+/// Changing the notes or the protection of snapshots and groups.
+pub const GROUP_MODIFY_PRIVS: GroupPrivs = GroupPrivs {
+    full: PRIV_DATASTORE_MODIFY,
+    partial: PRIV_DATASTORE_BACKUP,
+};
+
+/// Like [`check_ns_privs_full`], but also considers the privileges granted on the backup group.
+pub fn check_group_privs_full(
+    store: &str,
+    ns: &BackupNamespace,
+    group: &pbs_api_types::BackupGroup,
+    auth_id: &Authid,
+    full_access_privs: u64,
+    partial_access_privs: u64,
+) -> Result<bool, Error> {
+    let user_info = CachedUserInfo::new()?;
+    let privs = GroupPrivs {
+        full: full_access_privs,
+        partial: partial_access_privs,
+    };
+
+    check_group_privs_with(&user_info, store, ns, group, auth_id, &privs)
+}
+
+/// Like [`check_group_privs_full`], with the given user information.
+pub fn check_group_privs_with(
+    user_info: &CachedUserInfo,
+    store: &str,
+    ns: &BackupNamespace,
+    group: &pbs_api_types::BackupGroup,
+    auth_id: &Authid,
+    privs: &GroupPrivs,
+) -> Result<bool, Error> {
+    check_privs_full(
+        lookup_group_privs(user_info, auth_id, store, ns, group),
+        &ns.group_acl_path(store, group),
+        privs.full,
+        privs.partial,
+    )
+}
+
+fn check_privs_full(
+    privs: u64,
+    acl_path: &[&str],
+    full_access_privs: u64,
+    partial_access_privs: u64,
+) -> Result<bool, Error> {
     if full_access_privs != 0 && (privs & full_access_privs) != 0 {
         return Ok(false);
     }
@@ -103,8 +193,8 @@ pub struct ListAccessibleBackupGroups<'a> {
     override_owner_priv: u64,
     /// The priv that auth_id is required to have on NS level additionally to being owner
     owner_and_priv: u64,
-    /// Contains the intertnal state, group iter and a bool flag for override_owner_priv
-    state: Option<(ListGroups, bool)>,
+    /// Contains the intertnal state, group iter and the privs of auth_id on its NS
+    state: Option<(ListGroups, u64)>,
     ns_iter: ListNamespacesRecursive,
 }
 
@@ -150,24 +240,40 @@ impl<'a> Iterator for ListAccessibleBackupGroups<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            if let Some((ref mut state, override_owner)) = self.state {
+            if let Some((ref mut state, ns_privs)) = self.state {
                 match state.next() {
                     Some(Ok(group)) => {
-                        if override_owner {
-                            return Some(Ok(group));
+                        let auth_id = match &self.auth_id {
+                            Some(auth_id) => auth_id,
+                            None => return Some(Ok(group)),
+                        };
+
+                        // privileges can also be granted on the group itself
+                        let privs = ns_privs
+                            | self.user_info.lookup_privs(
+                                auth_id,
+                                &group
+                                    .backup_ns()
+                                    .group_acl_path(self.store.name(), group.group()),
+                            );
+
+                        if privs & NS_PRIVS_OK == 0 {
+                            continue;
                         }
-                        if let Some(auth_id) = &self.auth_id {
-                            match self
-                                .store
-                                .owns_backup(group.backup_ns(), group.group(), auth_id)
-                            {
-                                Ok(is_owner) if is_owner => return Some(Ok(group)),
-                                Ok(_) => continue,
-                                Err(err) => return Some(Err(err)),
-                            }
-                        } else {
+                        if privs & self.override_owner_priv != 0 {
                             return Some(Ok(group));
                         }
+                        if privs & self.owner_and_priv != self.owner_and_priv {
+                            continue; // no owner override and no extra privs -> not visible
+                        }
+                        match self
+                            .store
+                            .owns_backup(group.backup_ns(), group.group(), auth_id)
+                        {
+                            Ok(is_owner) if is_owner => return Some(Ok(group)),
+                            Ok(_) => continue,
+                            Err(err) => return Some(Err(err)),
+                        }
                     }
                     Some(Err(err)) => return Some(Err(err)),
                     None => {
@@ -177,25 +283,14 @@ impl<'a> Iterator for ListAccessibleBackupGroups<'a> {
             } else {
                 match self.ns_iter.next() {
                     Some(Ok(ns)) => {
-                        let mut override_owner = false;
-                        if let Some(auth_id) = &self.auth_id {
-                            let info = &self.user_info;
-
-                            let privs = info.lookup_privs(auth_id, &ns.acl_path(self.store.name()));
-
-                            if privs & NS_PRIVS_OK == 0 {
-                                continue;
-                            }
-
-                            // check first if *any* override owner priv is available up front
-                            if privs & self.override_owner_priv != 0 {
-                                override_owner = true;
-                            } else if privs & self.owner_and_priv != self.owner_and_priv {
-                                continue; // no owner override and no extra privs -> nothing visible
-                            }
-                        }
+                        let ns_privs = match &self.auth_id {
+                            Some(auth_id) => self
+                                .user_info
+                                .lookup_privs(auth_id, &ns.acl_path(self.store.name())),
+                            None => 0,
+                        };
                         self.state = match ListGroups::new(Arc::clone(self.store), ns) {
-                            Ok(iter) => Some((iter, override_owner)),
+                            Ok(iter) => Some((iter, ns_privs)),
                             Err(err) => return Some(Err(err)),
                         };
                     }
@@ -206,3 +301,37 @@ impl<'a> Iterator for ListAccessibleBackupGroups<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_restore_role_group_grant() -> Result<(), Error> {
+        let (user_cfg, _) = pbs_config::user::test_cfg_from_str("user: helpdesk@pbs\n")?;
+        let acl_tree = pbs_config::acl::AclTree::from_raw(
+            "acl:1:/datastore/store1/ns1/@group/vm/100:helpdesk@pbs:DatastoreRestore\n",
+        )?;
+        let user_info = CachedUserInfo::test_new(user_cfg, acl_tree);
+
+        let helpdesk: Authid = "helpdesk@pbs".parse()?;
+        let ns: BackupNamespace = "ns1".parse()?;
+        let granted: pbs_api_types::BackupGroup = "vm/100".parse()?;
+        let other: pbs_api_types::BackupGroup = "vm/101".parse()?;
+
+        let check = |group, privs| {
+            check_group_privs_with(&user_info, "store1", &ns, group, &helpdesk, privs)
+        };
+
+        // restores of the granted group, without owner check
+        assert!(!check(&granted, &GROUP_READ_PRIVS)?);
+        assert!(check(&other, &GROUP_READ_PRIVS).is_err());
+
+        // backup upgrade, delete, notes and protection endpoints
+        assert!(check(&granted, &GROUP_BACKUP_PRIVS).is_err());
+        assert!(check(&granted, &GROUP_REMOVE_PRIVS).is_err());
+        assert!(check(&granted, &GROUP_MODIFY_PRIVS).is_err());
+
+        Ok(())
+    }
+}
@@ -6,7 +6,7 @@ use std::time::Instant;
 
 use anyhow::{bail, format_err, Error};
 
-use proxmox_sys::{task_log, WorkerTaskContext};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
 
 use pbs_api_types::{
     print_ns_and_snapshot, print_store_and_ns, Authid, BackupNamespace, BackupType, CryptMode,
@@ -14,6 +14,7 @@ use pbs_api_types::{
 };
 use pbs_datastore::backup_info::{BackupDir, BackupGroup, BackupInfo};
 use pbs_datastore::index::IndexFile;
+use pbs_datastore::maintenance_throttle::MaintenanceThrottle;
 use pbs_datastore::manifest::{archive_type, ArchiveType, BackupManifest, FileInfo};
 use pbs_datastore::{DataBlob, DataStore, StoreProgress};
 use proxmox_sys::fs::lock_dir_noblock_shared;
@@ -29,11 +30,13 @@ pub struct VerifyWorker {
     datastore: Arc<DataStore>,
     verified_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
     corrupt_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+    throttle: Mutex<MaintenanceThrottle>,
 }
 
 impl VerifyWorker {
     /// Creates a new VerifyWorker for a given task worker and datastore.
     pub fn new(worker: Arc<dyn WorkerTaskContext>, datastore: Arc<DataStore>) -> Self {
+        let throttle = Mutex::new(datastore.maintenance_throttle());
         Self {
             worker,
             datastore,
@@ -41,8 +44,43 @@ impl VerifyWorker {
             verified_chunks: Arc::new(Mutex::new(HashSet::with_capacity(16 * 1024))),
             // start with 64 chunks since we assume there are few corrupt ones
             corrupt_chunks: Arc::new(Mutex::new(HashSet::with_capacity(64))),
+            throttle,
         }
     }
+
+    /// Treat the chunks of `backup_dir` as verified if its last verification was successful,
+    /// so that verifying a snapshot sharing most of its chunks only reads the new ones.
+    ///
+    /// Returns the number of chunks marked as verified.
+    pub fn assume_verified_snapshot(&self, backup_dir: &BackupDir) -> Result<usize, Error> {
+        let (manifest, _) = backup_dir.load_manifest()?;
+        let verify_state: SnapshotVerifyState =
+            match serde_json::from_value(manifest.unprotected["verify_state"].clone()) {
+                Ok(verify_state) => verify_state,
+                Err(_) => return Ok(0), // never verified
+            };
+        if verify_state.state != VerifyState::Ok {
+            return Ok(0);
+        }
+
+        let mut verified_chunks = self.verified_chunks.lock().unwrap();
+        let mut count = 0;
+        for info in manifest.files() {
+            if archive_type(&info.filename)? == ArchiveType::Blob {
+                continue;
+            }
+            let index = self
+                .datastore
+                .open_index(backup_dir.full_path().join(&info.filename))?;
+            for pos in 0..index.index_count() {
+                if verified_chunks.insert(*index.index_digest(pos).unwrap()) {
+                    count += 1;
+                }
+            }
+        }
+
+        Ok(count)
+    }
 }
 
 fn verify_blob(backup_dir: &BackupDir, info: &FileInfo) -> Result<(), Error> {
@@ -109,8 +147,11 @@ fn verify_index_chunks(
     verify_worker: &VerifyWorker,
     index: Box<dyn IndexFile + Send>,
     crypt_mode: CryptMode,
+    snapshot: &str,
 ) -> Result<(), Error> {
     let errors = Arc::new(AtomicUsize::new(0));
+    // corrupt chunks referenced by this index, recorded in the chunk quarantine
+    let found_corrupt = Arc::new(Mutex::new(Vec::new()));
 
     let start_time = Instant::now();
 
@@ -119,9 +160,11 @@ fn verify_index_chunks(
 
     let worker2 = Arc::clone(&verify_worker.worker);
     let datastore2 = Arc::clone(&verify_worker.datastore);
+    let digest_algorithm = verify_worker.datastore.digest_algorithm();
     let corrupt_chunks2 = Arc::clone(&verify_worker.corrupt_chunks);
     let verified_chunks2 = Arc::clone(&verify_worker.verified_chunks);
     let errors2 = Arc::clone(&errors);
+    let found_corrupt2 = Arc::clone(&found_corrupt);
 
     let decoder_pool = ParallelHandler::new(
         "verify chunk decoder",
@@ -130,6 +173,8 @@ fn verify_index_chunks(
             let chunk_crypt_mode = match chunk.crypt_mode() {
                 Err(err) => {
                     corrupt_chunks2.lock().unwrap().insert(digest);
+                    let reason = format!("unknown CryptMode - {err}");
+                    found_corrupt2.lock().unwrap().push((digest, reason));
                     task_log!(worker2, "can't verify chunk, unknown CryptMode - {}", err);
                     errors2.fetch_add(1, Ordering::SeqCst);
                     return Ok(());
@@ -147,8 +192,12 @@ fn verify_index_chunks(
                 errors2.fetch_add(1, Ordering::SeqCst);
             }
 
-            if let Err(err) = chunk.verify_unencrypted(size as usize, &digest) {
+            if let Err(err) =
+                chunk.verify_unencrypted_with(digest_algorithm, size as usize, &digest)
+            {
                 corrupt_chunks2.lock().unwrap().insert(digest);
+                let reason = err.to_string();
+                found_corrupt2.lock().unwrap().push((digest, reason));
                 task_log!(worker2, "{}", err);
                 errors2.fetch_add(1, Ordering::SeqCst);
                 rename_corrupted_chunk(datastore2.clone(), &digest, &worker2);
@@ -180,6 +229,8 @@ fn verify_index_chunks(
                 "chunk {} was marked as corrupt",
                 digest_str
             );
+            let reason = "marked as corrupt".to_string();
+            found_corrupt.lock().unwrap().push((*digest, reason));
             errors.fetch_add(1, Ordering::SeqCst);
             true
         } else {
@@ -203,6 +254,11 @@ fn verify_index_chunks(
     for (pos, _) in chunk_list {
         verify_worker.worker.check_abort()?;
         verify_worker.worker.fail_on_shutdown()?;
+        verify_worker
+            .throttle
+            .lock()
+            .unwrap()
+            .pause(&*verify_worker.worker)?;
 
         let info = index.chunk_info(pos).unwrap();
 
@@ -218,6 +274,8 @@ fn verify_index_chunks(
                     .lock()
                     .unwrap()
                     .insert(info.digest);
+                let reason = format!("load failed - {err}");
+                found_corrupt.lock().unwrap().push((info.digest, reason));
                 task_log!(
                     verify_worker.worker,
                     "can't verify chunk, load failed - {}",
@@ -241,6 +299,15 @@ fn verify_index_chunks(
 
     decoder_pool.complete()?;
 
+    let found_corrupt = std::mem::take(&mut *found_corrupt.lock().unwrap());
+    let quarantine = verify_worker.datastore.chunk_quarantine();
+    if let Err(err) = quarantine.add(&found_corrupt, snapshot) {
+        task_warn!(
+            verify_worker.worker,
+            "could not record corrupt chunks in quarantine - {err}"
+        );
+    }
+
     let elapsed = start_time.elapsed().as_secs_f64();
 
     let read_bytes_mib = (read_bytes as f64) / (1024.0 * 1024.0);
@@ -288,7 +355,13 @@ fn verify_fixed_index(
         bail!("wrong index checksum");
     }
 
-    verify_index_chunks(verify_worker, Box::new(index), info.chunk_crypt_mode())
+    let snapshot = print_ns_and_snapshot(backup_dir.backup_ns(), backup_dir.as_ref());
+    verify_index_chunks(
+        verify_worker,
+        Box::new(index),
+        info.chunk_crypt_mode(),
+        &snapshot,
+    )
 }
 
 fn verify_dynamic_index(
@@ -310,7 +383,13 @@ fn verify_dynamic_index(
         bail!("wrong index checksum");
     }
 
-    verify_index_chunks(verify_worker, Box::new(index), info.chunk_crypt_mode())
+    let snapshot = print_ns_and_snapshot(backup_dir.backup_ns(), backup_dir.as_ref());
+    verify_index_chunks(
+        verify_worker,
+        Box::new(index),
+        info.chunk_crypt_mode(),
+        &snapshot,
+    )
 }
 
 /// Verify a single backup snapshot
@@ -368,6 +447,16 @@ pub fn verify_backup_dir_with_lock(
     filter: Option<&dyn Fn(&BackupManifest) -> bool>,
     _snap_lock: Dir,
 ) -> Result<bool, Error> {
+    if backup_dir.is_inventory_only() {
+        task_log!(
+            verify_worker.worker,
+            "SKIPPED: verify {}:{} - only the inventory of this snapshot was synced",
+            verify_worker.datastore.name(),
+            backup_dir.dir(),
+        );
+        return Ok(true);
+    }
+
     let manifest = match backup_dir.load_manifest() {
         Ok((manifest, _)) => manifest,
         Err(err) => {
@@ -404,6 +493,21 @@ pub fn verify_backup_dir_with_lock(
     let mut error_count = 0;
 
     let mut verify_result = VerifyState::Ok;
+
+    let digest_algorithm = verify_worker.datastore.digest_algorithm();
+    if manifest.digest_algorithm() != digest_algorithm {
+        task_log!(
+            verify_worker.worker,
+            "verify {}:{} failed: chunk digest algorithm {} does not match datastore ({})",
+            verify_worker.datastore.name(),
+            backup_dir.dir(),
+            manifest.digest_algorithm(),
+            digest_algorithm,
+        );
+        error_count += 1;
+        verify_result = VerifyState::Failed;
+    }
+
     for info in manifest.files() {
         let result = proxmox_lang::try_block!({
             task_log!(verify_worker.worker, "  check {}", info.filename);
@@ -487,6 +591,9 @@ pub fn verify_backup_group(
 
     BackupInfo::sort_list(&mut list, false); // newest first
     for (pos, info) in list.into_iter().enumerate() {
+        verify_worker.worker.check_abort()?;
+        verify_worker.worker.fail_on_shutdown()?;
+
         if !verify_backup_dir(verify_worker, &info.backup_dir, upid.clone(), filter)? {
             errors.push(print_ns_and_snapshot(
                 info.backup_dir.backup_ns(),
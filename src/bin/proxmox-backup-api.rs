@@ -5,6 +5,7 @@ use anyhow::{bail, Error};
 use futures::*;
 use http::Response;
 use hyper::{Body, StatusCode};
+use serde_json::Value;
 
 use proxmox_lang::try_block;
 use proxmox_router::RpcEnvironmentType;
@@ -12,9 +13,13 @@ use proxmox_sys::fs::CreateOptions;
 
 use proxmox_rest_server::{daemon, ApiConfig, RestServer};
 
+use pbs_api_types::Authid;
+
 use proxmox_backup::auth_helpers::*;
 use proxmox_backup::config;
 use proxmox_backup::server::auth::check_pbs_auth;
+use proxmox_backup::server::do_dr_export_job;
+use proxmox_backup::server::jobstate::Job;
 
 fn main() {
     pbs_tools::setup_libc_malloc_opts();
@@ -81,6 +86,13 @@ async fn run() -> Result<(), Error> {
         backup_user.gid,
     );
 
+    // scheduled by the proxy, but the export needs to read root-only config files
+    command_sock.register_command("dr-export".to_string(), |_value| {
+        let job = Job::new("drexport", "dr-export")?;
+        let upid_str = do_dr_export_job(job, Authid::root_auth_id(), false)?;
+        Ok(Value::String(upid_str))
+    })?;
+
     let dir_opts = CreateOptions::new()
         .owner(backup_user.uid)
         .group(backup_user.gid);
@@ -2,19 +2,19 @@ use std::collections::HashMap;
 use std::io::{self, Write};
 use std::str::FromStr;
 
-use anyhow::{format_err, Error};
+use anyhow::{bail, format_err, Error};
 use serde_json::{json, Value};
 
 use proxmox_router::{cli::*, RpcEnvironment};
-use proxmox_schema::api;
+use proxmox_schema::{api, BooleanSchema, Schema};
 use proxmox_sys::fs::CreateOptions;
 
 use pbs_api_types::percent_encoding::percent_encode_component;
 use pbs_api_types::{
     BackupNamespace, GroupFilter, RateLimitConfig, SyncJobConfig, DATASTORE_SCHEMA,
     GROUP_FILTER_LIST_SCHEMA, IGNORE_VERIFIED_BACKUPS_SCHEMA, NS_MAX_DEPTH_SCHEMA,
-    REMOTE_ID_SCHEMA, REMOVE_VANISHED_BACKUPS_SCHEMA, TRANSFER_LAST_SCHEMA, UPID_SCHEMA,
-    VERIFICATION_OUTDATED_AFTER_SCHEMA,
+    REMOTE_ID_SCHEMA, REMOVE_VANISHED_BACKUPS_SCHEMA, SYNC_INVENTORY_ONLY_SCHEMA,
+    SYNC_METADATA_SCHEMA, TRANSFER_LAST_SCHEMA, UPID_SCHEMA, VERIFICATION_OUTDATED_AFTER_SCHEMA,
 };
 use pbs_client::{display_task_log, view_task_result};
 use pbs_config::sync;
@@ -29,12 +29,23 @@ use proxmox_backup::config;
 mod proxmox_backup_manager;
 use proxmox_backup_manager::*;
 
+const OFFLINE_SCHEMA: Schema = BooleanSchema::new(
+    "Work directly on the configuration and datastores instead of going through the proxy, \
+    for example if it does not start.",
+)
+.default(false)
+.schema();
+
 #[api(
    input: {
         properties: {
             store: {
                 schema: DATASTORE_SCHEMA,
             },
+            offline: {
+                schema: OFFLINE_SCHEMA,
+                optional: true,
+            },
             "output-format": {
                 schema: OUTPUT_FORMAT,
                 optional: true,
@@ -43,11 +54,24 @@ use proxmox_backup_manager::*;
    }
 )]
 /// Start garbage collection for a specific datastore.
-async fn start_garbage_collection(param: Value) -> Result<Value, Error> {
+async fn start_garbage_collection(
+    param: Value,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
     let output_format = get_output_format(&param);
 
     let store = required_string_param(&param, "store")?;
 
+    if param["offline"].as_bool().unwrap_or(false) {
+        let upid = api2::admin::datastore::start_garbage_collection(
+            store.to_string(),
+            &api2::admin::datastore::API_METHOD_START_GARBAGE_COLLECTION,
+            rpcenv,
+        )?;
+        wait_for_local_worker(upid.as_str().unwrap()).await?;
+        return Ok(Value::Null);
+    }
+
     let client = connect_to_localhost()?;
 
     let path = format!("api2/json/admin/datastore/{}/gc", store);
@@ -65,6 +89,10 @@ async fn start_garbage_collection(param: Value) -> Result<Value, Error> {
             store: {
                 schema: DATASTORE_SCHEMA,
             },
+            offline: {
+                schema: OFFLINE_SCHEMA,
+                optional: true,
+            },
             "output-format": {
                 schema: OUTPUT_FORMAT,
                 optional: true,
@@ -73,17 +101,26 @@ async fn start_garbage_collection(param: Value) -> Result<Value, Error> {
    }
 )]
 /// Show garbage collection status for a specific datastore.
-async fn garbage_collection_status(param: Value) -> Result<Value, Error> {
+async fn garbage_collection_status(
+    param: Value,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
     let output_format = get_output_format(&param);
 
     let store = required_string_param(&param, "store")?;
 
-    let client = connect_to_localhost()?;
-
-    let path = format!("api2/json/admin/datastore/{}/gc", store);
-
-    let mut result = client.get(&path, None).await?;
-    let mut data = result["data"].take();
+    let mut data = if param["offline"].as_bool().unwrap_or(false) {
+        serde_json::to_value(api2::admin::datastore::garbage_collection_status(
+            store.to_string(),
+            &api2::admin::datastore::API_METHOD_GARBAGE_COLLECTION_STATUS,
+            rpcenv,
+        )?)?
+    } else {
+        let client = connect_to_localhost()?;
+        let path = format!("api2/json/admin/datastore/{}/gc", store);
+        let mut result = client.get(&path, None).await?;
+        result["data"].take()
+    };
     let return_type = &api2::admin::datastore::API_METHOD_GARBAGE_COLLECTION_STATUS.returns;
 
     let options = default_table_format_options();
@@ -192,28 +229,48 @@ fn garbage_collection_commands() -> CommandLineInterface {
                 type: Boolean,
                 description: "Also list stopped tasks.",
                 optional: true,
-            }
+            },
+            offline: {
+                schema: OFFLINE_SCHEMA,
+                optional: true,
+            },
         }
     }
 )]
 /// List running server tasks.
-async fn task_list(param: Value) -> Result<Value, Error> {
+async fn task_list(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
     let output_format = get_output_format(&param);
 
-    let client = connect_to_localhost()?;
-
     let limit = param["limit"].as_u64().unwrap_or(50) as usize;
     let running = !param["all"].as_bool().unwrap_or(false);
-    let args = json!({
-        "running": running,
-        "start": 0,
-        "limit": limit,
-    });
-    let mut result = client
-        .get("api2/json/nodes/localhost/tasks", Some(args))
-        .await?;
 
-    let mut data = result["data"].take();
+    let mut data = if param["offline"].as_bool().unwrap_or(false) {
+        let list = api2::node::tasks::list_tasks(
+            0,
+            limit as u64,
+            false,
+            running,
+            None,
+            None,
+            None,
+            None,
+            None,
+            json!({}),
+            rpcenv,
+        )?;
+        serde_json::to_value(list)?
+    } else {
+        let client = connect_to_localhost()?;
+        let args = json!({
+            "running": running,
+            "start": 0,
+            "limit": limit,
+        });
+        let mut result = client
+            .get("api2/json/nodes/localhost/tasks", Some(args))
+            .await?;
+        result["data"].take()
+    };
     let return_type = &api2::node::tasks::API_METHOD_LIST_TASKS.returns;
 
     use pbs_tools::format::{render_epoch, render_task_status};
@@ -242,6 +299,10 @@ async fn task_list(param: Value) -> Result<Value, Error> {
             upid: {
                 schema: UPID_SCHEMA,
             },
+            offline: {
+                schema: OFFLINE_SCHEMA,
+                optional: true,
+            },
         }
     }
 )]
@@ -249,6 +310,14 @@ async fn task_list(param: Value) -> Result<Value, Error> {
 async fn task_log(param: Value) -> Result<Value, Error> {
     let upid = required_string_param(&param, "upid")?;
 
+    if param["offline"].as_bool().unwrap_or(false) {
+        let path = proxmox_rest_server::upid_log_path(&upid.parse()?)?;
+        let log = proxmox_sys::fs::file_read_optional_string(&path)?
+            .ok_or_else(|| format_err!("no task log found for {upid}"))?;
+        io::stdout().write_all(log.as_bytes())?;
+        return Ok(Value::Null);
+    }
+
     let client = connect_to_localhost()?;
 
     display_task_log(&client, upid, true, false).await?;
@@ -261,21 +330,115 @@ async fn task_log(param: Value) -> Result<Value, Error> {
         properties: {
             upid: {
                 schema: UPID_SCHEMA,
+                optional: true,
+            },
+            all: {
+                type: Boolean,
+                description: "Stop all running tasks matching the filters.",
+                optional: true,
+                default: false,
+            },
+            "type": {
+                type: String,
+                description: "Only stop tasks whose type contains this (requires --all).",
+                optional: true,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            user: {
+                type: String,
+                description: "Only stop tasks from this user (requires --all).",
+                optional: true,
             },
         }
     }
 )]
-/// Try to stop a specific task.
+/// Try to stop a specific task, or all running tasks matching the filters.
 async fn task_stop(param: Value) -> Result<Value, Error> {
-    let upid_str = required_string_param(&param, "upid")?;
+    let client = connect_to_localhost()?;
+
+    if let Some(upid_str) = param["upid"].as_str() {
+        let path = format!(
+            "api2/json/nodes/localhost/tasks/{}",
+            percent_encode_component(upid_str)
+        );
+        let _ = client.delete(&path, None).await?;
+        return Ok(Value::Null);
+    }
+
+    if !param["all"].as_bool().unwrap_or(false) {
+        bail!("either a task UPID or --all is required");
+    }
+
+    let mut args = json!({});
+    if let Some(typefilter) = param["type"].as_str() {
+        args["typefilter"] = typefilter.into();
+    }
+    if let Some(store) = param["store"].as_str() {
+        args["store"] = store.into();
+    }
+    if let Some(user) = param["user"].as_str() {
+        args["userfilter"] = user.into();
+    }
+
+    let mut result = client
+        .delete("api2/json/nodes/localhost/tasks", Some(args))
+        .await?;
+
+    if let Value::Array(list) = result["data"].take() {
+        for upid in list {
+            if let Some(upid) = upid.as_str() {
+                println!("stopping {upid}");
+            }
+        }
+    }
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            "max-days": {
+                description: "Remove tasks older than this many days.",
+                type: Integer,
+                minimum: 0,
+                optional: true,
+            },
+            "max-files": {
+                description: "Maximum number of rotated archive files to keep.",
+                type: Integer,
+                minimum: 1,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Rotate the task archive and remove old entries and their task logs.
+async fn task_purge(param: Value) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
 
     let client = connect_to_localhost()?;
 
-    let path = format!(
-        "api2/json/nodes/localhost/tasks/{}",
-        percent_encode_component(upid_str)
-    );
-    let _ = client.delete(&path, None).await?;
+    let mut args = json!({});
+    if let Some(max_days) = param["max-days"].as_u64() {
+        args["max-days"] = max_days.into();
+    }
+    if let Some(max_files) = param["max-files"].as_u64() {
+        args["max-files"] = max_files.into();
+    }
+
+    let result = client
+        .delete("api2/json/nodes/localhost/task-archive", Some(args))
+        .await?;
+
+    view_task_result(&client, result, &output_format).await?;
 
     Ok(Value::Null)
 }
@@ -283,11 +446,14 @@ async fn task_stop(param: Value) -> Result<Value, Error> {
 fn task_mgmt_cli() -> CommandLineInterface {
     let task_log_cmd_def = CliCommand::new(&API_METHOD_TASK_LOG).arg_param(&["upid"]);
 
-    let task_stop_cmd_def = CliCommand::new(&API_METHOD_TASK_STOP).arg_param(&["upid"]);
+    let task_stop_cmd_def = CliCommand::new(&API_METHOD_TASK_STOP)
+        .arg_param(&["upid"])
+        .completion_cb("store", pbs_config::datastore::complete_datastore_name);
 
     let cmd_def = CliCommandMap::new()
         .insert("list", CliCommand::new(&API_METHOD_TASK_LIST))
         .insert("log", task_log_cmd_def)
+        .insert("purge", CliCommand::new(&API_METHOD_TASK_PURGE))
         .insert("stop", task_stop_cmd_def);
 
     cmd_def.into()
@@ -338,6 +504,14 @@ fn task_mgmt_cli() -> CommandLineInterface {
                 schema: TRANSFER_LAST_SCHEMA,
                 optional: true,
             },
+            "sync-metadata": {
+                schema: SYNC_METADATA_SCHEMA,
+                optional: true,
+            },
+            "inventory-only": {
+                schema: SYNC_INVENTORY_ONLY_SCHEMA,
+                optional: true,
+            },
         }
    }
 )]
@@ -354,6 +528,8 @@ async fn pull_datastore(
     group_filter: Option<Vec<GroupFilter>>,
     limit: RateLimitConfig,
     transfer_last: Option<usize>,
+    sync_metadata: Option<bool>,
+    inventory_only: Option<bool>,
     param: Value,
 ) -> Result<Value, Error> {
     let output_format = get_output_format(&param);
@@ -390,6 +566,14 @@ async fn pull_datastore(
         args["transfer-last"] = json!(transfer_last)
     }
 
+    if let Some(sync_metadata) = sync_metadata {
+        args["sync-metadata"] = Value::from(sync_metadata);
+    }
+
+    if let Some(inventory_only) = inventory_only {
+        args["inventory-only"] = Value::from(inventory_only);
+    }
+
     let mut limit_json = json!(limit);
     let limit_map = limit_json
         .as_object_mut()
@@ -418,6 +602,10 @@ async fn pull_datastore(
                 schema: VERIFICATION_OUTDATED_AFTER_SCHEMA,
                 optional: true,
             },
+            offline: {
+                schema: OFFLINE_SCHEMA,
+                optional: true,
+            },
             "output-format": {
                 schema: OUTPUT_FORMAT,
                 optional: true,
@@ -426,9 +614,34 @@ async fn pull_datastore(
    }
 )]
 /// Verify backups
-async fn verify(store: String, mut param: Value) -> Result<Value, Error> {
+async fn verify(
+    store: String,
+    mut param: Value,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
     let output_format = extract_output_format(&mut param);
 
+    let offline = param
+        .as_object_mut()
+        .and_then(|param| param.remove("offline"))
+        .and_then(|offline| offline.as_bool())
+        .unwrap_or(false);
+    if offline {
+        let upid = api2::admin::datastore::verify(
+            store,
+            None,
+            None,
+            None,
+            None,
+            param["ignore-verified"].as_bool(),
+            param["outdated-after"].as_i64(),
+            None,
+            rpcenv,
+        )?;
+        wait_for_local_worker(upid.as_str().unwrap()).await?;
+        return Ok(Value::Null);
+    }
+
     let client = connect_to_localhost()?;
 
     let args = json!(param);
@@ -496,17 +709,25 @@ async fn run() -> Result<(), Error> {
 
     let cmd_def = CliCommandMap::new()
         .insert("acl", acl_commands())
+        .insert("config", config_commands())
         .insert("datastore", datastore_commands())
         .insert("disk", disk_commands())
         .insert("dns", dns_commands())
+        .insert("dr-export", dr_export_commands())
+        .insert("expected-backup", expected_backup_commands())
+        .insert("failover", failover_commands())
+        .insert("hook", hook_commands())
+        .insert("kms", kms_commands())
         .insert("ldap", ldap_commands())
         .insert("ad", ad_commands())
         .insert("network", network_commands())
+        .insert("network-share", network_share_commands())
         .insert("node", node_commands())
         .insert("notification", notification_commands())
         .insert("user", user_commands())
         .insert("openid", openid_commands())
         .insert("remote", remote_commands())
+        .insert("session", session_commands())
         .insert("traffic-control", traffic_control_commands())
         .insert("garbage-collection", garbage_collection_commands())
         .insert("acme", acme_mgmt_cli())
@@ -516,6 +737,7 @@ async fn run() -> Result<(), Error> {
         .insert("verify-job", verify_job_commands())
         .insert("prune-job", prune_job_commands())
         .insert("task", task_mgmt_cli())
+        .insert("test", load_test_commands())
         .insert(
             "pull",
             CliCommand::new(&API_METHOD_PULL_DATASTORE)
@@ -749,7 +971,7 @@ pub fn complete_remote_datastore_group(_arg: &str, param: &HashMap<String, Strin
         Some((None, source_store)) => {
             let mut rpcenv = CliEnvironment::new();
             rpcenv.set_auth_id(Some(String::from("root@pam")));
-            crate::api2::admin::datastore::list_groups(source_store, ns, &mut rpcenv).ok()
+            crate::api2::admin::datastore::list_groups(source_store, ns, None, &mut rpcenv).ok()
         }
         _ => None,
     } {
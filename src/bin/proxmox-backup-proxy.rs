@@ -23,8 +23,7 @@ use proxmox_sys::{task_log, task_warn};
 use pbs_datastore::DataStore;
 
 use proxmox_rest_server::{
-    cleanup_old_tasks, cookie_from_header, rotate_task_log_archive, ApiConfig, Redirector,
-    RestEnvironment, RestServer, WorkerTask,
+    cookie_from_header, ApiConfig, Redirector, RestEnvironment, RestServer, WorkerTask,
 };
 
 use proxmox_backup::rrd_cache::{
@@ -34,6 +33,7 @@ use proxmox_backup::{
     server::{
         auth::check_pbs_auth,
         jobstate::{self, Job},
+        task_archive,
     },
     tools::disks::BlockDevStat,
     traffic_control_cache::{SharedRateLimit, TRAFFIC_CONTROL_CACHE},
@@ -58,8 +58,11 @@ use proxmox_backup::tools::{
 
 use proxmox_backup::api2::pull::do_sync_job;
 use proxmox_backup::api2::tape::backup::do_tape_backup_job;
+use proxmox_backup::server::do_compliance_report_job;
+use proxmox_backup::server::do_expected_backup_check;
 use proxmox_backup::server::do_prune_job;
 use proxmox_backup::server::do_verification_job;
+use proxmox_backup::server::request_dr_export;
 
 fn main() -> Result<(), Error> {
     pbs_tools::setup_libc_malloc_opts();
@@ -253,7 +256,20 @@ async fn run() -> Result<(), Error> {
             &mut command_sock,
         )?;
 
-    let rest_server = RestServer::new(config);
+    let access_log = server::access_log::StructuredAccessLog::from_node_config()?;
+    if let Some(access_log) = &access_log {
+        let access_log = Arc::clone(access_log);
+        command_sock.register_command(
+            "api-structured-access-log-reopen".to_string(),
+            move |_value| -> Result<_, Error> {
+                log::info!("re-opening structured access log file");
+                access_log.reopen()?;
+                Ok(Value::Null)
+            },
+        )?;
+    }
+
+    let rest_server = server::access_log::AccessLogServer::new(RestServer::new(config), access_log);
     let redirector = Redirector::new();
     proxmox_rest_server::init_worker_tasks(
         pbs_buildcfg::PROXMOX_BACKUP_LOG_DIR_M!().into(),
@@ -455,6 +471,10 @@ async fn run_task_scheduler() {
             Ok(Err(err)) => eprintln!("task scheduler failed - {err:?}"),
             Ok(Ok(_)) => {}
         }
+
+        if let Err(err) = server::update_scheduler_heartbeat() {
+            eprintln!("could not update task scheduler heartbeat - {err}");
+        }
     }
 }
 
@@ -464,6 +484,10 @@ async fn schedule_tasks() -> Result<(), Error> {
     schedule_datastore_sync_jobs().await;
     schedule_datastore_verify_jobs().await;
     schedule_tape_backup_jobs().await;
+    schedule_expected_backup_check().await;
+    schedule_compliance_reports().await;
+    schedule_dr_export().await;
+    schedule_failover_check().await;
     schedule_task_log_rotate().await;
 
     Ok(())
@@ -635,6 +659,15 @@ async fn schedule_datastore_sync_jobs() {
             None => continue,
         };
 
+        match proxmox_backup::server::failover::sync_job_allowed(&job_id) {
+            Ok(true) => {}
+            Ok(false) => continue, // failover sync job, but this node is the active one
+            Err(err) => {
+                eprintln!("unable to check failover state - {err}");
+                continue;
+            }
+        }
+
         let worker_type = "syncjob";
         if check_schedule(worker_type, &event_str, &job_id) {
             let job = match Job::new(worker_type, &job_id) {
@@ -723,6 +756,114 @@ async fn schedule_tape_backup_jobs() {
     }
 }
 
+async fn schedule_expected_backup_check() {
+    let worker_type = "expectedbackupcheck";
+    let job_id = "expected-backups";
+
+    match pbs_config::expected_backup::config() {
+        Ok((config, _digest)) if !config.sections.is_empty() => {}
+        Ok(_) => return, // nothing to monitor
+        Err(err) => {
+            eprintln!("unable to read expected backup config - {err}");
+            return;
+        }
+    }
+
+    if !check_schedule(worker_type, "hourly", job_id) {
+        return;
+    }
+
+    let job = match Job::new(worker_type, job_id) {
+        Ok(job) => job,
+        Err(_) => return, // could not get lock
+    };
+
+    if let Err(err) = do_expected_backup_check(job, Authid::root_auth_id(), false) {
+        eprintln!("unable to start expected backup check - {err}");
+    }
+}
+
+async fn schedule_dr_export() {
+    let worker_type = "drexport";
+    let job_id = "dr-export";
+
+    let config = match proxmox_backup::config::node::config() {
+        Ok((config, _digest)) => config,
+        Err(err) => {
+            eprintln!("unable to read node config - {err}");
+            return;
+        }
+    };
+
+    let event_str = match config.dr_export_config() {
+        Some(Ok(dr_export)) => dr_export.schedule,
+        Some(Err(err)) => {
+            eprintln!("unable to parse dr-export config - {err}");
+            return;
+        }
+        None => return, // not configured
+    };
+
+    if !check_schedule(worker_type, &event_str, job_id) {
+        return;
+    }
+
+    // the bundle includes root-only config files, so the API daemon runs the export
+    if let Err(err) = request_dr_export().await {
+        eprintln!("unable to start disaster recovery export - {err}");
+    }
+}
+
+async fn schedule_failover_check() {
+    // querying the peer must not delay the other scheduled tasks
+    tokio::spawn(async {
+        if let Err(err) = proxmox_backup::server::failover::check_failover_peer().await {
+            eprintln!("failover peer check failed - {err}");
+        }
+    });
+}
+
+async fn schedule_compliance_reports() {
+    let config = match pbs_config::datastore::config() {
+        Err(err) => {
+            eprintln!("unable to read datastore config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+
+    for (store, (_, store_config)) in config.sections {
+        let store_config: DataStoreConfig = match serde_json::from_value(store_config) {
+            Ok(c) => c,
+            Err(err) => {
+                eprintln!("datastore config from_value failed - {err}");
+                continue;
+            }
+        };
+
+        let period = match store_config.compliance_report {
+            Some(period) => period,
+            None => continue,
+        };
+
+        let worker_type = "compliancereport";
+        if !check_schedule(worker_type, period.schedule(), &store) {
+            continue;
+        }
+
+        let job = match Job::new(worker_type, &store) {
+            Ok(job) => job,
+            Err(_) => continue, // could not get lock
+        };
+
+        if let Err(err) =
+            do_compliance_report_job(job, store.clone(), period, Authid::root_auth_id(), false)
+        {
+            eprintln!("unable to start compliance report job for {store} - {err}");
+        }
+    }
+}
+
 async fn schedule_task_log_rotate() {
     let worker_type = "logrotate";
     let job_id = "access-log_and_task-archive";
@@ -753,33 +894,13 @@ async fn schedule_task_log_rotate() {
             task_log!(worker, "starting task log rotation");
 
             let result = try_block!({
-                let max_size = 512 * 1024 - 1; // an entry has ~ 100b, so > 5000 entries/file
-                let max_files = 20; // times twenty files gives > 100000 task entries
-
-                let max_days = proxmox_backup::config::node::config()
-                    .map(|(cfg, _)| cfg.task_log_max_days)
-                    .ok()
-                    .flatten();
+                task_archive::rotate_task_archive(&worker, false, None, None)?;
 
                 let user = pbs_config::backup_user()?;
                 let options = proxmox_sys::fs::CreateOptions::new()
                     .owner(user.uid)
                     .group(user.gid);
 
-                let has_rotated = rotate_task_log_archive(
-                    max_size,
-                    true,
-                    Some(max_files),
-                    max_days,
-                    Some(options.clone()),
-                )?;
-
-                if has_rotated {
-                    task_log!(worker, "task log archive was rotated");
-                } else {
-                    task_log!(worker, "task log archive was not rotated");
-                }
-
                 let max_size = 32 * 1024 * 1024 - 1;
                 let max_files = 14;
 
@@ -798,6 +919,25 @@ async fn schedule_task_log_rotate() {
                     task_log!(worker, "API access log was not rotated");
                 }
 
+                if Path::new(pbs_buildcfg::API_STRUCTURED_ACCESS_LOG_FN).exists() {
+                    let mut logrotate = LogRotate::new(
+                        pbs_buildcfg::API_STRUCTURED_ACCESS_LOG_FN,
+                        true,
+                        Some(max_files),
+                        Some(options.clone()),
+                    )?;
+
+                    if logrotate.rotate(max_size)? {
+                        task_log!(worker, "API structured access log was rotated");
+                        let result = proxmox_async::runtime::block_on(
+                            command_reopen_structured_access_log(),
+                        );
+                        if let Err(err) = result {
+                            task_warn!(worker, "could not re-open structured access log: {err}");
+                        }
+                    }
+                }
+
                 let mut logrotate = LogRotate::new(
                     pbs_buildcfg::API_AUTH_LOG_FN,
                     true,
@@ -813,13 +953,6 @@ async fn schedule_task_log_rotate() {
                     task_log!(worker, "API authentication log was not rotated");
                 }
 
-                if has_rotated {
-                    task_log!(worker, "cleaning up old task logs");
-                    if let Err(err) = cleanup_old_tasks(&worker, true) {
-                        task_warn!(worker, "could not completely cleanup old tasks: {err}");
-                    }
-                }
-
                 Ok(())
             });
 
@@ -858,6 +991,17 @@ async fn command_reopen_access_logfiles() -> Result<(), Error> {
     }
 }
 
+async fn command_reopen_structured_access_log() -> Result<(), Error> {
+    // only the proxy writes the structured access log
+    let sock = proxmox_rest_server::our_ctrl_sock();
+    let _: Value = proxmox_rest_server::send_raw_command(
+        sock,
+        "{\"command\":\"api-structured-access-log-reopen\"}\n",
+    )
+    .await?;
+    Ok(())
+}
+
 async fn command_reopen_auth_logfiles() -> Result<(), Error> {
     // only care about the most recent daemon instance for each, proxy & api, as other older ones
     // should not respond to new requests anyway, but only finish their current one and then exit.
@@ -13,6 +13,7 @@ use proxmox_router::cli::{
     OUTPUT_FORMAT,
 };
 use proxmox_schema::api;
+use proxmox_uuid::Uuid;
 
 use pbs_client::tools::key_source::get_encryption_key_password;
 use pbs_datastore::dynamic_index::DynamicIndexReader;
@@ -236,6 +237,12 @@ fn inspect_chunk(
                 type: String,
                 optional: true,
             },
+            "chunk-list": {
+                description: "For index files, list the chunks in order, with their offset and size.",
+                type: Boolean,
+                optional: true,
+                default: false,
+            },
             "output-format": {
                 schema: OUTPUT_FORMAT,
                 optional: true,
@@ -243,11 +250,13 @@ fn inspect_chunk(
         }
     }
 )]
-/// Inspect a file, for blob file without decode only the size and encryption mode is printed
+/// Inspect a file, for blob file without decode only the size and encryption mode is printed,
+/// for index files the header and the referenced chunks
 fn inspect_file(
     file: String,
     decode: Option<String>,
     keyfile: Option<String>,
+    chunk_list: bool,
     param: Value,
 ) -> Result<(), Error> {
     let output_format = get_output_format(&param);
@@ -277,12 +286,25 @@ fn inspect_file(
             })
         }
         FIXED_SIZED_CHUNK_INDEX_1_0 | DYNAMIC_SIZED_CHUNK_INDEX_1_0 => {
-            let index: Box<dyn IndexFile> = match magic {
+            let (index, header): (Box<dyn IndexFile>, Value) = match magic {
                 FIXED_SIZED_CHUNK_INDEX_1_0 => {
-                    Box::new(FixedIndexReader::new(file)?) as Box<dyn IndexFile>
+                    let index = FixedIndexReader::new(file)?;
+                    let header = json!({
+                        "index-type": "fixed",
+                        "uuid": Uuid::from(index.uuid).to_string(),
+                        "index-csum": hex::encode(index.index_csum),
+                        "chunk-size": index.chunk_size,
+                    });
+                    (Box::new(index), header)
                 }
                 DYNAMIC_SIZED_CHUNK_INDEX_1_0 => {
-                    Box::new(DynamicIndexReader::new(file)?) as Box<dyn IndexFile>
+                    let index = DynamicIndexReader::new(file)?;
+                    let header = json!({
+                        "index-type": "dynamic",
+                        "uuid": Uuid::from(index.uuid).to_string(),
+                        "index-csum": hex::encode(index.index_csum),
+                    });
+                    (Box::new(index), header)
                 }
                 _ => bail!(format_err!("This is technically not possible")),
             };
@@ -293,17 +315,36 @@ fn inspect_file(
             }
 
             let mut chunk_digests = HashSet::new();
+            let mut chunks = Vec::new();
 
             for pos in 0..index.index_count() {
-                let digest = index.index_digest(pos).unwrap();
-                chunk_digests.insert(hex::encode(digest));
+                let info = index.chunk_info(pos).unwrap();
+                chunk_digests.insert(hex::encode(info.digest));
+                if chunk_list {
+                    chunks.push(json!({
+                        "offset": info.range.start,
+                        "size": info.size(),
+                        "digest": hex::encode(info.digest),
+                    }));
+                }
             }
 
-            json!({
+            let (csum, _) = index.compute_csum();
+
+            let mut val = json!({
                 "size": index.index_size(),
                 "ctime": ctime_str,
+                "chunk-count": index.index_count(),
+                "csum-ok": header["index-csum"] == hex::encode(csum),
                 "chunk-digests": chunk_digests
-            })
+            });
+            for (key, value) in header.as_object().unwrap() {
+                val[key] = value.clone();
+            }
+            if chunk_list {
+                val["chunks"] = chunks.into();
+            }
+            val
         }
         _ => bail!(format_err!(
             "Only .blob, .fidx and .didx files may be inspected"
@@ -318,7 +359,34 @@ fn inspect_file(
         if let Some(ctime) = val["ctime"].as_str() {
             println!("creation time: {}", ctime);
         }
-        if let Some(chunks) = val["chunk-digests"].as_array() {
+        if let Some(index_type) = val["index-type"].as_str() {
+            println!("index type: {}", index_type);
+            println!("uuid: {}", val["uuid"].as_str().unwrap_or_default());
+            println!(
+                "index checksum: {} ({})",
+                val["index-csum"].as_str().unwrap_or_default(),
+                if val["csum-ok"].as_bool() == Some(true) {
+                    "ok"
+                } else {
+                    "mismatch"
+                },
+            );
+            if let Some(chunk_size) = val["chunk-size"].as_u64() {
+                println!("chunk size: {}", chunk_size);
+            }
+            println!("chunk count: {}", val["chunk-count"]);
+        }
+        if let Some(chunks) = val["chunks"].as_array() {
+            println!("chunks (offset, size, digest):");
+            for chunk in chunks {
+                println!(
+                    "  {} {} {}",
+                    chunk["offset"],
+                    chunk["size"],
+                    chunk["digest"].as_str().unwrap_or_default(),
+                );
+            }
+        } else if let Some(chunks) = val["chunk-digests"].as_array() {
             println!("chunks:");
             for chunk in chunks {
                 println!("  {}", chunk);
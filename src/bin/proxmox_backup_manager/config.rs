@@ -0,0 +1,54 @@
+use anyhow::{bail, Error};
+use serde_json::Value;
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+
+use proxmox_backup::api2;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Parse all configuration files and check the references between them, without applying
+/// anything. Fails if there are any problems.
+fn check_config(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::check::API_METHOD_CHECK_CONFIG;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let count = data.as_array().map(|list| list.len()).unwrap_or(0);
+    if count == 0 && output_format == "text" {
+        println!("configuration OK");
+        return Ok(Value::Null);
+    }
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("config"))
+        .column(ColumnConfig::new("id"))
+        .column(ColumnConfig::new("message"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    if count > 0 {
+        bail!("found {count} configuration problem(s)");
+    }
+
+    Ok(Value::Null)
+}
+
+pub fn config_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new().insert("check", CliCommand::new(&API_METHOD_CHECK_CONFIG));
+
+    cmd_def.into()
+}
@@ -1,10 +1,15 @@
 use anyhow::Error;
 use serde_json::Value;
 
+use proxmox_human_byte::HumanByte;
 use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
 use proxmox_schema::api;
 
-use pbs_api_types::{DataStoreConfig, DATASTORE_SCHEMA, PROXMOX_CONFIG_DIGEST_SCHEMA};
+use pbs_api_types::{
+    Authid, BackupGroup, BackupNamespace, ComplianceReport, DataStoreConfig,
+    UnfinishedSnapshotAction, BACKUP_GROUP_SCHEMA, CONFIRMATION_TOKEN_SCHEMA, DATASTORE_SCHEMA,
+    NS_MAX_DEPTH_SCHEMA, PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
 use pbs_client::view_task_result;
 
 use proxmox_backup::api2;
@@ -99,6 +104,43 @@ async fn create_datastore(mut param: Value) -> Result<Value, Error> {
     Ok(Value::Null)
 }
 
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            config: {
+                type: DataStoreConfig,
+                flatten: true,
+            },
+            owner: {
+                type: Authid,
+                optional: true,
+                description: "Owner for backup groups without owner file, defaults to root@pam.",
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Import an existing datastore directory, e.g. after reinstalling the server.
+async fn import_datastore(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    param["import-existing"] = true.into();
+
+    let client = connect_to_localhost()?;
+
+    let result = client
+        .post("api2/json/config/datastore", Some(param))
+        .await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
 #[api(
     protected: true,
     input: {
@@ -122,6 +164,10 @@ async fn create_datastore(mut param: Value) -> Result<Value, Error> {
                 optional: true,
                 schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
             },
+            "confirm-token": {
+                schema: CONFIRMATION_TOKEN_SCHEMA,
+                optional: true,
+            },
         },
     },
 )]
@@ -139,6 +185,440 @@ async fn delete_datastore(mut param: Value, rpcenv: &mut dyn RpcEnvironment) ->
     Ok(())
 }
 
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "keep-job-configs": {
+                description: "If enabled, the job configurations related to this datastore will be kept.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "destroy-data": {
+                description: "Delete the datastore's underlying contents",
+                optional: true,
+                type: bool,
+                default: false,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Show what removing a datastore would remove, and the token to confirm it in safe mode.
+fn remove_preview(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let info = &api2::config::datastore::API_METHOD_PREVIEW_DELETE_DATASTORE;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            since: {
+                type: i64,
+                description: "Start of the reported period (epoch), defaults to 7 days before 'until'.",
+                optional: true,
+            },
+            until: {
+                type: i64,
+                description: "End of the reported period (epoch), defaults to now.",
+                optional: true,
+            },
+            html: {
+                type: Boolean,
+                description: "Print the report as HTML, like in the notification.",
+                optional: true,
+                default: false,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Generate a compliance report for a datastore
+fn compliance_report(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+    let html = param
+        .as_object_mut()
+        .and_then(|param| param.remove("html"))
+        .and_then(|html| html.as_bool())
+        .unwrap_or(false);
+
+    let info = &api2::admin::datastore::API_METHOD_GET_COMPLIANCE_REPORT;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    if html {
+        let report: ComplianceReport = serde_json::from_value(data)?;
+        println!(
+            "{}",
+            proxmox_backup::server::render_compliance_report_html(&report)?
+        );
+        return Ok(Value::Null);
+    }
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("since").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("until").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("backups-ok"))
+        .column(ColumnConfig::new("backups-failed"))
+        .column(ColumnConfig::new("success-rate"))
+        .column(ColumnConfig::new("snapshots"))
+        .column(ColumnConfig::new("verified"))
+        .column(ColumnConfig::new("verify-failed"))
+        .column(ColumnConfig::new("verify-coverage"))
+        .column(ColumnConfig::new("missed-backups"))
+        .column(ColumnConfig::new("rpo-violations"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List the snapshots without manifest, including those of running backups.
+fn list_unfinished(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::admin::unfinished_snapshots::API_METHOD_LIST_UNFINISHED;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("snapshot"))
+        .column(ColumnConfig::new("last-modified").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("in-use"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            action: {
+                type: UnfinishedSnapshotAction,
+                optional: true,
+            },
+            "min-age": {
+                description: "Only handle snapshots not modified for this many hours.",
+                type: Integer,
+                minimum: 0,
+                optional: true,
+                default: 24,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Report, remove or quarantine stale unfinished snapshots.
+async fn cleanup_unfinished(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let store = pbs_tools::json::required_string_param(&param, "store")?.to_owned();
+    param.as_object_mut().unwrap().remove("store");
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/unfinished-snapshots");
+    let result = client.post(&path, Some(param)).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List the tombstones of recently removed backup groups.
+fn list_tombstones(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::admin::group_tombstones::API_METHOD_LIST_TOMBSTONES;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("ns"))
+        .column(ColumnConfig::new("backup-type"))
+        .column(ColumnConfig::new("backup-id"))
+        .column(ColumnConfig::new("owner"))
+        .column(ColumnConfig::new("removed").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("removed-by"))
+        .column(ColumnConfig::new("expires").renderer(pbs_tools::format::render_epoch));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Upgrade the manifests of the snapshots to the current format version.
+async fn upgrade_manifests(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let store = pbs_tools::json::required_string_param(&param, "store")?.to_owned();
+    param.as_object_mut().unwrap().remove("store");
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/upgrade-manifests");
+    let result = client.post(&path, Some(param)).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            group: {
+                schema: BACKUP_GROUP_SCHEMA,
+            },
+            pattern: {
+                type: Array,
+                description: "Paths or match patterns of the entries to remove.",
+                items: {
+                    type: String,
+                    description: "Path or match pattern.",
+                },
+            },
+            "dry-run": {
+                type: bool,
+                optional: true,
+                default: false,
+                description: "Only log the entries which would be removed.",
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Remove files from the pxar archives of all snapshots of a backup group.
+async fn redact_group(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let store = pbs_tools::json::required_string_param(&param, "store")?.to_owned();
+    let group: BackupGroup = pbs_tools::json::required_string_param(&param, "group")?.parse()?;
+
+    let args = param.as_object_mut().unwrap();
+    args.remove("store");
+    args.remove("group");
+    if let Some(patterns) = args.remove("pattern") {
+        args.insert("patterns".to_string(), patterns);
+    }
+    args.insert("backup-type".to_string(), group.ty.to_string().into());
+    args.insert("backup-id".to_string(), group.id.into());
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/redact");
+    let result = client.post(&path, Some(param)).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "min-size": {
+                type: HumanByte,
+                description: "Only report duplicate files of at least this size (default 16 MiB).",
+                optional: true,
+            },
+            limit: {
+                type: Integer,
+                description: "Number of duplicate files and groups to report.",
+                minimum: 1,
+                default: 10,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Report the largest duplicate files and the least deduplicating groups of a datastore.
+async fn analyze_storage(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let store = pbs_tools::json::required_string_param(&param, "store")?.to_owned();
+    param.as_object_mut().unwrap().remove("store");
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/storage-analysis");
+    let result = client.post(&path, Some(param)).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Rebuild the content index of a datastore from its snapshots.
+async fn rebuild_content_index(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let store = pbs_tools::json::required_string_param(&param, "store")?.to_owned();
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/content-index");
+    let result = client.post(&path, None).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Add the snapshots which are not indexed yet to the filename indexes of a datastore.
+async fn update_filename_index(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let store = pbs_tools::json::required_string_param(&param, "store")?.to_owned();
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/filename-index");
+    let result = client.post(&path, None).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
 pub fn datastore_commands() -> CommandLineInterface {
     let cmd_def = CliCommandMap::new()
         .insert("list", CliCommand::new(&API_METHOD_LIST_DATASTORES))
@@ -152,6 +632,10 @@ pub fn datastore_commands() -> CommandLineInterface {
             "create",
             CliCommand::new(&API_METHOD_CREATE_DATASTORE).arg_param(&["name", "path"]),
         )
+        .insert(
+            "import",
+            CliCommand::new(&API_METHOD_IMPORT_DATASTORE).arg_param(&["name", "path"]),
+        )
         .insert(
             "update",
             CliCommand::new(&api2::config::datastore::API_METHOD_UPDATE_DATASTORE)
@@ -166,11 +650,77 @@ pub fn datastore_commands() -> CommandLineInterface {
                     pbs_config::datastore::complete_calendar_event,
                 ),
         )
+        .insert(
+            "compliance-report",
+            CliCommand::new(&API_METHOD_COMPLIANCE_REPORT)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "unfinished",
+            CliCommand::new(&API_METHOD_LIST_UNFINISHED)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "cleanup-unfinished",
+            CliCommand::new(&API_METHOD_CLEANUP_UNFINISHED)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "tombstones",
+            CliCommand::new(&API_METHOD_LIST_TOMBSTONES)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "purge-tombstones",
+            CliCommand::new(&api2::admin::group_tombstones::API_METHOD_PURGE_TOMBSTONES)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "upgrade-manifests",
+            CliCommand::new(&API_METHOD_UPGRADE_MANIFESTS)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "redact",
+            CliCommand::new(&API_METHOD_REDACT_GROUP)
+                .arg_param(&["store", "group"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "storage-analysis",
+            CliCommand::new(&API_METHOD_ANALYZE_STORAGE)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "rebuild-content-index",
+            CliCommand::new(&API_METHOD_REBUILD_CONTENT_INDEX)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "update-filename-index",
+            CliCommand::new(&API_METHOD_UPDATE_FILENAME_INDEX)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
         .insert(
             "remove",
             CliCommand::new(&API_METHOD_DELETE_DATASTORE)
                 .arg_param(&["name"])
                 .completion_cb("name", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "remove-preview",
+            CliCommand::new(&API_METHOD_REMOVE_PREVIEW)
+                .arg_param(&["name"])
+                .completion_cb("name", pbs_config::datastore::complete_datastore_name),
         );
 
     cmd_def.into()
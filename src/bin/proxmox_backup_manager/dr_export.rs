@@ -0,0 +1,97 @@
+use std::path::Path;
+
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::cli::*;
+use proxmox_schema::api;
+
+use pbs_client::tools::key_source::get_encryption_key_password;
+use pbs_client::view_task_result;
+use pbs_key_config::load_and_decrypt_key;
+use pbs_tools::crypt_config::CryptConfig;
+
+use proxmox_backup::client_helpers::connect_to_localhost;
+use proxmox_backup::server::DrBundle;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Run the disaster recovery metadata export now
+async fn run_dr_export(param: Value) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let client = connect_to_localhost()?;
+
+    let result = client.post("api2/json/admin/dr-export", None).await?;
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            bundle: {
+                description: "Path to the disaster recovery bundle.",
+                type: String,
+            },
+            keyfile: {
+                description: "Path to the encryption key of the bundle.",
+                type: String,
+            },
+            "restore-configs": {
+                description: "Restore all configuration files, overwriting the current ones.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        }
+    }
+)]
+/// Recreate namespaces and groups from a disaster recovery bundle, and restore the metadata of
+/// already re-seeded snapshots.
+fn import_dr_bundle(bundle: String, keyfile: String, restore_configs: bool) -> Result<(), Error> {
+    let (key, _created, _fingerprint) =
+        load_and_decrypt_key(Path::new(&keyfile), &get_encryption_key_password)?;
+    let crypt_config = CryptConfig::new(key)?;
+
+    let bundle = DrBundle::load(Path::new(&bundle), &crypt_config)?;
+    log::info!(
+        "bundle of '{}' created {}",
+        bundle.hostname(),
+        proxmox_time::epoch_to_rfc3339_utc(bundle.created())?,
+    );
+
+    if restore_configs {
+        bundle.restore_configs()?;
+    }
+
+    // groups and snapshot metadata must be owned by the backup user
+    let backup_user = pbs_config::backup_user()?;
+    nix::unistd::setgid(backup_user.gid)?;
+    nix::unistd::setuid(backup_user.uid)?;
+
+    bundle.restore_datastores()
+}
+
+pub fn dr_export_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("run", CliCommand::new(&API_METHOD_RUN_DR_EXPORT))
+        .insert(
+            "import",
+            CliCommand::new(&API_METHOD_IMPORT_DR_BUNDLE)
+                .arg_param(&["bundle"])
+                .completion_cb("bundle", complete_file_name)
+                .completion_cb("keyfile", complete_file_name),
+        );
+
+    cmd_def.into()
+}
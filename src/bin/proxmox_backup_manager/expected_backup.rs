@@ -0,0 +1,186 @@
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::{DATASTORE_SCHEMA, JOB_ID_SCHEMA};
+use pbs_client::view_task_result;
+
+use proxmox_backup::api2;
+use proxmox_backup::client_helpers::connect_to_localhost;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List all expected backup entries
+fn list_expected_backups(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::expected_backup::API_METHOD_LIST_EXPECTED_BACKUPS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("id"))
+        .column(ColumnConfig::new("disable"))
+        .column(ColumnConfig::new("store"))
+        .column(ColumnConfig::new("ns"))
+        .column(ColumnConfig::new("group-filter"))
+        .column(ColumnConfig::new("max-age"))
+        .column(ColumnConfig::new("comment"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show an expected backup entry
+fn show_expected_backup(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::expected_backup::API_METHOD_READ_EXPECTED_BACKUP;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            "overdue-only": {
+                description: "Only list groups which missed their backup window.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show the status of all monitored groups
+fn expected_backup_status(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::admin::expected_backup::API_METHOD_LIST_EXPECTED_BACKUP_STATUS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("id"))
+        .column(ColumnConfig::new("ns"))
+        .column(ColumnConfig::new("backup-type"))
+        .column(ColumnConfig::new("backup-id"))
+        .column(ColumnConfig::new("last-backup").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("overdue"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Check all expected backups now, sending notifications for missed backups
+async fn check_expected_backups(param: Value) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let client = connect_to_localhost()?;
+
+    let result = client.post("api2/json/admin/expected-backup", None).await?;
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+pub fn expected_backup_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_EXPECTED_BACKUPS))
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_EXPECTED_BACKUP)
+                .arg_param(&["id"])
+                .completion_cb(
+                    "id",
+                    pbs_config::expected_backup::complete_expected_backup_id,
+                ),
+        )
+        .insert(
+            "status",
+            CliCommand::new(&API_METHOD_EXPECTED_BACKUP_STATUS)
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert("check", CliCommand::new(&API_METHOD_CHECK_EXPECTED_BACKUPS))
+        .insert(
+            "create",
+            CliCommand::new(&api2::config::expected_backup::API_METHOD_CREATE_EXPECTED_BACKUP)
+                .arg_param(&["id"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "update",
+            CliCommand::new(&api2::config::expected_backup::API_METHOD_UPDATE_EXPECTED_BACKUP)
+                .arg_param(&["id"])
+                .completion_cb(
+                    "id",
+                    pbs_config::expected_backup::complete_expected_backup_id,
+                )
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&api2::config::expected_backup::API_METHOD_DELETE_EXPECTED_BACKUP)
+                .arg_param(&["id"])
+                .completion_cb(
+                    "id",
+                    pbs_config::expected_backup::complete_expected_backup_id,
+                ),
+        );
+
+    cmd_def.into()
+}
@@ -0,0 +1,82 @@
+use anyhow::Error;
+use serde_json::{json, Value};
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_client::view_task_result;
+
+use proxmox_backup::api2;
+use proxmox_backup::client_helpers::connect_to_localhost;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show the failover role of this node
+fn failover_status(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::admin::failover::API_METHOD_GET_FAILOVER_STATUS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            force: {
+                description: "Promote even if the peer is not reachable or the catch-up sync \
+                    failed.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Promote this node to the active node
+async fn promote(force: bool, param: Value) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let client = connect_to_localhost()?;
+
+    let result = client
+        .post(
+            "api2/json/admin/failover/promote",
+            Some(json!({ "force": force })),
+        )
+        .await?;
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+pub fn failover_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("status", CliCommand::new(&API_METHOD_FAILOVER_STATUS))
+        .insert("promote", CliCommand::new(&API_METHOD_PROMOTE))
+        .insert(
+            "demote",
+            CliCommand::new(&api2::admin::failover::API_METHOD_DEMOTE),
+        );
+
+    cmd_def.into()
+}
@@ -0,0 +1,104 @@
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::KMS_ID_SCHEMA;
+
+use proxmox_backup::api2;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List configured key management services.
+fn list_kms(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::kms::API_METHOD_LIST_KMS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("id"))
+        .column(ColumnConfig::new("backend"))
+        .column(ColumnConfig::new("url"))
+        .column(ColumnConfig::new("command"))
+        .column(ColumnConfig::new("cache-time"))
+        .column(ColumnConfig::new("failure-policy"))
+        .column(ColumnConfig::new("comment"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: KMS_ID_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show key management service configuration.
+fn show_kms(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::kms::API_METHOD_READ_KMS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+pub fn kms_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_KMS))
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_KMS)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::kms::complete_kms_id),
+        )
+        .insert(
+            "create",
+            CliCommand::new(&api2::config::kms::API_METHOD_CREATE_KMS)
+                .arg_param(&["id"])
+                .completion_cb("command", complete_file_name),
+        )
+        .insert(
+            "update",
+            CliCommand::new(&api2::config::kms::API_METHOD_UPDATE_KMS)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::kms::complete_kms_id)
+                .completion_cb("command", complete_file_name),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&api2::config::kms::API_METHOD_DELETE_KMS)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::kms::complete_kms_id),
+        );
+
+    cmd_def.into()
+}
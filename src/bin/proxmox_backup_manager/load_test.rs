@@ -0,0 +1,353 @@
+//! Synthetic load tests against the local server
+//!
+//! The backups are uploaded through the regular backup protocol of the proxy, so the numbers
+//! include the whole API path, not only the chunk store.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{bail, Error};
+use bytes::BytesMut;
+use futures::stream::StreamExt;
+use serde_json::{json, Value};
+
+use proxmox_human_byte::HumanByte;
+use proxmox_router::cli::*;
+use proxmox_schema::api;
+use proxmox_time::epoch_i64;
+
+use pbs_api_types::{BackupDir, BackupNamespace, BackupType, CryptMode, DATASTORE_SCHEMA};
+use pbs_client::tools::CHUNK_SIZE_SCHEMA;
+use pbs_client::{BackupWriter, HttpClient, UploadOptions};
+use pbs_datastore::manifest::{BackupManifest, MANIFEST_BLOB_NAME};
+
+use proxmox_backup::client_helpers::connect_to_localhost;
+
+/// Backup ID prefix of the groups created by `test ingest`.
+const INGEST_BACKUP_ID: &str = "ingest-test";
+const INGEST_ARCHIVE_NAME: &str = "ingest.img.fidx";
+
+/// Pseudo random numbers for chunk contents and dedup decisions, cheap enough to not limit the
+/// upload rate.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // spread similar seeds, the state must not be zero
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // uniformly distributed in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// chunks generated from the same seed are identical, and thus deduplicated
+fn generate_chunk(seed: u64, size: usize) -> BytesMut {
+    let mut rng = XorShift::new(seed);
+    let mut data = BytesMut::with_capacity(size);
+    while data.len() < size {
+        let bytes = rng.next().to_le_bytes();
+        let len = bytes.len().min(size - data.len());
+        data.extend_from_slice(&bytes[..len]);
+    }
+    data
+}
+
+struct IngestResult {
+    chunks: u64,
+    new_chunks: u64,
+    seconds: f64,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn ingest_session(
+    client: Arc<HttpClient>,
+    store: String,
+    ns: BackupNamespace,
+    snapshot: BackupDir,
+    seed: u64,
+    size: u64,
+    chunk_size: usize,
+    dedup_ratio: f64,
+    compress: bool,
+) -> Result<IngestResult, Error> {
+    let start = Instant::now();
+
+    // duplicates repeat the content of an earlier chunk, like unchanged data in an image
+    let mut rng = XorShift::new(seed);
+    let chunk_count = (size + chunk_size as u64 - 1) / chunk_size as u64;
+    let mut seeds: Vec<u64> = Vec::with_capacity(chunk_count as usize);
+    let mut new_chunks = 0;
+    for _ in 0..chunk_count {
+        if !seeds.is_empty() && rng.next_f64() < dedup_ratio {
+            let earlier = seeds[(rng.next() % seeds.len() as u64) as usize];
+            seeds.push(earlier);
+        } else {
+            seeds.push(rng.next());
+            new_chunks += 1;
+        }
+    }
+
+    let stream = futures::stream::iter(seeds.into_iter().enumerate()).map(move |(pos, seed)| {
+        let offset = pos as u64 * chunk_size as u64;
+        let len = (size - offset).min(chunk_size as u64) as usize;
+        Ok::<_, Error>(generate_chunk(seed, len))
+    });
+
+    let writer =
+        BackupWriter::start(&client, None, &store, &ns, &snapshot, false, false, None).await?;
+
+    let upload_options = UploadOptions {
+        compress,
+        fixed_size: Some(size),
+        fixed_chunk_size: Some(chunk_size),
+        ..UploadOptions::default()
+    };
+    let stats = writer
+        .upload_stream(INGEST_ARCHIVE_NAME, stream, upload_options)
+        .await?;
+
+    let mut manifest = BackupManifest::new(snapshot);
+    manifest.add_file(
+        INGEST_ARCHIVE_NAME.to_string(),
+        stats.size,
+        stats.csum,
+        CryptMode::None,
+    )?;
+    let manifest = manifest.to_string(None)?;
+    let options = UploadOptions {
+        compress: true,
+        ..UploadOptions::default()
+    };
+    writer
+        .upload_blob_from_data(manifest.into_bytes(), MANIFEST_BLOB_NAME, options)
+        .await?;
+    writer.finish().await?;
+
+    Ok(IngestResult {
+        chunks: chunk_count,
+        new_chunks,
+        seconds: start.elapsed().as_secs_f64(),
+    })
+}
+
+fn ns_args(ns: &BackupNamespace, mut args: Value) -> Value {
+    if !ns.is_root() {
+        args["ns"] = ns.to_string().into();
+    }
+    args
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            size: {
+                type: HumanByte,
+                description: "Amount of data uploaded by each session, defaults to 1 GiB.",
+                optional: true,
+            },
+            "chunk-size": {
+                schema: CHUNK_SIZE_SCHEMA,
+                optional: true,
+            },
+            "dedup-ratio": {
+                type: Number,
+                description: "Fraction of chunks which repeat an earlier chunk of the session.",
+                minimum: 0.0,
+                maximum: 1.0,
+                optional: true,
+                default: 0.0,
+            },
+            concurrency: {
+                type: Integer,
+                description: "Number of backup sessions running in parallel.",
+                minimum: 1,
+                maximum: 64,
+                optional: true,
+                default: 1,
+            },
+            compress: {
+                type: Boolean,
+                description: "Compress the chunks. The generated data is incompressible, so this \
+                    only adds the CPU load of a real client.",
+                optional: true,
+                default: false,
+            },
+            keep: {
+                type: Boolean,
+                description: "Keep the test backup groups instead of removing them afterwards.",
+                optional: true,
+                default: false,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Upload synthetic image backups to a datastore through the backup API of the local server,
+/// and report the ingest rate.
+///
+/// Each session creates a snapshot in its own group 'host/ingest-test-<n>'. The chunks stay in
+/// the datastore until the next garbage collection, also if the groups are removed.
+#[allow(clippy::too_many_arguments)]
+async fn ingest(
+    store: String,
+    ns: Option<BackupNamespace>,
+    size: Option<HumanByte>,
+    chunk_size: Option<u64>,
+    dedup_ratio: f64,
+    concurrency: u64,
+    compress: bool,
+    keep: bool,
+    param: Value,
+) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+    let ns = ns.unwrap_or_default();
+    let size = size.map(|size| size.as_u64()).unwrap_or(1024 * 1024 * 1024);
+    if size == 0 {
+        bail!("size must not be zero");
+    }
+    let chunk_size = (chunk_size.unwrap_or(4096) * 1024) as usize;
+    if !chunk_size.is_power_of_two() {
+        bail!("chunk size must be a power of 2");
+    }
+
+    let client = Arc::new(connect_to_localhost()?);
+
+    let groups_path = format!("api2/json/admin/datastore/{store}/groups");
+    let backup_ids: Vec<String> = (0..concurrency)
+        .map(|n| format!("{INGEST_BACKUP_ID}-{n}"))
+        .collect();
+
+    // never touch groups which were not created by this run
+    let mut result = client
+        .get(&groups_path, Some(ns_args(&ns, json!({}))))
+        .await?;
+    if let Some(groups) = result["data"].take().as_array() {
+        for group in groups {
+            let backup_id = group["backup-id"].as_str().unwrap_or_default();
+            if group["backup-type"] == "host" && backup_ids.iter().any(|id| id == backup_id) {
+                bail!("backup group 'host/{backup_id}' already exists, remove it first");
+            }
+        }
+    }
+
+    // a fresh seed, so that earlier runs do not turn new chunks into known ones
+    let mut run_seed = [0u8; 8];
+    openssl::rand::rand_bytes(&mut run_seed)?;
+    let run_seed = u64::from_le_bytes(run_seed);
+
+    let backup_time = epoch_i64();
+    let start = Instant::now();
+
+    let mut tasks = Vec::new();
+    for (n, backup_id) in backup_ids.iter().enumerate() {
+        let snapshot = BackupDir::from((BackupType::Host, backup_id.clone(), backup_time));
+        tasks.push(tokio::spawn(ingest_session(
+            Arc::clone(&client),
+            store.clone(),
+            ns.clone(),
+            snapshot,
+            run_seed.wrapping_add(n as u64),
+            size,
+            chunk_size,
+            dedup_ratio,
+            compress,
+        )));
+    }
+
+    let mut chunks = 0;
+    let mut new_chunks = 0;
+    let mut failed = 0;
+    for (task, backup_id) in tasks.into_iter().zip(&backup_ids) {
+        match task.await? {
+            Ok(result) => {
+                if output_format == "text" {
+                    println!(
+                        "host/{backup_id}: {} in {:.2}s ({}/s), {} of {} chunks new",
+                        HumanByte::from(size),
+                        result.seconds,
+                        HumanByte::from((size as f64 / result.seconds) as u64),
+                        result.new_chunks,
+                        result.chunks,
+                    );
+                }
+                chunks += result.chunks;
+                new_chunks += result.new_chunks;
+            }
+            Err(err) => {
+                log::error!("host/{backup_id}: ingest failed - {err}");
+                failed += 1;
+            }
+        }
+    }
+
+    let seconds = start.elapsed().as_secs_f64();
+    let total = size * (concurrency - failed);
+
+    if !keep {
+        for backup_id in &backup_ids {
+            let args = ns_args(
+                &ns,
+                json!({ "backup-type": "host", "backup-id": backup_id }),
+            );
+            if let Err(err) = client.delete(&groups_path, Some(args)).await {
+                log::warn!("unable to remove test group 'host/{backup_id}' - {err}");
+            }
+        }
+    }
+
+    if output_format == "text" {
+        println!(
+            "total: {} in {seconds:.2}s ({}/s), {new_chunks} of {chunks} chunks new",
+            HumanByte::from(total),
+            HumanByte::from((total as f64 / seconds) as u64),
+        );
+    } else {
+        let result = json!({
+            "sessions": concurrency,
+            "failed": failed,
+            "size": total,
+            "chunks": chunks,
+            "new-chunks": new_chunks,
+            "seconds": seconds,
+            "rate": total as f64 / seconds,
+        });
+        format_and_print_result(&result, &output_format);
+    }
+
+    if failed > 0 {
+        bail!("{failed} of {concurrency} sessions failed");
+    }
+
+    Ok(Value::Null)
+}
+
+pub fn load_test_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new().insert(
+        "ingest",
+        CliCommand::new(&API_METHOD_INGEST)
+            .arg_param(&["store"])
+            .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+    );
+
+    cmd_def.into()
+}
@@ -6,14 +6,30 @@ mod ad;
 pub use ad::*;
 mod cert;
 pub use cert::*;
+mod config;
+pub use config::*;
 mod datastore;
 pub use datastore::*;
 mod dns;
 pub use dns::*;
+mod dr_export;
+pub use dr_export::*;
+mod expected_backup;
+pub use expected_backup::*;
+mod failover;
+pub use failover::*;
+mod hook;
+pub use hook::*;
+mod kms;
+pub use kms::*;
 mod ldap;
 pub use ldap::*;
+mod load_test;
+pub use load_test::*;
 mod network;
 pub use network::*;
+mod network_share;
+pub use network_share::*;
 mod prune;
 pub use prune::*;
 mod remote;
@@ -36,3 +52,5 @@ mod openid;
 pub use openid::*;
 mod traffic_control;
 pub use traffic_control::*;
+mod session;
+pub use session::*;
@@ -0,0 +1,155 @@
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::NETWORK_SHARE_ID_SCHEMA;
+
+use proxmox_backup::api2;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List configured network shares.
+fn list_network_shares(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::network_share::API_METHOD_LIST_NETWORK_SHARES;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("name"))
+        .column(ColumnConfig::new("share-type"))
+        .column(ColumnConfig::new("server"))
+        .column(ColumnConfig::new("path"))
+        .column(ColumnConfig::new("username"))
+        .column(ColumnConfig::new("comment"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: NETWORK_SHARE_ID_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show network share configuration
+fn show_network_share(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::network_share::API_METHOD_READ_NETWORK_SHARE;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show the mount status of the network shares.
+fn network_share_status(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    param["node"] = "localhost".into();
+
+    let info = &api2::node::disks::network_share::API_METHOD_LIST_NETWORK_SHARE_STATUS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("name"))
+        .column(ColumnConfig::new("source"))
+        .column(ColumnConfig::new("mount-point"))
+        .column(ColumnConfig::new("available"))
+        .column(ColumnConfig::new("error"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+pub fn network_share_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_NETWORK_SHARES))
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_NETWORK_SHARE)
+                .arg_param(&["name"])
+                .completion_cb(
+                    "name",
+                    pbs_config::network_share::complete_network_share_name,
+                ),
+        )
+        .insert("status", CliCommand::new(&API_METHOD_NETWORK_SHARE_STATUS))
+        .insert(
+            "create",
+            CliCommand::new(&api2::config::network_share::API_METHOD_CREATE_NETWORK_SHARE)
+                .arg_param(&["name"]),
+        )
+        .insert(
+            "update",
+            CliCommand::new(&api2::config::network_share::API_METHOD_UPDATE_NETWORK_SHARE)
+                .arg_param(&["name"])
+                .completion_cb(
+                    "name",
+                    pbs_config::network_share::complete_network_share_name,
+                ),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&api2::config::network_share::API_METHOD_DELETE_NETWORK_SHARE)
+                .arg_param(&["name"])
+                .completion_cb(
+                    "name",
+                    pbs_config::network_share::complete_network_share_name,
+                ),
+        )
+        .insert(
+            "remount",
+            CliCommand::new(&api2::node::disks::network_share::API_METHOD_REMOUNT_NETWORK_SHARE)
+                .arg_param(&["name"])
+                .fixed_param("node", String::from("localhost"))
+                .completion_cb(
+                    "name",
+                    pbs_config::network_share::complete_network_share_name,
+                ),
+        );
+
+    cmd_def.into()
+}
@@ -2,6 +2,7 @@ use proxmox_router::cli::{CliCommandMap, CommandLineInterface};
 
 mod gotify;
 mod matchers;
+mod queue;
 mod sendmail;
 mod smtp;
 mod targets;
@@ -15,6 +16,7 @@ pub fn notification_commands() -> CommandLineInterface {
     let cmd_def = CliCommandMap::new()
         .insert("endpoint", endpoint_def)
         .insert("matcher", matchers::commands())
+        .insert("queue", queue::commands())
         .insert("target", targets::commands());
 
     cmd_def.into()
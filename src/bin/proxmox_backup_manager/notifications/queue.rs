@@ -0,0 +1,57 @@
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+
+use proxmox_backup::api2;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List queued and failed notifications.
+fn list_queue(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::notifications::queue::API_METHOD_LIST_QUEUE;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("id"))
+        .column(ColumnConfig::new("time").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("state"))
+        .column(ColumnConfig::new("attempts"))
+        .column(ColumnConfig::new("next-attempt").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("last-error"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+pub fn commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_QUEUE))
+        .insert(
+            "retry",
+            CliCommand::new(&api2::config::notifications::queue::API_METHOD_RETRY_NOTIFICATION)
+                .arg_param(&["id"]),
+        )
+        .insert(
+            "drop",
+            CliCommand::new(&api2::config::notifications::queue::API_METHOD_DROP_NOTIFICATION)
+                .arg_param(&["id"]),
+        );
+
+    cmd_def.into()
+}
@@ -247,6 +247,8 @@ pub(crate) fn update_to_prune_jobs_config() -> Result<(), Error> {
             comment: None,
             schedule,
             options,
+            notify: None,
+            notify_user: None,
         };
 
         let prune_config = serde_json::to_value(prune_config)?;
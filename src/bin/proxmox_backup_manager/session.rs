@@ -0,0 +1,85 @@
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::cli::*;
+use proxmox_schema::api;
+
+use pbs_api_types::percent_encoding::percent_encode_component;
+use pbs_api_types::UPID_SCHEMA;
+use pbs_tools::format::{render_bytes_human_readable, render_epoch};
+
+use proxmox_backup::api2;
+use proxmox_backup::client_helpers::connect_to_localhost;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List the active backup and reader sessions.
+async fn list_sessions(param: Value) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let client = connect_to_localhost()?;
+
+    let mut result = client.get("api2/json/admin/sessions", None).await?;
+
+    let mut data = result["data"].take();
+
+    let info = &api2::admin::sessions::API_METHOD_LIST_SESSIONS;
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("session-type"))
+        .column(ColumnConfig::new("auth-id"))
+        .column(ColumnConfig::new("client"))
+        .column(ColumnConfig::new("store"))
+        .column(ColumnConfig::new("ns"))
+        .column(ColumnConfig::new("backup-type"))
+        .column(ColumnConfig::new("backup-id"))
+        .column(ColumnConfig::new("backup-time").renderer(render_epoch))
+        .column(ColumnConfig::new("starttime").renderer(render_epoch))
+        .column(ColumnConfig::new("transferred").renderer(render_bytes_human_readable))
+        .column(ColumnConfig::new("upid"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            upid: {
+                schema: UPID_SCHEMA,
+            },
+        }
+    }
+)]
+/// Terminate an active session.
+async fn terminate_session(upid: String) -> Result<Value, Error> {
+    let client = connect_to_localhost()?;
+
+    let path = format!(
+        "api2/json/admin/sessions/{}",
+        percent_encode_component(&upid)
+    );
+    client.delete(&path, None).await?;
+
+    Ok(Value::Null)
+}
+
+pub fn session_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_SESSIONS))
+        .insert(
+            "terminate",
+            CliCommand::new(&API_METHOD_TERMINATE_SESSION).arg_param(&["upid"]),
+        );
+
+    cmd_def.into()
+}
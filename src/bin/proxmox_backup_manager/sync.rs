@@ -5,8 +5,10 @@ use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
 use proxmox_schema::api;
 
 use pbs_api_types::JOB_ID_SCHEMA;
+use pbs_client::view_task_result;
 
 use proxmox_backup::api2;
+use proxmox_backup::client_helpers::connect_to_localhost;
 
 fn render_group_filter(value: &Value, _record: &Value) -> Result<String, Error> {
     if let Some(group_filters) = value.as_array() {
@@ -105,6 +107,32 @@ async fn run_sync_job(param: Value) -> Result<Value, Error> {
     crate::run_job("sync", param).await
 }
 
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Compare the snapshots synced by the specified sync job with the source
+async fn compare_sync_job(id: String, param: Value) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/sync/{id}/compare");
+    let result = client.post(&path, None).await?;
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
 pub fn sync_job_commands() -> CommandLineInterface {
     let cmd_def = CliCommandMap::new()
         .insert("list", CliCommand::new(&API_METHOD_LIST_SYNC_JOBS))
@@ -145,6 +173,12 @@ pub fn sync_job_commands() -> CommandLineInterface {
                 )
                 .completion_cb("remote-ns", crate::complete_remote_datastore_namespace),
         )
+        .insert(
+            "compare",
+            CliCommand::new(&API_METHOD_COMPARE_SYNC_JOB)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::sync::complete_sync_job_id),
+        )
         .insert(
             "run",
             CliCommand::new(&API_METHOD_RUN_SYNC_JOB)
@@ -8,7 +8,7 @@ use proxmox_schema::{api, param_bail};
 use proxmox_sys::linux::tty;
 
 use pbs_api_types::{
-    Fingerprint, Kdf, DRIVE_NAME_SCHEMA, PASSWORD_HINT_SCHEMA,
+    Fingerprint, Kdf, DRIVE_NAME_SCHEMA, KMS_ID_SCHEMA, PASSWORD_HINT_SCHEMA,
     TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
 };
 
@@ -21,7 +21,11 @@ use proxmox_backup::tape::encryption_keys::{complete_key_fingerprint, load_key_c
 pub fn encryption_key_commands() -> CommandLineInterface {
     let cmd_def = CliCommandMap::new()
         .insert("list", CliCommand::new(&API_METHOD_LIST_KEYS))
-        .insert("create", CliCommand::new(&API_METHOD_CREATE_KEY))
+        .insert(
+            "create",
+            CliCommand::new(&API_METHOD_CREATE_KEY)
+                .completion_cb("kms", pbs_config::kms::complete_kms_id),
+        )
         .insert(
             "change-passphrase",
             CliCommand::new(&API_METHOD_CHANGE_PASSPHRASE)
@@ -280,6 +284,10 @@ async fn restore_key(
                 min_length: 1,
                 max_length: 32,
             },
+            kms: {
+                schema: KMS_ID_SCHEMA,
+                optional: true,
+            },
         },
     },
 )]
@@ -4,13 +4,15 @@ use anyhow::{bail, Error};
 use openssl::ssl::{SslAcceptor, SslMethod};
 use serde::{Deserialize, Serialize};
 
-use proxmox_schema::{api, ApiStringFormat, ApiType, Updater};
+use proxmox_schema::{api, ApiStringFormat, ApiType, Schema, StringSchema, Updater};
 
 use proxmox_http::ProxyConfig;
+use proxmox_human_byte::HumanByte;
 
 use pbs_api_types::{
-    EMAIL_SCHEMA, MULTI_LINE_COMMENT_SCHEMA, OPENSSL_CIPHERS_TLS_1_2_SCHEMA,
-    OPENSSL_CIPHERS_TLS_1_3_SCHEMA,
+    BackupType, CUSTOM_BACKUP_TYPE_LIST_SCHEMA, EMAIL_SCHEMA, JOB_ID_SCHEMA,
+    MULTI_LINE_COMMENT_SCHEMA, OPENSSL_CIPHERS_TLS_1_2_SCHEMA, OPENSSL_CIPHERS_TLS_1_3_SCHEMA,
+    REMOTE_ID_SCHEMA,
 };
 
 use pbs_buildcfg::configdir;
@@ -60,6 +62,133 @@ pub struct AcmeConfig {
     account: AcmeAccountName,
 }
 
+#[api(
+    properties: {
+        schedule: {
+            schema: DR_EXPORT_SCHEDULE_SCHEMA,
+        },
+        keep: {
+            type: Integer,
+            minimum: 1,
+            optional: true,
+            default: 7,
+        },
+    }
+)]
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// Disaster recovery metadata export settings.
+pub struct DrExportConfig {
+    /// When to export the metadata bundle.
+    pub schedule: String,
+    /// Directory the bundles are written to, e.g. an offsite mount.
+    pub path: String,
+    /// Encryption key file, as created by 'proxmox-backup-client key create --kdf none'.
+    pub keyfile: String,
+    /// Number of bundles to keep in the target directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep: Option<usize>,
+}
+
+#[api(
+    properties: {
+        peer: {
+            schema: REMOTE_ID_SCHEMA,
+        },
+        "sync-job": {
+            schema: JOB_ID_SCHEMA,
+        },
+    }
+)]
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// Active-standby failover settings.
+pub struct FailoverConfig {
+    /// The remote entry of the other node of the failover pair.
+    pub peer: String,
+    /// The sync job pulling from the peer, it only runs while this node is the standby.
+    pub sync_job: String,
+}
+
+#[api]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// IO priority of a class of worker tasks.
+pub enum TaskIoPriority {
+    /// Same priority as backups and restores.
+    Normal,
+    /// Lowest best-effort priority, still gets a share of the disk time under load.
+    Low,
+    /// Only gets disk time if no other task needs it.
+    Idle,
+}
+
+#[api(
+    properties: {
+        verify: {
+            type: TaskIoPriority,
+            optional: true,
+        },
+        gc: {
+            type: TaskIoPriority,
+            optional: true,
+        },
+        prune: {
+            type: TaskIoPriority,
+            optional: true,
+        },
+    }
+)]
+#[derive(Default, Deserialize, Serialize)]
+/// IO priorities of the maintenance tasks.
+pub struct TaskIoPriorityConfig {
+    /// IO priority of verification tasks (default low).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify: Option<TaskIoPriority>,
+    /// IO priority of garbage collection tasks (default idle).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gc: Option<TaskIoPriority>,
+    /// IO priority of prune tasks (default low).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prune: Option<TaskIoPriority>,
+}
+
+#[api(
+    properties: {
+        "cpu-weight": {
+            type: Integer,
+            minimum: 1,
+            maximum: 10000,
+            optional: true,
+            default: 100,
+        },
+        "cpu-max": {
+            type: Number,
+            minimum: 0.01,
+            optional: true,
+        },
+    }
+)]
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// CPU limits for the worker tasks of a class.
+pub struct TaskCgroupLimits {
+    /// Relative CPU share of all tasks of the class together, compared to the REST service.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_weight: Option<u64>,
+    /// Maximum number of CPUs all tasks of the class may use together.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_max: Option<f64>,
+}
+
+const DR_EXPORT_SCHEDULE_SCHEMA: Schema =
+    StringSchema::new("Run the disaster recovery export at specified schedule.")
+        .format(&ApiStringFormat::VerifyFn(
+            proxmox_time::verify_calendar_event,
+        ))
+        .type_text("<calendar-event>")
+        .schema();
+
 /// All available languages in Proxmox. Taken from proxmox-i18n repository.
 /// pt_BR, zh_CN, and zh_TW use the same case in the translation files.
 // TODO: auto-generate from available translations
@@ -174,7 +303,69 @@ pub enum Translation {
         "description" : {
             optional: true,
             schema: MULTI_LINE_COMMENT_SCHEMA,
-        }
+        },
+        "task-log-max-files": {
+            type: Integer,
+            optional: true,
+            minimum: 1,
+        },
+        "max-sessions-per-authid": {
+            type: Integer,
+            optional: true,
+            minimum: 1,
+        },
+        "max-sessions-per-ip": {
+            type: Integer,
+            optional: true,
+            minimum: 1,
+        },
+        "reader-rate-per-authid": {
+            type: HumanByte,
+            optional: true,
+        },
+        "upload-buffer-limit": {
+            type: HumanByte,
+            optional: true,
+        },
+        "upload-buffer-per-session": {
+            type: HumanByte,
+            optional: true,
+        },
+        "dr-export": {
+            optional: true,
+            type: String,
+            format: &ApiStringFormat::PropertyString(&DrExportConfig::API_SCHEMA),
+        },
+        failover: {
+            optional: true,
+            type: String,
+            format: &ApiStringFormat::PropertyString(&FailoverConfig::API_SCHEMA),
+        },
+        "task-io-priority": {
+            optional: true,
+            type: String,
+            format: &ApiStringFormat::PropertyString(&TaskIoPriorityConfig::API_SCHEMA),
+        },
+        "task-limits-gc": {
+            optional: true,
+            type: String,
+            format: &ApiStringFormat::PropertyString(&TaskCgroupLimits::API_SCHEMA),
+        },
+        "task-limits-verify": {
+            optional: true,
+            type: String,
+            format: &ApiStringFormat::PropertyString(&TaskCgroupLimits::API_SCHEMA),
+        },
+        "task-limits-tape": {
+            optional: true,
+            type: String,
+            format: &ApiStringFormat::PropertyString(&TaskCgroupLimits::API_SCHEMA),
+        },
+        "custom-backup-types": {
+            optional: true,
+            type: String,
+            format: &ApiStringFormat::PropertyString(&CUSTOM_BACKUP_TYPE_LIST_SCHEMA),
+        },
     },
 )]
 #[derive(Deserialize, Serialize, Updater)]
@@ -225,6 +416,70 @@ pub struct NodeConfig {
     /// Maximum days to keep Task logs
     #[serde(skip_serializing_if = "Option::is_none")]
     pub task_log_max_days: Option<usize>,
+
+    /// Maximum number of rotated task archive files to keep (default 20)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_log_max_files: Option<usize>,
+
+    /// Maximum number of concurrent backup and reader sessions per user or API token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_sessions_per_authid: Option<usize>,
+
+    /// Maximum number of concurrent backup and reader sessions per client IP address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_sessions_per_ip: Option<usize>,
+
+    /// Rate limit for the data sent by all reader (restore) sessions of a single user or API token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reader_rate_per_authid: Option<HumanByte>,
+
+    /// Memory for buffering uploaded data of all backup sessions together (default 1 GiB). (Proxy has to be restarted for changes to take effect)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_buffer_limit: Option<HumanByte>,
+
+    /// Memory for buffering uploaded data of a single backup session (default 128 MiB)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_buffer_per_session: Option<HumanByte>,
+
+    /// Write an additional access log with one JSON object per request. (Proxy has to be restarted for changes to take effect)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub structured_access_log: Option<bool>,
+
+    /// Anonymize client IP addresses in the structured access log. (Proxy has to be restarted for changes to take effect)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_log_anonymize_ip: Option<bool>,
+
+    /// Require a confirmation token, obtained from a preview call, for forgetting snapshots and groups and for removing datastores.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub safe_mode: Option<bool>,
+
+    /// Disaster recovery metadata export settings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dr_export: Option<String>,
+
+    /// Active-standby failover settings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failover: Option<String>,
+
+    /// IO priorities of the maintenance tasks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_io_priority: Option<String>,
+
+    /// CPU limits for garbage collection tasks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_limits_gc: Option<String>,
+
+    /// CPU limits for verification tasks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_limits_verify: Option<String>,
+
+    /// CPU limits for tape backup and restore tasks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_limits_tape: Option<String>,
+
+    /// Custom backup types accepted for new backups, in addition to vm, ct and host.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_backup_types: Option<String>,
 }
 
 impl NodeConfig {
@@ -234,6 +489,39 @@ impl NodeConfig {
         })
     }
 
+    pub fn dr_export_config(&self) -> Option<Result<DrExportConfig, Error>> {
+        self.dr_export.as_deref().map(|config| -> Result<_, Error> {
+            crate::tools::config::from_property_string(config, &DrExportConfig::API_SCHEMA)
+        })
+    }
+
+    pub fn failover_config(&self) -> Option<Result<FailoverConfig, Error>> {
+        self.failover.as_deref().map(|config| -> Result<_, Error> {
+            crate::tools::config::from_property_string(config, &FailoverConfig::API_SCHEMA)
+        })
+    }
+
+    pub fn task_io_priority_config(&self) -> Option<Result<TaskIoPriorityConfig, Error>> {
+        self.task_io_priority
+            .as_deref()
+            .map(|config| -> Result<_, Error> {
+                crate::tools::config::from_property_string(
+                    config,
+                    &TaskIoPriorityConfig::API_SCHEMA,
+                )
+            })
+    }
+
+    /// Returns the custom backup types registered on this node.
+    pub fn registered_backup_types(&self) -> Result<Vec<BackupType>, Error> {
+        let types = match self.custom_backup_types.as_deref() {
+            Some(types) => types,
+            None => return Ok(Vec::new()),
+        };
+        let types = CUSTOM_BACKUP_TYPE_LIST_SCHEMA.parse_property_string(types)?;
+        Ok(serde_json::from_value(types)?)
+    }
+
     pub async fn acme_client(&self) -> Result<AcmeClient, Error> {
         let account = if let Some(cfg) = self.acme_config().transpose()? {
             cfg.account
@@ -280,6 +568,11 @@ impl NodeConfig {
         if let Some(ciphers) = self.ciphers_tls_1_2.as_deref() {
             dummy_acceptor.set_cipher_list(ciphers)?;
         }
+        for ty in self.registered_backup_types()? {
+            if ty.is_builtin() {
+                bail!("'{ty}' is a built-in backup type and cannot be registered");
+            }
+        }
 
         Ok(())
     }
@@ -0,0 +1,232 @@
+//! Structured API access log
+//!
+//! Optionally writes one JSON object per request handled by the proxy, in addition to the plain
+//! access log of the REST server. Enabled via the `structured-access-log` node option.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::future::Future;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::fs::OpenOptionsExt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use anyhow::{format_err, Error};
+use hyper::body::HttpBody;
+use hyper::{header, Body, Request, Response};
+use serde_json::json;
+use tokio::net::TcpStream;
+use tokio_openssl::SslStream;
+use tower_service::Service;
+
+use pbs_buildcfg::API_STRUCTURED_ACCESS_LOG_FN;
+
+tokio::task_local! {
+    static REQUEST_AUTH_ID: RefCell<Option<String>>;
+}
+
+/// Remember the authenticated user or token of the request currently handled by this task.
+pub(crate) fn record_auth_id(auth_id: &str) {
+    let _ = REQUEST_AUTH_ID.try_with(|cell| *cell.borrow_mut() = Some(auth_id.to_string()));
+}
+
+/// Strip the host part of an address: IPv4 addresses are truncated to /24, IPv6 addresses to /48.
+pub fn anonymize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(ip) => {
+            let seg = ip.segments();
+            IpAddr::V6(Ipv6Addr::new(seg[0], seg[1], seg[2], 0, 0, 0, 0, 0))
+        }
+    }
+}
+
+/// The structured access log file.
+pub struct StructuredAccessLog {
+    file: Mutex<File>,
+    anonymize_ip: bool,
+}
+
+fn open_log_file() -> Result<File, Error> {
+    std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o640)
+        .open(API_STRUCTURED_ACCESS_LOG_FN)
+        .map_err(|err| format_err!("unable to open {API_STRUCTURED_ACCESS_LOG_FN:?} - {err}"))
+}
+
+impl StructuredAccessLog {
+    /// Opens the log if it is enabled in the node configuration.
+    pub fn from_node_config() -> Result<Option<Arc<Self>>, Error> {
+        let (config, _digest) = crate::config::node::config()?;
+        if !config.structured_access_log.unwrap_or(false) {
+            return Ok(None);
+        }
+        Ok(Some(Arc::new(Self {
+            file: Mutex::new(open_log_file()?),
+            anonymize_ip: config.access_log_anonymize_ip.unwrap_or(false),
+        })))
+    }
+
+    /// Re-open the log file, used after rotation.
+    pub fn reopen(&self) -> Result<(), Error> {
+        let file = open_log_file()?;
+        *self.file.lock().unwrap() = file;
+        Ok(())
+    }
+
+    fn log(&self, mut entry: serde_json::Value) {
+        if let Some(ip) = entry["client"].as_str().and_then(|ip| ip.parse().ok()) {
+            if self.anonymize_ip {
+                entry["client"] = anonymize_ip(ip).to_string().into();
+            }
+        }
+        let mut file = self.file.lock().unwrap();
+        if let Err(err) = writeln!(file, "{entry}") {
+            log::error!("writing structured access log failed - {err}");
+        }
+    }
+}
+
+/// Connections which know the address of their client.
+pub trait ClientAddress {
+    fn client_addr(&self) -> Option<SocketAddr>;
+}
+
+impl ClientAddress for Pin<Box<SslStream<TcpStream>>> {
+    fn client_addr(&self) -> Option<SocketAddr> {
+        (**self).get_ref().peer_addr().ok()
+    }
+}
+
+/// Wraps the connection level service of the REST server to log every request.
+pub struct AccessLogServer<S> {
+    inner: S,
+    log: Option<Arc<StructuredAccessLog>>,
+}
+
+impl<S> AccessLogServer<S> {
+    pub fn new(inner: S, log: Option<Arc<StructuredAccessLog>>) -> Self {
+        Self { inner, log }
+    }
+}
+
+impl<'a, T, S> Service<&'a T> for AccessLogServer<S>
+where
+    T: ClientAddress,
+    S: Service<&'a T>,
+    S::Future: Send + 'static,
+    S::Response: Send + 'static,
+{
+    type Response = AccessLogService<S::Response>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, conn: &'a T) -> Self::Future {
+        let peer = conn.client_addr();
+        let log = self.log.clone();
+        let future = self.inner.call(conn);
+        Box::pin(async move {
+            let inner = future.await?;
+            Ok(AccessLogService { inner, peer, log })
+        })
+    }
+}
+
+/// Request level service logging each request to the structured access log.
+pub struct AccessLogService<S> {
+    inner: S,
+    peer: Option<SocketAddr>,
+    log: Option<Arc<StructuredAccessLog>>,
+}
+
+fn response_size(response: &Response<Body>) -> Option<u64> {
+    response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .or_else(|| response.body().size_hint().exact())
+}
+
+impl<S> Service<Request<Body>> for AccessLogService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let log = match &self.log {
+            Some(log) => Arc::clone(log),
+            None => return Box::pin(self.inner.call(req)),
+        };
+
+        let start = Instant::now();
+        let time = proxmox_time::epoch_i64();
+        let method = req.method().to_string();
+        // the query is left out on purpose, it may contain sensitive parameters
+        let path = req.uri().path().to_string();
+        let correlation_id =
+            crate::api2::helpers::correlation_id(req.headers()).map(|id| id.to_string());
+        let client = self.peer.map(|peer| peer.ip().to_string());
+
+        let future = self.inner.call(req);
+
+        Box::pin(REQUEST_AUTH_ID.scope(RefCell::new(None), async move {
+            let result = future.await;
+            let auth_id = REQUEST_AUTH_ID.with(|cell| cell.borrow_mut().take());
+
+            let (status, bytes) = match &result {
+                Ok(response) => (Some(response.status().as_u16()), response_size(response)),
+                Err(_) => (None, None),
+            };
+
+            log.log(json!({
+                "time": time,
+                "client": client,
+                "authid": auth_id,
+                "method": method,
+                "path": path,
+                "status": status,
+                "duration": start.elapsed().as_secs_f64(),
+                "bytes": bytes,
+                "correlation-id": correlation_id,
+            }));
+
+            result
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_anonymize_ip() {
+        let ip: IpAddr = "192.168.17.42".parse().unwrap();
+        assert_eq!(anonymize_ip(ip).to_string(), "192.168.17.0");
+
+        let ip: IpAddr = "2001:db8:1234:5678::1".parse().unwrap();
+        assert_eq!(anonymize_ip(ip).to_string(), "2001:db8:1234::");
+    }
+}
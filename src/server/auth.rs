@@ -8,6 +8,8 @@ pub async fn check_pbs_auth(
     method: &hyper::Method,
 ) -> Result<(String, Box<dyn UserInformation + Sync + Send>), AuthError> {
     let user_info = CachedUserInfo::new()?;
-    proxmox_auth_api::api::http_check_auth(headers, method)
-        .map(move |name| (name, Box::new(user_info) as _))
+    proxmox_auth_api::api::http_check_auth(headers, method).map(move |name| {
+        crate::server::access_log::record_auth_id(&name);
+        (name, Box::new(user_info) as _)
+    })
 }
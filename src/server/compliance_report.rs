@@ -0,0 +1,322 @@
+//! Compliance reports
+//!
+//! Summarizes backup task results, verification coverage, missed backups and recovery point
+//! objective (RPO) violations of a datastore for a time period.
+
+use std::sync::Arc;
+
+use anyhow::{format_err, Error};
+
+use proxmox_rest_server::{TaskListInfoIterator, TaskState, WorkerTask};
+use proxmox_sys::task_log;
+
+use pbs_api_types::{
+    Authid, BackupGroup, BackupNamespace, ComplianceReport, ComplianceReportPeriod,
+    ExpectedBackupConfig, Operation, SnapshotVerifyState, VerifyState, PRIV_DATASTORE_AUDIT,
+};
+use pbs_config::CachedUserInfo;
+use pbs_datastore::backup_info::BackupInfo;
+use pbs_datastore::DataStore;
+
+use crate::backup::lookup_group_privs;
+use crate::server::jobstate::Job;
+use crate::server::{check_expected_backups, describe_overdue};
+
+/// Restricts a compliance report to what a user may audit.
+pub struct ComplianceReportAccess {
+    user_info: Arc<CachedUserInfo>,
+    auth_id: Authid,
+}
+
+impl ComplianceReportAccess {
+    pub fn new(auth_id: Authid) -> Result<Self, Error> {
+        Ok(Self {
+            user_info: CachedUserInfo::new()?,
+            auth_id,
+        })
+    }
+
+    // task counts are per datastore, so they require audit on the datastore itself
+    fn can_audit_store(&self, store: &str) -> bool {
+        self.user_info
+            .lookup_privs(&self.auth_id, &["datastore", store])
+            & PRIV_DATASTORE_AUDIT
+            != 0
+    }
+
+    fn can_audit_group(&self, store: &str, ns: &BackupNamespace, group: &BackupGroup) -> bool {
+        lookup_group_privs(&self.user_info, &self.auth_id, store, ns, group) & PRIV_DATASTORE_AUDIT
+            != 0
+    }
+}
+
+fn percentage(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 100.0;
+    }
+    (part as f64 * 10000.0 / total as f64).round() / 100.0
+}
+
+/// Count finished backup tasks of `store` started within the period as (ok, failed).
+///
+/// Backup task IDs do not contain the namespace, so this always covers the whole datastore.
+fn count_backup_tasks(store: &str, since: i64, until: i64) -> Result<(u64, u64), Error> {
+    let prefix = format!("{}:", store);
+
+    let mut ok = 0;
+    let mut failed = 0;
+
+    for info in TaskListInfoIterator::new(false)? {
+        let info = info.map_err(|err| format_err!("unable to read task list - {err}"))?;
+        let state = match &info.state {
+            Some(state) => state,
+            None => continue,
+        };
+        if state.endtime() < since {
+            // the list is sorted by time, nothing of interest follows
+            break;
+        }
+        if info.upid.starttime < since || info.upid.starttime > until {
+            continue;
+        }
+        if info.upid.worker_type != "backup" {
+            continue;
+        }
+        match &info.upid.worker_id {
+            Some(worker_id) if worker_id.starts_with(&prefix) => {}
+            _ => continue,
+        }
+        match state {
+            TaskState::OK { .. } | TaskState::Warning { .. } => ok += 1,
+            _ => failed += 1,
+        }
+    }
+
+    Ok((ok, failed))
+}
+
+/// Find gaps between recovery points longer than the maximum age of `job` within the period.
+fn rpo_violations(
+    datastore: &Arc<DataStore>,
+    job: &ExpectedBackupConfig,
+    since: i64,
+    until: i64,
+    visible: &dyn Fn(&BackupNamespace, &BackupGroup) -> bool,
+) -> Result<Vec<String>, Error> {
+    let ns = job.ns.clone().unwrap_or_default();
+    let filters = job.group_filter.as_deref().unwrap_or(&[]);
+    let max_age = (job.max_age * 3600) as i64;
+
+    let mut violations = Vec::new();
+
+    for group in datastore.iter_backup_groups_ok(ns.clone())? {
+        if !group.group().apply_filters(filters) || !visible(&ns, group.group()) {
+            continue;
+        }
+        let name = if ns.is_root() {
+            group.group().to_string()
+        } else {
+            format!("[{}]:{}", ns, group.group())
+        };
+
+        let mut backups = group.list_backups()?;
+        BackupInfo::sort_list(&mut backups, true);
+
+        let mut times = backups
+            .iter()
+            .filter(|info| info.is_finished())
+            .map(|info| info.backup_dir.backup_time())
+            .filter(|time| *time <= until)
+            .collect::<Vec<i64>>();
+        // the age of the last recovery point matters up to the end of the period
+        times.push(until);
+
+        for window in times.windows(2) {
+            let (last, next) = (window[0], window[1]);
+            if next <= since || next - last <= max_age {
+                continue;
+            }
+            violations.push(format!(
+                "{}: no recovery point between {} and {} ({}h)",
+                name,
+                proxmox_time::epoch_to_rfc3339_utc(last)?,
+                proxmox_time::epoch_to_rfc3339_utc(next)?,
+                (next - last) / 3600,
+            ));
+        }
+    }
+
+    Ok(violations)
+}
+
+/// Generate a compliance report for `store` (or the namespace subtree `ns` of it).
+///
+/// Missed backups and RPO violations are derived from the expected backup entries of the
+/// datastore. With `access`, only the groups the user may audit are included, and the backup
+/// task counts only if the user may audit the whole datastore.
+pub fn generate_compliance_report(
+    store: &str,
+    ns: Option<BackupNamespace>,
+    since: i64,
+    until: i64,
+    access: Option<&ComplianceReportAccess>,
+) -> Result<ComplianceReport, Error> {
+    let datastore = DataStore::lookup_datastore(store, Some(Operation::Read))?;
+    let report_ns = ns.clone().unwrap_or_default();
+
+    let visible = |ns: &BackupNamespace, group: &BackupGroup| {
+        access.map_or(true, |access| access.can_audit_group(store, ns, group))
+    };
+
+    let task_counts = match access {
+        Some(access) if !access.can_audit_store(store) => None,
+        _ => Some(count_backup_tasks(store, since, until)?),
+    };
+
+    let mut snapshots = 0;
+    let mut verified = 0;
+    let mut verify_failed = 0;
+
+    for ns in datastore.recursive_iter_backup_ns_ok(report_ns.clone(), None)? {
+        for group in datastore.iter_backup_groups_ok(ns.clone())? {
+            if !visible(&ns, group.group()) {
+                continue;
+            }
+            for info in group.list_backups()? {
+                let time = info.backup_dir.backup_time();
+                if time < since || time > until || !info.is_finished() {
+                    continue;
+                }
+                snapshots += 1;
+
+                let verify_state =
+                    info.backup_dir
+                        .load_manifest()
+                        .ok()
+                        .and_then(|(manifest, _)| {
+                            let state = manifest.unprotected["verify_state"].clone();
+                            serde_json::from_value::<SnapshotVerifyState>(state).ok()
+                        });
+                match verify_state.map(|verify_state| verify_state.state) {
+                    Some(VerifyState::Ok) => verified += 1,
+                    Some(VerifyState::Failed) => verify_failed += 1,
+                    None => {}
+                }
+            }
+        }
+    }
+
+    let (config, _digest) = pbs_config::expected_backup::config()?;
+    let jobs: Vec<ExpectedBackupConfig> = config.convert_to_typed_array("expected")?;
+
+    let mut missed_backups = Vec::new();
+    let mut rpo_violations_list = Vec::new();
+
+    for job in jobs.iter().filter(|job| !job.disable && job.store == store) {
+        if report_ns
+            .contains(&job.ns.clone().unwrap_or_default())
+            .is_none()
+        {
+            continue;
+        }
+        let job_ns = job.ns.clone().unwrap_or_default();
+        missed_backups.extend(
+            check_expected_backups(job, until)?
+                .iter()
+                .filter(|status| status.overdue && visible(&job_ns, &status.group))
+                .map(|status| describe_overdue(status, until)),
+        );
+        rpo_violations_list.extend(rpo_violations(&datastore, job, since, until, &visible)?);
+    }
+
+    Ok(ComplianceReport {
+        store: store.to_string(),
+        ns: ns.filter(|ns| !ns.is_root()),
+        since,
+        until,
+        backups_ok: task_counts.map(|(ok, _)| ok),
+        backups_failed: task_counts.map(|(_, failed)| failed),
+        success_rate: task_counts.map(|(ok, failed)| percentage(ok, ok + failed)),
+        snapshots,
+        verified,
+        verify_failed,
+        verify_coverage: percentage(verified, snapshots),
+        missed_backups,
+        rpo_violations: rpo_violations_list,
+    })
+}
+
+/// The period covered by a scheduled report generated at `now`: the last 7 days, or the
+/// previous calendar month in local time.
+fn report_period(period: ComplianceReportPeriod, now: i64) -> Result<(i64, i64), Error> {
+    match period {
+        ComplianceReportPeriod::Weekly => Ok((now - 7 * 86400, now)),
+        ComplianceReportPeriod::Monthly => {
+            let mut tm = proxmox_time::localtime(now)?;
+            tm.tm_sec = 0;
+            tm.tm_min = 0;
+            tm.tm_hour = 0;
+            tm.tm_mday = 1;
+            tm.tm_isdst = -1;
+            let until = proxmox_time::timelocal(&mut tm)?;
+
+            // mktime normalizes month -1 to december of the previous year
+            tm.tm_mon -= 1;
+            tm.tm_isdst = -1;
+            let since = proxmox_time::timelocal(&mut tm)?;
+
+            Ok((since, until))
+        }
+    }
+}
+
+/// Runs a worker generating the compliance report of `store` for the last `period` and sends
+/// it as notification.
+pub fn do_compliance_report_job(
+    mut job: Job,
+    store: String,
+    period: ComplianceReportPeriod,
+    auth_id: &Authid,
+    to_stdout: bool,
+) -> Result<String, Error> {
+    let worker_type = job.jobtype().to_string();
+    let upid_str = WorkerTask::new_thread(
+        &worker_type,
+        Some(store.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            job.start(&worker.upid().to_string())?;
+
+            let (since, until) = report_period(period, proxmox_time::epoch_i64())?;
+
+            task_log!(
+                worker,
+                "generating compliance report for datastore '{}'",
+                store
+            );
+
+            let result = generate_compliance_report(&store, None, since, until, None).and_then(|report| {
+                task_log!(
+                    worker,
+                    "backup success rate {}%, verify coverage {}%, {} missed backups, {} RPO violations",
+                    report.success_rate.unwrap_or(100.0),
+                    report.verify_coverage,
+                    report.missed_backups.len(),
+                    report.rpo_violations.len(),
+                );
+                crate::server::send_compliance_report(&report)
+            });
+
+            let status = worker.create_state(&result);
+
+            if let Err(err) = job.finish(status) {
+                eprintln!("could not finish job state for {}: {err}", job.jobtype());
+            }
+
+            result
+        },
+    )?;
+
+    Ok(upid_str)
+}
@@ -0,0 +1,509 @@
+//! Disaster recovery metadata export
+//!
+//! Exports all configuration files and the manifests of all snapshots (but no chunks) into a
+//! small encrypted bundle. After a total server loss, the bundle allows to recreate the
+//! datastore skeleton including owners, notes and limits, and to restore the notes, verify
+//! state and protection of snapshots once their data got re-seeded, e.g. by a sync job.
+
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use nix::sys::stat::Mode;
+use nix::unistd::{Gid, Uid};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use proxmox_rest_server::WorkerTask;
+use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
+use proxmox_sys::{task_log, task_warn};
+
+use pbs_api_types::{Authid, BackupNamespace, DataStoreConfig, Operation};
+use pbs_buildcfg::CONFIGDIR;
+use pbs_datastore::manifest::MANIFEST_BLOB_NAME;
+use pbs_datastore::{DataBlob, DataStore};
+use pbs_key_config::load_and_decrypt_key;
+use pbs_tools::crypt_config::CryptConfig;
+
+use crate::config::node::DrExportConfig;
+use crate::server::jobstate::Job;
+
+const BUNDLE_VERSION: u32 = 1;
+const BUNDLE_PREFIX: &str = "pbs-dr-";
+const BUNDLE_EXTENSION: &str = ".blob";
+const GROUP_NOTES_FILE_NAME: &str = "notes";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ConfigFile {
+    name: String,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    /// base64 encoded file content
+    data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct SnapshotMetadata {
+    time: i64,
+    protected: bool,
+    manifest: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct GroupMetadata {
+    ns: BackupNamespace,
+    #[serde(flatten)]
+    group: pbs_api_types::BackupGroup,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<Authid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_snapshots: Option<usize>,
+    snapshots: Vec<SnapshotMetadata>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DatastoreMetadata {
+    name: String,
+    namespaces: Vec<BackupNamespace>,
+    groups: Vec<GroupMetadata>,
+}
+
+/// Content of a disaster recovery bundle.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DrBundle {
+    version: u32,
+    created: i64,
+    hostname: String,
+    configs: Vec<ConfigFile>,
+    datastores: Vec<DatastoreMetadata>,
+}
+
+/// Load the bundle encryption key, only keys without passphrase are supported for the job.
+fn load_crypt_config(keyfile: &str) -> Result<CryptConfig, Error> {
+    let (key, _created, _fingerprint) = load_and_decrypt_key(Path::new(keyfile), &|| {
+        bail!("key file {keyfile:?} is protected by a passphrase")
+    })?;
+    CryptConfig::new(key)
+}
+
+/// Collect all configuration files, a bundle without e.g. the shadow files or the tape
+/// encryption keys would be useless for recovery, so unreadable files are an error.
+fn collect_configs() -> Result<Vec<ConfigFile>, Error> {
+    let mut configs = Vec::new();
+    let mut unreadable = Vec::new();
+
+    for entry in std::fs::read_dir(CONFIGDIR)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // skip lock files
+        if name.starts_with('.') {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        match std::fs::read(entry.path()) {
+            Ok(data) => configs.push(ConfigFile {
+                name,
+                mode: metadata.mode() & 0o7777,
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+                data: base64::encode(data),
+            }),
+            Err(err) => unreadable.push(format!("'{name}' ({err})")),
+        }
+    }
+
+    if !unreadable.is_empty() {
+        bail!(
+            "unable to read config files {} - the export needs to run as root",
+            unreadable.join(", ")
+        );
+    }
+
+    configs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(configs)
+}
+
+fn collect_datastore(
+    worker: &WorkerTask,
+    datastore: &Arc<DataStore>,
+) -> Result<DatastoreMetadata, Error> {
+    let namespaces: Vec<BackupNamespace> = datastore
+        .recursive_iter_backup_ns_ok(BackupNamespace::root(), None)?
+        .collect();
+
+    let mut groups = Vec::new();
+
+    for ns in &namespaces {
+        for group in datastore.iter_backup_groups_ok(ns.clone())? {
+            let notes_path = group.full_group_path().join(GROUP_NOTES_FILE_NAME);
+
+            let mut snapshots = Vec::new();
+            for info in group.list_backups()? {
+                if !info.is_finished() {
+                    continue;
+                }
+                let manifest = match info.backup_dir.load_manifest() {
+                    Ok((manifest, _)) => serde_json::to_value(manifest)?,
+                    Err(err) => {
+                        task_warn!(worker, "skipping snapshot {:?} - {err}", info.backup_dir);
+                        continue;
+                    }
+                };
+                snapshots.push(SnapshotMetadata {
+                    time: info.backup_dir.backup_time(),
+                    protected: info.protected,
+                    manifest,
+                });
+            }
+
+            groups.push(GroupMetadata {
+                ns: ns.clone(),
+                group: group.group().clone(),
+                owner: group.get_owner().ok(),
+                notes: file_read_optional_string(notes_path)?,
+                max_snapshots: group.max_snapshots()?,
+                snapshots,
+            });
+        }
+    }
+
+    Ok(DatastoreMetadata {
+        name: datastore.name().to_string(),
+        namespaces,
+        groups,
+    })
+}
+
+fn collect_datastores(worker: &WorkerTask) -> Result<Vec<DatastoreMetadata>, Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let list: Vec<DataStoreConfig> = config.convert_to_typed_array("datastore")?;
+
+    let mut datastores = Vec::new();
+
+    for store in list {
+        let datastore = match DataStore::lookup_datastore(&store.name, Some(Operation::Read)) {
+            Ok(datastore) => datastore,
+            Err(err) => {
+                task_warn!(worker, "skipping datastore '{}' - {err}", store.name);
+                continue;
+            }
+        };
+        match collect_datastore(worker, &datastore) {
+            Ok(metadata) => {
+                let snapshots: usize = metadata.groups.iter().map(|g| g.snapshots.len()).sum();
+                task_log!(
+                    worker,
+                    "datastore '{}': {} groups, {snapshots} snapshots",
+                    store.name,
+                    metadata.groups.len(),
+                );
+                datastores.push(metadata);
+            }
+            Err(err) => task_warn!(worker, "skipping datastore '{}' - {err}", store.name),
+        }
+    }
+
+    Ok(datastores)
+}
+
+fn remove_old_bundles(
+    worker: &WorkerTask,
+    path: &str,
+    prefix: &str,
+    keep: usize,
+) -> Result<(), Error> {
+    let mut bundles = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if name.starts_with(prefix) && name.ends_with(BUNDLE_EXTENSION) {
+            bundles.push(name);
+        }
+    }
+
+    // the timestamp in the file name sorts chronologically
+    bundles.sort_unstable();
+
+    let remove = bundles.len().saturating_sub(keep);
+    for name in &bundles[..remove] {
+        task_log!(worker, "removing old bundle {name}");
+        if let Err(err) = std::fs::remove_file(Path::new(path).join(name)) {
+            task_warn!(worker, "unable to remove {name} - {err}");
+        }
+    }
+
+    Ok(())
+}
+
+fn export_metadata(worker: &WorkerTask, config: &DrExportConfig) -> Result<(), Error> {
+    let crypt_config = load_crypt_config(&config.keyfile)?;
+
+    let now = proxmox_time::epoch_i64();
+    let hostname = proxmox_sys::nodename().to_string();
+
+    let bundle = DrBundle {
+        version: BUNDLE_VERSION,
+        created: now,
+        hostname: hostname.clone(),
+        configs: collect_configs()?,
+        datastores: collect_datastores(worker)?,
+    };
+
+    let data = serde_json::to_vec(&bundle)?;
+    let blob = DataBlob::encode(&data, Some(&crypt_config), true)?;
+
+    let prefix = format!("{BUNDLE_PREFIX}{hostname}-");
+    let stamp = proxmox_time::strftime_utc("%Y%m%dT%H%M%SZ", now)?;
+    let filename = Path::new(&config.path).join(format!("{prefix}{stamp}{BUNDLE_EXTENSION}"));
+
+    replace_file(
+        &filename,
+        blob.raw_data(),
+        CreateOptions::new().perm(Mode::from_bits_truncate(0o600)),
+        true,
+    )?;
+
+    task_log!(
+        worker,
+        "wrote {:?} ({} bytes, {} config files)",
+        filename,
+        blob.raw_data().len(),
+        bundle.configs.len(),
+    );
+
+    remove_old_bundles(worker, &config.path, &prefix, config.keep.unwrap_or(7))
+}
+
+/// Runs the disaster recovery metadata export configured in the node config.
+///
+/// Must be called in the privileged API daemon, as the bundle includes root-only config files.
+pub fn do_dr_export_job(mut job: Job, auth_id: &Authid, to_stdout: bool) -> Result<String, Error> {
+    let (node_config, _digest) = crate::config::node::config()?;
+    let config = match node_config.dr_export_config() {
+        Some(config) => config?,
+        None => bail!("no disaster recovery export configured"),
+    };
+
+    let worker_type = job.jobtype().to_string();
+    let upid_str = WorkerTask::new_thread(
+        &worker_type,
+        None,
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            job.start(&worker.upid().to_string())?;
+
+            task_log!(
+                worker,
+                "exporting disaster recovery metadata to {:?}",
+                config.path
+            );
+
+            let result = export_metadata(&worker, &config);
+
+            let status = worker.create_state(&result);
+
+            if let Err(err) = job.finish(status) {
+                eprintln!("could not finish job state for {}: {err}", job.jobtype());
+            }
+
+            result
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+/// Let the privileged API daemon start the scheduled export, used by the proxy.
+pub async fn request_dr_export() -> Result<(), Error> {
+    let api_pid = proxmox_rest_server::read_pid(pbs_buildcfg::PROXMOX_BACKUP_API_PID_FN)?;
+    let sock = proxmox_rest_server::ctrl_sock_from_pid(api_pid);
+    let _: Value =
+        proxmox_rest_server::send_raw_command(sock, "{\"command\":\"dr-export\"}\n").await?;
+    Ok(())
+}
+
+impl DrBundle {
+    /// Load and decrypt a bundle written by the export job.
+    pub fn load(path: &Path, crypt_config: &CryptConfig) -> Result<Self, Error> {
+        let mut file = std::fs::File::open(path)
+            .map_err(|err| format_err!("unable to open {path:?} - {err}"))?;
+        let blob = DataBlob::load_from_reader(&mut file)?;
+        let data = blob.decode(Some(crypt_config), None)?;
+        let bundle: Self = serde_json::from_slice(&data)?;
+        if bundle.version != BUNDLE_VERSION {
+            bail!("unsupported bundle version {}", bundle.version);
+        }
+        Ok(bundle)
+    }
+
+    /// The node the bundle was created on.
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    /// Creation time of the bundle.
+    pub fn created(&self) -> i64 {
+        self.created
+    }
+
+    /// Write all configuration files of the bundle back, with their original permissions.
+    ///
+    /// Needs to run as root, existing files get overwritten.
+    pub fn restore_configs(&self) -> Result<(), Error> {
+        for config in &self.configs {
+            if config.name.contains('/') {
+                bail!("invalid config file name '{}'", config.name);
+            }
+            let data = base64::decode(&config.data)?;
+            let options = CreateOptions::new()
+                .perm(Mode::from_bits_truncate(config.mode))
+                .owner(Uid::from_raw(config.uid))
+                .group(Gid::from_raw(config.gid));
+            replace_file(
+                Path::new(CONFIGDIR).join(&config.name),
+                &data,
+                options,
+                true,
+            )?;
+            log::info!("restored config file '{}'", config.name);
+        }
+        Ok(())
+    }
+
+    /// Recreate namespaces and groups of all datastores in the bundle, and restore the
+    /// snapshot metadata of all snapshots already present again.
+    ///
+    /// Datastores must exist (or got restored via the configs) beforehand. Running this again
+    /// after the snapshots got re-seeded restores their notes, verify state and protection.
+    pub fn restore_datastores(&self) -> Result<(), Error> {
+        for metadata in &self.datastores {
+            let datastore = DataStore::lookup_datastore(&metadata.name, Some(Operation::Write));
+            let datastore = match datastore {
+                Ok(datastore) => datastore,
+                Err(err) => {
+                    log::warn!("skipping datastore '{}' - {err}", metadata.name);
+                    continue;
+                }
+            };
+            restore_datastore(&datastore, metadata)?;
+        }
+        Ok(())
+    }
+}
+
+fn restore_namespaces(
+    datastore: &Arc<DataStore>,
+    namespaces: &[BackupNamespace],
+) -> Result<(), Error> {
+    let mut namespaces: Vec<&BackupNamespace> = namespaces.iter().collect();
+    // parents need to exist first
+    namespaces.sort_by_key(|ns| ns.depth());
+
+    for ns in namespaces {
+        if ns.is_root() || datastore.namespace_exists(ns) {
+            continue;
+        }
+        datastore.create_namespace(&ns.parent(), ns.name())?;
+    }
+
+    Ok(())
+}
+
+fn restore_snapshot(
+    group: &pbs_datastore::BackupGroup,
+    snapshot: &SnapshotMetadata,
+) -> Result<bool, Error> {
+    let backup_dir = group.backup_dir(snapshot.time)?;
+    if !backup_dir.full_path().join(MANIFEST_BLOB_NAME).exists() {
+        return Ok(false);
+    }
+
+    let unprotected = &snapshot.manifest["unprotected"];
+    backup_dir.update_manifest(|manifest| {
+        for key in ["notes", "verify_state"] {
+            if manifest.unprotected[key].is_null() && !unprotected[key].is_null() {
+                manifest.unprotected[key] = unprotected[key].clone();
+            }
+        }
+    })?;
+
+    if snapshot.protected && !backup_dir.is_protected() {
+        backup_dir
+            .datastore()
+            .update_protection(&backup_dir, true)?;
+    }
+
+    Ok(true)
+}
+
+fn restore_datastore(
+    datastore: &Arc<DataStore>,
+    metadata: &DatastoreMetadata,
+) -> Result<(), Error> {
+    restore_namespaces(datastore, &metadata.namespaces)?;
+
+    let mut restored = 0;
+    let mut missing = 0;
+
+    for entry in &metadata.groups {
+        let group = datastore.backup_group(entry.ns.clone(), entry.group.clone());
+
+        if !group.exists() {
+            let owner = entry
+                .owner
+                .clone()
+                .unwrap_or_else(|| Authid::root_auth_id().clone());
+            datastore.create_locked_backup_group(&entry.ns, &entry.group, &owner)?;
+        } else if let Some(owner) = &entry.owner {
+            if group.get_owner().is_err() {
+                group.set_owner(owner, false)?;
+            }
+        }
+
+        if let Some(notes) = &entry.notes {
+            let notes_path = group.full_group_path().join(GROUP_NOTES_FILE_NAME);
+            if !notes_path.exists() {
+                replace_file(notes_path, notes.as_bytes(), CreateOptions::new(), false)?;
+            }
+        }
+
+        if entry.max_snapshots.is_some() && group.max_snapshots()?.is_none() {
+            group.set_max_snapshots(entry.max_snapshots)?;
+        }
+
+        for snapshot in &entry.snapshots {
+            match restore_snapshot(&group, snapshot) {
+                Ok(true) => restored += 1,
+                Ok(false) => missing += 1,
+                Err(err) => {
+                    log::warn!(
+                        "restoring metadata of {group:?}/{} failed - {err}",
+                        snapshot.time
+                    )
+                }
+            }
+        }
+    }
+
+    log::info!(
+        "datastore '{}': {} groups, restored metadata of {restored} snapshots, {missing} \
+        snapshots not (yet) re-seeded",
+        metadata.name,
+        metadata.groups.len(),
+    );
+
+    Ok(())
+}
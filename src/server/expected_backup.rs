@@ -0,0 +1,180 @@
+//! Expected backup monitoring
+//!
+//! Checks the groups described by the expected backup configuration for missing or outdated
+//! backups, and notifies once per missed backup window.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{bail, Error};
+use serde_json::json;
+
+use proxmox_rest_server::WorkerTask;
+use proxmox_sys::fs::{file_get_json, replace_file, CreateOptions};
+use proxmox_sys::{task_log, task_warn};
+
+use pbs_api_types::{
+    Authid, BackupGroup, ExpectedBackupConfig, ExpectedBackupGroupStatus, FilterType, Operation,
+};
+use pbs_buildcfg::PROXMOX_BACKUP_STATE_DIR_M;
+use pbs_datastore::DataStore;
+
+use crate::server::jobstate::Job;
+
+/// Overdue groups we already sent a notification for, with their last backup time.
+const NOTIFY_STATE_FN: &str = concat!(PROXMOX_BACKUP_STATE_DIR_M!(), "/expected-backups.json");
+
+/// Compute the status of all groups monitored by `job`.
+pub fn check_expected_backups(
+    job: &ExpectedBackupConfig,
+    now: i64,
+) -> Result<Vec<ExpectedBackupGroupStatus>, Error> {
+    let datastore = DataStore::lookup_datastore(&job.store, Some(Operation::Read))?;
+    let ns = job.ns.clone().unwrap_or_default();
+    let filters = job.group_filter.as_deref().unwrap_or(&[]);
+    let max_age = (job.max_age * 3600) as i64;
+
+    let mut groups: BTreeMap<BackupGroup, Option<i64>> = BTreeMap::new();
+
+    for group in datastore.iter_backup_groups_ok(ns)? {
+        if !group.group().apply_filters(filters) {
+            continue;
+        }
+        let last_backup = group.last_successful_backup()?;
+        groups.insert(group.group().clone(), last_backup);
+    }
+
+    // explicitly named groups are expected even if they never backed up
+    for filter in filters.iter().filter(|filter| !filter.is_exclude) {
+        if let FilterType::Group(name) = &filter.filter_type {
+            let group: BackupGroup = name.parse()?;
+            if group.apply_filters(filters) {
+                groups.entry(group).or_insert(None);
+            }
+        }
+    }
+
+    Ok(groups
+        .into_iter()
+        .map(|(group, last_backup)| ExpectedBackupGroupStatus {
+            id: job.id.clone(),
+            ns: job.ns.clone(),
+            group,
+            last_backup,
+            overdue: last_backup.map(|last| now - last > max_age).unwrap_or(true),
+        })
+        .collect())
+}
+
+fn group_name(status: &ExpectedBackupGroupStatus) -> String {
+    match &status.ns {
+        Some(ns) if !ns.is_root() => format!("[{}]:{}", ns, status.group),
+        _ => status.group.to_string(),
+    }
+}
+
+/// Human readable description of an overdue group.
+pub(crate) fn describe_overdue(status: &ExpectedBackupGroupStatus, now: i64) -> String {
+    let name = group_name(status);
+    match status.last_backup {
+        Some(last) => format!("no backup for {} in {}h", name, (now - last) / 3600),
+        None => format!("no backup for {} found", name),
+    }
+}
+
+fn check_all_expected_backups(worker: &WorkerTask) -> Result<(), Error> {
+    let (config, _digest) = pbs_config::expected_backup::config()?;
+    let list: Vec<ExpectedBackupConfig> = config.convert_to_typed_array("expected")?;
+
+    let now = proxmox_time::epoch_i64();
+
+    let notified: HashMap<String, Option<i64>> =
+        serde_json::from_value(file_get_json(NOTIFY_STATE_FN, Some(json!({})))?)
+            .unwrap_or_default();
+    let mut still_overdue = HashMap::new();
+
+    let mut errors = false;
+
+    for job in list.iter().filter(|job| !job.disable) {
+        let status = match check_expected_backups(job, now) {
+            Ok(status) => status,
+            Err(err) => {
+                task_warn!(worker, "checking '{}' failed - {}", job.id, err);
+                errors = true;
+                continue;
+            }
+        };
+
+        let mut missed = Vec::new();
+        for entry in status.iter().filter(|entry| entry.overdue) {
+            let line = describe_overdue(entry, now);
+            task_log!(worker, "{}: {}", job.id, line);
+
+            // only notify once per missed window, i.e. until a new backup shows up
+            let key = format!("{}/{}", job.id, group_name(entry));
+            if notified.get(&key) != Some(&entry.last_backup) {
+                missed.push(line);
+            }
+            still_overdue.insert(key, entry.last_backup);
+        }
+
+        if !missed.is_empty() {
+            if let Err(err) = crate::server::send_backups_missed(job, &missed) {
+                task_warn!(
+                    worker,
+                    "sending notification for '{}' failed - {}",
+                    job.id,
+                    err
+                );
+            }
+        }
+    }
+
+    let backup_user = pbs_config::backup_user()?;
+    let options = CreateOptions::new()
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+    replace_file(
+        NOTIFY_STATE_FN,
+        &serde_json::to_vec(&still_overdue)?,
+        options,
+        false,
+    )?;
+
+    if errors {
+        bail!("checking some expected backups failed");
+    }
+
+    Ok(())
+}
+
+/// Runs the expected backup check for all configured entries.
+pub fn do_expected_backup_check(
+    mut job: Job,
+    auth_id: &Authid,
+    to_stdout: bool,
+) -> Result<String, Error> {
+    let worker_type = job.jobtype().to_string();
+    let upid_str = WorkerTask::new_thread(
+        &worker_type,
+        None,
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            job.start(&worker.upid().to_string())?;
+
+            task_log!(worker, "checking expected backups");
+
+            let result = check_all_expected_backups(&worker);
+
+            let status = worker.create_state(&result);
+
+            if let Err(err) = job.finish(status) {
+                eprintln!("could not finish job state for {}: {err}", job.jobtype());
+            }
+
+            result
+        },
+    )?;
+
+    Ok(upid_str)
+}
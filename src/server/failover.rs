@@ -0,0 +1,283 @@
+//! Active-standby failover
+//!
+//! Two nodes configure each other as failover peer. The standby continuously pulls from the
+//! active node via a regular sync job and refuses new backups. Promoting the standby demotes
+//! the peer if it is reachable, waits for the backups still running there, runs a final
+//! catch-up sync and increases the failover epoch.
+//! An active node seeing its peer active with a higher epoch demotes itself, so a node coming
+//! back after a forced promotion does not keep accepting writes to the same groups.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use proxmox_rest_server::WorkerTask;
+use proxmox_router::http_bail;
+use proxmox_sys::fs::{file_get_json, replace_file, CreateOptions};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{Authid, FailoverRole, FailoverStatus, Remote, SessionType, SyncJobConfig};
+use pbs_buildcfg::PROXMOX_BACKUP_STATE_DIR_M;
+use pbs_client::HttpClient;
+
+use crate::config::node::FailoverConfig;
+use crate::server::pull::{pull_store, PullParameters};
+use crate::server::session_limits::active_sessions;
+
+const FAILOVER_STATE_FN: &str = concat!(PROXMOX_BACKUP_STATE_DIR_M!(), "/failover.json");
+
+/// How long a promotion waits for backups running on the demoted peer.
+const PEER_BACKUP_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const PEER_BACKUP_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+struct FailoverState {
+    role: FailoverRole,
+    epoch: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changed: Option<i64>,
+}
+
+fn load_state() -> Result<FailoverState, Error> {
+    let data = file_get_json(FAILOVER_STATE_FN, Some(json!({})))?;
+    Ok(serde_json::from_value(data)?)
+}
+
+fn save_state(role: FailoverRole, epoch: u64) -> Result<(), Error> {
+    let state = FailoverState {
+        role,
+        epoch,
+        changed: Some(proxmox_time::epoch_i64()),
+    };
+    let backup_user = pbs_config::backup_user()?;
+    let options = CreateOptions::new()
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+    replace_file(
+        FAILOVER_STATE_FN,
+        &serde_json::to_vec(&state)?,
+        options,
+        true,
+    )
+}
+
+fn failover_config() -> Result<Option<FailoverConfig>, Error> {
+    let (config, _digest) = crate::config::node::config()?;
+    config.failover_config().transpose()
+}
+
+/// Returns the failover role and configuration of this node.
+pub fn failover_status() -> Result<FailoverStatus, Error> {
+    let config = failover_config()?;
+    let state = load_state()?;
+    Ok(FailoverStatus {
+        role: state.role,
+        epoch: state.epoch,
+        changed: state.changed,
+        peer: config.as_ref().map(|config| config.peer.clone()),
+        sync_job: config.map(|config| config.sync_job),
+        active_backups: Some(
+            active_sessions()
+                .iter()
+                .filter(|session| session.session_type == SessionType::Backup)
+                .count(),
+        ),
+    })
+}
+
+fn is_standby() -> Result<bool, Error> {
+    if failover_config()?.is_none() {
+        return Ok(false);
+    }
+    Ok(load_state()?.role == FailoverRole::Standby)
+}
+
+/// Refuse new backups while this node is a failover standby.
+pub fn check_backup_allowed() -> Result<(), Error> {
+    if is_standby()? {
+        http_bail!(
+            SERVICE_UNAVAILABLE,
+            "this server is a failover standby - backups go to the active node"
+        );
+    }
+    Ok(())
+}
+
+/// The failover sync job pulls from the peer, so it must only run on the standby.
+pub fn sync_job_allowed(job_id: &str) -> Result<bool, Error> {
+    match failover_config()? {
+        Some(config) if config.sync_job == job_id => {
+            Ok(load_state()?.role == FailoverRole::Standby)
+        }
+        _ => Ok(true),
+    }
+}
+
+/// Demote this node to standby, adopting the epoch of the promoted peer if it is newer.
+pub fn demote(epoch: Option<u64>) -> Result<(), Error> {
+    if failover_config()?.is_none() {
+        bail!("no failover peer configured");
+    }
+    let state = load_state()?;
+    let epoch = epoch.map_or(state.epoch, |epoch| epoch.max(state.epoch));
+    save_state(FailoverRole::Standby, epoch)
+}
+
+async fn peer_client(peer: &str) -> Result<HttpClient, Error> {
+    let (remote_config, _digest) = pbs_config::remote::config()?;
+    let remote: Remote = remote_config.lookup("remote", peer)?;
+    crate::api2::config::remote::remote_client(&remote, None).await
+}
+
+async fn peer_status(client: &HttpClient) -> Result<FailoverStatus, Error> {
+    let mut result = client.get("api2/json/admin/failover/status", None).await?;
+    Ok(serde_json::from_value(result["data"].take())?)
+}
+
+/// Wait for the backups still running on the demoted peer, so the catch-up sync includes them.
+async fn wait_for_peer_backups(
+    worker: &WorkerTask,
+    client: &HttpClient,
+    peer: &str,
+) -> Result<(), Error> {
+    let start = Instant::now();
+    loop {
+        let running = peer_status(client).await?.active_backups.unwrap_or(0);
+        if running == 0 {
+            return Ok(());
+        }
+        if start.elapsed() >= PEER_BACKUP_TIMEOUT {
+            bail!("{running} backups still running on peer '{peer}'");
+        }
+        task_log!(
+            worker,
+            "waiting for {running} backups to finish on peer '{peer}'"
+        );
+        worker.check_abort()?;
+        tokio::time::sleep(PEER_BACKUP_POLL_INTERVAL).await;
+    }
+}
+
+async fn promote(worker: &WorkerTask, force: bool) -> Result<(), Error> {
+    let config = failover_config()?.ok_or_else(|| format_err!("no failover peer configured"))?;
+    let state = load_state()?;
+    if state.role == FailoverRole::Active {
+        bail!("this node is already the active node");
+    }
+
+    let peer = async {
+        let client = peer_client(&config.peer).await?;
+        let status = peer_status(&client).await?;
+        Ok::<_, Error>((client, status))
+    }
+    .await;
+
+    let epoch = match peer {
+        Ok((client, status)) => {
+            let epoch = state.epoch.max(status.epoch) + 1;
+            if status.role == FailoverRole::Active {
+                task_log!(worker, "demoting peer '{}'", config.peer);
+                client
+                    .post(
+                        "api2/json/admin/failover/demote",
+                        Some(json!({ "epoch": epoch })),
+                    )
+                    .await?;
+            }
+
+            if let Err(err) = wait_for_peer_backups(worker, &client, &config.peer).await {
+                if !force {
+                    bail!("{err} - retry once they finished, the peer stays demoted");
+                }
+                task_warn!(worker, "{err} - they are not included in the catch-up sync");
+            }
+
+            task_log!(
+                worker,
+                "catch-up sync from peer via sync job '{}'",
+                config.sync_job
+            );
+            let (sync_config, _digest) = pbs_config::sync::config()?;
+            let sync_job: SyncJobConfig = sync_config.lookup("sync", &config.sync_job)?;
+            if let Err(err) = pull_store(worker, PullParameters::try_from(&sync_job)?).await {
+                if !force {
+                    bail!("catch-up sync failed - {err}");
+                }
+                task_warn!(worker, "catch-up sync failed - {err}");
+            }
+            epoch
+        }
+        Err(err) => {
+            if !force {
+                bail!("peer '{}' not reachable - {err}", config.peer);
+            }
+            task_warn!(worker, "peer '{}' not reachable - {err}", config.peer);
+            task_warn!(
+                worker,
+                "promoting without catch-up sync, the peer demotes itself later"
+            );
+            state.epoch + 1
+        }
+    };
+
+    save_state(FailoverRole::Active, epoch)?;
+    task_log!(worker, "promoted to active node (epoch {epoch})");
+
+    Ok(())
+}
+
+/// Promote this node from standby to active.
+pub fn do_failover_promote(
+    auth_id: &Authid,
+    force: bool,
+    to_stdout: bool,
+) -> Result<String, Error> {
+    WorkerTask::spawn(
+        "failoverpromote",
+        None,
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| async move { promote(&worker, force).await },
+    )
+}
+
+/// Demote this node if the peer got promoted in the meantime.
+///
+/// Called regularly by the scheduler, this protects against both nodes accepting backups after
+/// a forced promotion.
+pub async fn check_failover_peer() -> Result<(), Error> {
+    let config = match failover_config()? {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    let state = load_state()?;
+    if state.role != FailoverRole::Active {
+        return Ok(());
+    }
+
+    let client = peer_client(&config.peer).await?;
+    let status = peer_status(&client).await?;
+    if status.role != FailoverRole::Active {
+        return Ok(());
+    }
+
+    if status.epoch > state.epoch {
+        log::warn!(
+            "failover peer '{}' got promoted (epoch {}) - demoting this node",
+            config.peer,
+            status.epoch
+        );
+        save_state(FailoverRole::Standby, status.epoch)?;
+    } else if status.epoch == state.epoch {
+        log::error!(
+            "failover peer '{}' is active with the same epoch {} - manual intervention required",
+            config.peer,
+            status.epoch
+        );
+    }
+
+    Ok(())
+}
@@ -1,12 +1,17 @@
 use anyhow::Error;
 use std::sync::Arc;
 
-use proxmox_sys::task_log;
+use proxmox_sys::{task_log, task_warn};
 
 use pbs_api_types::Authid;
 use pbs_datastore::DataStore;
 use proxmox_rest_server::WorkerTask;
 
+use crate::server::io_priority::{apply_task_io_priority, TaskIoClass};
+use crate::server::task_cgroup::{enter_task_cgroup, TaskCgroup};
+use crate::server::unfinished_snapshots::{
+    cleanup_unfinished_snapshots, configured_action, DEFAULT_UNFINISHED_MIN_AGE,
+};
 use crate::server::{jobstate::Job, send_gc_status};
 
 /// Runs a garbage collection job.
@@ -32,6 +37,21 @@ pub fn do_garbage_collection_job(
             if let Some(event_str) = schedule {
                 task_log!(worker, "task triggered by schedule '{event_str}'");
             }
+            apply_task_io_priority(&worker, TaskIoClass::GarbageCollection);
+            enter_task_cgroup(&worker, TaskCgroup::GarbageCollection);
+
+            // before marking, so chunks only used by removed snapshots get freed right away
+            let unfinished = configured_action(&store).and_then(|action| {
+                cleanup_unfinished_snapshots(
+                    &*worker,
+                    &datastore,
+                    action,
+                    DEFAULT_UNFINISHED_MIN_AGE,
+                )
+            });
+            if let Err(err) = unfinished {
+                task_warn!(worker, "checking for unfinished snapshots failed - {err}");
+            }
 
             let result = datastore.garbage_collection(&*worker, worker.upid());
 
@@ -0,0 +1,178 @@
+//! Run hook scripts configured for server events
+//!
+//! Hooks are executables configured in `hooks.cfg`, run when one of their events occurs, for
+//! example to update a ticketing system or CMDB when a backup finished or a verification failed.
+//! They run in the background, so a slow hook never delays the task triggering it.
+//!
+//! The details of the event are passed as environment variables, the environment of the daemon
+//! is not inherited. Hooks run in a new session with `no_new_privs` set, without stdin and with
+//! `/` as working directory, and are killed with their whole process group after the configured
+//! timeout. Only scripts owned by root and not writable by group or others are executed.
+
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Error};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+
+use pbs_api_types::{HookConfig, HookEvent, UPID};
+
+// default of HOOK_TIMEOUT_SCHEMA
+const DEFAULT_TIMEOUT: u64 = 60;
+
+// at most this much output of a hook is logged
+const MAX_LOGGED_OUTPUT: u64 = 64 * 1024;
+
+/// Check that `script` may be used as hook.
+pub fn check_hook_script(script: &str) -> Result<(), Error> {
+    crate::tools::fs::check_root_executable(script, "hook script")
+}
+
+/// Run the hooks for the outcome of a task in the background.
+///
+/// Hooks for `finish` run in any case, hooks for `failure` only if `result` is an error. `env`
+/// holds additional details passed to the hooks, like the datastore.
+pub fn run_task_hooks(
+    finish: HookEvent,
+    failure: HookEvent,
+    upid: &UPID,
+    result: &Result<(), Error>,
+    mut env: Vec<(&'static str, String)>,
+) {
+    let hooks = match pbs_config::hook::config() {
+        Ok((config, _digest)) => match config.convert_to_typed_array::<HookConfig>("hook") {
+            Ok(hooks) => hooks,
+            Err(err) => {
+                log::error!("unable to parse hook config - {err}");
+                return;
+            }
+        },
+        Err(err) => {
+            log::error!("unable to read hook config - {err}");
+            return;
+        }
+    };
+
+    let mut events = vec![finish];
+    if result.is_err() {
+        events.push(failure);
+    }
+
+    let mut to_run = Vec::new();
+    for hook in hooks {
+        if hook.disable {
+            continue;
+        }
+        for event in &events {
+            if hook.event.contains(event) {
+                to_run.push((hook.clone(), *event));
+            }
+        }
+    }
+    if to_run.is_empty() {
+        return;
+    }
+
+    env.push(("PBS_TASK_UPID", upid.to_string()));
+    match result {
+        Ok(()) => env.push(("PBS_TASK_STATUS", "ok".to_string())),
+        Err(err) => {
+            env.push(("PBS_TASK_STATUS", "error".to_string()));
+            env.push(("PBS_TASK_ERROR", err.to_string()));
+        }
+    }
+
+    let spawned = std::thread::Builder::new()
+        .name("hooks".to_string())
+        .spawn(move || {
+            for (hook, event) in to_run {
+                if let Err(err) = run_hook(&hook, event, &env) {
+                    log::error!("hook '{}' for event '{event}' failed - {err}", hook.id);
+                }
+            }
+        });
+    if let Err(err) = spawned {
+        log::error!("unable to start thread for hooks - {err}");
+    }
+}
+
+fn run_hook(hook: &HookConfig, event: HookEvent, env: &[(&str, String)]) -> Result<(), Error> {
+    check_hook_script(&hook.script)?;
+
+    let output = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_TMPFILE)
+        .open("/tmp")?;
+
+    let mut command = Command::new(&hook.script);
+    command
+        .env_clear()
+        .env("PATH", "/usr/sbin:/usr/bin:/sbin:/bin")
+        .env("PBS_HOOK_ID", &hook.id)
+        .env("PBS_HOOK_EVENT", event.to_string())
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .current_dir("/")
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(output.try_clone()?))
+        .stderr(Stdio::from(output.try_clone()?));
+
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() < 0 || libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let mut child = command.spawn()?;
+    let pgid = Pid::from_raw(child.id() as i32);
+
+    let timeout = hook.timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = killpg(pgid, Signal::SIGKILL);
+            let _ = child.wait();
+            log_output(hook, output);
+            bail!("timed out after {timeout} seconds");
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+
+    log_output(hook, output);
+
+    if !status.success() {
+        bail!("{status}");
+    }
+
+    Ok(())
+}
+
+fn log_output(hook: &HookConfig, mut output: File) {
+    let mut data = Vec::new();
+    // the file offset is shared with the descriptors of the hook
+    let result = output.rewind().and_then(|()| {
+        output
+            .by_ref()
+            .take(MAX_LOGGED_OUTPUT)
+            .read_to_end(&mut data)
+    });
+    if let Err(err) = result {
+        log::error!("unable to read output of hook '{}' - {err}", hook.id);
+        return;
+    }
+    for line in String::from_utf8_lossy(&data).lines() {
+        log::info!("hook '{}': {line}", hook.id);
+    }
+}
@@ -0,0 +1,197 @@
+//! On-demand fetching of archives of snapshots synced in inventory mode
+//!
+//! Snapshots synced with `inventory-only` lack their index archives and chunks. Reader sessions
+//! on such a snapshot fetch a missing index from the origin recorded at sync time, and the
+//! chunks referenced by it when the client requests them.
+//!
+//! Fetched chunks are inserted into the local datastore, so each is only transferred once per
+//! reader session. The index files are not stored, as garbage collection would otherwise
+//! complain about their chunks not fetched yet. So the fetched chunks are not referenced by
+//! any index and removed again by the next garbage collection.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+
+use pbs_api_types::{Operation, Remote};
+use pbs_client::{BackupReader, HttpClient};
+use pbs_datastore::dynamic_index::DynamicIndexReader;
+use pbs_datastore::fixed_index::FixedIndexReader;
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::manifest::{archive_type, ArchiveType};
+use pbs_datastore::{BackupDir, DataBlob, DataStore, InventoryOrigin};
+
+enum OriginSource {
+    Remote {
+        _client: HttpClient,
+        reader: Arc<BackupReader>,
+    },
+    Local(BackupDir),
+}
+
+/// Fetches the missing data of a snapshot synced in inventory mode from its origin.
+pub struct InventoryFetcher {
+    snapshot: BackupDir,
+    origin: InventoryOrigin,
+    source: tokio::sync::Mutex<Option<Arc<OriginSource>>>,
+    /// Sizes of the chunks referenced by the fetched indexes, by digest.
+    chunk_sizes: Mutex<HashMap<[u8; 32], u64>>,
+}
+
+impl InventoryFetcher {
+    /// Returns a fetcher for `snapshot`, if it was synced in inventory mode with a known origin.
+    pub fn new(snapshot: &BackupDir) -> Result<Option<Self>, Error> {
+        if !snapshot.is_inventory_only() {
+            return Ok(None);
+        }
+        Ok(snapshot.inventory_origin()?.map(|origin| Self {
+            snapshot: snapshot.clone(),
+            origin,
+            source: tokio::sync::Mutex::new(None),
+            chunk_sizes: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Describes the origin for log messages.
+    pub fn origin(&self) -> String {
+        match &self.origin.remote {
+            Some(remote) => format!("remote '{remote}', datastore '{}'", self.origin.store),
+            None => format!("datastore '{}'", self.origin.store),
+        }
+    }
+
+    // connects to the origin on first use
+    async fn source(&self) -> Result<Arc<OriginSource>, Error> {
+        let mut source = self.source.lock().await;
+        if let Some(source) = &*source {
+            return Ok(Arc::clone(source));
+        }
+
+        let dir = self.snapshot.dir();
+        let new_source = match &self.origin.remote {
+            Some(remote) => {
+                let (config, _digest) = pbs_config::remote::config()?;
+                let remote: Remote = config.lookup("remote", remote)?;
+                let client = crate::api2::config::remote::remote_client(&remote, None).await?;
+                let reader = BackupReader::start(
+                    &client,
+                    None,
+                    &self.origin.store,
+                    &self.origin.ns,
+                    dir,
+                    false,
+                )
+                .await?;
+                OriginSource::Remote {
+                    _client: client,
+                    reader,
+                }
+            }
+            None => {
+                let store = DataStore::lookup_datastore(&self.origin.store, Some(Operation::Read))?;
+                OriginSource::Local(store.backup_dir(self.origin.ns.clone(), dir.clone())?)
+            }
+        };
+
+        let new_source = Arc::new(new_source);
+        *source = Some(Arc::clone(&new_source));
+        Ok(new_source)
+    }
+
+    /// Fetch the index archive `file_name` and verify it against the local manifest.
+    ///
+    /// Returns the raw index data and the digests of the chunks it references.
+    pub async fn fetch_index(&self, file_name: &str) -> Result<(Vec<u8>, Vec<[u8; 32]>), Error> {
+        let ty = archive_type(file_name)?;
+        if ty == ArchiveType::Blob {
+            bail!("blob '{file_name}' cannot be fetched from the origin");
+        }
+
+        let mut tmpfile = std::fs::OpenOptions::new()
+            .write(true)
+            .read(true)
+            .custom_flags(libc::O_TMPFILE)
+            .open("/tmp")?;
+
+        match &*self.source().await? {
+            OriginSource::Remote { reader, .. } => reader.download(file_name, &mut tmpfile).await?,
+            OriginSource::Local(dir) => proxmox_async::runtime::block_in_place(|| {
+                let path = dir.full_path().join(file_name);
+                let mut file = File::open(&path)
+                    .map_err(|err| format_err!("unable to open {path:?} - {err}"))?;
+                std::io::copy(&mut file, &mut tmpfile)?;
+                Ok::<_, Error>(())
+            })?,
+        }
+
+        proxmox_async::runtime::block_in_place(|| self.verify_index(file_name, ty, tmpfile))
+    }
+
+    // parse the fetched index, check it against the manifest and remember its chunks
+    fn verify_index(
+        &self,
+        file_name: &str,
+        ty: ArchiveType,
+        mut tmpfile: File,
+    ) -> Result<(Vec<u8>, Vec<[u8; 32]>), Error> {
+        tmpfile.seek(SeekFrom::Start(0))?;
+
+        let index: Box<dyn IndexFile> = match ty {
+            ArchiveType::DynamicIndex => Box::new(DynamicIndexReader::new(tmpfile.try_clone()?)?),
+            _ => Box::new(FixedIndexReader::new(tmpfile.try_clone()?)?),
+        };
+
+        let (manifest, _) = self.snapshot.load_manifest()?;
+        let (csum, size) = index.compute_csum();
+        manifest.verify_file(file_name, &csum, size)?;
+
+        let mut digests = Vec::with_capacity(index.index_count());
+        let mut chunk_sizes = self.chunk_sizes.lock().unwrap();
+        for pos in 0..index.index_count() {
+            let info = index.chunk_info(pos).unwrap();
+            chunk_sizes.insert(info.digest, info.size());
+            digests.push(info.digest);
+        }
+        drop(chunk_sizes);
+
+        let mut data = Vec::new();
+        tmpfile.seek(SeekFrom::Start(0))?;
+        tmpfile.read_to_end(&mut data)?;
+
+        Ok((data, digests))
+    }
+
+    /// Fetch a chunk referenced by a previously fetched index and store it locally.
+    ///
+    /// Returns the raw chunk data.
+    pub async fn fetch_chunk(&self, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        let size = match self.chunk_sizes.lock().unwrap().get(digest) {
+            Some(size) => *size,
+            None => bail!("chunk is not referenced by any fetched index"),
+        };
+
+        let raw_data = match &*self.source().await? {
+            OriginSource::Remote { reader, .. } => {
+                let mut raw_data = Vec::with_capacity(4 * 1024 * 1024);
+                reader.download_chunk(digest, &mut raw_data).await?;
+                raw_data
+            }
+            OriginSource::Local(dir) => {
+                proxmox_async::runtime::block_in_place(|| dir.datastore().read_raw_chunk(digest))?
+            }
+        };
+
+        let chunk = DataBlob::load_from_reader(&mut &raw_data[..])?;
+        chunk.verify_unencrypted(size as usize, digest)?;
+
+        proxmox_async::runtime::block_in_place(|| {
+            self.snapshot.datastore().insert_chunk(&chunk, digest)
+        })?;
+
+        Ok(chunk.into_inner())
+    }
+}
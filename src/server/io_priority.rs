@@ -0,0 +1,98 @@
+//! IO priority of maintenance tasks
+//!
+//! Verification, garbage collection and prune tasks lower the IO priority of their worker
+//! thread, so that backups and restores hitting the same disks are served first. Threads
+//! spawned by the worker, e.g. the verify decoder pool, inherit the priority.
+
+use anyhow::{bail, Error};
+
+use proxmox_rest_server::WorkerTask;
+use proxmox_sys::{task_log, task_warn};
+
+use crate::config::node::{TaskIoPriority, TaskIoPriorityConfig};
+
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+const IOPRIO_CLASS_BE: libc::c_int = 2;
+const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+
+/// Classes of worker tasks with a configurable IO priority.
+#[derive(Clone, Copy, Debug)]
+pub enum TaskIoClass {
+    Verify,
+    GarbageCollection,
+    Prune,
+}
+
+impl TaskIoClass {
+    fn priority(self, config: &TaskIoPriorityConfig) -> TaskIoPriority {
+        match self {
+            TaskIoClass::Verify => config.verify.unwrap_or(TaskIoPriority::Low),
+            TaskIoClass::GarbageCollection => config.gc.unwrap_or(TaskIoPriority::Idle),
+            TaskIoClass::Prune => config.prune.unwrap_or(TaskIoPriority::Low),
+        }
+    }
+}
+
+fn ioprio_value(priority: TaskIoPriority) -> libc::c_int {
+    match priority {
+        // best-effort level 4 is what the kernel derives for threads with nice value 0
+        TaskIoPriority::Normal => (IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT) | 4,
+        TaskIoPriority::Low => (IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT) | 7,
+        TaskIoPriority::Idle => IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+    }
+}
+
+/// Set the IO priority of the calling thread.
+pub fn set_thread_io_priority(priority: TaskIoPriority) -> Result<(), Error> {
+    // `who` 0 refers to the calling thread
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            ioprio_value(priority),
+        )
+    };
+    if res < 0 {
+        bail!("ioprio_set failed - {}", std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Apply the configured IO priority for `class` to the calling worker thread.
+///
+/// Failures are only logged, a task should not fail because its priority could not be set.
+pub fn apply_task_io_priority(worker: &WorkerTask, class: TaskIoClass) {
+    let config = match crate::config::node::config() {
+        Ok((config, _digest)) => match config.task_io_priority_config() {
+            Some(Ok(config)) => config,
+            Some(Err(err)) => {
+                task_warn!(worker, "invalid task-io-priority in node config - {err}");
+                TaskIoPriorityConfig::default()
+            }
+            None => TaskIoPriorityConfig::default(),
+        },
+        Err(err) => {
+            task_warn!(worker, "unable to read node config - {err}");
+            TaskIoPriorityConfig::default()
+        }
+    };
+
+    let priority = class.priority(&config);
+    if priority == TaskIoPriority::Normal {
+        return;
+    }
+
+    match set_thread_io_priority(priority) {
+        Ok(()) => {
+            let name = match priority {
+                TaskIoPriority::Normal => "normal",
+                TaskIoPriority::Low => "low",
+                TaskIoPriority::Idle => "idle",
+            };
+            task_log!(worker, "running with {name} IO priority");
+        }
+        Err(err) => task_warn!(worker, "could not set IO priority - {err}"),
+    }
+}
@@ -46,7 +46,7 @@ use proxmox_sys::fs::{create_path, file_read_optional_string, replace_file, Crea
 
 use proxmox_time::CalendarEvent;
 
-use pbs_api_types::{JobScheduleStatus, UPID};
+use pbs_api_types::{JobRunInfo, JobScheduleStatus, TaskStateType, UPID};
 use pbs_buildcfg::PROXMOX_BACKUP_STATE_DIR_M;
 use pbs_config::{open_backup_lockfile, BackupLockGuard};
 
@@ -79,6 +79,9 @@ pub struct Job {
 
 const JOB_STATE_BASEDIR: &str = concat!(PROXMOX_BACKUP_STATE_DIR_M!(), "/jobstates");
 
+/// Number of finished runs kept in the history of a job.
+const JOB_HISTORY_LENGTH: usize = 20;
+
 /// Create jobstate stat dir with correct permission
 pub fn create_jobstate_dir() -> Result<(), Error> {
     let backup_user = pbs_config::backup_user()?;
@@ -99,6 +102,12 @@ fn get_path(jobtype: &str, jobname: &str) -> PathBuf {
     path
 }
 
+fn get_history_path(jobtype: &str, jobname: &str) -> PathBuf {
+    let mut path = PathBuf::from(JOB_STATE_BASEDIR);
+    path.push(format!("{jobtype}-{jobname}.history.json"));
+    path
+}
+
 fn get_lock<P>(path: P) -> Result<BackupLockGuard, Error>
 where
     P: AsRef<Path>,
@@ -117,6 +126,11 @@ pub fn remove_state_file(jobtype: &str, jobname: &str) -> Result<(), Error> {
             bail!("cannot remove statefile for {jobtype} - {jobname}: {err}");
         }
     }
+    if let Err(err) = std::fs::remove_file(get_history_path(jobtype, jobname)) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            bail!("cannot remove history for {jobtype} - {jobname}: {err}");
+        }
+    }
     path.set_extension("lck");
     if let Err(err) = std::fs::remove_file(&path) {
         if err.kind() != std::io::ErrorKind::NotFound {
@@ -126,6 +140,37 @@ pub fn remove_state_file(jobtype: &str, jobname: &str) -> Result<(), Error> {
     Ok(())
 }
 
+/// Returns the most recent finished runs of a job, newest first.
+/// Note that this is not locked
+pub fn load_history(jobtype: &str, jobname: &str) -> Result<Vec<JobRunInfo>, Error> {
+    match file_read_optional_string(get_history_path(jobtype, jobname))? {
+        Some(data) => Ok(serde_json::from_str(&data)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Add the duration of the last run, the success streak and the run history of a job to its
+/// schedule status.
+pub fn add_run_history(status: &mut JobScheduleStatus, jobtype: &str, jobname: &str) {
+    let history = match load_history(jobtype, jobname) {
+        Ok(history) => history,
+        Err(err) => {
+            log::warn!("could not load history of {jobtype} - {jobname}: {err}");
+            return;
+        }
+    };
+
+    if let Some(last) = history.first() {
+        status.last_run_duration = Some(last.endtime - last.starttime);
+    }
+    let streak = history
+        .iter()
+        .take_while(|run| matches!(run.status, TaskStateType::OK | TaskStateType::Warning))
+        .count();
+    status.success_streak = Some(streak as u64);
+    status.history = Some(history);
+}
+
 /// Creates the statefile with the state 'Created'
 /// overwrites if it exists already
 pub fn create_state_file(jobtype: &str, jobname: &str) -> Result<(), Error> {
@@ -266,6 +311,14 @@ impl Job {
         }
         .to_string();
 
+        if let Err(err) = self.add_to_history(&upid, &state) {
+            log::warn!(
+                "could not update history of {} - {}: {err}",
+                self.jobtype,
+                self.jobname
+            );
+        }
+
         self.state = JobState::Finished {
             upid,
             state,
@@ -275,6 +328,29 @@ impl Job {
         self.write_state()
     }
 
+    fn add_to_history(&self, upid: &str, state: &TaskState) -> Result<(), Error> {
+        let parsed: UPID = upid.parse()?;
+        let mut history = load_history(&self.jobtype, &self.jobname)?;
+        history.insert(
+            0,
+            JobRunInfo {
+                upid: upid.to_string(),
+                starttime: parsed.starttime,
+                endtime: state.endtime(),
+                status: crate::api2::node::tasks::tasktype(state),
+            },
+        );
+        history.truncate(JOB_HISTORY_LENGTH);
+
+        let path = get_history_path(&self.jobtype, &self.jobname);
+        replace_file(
+            path,
+            &serde_json::to_vec(&history)?,
+            self.file_options()?,
+            false,
+        )
+    }
+
     pub fn jobtype(&self) -> &str {
         &self.jobtype
     }
@@ -283,20 +359,22 @@ impl Job {
         &self.jobname
     }
 
-    fn write_state(&mut self) -> Result<(), Error> {
-        let serialized = serde_json::to_string(&self.state)?;
-        let path = get_path(&self.jobtype, &self.jobname);
-
+    fn file_options(&self) -> Result<CreateOptions, Error> {
         let backup_user = pbs_config::backup_user()?;
         let mode = nix::sys::stat::Mode::from_bits_truncate(0o0644);
         // set the correct owner/group/permissions while saving file
         // owner(rw) = backup, group(r)= backup
-        let options = CreateOptions::new()
+        Ok(CreateOptions::new()
             .perm(mode)
             .owner(backup_user.uid)
-            .group(backup_user.gid);
+            .group(backup_user.gid))
+    }
+
+    fn write_state(&mut self) -> Result<(), Error> {
+        let serialized = serde_json::to_string(&self.state)?;
+        let path = get_path(&self.jobtype, &self.jobname);
 
-        replace_file(path, serialized.as_bytes(), options, false)
+        replace_file(path, serialized.as_bytes(), self.file_options()?, false)
     }
 }
 
@@ -0,0 +1,76 @@
+//! Upgrade of snapshot manifests to the current format version
+//!
+//! Manifests of older versions are migrated whenever they are read, so upgrading them on disk
+//! is never required. Doing so anyway avoids the migration on each read, and makes sure older
+//! formats can be dropped from the migration layer eventually.
+
+use std::sync::Arc;
+
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{print_ns_and_snapshot, BackupNamespace};
+use pbs_datastore::backup_info::BackupDir;
+use pbs_datastore::manifest::{manifest_version, MANIFEST_BLOB_NAME, MANIFEST_VERSION};
+use pbs_datastore::DataStore;
+
+/// Returns the on-disk format version of the manifest of `dir`.
+fn stored_manifest_version(dir: &BackupDir) -> Result<u64, Error> {
+    let data = dir.load_blob(MANIFEST_BLOB_NAME)?.decode(None, None)?;
+    let json: Value = serde_json::from_slice(&data)?;
+    Ok(manifest_version(&json))
+}
+
+/// Rewrite the manifests of all finished snapshots in `ns` and below, up to `max_depth`, which
+/// use an older format version.
+///
+/// Returns the number of upgraded manifests and the number of failures.
+pub fn upgrade_manifests(
+    worker: &dyn WorkerTaskContext,
+    datastore: &Arc<DataStore>,
+    ns: BackupNamespace,
+    max_depth: Option<usize>,
+) -> Result<(usize, usize), Error> {
+    let mut upgraded = 0;
+    let mut failed = 0;
+
+    for ns in datastore.recursive_iter_backup_ns_ok(ns, max_depth)? {
+        for group in datastore.iter_backup_groups_ok(ns)? {
+            for info in group.list_backups()? {
+                worker.check_abort()?;
+
+                if !info.is_finished() {
+                    continue;
+                }
+                let dir = info.backup_dir;
+                let name = print_ns_and_snapshot(dir.backup_ns(), dir.as_ref());
+
+                match stored_manifest_version(&dir) {
+                    Ok(version) if version >= MANIFEST_VERSION => continue,
+                    Ok(_) => {}
+                    Err(err) => {
+                        task_warn!(worker, "unable to read manifest of {name} - {err}");
+                        failed += 1;
+                        continue;
+                    }
+                }
+
+                // the migration happens when loading, so writing back is enough
+                match dir.update_manifest(|_manifest| {}) {
+                    Ok(()) => {
+                        task_log!(worker, "upgraded manifest of {name}");
+                        upgraded += 1;
+                    }
+                    Err(err) => {
+                        task_warn!(worker, "unable to upgrade manifest of {name} - {err}");
+                        failed += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok((upgraded, failed))
+}
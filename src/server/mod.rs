@@ -7,7 +7,7 @@
 use anyhow::{format_err, Error};
 use serde_json::Value;
 
-use proxmox_sys::fs::{create_path, CreateOptions};
+use proxmox_sys::fs::{create_path, file_read_optional_string, replace_file, CreateOptions};
 
 use pbs_buildcfg;
 
@@ -25,14 +25,49 @@ pub use gc_job::*;
 mod realm_sync_job;
 pub use realm_sync_job::*;
 
+mod expected_backup;
+pub use expected_backup::*;
+
+mod compliance_report;
+pub use compliance_report::*;
+
+mod dr_export;
+pub use dr_export::*;
+
+pub mod failover;
+
+pub mod task_archive;
+
 pub mod notifications;
 pub use notifications::*;
 
 mod report;
 pub use report::*;
 
+pub mod access_log;
+
 pub mod auth;
 
+pub mod session_limits;
+
+pub mod io_priority;
+
+pub mod inventory_fetch;
+
+pub mod hooks;
+
+pub mod manifest_upgrade;
+
+pub mod redact;
+
+pub mod snapshot_clone;
+
+pub mod storage_analysis;
+
+pub mod task_cgroup;
+
+pub mod unfinished_snapshots;
+
 pub(crate) mod pull;
 
 pub(crate) async fn reload_proxy_certificate() -> Result<(), Error> {
@@ -92,3 +127,26 @@ pub fn create_active_operations_dir() -> Result<(), Error> {
         .map_err(|err: Error| format_err!("unable to create active operations dir - {err}"))?;
     Ok(())
 }
+
+const SCHEDULER_HEARTBEAT_FN: &str = concat!(
+    pbs_buildcfg::PROXMOX_BACKUP_RUN_DIR_M!(),
+    "/scheduler-heartbeat"
+);
+
+/// Record that the task scheduler of the proxy completed another round.
+pub fn update_scheduler_heartbeat() -> Result<(), Error> {
+    let backup_user = pbs_config::backup_user()?;
+    let opts = CreateOptions::new()
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+    let now = proxmox_time::epoch_i64().to_string();
+    replace_file(SCHEDULER_HEARTBEAT_FN, now.as_bytes(), opts, false)
+}
+
+/// Returns the time of the last task scheduler round, if the scheduler ever ran.
+pub fn last_scheduler_heartbeat() -> Result<Option<i64>, Error> {
+    match file_read_optional_string(SCHEDULER_HEARTBEAT_FN)? {
+        Some(time) => Ok(Some(time.trim().parse()?)),
+        None => Ok(None),
+    }
+}
@@ -1,26 +1,39 @@
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
-use anyhow::Error;
+use anyhow::{bail, Error};
 use const_format::concatcp;
 use nix::unistd::Uid;
-use serde_json::json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
 use proxmox_notify::context::pbs::PBS_CONTEXT;
+use proxmox_router::http_bail;
 use proxmox_schema::ApiType;
 use proxmox_sys::fs::{create_path, CreateOptions};
 
 use crate::tape::TapeNotificationMode;
 use pbs_api_types::{
-    APTUpdateInfo, DataStoreConfig, DatastoreNotify, GarbageCollectionStatus, NotificationMode,
-    Notify, SyncJobConfig, TapeBackupJobSetup, User, Userid, VerificationJobConfig,
+    APTUpdateInfo, ComplianceReport, DataStoreConfig, DatastoreNotify, ExpectedBackupConfig,
+    GarbageCollectionStatus, NotificationMode, Notify, PruneJobConfig, QueuedNotificationInfo,
+    QueuedNotificationState, SyncJobConfig, TapeBackupJobSetup, User, Userid,
+    VerificationJobConfig,
 };
 use proxmox_notify::endpoints::sendmail::{SendmailConfig, SendmailEndpoint};
+use proxmox_notify::renderer::{render_template, TemplateType};
 use proxmox_notify::{Endpoint, Notification, Severity};
 
 const SPOOL_DIR: &str = concatcp!(pbs_buildcfg::PROXMOX_BACKUP_STATE_DIR, "/notifications");
 
+/// Notifications which could not be sent after [`MAX_SEND_ATTEMPTS`] end up here.
+const FAILED_DIR: &str = concatcp!(SPOOL_DIR, "/failed");
+
+/// Failed notifications are retried with an exponential backoff, starting at
+/// [`RETRY_BASE_DELAY`] seconds.
+const MAX_SEND_ATTEMPTS: u64 = 8;
+const RETRY_BASE_DELAY: i64 = 60;
+
 /// Initialize the notification system by setting context in proxmox_notify
 pub fn init() -> Result<(), Error> {
     proxmox_notify::context::set_context(&PBS_CONTEXT);
@@ -28,61 +41,170 @@ pub fn init() -> Result<(), Error> {
 }
 
 /// Create the directory which will be used to temporarily store notifications
-/// which were sent from an unprivileged process.
+/// which were sent from an unprivileged process, or could not be sent yet.
 pub fn create_spool_dir() -> Result<(), Error> {
     let backup_user = pbs_config::backup_user()?;
     let opts = CreateOptions::new()
         .owner(backup_user.uid)
         .group(backup_user.gid);
 
-    create_path(SPOOL_DIR, None, Some(opts))?;
+    create_path(SPOOL_DIR, None, Some(opts.clone()))?;
+    create_path(FAILED_DIR, None, Some(opts))?;
     Ok(())
 }
 
-async fn send_queued_notifications() -> Result<(), Error> {
-    let mut read_dir = tokio::fs::read_dir(SPOOL_DIR).await?;
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct QueuedNotification {
+    notification: Notification,
+    /// Send via sendmail to these addresses instead of the notification system (legacy mode).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    mailto: Vec<String>,
+    #[serde(default)]
+    attempts: u64,
+    #[serde(default)]
+    next_attempt: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_error: Option<String>,
+}
 
-    let mut notifications = Vec::new();
+impl QueuedNotification {
+    fn new(notification: Notification, mailto: Vec<String>) -> Self {
+        Self {
+            notification,
+            mailto,
+            attempts: 0,
+            next_attempt: 0,
+            last_error: None,
+        }
+    }
 
-    while let Some(entry) = read_dir.next_entry().await? {
-        let path = entry.path();
+    fn id(&self) -> String {
+        self.notification.id().to_string()
+    }
 
-        if let Some(ext) = path.extension() {
-            if ext == "json" {
-                let p = path.clone();
+    fn send(&self) -> Result<(), Error> {
+        if self.mailto.is_empty() {
+            let config = pbs_config::notifications::config()?;
+            proxmox_notify::api::common::send(&config, &self.notification)?;
+        } else {
+            let endpoint = SendmailEndpoint {
+                config: SendmailConfig {
+                    mailto: self.mailto.clone(),
+                    ..Default::default()
+                },
+            };
+            endpoint.send(&self.notification)?;
+        }
+        Ok(())
+    }
 
-                let bytes = tokio::fs::read(p).await?;
-                let notification: Notification = serde_json::from_slice(&bytes)?;
-                notifications.push(notification);
+    /// Record a failed attempt, returns false if the notification should not be retried.
+    fn failed(&mut self, err: &Error) -> bool {
+        self.attempts += 1;
+        self.last_error = Some(err.to_string());
+        self.next_attempt = proxmox_time::epoch_i64() + (RETRY_BASE_DELAY << (self.attempts - 1));
+        self.attempts < MAX_SEND_ATTEMPTS
+    }
 
-                // Currently, there is no retry-mechanism in case of failure...
-                // For retries, we'd have to keep track of which targets succeeded/failed
-                // to send, so we do not retry notifying a target which succeeded before.
-                tokio::fs::remove_file(path).await?;
-            }
+    fn info(&self, state: QueuedNotificationState) -> QueuedNotificationInfo {
+        QueuedNotificationInfo {
+            id: self.id(),
+            time: self.notification.timestamp(),
+            state,
+            attempts: self.attempts,
+            next_attempt: match state {
+                QueuedNotificationState::Pending => Some(self.next_attempt),
+                QueuedNotificationState::Failed => None,
+            },
+            last_error: self.last_error.clone(),
+            mailto: (!self.mailto.is_empty()).then(|| self.mailto.join(",")),
         }
     }
+}
 
-    // Make sure that we send the oldest notification first
-    notifications.sort_unstable_by_key(|n| n.timestamp());
+fn queue_file_path(dir: &str, id: &str) -> PathBuf {
+    Path::new(dir).join(format!("{id}.json"))
+}
+
+fn store_queued(dir: &str, entry: &QueuedNotification) -> Result<(), Error> {
+    let backup_user = pbs_config::backup_user()?;
+    let opts = CreateOptions::new()
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+    let path = queue_file_path(dir, &entry.id());
+    proxmox_sys::fs::replace_file(path, &serde_json::to_vec(entry)?, opts, true)
+}
+
+fn load_queued(path: &Path) -> Result<QueuedNotification, Error> {
+    let bytes = std::fs::read(path)?;
+    match serde_json::from_slice(&bytes) {
+        Ok(entry) => Ok(entry),
+        // queued by an older version
+        Err(_) => Ok(QueuedNotification::new(
+            serde_json::from_slice(&bytes)?,
+            Vec::new(),
+        )),
+    }
+}
+
+fn read_queue(dir: &str) -> Result<Vec<(PathBuf, QueuedNotification)>, Error> {
+    let mut list = Vec::new();
 
-    let res = tokio::task::spawn_blocking(move || {
-        let config = pbs_config::notifications::config()?;
-        for notification in notifications {
-            if let Err(err) = proxmox_notify::api::common::send(&config, &notification) {
-                log::error!("failed to send notification: {err}");
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(list),
+        Err(err) => bail!("unable to read notification queue {dir:?} - {err}"),
+    };
+
+    for entry in read_dir {
+        let path = entry?.path();
+        if path.extension().map(|ext| ext == "json") != Some(true) {
+            continue;
+        }
+        match load_queued(&path) {
+            Ok(queued) => list.push((path, queued)),
+            Err(err) => {
+                log::error!("removing invalid queued notification {path:?} - {err}");
+                let _ = std::fs::remove_file(&path);
             }
         }
+    }
+
+    // Make sure that we send the oldest notification first
+    list.sort_unstable_by_key(|(_, entry)| entry.notification.timestamp());
+
+    Ok(list)
+}
 
-        Ok::<(), Error>(())
-    })
-    .await?;
+fn send_queued_notifications() -> Result<(), Error> {
+    let now = proxmox_time::epoch_i64();
 
-    if let Err(e) = res {
-        log::error!("could not read notification config: {e}");
+    for (path, mut entry) in read_queue(SPOOL_DIR)? {
+        if entry.next_attempt > now {
+            continue;
+        }
+
+        let id = entry.id();
+        match entry.send() {
+            Ok(()) => std::fs::remove_file(&path)?,
+            Err(err) => {
+                if entry.failed(&err) {
+                    log::warn!("failed to send notification (id={id}), retrying later - {err}");
+                    store_queued(SPOOL_DIR, &entry)?;
+                } else {
+                    log::error!(
+                        "failed to send notification (id={id}) {} times, giving up - {err}",
+                        entry.attempts
+                    );
+                    store_queued(FAILED_DIR, &entry)?;
+                    std::fs::remove_file(&path)?;
+                }
+            }
+        }
     }
 
-    Ok::<(), Error>(())
+    Ok(())
 }
 
 /// Worker task to periodically send any queued notifications.
@@ -90,46 +212,93 @@ pub async fn notification_worker() {
     loop {
         let delay_target = Instant::now() + Duration::from_secs(5);
 
-        if let Err(err) = send_queued_notifications().await {
-            log::error!("notification worker task error: {err}");
+        let result = tokio::task::spawn_blocking(send_queued_notifications).await;
+        match result {
+            Ok(Err(err)) => log::error!("notification worker task error: {err}"),
+            Err(err) => log::error!("notification worker task panicked: {err}"),
+            Ok(Ok(())) => {}
         }
 
         tokio::time::sleep_until(tokio::time::Instant::from_std(delay_target)).await;
     }
 }
 
+/// Queue a notification, for the notification worker of the API daemon to send it.
+fn queue_notification(entry: QueuedNotification) -> Result<(), Error> {
+    store_queued(SPOOL_DIR, &entry)?;
+    log::info!("queued notification (id={id})", id = entry.id());
+    Ok(())
+}
+
+/// Send a notification directly, or queue it for retry if that fails.
+fn send_or_queue(mut entry: QueuedNotification) -> Result<(), Error> {
+    if let Err(err) = entry.send() {
+        log::warn!("failed to send notification, queued for retry - {err}");
+        entry.failed(&err);
+        queue_notification(entry)?;
+    }
+    Ok(())
+}
+
 fn send_notification(notification: Notification) -> Result<(), Error> {
+    let entry = QueuedNotification::new(notification, Vec::new());
     if nix::unistd::ROOT == Uid::current() {
-        let config = pbs_config::notifications::config()?;
-        proxmox_notify::api::common::send(&config, &notification)?;
+        send_or_queue(entry)
     } else {
-        let ser = serde_json::to_vec(&notification)?;
-        let path = Path::new(SPOOL_DIR).join(format!("{id}.json", id = notification.id()));
-
-        let backup_user = pbs_config::backup_user()?;
-        let opts = CreateOptions::new()
-            .owner(backup_user.uid)
-            .group(backup_user.gid);
-        proxmox_sys::fs::replace_file(path, &ser, opts, true)?;
-        log::info!("queued notification (id={id})", id = notification.id())
+        queue_notification(entry)
     }
-
-    Ok(())
 }
 
 fn send_sendmail_legacy_notification(notification: Notification, email: &str) -> Result<(), Error> {
-    let endpoint = SendmailEndpoint {
-        config: SendmailConfig {
-            mailto: vec![email.into()],
-            ..Default::default()
-        },
-    };
+    send_or_queue(QueuedNotification::new(notification, vec![email.into()]))
+}
 
-    endpoint.send(&notification)?;
+/// List the notifications waiting to be sent and the failed ones.
+pub fn list_queued_notifications() -> Result<Vec<QueuedNotificationInfo>, Error> {
+    let mut list = Vec::new();
+    for (_, entry) in read_queue(SPOOL_DIR)? {
+        list.push(entry.info(QueuedNotificationState::Pending));
+    }
+    for (_, entry) in read_queue(FAILED_DIR)? {
+        list.push(entry.info(QueuedNotificationState::Failed));
+    }
+    Ok(list)
+}
+
+/// Retry sending a queued or failed notification right away.
+pub fn retry_queued_notification(id: &str) -> Result<(), Error> {
+    let path = queue_file_path(SPOOL_DIR, id);
+    if path.exists() {
+        let mut entry = load_queued(&path)?;
+        entry.next_attempt = 0;
+        return store_queued(SPOOL_DIR, &entry);
+    }
+
+    let failed_path = queue_file_path(FAILED_DIR, id);
+    if !failed_path.exists() {
+        http_bail!(NOT_FOUND, "no such queued notification '{id}'");
+    }
+    let mut entry = load_queued(&failed_path)?;
+    entry.attempts = 0;
+    entry.next_attempt = 0;
+    store_queued(SPOOL_DIR, &entry)?;
+    std::fs::remove_file(failed_path)?;
 
     Ok(())
 }
 
+/// Remove a queued or failed notification without sending it.
+pub fn drop_queued_notification(id: &str) -> Result<(), Error> {
+    for dir in [SPOOL_DIR, FAILED_DIR] {
+        match std::fs::remove_file(queue_file_path(dir, id)) {
+            Ok(()) => return Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => bail!("unable to remove queued notification '{id}' - {err}"),
+        }
+    }
+    http_bail!(NOT_FOUND, "no such queued notification '{id}'");
+}
+
 /// Summary of a successful Tape Job
 #[derive(Default)]
 pub struct TapeBackupJobSummary {
@@ -232,11 +401,12 @@ pub fn send_verify_status(
 
     let notification = Notification::from_template(severity, template, data, metadata);
 
-    let (email, notify, mode) = lookup_datastore_notify_settings(&job.store);
+    let (email, notify, mode) =
+        lookup_job_notify_settings(&job.store, job.notify, job.notify_user.as_ref(), |notify| {
+            notify.verify.unwrap_or(Notify::Always)
+        });
     match mode {
         NotificationMode::LegacySendmail => {
-            let notify = notify.verify.unwrap_or(Notify::Always);
-
             if notify == Notify::Never || (result.is_ok() && notify == Notify::Error) {
                 return Ok(());
             }
@@ -253,6 +423,91 @@ pub fn send_verify_status(
     Ok(())
 }
 
+/// Notify about groups of an expected backup entry which missed their backup window.
+///
+/// `missed` contains one human readable line per group.
+pub fn send_backups_missed(job: &ExpectedBackupConfig, missed: &[String]) -> Result<(), Error> {
+    let (fqdn, port) = get_server_url();
+    let data = json!({
+        "job": job,
+        "missed": missed,
+        "fqdn": fqdn,
+        "port": port,
+    });
+
+    let metadata = HashMap::from([
+        ("job-id".into(), job.id.clone()),
+        ("datastore".into(), job.store.clone()),
+        ("hostname".into(), proxmox_sys::nodename().into()),
+        ("type".into(), "backup-missed".into()),
+    ]);
+
+    let notification =
+        Notification::from_template(Severity::Warning, "backup-missed", data, metadata);
+
+    let (email, _notify, mode) = lookup_datastore_notify_settings(&job.store);
+    match mode {
+        NotificationMode::LegacySendmail => {
+            if let Some(email) = email {
+                send_sendmail_legacy_notification(notification, &email)?;
+            }
+        }
+        NotificationMode::NotificationSystem => {
+            send_notification(notification)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn compliance_report_data(report: &ComplianceReport) -> Result<Value, Error> {
+    let (fqdn, port) = get_server_url();
+    Ok(json!({
+        "report": report,
+        "since": proxmox_time::epoch_to_rfc3339_utc(report.since)?,
+        "until": proxmox_time::epoch_to_rfc3339_utc(report.until)?,
+        "fqdn": fqdn,
+        "port": port,
+    }))
+}
+
+/// Render `report` as HTML, like in the body of the notification.
+pub fn render_compliance_report_html(report: &ComplianceReport) -> Result<String, Error> {
+    let data = compliance_report_data(report)?;
+    Ok(render_template(
+        TemplateType::HtmlBody,
+        "compliance-report",
+        &data,
+    )?)
+}
+
+pub fn send_compliance_report(report: &ComplianceReport) -> Result<(), Error> {
+    let data = compliance_report_data(report)?;
+
+    let metadata = HashMap::from([
+        ("datastore".into(), report.store.clone()),
+        ("hostname".into(), proxmox_sys::nodename().into()),
+        ("type".into(), "compliance-report".into()),
+    ]);
+
+    let notification =
+        Notification::from_template(Severity::Info, "compliance-report", data, metadata);
+
+    let (email, _notify, mode) = lookup_datastore_notify_settings(&report.store);
+    match mode {
+        NotificationMode::LegacySendmail => {
+            if let Some(email) = email {
+                send_sendmail_legacy_notification(notification, &email)?;
+            }
+        }
+        NotificationMode::NotificationSystem => {
+            send_notification(notification)?;
+        }
+    }
+
+    Ok(())
+}
+
 pub fn send_prune_status(
     store: &str,
     jobname: &str,
@@ -283,11 +538,19 @@ pub fn send_prune_status(
 
     let notification = Notification::from_template(severity, template, data, metadata);
 
-    let (email, notify, mode) = lookup_datastore_notify_settings(store);
+    // prune jobs configured in the datastore itself have no job config
+    let job = pbs_config::prune::config()
+        .ok()
+        .and_then(|(config, _digest)| config.lookup::<PruneJobConfig>("prune", jobname).ok());
+
+    let (email, notify, mode) = lookup_job_notify_settings(
+        store,
+        job.as_ref().and_then(|job| job.notify),
+        job.as_ref().and_then(|job| job.notify_user.as_ref()),
+        |notify| notify.prune.unwrap_or(Notify::Error),
+    );
     match mode {
         NotificationMode::LegacySendmail => {
-            let notify = notify.prune.unwrap_or(Notify::Error);
-
             if notify == Notify::Never || (result.is_ok() && notify == Notify::Error) {
                 return Ok(());
             }
@@ -329,11 +592,12 @@ pub fn send_sync_status(job: &SyncJobConfig, result: &Result<(), Error>) -> Resu
 
     let notification = Notification::from_template(severity, template, data, metadata);
 
-    let (email, notify, mode) = lookup_datastore_notify_settings(&job.store);
+    let (email, notify, mode) =
+        lookup_job_notify_settings(&job.store, job.notify, job.notify_user.as_ref(), |notify| {
+            notify.sync.unwrap_or(Notify::Always)
+        });
     match mode {
         NotificationMode::LegacySendmail => {
-            let notify = notify.sync.unwrap_or(Notify::Always);
-
             if notify == Notify::Never || (result.is_ok() && notify == Notify::Error) {
                 return Ok(());
             }
@@ -525,6 +789,27 @@ pub fn lookup_user_email(userid: &Userid) -> Option<String> {
     None
 }
 
+/// Lookup the notify settings of a job.
+///
+/// The job's own `notify` and `notify-user` settings override those of its datastore, where
+/// `datastore_default` picks the setting for the job type.
+fn lookup_job_notify_settings(
+    store: &str,
+    job_notify: Option<Notify>,
+    job_notify_user: Option<&Userid>,
+    datastore_default: impl FnOnce(&DatastoreNotify) -> Notify,
+) -> (Option<String>, Notify, NotificationMode) {
+    let (email, notify, mode) = lookup_datastore_notify_settings(store);
+
+    let notify = job_notify.unwrap_or_else(|| datastore_default(&notify));
+    let email = match job_notify_user {
+        Some(userid) => lookup_user_email(userid),
+        None => email,
+    };
+
+    (email, notify, mode)
+}
+
 /// Lookup Datastore notify settings
 pub fn lookup_datastore_notify_settings(
     store: &str,
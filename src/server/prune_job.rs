@@ -2,17 +2,18 @@ use std::sync::Arc;
 
 use anyhow::Error;
 
-use proxmox_sys::{task_log, task_warn};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
 
 use pbs_api_types::{
     print_store_and_ns, Authid, KeepOptions, Operation, PruneJobOptions, MAX_NAMESPACE_DEPTH,
     PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE,
 };
-use pbs_datastore::prune::compute_prune_info;
+use pbs_datastore::prune::{compute_prune_info_with_tags, update_retention_tags};
 use pbs_datastore::DataStore;
 use proxmox_rest_server::WorkerTask;
 
 use crate::backup::ListAccessibleBackupGroups;
+use crate::server::io_priority::{apply_task_io_priority, TaskIoClass};
 use crate::server::jobstate::Job;
 
 pub fn prune_datastore(
@@ -54,11 +55,13 @@ pub fn prune_datastore(
         Some(PRIV_DATASTORE_PRUNE),  // additionally required if owner
         Some(&auth_id),
     )? {
+        worker.check_abort()?;
+
         let group = group?;
         let ns = group.backup_ns();
         let list = group.list_backups()?;
 
-        let mut prune_info = compute_prune_info(list, &prune_options.keep)?;
+        let mut prune_info = compute_prune_info_with_tags(list, &prune_options.keep)?;
         prune_info.reverse(); // delete older snapshots first
 
         task_log!(
@@ -68,17 +71,32 @@ pub fn prune_datastore(
             group.backup_id()
         );
 
-        for (info, mark) in prune_info {
+        for (info, mark, retention_tags) in prune_info {
             let keep = keep_all || mark.keep();
-            task_log!(
-                worker,
-                "{}{} {}/{}/{}",
-                if dry_run { "would " } else { "" },
-                mark,
+            let snapshot = format!(
+                "{}/{}/{}",
                 group.backup_type(),
                 group.backup_id(),
                 info.backup_dir.backup_time_string()
             );
+            let tags = if retention_tags.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", retention_tags.join(", "))
+            };
+            task_log!(
+                worker,
+                "{}{mark} {snapshot}{tags}",
+                if dry_run { "would " } else { "" }
+            );
+            if keep && !keep_all && !dry_run && info.is_finished() {
+                if let Err(err) = update_retention_tags(&info.backup_dir, &retention_tags) {
+                    task_warn!(
+                        worker,
+                        "failed to update retention tags of {snapshot} - {err}"
+                    );
+                }
+            }
             if !keep && !dry_run {
                 if let Err(err) = datastore.remove_backup_dir(ns, info.backup_dir.as_ref(), false) {
                     let path = info.backup_dir.relative_path();
@@ -155,6 +173,7 @@ pub fn do_prune_job(
             if let Some(event_str) = schedule {
                 task_log!(worker, "task triggered by schedule '{event_str}'");
             }
+            apply_task_io_priority(&worker, TaskIoClass::Prune);
 
             let result = prune_datastore(worker.clone(), auth_id, prune_options, datastore, false);
 
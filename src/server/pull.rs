@@ -8,17 +8,20 @@ use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime};
 
 use anyhow::{bail, format_err, Error};
+use hex::FromHex;
 use http::StatusCode;
 use proxmox_human_byte::HumanByte;
 use proxmox_rest_server::WorkerTask;
 use proxmox_router::HttpError;
-use proxmox_sys::{task_log, task_warn};
-use serde_json::json;
+use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+use serde_json::{json, Value};
 
 use pbs_api_types::{
-    print_store_and_ns, Authid, BackupDir, BackupGroup, BackupNamespace, CryptMode, GroupFilter,
-    GroupListItem, Operation, RateLimitConfig, Remote, SnapshotListItem, MAX_NAMESPACE_DEPTH,
-    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ,
+    parse_ns_and_snapshot, print_ns_and_snapshot, print_store_and_ns, Authid, BackupDir,
+    BackupGroup, BackupNamespace, CryptMode, GroupFilter, GroupListItem, Operation,
+    RateLimitConfig, Remote, SnapshotListItem, MAX_NAMESPACE_DEPTH, PRIV_DATASTORE_AUDIT,
+    PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ,
 };
 use pbs_client::{BackupReader, BackupRepository, HttpClient, RemoteChunkReader};
 use pbs_config::CachedUserInfo;
@@ -31,13 +34,16 @@ use pbs_datastore::manifest::{
 };
 use pbs_datastore::read_chunk::AsyncReadChunk;
 use pbs_datastore::{
-    check_backup_owner, DataStore, ListNamespacesRecursive, LocalChunkReader, StoreProgress,
+    check_backup_owner, DataStore, InventoryOrigin, ListNamespacesRecursive, LocalChunkReader,
+    StoreProgress,
 };
 use pbs_tools::sha::sha256;
 
 use crate::backup::{check_ns_modification_privs, check_ns_privs, ListAccessibleBackupGroups};
 use crate::tools::parallel_handler::ParallelHandler;
 
+const GROUP_NOTES_FILE_NAME: &str = "notes";
+
 struct RemoteReader {
     backup_reader: Arc<BackupReader>,
     dir: BackupDir,
@@ -55,6 +61,7 @@ pub(crate) struct PullTarget {
 }
 
 pub(crate) struct RemoteSource {
+    remote: String,
     repo: BackupRepository,
     ns: BackupNamespace,
     client: HttpClient,
@@ -113,6 +120,25 @@ impl PullStats {
     }
 }
 
+/// Metadata of a source group and its snapshots, synced if `sync-metadata` is set.
+#[derive(Default)]
+struct GroupMetadata {
+    notes: Option<String>,
+    /// Snapshot metadata by backup time
+    snapshots: HashMap<i64, SnapshotMetadata>,
+}
+
+struct SnapshotMetadata {
+    protected: bool,
+    notes: Option<String>,
+    verify_state: Value,
+}
+
+/// Empty notes are treated like no notes at all.
+fn non_empty_notes(notes: Option<&str>) -> Option<String> {
+    notes.filter(|notes| !notes.is_empty()).map(String::from)
+}
+
 #[async_trait::async_trait]
 /// `PullSource` is a trait that provides an interface for pulling data/information from a source.
 /// The trait includes methods for listing namespaces, groups, and backup directories,
@@ -139,8 +165,24 @@ trait PullSource: Send + Sync {
         group: &BackupGroup,
         worker: &WorkerTask,
     ) -> Result<Vec<BackupDir>, Error>;
+
+    /// Returns the owners of all groups within a specific namespace from the source.
+    async fn group_owners(
+        &self,
+        namespace: &BackupNamespace,
+    ) -> Result<HashMap<BackupGroup, Authid>, Error>;
+
+    /// Returns the notes of a group and the metadata of its snapshots from the source.
+    async fn group_metadata(
+        &self,
+        namespace: &BackupNamespace,
+        group: &BackupGroup,
+    ) -> Result<GroupMetadata, Error>;
+
     fn get_ns(&self) -> BackupNamespace;
     fn get_store(&self) -> &str;
+    /// Returns the name of the remote, `None` for a local source.
+    fn get_remote(&self) -> Option<&str>;
 
     /// Returns a reader for reading data from a specific backup directory.
     async fn reader(
@@ -268,12 +310,95 @@ impl PullSource for RemoteSource {
                     );
                     return None;
                 }
+                // nothing to sync from, the archives lack their data on the source as well
+                if item.inventory_only {
+                    task_log!(
+                        worker,
+                        "skipping snapshot {} - only its inventory was synced to the source",
+                        snapshot
+                    );
+                    return None;
+                }
 
                 Some(snapshot)
             })
             .collect::<Vec<BackupDir>>())
     }
 
+    async fn group_owners(
+        &self,
+        namespace: &BackupNamespace,
+    ) -> Result<HashMap<BackupGroup, Authid>, Error> {
+        let path = format!("api2/json/admin/datastore/{}/groups", self.repo.store());
+
+        let args = if !namespace.is_root() {
+            Some(json!({ "ns": namespace.clone() }))
+        } else {
+            None
+        };
+
+        self.client.login().await?;
+        let mut result = self.client.get(&path, args).await?;
+
+        Ok(
+            serde_json::from_value::<Vec<GroupListItem>>(result["data"].take())?
+                .into_iter()
+                .filter_map(|item| Some((item.backup, item.owner?)))
+                .collect(),
+        )
+    }
+
+    async fn group_metadata(
+        &self,
+        namespace: &BackupNamespace,
+        group: &BackupGroup,
+    ) -> Result<GroupMetadata, Error> {
+        let store = self.repo.store();
+
+        let mut args = json!({
+            "backup-type": group.ty,
+            "backup-id": group.id,
+        });
+        if !namespace.is_root() {
+            args["ns"] = serde_json::to_value(namespace)?;
+        }
+
+        self.client.login().await?;
+
+        let path = format!("api2/json/admin/datastore/{store}/group-notes");
+        let mut result = self.client.get(&path, Some(args.clone())).await?;
+        let notes = non_empty_notes(result["data"].as_str());
+
+        let path = format!("api2/json/admin/datastore/{store}/snapshots");
+        let mut result = self.client.get(&path, Some(args.clone())).await?;
+        let snapshot_list: Vec<SnapshotListItem> = serde_json::from_value(result["data"].take())?;
+
+        let mut snapshots = HashMap::new();
+        for item in snapshot_list {
+            // the list only contains the first line of the notes
+            let notes = if item.comment.is_some() {
+                let mut args = args.clone();
+                args["backup-time"] = item.backup.time.into();
+                let path = format!("api2/json/admin/datastore/{store}/notes");
+                let mut result = self.client.get(&path, Some(args)).await?;
+                non_empty_notes(result["data"].as_str())
+            } else {
+                None
+            };
+
+            snapshots.insert(
+                item.backup.time,
+                SnapshotMetadata {
+                    protected: item.protected,
+                    notes,
+                    verify_state: serde_json::to_value(item.verification)?,
+                },
+            );
+        }
+
+        Ok(GroupMetadata { notes, snapshots })
+    }
+
     fn get_ns(&self) -> BackupNamespace {
         self.ns.clone()
     }
@@ -282,6 +407,10 @@ impl PullSource for RemoteSource {
         self.repo.store()
     }
 
+    fn get_remote(&self) -> Option<&str> {
+        Some(&self.remote)
+    }
+
     async fn reader(
         &self,
         ns: &BackupNamespace,
@@ -333,17 +462,70 @@ impl PullSource for LocalSource {
         &self,
         namespace: &BackupNamespace,
         group: &BackupGroup,
-        _worker: &WorkerTask,
+        worker: &WorkerTask,
     ) -> Result<Vec<BackupDir>, Error> {
         Ok(self
             .store
             .backup_group(namespace.clone(), group.clone())
             .iter_snapshots()?
             .filter_map(Result::ok)
+            .filter(|snapshot| {
+                if snapshot.is_inventory_only() {
+                    task_log!(
+                        worker,
+                        "skipping snapshot {} - only its inventory was synced to the source",
+                        snapshot.dir()
+                    );
+                    return false;
+                }
+                true
+            })
             .map(|snapshot| snapshot.dir().to_owned())
             .collect::<Vec<BackupDir>>())
     }
 
+    async fn group_owners(
+        &self,
+        namespace: &BackupNamespace,
+    ) -> Result<HashMap<BackupGroup, Authid>, Error> {
+        let mut owners = HashMap::new();
+        for group in self.store.iter_backup_groups_ok(namespace.clone())? {
+            if let Ok(owner) = group.get_owner() {
+                owners.insert(group.group().clone(), owner);
+            }
+        }
+        Ok(owners)
+    }
+
+    async fn group_metadata(
+        &self,
+        namespace: &BackupNamespace,
+        group: &BackupGroup,
+    ) -> Result<GroupMetadata, Error> {
+        let group = self.store.backup_group(namespace.clone(), group.clone());
+
+        let notes_path = group.full_group_path().join(GROUP_NOTES_FILE_NAME);
+        let notes = non_empty_notes(file_read_optional_string(notes_path)?.as_deref());
+
+        let mut snapshots = HashMap::new();
+        for info in group.list_backups()? {
+            if !info.is_finished() {
+                continue;
+            }
+            let (manifest, _) = info.backup_dir.load_manifest()?;
+            snapshots.insert(
+                info.backup_dir.backup_time(),
+                SnapshotMetadata {
+                    protected: info.protected,
+                    notes: non_empty_notes(manifest.unprotected["notes"].as_str()),
+                    verify_state: manifest.unprotected["verify_state"].clone(),
+                },
+            );
+        }
+
+        Ok(GroupMetadata { notes, snapshots })
+    }
+
     fn get_ns(&self) -> BackupNamespace {
         self.ns.clone()
     }
@@ -352,6 +534,10 @@ impl PullSource for LocalSource {
         self.store.name()
     }
 
+    fn get_remote(&self) -> Option<&str> {
+        None
+    }
+
     async fn reader(
         &self,
         ns: &BackupNamespace,
@@ -396,6 +582,12 @@ trait PullReader: Send + Sync {
     ) -> Result<(), Error>;
 
     fn skip_chunk_sync(&self, target_store_name: &str) -> bool;
+
+    /// Loads the manifest of the snapshot.
+    async fn load_manifest(&self) -> Result<BackupManifest, Error>;
+
+    /// Checks a file of the snapshot against the checksum recorded in `manifest`.
+    async fn check_file(&self, manifest: &BackupManifest, filename: &str) -> Result<(), Error>;
 }
 
 #[async_trait::async_trait]
@@ -478,6 +670,33 @@ impl PullReader for RemoteReader {
     fn skip_chunk_sync(&self, _target_store_name: &str) -> bool {
         false
     }
+
+    async fn load_manifest(&self) -> Result<BackupManifest, Error> {
+        let (manifest, _data) = self.backup_reader.download_manifest().await?;
+        Ok(manifest)
+    }
+
+    async fn check_file(&self, manifest: &BackupManifest, filename: &str) -> Result<(), Error> {
+        match archive_type(filename)? {
+            ArchiveType::DynamicIndex => self
+                .backup_reader
+                .download_dynamic_index(manifest, filename)
+                .await
+                .map(drop),
+            ArchiveType::FixedIndex => self
+                .backup_reader
+                .download_fixed_index(manifest, filename)
+                .await
+                .map(drop),
+            ArchiveType::Blob => {
+                // the blob may be encrypted, so only check the raw data
+                let mut data = Vec::new();
+                self.backup_reader.download(filename, &mut data).await?;
+                let (csum, size) = sha256(&mut &data[..])?;
+                manifest.verify_file(filename, &csum, size)
+            }
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -520,6 +739,26 @@ impl PullReader for LocalReader {
     fn skip_chunk_sync(&self, target_store_name: &str) -> bool {
         self.datastore.name() == target_store_name
     }
+
+    async fn load_manifest(&self) -> Result<BackupManifest, Error> {
+        let mut file = std::fs::File::open(self.path.join(MANIFEST_BLOB_NAME))?;
+        BackupManifest::try_from(DataBlob::load_from_reader(&mut file)?)
+    }
+
+    async fn check_file(&self, manifest: &BackupManifest, filename: &str) -> Result<(), Error> {
+        check_local_file(&self.path, manifest, filename)
+    }
+}
+
+/// Checks a file in the snapshot directory `dir` against the checksum recorded in `manifest`.
+fn check_local_file(dir: &Path, manifest: &BackupManifest, filename: &str) -> Result<(), Error> {
+    let path = dir.join(filename);
+    let (csum, size) = match archive_type(filename)? {
+        ArchiveType::DynamicIndex => DynamicIndexReader::open(&path)?.compute_csum(),
+        ArchiveType::FixedIndex => FixedIndexReader::open(&path)?.compute_csum(),
+        ArchiveType::Blob => sha256(&mut std::fs::File::open(&path)?)?,
+    };
+    manifest.verify_file(filename, &csum, size)
 }
 
 /// Parameters for a pull operation.
@@ -538,6 +777,10 @@ pub(crate) struct PullParameters {
     group_filter: Vec<GroupFilter>,
     /// How many snapshots should be transferred at most (taking the newest N snapshots)
     transfer_last: Option<usize>,
+    /// Whether to sync group owners and notes, and notes, protection and verify state of snapshots
+    sync_metadata: bool,
+    /// Whether to only sync manifests and blobs, without the index archives and their chunks
+    inventory_only: bool,
 }
 
 impl PullParameters {
@@ -554,6 +797,8 @@ impl PullParameters {
         group_filter: Option<Vec<GroupFilter>>,
         limit: RateLimitConfig,
         transfer_last: Option<usize>,
+        sync_metadata: bool,
+        inventory_only: bool,
     ) -> Result<Self, Error> {
         if let Some(max_depth) = max_depth {
             ns.check_max_depth(max_depth)?;
@@ -573,6 +818,7 @@ impl PullParameters {
             );
             let client = crate::api2::config::remote::remote_client_config(&remote, Some(limit))?;
             Arc::new(RemoteSource {
+                remote: remote.name,
                 repo,
                 ns: remote_ns,
                 client,
@@ -598,6 +844,8 @@ impl PullParameters {
             max_depth,
             group_filter,
             transfer_last,
+            sync_metadata,
+            inventory_only,
         })
     }
 }
@@ -654,6 +902,8 @@ async fn pull_index_chunks<I: IndexFile>(
             let verify_and_write_channel = verify_and_write_channel.clone();
 
             Ok::<_, Error>(async move {
+                worker.check_abort()?;
+
                 let chunk_exists = proxmox_async::runtime::block_in_place(|| {
                     target.cond_touch_chunk(&info.digest, false)
                 })?;
@@ -815,14 +1065,16 @@ async fn pull_single_archive<'a>(
 /// -- if it matches, only download log and treat snapshot as already synced
 /// - Iterate over referenced files
 /// -- if file already exists, verify contents
-/// -- if not, pull it from the remote
+/// -- if not, pull it from the remote (only blobs with an `inventory_origin`)
 /// - Download log if not already existing
 async fn pull_snapshot<'a>(
     worker: &'a WorkerTask,
     reader: Arc<dyn PullReader + 'a>,
     snapshot: &'a pbs_datastore::BackupDir,
     downloaded_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+    inventory_origin: Option<&InventoryOrigin>,
 ) -> Result<PullStats, Error> {
+    let inventory_only = inventory_origin.is_some();
     let mut pull_stats = PullStats::default();
     let mut manifest_name = snapshot.full_path();
     manifest_name.push(MANIFEST_BLOB_NAME);
@@ -842,7 +1094,10 @@ async fn pull_snapshot<'a>(
         return Ok(pull_stats);
     }
 
-    if manifest_name.exists() {
+    // a full sync needs to fetch the archives missing in snapshots synced in inventory mode
+    let fill_inventory = !inventory_only && snapshot.is_inventory_only();
+
+    if manifest_name.exists() && !fill_inventory {
         let manifest_blob = proxmox_lang::try_block!({
             let mut manifest_file = std::fs::File::open(&manifest_name).map_err(|err| {
                 format_err!("unable to open local manifest {manifest_name:?} - {err}")
@@ -868,8 +1123,11 @@ async fn pull_snapshot<'a>(
     }
 
     let manifest = BackupManifest::try_from(tmp_manifest_blob)?;
+    let mut skipped_archives = 0;
 
     for item in manifest.files() {
+        worker.check_abort()?;
+
         let mut path = snapshot.full_path();
         path.push(&item.filename);
 
@@ -908,6 +1166,17 @@ async fn pull_snapshot<'a>(
             }
         }
 
+        if inventory_only && archive_type(&item.filename)? != ArchiveType::Blob {
+            // don't keep outdated index files around, the snapshot is incomplete anyway
+            if let Err(err) = std::fs::remove_file(&path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    bail!("unable to remove outdated archive {path:?} - {err}");
+                }
+            }
+            skipped_archives += 1;
+            continue;
+        }
+
         let stats = pull_single_archive(
             worker,
             reader.clone(),
@@ -922,6 +1191,13 @@ async fn pull_snapshot<'a>(
     if let Err(err) = std::fs::rename(&tmp_manifest_name, &manifest_name) {
         bail!("Atomic rename file {:?} failed - {}", manifest_name, err);
     }
+    if let Err(err) = snapshot.record_verify_state(&manifest.unprotected["verify_state"]) {
+        task_warn!(
+            worker,
+            "unable to record verify state of snapshot {} - {err}",
+            snapshot.dir()
+        );
+    }
 
     if !client_log_name.exists() {
         reader
@@ -932,6 +1208,21 @@ async fn pull_snapshot<'a>(
         .cleanup_unreferenced_files(&manifest)
         .map_err(|err| format_err!("failed to cleanup unreferenced files - {err}"))?;
 
+    // the cleanup removed the marker of a previous inventory sync
+    if let (Some(origin), true) = (inventory_origin, skipped_archives > 0) {
+        task_log!(
+            worker,
+            "inventory only - skipped {skipped_archives} archive(s)"
+        );
+        let marker = snapshot.inventory_file();
+        std::fs::write(&marker, serde_json::to_string(origin)?)
+            .map_err(|err| format_err!("unable to create inventory marker {marker:?} - {err}"))?;
+    }
+
+    if let Some(index) = snapshot.datastore().content_index() {
+        index.snapshot_changed(snapshot);
+    }
+
     Ok(pull_stats)
 }
 
@@ -944,6 +1235,7 @@ async fn pull_snapshot_from<'a>(
     reader: Arc<dyn PullReader + 'a>,
     snapshot: &'a pbs_datastore::BackupDir,
     downloaded_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+    inventory_origin: Option<&InventoryOrigin>,
 ) -> Result<PullStats, Error> {
     let (_path, is_new, _snap_lock) = snapshot
         .datastore()
@@ -952,7 +1244,15 @@ async fn pull_snapshot_from<'a>(
     let pull_stats = if is_new {
         task_log!(worker, "sync snapshot {}", snapshot.dir());
 
-        match pull_snapshot(worker, reader, snapshot, downloaded_chunks).await {
+        match pull_snapshot(
+            worker,
+            reader,
+            snapshot,
+            downloaded_chunks,
+            inventory_origin,
+        )
+        .await
+        {
             Err(err) => {
                 if let Err(cleanup_err) = snapshot.datastore().remove_backup_dir(
                     snapshot.backup_ns(),
@@ -970,7 +1270,14 @@ async fn pull_snapshot_from<'a>(
         }
     } else {
         task_log!(worker, "re-sync snapshot {}", snapshot.dir());
-        pull_snapshot(worker, reader, snapshot, downloaded_chunks).await?
+        pull_snapshot(
+            worker,
+            reader,
+            snapshot,
+            downloaded_chunks,
+            inventory_origin,
+        )
+        .await?
     };
 
     Ok(pull_stats)
@@ -1104,12 +1411,28 @@ async fn pull_group(
         .last_successful_backup(&target_ns, group)?
         .unwrap_or(i64::MIN);
 
+    // snapshots synced in inventory mode before are not complete yet for a full sync
+    let mut inventory_snapshots = HashSet::new();
+    if !params.inventory_only {
+        let local_group = params
+            .target
+            .store
+            .backup_group(target_ns.clone(), group.clone());
+        if local_group.exists() {
+            for info in local_group.list_backups()? {
+                if info.backup_dir.is_inventory_only() {
+                    inventory_snapshots.insert(info.backup_dir.backup_time());
+                }
+            }
+        }
+    }
+
     let list: Vec<BackupDir> = raw_list
         .into_iter()
         .enumerate()
         .filter(|&(pos, ref dir)| {
             source_snapshots.insert(dir.time);
-            if last_sync_time > dir.time {
+            if last_sync_time > dir.time && !inventory_snapshots.contains(&dir.time) {
                 already_synced_skip_info.update(dir.time);
                 return false;
             } else if already_synced_skip_info.count > 0 {
@@ -1130,6 +1453,12 @@ async fn pull_group(
         .map(|(_, dir)| dir)
         .collect();
 
+    let inventory_origin = params.inventory_only.then(|| InventoryOrigin {
+        remote: params.source.get_remote().map(String::from),
+        store: params.source.get_store().to_string(),
+        ns: source_namespace.clone(),
+    });
+
     // start with 65536 chunks (up to 256 GiB)
     let downloaded_chunks = Arc::new(Mutex::new(HashSet::with_capacity(1024 * 64)));
 
@@ -1138,6 +1467,8 @@ async fn pull_group(
     let mut pull_stats = PullStats::default();
 
     for (pos, from_snapshot) in list.into_iter().enumerate() {
+        worker.check_abort()?;
+
         let to_snapshot = params
             .target
             .store
@@ -1147,8 +1478,14 @@ async fn pull_group(
             .source
             .reader(source_namespace, &from_snapshot)
             .await?;
-        let result =
-            pull_snapshot_from(worker, reader, &to_snapshot, downloaded_chunks.clone()).await;
+        let result = pull_snapshot_from(
+            worker,
+            reader,
+            &to_snapshot,
+            downloaded_chunks.clone(),
+            inventory_origin.as_ref(),
+        )
+        .await;
 
         progress.done_snapshots = pos as u64 + 1;
         task_log!(worker, "percentage done: {}", progress);
@@ -1157,6 +1494,18 @@ async fn pull_group(
         pull_stats.add(stats);
     }
 
+    if params.sync_metadata {
+        let metadata = params
+            .source
+            .group_metadata(source_namespace, group)
+            .await?;
+        let group = params
+            .target
+            .store
+            .backup_group(target_ns.clone(), group.clone());
+        sync_group_metadata(worker, &group, &metadata)?;
+    }
+
     if params.remove_vanished {
         let group = params
             .target
@@ -1164,6 +1513,8 @@ async fn pull_group(
             .backup_group(target_ns.clone(), group.clone());
         let local_list = group.list_backups()?;
         for info in local_list {
+            worker.check_abort()?;
+
             let snapshot = info.backup_dir;
             if source_snapshots.contains(&snapshot.backup_time()) {
                 continue;
@@ -1192,6 +1543,72 @@ async fn pull_group(
     Ok(pull_stats)
 }
 
+/// Overwrite the local group notes and the notes, verify state and protection of all local
+/// snapshots with the ones of the source.
+fn sync_group_metadata(
+    worker: &WorkerTask,
+    group: &pbs_datastore::BackupGroup,
+    metadata: &GroupMetadata,
+) -> Result<(), Error> {
+    let notes_path = group.full_group_path().join(GROUP_NOTES_FILE_NAME);
+    let local_notes = non_empty_notes(file_read_optional_string(&notes_path)?.as_deref());
+    if local_notes != metadata.notes {
+        match &metadata.notes {
+            Some(notes) => {
+                replace_file(&notes_path, notes.as_bytes(), CreateOptions::new(), false)?
+            }
+            None => std::fs::remove_file(&notes_path)?,
+        }
+        task_log!(worker, "updated notes of group {}", group.group());
+    }
+
+    for info in group.list_backups()? {
+        if !info.is_finished() {
+            continue;
+        }
+        let snapshot = info.backup_dir;
+        let source = match metadata.snapshots.get(&snapshot.backup_time()) {
+            Some(source) => source,
+            None => continue,
+        };
+
+        let (manifest, _) = snapshot.load_manifest()?;
+        let notes = non_empty_notes(manifest.unprotected["notes"].as_str());
+        if notes != source.notes || manifest.unprotected["verify_state"] != source.verify_state {
+            snapshot.update_manifest(|manifest| {
+                match &source.notes {
+                    Some(notes) => manifest.unprotected["notes"] = notes.as_str().into(),
+                    None => {
+                        if let Some(unprotected) = manifest.unprotected.as_object_mut() {
+                            unprotected.remove("notes");
+                        }
+                    }
+                }
+                manifest.unprotected["verify_state"] = source.verify_state.clone();
+            })?;
+            task_log!(worker, "updated metadata of snapshot {}", snapshot.dir());
+        }
+
+        if info.protected != source.protected {
+            snapshot
+                .datastore()
+                .update_protection(&snapshot, source.protected)?;
+            task_log!(
+                worker,
+                "{} snapshot {}",
+                if source.protected {
+                    "protected"
+                } else {
+                    "unprotected"
+                },
+                snapshot.dir(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
 fn check_and_create_ns(params: &PullParameters, ns: &BackupNamespace) -> Result<bool, Error> {
     let mut created = false;
     let store_ns_str = print_store_and_ns(params.target.store.name(), ns);
@@ -1231,7 +1648,7 @@ fn check_and_remove_ns(params: &PullParameters, local_ns: &BackupNamespace) -> R
     params
         .target
         .store
-        .remove_namespace_recursive(local_ns, true)
+        .remove_namespace_recursive(local_ns, true, &params.owner)
 }
 
 fn check_and_remove_vanished_ns(
@@ -1353,6 +1770,10 @@ pub(crate) async fn pull_store(
     let mut pull_stats = PullStats::default();
 
     for namespace in namespaces {
+        // stop here instead of continuing with the next namespace, and never remove vanished
+        // namespaces based on an incomplete sync
+        worker.check_abort()?;
+
         let source_store_ns_str = print_store_and_ns(params.source.get_store(), &namespace);
 
         let target_ns = namespace.map_prefix(&params.source.get_ns(), &params.target.ns)?;
@@ -1479,7 +1900,16 @@ pub(crate) async fn pull_ns(
 
     let target_ns = namespace.map_prefix(&params.source.get_ns(), &params.target.ns)?;
 
+    let source_owners = if params.sync_metadata {
+        params.source.group_owners(namespace).await?
+    } else {
+        HashMap::new()
+    };
+
     for (done, group) in list.into_iter().enumerate() {
+        // group errors are only logged, so check for aborts here to really stop
+        worker.check_abort()?;
+
         progress.done_groups = done as u64;
         progress.done_snapshots = 0;
         progress.group_snapshots = 0;
@@ -1505,8 +1935,8 @@ pub(crate) async fn pull_ns(
                 }
             };
 
-        // permission check
-        if params.owner != owner {
+        // permission check, groups get the owner of the source when syncing metadata
+        if params.owner != owner && !params.sync_metadata {
             // only the owner is allowed to create additional snapshots
             task_log!(
                 worker,
@@ -1524,6 +1954,22 @@ pub(crate) async fn pull_ns(
                     errors = true; // do not stop here, instead continue
                 }
             }
+
+            if let Some(source_owner) = source_owners.get(&group) {
+                if *source_owner != owner {
+                    match params
+                        .target
+                        .store
+                        .set_owner(&target_ns, &group, source_owner, true)
+                    {
+                        Ok(()) => task_log!(worker, "set owner of group {group} to {source_owner}"),
+                        Err(err) => {
+                            task_log!(worker, "setting owner of group {group} failed - {err}");
+                            errors = true;
+                        }
+                    }
+                }
+            }
         }
     }
 
@@ -1536,17 +1982,18 @@ pub(crate) async fn pull_ns(
                     continue;
                 }
                 let owner = params.target.store.get_owner(&target_ns, local_group)?;
-                if check_backup_owner(&owner, &params.owner).is_err() {
+                if !params.sync_metadata && check_backup_owner(&owner, &params.owner).is_err() {
                     continue;
                 }
                 if !local_group.apply_filters(&params.group_filter) {
                     continue;
                 }
                 task_log!(worker, "delete vanished group '{local_group}'",);
-                let delete_stats_result = params
-                    .target
-                    .store
-                    .remove_backup_group(&target_ns, local_group);
+                let delete_stats_result =
+                    params
+                        .target
+                        .store
+                        .remove_backup_group(&target_ns, local_group, &params.owner);
 
                 match delete_stats_result {
                     Ok(stats) => {
@@ -1584,3 +2031,263 @@ pub(crate) async fn pull_ns(
 
     Ok((progress, pull_stats, errors))
 }
+
+/// Fetch the chunks of `snapshot` which are contained in `wanted` from the sync source.
+///
+/// The crypt mode of each chunk is taken from the archive referencing it in the local manifest.
+async fn pull_quarantined_chunks(
+    worker: &WorkerTask,
+    reader: Arc<dyn PullReader>,
+    snapshot: &pbs_datastore::BackupDir,
+    wanted: &HashSet<[u8; 32]>,
+    repaired: &mut HashSet<[u8; 32]>,
+) -> Result<(), Error> {
+    let (manifest, _) = snapshot.load_manifest()?;
+
+    for item in manifest.files() {
+        let path = snapshot.full_path().join(&item.filename);
+        let index: Box<dyn IndexFile + Send> = match archive_type(&item.filename)? {
+            ArchiveType::DynamicIndex => Box::new(DynamicIndexReader::open(&path)?),
+            ArchiveType::FixedIndex => Box::new(FixedIndexReader::open(&path)?),
+            ArchiveType::Blob => continue,
+        };
+
+        let chunk_reader = reader.chunk_reader(item.crypt_mode);
+        for pos in 0..index.index_count() {
+            worker.check_abort()?;
+            let info = index.chunk_info(pos).unwrap();
+            if !wanted.contains(&info.digest) || repaired.contains(&info.digest) {
+                continue;
+            }
+            let digest_str = hex::encode(info.digest);
+            let result = async {
+                let chunk = chunk_reader.read_raw_chunk(&info.digest).await?;
+                chunk.verify_unencrypted(info.size() as usize, &info.digest)?;
+                proxmox_async::runtime::block_in_place(|| {
+                    snapshot.datastore().insert_chunk(&chunk, &info.digest)
+                })
+            }
+            .await;
+            match result {
+                Ok(_) => {
+                    task_log!(worker, "repaired chunk {digest_str}");
+                    repaired.insert(info.digest);
+                }
+                Err(err) => task_warn!(worker, "could not fetch chunk {digest_str} - {err}"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Fetch the chunks in the quarantine of the target datastore from the sync source.
+///
+/// Only snapshots within the namespace scope of `params` are considered. Repaired chunks are
+/// removed from the quarantine, the snapshots which referenced them are returned so that they
+/// can be verified again.
+pub(crate) async fn repair_quarantined_chunks(
+    worker: &WorkerTask,
+    params: &PullParameters,
+) -> Result<Vec<pbs_datastore::BackupDir>, Error> {
+    let target = &params.target.store;
+    let quarantine = target.chunk_quarantine();
+
+    // group the chunks by the snapshots referencing them, so each one is only opened once
+    let mut snapshots: HashMap<String, HashSet<[u8; 32]>> = HashMap::new();
+    for chunk in quarantine.list()? {
+        let digest = <[u8; 32]>::from_hex(&chunk.digest)?;
+        for snapshot in chunk.snapshots {
+            snapshots.entry(snapshot).or_default().insert(digest);
+        }
+    }
+
+    let mut repaired = HashSet::new();
+    let mut affected = Vec::new();
+
+    for (snapshot_str, wanted) in snapshots {
+        worker.check_abort()?;
+
+        let (ns, dir) = parse_ns_and_snapshot(&snapshot_str)?;
+        let source_ns = match ns.map_prefix(&params.target.ns, &params.source.get_ns()) {
+            Ok(source_ns) => source_ns,
+            Err(_) => continue, // not covered by this sync job
+        };
+        if let Some(max_depth) = params.max_depth {
+            if ns.depth() - params.target.ns.depth() > max_depth {
+                continue;
+            }
+        }
+        if !dir.group.apply_filters(&params.group_filter) {
+            continue;
+        }
+
+        let snapshot = target.backup_dir(ns, dir.clone())?;
+        if !snapshot.full_path().exists() {
+            continue;
+        }
+
+        task_log!(
+            worker,
+            "fetching quarantined chunks of snapshot {snapshot_str}"
+        );
+        let result = async {
+            let reader = params.source.reader(&source_ns, &dir).await?;
+            pull_quarantined_chunks(worker, reader, &snapshot, &wanted, &mut repaired).await
+        }
+        .await;
+        if let Err(err) = result {
+            task_warn!(worker, "snapshot {snapshot_str} - {err}");
+        }
+
+        affected.push((snapshot, wanted));
+    }
+
+    let repaired_list: Vec<[u8; 32]> = repaired.iter().copied().collect();
+    quarantine.remove(&repaired_list)?;
+
+    Ok(affected
+        .into_iter()
+        .filter(|(_, wanted)| !wanted.is_disjoint(&repaired))
+        .map(|(snapshot, _)| snapshot)
+        .collect())
+}
+
+/// Compares a snapshot on the sync source with its local copy.
+///
+/// Only the manifests and the checksums of the files are compared, no chunks are read. Returns a
+/// description of each divergence found.
+async fn compare_snapshot(
+    reader: Arc<dyn PullReader>,
+    snapshot: &pbs_datastore::BackupDir,
+) -> Result<Vec<String>, Error> {
+    let source_manifest = reader
+        .load_manifest()
+        .await
+        .map_err(|err| format_err!("unable to load source manifest - {err}"))?;
+    let (local_manifest, _) = snapshot
+        .load_manifest()
+        .map_err(|err| format_err!("unable to load local manifest - {err}"))?;
+
+    let source_files: HashMap<&str, &FileInfo> = source_manifest
+        .files()
+        .iter()
+        .map(|info| (info.filename.as_str(), info))
+        .collect();
+
+    let mut divergences = Vec::new();
+
+    for local in local_manifest.files() {
+        let name = &local.filename;
+        match source_files.get(name.as_str()) {
+            None => divergences.push(format!("{name}: only present locally")),
+            Some(source) if source.size != local.size || source.csum != local.csum => {
+                divergences.push(format!(
+                    "{name}: manifests differ (source {} bytes, csum {}, local {} bytes, csum {})",
+                    source.size,
+                    hex::encode(source.csum),
+                    local.size,
+                    hex::encode(local.csum),
+                ));
+            }
+            Some(_) => {}
+        }
+        if let Err(err) = check_local_file(&snapshot.full_path(), &local_manifest, name) {
+            divergences.push(format!("{name}: local copy - {err}"));
+        }
+    }
+
+    for source in source_manifest.files() {
+        let name = &source.filename;
+        if local_manifest.lookup_file_info(name).is_err() {
+            divergences.push(format!("{name}: only present on source"));
+        }
+        if let Err(err) = reader.check_file(&source_manifest, name).await {
+            divergences.push(format!("{name}: source copy - {err}"));
+        }
+    }
+
+    Ok(divergences)
+}
+
+/// Compares the snapshots present both on the sync source and locally.
+///
+/// Snapshots not synced yet, or removed on the source, are skipped. Returns the number of
+/// compared snapshots and the names of the diverging ones.
+pub(crate) async fn compare_store(
+    worker: &WorkerTask,
+    params: &PullParameters,
+) -> Result<(usize, Vec<String>), Error> {
+    let mut compared = 0;
+    let mut diverged = Vec::new();
+
+    let mut max_depth = params.max_depth;
+    let namespaces = params
+        .source
+        .list_namespaces(&mut max_depth, worker)
+        .await?;
+
+    for source_ns in namespaces {
+        let target_ns = source_ns.map_prefix(&params.source.get_ns(), &params.target.ns)?;
+
+        let mut groups = params.source.list_groups(&source_ns, &params.owner).await?;
+        groups.retain(|group| group.apply_filters(&params.group_filter));
+        groups.sort_unstable();
+
+        for group in groups {
+            let dirs = params
+                .source
+                .list_backup_dirs(&source_ns, &group, worker)
+                .await?;
+
+            for dir in dirs {
+                worker.check_abort()?;
+
+                let snapshot = params
+                    .target
+                    .store
+                    .backup_dir(target_ns.clone(), dir.clone())?;
+                if !snapshot.full_path().exists() {
+                    continue;
+                }
+                let snapshot_str = print_ns_and_snapshot(&target_ns, &dir);
+
+                let _guard = match proxmox_sys::fs::lock_dir_noblock_shared(
+                    &snapshot.full_path(),
+                    "snapshot",
+                    "locked by another operation",
+                ) {
+                    Ok(guard) => guard,
+                    Err(err) => {
+                        task_log!(worker, "skipping snapshot {snapshot_str} - {err}");
+                        continue;
+                    }
+                };
+
+                let result = async {
+                    let reader = params.source.reader(&source_ns, &dir).await?;
+                    compare_snapshot(reader, &snapshot).await
+                }
+                .await;
+
+                compared += 1;
+                match result {
+                    Ok(divergences) if divergences.is_empty() => {}
+                    Ok(divergences) => {
+                        task_warn!(worker, "snapshot {snapshot_str} diverges:");
+                        for divergence in divergences {
+                            task_warn!(worker, "  {divergence}");
+                        }
+                        diverged.push(snapshot_str);
+                    }
+                    Err(err) => {
+                        task_warn!(worker, "snapshot {snapshot_str} - {err}");
+                        diverged.push(snapshot_str);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok((compared, diverged))
+}
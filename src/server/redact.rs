@@ -0,0 +1,490 @@
+//! Targeted removal of files from the snapshots of a backup group
+//!
+//! To comply with deletion requests, the pxar archives of all snapshots of a group are rewritten
+//! without the entries matching a list of patterns, together with the catalog. The rewritten
+//! archives are chunked anew, chunks only referenced by the old archives are removed by the next
+//! garbage collection.
+//!
+//! The server has no access to the keys of the clients, so it cannot rewrite encrypted archives
+//! or sign the updated manifest. Snapshots with a signed manifest are skipped and reported.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::io::{Read, Seek, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use pathpatterns::{MatchEntry, MatchList, MatchType};
+use pxar::decoder::sync::Decoder;
+use pxar::decoder::SeqRead;
+use pxar::encoder::sync::{Encoder, StandardWriter};
+use pxar::encoder::{LinkOffset, SeqWrite};
+use pxar::EntryKind;
+
+use proxmox_sys::fs::lock_dir_noblock;
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{print_ns_and_snapshot, CryptMode};
+use pbs_datastore::backup_info::{BackupDir, BackupGroup};
+use pbs_datastore::catalog::{
+    BackupCatalogWriter, CatalogReader, CatalogWriter, DirEntry, DirEntryAttribute,
+};
+use pbs_datastore::dynamic_index::{BufferedDynamicReader, DynamicChunkWriter};
+use pbs_datastore::filename_index::{open_catalog, FilenameIndex};
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::merkle::index_merkle_root;
+use pbs_datastore::{LocalChunkReader, CATALOG_NAME};
+
+/// Average chunk size of the rewritten archives, the default of the client.
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Suffix of the rewritten index files until all archives of a snapshot are done.
+const REDACTED_SUFFIX: &str = ".redacted";
+
+/// Copies a pxar archive, leaving out the entries matching the patterns.
+struct ArchiveRedactor<'a> {
+    patterns: &'a [MatchEntry],
+    /// Offsets of the copied regular files, by path relative to the archive root, for hard links.
+    files: HashMap<PathBuf, LinkOffset>,
+    /// Matching entries, directories are removed with all their contents.
+    removed: Vec<PathBuf>,
+    /// Hard links to removed files.
+    removed_links: Vec<PathBuf>,
+}
+
+impl<'a> ArchiveRedactor<'a> {
+    fn new(patterns: &'a [MatchEntry]) -> Self {
+        Self {
+            patterns,
+            files: HashMap::new(),
+            removed: Vec::new(),
+            removed_links: Vec::new(),
+        }
+    }
+
+    fn copy_archive<R: SeqRead, W: Write>(
+        &mut self,
+        worker: &dyn WorkerTaskContext,
+        decoder: &mut Decoder<R>,
+        output: W,
+    ) -> Result<(), Error> {
+        decoder.enable_goodbye_entries(true);
+
+        let root = match decoder.next() {
+            None => bail!("missing root entry"),
+            Some(root) => root?,
+        };
+        if !root.is_dir() {
+            bail!("archive does not start with a directory entry");
+        }
+
+        let mut encoder = Encoder::new(StandardWriter::new(output), root.metadata())?;
+        self.copy_directory(worker, decoder, &mut encoder)?;
+        encoder.finish()?;
+
+        Ok(())
+    }
+
+    // copies the entries up to the goodbye table of the current directory
+    fn copy_directory<R: SeqRead, T: SeqWrite>(
+        &mut self,
+        worker: &dyn WorkerTaskContext,
+        decoder: &mut Decoder<R>,
+        encoder: &mut Encoder<'_, T>,
+    ) -> Result<(), Error> {
+        loop {
+            worker.check_abort()?;
+
+            let entry = match decoder.next() {
+                None => bail!("unexpected end of archive"),
+                Some(entry) => entry?,
+            };
+            if let EntryKind::GoodbyeTable = entry.kind() {
+                return Ok(());
+            }
+
+            let path = entry.path();
+            let metadata = entry.metadata();
+
+            // we only use the `u32` implementation of `GetFileMode`, which is infallible
+            let matched = self
+                .patterns
+                .matches(path.as_os_str().as_bytes(), metadata.file_type() as u32)
+                .unwrap();
+            if matched == Some(MatchType::Include) {
+                self.removed.push(path.to_owned());
+                if entry.is_dir() {
+                    skip_directory(decoder)?;
+                }
+                continue;
+            }
+
+            let file_name = entry.file_name();
+            match entry.kind() {
+                EntryKind::Directory => {
+                    let mut dir_encoder = encoder.create_directory(file_name, metadata)?;
+                    self.copy_directory(worker, decoder, &mut dir_encoder)?;
+                    dir_encoder.finish()?;
+                }
+                EntryKind::File { size, .. } => {
+                    let mut contents = decoder
+                        .contents()
+                        .ok_or_else(|| format_err!("missing contents of {path:?}"))?;
+                    let offset = encoder.add_file(metadata, file_name, *size, &mut contents)?;
+                    let relative = path.strip_prefix("/").unwrap_or(path);
+                    self.files.insert(relative.to_owned(), offset);
+                }
+                EntryKind::Hardlink(link) => {
+                    let target = Path::new(link.as_os_str());
+                    match self.files.get(target) {
+                        Some(offset) => encoder.add_hardlink(file_name, target, *offset)?,
+                        None => self.removed_links.push(path.to_owned()),
+                    }
+                }
+                EntryKind::Symlink(link) => {
+                    encoder.add_symlink(metadata, file_name, link.as_os_str())?
+                }
+                EntryKind::Device(device) => {
+                    encoder.add_device(metadata, file_name, device.clone())?
+                }
+                EntryKind::Fifo => encoder.add_fifo(metadata, file_name)?,
+                EntryKind::Socket => encoder.add_socket(metadata, file_name)?,
+                EntryKind::GoodbyeTable => unreachable!(),
+            }
+        }
+    }
+
+    /// All removed paths, as used in the catalog.
+    fn removed_paths(&self) -> HashSet<Vec<u8>> {
+        self.removed
+            .iter()
+            .chain(&self.removed_links)
+            .map(|path| path.as_os_str().as_bytes().to_vec())
+            .collect()
+    }
+}
+
+// skips the rest of the directory whose entry was just read, including its goodbye table
+fn skip_directory<R: SeqRead>(decoder: &mut Decoder<R>) -> Result<(), Error> {
+    let mut depth = 0;
+    loop {
+        let entry = match decoder.next() {
+            None => bail!("unexpected end of archive"),
+            Some(entry) => entry?,
+        };
+        match entry.kind() {
+            EntryKind::Directory => depth += 1,
+            EntryKind::GoodbyeTable if depth == 0 => return Ok(()),
+            EntryKind::GoodbyeTable => depth -= 1,
+            _ => {}
+        }
+    }
+}
+
+/// Copy the catalog directory `parent`, leaving out the entries whose path is in `removed`.
+fn copy_catalog_directory<R: Read + Seek, W: Write>(
+    reader: &mut CatalogReader<R>,
+    writer: &mut CatalogWriter<W>,
+    parent: &DirEntry,
+    path: &mut Vec<u8>,
+    removed: Option<&HashSet<Vec<u8>>>,
+) -> Result<(), Error> {
+    let path_len = path.len();
+    for entry in reader.read_dir(parent)? {
+        path.truncate(path_len);
+        if !entry.name.starts_with(b"/") {
+            path.push(b'/');
+        }
+        path.extend(&entry.name);
+
+        if removed
+            .map(|removed| removed.contains(path))
+            .unwrap_or(false)
+        {
+            continue;
+        }
+
+        let name = CString::new(entry.name.clone())?;
+        match entry.attr {
+            DirEntryAttribute::Directory { .. } => {
+                writer.start_directory(&name)?;
+                copy_catalog_directory(reader, writer, &entry, path, removed)?;
+                writer.end_directory()?;
+            }
+            DirEntryAttribute::File { size, mtime } => writer.add_file(&name, size, mtime)?,
+            DirEntryAttribute::Symlink => writer.add_symlink(&name)?,
+            DirEntryAttribute::Hardlink => writer.add_hardlink(&name)?,
+            DirEntryAttribute::BlockDevice => writer.add_block_device(&name)?,
+            DirEntryAttribute::CharDevice => writer.add_char_device(&name)?,
+            DirEntryAttribute::Fifo => writer.add_fifo(&name)?,
+            DirEntryAttribute::Socket => writer.add_socket(&name)?,
+        }
+    }
+    path.truncate(path_len);
+
+    Ok(())
+}
+
+/// Write the catalog of `dir` without the removed entries of the rewritten archives, to the
+/// file `target` in the snapshot directory.
+fn rewrite_catalog(
+    dir: &BackupDir,
+    removed: &HashMap<String, HashSet<Vec<u8>>>,
+    target: &str,
+) -> Result<(), Error> {
+    let mut reader = open_catalog(dir)?;
+
+    let index = dir
+        .datastore()
+        .create_dynamic_writer(dir.relative_path().join(target))?;
+    let mut chunk_writer = DynamicChunkWriter::new(index, CHUNK_SIZE);
+
+    let mut writer = CatalogWriter::new(&mut chunk_writer)?;
+    let root = reader.root()?;
+    for archive in reader.read_dir(&root)? {
+        let archive_name = String::from_utf8_lossy(&archive.name).to_string();
+        let name = CString::new(archive.name.clone())?;
+        writer.start_directory(&name)?;
+        let removed = removed.get(&archive_name);
+        copy_catalog_directory(&mut reader, &mut writer, &archive, &mut Vec::new(), removed)?;
+        writer.end_directory()?;
+    }
+    writer.finish()?;
+    drop(writer);
+
+    chunk_writer.close()?;
+
+    Ok(())
+}
+
+/// Rewrite the archive `name` of `dir` without the entries matching `patterns`.
+///
+/// The new index is written to `name` with [`REDACTED_SUFFIX`], unless `dry_run` is set. Returns
+/// the redactor with the removed entries.
+fn rewrite_archive<'a>(
+    worker: &dyn WorkerTaskContext,
+    dir: &BackupDir,
+    name: &str,
+    patterns: &'a [MatchEntry],
+    dry_run: bool,
+) -> Result<ArchiveRedactor<'a>, Error> {
+    let datastore = dir.datastore();
+
+    let index = datastore.open_dynamic_reader(dir.relative_path().join(name))?;
+    let chunk_reader = LocalChunkReader::new(datastore.clone(), None, CryptMode::None);
+    let reader = BufferedDynamicReader::new(index, chunk_reader);
+    let mut decoder = Decoder::from_std(reader)?;
+
+    let mut redactor = ArchiveRedactor::new(patterns);
+
+    if dry_run {
+        redactor.copy_archive(worker, &mut decoder, std::io::sink())?;
+        return Ok(redactor);
+    }
+
+    let target = dir.relative_path().join(format!("{name}{REDACTED_SUFFIX}"));
+    let index = datastore.create_dynamic_writer(target)?;
+    let mut chunk_writer = DynamicChunkWriter::new(index, CHUNK_SIZE);
+    redactor.copy_archive(worker, &mut decoder, &mut chunk_writer)?;
+    chunk_writer.close()?;
+
+    Ok(redactor)
+}
+
+fn remove_redacted_files(dir: &BackupDir, names: &[String]) {
+    for name in names {
+        let path = dir.full_path().join(format!("{name}{REDACTED_SUFFIX}"));
+        let _ = std::fs::remove_file(path); // ignore errors
+    }
+}
+
+/// Remove the entries matching `patterns` from the pxar archives of the finished snapshot `dir`.
+///
+/// Returns the number of removed entries, or `None` if the snapshot was skipped.
+fn redact_snapshot(
+    worker: &dyn WorkerTaskContext,
+    upid: &str,
+    dir: &BackupDir,
+    patterns: &[MatchEntry],
+    dry_run: bool,
+) -> Result<Option<u64>, Error> {
+    let name = print_ns_and_snapshot(dir.backup_ns(), dir.as_ref());
+
+    let _guard = lock_dir_noblock(&dir.full_path(), "snapshot", "possibly running or in use")?;
+    let (manifest, _) = dir.load_manifest()?;
+
+    if manifest.signature.is_some() {
+        task_warn!(
+            worker,
+            "{name}: skipped, the manifest is signed and needs to be rewritten by the client"
+        );
+        return Ok(None);
+    }
+
+    let archives: Vec<String> = manifest
+        .files()
+        .iter()
+        .filter(|file| file.filename.ends_with(".pxar.didx"))
+        .map(|file| file.filename.clone())
+        .collect();
+
+    let mut entries = 0;
+    let mut rewritten = Vec::new();
+    let mut removed = HashMap::new();
+
+    for archive in &archives {
+        if manifest.lookup_file_info(archive)?.crypt_mode != CryptMode::None {
+            remove_redacted_files(dir, &rewritten);
+            task_warn!(worker, "{name}: skipped, '{archive}' is encrypted");
+            return Ok(None);
+        }
+
+        let redactor = match rewrite_archive(worker, dir, archive, patterns, dry_run) {
+            Ok(redactor) => redactor,
+            Err(err) => {
+                remove_redacted_files(dir, &rewritten);
+                remove_redacted_files(dir, std::slice::from_ref(archive));
+                bail!("rewriting '{archive}' failed - {err}");
+            }
+        };
+
+        for path in &redactor.removed {
+            task_log!(worker, "{name}: {archive}: removed {path:?}");
+        }
+        for path in &redactor.removed_links {
+            task_log!(
+                worker,
+                "{name}: {archive}: removed hard link {path:?} to removed file"
+            );
+        }
+
+        let count = (redactor.removed.len() + redactor.removed_links.len()) as u64;
+        if count == 0 {
+            remove_redacted_files(dir, std::slice::from_ref(archive));
+            continue;
+        }
+        entries += count;
+        removed.insert(archive.clone(), redactor.removed_paths());
+        rewritten.push(archive.clone());
+    }
+
+    if dry_run || rewritten.is_empty() {
+        return Ok(Some(entries));
+    }
+
+    if manifest
+        .files()
+        .iter()
+        .any(|file| file.filename == CATALOG_NAME)
+    {
+        let target = format!("{CATALOG_NAME}{REDACTED_SUFFIX}");
+        if let Err(err) = rewrite_catalog(dir, &removed, &target) {
+            remove_redacted_files(dir, &rewritten);
+            remove_redacted_files(dir, &[CATALOG_NAME.to_string()]);
+            bail!("rewriting the catalog failed - {err}");
+        }
+        rewritten.push(CATALOG_NAME.to_string());
+    }
+
+    let datastore = dir.datastore();
+    let mut updates = Vec::new();
+    for file in &rewritten {
+        let target = dir.full_path().join(file);
+        let mut source = target.clone().into_os_string();
+        source.push(REDACTED_SUFFIX);
+        std::fs::rename(&source, &target)
+            .map_err(|err| format_err!("unable to rename {source:?} to {target:?} - {err}"))?;
+
+        let index = datastore.open_dynamic_reader(dir.relative_path().join(file))?;
+        let (csum, size) = index.compute_csum();
+        let merkle_root = match manifest.merkle_root(file)? {
+            Some(_) => Some(index_merkle_root(&index)),
+            None => None,
+        };
+        updates.push((file.clone(), size, csum, merkle_root));
+    }
+
+    let mut result = Ok(());
+    dir.update_manifest(|manifest| {
+        for (file, size, csum, merkle_root) in &updates {
+            if let Err(err) = manifest.update_file(file, *size, *csum) {
+                result = Err(err);
+            }
+            if let Some(root) = merkle_root {
+                manifest.set_merkle_root(file, root);
+            }
+        }
+        // the new archives were never verified
+        if let Some(unprotected) = manifest.unprotected.as_object_mut() {
+            unprotected.remove("verify_state");
+        }
+        manifest.add_redaction(upid, proxmox_time::epoch_i64(), entries);
+    })?;
+    result?;
+
+    Ok(Some(entries))
+}
+
+/// Remove the entries matching `patterns` from the pxar archives and catalogs of all finished
+/// snapshots of `group`, logging each removed path as audit record.
+pub fn redact_group(
+    worker: &dyn WorkerTaskContext,
+    upid: &str,
+    group: &BackupGroup,
+    patterns: &[MatchEntry],
+    dry_run: bool,
+) -> Result<(), Error> {
+    let mut snapshots = 0;
+    let mut entries = 0;
+    let mut failed = 0;
+    let mut filename_index = false;
+
+    let mut list = group.list_backups()?;
+    list.sort_unstable_by_key(|info| info.backup_dir.backup_time());
+
+    for info in list {
+        worker.check_abort()?;
+
+        if !info.is_finished() {
+            continue;
+        }
+        let dir = info.backup_dir;
+
+        match redact_snapshot(worker, upid, &dir, patterns, dry_run) {
+            Ok(Some(0)) => {}
+            Ok(Some(count)) => {
+                snapshots += 1;
+                entries += count;
+                filename_index = dir.datastore().filename_index();
+            }
+            Ok(None) => failed += 1,
+            Err(err) => {
+                let name = print_ns_and_snapshot(dir.backup_ns(), dir.as_ref());
+                task_warn!(worker, "{name}: {err}");
+                failed += 1;
+            }
+        }
+    }
+
+    if dry_run {
+        task_log!(
+            worker,
+            "would remove {entries} entries from {snapshots} snapshots"
+        );
+    } else {
+        task_log!(
+            worker,
+            "removed {entries} entries from {snapshots} snapshots"
+        );
+        if filename_index {
+            FilenameIndex::remove(group)?;
+        }
+    }
+
+    if failed > 0 {
+        bail!("{failed} snapshots could not be rewritten, they may still contain matching files");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,345 @@
+//! Limits for concurrent backup and reader sessions
+//!
+//! Sessions are counted per [`Authid`] and per client IP address. The limits are read from the
+//! node configuration whenever a new session is started, so changes apply to new sessions
+//! immediately.
+//!
+//! The traffic of reader sessions can additionally be rate limited per datastore, for each
+//! session, and per [`Authid`], for all its sessions together.
+//!
+//! The memory used for buffering uploaded chunks and blobs is bounded per backup session and
+//! for all sessions together. Uploads wait for memory to become available, which stops reading
+//! from the connection and so makes the HTTP/2 flow control slow down the client.
+//!
+//! Once their task is started, sessions are listed as active sessions, together with the number
+//! of bytes transferred so far.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::Error;
+use lazy_static::lazy_static;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use proxmox_http::RateLimiter;
+use proxmox_router::http_bail;
+use proxmox_schema::ApiType;
+
+use pbs_api_types::{ActiveSession, Authid, DataStoreConfig, DatastoreTuning, SessionType};
+use pbs_datastore::BackupDir;
+use proxmox_rest_server::WorkerTask;
+
+use crate::traffic_control_cache::SharedRateLimit;
+
+struct ActiveSessionEntry {
+    info: ActiveSession,
+    transferred: Arc<AtomicU64>,
+}
+
+#[derive(Default)]
+struct SessionCounts {
+    by_auth_id: HashMap<Authid, usize>,
+    by_ip: HashMap<IpAddr, usize>,
+}
+
+/// Default memory for buffered uploads of all backup sessions together.
+pub const DEFAULT_UPLOAD_BUFFER_LIMIT: u64 = 1024 * 1024 * 1024;
+
+/// Default memory for buffered uploads of a single backup session.
+pub const DEFAULT_UPLOAD_BUFFER_PER_SESSION: u64 = 128 * 1024 * 1024;
+
+lazy_static! {
+    static ref UPLOAD_MEMORY: UploadMemory = {
+        let limit = match crate::config::node::config() {
+            Ok((config, _digest)) => config.upload_buffer_limit.map(|limit| limit.as_u64()),
+            Err(err) => {
+                log::error!("unable to read node config for upload buffer limit - {err}");
+                None
+            }
+        };
+        UploadMemory::new(limit.unwrap_or(DEFAULT_UPLOAD_BUFFER_LIMIT))
+    };
+    static ref SESSIONS: Mutex<SessionCounts> = Mutex::new(SessionCounts::default());
+    static ref ACTIVE_SESSIONS: Mutex<HashMap<String, ActiveSessionEntry>> =
+        Mutex::new(HashMap::new());
+    static ref READER_RATE_BY_AUTH_ID: Mutex<HashMap<Authid, SharedRateLimit>> =
+        Mutex::new(HashMap::new());
+}
+
+/// A started session, counted towards the limits until dropped.
+pub struct SessionGuard {
+    auth_id: Authid,
+    ip: Option<IpAddr>,
+    upid: Option<String>,
+}
+
+impl SessionGuard {
+    /// List the session as active until the guard is dropped.
+    ///
+    /// Returns the counter the session adds its transferred bytes to.
+    pub fn register(
+        &mut self,
+        worker: &WorkerTask,
+        session_type: SessionType,
+        backup_dir: &BackupDir,
+    ) -> Arc<AtomicU64> {
+        let upid = worker.upid().to_string();
+        let ns = backup_dir.backup_ns();
+        let transferred = Arc::new(AtomicU64::new(0));
+
+        let info = ActiveSession {
+            session_type,
+            upid: upid.clone(),
+            auth_id: self.auth_id.clone(),
+            client: self.ip.map(|ip| ip.to_string()),
+            store: backup_dir.datastore().name().to_string(),
+            ns: (!ns.is_root()).then(|| ns.clone()),
+            backup: backup_dir.dir().clone(),
+            starttime: worker.upid().starttime,
+            transferred: 0,
+        };
+
+        ACTIVE_SESSIONS.lock().unwrap().insert(
+            upid.clone(),
+            ActiveSessionEntry {
+                info,
+                transferred: Arc::clone(&transferred),
+            },
+        );
+        self.upid = Some(upid);
+
+        transferred
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        if let Some(upid) = &self.upid {
+            ACTIVE_SESSIONS.lock().unwrap().remove(upid);
+        }
+
+        let mut sessions = SESSIONS.lock().unwrap();
+
+        if let Some(count) = sessions.by_auth_id.get_mut(&self.auth_id) {
+            *count -= 1;
+            if *count == 0 {
+                sessions.by_auth_id.remove(&self.auth_id);
+            }
+        }
+
+        if let Some(ip) = &self.ip {
+            if let Some(count) = sessions.by_ip.get_mut(ip) {
+                *count -= 1;
+                if *count == 0 {
+                    sessions.by_ip.remove(ip);
+                }
+            }
+        }
+    }
+}
+
+/// Register a new backup or reader session, failing with `429 Too Many Requests` if this would
+/// exceed the configured limits.
+pub fn start_session(auth_id: &Authid, ip: Option<IpAddr>) -> Result<SessionGuard, Error> {
+    let (max_per_auth_id, max_per_ip) = match crate::config::node::config() {
+        Ok((config, _digest)) => (config.max_sessions_per_authid, config.max_sessions_per_ip),
+        Err(err) => {
+            log::error!("unable to read node config for session limits - {err}");
+            (None, None)
+        }
+    };
+
+    let mut sessions = SESSIONS.lock().unwrap();
+
+    if let Some(max) = max_per_auth_id {
+        if sessions.by_auth_id.get(auth_id).copied().unwrap_or(0) >= max {
+            http_bail!(
+                TOO_MANY_REQUESTS,
+                "too many concurrent sessions for '{auth_id}' (limit {max})"
+            );
+        }
+    }
+
+    if let (Some(max), Some(ip)) = (max_per_ip, &ip) {
+        if sessions.by_ip.get(ip).copied().unwrap_or(0) >= max {
+            http_bail!(
+                TOO_MANY_REQUESTS,
+                "too many concurrent sessions from {ip} (limit {max})"
+            );
+        }
+    }
+
+    *sessions.by_auth_id.entry(auth_id.clone()).or_default() += 1;
+    if let Some(ip) = ip {
+        *sessions.by_ip.entry(ip).or_default() += 1;
+    }
+
+    Ok(SessionGuard {
+        auth_id: auth_id.clone(),
+        ip,
+        upid: None,
+    })
+}
+
+/// Returns the currently active backup and reader sessions of this process.
+pub fn active_sessions() -> Vec<ActiveSession> {
+    let mut list: Vec<ActiveSession> = ACTIVE_SESSIONS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|entry| ActiveSession {
+            transferred: entry.transferred.load(Ordering::Relaxed),
+            ..entry.info.clone()
+        })
+        .collect();
+    list.sort_by_key(|session| session.starttime);
+    list
+}
+
+/// Returns the active session with the task `upid`, if any.
+pub fn lookup_active_session(upid: &str) -> Option<ActiveSession> {
+    ACTIVE_SESSIONS
+        .lock()
+        .unwrap()
+        .get(upid)
+        .map(|entry| entry.info.clone())
+}
+
+fn new_rate_limiter(rate: u64) -> SharedRateLimit {
+    Arc::new(Mutex::new(RateLimiter::new(rate, rate)))
+}
+
+fn datastore_reader_rate(store: &str) -> Result<Option<u64>, Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let config: DataStoreConfig = config.lookup("datastore", store)?;
+    let tuning: DatastoreTuning = serde_json::from_value(
+        DatastoreTuning::API_SCHEMA
+            .parse_property_string(config.tuning.as_deref().unwrap_or(""))?,
+    )?;
+    Ok(tuning.reader_rate.map(|rate| rate.as_u64()))
+}
+
+/// Returns the rate limiters the traffic of a new reader session on `store` has to obey.
+///
+/// The burst size of the limiters equals one second worth of traffic.
+pub fn reader_rate_limiters(auth_id: &Authid, store: &str) -> Vec<SharedRateLimit> {
+    let mut limiters = Vec::new();
+
+    match datastore_reader_rate(store) {
+        Ok(Some(rate)) => limiters.push(new_rate_limiter(rate)),
+        Ok(None) => {}
+        Err(err) => log::error!("unable to read reader rate limit of datastore {store} - {err}"),
+    }
+
+    let rate = match crate::config::node::config() {
+        Ok((config, _digest)) => config.reader_rate_per_authid.map(|rate| rate.as_u64()),
+        Err(err) => {
+            log::error!("unable to read node config for reader rate limit - {err}");
+            None
+        }
+    };
+
+    let mut by_auth_id = READER_RATE_BY_AUTH_ID.lock().unwrap();
+    match rate {
+        Some(rate) => {
+            let limiter = by_auth_id
+                .entry(auth_id.clone())
+                .or_insert_with(|| new_rate_limiter(rate));
+            limiter.update_rate(rate, rate);
+            limiters.push(Arc::clone(limiter));
+        }
+        None => {
+            by_auth_id.clear();
+        }
+    }
+
+    limiters
+}
+
+/// Wait until sending `len` more bytes is allowed by all `limiters`.
+pub async fn throttle(limiters: &[SharedRateLimit], len: u64) {
+    let now = Instant::now();
+    let delay = limiters
+        .iter()
+        .map(|limiter| limiter.register_traffic(now, len))
+        .max()
+        .unwrap_or_default();
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+}
+
+struct UploadMemory {
+    semaphore: Arc<Semaphore>,
+    limit: usize,
+}
+
+impl UploadMemory {
+    fn new(limit: u64) -> Self {
+        let limit = (limit as usize).clamp(1, Semaphore::MAX_PERMITS);
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            limit,
+        }
+    }
+
+    async fn reserve(&self, size: usize) -> Result<OwnedSemaphorePermit, Error> {
+        // a single upload larger than the limit must still be able to proceed on its own
+        let permits = size.min(self.limit).min(u32::MAX as usize) as u32;
+        Ok(Arc::clone(&self.semaphore)
+            .acquire_many_owned(permits)
+            .await?)
+    }
+}
+
+/// Memory reserved for buffering an upload, released when dropped.
+pub struct UploadMemoryGuard {
+    _session: OwnedSemaphorePermit,
+    _global: OwnedSemaphorePermit,
+}
+
+/// The upload memory budget of a backup session.
+pub struct SessionUploadMemory(UploadMemory);
+
+impl SessionUploadMemory {
+    /// Create the budget of a new session, as configured in the node configuration.
+    pub fn new() -> Self {
+        let limit = match crate::config::node::config() {
+            Ok((config, _digest)) => config.upload_buffer_per_session.map(|l| l.as_u64()),
+            Err(err) => {
+                log::error!("unable to read node config for upload buffer limit - {err}");
+                None
+            }
+        };
+        Self(UploadMemory::new(
+            limit.unwrap_or(DEFAULT_UPLOAD_BUFFER_PER_SESSION),
+        ))
+    }
+
+    /// Memory limit of the session in bytes.
+    pub fn limit(&self) -> usize {
+        self.0.limit
+    }
+
+    /// Wait until `size` bytes are available within the limits of the session and of all
+    /// sessions together.
+    pub async fn reserve(&self, size: usize) -> Result<UploadMemoryGuard, Error> {
+        // session first, so a single session cannot block the global budget while waiting
+        let session = self.0.reserve(size).await?;
+        let global = UPLOAD_MEMORY.reserve(size).await?;
+        Ok(UploadMemoryGuard {
+            _session: session,
+            _global: global,
+        })
+    }
+}
+
+impl Default for SessionUploadMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
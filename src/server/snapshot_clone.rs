@@ -0,0 +1,108 @@
+//! Server-side copies ("fast clones") of snapshots into other backup groups
+//!
+//! A clone gets its own copy of the index files and blobs of the source snapshot, but references
+//! the very same chunks, so no chunk data is read or written. Notes, protection and verification
+//! state of the clone are independent of the source.
+
+use anyhow::{format_err, Error};
+use serde_json::Value;
+
+use proxmox_sys::fs::{replace_file, CreateOptions};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::print_ns_and_snapshot;
+use pbs_datastore::backup_info::BackupDir;
+use pbs_datastore::manifest::{
+    archive_type, ArchiveType, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME,
+};
+use pbs_datastore::DataBlob;
+
+fn copy_snapshot_file(source: &BackupDir, target: &BackupDir, name: &str) -> Result<(), Error> {
+    let source_path = source.full_path().join(name);
+    let target_path = target.full_path().join(name);
+    std::fs::copy(&source_path, &target_path)
+        .map_err(|err| format_err!("unable to copy {source_path:?} to {target_path:?} - {err}"))?;
+    Ok(())
+}
+
+/// Copy the finished snapshot `source` into the new, empty and locked snapshot `target`.
+///
+/// The caller needs to hold a lock on `source` for the whole operation, and has to remove
+/// `target` again on error.
+pub fn clone_snapshot(
+    worker: &dyn WorkerTaskContext,
+    source: &BackupDir,
+    target: &BackupDir,
+) -> Result<(), Error> {
+    let datastore = source.datastore();
+    let (manifest, _) = source.load_manifest()?;
+
+    let mut missing_chunks = 0;
+
+    for item in manifest.files() {
+        worker.check_abort()?;
+
+        copy_snapshot_file(source, target, &item.filename)?;
+        task_log!(worker, "copied {}", item.filename);
+
+        if archive_type(&item.filename)? == ArchiveType::Blob {
+            continue;
+        }
+
+        // touch the chunks like a backup reusing them does, so that a garbage collection which
+        // is already running keeps them, even if the source is removed in the meantime
+        let index = datastore.open_index(target.full_path().join(&item.filename))?;
+        for pos in 0..index.index_count() {
+            let digest = index.index_digest(pos).unwrap();
+            if !datastore.cond_touch_chunk(digest, false)? {
+                missing_chunks += 1;
+            }
+        }
+    }
+
+    if missing_chunks > 0 {
+        task_warn!(
+            worker,
+            "{missing_chunks} chunks referenced by the snapshot are missing, verify the source"
+        );
+    }
+
+    // the client log is not listed in the manifest
+    if source.full_path().join(CLIENT_LOG_BLOB_NAME).exists() {
+        copy_snapshot_file(source, target, CLIENT_LOG_BLOB_NAME)?;
+    }
+
+    let data = source.load_blob(MANIFEST_BLOB_NAME)?.decode(None, None)?;
+    let mut json: Value = serde_json::from_slice(&data)?;
+
+    // the signature covers the backup type and ID, so those of signed manifests keep referring
+    // to the source group
+    if json["signature"].is_null() {
+        json["backup-type"] = serde_json::to_value(target.backup_type())?;
+        json["backup-id"] = target.backup_id().into();
+    }
+    json["unprotected"]["cloned-from"] =
+        print_ns_and_snapshot(source.backup_ns(), source.dir()).into();
+    if let Some(unprotected) = json["unprotected"].as_object_mut() {
+        unprotected.remove("verify_state");
+    }
+
+    // the manifest is written last, its existence marks the snapshot as finished
+    let manifest = serde_json::to_string_pretty(&json)?;
+    let blob = DataBlob::encode(manifest.as_bytes(), None, true)?;
+    let raw_data = datastore.encode_at_rest(blob.raw_data())?;
+    replace_file(
+        target.full_path().join(MANIFEST_BLOB_NAME),
+        &raw_data,
+        CreateOptions::new(),
+        datastore.sync_metadata(),
+    )?;
+
+    datastore.try_ensure_sync_level()?;
+
+    if let Some(index) = datastore.content_index() {
+        index.snapshot_changed(target);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,330 @@
+//! Storage efficiency analysis of a datastore
+//!
+//! Reports the largest files which are stored in several places, and the backup groups which
+//! deduplicate worst, to help with choosing exclusions and with splitting up backups.
+//!
+//! Duplicates are found by looking up the large files of the catalog of the last snapshot of
+//! each group in their pxar archive, and comparing the chunks which store their contents. Files
+//! are considered duplicates if they have the same size and are stored in the same chunks,
+//! apart from the first chunk and the ones shared with the surrounding data, where chunk
+//! boundaries can differ.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{format_err, Error};
+
+use proxmox_human_byte::HumanByte;
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pxar::accessor::aio::Accessor;
+
+use pbs_api_types::{print_ns_and_snapshot, BackupNamespace, CryptMode};
+use pbs_datastore::backup_info::{BackupDir, BackupGroup};
+use pbs_datastore::catalog::{DirEntry, DirEntryAttribute};
+use pbs_datastore::dynamic_index::{BufferedDynamicReader, DynamicIndexReader, LocalDynamicReadAt};
+use pbs_datastore::filename_index::{open_catalog, walk_catalog};
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::manifest::{archive_type, ArchiveType};
+use pbs_datastore::{DataStore, LocalChunkReader, CATALOG_NAME};
+
+/// Deduplication statistics of a backup group.
+struct GroupUsage {
+    group: BackupGroup,
+    snapshots: usize,
+    /// Size of the data referenced by all snapshots.
+    referenced: u64,
+    /// Size of the distinct chunks referenced by the snapshots.
+    unique: u64,
+}
+
+impl GroupUsage {
+    fn factor(&self) -> f64 {
+        self.referenced as f64 / self.unique as f64
+    }
+}
+
+/// A large file in the last snapshot of a group.
+struct FileLocation {
+    snapshot: BackupDir,
+    archive: String,
+    path: Vec<u8>,
+}
+
+impl FileLocation {
+    fn display(&self) -> String {
+        format!(
+            "{} {} {}",
+            print_ns_and_snapshot(self.snapshot.backup_ns(), self.snapshot.dir()),
+            self.archive,
+            String::from_utf8_lossy(&self.path),
+        )
+    }
+}
+
+fn group_usage(worker: &dyn WorkerTaskContext, group: BackupGroup) -> Result<GroupUsage, Error> {
+    let mut usage = GroupUsage {
+        group,
+        snapshots: 0,
+        referenced: 0,
+        unique: 0,
+    };
+    let mut seen = HashSet::new();
+
+    for info in usage.group.list_backups()? {
+        if !info.is_finished() {
+            continue;
+        }
+        let (manifest, _) = info.backup_dir.load_manifest()?;
+        for file in manifest.files() {
+            worker.check_abort()?;
+            if archive_type(&file.filename)? == ArchiveType::Blob {
+                continue;
+            }
+            let index = info
+                .backup_dir
+                .datastore()
+                .open_index(info.backup_dir.full_path().join(&file.filename))?;
+            for pos in 0..index.index_count() {
+                let info = index.chunk_info(pos).unwrap();
+                usage.referenced += info.size();
+                if seen.insert(info.digest) {
+                    usage.unique += info.size();
+                }
+            }
+        }
+        usage.snapshots += 1;
+    }
+
+    Ok(usage)
+}
+
+// collects the files of at least `min_size` bytes from the catalog of the last snapshot
+fn collect_large_files(
+    group: &BackupGroup,
+    min_size: u64,
+    files: &mut HashMap<u64, Vec<FileLocation>>,
+) -> Result<(), Error> {
+    let last = group
+        .list_backups()?
+        .into_iter()
+        .filter(|info| info.is_finished() && info.files.iter().any(|f| f == CATALOG_NAME))
+        .max_by_key(|info| info.backup_dir.backup_time());
+    let snapshot = match last {
+        Some(info) => info.backup_dir,
+        None => return Ok(()),
+    };
+
+    let mut catalog = open_catalog(&snapshot)?;
+    let root = catalog.root()?;
+    for archive in catalog.read_dir(&root)? {
+        let archive_name = String::from_utf8_lossy(&archive.name).to_string();
+        walk_catalog(
+            &mut catalog,
+            &archive,
+            &mut Vec::new(),
+            &mut |path: &[u8], entry: &DirEntry| -> Result<(), Error> {
+                if let DirEntryAttribute::File { size, .. } = entry.attr {
+                    if size >= min_size {
+                        files.entry(size).or_default().push(FileLocation {
+                            snapshot: snapshot.clone(),
+                            archive: archive_name.clone(),
+                            path: path.to_vec(),
+                        });
+                    }
+                }
+                Ok(())
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+/// An opened pxar archive, with its index to map file contents to chunks.
+struct ArchiveChunks {
+    accessor: Accessor<LocalDynamicReadAt<LocalChunkReader>>,
+    index: DynamicIndexReader,
+}
+
+impl ArchiveChunks {
+    fn open(snapshot: &BackupDir, archive: &str) -> Result<Self, Error> {
+        let (manifest, _) = snapshot.load_manifest()?;
+        if manifest.lookup_file_info(archive)?.crypt_mode == CryptMode::Encrypt {
+            return Err(format_err!("archive is encrypted"));
+        }
+
+        let path = snapshot.full_path().join(archive);
+        let index = DynamicIndexReader::open(&path)?;
+        let chunk_reader =
+            LocalChunkReader::new(snapshot.datastore().clone(), None, CryptMode::None);
+        let reader = BufferedDynamicReader::new(DynamicIndexReader::open(&path)?, chunk_reader);
+        let archive_size = reader.archive_size();
+        let accessor = proxmox_async::runtime::block_on(Accessor::new(
+            LocalDynamicReadAt::new(reader),
+            archive_size,
+        ))?;
+
+        Ok(Self { accessor, index })
+    }
+
+    // identifies the contents of a file by the chunks storing them, if it spans enough chunks
+    fn content_key(&self, path: &[u8], size: u64) -> Result<Option<[u8; 32]>, Error> {
+        let entry = proxmox_async::runtime::block_on(async {
+            let root = self.accessor.open_root().await?;
+            root.lookup(OsStr::from_bytes(path)).await
+        })?
+        .ok_or_else(|| format_err!("not found in archive"))?;
+
+        // the contents are stored at the end of the entry
+        let end = entry.entry_range_info().entry_range.end;
+        let start = end - size;
+
+        let mut pos = match self.index.chunk_from_offset(start) {
+            Some((pos, 0)) => pos,
+            Some((pos, _)) => pos + 1,
+            None => return Ok(None),
+        };
+        // the first boundary within the file depends on the data in front of it
+        pos += 1;
+
+        let mut hasher = openssl::sha::Sha256::new();
+        let mut chunks = 0;
+        while let Some(info) = self.index.chunk_info(pos) {
+            if info.range.end > end {
+                break;
+            }
+            hasher.update(&info.digest);
+            chunks += 1;
+            pos += 1;
+        }
+
+        Ok((chunks > 0).then(|| hasher.finish()))
+    }
+}
+
+// groups the candidate files of each size by their contents
+fn find_duplicates(
+    worker: &dyn WorkerTaskContext,
+    files: HashMap<u64, Vec<FileLocation>>,
+) -> Result<Vec<(u64, Vec<FileLocation>)>, Error> {
+    let mut archives: HashMap<(PathBuf, String), Option<ArchiveChunks>> = HashMap::new();
+    let mut duplicates = Vec::new();
+
+    for (size, locations) in files {
+        if locations.len() < 2 {
+            continue;
+        }
+
+        let mut by_content: HashMap<[u8; 32], Vec<FileLocation>> = HashMap::new();
+        for location in locations {
+            worker.check_abort()?;
+
+            let key = (location.snapshot.full_path(), location.archive.clone());
+            let archive = archives.entry(key).or_insert_with(|| {
+                ArchiveChunks::open(&location.snapshot, &location.archive)
+                    .map_err(|err| {
+                        let name = print_ns_and_snapshot(
+                            location.snapshot.backup_ns(),
+                            location.snapshot.dir(),
+                        );
+                        task_warn!(worker, "skipping {name} {} - {err}", location.archive);
+                    })
+                    .ok()
+            });
+            let archive = match archive {
+                Some(archive) => archive,
+                None => continue,
+            };
+
+            match archive.content_key(&location.path, size) {
+                Ok(Some(key)) => by_content.entry(key).or_default().push(location),
+                Ok(None) => {}
+                Err(err) => task_warn!(worker, "skipping {} - {err}", location.display()),
+            }
+        }
+
+        duplicates.extend(
+            by_content
+                .into_values()
+                .filter(|locations| locations.len() > 1)
+                .map(|locations| (size, locations)),
+        );
+    }
+
+    Ok(duplicates)
+}
+
+/// Analyze the storage efficiency of the groups in `ns` and below, up to `max_depth`, and log
+/// the `limit` largest duplicate files of at least `min_size` bytes and the `limit` groups which
+/// deduplicate worst.
+pub fn analyze_storage(
+    worker: &dyn WorkerTaskContext,
+    datastore: &Arc<DataStore>,
+    ns: BackupNamespace,
+    max_depth: Option<usize>,
+    min_size: u64,
+    limit: usize,
+) -> Result<(), Error> {
+    let mut usages = Vec::new();
+    let mut files = HashMap::new();
+
+    for ns in datastore.recursive_iter_backup_ns_ok(ns, max_depth)? {
+        for group in datastore.iter_backup_groups_ok(ns)? {
+            worker.check_abort()?;
+            let name = group.relative_group_path();
+
+            if let Err(err) = collect_large_files(&group, min_size, &mut files) {
+                task_warn!(worker, "skipping catalog of {name:?} - {err}");
+            }
+
+            match group_usage(worker, group) {
+                Ok(usage) if usage.unique > 0 => usages.push(usage),
+                Ok(_) => {}
+                Err(err) => task_warn!(worker, "unable to read snapshots of {name:?} - {err}"),
+            }
+        }
+    }
+
+    let mut duplicates = find_duplicates(worker, files)?;
+    duplicates
+        .sort_by_key(|(size, locations)| std::cmp::Reverse(size * (locations.len() as u64 - 1)));
+    duplicates.truncate(limit);
+
+    task_log!(worker, "largest duplicate files:");
+    if duplicates.is_empty() {
+        task_log!(worker, "  none found");
+    }
+    for (size, locations) in duplicates {
+        task_log!(
+            worker,
+            "  {} in {} places:",
+            HumanByte::from(size),
+            locations.len()
+        );
+        for location in locations {
+            task_log!(worker, "    {}", location.display());
+        }
+    }
+
+    usages.sort_by(|a, b| a.factor().total_cmp(&b.factor()));
+    usages.truncate(limit);
+
+    task_log!(worker, "least deduplicating groups:");
+    for usage in usages {
+        task_log!(
+            worker,
+            "  {}: {} snapshots, {} referenced, {} unique chunks, deduplication factor {:.2}",
+            usage.group.relative_group_path().display(),
+            usage.snapshots,
+            HumanByte::from(usage.referenced),
+            HumanByte::from(usage.unique),
+            usage.factor(),
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+//! Rotation and retention of the finished-task archive
+
+use anyhow::Error;
+
+use proxmox_rest_server::{cleanup_old_tasks, rotate_task_log_archive, WorkerTask};
+use proxmox_sys::fs::CreateOptions;
+use proxmox_sys::{task_log, task_warn};
+
+/// The archive gets rotated once it reaches this size. An entry has ~ 100b, so a file holds
+/// more than 5000 entries.
+const TASK_ARCHIVE_MAX_SIZE: u64 = 512 * 1024 - 1;
+
+/// Number of rotated archive files kept if not configured, twenty files give more than 100000
+/// task entries.
+pub const DEFAULT_TASK_LOG_MAX_FILES: usize = 20;
+
+/// Rotate the task archive and remove the logs of tasks no longer in it.
+///
+/// `max_days` and `max_files` default to the node configuration. If `force` is set, the
+/// current archive gets rotated regardless of its size.
+pub fn rotate_task_archive(
+    worker: &WorkerTask,
+    force: bool,
+    max_days: Option<usize>,
+    max_files: Option<usize>,
+) -> Result<bool, Error> {
+    let (config, _digest) = crate::config::node::config()?;
+    let max_days = max_days.or(config.task_log_max_days);
+    let max_files = max_files
+        .or(config.task_log_max_files)
+        .unwrap_or(DEFAULT_TASK_LOG_MAX_FILES);
+
+    let user = pbs_config::backup_user()?;
+    let options = CreateOptions::new().owner(user.uid).group(user.gid);
+
+    let max_size = if force { 0 } else { TASK_ARCHIVE_MAX_SIZE };
+
+    let has_rotated =
+        rotate_task_log_archive(max_size, true, Some(max_files), max_days, Some(options))?;
+
+    if has_rotated {
+        task_log!(worker, "task log archive was rotated");
+        task_log!(worker, "cleaning up old task logs");
+        if let Err(err) = cleanup_old_tasks(worker, true) {
+            task_warn!(worker, "could not completely cleanup old tasks: {err}");
+        }
+    } else {
+        task_log!(worker, "task log archive was not rotated");
+    }
+
+    Ok(has_rotated)
+}
+
+/// Purge the task archive, keeping at most `max_files` rotated files and the tasks of the last
+/// `max_days` days.
+pub fn do_purge_task_archive(
+    auth_id: String,
+    max_days: Option<usize>,
+    max_files: Option<usize>,
+    to_stdout: bool,
+) -> Result<String, Error> {
+    WorkerTask::new_thread(
+        "taskarchivepurge",
+        None,
+        auth_id,
+        to_stdout,
+        move |worker| {
+            task_log!(worker, "purging task archive");
+            if let Some(max_days) = max_days {
+                task_log!(worker, "keeping tasks of the last {max_days} days");
+            }
+            rotate_task_archive(&worker, true, max_days, max_files)?;
+            Ok(())
+        },
+    )
+}
@@ -0,0 +1,148 @@
+//! cgroup isolation of heavyweight worker tasks
+//!
+//! Worker tasks are threads of the daemon processes, so they cannot get a cgroup of their own.
+//! Instead, the daemons use threaded child cgroups of their (delegated) service cgroup, one per
+//! task class, and move the worker thread into it. Threads spawned by the worker, e.g. the
+//! verify decoder pool, stay in that cgroup.
+//!
+//! Only the threaded `cpu` controller can be used this way, memory and IO are accounted for
+//! the whole service.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{bail, format_err, Error};
+use lazy_static::lazy_static;
+
+use proxmox_rest_server::WorkerTask;
+use proxmox_sys::task_warn;
+
+use crate::config::node::{NodeConfig, TaskCgroupLimits};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const CPU_PERIOD: u64 = 100_000;
+
+/// Units which get their cgroup delegated by systemd.
+const SERVICE_UNITS: &[&str] = &["proxmox-backup.service", "proxmox-backup-proxy.service"];
+
+lazy_static! {
+    static ref SETUP_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Classes of worker tasks with their own cgroup.
+#[derive(Clone, Copy, Debug)]
+pub enum TaskCgroup {
+    GarbageCollection,
+    Verify,
+    Tape,
+}
+
+impl TaskCgroup {
+    fn name(self) -> &'static str {
+        match self {
+            TaskCgroup::GarbageCollection => "task-gc",
+            TaskCgroup::Verify => "task-verify",
+            TaskCgroup::Tape => "task-tape",
+        }
+    }
+
+    fn limits(self, config: &NodeConfig) -> Result<TaskCgroupLimits, Error> {
+        let limits = match self {
+            TaskCgroup::GarbageCollection => config.task_limits_gc.as_deref(),
+            TaskCgroup::Verify => config.task_limits_verify.as_deref(),
+            TaskCgroup::Tape => config.task_limits_tape.as_deref(),
+        };
+        match limits {
+            Some(limits) => {
+                crate::tools::config::from_property_string(limits, &TaskCgroupLimits::API_SCHEMA)
+            }
+            None => Ok(TaskCgroupLimits::default()),
+        }
+    }
+}
+
+/// Returns the cgroup of the service, or `None` if not running as one of the daemons.
+fn service_cgroup() -> Result<Option<PathBuf>, Error> {
+    let data = std::fs::read_to_string("/proc/self/cgroup")?;
+    // the unified hierarchy is the only line on a pure cgroup v2 system
+    let path = match data.lines().find_map(|line| line.strip_prefix("0::")) {
+        Some(path) => Path::new(CGROUP_ROOT).join(path.trim_start_matches('/')),
+        None => return Ok(None),
+    };
+
+    let is_service = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| SERVICE_UNITS.contains(&name))
+        .unwrap_or(false);
+
+    Ok(is_service.then_some(path))
+}
+
+fn write_cgroup_file(path: &Path, value: &str) -> Result<(), Error> {
+    std::fs::write(path, value).map_err(|err| format_err!("unable to write {path:?} - {err}"))
+}
+
+/// Create the threaded child cgroup `name` and enable the cpu controller for it.
+fn setup_task_cgroup(service: &Path, name: &str) -> Result<PathBuf, Error> {
+    let path = service.join(name);
+    match std::fs::create_dir(&path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(err) => bail!("unable to create cgroup {path:?} - {err}"),
+    }
+
+    let type_path = path.join("cgroup.type");
+    if std::fs::read_to_string(&type_path)?.trim() != "threaded" {
+        write_cgroup_file(&type_path, "threaded")?;
+    }
+
+    // must happen after the children were made threaded, as the service cgroup has processes
+    let control_path = service.join("cgroup.subtree_control");
+    let controllers = std::fs::read_to_string(&control_path)?;
+    if !controllers.split_whitespace().any(|c| c == "cpu") {
+        write_cgroup_file(&control_path, "+cpu")?;
+    }
+
+    Ok(path)
+}
+
+fn enter(service: &Path, class: TaskCgroup) -> Result<(), Error> {
+    let (config, _digest) = crate::config::node::config()?;
+    let limits = class.limits(&config)?;
+
+    let _guard = SETUP_LOCK.lock().unwrap();
+    let path = setup_task_cgroup(service, class.name())?;
+
+    let weight = limits.cpu_weight.unwrap_or(100);
+    write_cgroup_file(&path.join("cpu.weight"), &weight.to_string())?;
+
+    let cpu_max = match limits.cpu_max {
+        Some(cpus) => format!("{} {CPU_PERIOD}", (cpus * CPU_PERIOD as f64) as u64),
+        None => format!("max {CPU_PERIOD}"),
+    };
+    write_cgroup_file(&path.join("cpu.max"), &cpu_max)?;
+
+    let tid = nix::unistd::gettid();
+    write_cgroup_file(&path.join("cgroup.threads"), &tid.to_string())
+}
+
+/// Move the calling worker thread into the cgroup of its task class.
+///
+/// The limits are read from the node configuration, so changes apply to all tasks of the
+/// class once the next one starts. Does nothing if not running as a service, e.g. for tasks
+/// started by the CLI tools, and only logs failures.
+pub fn enter_task_cgroup(worker: &WorkerTask, class: TaskCgroup) {
+    let service = match service_cgroup() {
+        Ok(Some(service)) => service,
+        Ok(None) => return,
+        Err(err) => {
+            task_warn!(worker, "unable to determine service cgroup - {err}");
+            return;
+        }
+    };
+
+    if let Err(err) = enter(&service, class) {
+        task_warn!(worker, "could not move task into its cgroup - {err}");
+    }
+}
@@ -0,0 +1,169 @@
+//! Cleanup of unfinished snapshots
+//!
+//! A backup creates the snapshot directory right away, but only writes the manifest when it
+//! finishes. If the backup gets aborted without cleaning up, e.g. because the service crashed or
+//! the host lost power, the directory without manifest stays behind. Such snapshots cannot be
+//! restored or synced, but still keep their chunks from being garbage collected.
+
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{format_err, Error};
+
+use proxmox_schema::ApiType;
+use proxmox_sys::fs::{create_path, lock_dir_noblock, CreateOptions, DirLockGuard};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{
+    print_ns_and_snapshot, BackupNamespace, DataStoreConfig, DatastoreTuning, UnfinishedSnapshot,
+    UnfinishedSnapshotAction,
+};
+use pbs_datastore::backup_info::BackupDir;
+use pbs_datastore::manifest::MANIFEST_BLOB_NAME;
+use pbs_datastore::DataStore;
+
+/// Directory below the datastore base unfinished snapshots get moved to.
+pub const UNFINISHED_QUARANTINE_DIR: &str = ".unfinished";
+
+/// Default minimum age in seconds of unfinished snapshots to be considered stale.
+pub const DEFAULT_UNFINISHED_MIN_AGE: i64 = 24 * 3600;
+
+fn last_modified(path: &Path) -> Result<i64, Error> {
+    Ok(std::fs::metadata(path)?.mtime())
+}
+
+fn lock_snapshot(dir: &BackupDir) -> Result<DirLockGuard, Error> {
+    lock_dir_noblock(&dir.full_path(), "snapshot", "possibly running or in use")
+}
+
+fn unfinished_snapshots(datastore: &Arc<DataStore>) -> Result<Vec<BackupDir>, Error> {
+    let mut list = Vec::new();
+    for ns in datastore.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+        for group in datastore.iter_backup_groups_ok(ns)? {
+            for info in group.list_backups()? {
+                if !info.is_finished() {
+                    list.push(info.backup_dir);
+                }
+            }
+        }
+    }
+    Ok(list)
+}
+
+/// Returns the action for stale unfinished snapshots configured for `store`.
+pub fn configured_action(store: &str) -> Result<UnfinishedSnapshotAction, Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let config: DataStoreConfig = config.lookup("datastore", store)?;
+    let tuning: DatastoreTuning = serde_json::from_value(
+        DatastoreTuning::API_SCHEMA
+            .parse_property_string(config.tuning.as_deref().unwrap_or(""))?,
+    )?;
+    Ok(tuning.unfinished_snapshots.unwrap_or_default())
+}
+
+/// List the unfinished snapshots of a datastore, including those of running backups.
+pub fn list_unfinished_snapshots(
+    datastore: &Arc<DataStore>,
+) -> Result<Vec<UnfinishedSnapshot>, Error> {
+    let mut list = Vec::new();
+    for dir in unfinished_snapshots(datastore)? {
+        // vanished in the meantime, e.g. a failed backup cleaned up after itself
+        let last_modified = match last_modified(&dir.full_path()) {
+            Ok(last_modified) => last_modified,
+            Err(_) => continue,
+        };
+        list.push(UnfinishedSnapshot {
+            snapshot: print_ns_and_snapshot(dir.backup_ns(), dir.as_ref()),
+            last_modified,
+            in_use: lock_snapshot(&dir).is_err(),
+        });
+    }
+    Ok(list)
+}
+
+fn quarantine_snapshot(datastore: &DataStore, dir: &BackupDir) -> Result<(), Error> {
+    let target = datastore
+        .base_path()
+        .join(UNFINISHED_QUARANTINE_DIR)
+        .join(dir.relative_path());
+    if let Some(parent) = target.parent() {
+        let backup_user = pbs_config::backup_user()?;
+        let options = CreateOptions::new()
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+        create_path(parent, Some(options.clone()), Some(options))?;
+    }
+    std::fs::rename(dir.full_path(), &target)
+        .map_err(|err| format_err!("unable to move snapshot to {target:?} - {err}"))?;
+    if let Some(index) = datastore.content_index() {
+        index.snapshot_removed(dir.backup_ns(), dir.dir());
+    }
+    Ok(())
+}
+
+/// Report, remove or quarantine the unfinished snapshots of a datastore which were not
+/// modified within the last `min_age` seconds.
+///
+/// Snapshots locked by a running task are always skipped. Returns the number of stale
+/// snapshots found.
+pub fn cleanup_unfinished_snapshots(
+    worker: &dyn WorkerTaskContext,
+    datastore: &Arc<DataStore>,
+    action: UnfinishedSnapshotAction,
+    min_age: i64,
+) -> Result<usize, Error> {
+    let now = proxmox_time::epoch_i64();
+    let mut count = 0;
+
+    for dir in unfinished_snapshots(datastore)? {
+        worker.check_abort()?;
+
+        let name = print_ns_and_snapshot(dir.backup_ns(), dir.as_ref());
+        let path = dir.full_path();
+
+        let _guard = match lock_snapshot(&dir) {
+            Ok(guard) => guard,
+            Err(_) => {
+                task_log!(worker, "skipping unfinished snapshot {name} - in use");
+                continue;
+            }
+        };
+
+        // the backup might have finished since listing the snapshots
+        if path.join(MANIFEST_BLOB_NAME).exists() {
+            continue;
+        }
+
+        match last_modified(&path) {
+            Ok(mtime) if now - mtime < min_age => {
+                task_log!(
+                    worker,
+                    "skipping unfinished snapshot {name} - modified recently"
+                );
+                continue;
+            }
+            Ok(_) => {}
+            Err(_) => continue,
+        }
+
+        count += 1;
+
+        match action {
+            UnfinishedSnapshotAction::Report => {
+                task_warn!(worker, "found stale unfinished snapshot {name}");
+            }
+            // we hold the lock already
+            UnfinishedSnapshotAction::Remove => match dir.destroy(true) {
+                Ok(()) => task_log!(worker, "removed unfinished snapshot {name}"),
+                Err(err) => task_warn!(worker, "unable to remove {name} - {err}"),
+            },
+            UnfinishedSnapshotAction::Quarantine => match quarantine_snapshot(datastore, &dir) {
+                Ok(()) => task_log!(worker, "moved unfinished snapshot {name} to quarantine"),
+                Err(err) => task_warn!(worker, "unable to quarantine {name} - {err}"),
+            },
+        }
+    }
+
+    Ok(count)
+}
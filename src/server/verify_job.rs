@@ -1,13 +1,15 @@
 use anyhow::{format_err, Error};
 
-use pbs_api_types::{Authid, Operation, VerificationJobConfig};
+use pbs_api_types::{Authid, HookEvent, Operation, VerificationJobConfig};
 use pbs_datastore::DataStore;
 use proxmox_rest_server::WorkerTask;
 use proxmox_sys::task_log;
 
 use crate::{
     backup::{verify_all_backups, verify_filter},
+    server::io_priority::{apply_task_io_priority, TaskIoClass},
     server::jobstate::Job,
+    server::task_cgroup::{enter_task_cgroup, TaskCgroup},
 };
 
 /// Runs a verification job.
@@ -25,6 +27,10 @@ pub fn do_verification_job(
 
     // FIXME encode namespace here for filter/ACL check?
     let job_id = format!("{}:{}", &verification_job.store, job.jobname());
+    let hook_env = vec![
+        ("PBS_DATASTORE", verification_job.store.clone()),
+        ("PBS_JOB_ID", job.jobname().to_string()),
+    ];
     let worker_type = job.jobtype().to_string();
     let upid_str = WorkerTask::new_thread(
         &worker_type,
@@ -38,6 +44,8 @@ pub fn do_verification_job(
             if let Some(event_str) = schedule {
                 task_log!(worker, "task triggered by schedule '{}'", event_str);
             }
+            apply_task_io_priority(&worker, TaskIoClass::Verify);
+            enter_task_cgroup(&worker, TaskCgroup::Verify);
 
             let ns = match verification_job.ns {
                 Some(ref ns) => ns.clone(),
@@ -80,6 +88,14 @@ pub fn do_verification_job(
                 eprintln!("send verify notification failed: {err}");
             }
 
+            crate::server::hooks::run_task_hooks(
+                HookEvent::VerifyFinish,
+                HookEvent::VerifyFailure,
+                worker.upid(),
+                &job_result,
+                hook_env,
+            );
+
             job_result
         },
     )?;
@@ -9,6 +9,10 @@
 //! Tape backups store the password protected version on tape, so that
 //! it is possible to restore the key from tape if you know the
 //! password.
+//!
+//! Instead of storing the plain key locally, it can also be kept in an
+//! external key management service (see [`crate::tools::kms`]), which is
+//! queried whenever the key is not found in the local key file.
 
 use std::collections::HashMap;
 
@@ -92,11 +96,15 @@ pub fn load_keys() -> Result<(HashMap<Fingerprint, EncryptionKeyInfo>, [u8; 32])
     Ok((map, digest))
 }
 
+/// Load a plain tape encryption key, either from the local key file or from a configured key
+/// management service.
 pub fn load_key(fingerprint: &Fingerprint) -> Result<[u8; 32], Error> {
     let (key_map, _digest) = crate::tape::encryption_keys::load_keys()?;
-    key_map
-        .get(fingerprint)
-        .map(|data| data.key)
+    if let Some(data) = key_map.get(fingerprint) {
+        return Ok(data.key);
+    }
+
+    crate::tools::kms::lookup_key(fingerprint)?
         .ok_or_else(|| format_err!("unknown tape encryption key '{fingerprint}'"))
 }
 
@@ -153,8 +161,14 @@ pub fn save_key_configs(map: HashMap<Fingerprint, KeyConfig>) -> Result<(), Erro
 
 /// Insert a new key
 ///
-/// Get the lock, load both files, insert the new key, store files.
-pub fn insert_key(key: [u8; 32], key_config: KeyConfig, force: bool) -> Result<(), Error> {
+/// Get the lock, load both files, insert the new key, store files. If `kms` is set, the plain
+/// key is stored in that key management service instead of the local key file.
+pub fn insert_key(
+    key: [u8; 32],
+    key_config: KeyConfig,
+    force: bool,
+    kms: Option<&str>,
+) -> Result<(), Error> {
     let _lock = open_backup_lockfile(TAPE_KEYS_LOCKFILE, None, true)?;
 
     let (mut key_map, _) = load_keys()?;
@@ -169,9 +183,19 @@ pub fn insert_key(key: [u8; 32], key_config: KeyConfig, force: bool) -> Result<(
         bail!("encryption key '{}' already exists.", fingerprint);
     }
 
-    let item = EncryptionKeyInfo::new(key, fingerprint.clone());
-    key_map.insert(fingerprint.clone(), item);
-    save_keys(key_map)?;
+    match kms {
+        Some(kms) => {
+            crate::tools::kms::store_key(kms, &fingerprint, &key)?;
+            if key_map.remove(&fingerprint).is_some() {
+                save_keys(key_map)?;
+            }
+        }
+        None => {
+            let item = EncryptionKeyInfo::new(key, fingerprint.clone());
+            key_map.insert(fingerprint.clone(), item);
+            save_keys(key_map)?;
+        }
+    }
 
     config_map.insert(fingerprint, key_config);
     save_key_configs(config_map)?;
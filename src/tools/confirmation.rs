@@ -0,0 +1,105 @@
+//! Confirmation tokens for destructive API calls
+//!
+//! If safe mode is enabled in the node configuration, destructive endpoints require a token
+//! obtained from a preview call. The token is bound to the user, the operation and the exact
+//! list of items the preview showed, so it gets invalid as soon as anything changes.
+
+use anyhow::{format_err, Error};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+use proxmox_router::http_bail;
+use proxmox_sys::fs::file_get_contents;
+
+use pbs_api_types::{Authid, RemovalPreview};
+use pbs_buildcfg::configdir;
+
+/// Tokens are only valid for a short time, the preview is meant to be confirmed right away.
+const TOKEN_LIFETIME: i64 = 5 * 60;
+
+/// Returns whether destructive API calls need a confirmation token.
+pub fn safe_mode_enabled() -> Result<bool, Error> {
+    let (config, _digest) = crate::config::node::config()?;
+    Ok(config.safe_mode.unwrap_or(false))
+}
+
+fn compute_signature(
+    auth_id: &Authid,
+    operation: &str,
+    items: &[String],
+    expires: i64,
+) -> Result<Vec<u8>, Error> {
+    let key = file_get_contents(configdir!("/csrf.key"))?;
+    let key = PKey::hmac(&key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+
+    signer.update(format!("{auth_id}\n{operation}\n{expires}\n").as_bytes())?;
+    for item in items {
+        signer.update(item.as_bytes())?;
+        signer.update(b"\n")?;
+    }
+
+    Ok(signer.sign_to_vec()?)
+}
+
+/// Create a preview of `operation` removing `items`, including a token to confirm it.
+pub fn create_preview(
+    auth_id: &Authid,
+    operation: &str,
+    items: Vec<String>,
+) -> Result<RemovalPreview, Error> {
+    let expires = proxmox_time::epoch_i64() + TOKEN_LIFETIME;
+    let signature = compute_signature(auth_id, operation, &items, expires)?;
+
+    Ok(RemovalPreview {
+        token: format!("{:08X}:{}", expires, hex::encode(signature)),
+        items,
+        expires,
+    })
+}
+
+/// Check the confirmation token of `operation`, if safe mode is enabled.
+///
+/// `items` has to be computed the same way as for the preview.
+pub fn check_confirmation(
+    token: Option<&str>,
+    auth_id: &Authid,
+    operation: &str,
+    items: &[String],
+) -> Result<(), Error> {
+    if !safe_mode_enabled()? {
+        return Ok(());
+    }
+
+    let token = match token {
+        Some(token) => token,
+        None => http_bail!(
+            FORBIDDEN,
+            "safe mode is enabled - confirmation token from preview required"
+        ),
+    };
+
+    let (expires, signature) = token
+        .split_once(':')
+        .and_then(|(expires, signature)| {
+            let expires = i64::from_str_radix(expires, 16).ok()?;
+            let signature = hex::decode(signature).ok()?;
+            Some((expires, signature))
+        })
+        .ok_or_else(|| format_err!("invalid confirmation token"))?;
+
+    if expires < proxmox_time::epoch_i64() {
+        http_bail!(FORBIDDEN, "confirmation token expired");
+    }
+
+    let expected = compute_signature(auth_id, operation, items, expires)?;
+    if expected.len() != signature.len() || !openssl::memcmp::eq(&expected, &signature) {
+        http_bail!(
+            FORBIDDEN,
+            "confirmation token does not match - items to remove changed since preview?"
+        );
+    }
+
+    Ok(())
+}
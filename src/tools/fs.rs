@@ -1,7 +1,7 @@
 use std::ffi::CStr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::{format_err, Error};
+use anyhow::{bail, format_err, Error};
 use tokio::task::spawn_blocking;
 
 /// `proxmox_sys::fs::fs_into` wrapped in a `spawn_blocking` call.
@@ -22,3 +22,32 @@ pub async fn fs_info_static(
         .await
         .map_err(|err| format_err!("error waiting for fs_info call: {err}"))??)
 }
+
+/// Check that `path` is an executable which root may run without giving others control over
+/// it: an absolute path to a regular file, owned by root and not writable by group or others.
+///
+/// `what` describes the file in error messages, e.g. "hook script".
+pub fn check_root_executable(path: &str, what: &str) -> Result<(), Error> {
+    let file = Path::new(path);
+    if !file.is_absolute() {
+        bail!("{what} '{path}' is not an absolute path");
+    }
+
+    let stat = nix::sys::stat::stat(file)
+        .map_err(|err| format_err!("unable to stat {what} '{path}' - {err}"))?;
+
+    if stat.st_mode & libc::S_IFMT != libc::S_IFREG {
+        bail!("{what} '{path}' is not a regular file");
+    }
+    if stat.st_uid != 0 {
+        bail!("{what} '{path}' is not owned by root");
+    }
+    if stat.st_mode & 0o022 != 0 {
+        bail!("{what} '{path}' is writable by group or others");
+    }
+    if stat.st_mode & 0o111 == 0 {
+        bail!("{what} '{path}' is not executable");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,523 @@
+//! Key management service (KMS) integration
+//!
+//! Tape encryption keys can be kept in an external key management service instead of the local
+//! key files. Keys are fetched at use time and verified against their fingerprint. Fetched keys
+//! are cached in a root-only file below `/run` (tmpfs), so they never hit persistent storage.
+//!
+//! The at-rest encryption keys of datastores are not covered: they are needed by the proxy,
+//! which runs as backup user and has no access to the service credentials.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, format_err, Error};
+use hex::FromHex;
+use hyper::{Body, Request};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
+
+use pbs_api_types::{Fingerprint, KmsBackend, KmsConfig, KmsFailurePolicy};
+use pbs_key_config::KeyConfig;
+
+use crate::tools::fs::check_root_executable;
+use crate::tools::pbs_simple_http;
+
+const KMS_KEY_CACHE_FILENAME: &str = pbs_buildcfg::rundir!("/kms-key-cache.json");
+const DEFAULT_CACHE_TIME: u64 = 300;
+
+/// Access to the keys stored in a key management service.
+pub trait KeyManagementService {
+    /// Fetch the plain key with `fingerprint`.
+    fn fetch_key(&self, fingerprint: &Fingerprint) -> Result<[u8; 32], Error>;
+
+    /// Store the plain key with `fingerprint`.
+    fn store_key(&self, fingerprint: &Fingerprint, key: &[u8; 32]) -> Result<(), Error>;
+}
+
+/// HashiCorp Vault, KV secrets engine version 2.
+struct VaultKms<'a> {
+    url: &'a str,
+    token: &'a str,
+    mount: &'a str,
+    path: &'a str,
+}
+
+impl VaultKms<'_> {
+    fn secret_url(&self, fingerprint: &Fingerprint) -> String {
+        let mut url = format!(
+            "{}/v1/{}/data/",
+            self.url.trim_end_matches('/'),
+            self.mount.trim_matches('/'),
+        );
+        let path = self.path.trim_matches('/');
+        if !path.is_empty() {
+            url.push_str(path);
+            url.push('/');
+        }
+        url.push_str(&hex::encode(fingerprint.bytes()));
+        url
+    }
+
+    async fn request(&self, method: &str, url: &str, data: Option<Value>) -> Result<Value, Error> {
+        let client = pbs_simple_http(None);
+
+        let builder = Request::builder()
+            .method(method)
+            .uri(url)
+            .header("X-Vault-Token", self.token);
+
+        let request = match data {
+            Some(data) => builder
+                .header("Content-Type", "application/json")
+                .body(Body::from(data.to_string()))?,
+            None => builder.body(Body::empty())?,
+        };
+
+        let response = client.request(request).await?;
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+
+        if !parts.status.is_success() {
+            bail!(
+                "vault request failed with status {} - {}",
+                parts.status,
+                String::from_utf8_lossy(&body).trim()
+            );
+        }
+        if body.is_empty() {
+            return Ok(Value::Null);
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+impl KeyManagementService for VaultKms<'_> {
+    fn fetch_key(&self, fingerprint: &Fingerprint) -> Result<[u8; 32], Error> {
+        let url = self.secret_url(fingerprint);
+        let data = proxmox_async::runtime::block_on(self.request("GET", &url, None))?;
+        let key = data["data"]["data"]["key"]
+            .as_str()
+            .ok_or_else(|| format_err!("vault secret has no 'key' value"))?;
+        Ok(<[u8; 32]>::from_hex(key)?)
+    }
+
+    fn store_key(&self, fingerprint: &Fingerprint, key: &[u8; 32]) -> Result<(), Error> {
+        let url = self.secret_url(fingerprint);
+        let data = json!({ "data": { "key": hex::encode(key) } });
+        proxmox_async::runtime::block_on(self.request("POST", &url, Some(data)))?;
+        Ok(())
+    }
+}
+
+/// External helper command.
+struct CommandKms<'a> {
+    command: &'a str,
+}
+
+impl CommandKms<'_> {
+    // like hooks, the command does not inherit the environment of the daemon
+    fn command(&self, action: &str, fingerprint: &Fingerprint) -> Command {
+        let mut command = Command::new(self.command);
+        command
+            .env_clear()
+            .env("PATH", "/usr/sbin:/usr/bin:/sbin:/bin")
+            .current_dir("/")
+            .arg(action)
+            .arg(fingerprint.signature());
+        command
+    }
+}
+
+impl KeyManagementService for CommandKms<'_> {
+    fn fetch_key(&self, fingerprint: &Fingerprint) -> Result<[u8; 32], Error> {
+        let output = self
+            .command("get", fingerprint)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|err| format_err!("failed to execute {:?} - {}", self.command, err))?;
+
+        if !output.status.success() {
+            bail!(
+                "{:?} failed ({}) - {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let key = std::str::from_utf8(&output.stdout)?.trim();
+        Ok(<[u8; 32]>::from_hex(key)?)
+    }
+
+    fn store_key(&self, fingerprint: &Fingerprint, key: &[u8; 32]) -> Result<(), Error> {
+        let mut child = self
+            .command("put", fingerprint)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| format_err!("failed to execute {:?} - {}", self.command, err))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            writeln!(stdin, "{}", hex::encode(key))?;
+        }
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            bail!(
+                "{:?} failed ({}) - {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Instantiate the backend for a configured key management service.
+pub fn kms_backend(config: &KmsConfig) -> Result<Box<dyn KeyManagementService + '_>, Error> {
+    match config.backend {
+        KmsBackend::Vault => {
+            let url = config
+                .url
+                .as_deref()
+                .ok_or_else(|| format_err!("kms '{}': missing 'url'", config.id))?;
+            let token = config
+                .token
+                .as_deref()
+                .ok_or_else(|| format_err!("kms '{}': missing 'token'", config.id))?;
+            Ok(Box::new(VaultKms {
+                url,
+                token,
+                mount: config.mount.as_deref().unwrap_or("secret"),
+                path: config.path.as_deref().unwrap_or(""),
+            }))
+        }
+        KmsBackend::Command => {
+            let command = config
+                .command
+                .as_deref()
+                .ok_or_else(|| format_err!("kms '{}': missing 'command'", config.id))?;
+            check_root_executable(command, "kms command")
+                .map_err(|err| format_err!("kms '{}': {}", config.id, err))?;
+            Ok(Box::new(CommandKms { command }))
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedKey {
+    key: String,
+    fetched: i64,
+}
+
+/// File holding the fetched keys by fingerprint.
+struct KeyCache {
+    path: PathBuf,
+    options: CreateOptions,
+}
+
+impl Default for KeyCache {
+    fn default() -> Self {
+        let mode = nix::sys::stat::Mode::from_bits_truncate(0o0600);
+        let options = CreateOptions::new()
+            .perm(mode)
+            .owner(nix::unistd::ROOT)
+            .group(nix::unistd::Gid::from_raw(0));
+
+        Self {
+            path: PathBuf::from(KMS_KEY_CACHE_FILENAME),
+            options,
+        }
+    }
+}
+
+impl KeyCache {
+    fn load(&self) -> Result<HashMap<String, CachedKey>, Error> {
+        match file_read_optional_string(&self.path)? {
+            Some(content) => Ok(serde_json::from_str(&content)?),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    fn lookup(&self, fingerprint: &Fingerprint) -> Option<([u8; 32], i64)> {
+        let entry = self
+            .load()
+            .unwrap_or_default()
+            .remove(&fingerprint.signature())?;
+        let key = <[u8; 32]>::from_hex(&entry.key).ok()?;
+        Some((key, entry.fetched))
+    }
+
+    fn update(&self, fingerprint: &Fingerprint, key: &[u8; 32], now: i64) -> Result<(), Error> {
+        let mut cache = self.load().unwrap_or_default();
+        cache.insert(
+            fingerprint.signature(),
+            CachedKey {
+                key: hex::encode(key),
+                fetched: now,
+            },
+        );
+
+        replace_file(
+            &self.path,
+            serde_json::to_string(&cache)?.as_bytes(),
+            self.options.clone(),
+            false,
+        )
+    }
+}
+
+/// Drop all cached keys (e.g. after changing the KMS configuration).
+pub fn clear_key_cache() -> Result<(), Error> {
+    match std::fs::remove_file(KMS_KEY_CACHE_FILENAME) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => bail!("unable to remove kms key cache - {}", err),
+    }
+}
+
+fn verify_key(key: &[u8; 32], fingerprint: &Fingerprint) -> Result<(), Error> {
+    let key_config = KeyConfig::without_password(*key)?;
+    match key_config.fingerprint {
+        Some(ref fp) if fp == fingerprint => Ok(()),
+        _ => bail!("got key with wrong fingerprint"),
+    }
+}
+
+/// Fetch a key from a key management service, honoring its cache time and failure policy.
+pub fn fetch_key(config: &KmsConfig, fingerprint: &Fingerprint) -> Result<[u8; 32], Error> {
+    fetch_key_cached(
+        config,
+        fingerprint,
+        &KeyCache::default(),
+        proxmox_time::epoch_i64(),
+        || kms_backend(config)?.fetch_key(fingerprint),
+    )
+}
+
+fn fetch_key_cached<F>(
+    config: &KmsConfig,
+    fingerprint: &Fingerprint,
+    cache: &KeyCache,
+    now: i64,
+    fetch: F,
+) -> Result<[u8; 32], Error>
+where
+    F: FnOnce() -> Result<[u8; 32], Error>,
+{
+    let cache_time = config.cache_time.unwrap_or(DEFAULT_CACHE_TIME) as i64;
+
+    let cached = if cache_time > 0 {
+        cache.lookup(fingerprint)
+    } else {
+        None
+    };
+
+    if let Some((key, fetched)) = cached {
+        if now >= fetched && (now - fetched) < cache_time {
+            return Ok(key);
+        }
+    }
+
+    let result = fetch().and_then(|key| verify_key(&key, fingerprint).map(|()| key));
+
+    match result {
+        Ok(key) => {
+            if cache_time > 0 {
+                if let Err(err) = cache.update(fingerprint, &key, now) {
+                    log::warn!("unable to update kms key cache - {}", err);
+                }
+            }
+            Ok(key)
+        }
+        Err(err) => match (config.failure_policy.unwrap_or_default(), cached) {
+            (KmsFailurePolicy::UseStale, Some((key, _))) => {
+                log::warn!(
+                    "kms '{}' failed, using stale cached key '{}' - {}",
+                    config.id,
+                    fingerprint,
+                    err
+                );
+                Ok(key)
+            }
+            _ => Err(format_err!("kms '{}': {}", config.id, err)),
+        },
+    }
+}
+
+/// Search all configured key management services for the key with `fingerprint`.
+///
+/// Returns `None` if no service is configured.
+pub fn lookup_key(fingerprint: &Fingerprint) -> Result<Option<[u8; 32]>, Error> {
+    let (config, _digest) = pbs_config::kms::config()?;
+    let list: Vec<KmsConfig> = config.convert_to_typed_array("kms")?;
+
+    if list.is_empty() {
+        return Ok(None);
+    }
+
+    let mut errors = Vec::new();
+    for kms in list {
+        match fetch_key(&kms, fingerprint) {
+            Ok(key) => return Ok(Some(key)),
+            Err(err) => errors.push(err.to_string()),
+        }
+    }
+
+    bail!(
+        "unable to fetch key '{}' from key management services:\n{}",
+        fingerprint,
+        errors.join("\n")
+    );
+}
+
+/// Store a key in the key management service `id`.
+pub fn store_key(id: &str, fingerprint: &Fingerprint, key: &[u8; 32]) -> Result<(), Error> {
+    let (config, _digest) = pbs_config::kms::config()?;
+    let kms: KmsConfig = config.lookup("kms", id)?;
+    kms_backend(&kms)?.store_key(fingerprint, key)
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn test_config(cache_time: u64, failure_policy: KmsFailurePolicy) -> KmsConfig {
+        KmsConfig {
+            id: "test".to_string(),
+            backend: KmsBackend::Command,
+            comment: None,
+            url: None,
+            mount: None,
+            path: None,
+            token: None,
+            command: Some("/usr/bin/false".to_string()),
+            cache_time: Some(cache_time),
+            failure_policy: Some(failure_policy),
+        }
+    }
+
+    fn test_cache(name: &str) -> KeyCache {
+        let path = std::env::temp_dir().join(format!(
+            "pbs-kms-key-cache-{}-{name}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        KeyCache {
+            path,
+            options: CreateOptions::new(),
+        }
+    }
+
+    fn test_key(byte: u8) -> ([u8; 32], Fingerprint) {
+        let key = [byte; 32];
+        let fingerprint = KeyConfig::without_password(key)
+            .unwrap()
+            .fingerprint
+            .unwrap();
+        (key, fingerprint)
+    }
+
+    #[test]
+    fn test_key_cache_time() -> Result<(), Error> {
+        let cache = test_cache("time");
+        let config = test_config(300, KmsFailurePolicy::Fail);
+        let (key, fingerprint) = test_key(1);
+        let calls = Cell::new(0);
+        let fetch = || {
+            calls.set(calls.get() + 1);
+            Ok(key)
+        };
+
+        assert_eq!(
+            fetch_key_cached(&config, &fingerprint, &cache, 1000, fetch)?,
+            key
+        );
+        assert_eq!(calls.get(), 1);
+
+        // served from the cache, the service is not asked
+        let offline = || bail!("service not reachable");
+        assert_eq!(
+            fetch_key_cached(&config, &fingerprint, &cache, 1299, offline)?,
+            key
+        );
+
+        // expired
+        assert_eq!(
+            fetch_key_cached(&config, &fingerprint, &cache, 1300, fetch)?,
+            key
+        );
+        assert_eq!(calls.get(), 2);
+
+        // caching disabled
+        let config = test_config(0, KmsFailurePolicy::Fail);
+        assert_eq!(
+            fetch_key_cached(&config, &fingerprint, &cache, 1301, fetch)?,
+            key
+        );
+        assert_eq!(calls.get(), 3);
+
+        let _ = std::fs::remove_file(&cache.path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_failure_policy() -> Result<(), Error> {
+        let cache = test_cache("policy");
+        let (key, fingerprint) = test_key(2);
+        let offline = || bail!("service not reachable");
+
+        let config = test_config(300, KmsFailurePolicy::UseStale);
+        // nothing cached yet
+        assert!(fetch_key_cached(&config, &fingerprint, &cache, 0, offline).is_err());
+        fetch_key_cached(&config, &fingerprint, &cache, 0, || Ok(key))?;
+
+        assert_eq!(
+            fetch_key_cached(&config, &fingerprint, &cache, 1000, offline)?,
+            key
+        );
+
+        let config = test_config(300, KmsFailurePolicy::Fail);
+        assert!(fetch_key_cached(&config, &fingerprint, &cache, 1000, offline).is_err());
+
+        // a disabled cache is not used as fallback either
+        let config = test_config(0, KmsFailurePolicy::UseStale);
+        assert!(fetch_key_cached(&config, &fingerprint, &cache, 1000, offline).is_err());
+
+        let _ = std::fs::remove_file(&cache.path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_wrong_fingerprint() -> Result<(), Error> {
+        let cache = test_cache("fingerprint");
+        let config = test_config(300, KmsFailurePolicy::UseStale);
+        let (_key, fingerprint) = test_key(3);
+        let (other_key, _) = test_key(4);
+
+        assert!(fetch_key_cached(&config, &fingerprint, &cache, 0, || Ok(other_key)).is_err());
+        assert!(cache.lookup(&fingerprint).is_none());
+
+        let _ = std::fs::remove_file(&cache.path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_checks() {
+        let mut config = test_config(300, KmsFailurePolicy::Fail);
+
+        config.command = Some("kms-helper".to_string());
+        assert!(kms_backend(&config).is_err());
+
+        config.command = Some("/".to_string());
+        assert!(kms_backend(&config).is_err());
+    }
+}
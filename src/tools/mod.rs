@@ -8,8 +8,11 @@ use proxmox_http::{client::Client, HttpOptions, ProxyConfig};
 
 pub mod apt;
 pub mod config;
+pub mod confirmation;
 pub mod disks;
 pub mod fs;
+pub mod kms;
+pub mod network_share;
 
 mod shared_rate_limiter;
 pub use shared_rate_limiter::SharedRateLimiter;
@@ -0,0 +1,194 @@
+//! Systemd mount units for network shares
+//!
+//! Each share gets a mount unit and an automount unit for its mount point. Only the automount
+//! unit is enabled, so the share is mounted on first access and mounted again on the next access
+//! after it was unmounted, see [`pbs_datastore::network_share`].
+
+use anyhow::{bail, Error};
+
+use proxmox_section_config::SectionConfigData;
+
+use pbs_api_types::{NetworkShareConfig, NetworkShareType};
+
+use crate::tools::systemd::{self, types::*};
+
+fn credentials_path(name: &str) -> String {
+    format!("/etc/proxmox-backup/network-share-{name}.cred")
+}
+
+fn unit_name(share: &NetworkShareConfig, unit_type: &str) -> String {
+    let mut unit_name = proxmox_sys::systemd::escape_unit(&share.mount_point(), true);
+    unit_name.push('.');
+    unit_name.push_str(unit_type);
+    unit_name
+}
+
+fn read_password(name: &str) -> Result<Option<String>, Error> {
+    let content = match proxmox_sys::fs::file_read_optional_string(credentials_path(name))? {
+        Some(content) => content,
+        None => return Ok(None),
+    };
+    Ok(content
+        .lines()
+        .find_map(|line| line.strip_prefix("password="))
+        .map(String::from))
+}
+
+/// Write the credentials file of a CIFS share, or remove it if no longer needed.
+///
+/// Without a new `password`, the one of the existing credentials file is kept.
+pub fn update_credentials(
+    share: &NetworkShareConfig,
+    password: Option<String>,
+) -> Result<(), Error> {
+    let path = credentials_path(&share.name);
+
+    let username = match (&share.share_type, &share.username) {
+        (NetworkShareType::Cifs, Some(username)) => username,
+        _ => {
+            if let Err(err) = std::fs::remove_file(&path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    bail!("unable to remove credentials file {path:?} - {err}");
+                }
+            }
+            return Ok(());
+        }
+    };
+
+    let password = match password {
+        Some(password) => password,
+        None => read_password(&share.name)?.unwrap_or_default(),
+    };
+
+    let mut content = format!("username={username}\npassword={password}\n");
+    if let Some(domain) = &share.domain {
+        content.push_str(&format!("domain={domain}\n"));
+    }
+
+    pbs_config::replace_secret_config(path, content.as_bytes())
+}
+
+fn mount_options(share: &NetworkShareConfig) -> String {
+    let mut options = vec!["_netdev".to_string()];
+
+    if share.share_type == NetworkShareType::Cifs {
+        match share.username {
+            Some(_) => options.push(format!("credentials={}", credentials_path(&share.name))),
+            None => options.push("guest".to_string()),
+        }
+        // CIFS has no ownership on its own, the datastore needs to be writable by the backup user
+        options.push(format!("uid={}", pbs_buildcfg::BACKUP_USER_NAME));
+        options.push(format!("gid={}", pbs_buildcfg::BACKUP_GROUP_NAME));
+    }
+
+    if let Some(extra) = &share.options {
+        options.push(extra.clone());
+    }
+
+    options.join(",")
+}
+
+/// Write the mount and automount units of a share and reload systemd.
+pub fn write_units(share: &NetworkShareConfig) -> Result<(), Error> {
+    let mount_point = share.mount_point();
+
+    let description = format!("Mount network share '{}' under '{mount_point}'", share.name);
+
+    let mut config = SectionConfigData::new();
+    config.set_data(
+        "Unit",
+        "Unit",
+        SystemdUnitSection {
+            Description: description.clone(),
+            ..Default::default()
+        },
+    )?;
+    config.set_data(
+        "Mount",
+        "Mount",
+        SystemdMountSection {
+            What: share.mount_source(),
+            Where: mount_point.clone(),
+            Type: Some(share.share_type.to_string()),
+            Options: Some(mount_options(share)),
+            // allows unmounting shares of unreachable servers
+            LazyUnmount: Some(true),
+            ..Default::default()
+        },
+    )?;
+    let path = format!("/etc/systemd/system/{}", unit_name(share, "mount"));
+    systemd::config::save_systemd_mount(&path, &config)?;
+
+    let mut config = SectionConfigData::new();
+    config.set_data(
+        "Unit",
+        "Unit",
+        SystemdUnitSection {
+            Description: description,
+            ..Default::default()
+        },
+    )?;
+    config.set_data(
+        "Install",
+        "Install",
+        SystemdInstallSection {
+            WantedBy: Some(vec!["remote-fs.target".to_string()]),
+            ..Default::default()
+        },
+    )?;
+    config.set_data(
+        "Automount",
+        "Automount",
+        SystemdAutomountSection {
+            Where: mount_point,
+            ..Default::default()
+        },
+    )?;
+    let path = format!("/etc/systemd/system/{}", unit_name(share, "automount"));
+    systemd::config::save_systemd_automount(&path, &config)?;
+
+    systemd::reload_daemon()
+}
+
+/// Enable and start the automount unit of a share.
+pub fn activate(share: &NetworkShareConfig) -> Result<(), Error> {
+    let automount_unit = unit_name(share, "automount");
+    systemd::enable_unit(&automount_unit)?;
+    systemd::start_unit(&automount_unit)
+}
+
+/// Unmount a share, it is mounted again with the current configuration on the next access.
+pub fn unmount(share: &NetworkShareConfig) -> Result<(), Error> {
+    systemd::stop_unit(&unit_name(share, "mount"))?;
+    pbs_datastore::network_share::forget_network_share_probe(&share.name);
+    Ok(())
+}
+
+/// Unmount a share and remove its units and credentials.
+pub fn remove(share: &NetworkShareConfig) -> Result<(), Error> {
+    let automount_unit = unit_name(share, "automount");
+    let mount_unit = unit_name(share, "mount");
+
+    // the units might not be there anymore, continue with the removal in that case
+    if let Err(err) = systemd::disable_unit(&automount_unit) {
+        log::warn!("unable to disable {automount_unit} - {err}");
+    }
+    if let Err(err) = systemd::stop_unit(&automount_unit) {
+        log::warn!("unable to stop {automount_unit} - {err}");
+    }
+    systemd::stop_unit(&mount_unit)?;
+
+    for unit in [automount_unit, mount_unit] {
+        let path = format!("/etc/systemd/system/{unit}");
+        if let Err(err) = std::fs::remove_file(&path) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                bail!("unable to remove unit file {path:?} - {err}");
+            }
+        }
+    }
+    systemd::reload_daemon()?;
+
+    let mut share = share.clone();
+    share.username = None;
+    update_credentials(&share, None)
+}
@@ -12,6 +12,7 @@ lazy_static! {
     pub static ref SERVICE_CONFIG: SectionConfig = init_service();
     pub static ref TIMER_CONFIG: SectionConfig = init_timer();
     pub static ref MOUNT_CONFIG: SectionConfig = init_mount();
+    pub static ref AUTOMOUNT_CONFIG: SectionConfig = init_automount();
 }
 
 fn init_service() -> SectionConfig {
@@ -98,6 +99,34 @@ fn init_mount() -> SectionConfig {
     config
 }
 
+fn init_automount() -> SectionConfig {
+    let mut config = SectionConfig::with_systemd_syntax(&SYSTEMD_SECTION_NAME_SCHEMA);
+
+    match SystemdUnitSection::API_SCHEMA {
+        Schema::Object(ref obj_schema) => {
+            let plugin = SectionConfigPlugin::new("Unit".to_string(), None, obj_schema);
+            config.register_plugin(plugin);
+        }
+        _ => unreachable!(),
+    };
+    match SystemdInstallSection::API_SCHEMA {
+        Schema::Object(ref obj_schema) => {
+            let plugin = SectionConfigPlugin::new("Install".to_string(), None, obj_schema);
+            config.register_plugin(plugin);
+        }
+        _ => unreachable!(),
+    };
+    match SystemdAutomountSection::API_SCHEMA {
+        Schema::Object(ref obj_schema) => {
+            let plugin = SectionConfigPlugin::new("Automount".to_string(), None, obj_schema);
+            config.register_plugin(plugin);
+        }
+        _ => unreachable!(),
+    };
+
+    config
+}
+
 fn parse_systemd_config(
     config: &SectionConfig,
     filename: &str,
@@ -149,3 +178,7 @@ pub fn save_systemd_timer(filename: &str, data: &SectionConfigData) -> Result<()
 pub fn save_systemd_mount(filename: &str, data: &SectionConfigData) -> Result<(), Error> {
     save_systemd_config(&MOUNT_CONFIG, filename, data)
 }
+
+pub fn save_systemd_automount(filename: &str, data: &SectionConfigData) -> Result<(), Error> {
+    save_systemd_config(&AUTOMOUNT_CONFIG, filename, data)
+}
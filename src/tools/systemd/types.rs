@@ -223,6 +223,23 @@ pub struct SystemdMountSection {
     pub TimeoutSec: Option<String>,
 }
 
+#[api()]
+#[derive(Serialize, Deserialize, Default)]
+#[allow(non_snake_case)]
+/// Systemd Automount Section
+pub struct SystemdAutomountSection {
+    /// absolute path of a directory for the automount point
+    pub Where: String,
+    /// Directories of automount points (and any parent directories) are
+    /// automatically created if needed. Takes an access mode in octal
+    /// notation. Defaults to 0755.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub DirectoryMode: Option<String>,
+    /// Configures an idle timeout, after which the mount is unmounted again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub TimeoutIdleSec: Option<String>,
+}
+
 #[api()]
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
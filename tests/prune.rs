@@ -4,7 +4,7 @@ use anyhow::Error;
 
 use pbs_api_types::PruneJobOptions;
 use pbs_datastore::manifest::MANIFEST_BLOB_NAME;
-use pbs_datastore::prune::compute_prune_info;
+use pbs_datastore::prune::{compute_prune_info, compute_prune_info_with_tags};
 use pbs_datastore::{BackupDir, BackupInfo};
 
 fn get_prune_list(
@@ -335,3 +335,49 @@ fn test_prune_simple() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[test]
+fn test_prune_retention_tags() -> Result<(), Error> {
+    let orig_list = vec![
+        create_info("host/elsa/2019-11-15T11:59:15Z", false),
+        create_info("host/elsa/2019-11-22T11:59:15Z", false),
+        create_info("host/elsa/2019-11-29T11:59:15Z", false),
+        create_info("host/elsa/2019-12-01T11:59:15Z", false),
+        create_info("host/elsa/2019-12-03T11:59:15Z", false),
+        create_info("host/elsa/2019-12-04T11:59:15Z", false),
+    ];
+
+    let mut options = PruneJobOptions::default();
+    options.keep.keep_daily = Some(1);
+    options.keep.keep_weekly = Some(1);
+    options.keep.keep_monthly = Some(1);
+    let mut tagged: Vec<(PathBuf, Vec<String>)> =
+        compute_prune_info_with_tags(orig_list, &options.keep)?
+            .into_iter()
+            .filter(|(_info, mark, _tags)| mark.keep())
+            .map(|(info, _mark, tags)| (info.backup_dir.relative_path(), tags))
+            .collect();
+    tagged.reverse();
+
+    let expect: Vec<(PathBuf, Vec<String>)> = vec![
+        (
+            PathBuf::from("host/elsa/2019-11-22T11:59:15Z"),
+            vec!["keep-monthly".to_string()],
+        ),
+        (
+            PathBuf::from("host/elsa/2019-12-01T11:59:15Z"),
+            vec!["keep-weekly".to_string()],
+        ),
+        (
+            PathBuf::from("host/elsa/2019-12-04T11:59:15Z"),
+            vec![
+                "keep-daily".to_string(),
+                "keep-weekly".to_string(),
+                "keep-monthly".to_string(),
+            ],
+        ),
+    ];
+    assert_eq!(tagged, expect);
+
+    Ok(())
+}
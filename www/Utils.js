@@ -404,11 +404,16 @@ Ext.define('PBS.Utils', {
 	    backup: (type, id) => PBS.Utils.render_datastore_worker_id(id, gettext('Backup')),
 	    'barcode-label-media': [gettext('Drive'), gettext('Barcode-Label Media')],
 	    'catalog-media': [gettext('Drive'), gettext('Catalog Media')],
+	    chunkrepair: [gettext('Datastore'), gettext('Repair Chunks')],
+	    compliancereport: [gettext('Datastore'), gettext('Compliance Report')],
+	    contentindex: [gettext('Datastore'), gettext('Rebuild Content Index')],
 	    'delete-datastore': [gettext('Datastore'), gettext('Remove Datastore')],
 	    'delete-namespace': [gettext('Namespace'), gettext('Remove Namespace')],
 	    dircreate: [gettext('Directory Storage'), gettext('Create')],
 	    dirremove: [gettext('Directory'), gettext('Remove')],
 	    'eject-media': [gettext('Drive'), gettext('Eject Media')],
+	    expectedbackupcheck: [null, gettext('Check Expected Backups')],
+	    filenameindex: (type, id) => PBS.Utils.render_datastore_worker_id(id, gettext('Update Filename Index')),
 	    "format-media": [gettext('Drive'), gettext('Format media')],
 	    "forget-group": [gettext('Group'), gettext('Remove Group')],
 	    garbage_collection: ['Datastore', gettext('Garbage Collect')],
@@ -417,15 +422,20 @@ Ext.define('PBS.Utils', {
 	    'label-media': [gettext('Drive'), gettext('Label Media')],
 	    'load-media': (type, id) => PBS.Utils.render_drive_load_media_id(id, gettext('Load Media')),
 	    logrotate: [null, gettext('Log Rotation')],
+	    manifestupgrade: (type, id) => PBS.Utils.render_datastore_worker_id(id, gettext('Upgrade Manifests')),
 	    prune: (type, id) => PBS.Utils.render_datastore_worker_id(id, gettext('Prune')),
 	    prunejob: (type, id) => PBS.Utils.render_prune_job_worker_id(id, gettext('Prune Job')),
 	    reader: (type, id) => PBS.Utils.render_datastore_worker_id(id, gettext('Read Objects')),
 	    'rewind-media': [gettext('Drive'), gettext('Rewind Media')],
+	    storageanalysis: ['Datastore', gettext('Storage Analysis')],
 	    sync: ['Datastore', gettext('Remote Sync')],
+	    synccompare: [gettext('Sync Job'), gettext('Compare with Source')],
 	    syncjob: [gettext('Sync Job'), gettext('Remote Sync')],
 	    'tape-backup': (type, id) => PBS.Utils.render_tape_backup_id(id, gettext('Tape Backup')),
 	    'tape-backup-job': (type, id) => PBS.Utils.render_tape_backup_id(id, gettext('Tape Backup Job')),
 	    'tape-restore': ['Datastore', gettext('Tape Restore')],
+	    taskarchivepurge: [null, gettext('Purge Task Archive')],
+	    unfinishedcleanup: ['Datastore', gettext('Unfinished Snapshot Cleanup')],
 	    'unload-media': [gettext('Drive'), gettext('Unload Media')],
 	    verificationjob: [gettext('Verify Job'), gettext('Scheduled Verification')],
 	    verify: ['Datastore', gettext('Verification')],
@@ -805,6 +815,7 @@ Ext.define('PBS.Utils', {
 	'sync-level': {
 	    '__default__': Proxmox.Utils.defaultText + ` (${gettext('Filesystem')})`,
 	    none: gettext('None'),
+	    index: gettext('Index'),
 	    file: gettext('File'),
 	    filesystem: gettext('Filesystem'),
 	},
@@ -284,6 +284,28 @@ Ext.define('PBS.window.SyncJobEdit', {
 			uncheckedValue: false,
 			value: false,
 		    },
+		    {
+			fieldLabel: gettext('Sync Metadata'),
+			xtype: 'proxmoxcheckbox',
+			name: 'sync-metadata',
+			autoEl: {
+			    tag: 'div',
+			    'data-qtip': gettext('Also sync owners, notes, protection and verification state from the source datastore.'),
+			},
+			uncheckedValue: false,
+			value: false,
+		    },
+		    {
+			fieldLabel: gettext('Inventory Only'),
+			xtype: 'proxmoxcheckbox',
+			name: 'inventory-only',
+			autoEl: {
+			    tag: 'div',
+			    'data-qtip': gettext('Only sync manifests and blobs, without the archive data.'),
+			},
+			uncheckedValue: false,
+			value: false,
+		    },
 		],
 
 		columnB: [